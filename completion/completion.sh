@@ -23,13 +23,29 @@ _system76-power ()
     # 2nd/3rd level options
     case "${prev}" in
         graphics)
-            local _opts="compute integrated hybrid nvidia power switchable --help"
+            local _opts="capabilities compute default hotplug-check integrated hybrid list nvidia power set-option switchable watch --force --json --no-wait --short --timeout --wait --help"
             COMPREPLY=( $(compgen -W "${_opts}" -- ${cur}) )
             return 0
             ;;
 
-        battery|balanced|compute|integrated|hybrid|nvidia|performance|switchable|on|off|auto)
-            local _opts="--help"
+        battery|quiet|balanced|capabilities|compute|default|hotplug-check|integrated|hybrid|nvidia|performance|switchable|watch|on|off|status)
+            local _opts="--json --help"
+            COMPREPLY=( $(compgen -W "${_opts}" -- ${cur}) )
+            return 0
+            ;;
+
+        auto)
+            if [[ "${COMP_WORDS[1]}" == "profile" ]]; then
+                local _opts="on off status --json --help"
+            else
+                local _opts="--help"
+            fi
+            COMPREPLY=( $(compgen -W "${_opts}" -- ${cur}) )
+            return 0
+            ;;
+
+        list)
+            local _opts="--json --help"
             COMPREPLY=( $(compgen -W "${_opts}" -- ${cur}) )
             return 0
             ;;
@@ -40,24 +56,24 @@ _system76-power ()
             ;;
 
 	     daemon)
-	          local _opts="--quiet --verbose --help"
+	          local _opts="--check --log-level --no-restore --quiet --verbose --help"
             COMPREPLY=( $(compgen -W "${_opts}" -- ${cur}) )
             return 0
             ;;
 
         profile)
-            local _opts="battery balanced performance --help"
+            local _opts="auto battery quiet balanced performance --watch --list --json --help"
             COMPREPLY=( $(compgen -W "${_opts}" -- ${cur}) )
             return 0
             ;;
 
 	      power)
-	          local _opts="auto on off --help"
+	          local _opts="auto on off --json --help"
             COMPREPLY=( $(compgen -W "${_opts}" -- ${cur}) )
             return 0
             ;;
         charge-thresholds)
-            local _opts="--profile --list-profiles --help"
+            local _opts="--profile --list-profiles --json --help"
             COMPREPLY=( $(compgen -W "${_opts}" -- ${cur}) )
             return 0
             ;;
@@ -8,10 +8,11 @@
 //! - Available Platform Profiles:
 //!  - <https://mjmwired.net/kernel/Documentation/ABI/testing/sysfs-platform_profile>
 
+use crate::{util::Written, Profile};
 use once_cell::sync::Lazy;
-use std::{fs, path::Path};
+use std::path::Path;
 
-const SYSFS_PATH: &str = "/sys/firmware/acpi/platform_profile";
+pub const SYSFS_PATH: &str = "/sys/firmware/acpi/platform_profile";
 
 /// Displays available ACPI platform profiles to choose from.
 pub fn choices() -> impl Iterator<Item = &'static str> {
@@ -28,39 +29,87 @@ pub fn choices() -> impl Iterator<Item = &'static str> {
 #[must_use]
 pub fn supported() -> bool { Path::new(SYSFS_PATH).exists() }
 
-/// Applies the `low-power` or `quiet` ACPI platform profile.
-pub fn battery() {
-    let mut first_choice = None;
-
-    for choice in choices() {
-        if first_choice.is_none() {
-            first_choice = Some(choice);
-        }
-        match choice {
-            "low-power" | "quiet" => {
-                apply_profile(choice);
-                return;
-            }
-
-            _ => (),
-        }
+/// The ACPI platform profile applied with a profile, or the `choice` overriding it if the
+/// firmware offers it, without applying it.
+#[must_use]
+pub fn planned(profile: Profile, choice: Option<&str>) -> Option<String> {
+    if !supported() {
+        return None;
+    }
+
+    let choices = choices().collect::<Vec<_>>();
+    select(profile, choice, &choices).map(str::to_owned)
+}
+
+/// The ACPI platform profile, as written.
+#[must_use]
+pub fn plan(choice: &str) -> Written { Written::new("platform_profile", SYSFS_PATH, choice) }
+
+/// The choice of the firmware for a profile: the `choice` overriding it if offered, the choice
+/// named after it, or else one ranked alike among the choices, which the kernel lists from the
+/// lowest to the highest power, so that vendor-specific names are tolerated.
+fn select<'a>(profile: Profile, choice: Option<&'a str>, choices: &[&'a str]) -> Option<&'a str> {
+    // Without the list of choices, the override or the standard name is written as is.
+    if choices.is_empty() {
+        return choice.or(match profile {
+            Profile::Battery => None,
+            Profile::Quiet => Some("quiet"),
+            Profile::Balanced => Some("balanced"),
+            Profile::Performance => Some("performance"),
+        });
     }
 
-    // First profile is a best choice option, if unknown.
-    if let Some(choice) = first_choice {
-        apply_profile(choice);
+    if let Some(choice) = choice.filter(|choice| choices.contains(choice)) {
+        return Some(choice);
     }
+
+    let named: &[&str] = match profile {
+        Profile::Battery => &["low-power", "quiet", "cool"],
+        Profile::Quiet => &["quiet", "cool", "low-power"],
+        Profile::Balanced => &["balanced"],
+        Profile::Performance => &["performance"],
+    };
+
+    if let Some(choice) = named.iter().find(|name| choices.contains(name)) {
+        return Some(choice);
+    }
+
+    // `custom` is set by other tools, and is not ranked.
+    let ranked = choices.iter().filter(|choice| **choice != "custom").collect::<Vec<_>>();
+    let rank = match profile {
+        Profile::Battery | Profile::Quiet => 0,
+        Profile::Balanced => ranked.len() / 2,
+        Profile::Performance => ranked.len().checked_sub(1)?,
+    };
+
+    ranked.get(rank).map(|choice| **choice)
 }
 
-/// Applies the balanced ACPI platform profile.
-pub fn balanced() { apply_profile("balanced"); }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vendor_choices() {
+        let standard = ["low-power", "quiet", "balanced", "performance", "custom"];
+        assert_eq!(select(Profile::Battery, None, &standard), Some("low-power"));
+        assert_eq!(select(Profile::Balanced, None, &standard), Some("balanced"));
+        assert_eq!(select(Profile::Performance, None, &standard), Some("performance"));
+        assert_eq!(select(Profile::Battery, Some("quiet"), &standard), Some("quiet"));
+        assert_eq!(select(Profile::Quiet, None, &standard), Some("quiet"));
+        assert_eq!(select(Profile::Quiet, None, &["low-power", "balanced"]), Some("low-power"));
 
-/// Applies the performance ACPI platform profile.
-pub fn performance() { apply_profile("performance"); }
+        // Overrides the firmware lacks, and choices of other names, are mapped by rank.
+        let vendor = ["silent", "standard", "turbo", "custom"];
+        assert_eq!(select(Profile::Battery, None, &vendor), Some("silent"));
+        assert_eq!(select(Profile::Quiet, None, &vendor), Some("silent"));
+        assert_eq!(select(Profile::Balanced, Some("balanced"), &vendor), Some("standard"));
+        assert_eq!(select(Profile::Performance, None, &vendor), Some("turbo"));
+        assert_eq!(select(Profile::Performance, Some("turbo"), &vendor), Some("turbo"));
+        assert_eq!(select(Profile::Performance, None, &["custom"]), None);
 
-/// Applies the ACPI platform profile.
-fn apply_profile(profile: &str) {
-    if let Err(why) = fs::write(SYSFS_PATH, profile) {
-        log::error!("ACPI Platform Profile: could not set to {}: {}", profile, why);
+        assert_eq!(select(Profile::Battery, None, &[]), None);
+        assert_eq!(select(Profile::Balanced, None, &[]), Some("balanced"));
+        assert_eq!(select(Profile::Battery, Some("turbo"), &[]), Some("turbo"));
     }
 }
@@ -2,24 +2,283 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use clap::{builder::PossibleValuesParser, Parser};
+use crate::{
+    charge_thresholds::builtin_charge_profiles, graphics::GraphicsMode, logging::Filter, Profile,
+};
+use clap::{
+    builder::{PossibleValue, PossibleValuesParser, TypedValueParser},
+    error::ErrorKind,
+    CommandFactory, Parser, ValueEnum,
+};
+use clap_complete::Shell;
+use log::LevelFilter;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsStr,
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const JSON_HELP: &str = "Print the output, and errors to stderr, as JSON, like --format json";
+const DRY_RUN_HELP: &str = "Print the files that would be written and the commands that would be \
+                            run, without changing anything";
+const NO_INITRAMFS_HELP: &str = "Do not rebuild the initramfs, which must then be rebuilt, such \
+                                 as with `dracut --force`, before rebooting";
+
+/// How the client prints the results of queries and errors.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Text for people to read, which may change between versions.
+    Human,
+    /// JSON, with the fields documented in the README.
+    Json,
+    /// The JSON fields as `KEY=VALUE` lines, for `eval` in shell scripts.
+    Plain,
+}
+
+/// A power profile, as named on the command line, such as `battery`.
+#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerProfile {
+    Battery,
+    Quiet,
+    Balanced,
+    Performance,
+}
+
+impl fmt::Display for PowerProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for PowerProfile {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> { ValueEnum::from_str(name, false) }
+}
+
+impl From<PowerProfile> for Profile {
+    fn from(profile: PowerProfile) -> Self {
+        match profile {
+            PowerProfile::Battery => Profile::Battery,
+            PowerProfile::Quiet => Profile::Quiet,
+            PowerProfile::Balanced => Profile::Balanced,
+            PowerProfile::Performance => Profile::Performance,
+        }
+    }
+}
+
+impl From<Profile> for PowerProfile {
+    fn from(profile: Profile) -> Self {
+        match profile {
+            Profile::Battery => PowerProfile::Battery,
+            Profile::Quiet => PowerProfile::Quiet,
+            Profile::Balanced => PowerProfile::Balanced,
+            Profile::Performance => PowerProfile::Performance,
+        }
+    }
+}
+
+/// A profile to set, as named on the command line: a built-in profile, such as `battery`, or a
+/// custom profile of `profiles.toml`, which only the daemon knows of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProfileName {
+    Builtin(PowerProfile),
+    Custom(String),
+}
+
+impl ProfileName {
+    /// The name of the profile, as known to the daemon, such as `Battery`.
+    #[must_use]
+    pub fn daemon_name(&self) -> String {
+        match self {
+            Self::Builtin(profile) => Profile::from(*profile).to_string(),
+            Self::Custom(name) => name.clone(),
+        }
+    }
+}
+
+impl From<&str> for ProfileName {
+    fn from(name: &str) -> Self {
+        name.parse().map_or_else(|_| Self::Custom(name.to_owned()), Self::Builtin)
+    }
+}
+
+/// Parses a [`ProfileName`], completing the built-in profiles, as the custom profiles are only
+/// known to the daemon, which validates them.
+#[derive(Clone)]
+struct ProfileNameParser;
+
+impl TypedValueParser for ProfileNameParser {
+    type Value = ProfileName;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let name =
+            value.to_str().ok_or_else(|| clap::Error::new(ErrorKind::InvalidUtf8).with_cmd(cmd))?;
+        Ok(ProfileName::from(name))
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        let values = PowerProfile::value_variants().iter().filter_map(ValueEnum::to_possible_value);
+        Some(Box::new(values))
+    }
+}
+
+/// Parses the name of a charge profile, completing the built-in profiles, as the custom
+/// profiles of `charge-profiles.toml` are validated once they are loaded.
+#[derive(Clone)]
+struct ChargeProfileNameParser;
+
+impl TypedValueParser for ChargeProfileNameParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let name =
+            value.to_str().ok_or_else(|| clap::Error::new(ErrorKind::InvalidUtf8).with_cmd(cmd))?;
+        Ok(name.to_owned())
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        // Possible values only borrow static names, so those of the built-in profiles are
+        // leaked once.
+        static NAMES: Lazy<Vec<&'static str>> = Lazy::new(|| {
+            builtin_charge_profiles()
+                .into_iter()
+                .map(|profile| &*Box::leak(profile.id.into_boxed_str()))
+                .collect()
+        });
+
+        Some(Box::new(NAMES.iter().copied().map(PossibleValue::new)))
+    }
+}
+
+#[derive(Parser)]
+#[clap(about = "Enable, disable or show the automatic profile switching")]
+pub enum ProfileArgs {
+    #[clap(
+        about = "Enable, disable or show the automatic profile switching",
+        long_about = "Enables, disables or shows the switching of profiles on AC/battery \
+                      transitions, as mapped in /etc/system76-power/daemon.toml"
+    )]
+    Auto {
+        #[clap(
+            help = "Enable, disable or show the automatic profile switching",
+            value_parser = PossibleValuesParser::new(["on", "off", "status"]),
+        )]
+        state: Option<String>,
+    },
+    #[clap(
+        about = "Show the schedule of profiles by time of day",
+        long_about = "Shows the time ranges mapped to profiles in the [schedule] section of \
+                      /etc/system76-power/daemon.toml, the profile of the current range, and the \
+                      next transition"
+    )]
+    Schedule,
+}
+
+#[derive(Parser)]
+#[clap(about = "Calibrate a battery")]
+pub enum ChargeThresholdsArgs {
+    #[clap(
+        about = "Calibrate a battery, discharging it to a low point then charging it full",
+        long_about = "Calibrates the first battery which can be force-discharged, so that the \
+                      charge it reports matches its capacity again: its thresholds are lifted and \
+                      it is discharged while on AC down to low_percent of the [calibration] \
+                      section of /etc/system76-power/daemon.toml, then charged full. Its \
+                      thresholds and charge behaviour are restored once it ends, is cancelled, or \
+                      when the daemon restarts."
+    )]
+    Calibrate {
+        #[clap(long = "cancel", help = "Cancel the calibration running, restoring the battery")]
+        cancel: bool,
+        #[clap(
+            long = "watch",
+            help = "Print the progress of the calibration until it ends, without starting one",
+            conflicts_with = "cancel"
+        )]
+        watch:  bool,
+    },
+}
 
 #[derive(Parser)]
 #[clap(
     about = "Query or set the graphics mode",
     long_about = "Query or set the graphics mode.\n\n - If an argument is not provided, the \
-                  graphics profile will be queried\n - Otherwise, that profile will be set, if it \
-                  is a valid profile\n\nA reboot is required after switching modes."
+                  active and configured modes, the power state of the discrete GPU and whether a \
+                  reboot is pending are shown\n - Otherwise, that profile will be set, if it is a \
+                  valid profile\n\nA reboot is required after switching modes.",
+    infer_subcommands = true
 )]
 pub enum GraphicsArgs {
-    #[clap(about = "Like integrated, but the dGPU is available for compute")]
-    Compute,
-    #[clap(about = "Set the graphics mode to Hybrid (PRIME)")]
-    Hybrid,
-    #[clap(about = "Set the graphics mode to integrated")]
-    Integrated,
-    #[clap(about = "Set the graphics mode to NVIDIA")]
-    Nvidia,
+    #[clap(about = "Show graphics switching capabilities and driver versions")]
+    Capabilities,
+    #[clap(
+        about = "Like integrated, but the dGPU is available for compute",
+        visible_alias = "comp"
+    )]
+    Compute {
+        #[clap(long = "no-initramfs", help = NO_INITRAMFS_HELP)]
+        no_initramfs: bool,
+    },
+    #[clap(
+        about = "Show the graphics mode recommended for this model, and why",
+        long_about = "Show the graphics mode recommended for this model, how it was derived, and \
+                      the configured mode to compare it with"
+    )]
+    Default {
+        #[clap(
+            long = "apply",
+            help = "Switch to the recommended mode, unless it is configured already"
+        )]
+        apply: bool,
+    },
+    #[clap(about = "Determines if external displays require the discrete GPU")]
+    HotplugCheck,
+    #[clap(about = "List the GPUs on the PCI bus")]
+    List,
+    #[clap(about = "Set the graphics mode to Hybrid (PRIME)", visible_alias = "hyb")]
+    Hybrid {
+        #[clap(long = "no-initramfs", help = NO_INITRAMFS_HELP)]
+        no_initramfs: bool,
+    },
+    #[clap(about = "Set the graphics mode to integrated", visible_alias = "int")]
+    Integrated {
+        #[clap(long = "no-initramfs", help = NO_INITRAMFS_HELP)]
+        no_initramfs: bool,
+    },
+    #[clap(about = "Set the graphics mode to NVIDIA", visible_alias = "nv")]
+    Nvidia {
+        #[clap(long = "no-initramfs", help = NO_INITRAMFS_HELP)]
+        no_initramfs: bool,
+    },
+    #[clap(
+        about = "Set an option of the generated driver configuration",
+        long_about = "Set an option of the generated driver configuration.\n\nAvailable \
+                      options:\n - gsp=on|off|default: whether the NVIDIA driver uses its GSP \
+                      firmware\n\nA reboot is required for the option to take effect."
+    )]
+    SetOption {
+        #[clap(help = "Option to set, as key=value", value_name = "KEY=VALUE")]
+        option: String,
+    },
+    #[clap(about = "Print graphics mode changes as they happen")]
+    Watch,
     #[clap(about = "Determines if the system has switchable graphics")]
     Switchable,
     #[clap(about = "Query or set the discrete graphics power state")]
@@ -32,6 +291,34 @@ pub enum GraphicsArgs {
     },
 }
 
+/// The mode set by a graphics subcommand, such as `graphics hybrid`.
+impl TryFrom<&GraphicsArgs> for GraphicsMode {
+    type Error = ();
+
+    fn try_from(args: &GraphicsArgs) -> Result<Self, Self::Error> {
+        match args {
+            GraphicsArgs::Compute { .. } => Ok(GraphicsMode::Compute),
+            GraphicsArgs::Hybrid { .. } => Ok(GraphicsMode::Hybrid),
+            GraphicsArgs::Integrated { .. } => Ok(GraphicsMode::Integrated),
+            GraphicsArgs::Nvidia { .. } => Ok(GraphicsMode::Discrete),
+            _ => Err(()),
+        }
+    }
+}
+
+impl GraphicsArgs {
+    /// Whether a mode subcommand is given `--no-initramfs`.
+    pub fn no_initramfs(&self) -> bool {
+        matches!(
+            self,
+            Self::Compute { no_initramfs: true }
+                | Self::Hybrid { no_initramfs: true }
+                | Self::Integrated { no_initramfs: true }
+                | Self::Nvidia { no_initramfs: true }
+        )
+    }
+}
+
 #[derive(Parser)]
 #[clap(
     name = "system76-power",
@@ -39,8 +326,149 @@ pub enum GraphicsArgs {
     version = env!("CARGO_PKG_VERSION"),
     subcommand_required = true,
     arg_required_else_help = true,
+    infer_subcommands = true,
 )]
-pub enum Args {
+pub struct Args {
+    #[clap(
+        short = 'q',
+        long = "quiet",
+        help = "Print only results and errors, on a single line, and only log errors, or nothing \
+                for the daemon",
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    pub quiet:   bool,
+    #[clap(
+        short = 'v',
+        long = "verbose",
+        help = "Log debug messages, including the DBus calls of the client, with their arguments \
+                and duration",
+        global = true
+    )]
+    pub verbose: bool,
+    #[clap(
+        long = "format",
+        value_enum,
+        help = "Print results and errors as text, JSON, or KEY=VALUE lines for shell eval",
+        global = true
+    )]
+    pub format:  Option<Format>,
+    #[clap(
+        long = "timeout",
+        value_name = "SECONDS",
+        help = "Give up on a DBus call the daemon does not answer within this many seconds \
+                [default: 30 for queries, 120 for changes, which may wait for authorization], and \
+                stop following a graphics switch after as long; it continues in the daemon",
+        global = true
+    )]
+    pub timeout: Option<u64>,
+    #[clap(
+        long = "direct",
+        help = "As root, make a change without the daemon if it is not running, as it would: set \
+                a profile, a graphics mode, the graphics power or charge thresholds",
+        global = true
+    )]
+    pub direct:  bool,
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+impl Args {
+    /// Checks the relations between arguments which their definitions cannot express, as
+    /// usage errors. The daemon checks the thresholds against the hardware as well.
+    pub fn validate(mut self) -> Result<Self, clap::Error> {
+        if self.command.json() && matches!(self.format, Some(Format::Human | Format::Plain)) {
+            return Err(Self::command().error(
+                ErrorKind::ArgumentConflict,
+                "--json cannot be used with --format human or --format plain",
+            ));
+        }
+
+        if let Command::ChargeThresholds { start: Some(start), end: Some(end), .. } = self.command {
+            if end <= start {
+                return Err(Self::command().error(
+                    ErrorKind::ValueValidation,
+                    format!(
+                        "the end threshold ({}) must be greater than the start threshold ({})",
+                        end, start
+                    ),
+                ));
+            }
+        }
+
+        if let Command::ChargeThresholds {
+            cmd: Some(_),
+            profile,
+            list_profiles,
+            start,
+            battery,
+            watch,
+            ..
+        } = &self.command
+        {
+            if profile.is_some() || *list_profiles || start.is_some() || battery.is_some() || *watch
+            {
+                return Err(Self::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "calibrate takes no thresholds, --profile, --list-profiles, --battery or \
+                     --watch before it",
+                ));
+            }
+        }
+
+        if let Command::ChargeThresholds { battery: Some(_), profile: None, start: None, .. } =
+            self.command
+        {
+            return Err(Self::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "--battery only applies to setting thresholds or a profile",
+            ));
+        }
+
+        // The client prints JSON and plain output from the same structures, so both select it.
+        if let (Some(Format::Json | Format::Plain), Some(json)) =
+            (self.format, self.command.json_mut())
+        {
+            *json = true;
+        }
+
+        Ok(self)
+    }
+
+    /// The format of the output: `--format`, or JSON with `--json`.
+    #[must_use]
+    pub fn format(&self) -> Format {
+        match self.format {
+            Some(format) => format,
+            None if self.command.json() => Format::Json,
+            None => Format::Human,
+        }
+    }
+
+    /// The logs to keep: warnings and errors for the client, or only errors with `--quiet`,
+    /// and `info` for the daemon and `apply`, or none with `--quiet`. `--verbose` adds debug
+    /// messages, and `--log-level` of the daemon overrides both.
+    #[must_use]
+    pub fn filter(&self) -> Filter {
+        let daemon = match &self.command {
+            Command::Daemon { log_level: Some(filter), .. } => return filter.clone(),
+            Command::Daemon { check, .. } => !check,
+            Command::Apply => true,
+            _ => false,
+        };
+
+        Filter::new(match (self.verbose, self.quiet, daemon) {
+            (true, ..) => LevelFilter::Debug,
+            (false, true, true) => LevelFilter::Off,
+            (false, true, false) => LevelFilter::Error,
+            (false, false, true) => LevelFilter::Info,
+            (false, false, false) => LevelFilter::Warn,
+        })
+    }
+}
+
+#[derive(Parser)]
+pub enum Command {
     #[clap(
         about = "Runs the program in daemon mode",
         long_about = "Registers a new DBUS service and starts an event loop to listen for, and \
@@ -48,62 +476,557 @@ pub enum Args {
     )]
     Daemon {
         #[clap(
-            short = 'q',
-            long = "quiet",
-            help = "Set the verbosity of daemon logs to 'off' [default is 'info']",
-            global = true,
-            group = "verbosity"
+            long = "log-level",
+            value_name = "FILTER",
+            help = "Set the verbosity of daemon logs, per target, such as 'debug' or \
+                    'info,zbus=debug', instead of --quiet and --verbose"
         )]
-        quiet:   bool,
+        log_level:  Option<Filter>,
         #[clap(
-            short = 'v',
-            long = "verbose",
-            help = "Set the verbosity of daemon logs to 'debug' [default is 'info']",
-            global = true,
-            group = "verbosity"
+            long = "no-restore",
+            help = "Start with the default settings instead of those of the previous run"
+        )]
+        no_restore: bool,
+        #[clap(
+            long = "check",
+            help = "Verify the environment the daemon relies on, instead of starting it"
         )]
-        verbose: bool,
+        check:      bool,
     },
+    #[clap(
+        about = "Apply the saved settings without the daemon, and exit",
+        long_about = "Applies the settings the daemon restores when it starts, without starting \
+                      it: the graphics power, or the automatic power of the discrete GPU if \
+                      enabled, the power profile and the charge thresholds, as saved in \
+                      /var/lib/system76-power/state.json. Every setting is attempted, and the \
+                      command fails if any of them failed. Refuses to run while the daemon is \
+                      running, as it applies them itself."
+    )]
+    Apply,
     #[clap(
         about = "Query or set the power profile",
         long_about = "Queries or sets the power profile.\n\n - If an argument is not provided, \
-                      the power profile will be queried\n - Otherwise, that profile will be set, \
-                      if it is a valid profile"
+                      the power profile will be queried, with the profile restored on startup if \
+                      it differs\n - Otherwise, that profile will be set, if it is a valid \
+                      profile, and restored on startup unless `--temporary` is given: battery, \
+                      quiet, balanced, performance, or a custom profile of \
+                      /etc/system76-power/profiles.toml\n - `auto on|off|status` controls \
+                      switching profiles on AC/battery transitions, as mapped in \
+                      /etc/system76-power/daemon.toml",
+        visible_alias = "prof"
     )]
     Profile {
         #[clap(
             help = "set the power profile",
-            default_value = None,
-            value_parser = PossibleValuesParser::new(["battery", "balanced", "performance"]),
+            value_parser = ProfileNameParser,
+            hide_possible_values = true
+        )]
+        profile:   Option<ProfileName>,
+        #[clap(subcommand)]
+        cmd:       Option<ProfileArgs>,
+        #[clap(
+            long = "temporary",
+            help = "Set the profile for this boot only, keeping the saved profile to restore",
+            requires = "profile"
         )]
-        profile: Option<String>,
+        temporary: bool,
+        #[clap(
+            long = "strict",
+            help = "Fail if any parameter of the profile fails to apply, rather than warning",
+            requires = "profile"
+        )]
+        strict:    bool,
+        #[clap(
+            long = "watch",
+            help = "Print power profile changes as they happen",
+            conflicts_with = "profile"
+        )]
+        watch:     bool,
+        #[clap(
+            long = "list",
+            help = "List the profiles, with the parameters they set on this machine",
+            conflicts_with_all = &["profile", "watch"]
+        )]
+        list:      bool,
+        #[clap(long = "dry-run", help = DRY_RUN_HELP, global = true)]
+        dry_run:   bool,
+        #[clap(long = "json", help = JSON_HELP, global = true)]
+        json:      bool,
     },
+    #[clap(visible_alias = "gfx")]
     Graphics {
+        #[clap(
+            long = "force",
+            help = "Switch even if processes are using the discrete GPU; with `power off`, power \
+                    it off even if in use, stopping the services keeping it open",
+            global = true
+        )]
+        force:   bool,
+        #[clap(
+            long = "wait",
+            help = "Show the progress of a mode switch until it finishes [default when stdout is \
+                    a terminal]",
+            global = true,
+            overrides_with = "no_wait"
+        )]
+        wait:    bool,
+        #[clap(
+            long = "no-wait",
+            help = "Return as soon as a mode switch has started, printing its job",
+            global = true,
+            overrides_with = "wait"
+        )]
+        no_wait: bool,
+        #[clap(long = "dry-run", help = DRY_RUN_HELP, global = true)]
+        dry_run: bool,
+        #[clap(long = "json", help = JSON_HELP, global = true)]
+        json:    bool,
+        #[clap(long = "short", help = "Print only the active mode, as earlier versions did")]
+        short:   bool,
         #[clap(subcommand)]
-        cmd: Option<GraphicsArgs>,
+        cmd:     Option<GraphicsArgs>,
     },
     #[clap(
         about = "Set thresholds for battery charging",
+        visible_alias = "ct",
         // Autogenerated usage seemed to have issues
-        override_usage = "system76-power charge-thresholds [<start> <end> | --profile <profile>]",
+        override_usage = "system76-power charge-thresholds [<start> <end> | --profile <profile> | \
+                          --list-profiles | --watch | calibrate [--cancel | --watch]]",
     )]
     ChargeThresholds {
         #[clap(
             long = "profile",
-            help = "Profile name",
-            value_parser = PossibleValuesParser::new(["full_charge", "balanced", "max_lifespan"]),
-            group = "profile-or-thresholds",
+            help = "Profile name: full_charge, balanced, max_lifespan, or one defined in \
+                    /etc/system76-power/charge-profiles.toml",
+            value_parser = ChargeProfileNameParser,
+            hide_possible_values = true,
+            group = "profile-or-thresholds"
         )]
         profile:       Option<String>,
         #[clap(long = "list-profiles", help = "List profiles", group = "profile-or-thresholds")]
         list_profiles: bool,
         #[clap(
-            help = "Charge thresholds",
+            long = "battery",
+            value_name = "battery",
+            help = "Battery to set, such as BAT1, instead of every battery",
+            conflicts_with = "list_profiles"
+        )]
+        battery:       Option<String>,
+        #[clap(
+            long = "watch",
+            help = "Print charge threshold changes as they happen, including those made outside \
+                    of the daemon",
+            conflicts_with_all = &["profile", "list_profiles", "start", "battery"]
+        )]
+        watch:         bool,
+        #[clap(
+            help = "Charge below which charging resumes, in percent",
+            value_name = "start",
             value_parser = clap::value_parser!(u8).range(0..=100),
-            number_of_values = 2,
-            value_names = &["start", "end"],
+            requires = "end",
             group = "profile-or-thresholds",
         )]
-        thresholds:    Vec<u8>,
+        start:         Option<u8>,
+        #[clap(
+            help = "Charge at which charging stops, in percent, above the start",
+            value_name = "end",
+            value_parser = clap::value_parser!(u8).range(0..=100),
+            requires = "start"
+        )]
+        end:           Option<u8>,
+        #[clap(subcommand)]
+        cmd:           Option<ChargeThresholdsArgs>,
+        #[clap(long = "dry-run", help = DRY_RUN_HELP, global = true)]
+        dry_run:       bool,
+        #[clap(long = "json", help = JSON_HELP, global = true)]
+        json:          bool,
+    },
+    #[clap(
+        about = "Show the health and status of the batteries",
+        long_about = "Shows the charge, status, cycle count, health, model, charge thresholds and \
+                      rate of each system battery. AC adapters and the batteries of peripherals \
+                      are left out, and attributes a battery does not report are shown as unknown."
+    )]
+    Battery {
+        #[clap(long = "json", help = JSON_HELP)]
+        json: bool,
+    },
+    #[clap(
+        about = "Print a report of the machine, its graphics and power settings, for bug reports",
+        long_about = "Prints a report for bug reports: the machine, its GPUs and their drivers, \
+                      the graphics mode and the files configuring it, the initramfs tool, the \
+                      profile and its parameters, the charge thresholds, conflicting services and \
+                      the last graphics switch. Sections which cannot be read report why, and the \
+                      rest of the report is still printed."
+    )]
+    Info {
+        #[clap(long = "json", help = JSON_HELP)]
+        json: bool,
+    },
+    #[clap(
+        about = "Print the completion script of a shell",
+        long_about = "Prints the completion script of a shell to stdout, completing the \
+                      subcommands, profile names and graphics modes",
+        hide = true
+    )]
+    Completions {
+        #[clap(help = "Shell to complete for")]
+        shell: Shell,
+    },
+    #[clap(
+        about = "Write the man pages to a directory",
+        long_about = "Writes system76-power.1, and a page per subcommand such as \
+                      system76-power-graphics.1, to a directory, for packages to install",
+        hide = true
+    )]
+    Mangen {
+        #[clap(help = "Directory to write the pages to")]
+        dir: PathBuf,
     },
 }
+
+impl Command {
+    /// Whether the output, and errors, are printed as JSON, or as plain output after
+    /// [`Args::validate`].
+    pub fn json(&self) -> bool {
+        match self {
+            Self::Profile { json, .. }
+            | Self::Graphics { json, .. }
+            | Self::ChargeThresholds { json, .. }
+            | Self::Battery { json }
+            | Self::Info { json } => *json,
+            Self::Daemon { .. } | Self::Apply | Self::Completions { .. } | Self::Mangen { .. } => {
+                false
+            }
+        }
+    }
+
+    fn json_mut(&mut self) -> Option<&mut bool> {
+        match self {
+            Self::Profile { json, .. }
+            | Self::Graphics { json, .. }
+            | Self::ChargeThresholds { json, .. }
+            | Self::Battery { json }
+            | Self::Info { json } => Some(json),
+            Self::Daemon { .. } | Self::Apply | Self::Completions { .. } | Self::Mangen { .. } => {
+                None
+            }
+        }
+    }
+
+    /// Whether the changes are only printed, without making them.
+    pub fn dry_run(&self) -> bool {
+        match self {
+            Self::Profile { dry_run, .. }
+            | Self::Graphics { dry_run, .. }
+            | Self::ChargeThresholds { dry_run, .. } => *dry_run,
+            Self::Daemon { .. }
+            | Self::Apply
+            | Self::Battery { .. }
+            | Self::Info { .. }
+            | Self::Completions { .. }
+            | Self::Mangen { .. } => false,
+        }
+    }
+}
+
+/// Writes the completion script of a shell, generated from the definitions above.
+pub fn completions(shell: Shell, out: &mut dyn io::Write) {
+    clap_complete::generate(shell, &mut Args::command(), "system76-power", out);
+}
+
+/// Writes the man pages of the command and its visible subcommands, generated from the
+/// definitions above.
+pub fn man_pages(dir: &Path) -> io::Result<()> { clap_mangen::generate_to(Args::command(), dir) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn completions_name_subcommands() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = Vec::new();
+            completions(shell, &mut script);
+            let script = String::from_utf8(script).unwrap();
+
+            for name in
+                ["charge-thresholds", "graphics", "profile", "hybrid", "integrated", "max_lifespan"]
+            {
+                assert!(script.contains(name), "{} completions lack {}", shell, name);
+            }
+        }
+
+        for shell in [Shell::Bash, Shell::Zsh] {
+            let mut script = Vec::new();
+            completions(shell, &mut script);
+            let script = String::from_utf8(script).unwrap();
+
+            for alias in ["ct", "gfx", "prof", "hyb", "int"] {
+                assert!(script.contains(alias), "{} completions lack {}", shell, alias);
+            }
+        }
+    }
+
+    #[test]
+    fn man_pages_name_subcommands() {
+        let dir = TempDir::new("man");
+        man_pages(&dir).unwrap();
+
+        // roff escapes the hyphens of names.
+        let page =
+            std::fs::read_to_string(dir.join("system76-power.1")).unwrap().replace("\\-", "-");
+        for command in Args::command().get_subcommands().filter(|command| !command.is_hide_set()) {
+            let name = command.get_name();
+            assert!(page.contains(name), "system76-power.1 lacks {}", name);
+
+            let page = dir.join(format!("system76-power-{}.1", name));
+            assert!(page.exists(), "{} was not written", page.display());
+        }
+
+        let graphics = std::fs::read_to_string(dir.join("system76-power-graphics.1")).unwrap();
+        assert!(graphics.contains("integrated"));
+    }
+
+    #[test]
+    fn power_profiles_round_trip() {
+        for profile in Profile::ALL {
+            let power_profile = PowerProfile::from(profile);
+            assert_eq!(power_profile.to_string().parse(), Ok(power_profile));
+            assert_eq!(Profile::from(power_profile), profile);
+        }
+
+        let args = Args::try_parse_from(["system76-power", "profile", "battery"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Profile { profile: Some(ProfileName::Builtin(PowerProfile::Battery)), .. }
+        ));
+        let args = Args::try_parse_from(["system76-power", "profile", "auto", "on", "--json"]);
+        assert!(matches!(
+            args.unwrap().command,
+            Command::Profile { cmd: Some(ProfileArgs::Auto { .. }), json: true, .. }
+        ));
+
+        // Custom profiles are only known to the daemon, which validates them.
+        let Command::Profile { profile: Some(profile), .. } =
+            Args::try_parse_from(["system76-power", "profile", "turbo"]).unwrap().command
+        else {
+            panic!("custom profiles are refused");
+        };
+        assert_eq!(profile, ProfileName::Custom("turbo".into()));
+        assert_eq!(profile.daemon_name(), "turbo");
+        assert_eq!(ProfileName::Builtin(PowerProfile::Battery).daemon_name(), "Battery");
+
+        let args = ["system76-power", "profile", "performance", "--temporary"];
+        assert!(matches!(
+            Args::try_parse_from(args).unwrap().command,
+            Command::Profile {
+                profile: Some(ProfileName::Builtin(PowerProfile::Performance)),
+                temporary: true,
+                ..
+            }
+        ));
+        assert!(Args::try_parse_from(["system76-power", "profile", "--temporary"]).is_err());
+    }
+
+    #[test]
+    fn graphics_modes_round_trip() {
+        for mode in GraphicsMode::ALL {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+
+            let args = Args::try_parse_from(["system76-power", "graphics", &mode.to_string()]);
+            let Command::Graphics { cmd: Some(cmd), .. } = args.unwrap().command else {
+                panic!("{} is not a graphics subcommand", mode);
+            };
+            assert_eq!(GraphicsMode::try_from(&cmd), Ok(mode));
+        }
+
+        let args = ["system76-power", "graphics", "hybrid", "--no-initramfs", "--dry-run"];
+        let Command::Graphics { cmd: Some(cmd), dry_run: true, .. } =
+            Args::try_parse_from(args).unwrap().command
+        else {
+            panic!("--no-initramfs and --dry-run are not combined");
+        };
+        assert_eq!(
+            (GraphicsMode::try_from(&cmd), cmd.no_initramfs()),
+            (Ok(GraphicsMode::Hybrid), true)
+        );
+        assert!(Args::try_parse_from(["system76-power", "graphics", "--no-initramfs"]).is_err());
+        assert!(Args::try_parse_from(["system76-power", "graphics", "power", "--no-initramfs"])
+            .is_err());
+
+        assert_eq!("discrete".parse::<GraphicsMode>(), Err(()));
+        assert_eq!(GraphicsMode::try_from(&GraphicsArgs::Watch), Err(()));
+    }
+
+    #[test]
+    fn aliases() {
+        let command = |args: &[&str]| {
+            Args::try_parse_from([&["system76-power"][..], args].concat()).unwrap().command
+        };
+        let mode = |args: &[&str]| match command(args) {
+            Command::Graphics { cmd: Some(cmd), .. } => GraphicsMode::try_from(&cmd).ok(),
+            _ => None,
+        };
+
+        for args in [&["gfx", "int"][..], &["graphics", "int"], &["graph", "integ"], &["g", "i"]] {
+            assert_eq!(mode(args), Some(GraphicsMode::Integrated), "{:?}", args);
+        }
+        assert_eq!(mode(&["gfx", "hyb"]), Some(GraphicsMode::Hybrid));
+        assert_eq!(mode(&["gfx", "nv"]), Some(GraphicsMode::Discrete));
+        assert_eq!(mode(&["gfx", "comp"]), Some(GraphicsMode::Compute));
+        assert!(matches!(
+            command(&["gfx", "pow", "off"]),
+            Command::Graphics { cmd: Some(GraphicsArgs::Power { state: Some(_) }), .. }
+        ));
+
+        for args in [&["ct", "--list-profiles"][..], &["charge", "--list-profiles"]] {
+            assert!(
+                matches!(command(args), Command::ChargeThresholds { list_profiles: true, .. }),
+                "{:?}",
+                args
+            );
+        }
+        for args in [&["prof", "battery"][..], &["pro", "battery"]] {
+            assert!(matches!(
+                command(args),
+                Command::Profile { profile: Some(ProfileName::Builtin(PowerProfile::Battery)), .. }
+            ));
+        }
+
+        // A prefix of several subcommands, such as hybrid and hotplug-check, stays an error.
+        assert!(Args::try_parse_from(["system76-power", "graphics", "h"]).is_err());
+    }
+
+    #[test]
+    fn verbosity() {
+        let filter = |args: &[&str]| {
+            let args = Args::try_parse_from([&["system76-power"][..], args].concat()).unwrap();
+            args.filter().level("system76_power")
+        };
+
+        assert_eq!(filter(&["graphics"]), LevelFilter::Warn);
+        assert_eq!(filter(&["graphics", "hybrid", "-v"]), LevelFilter::Debug);
+        assert_eq!(filter(&["-q", "profile"]), LevelFilter::Error);
+        assert_eq!(filter(&["daemon"]), LevelFilter::Info);
+        assert_eq!(filter(&["daemon", "--quiet"]), LevelFilter::Off);
+        assert_eq!(filter(&["daemon", "-v"]), LevelFilter::Debug);
+        assert_eq!(filter(&["daemon", "--log-level", "trace"]), LevelFilter::Trace);
+        assert_eq!(filter(&["apply"]), LevelFilter::Info);
+        assert_eq!(filter(&["apply", "-q"]), LevelFilter::Off);
+        assert!(Args::try_parse_from(["system76-power", "-q", "-v", "profile"]).is_err());
+    }
+
+    #[test]
+    fn charge_thresholds() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from([&["system76-power", "charge-thresholds"][..], args].concat())
+                .and_then(Args::validate)
+                .map(|args| match args.command {
+                    Command::ChargeThresholds { start, end, .. } => (start, end),
+                    _ => unreachable!(),
+                })
+        };
+        let kind = |args: &[&str]| parse(args).unwrap_err().kind();
+
+        assert_eq!(parse(&["40", "80"]).unwrap(), (Some(40), Some(80)));
+        assert_eq!(parse(&["0", "100"]).unwrap(), (Some(0), Some(100)));
+        assert_eq!(parse(&[]).unwrap(), (None, None));
+
+        assert_eq!(kind(&["80", "40"]), ErrorKind::ValueValidation);
+        assert_eq!(kind(&["60", "60"]), ErrorKind::ValueValidation);
+        assert_eq!(kind(&["40", "101"]), ErrorKind::ValueValidation);
+        assert_eq!(kind(&["40"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(kind(&["40", "80", "--profile", "balanced"]), ErrorKind::ArgumentConflict);
+        assert_eq!(parse(&["40", "80", "--battery", "BAT1"]).unwrap(), (Some(40), Some(80)));
+        assert!(parse(&["--profile", "balanced", "--battery", "BAT1"]).is_ok());
+        assert_eq!(kind(&["--battery", "BAT1"]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(parse(&["--watch"]).unwrap(), (None, None));
+        assert_eq!(kind(&["40", "80", "--watch"]), ErrorKind::ArgumentConflict);
+        assert_eq!(kind(&["--list-profiles", "--watch"]), ErrorKind::ArgumentConflict);
+
+        let names: Vec<_> = ChargeProfileNameParser
+            .possible_values()
+            .unwrap()
+            .map(|value| value.get_name().to_owned())
+            .collect();
+        assert_eq!(names, ["full_charge", "balanced", "max_lifespan"]);
+
+        let why = parse(&["80", "40"]).unwrap_err().to_string();
+        assert!(why.contains("end threshold (40) must be greater than the start threshold (80)"));
+
+        let calibrate = |args: &[&str]| {
+            Args::try_parse_from([&["system76-power", "ct"][..], args].concat())
+                .and_then(Args::validate)
+                .map(|args| match args.command {
+                    Command::ChargeThresholds {
+                        cmd: Some(ChargeThresholdsArgs::Calibrate { cancel, watch }),
+                        json,
+                        ..
+                    } => (cancel, watch, json),
+                    _ => unreachable!(),
+                })
+        };
+        assert_eq!(calibrate(&["calibrate"]).unwrap(), (false, false, false));
+        assert_eq!(calibrate(&["calibrate", "--cancel"]).unwrap(), (true, false, false));
+        assert_eq!(calibrate(&["calibrate", "--watch", "--json"]).unwrap(), (false, true, true));
+        let kind = |args: &[&str]| calibrate(args).unwrap_err().kind();
+        assert_eq!(kind(&["calibrate", "--cancel", "--watch"]), ErrorKind::ArgumentConflict);
+        assert_eq!(kind(&["--watch", "calibrate"]), ErrorKind::ArgumentConflict);
+        assert_eq!(kind(&["--battery", "BAT1", "calibrate"]), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn formats() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from([&["system76-power"][..], args].concat()).and_then(Args::validate)
+        };
+
+        let args = parse(&["graphics"]).unwrap();
+        assert_eq!((args.format(), args.command.json()), (Format::Human, false));
+        let args = parse(&["graphics", "--json"]).unwrap();
+        assert_eq!((args.format(), args.command.json()), (Format::Json, true));
+        let args = parse(&["--format", "plain", "charge-thresholds"]).unwrap();
+        assert_eq!((args.format(), args.command.json()), (Format::Plain, true));
+        let args = parse(&["profile", "--format", "json", "--json"]).unwrap();
+        assert_eq!((args.format(), args.command.json()), (Format::Json, true));
+        let args = parse(&["battery", "--format", "plain"]).unwrap();
+        assert_eq!((args.format(), args.command.json()), (Format::Plain, true));
+
+        let why = parse(&["profile", "--json", "--format", "plain"]).err().unwrap();
+        assert_eq!(why.kind(), ErrorKind::ArgumentConflict);
+        assert!(parse(&["profile", "--format", "yaml"]).is_err());
+    }
+
+    #[test]
+    fn dry_run() {
+        let dry_run = |args: &[&str]| {
+            Args::try_parse_from([&["system76-power"][..], args].concat())
+                .map(|args| args.command.dry_run())
+        };
+
+        assert!(dry_run(&["profile", "battery", "--dry-run"]).unwrap());
+        assert!(dry_run(&["graphics", "power", "off", "--dry-run"]).unwrap());
+        assert!(dry_run(&["charge-thresholds", "--dry-run", "40", "80"]).unwrap());
+        assert!(dry_run(&["charge-thresholds", "--profile", "balanced", "--dry-run"]).unwrap());
+        assert!(dry_run(&["ct", "calibrate", "--dry-run"]).unwrap());
+        assert!(!dry_run(&["charge-thresholds", "40", "80"]).unwrap());
+        assert!(dry_run(&["battery", "--dry-run"]).is_err());
+    }
+
+    #[test]
+    fn timeout() {
+        let timeout = |args: &[&str]| {
+            Args::try_parse_from([&["system76-power"][..], args].concat()).map(|args| args.timeout)
+        };
+
+        assert_eq!(timeout(&["graphics", "hybrid"]).unwrap(), None);
+        assert_eq!(
+            timeout(&["graphics", "hybrid", "--wait", "--timeout", "300"]).unwrap(),
+            Some(300)
+        );
+        assert_eq!(timeout(&["--timeout", "5", "profile"]).unwrap(), Some(5));
+        assert!(timeout(&["graphics", "hybrid", "--wait-timeout", "300"]).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Turbo boost of the CPUs, through whichever interface the scaling driver provides:
+//! `intel_pstate/no_turbo`, the global `cpufreq/boost` of acpi-cpufreq and amd-pstate, or the
+//! `boost` file of each cpufreq policy on some kernels.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::util::Written;
+
+const CPU_PATH: &str = "/sys/devices/system/cpu";
+
+/// Whether boost was enabled before a profile first changed it, to restore it with the
+/// profiles which leave it alone.
+static ORIGINAL: Mutex<Option<bool>> = Mutex::new(None);
+
+/// The interface controlling boost, with the files it is written to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Boost {
+    /// `intel_pstate/no_turbo`, which holds the opposite of boost.
+    IntelPstate(PathBuf),
+    /// `cpufreq/boost`, of every CPU.
+    Global(PathBuf),
+    /// `cpufreq/policy*/boost`, in order.
+    PerPolicy(Vec<PathBuf>),
+}
+
+impl Boost {
+    /// The interface of this machine, if it has one.
+    #[must_use]
+    pub fn detect() -> Option<Self> { Self::detect_in(Path::new(CPU_PATH)) }
+
+    /// The interface of a tree such as `/sys/devices/system/cpu`, preferring intel_pstate, as
+    /// it overrides the others.
+    pub(crate) fn detect_in(root: &Path) -> Option<Self> {
+        let no_turbo = root.join("intel_pstate/no_turbo");
+        if no_turbo.exists() {
+            return Some(Self::IntelPstate(no_turbo));
+        }
+
+        let global = root.join("cpufreq/boost");
+        if global.exists() {
+            return Some(Self::Global(global));
+        }
+
+        let mut policies = fs::read_dir(root.join("cpufreq"))
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let index = name.strip_prefix("policy")?.parse::<usize>().ok()?;
+                let path = entry.path().join("boost");
+                path.exists().then_some((index, path))
+            })
+            .collect::<Vec<_>>();
+
+        policies.sort_unstable();
+        let policies = policies.into_iter().map(|(_, path)| path).collect::<Vec<_>>();
+        (!policies.is_empty()).then_some(Self::PerPolicy(policies))
+    }
+
+    /// Name of the interface, such as `cpufreq`.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::IntelPstate(_) => "intel_pstate",
+            Self::Global(_) => "cpufreq",
+            Self::PerPolicy(_) => "policy",
+        }
+    }
+
+    /// Whether boost is enabled, as the first file tells, or `None` if it cannot be read.
+    #[must_use]
+    pub fn get(&self) -> Option<bool> {
+        let path = self.paths().first()?;
+        let enabled = match fs::read_to_string(path).ok()?.trim() {
+            "0" => false,
+            "1" => true,
+            _ => return None,
+        };
+
+        Some(enabled != matches!(self, Self::IntelPstate(_)))
+    }
+
+    /// The values enabling or disabling boost, as written to each file.
+    #[must_use]
+    pub fn plan(&self, enabled: bool) -> Vec<Written> {
+        let (name, value) = match self {
+            Self::IntelPstate(_) => ("no_turbo", !enabled),
+            _ => ("boost", enabled),
+        };
+
+        let value = if value { "1" } else { "0" };
+        self.paths().iter().map(|path| Written::new(name, path.to_string_lossy(), value)).collect()
+    }
+
+    /// The files of the interface, with the values they hold, to report the state of boost.
+    #[must_use]
+    pub fn read_from(&self) -> Vec<Written> {
+        self.get().map_or_else(Vec::new, |enabled| self.plan(enabled))
+    }
+
+    /// Remembers whether boost is enabled, unless a profile already changed it since it was
+    /// last restored.
+    pub fn remember(&self) {
+        let mut original = ORIGINAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if original.is_none() {
+            *original = self.get();
+        }
+    }
+
+    fn paths(&self) -> &[PathBuf] {
+        match self {
+            Self::IntelPstate(path) | Self::Global(path) => std::slice::from_ref(path),
+            Self::PerPolicy(paths) => paths,
+        }
+    }
+}
+
+/// Whether boost was enabled before a profile changed it, if one did.
+#[must_use]
+pub fn original() -> Option<bool> {
+    *ORIGINAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Takes the value to restore, so that changes made by others afterwards are kept.
+pub fn restore() -> Option<bool> {
+    ORIGINAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn set(boost: &Boost, enabled: bool) -> Vec<Written> {
+        let written = boost.plan(enabled);
+        written.iter().for_each(|write| write.write().unwrap());
+        written
+    }
+
+    /// A tree of `/sys/devices/system/cpu` with the files of `files`, relative to it.
+    fn tree(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let root = TempDir::new(&format!("boost-{}", name));
+        for (file, value) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn intel_pstate() {
+        let root = tree("intel", &[("intel_pstate/no_turbo", "0\n"), ("cpufreq/boost", "1\n")]);
+        let boost = Boost::detect_in(&root).unwrap();
+        assert_eq!(boost, Boost::IntelPstate(root.join("intel_pstate/no_turbo")));
+        assert_eq!(boost.get(), Some(true));
+
+        let written = set(&boost, false);
+        assert_eq!((written[0].name, written[0].value.as_str()), ("no_turbo", "1"));
+        assert_eq!(boost.get(), Some(false));
+    }
+
+    #[test]
+    fn global() {
+        let root = tree("global", &[("cpufreq/boost", "1\n"), ("cpufreq/policy0/boost", "1\n")]);
+        let boost = Boost::detect_in(&root).unwrap();
+        assert_eq!(boost, Boost::Global(root.join("cpufreq/boost")));
+        assert_eq!(boost.name(), "cpufreq");
+
+        set(&boost, false);
+        assert_eq!(fs::read_to_string(root.join("cpufreq/boost")).unwrap(), "0");
+        assert_eq!(boost.get(), Some(false));
+        assert_eq!(boost.read_from()[0].value, "0");
+    }
+
+    #[test]
+    fn per_policy() {
+        let root = tree(
+            "policy",
+            &[
+                ("cpufreq/policy10/boost", "1\n"),
+                ("cpufreq/policy2/boost", "1\n"),
+                ("cpufreq/policy0/scaling_governor", "powersave\n"),
+            ],
+        );
+        let boost = Boost::detect_in(&root).unwrap();
+        let paths = ["cpufreq/policy2/boost", "cpufreq/policy10/boost"].map(|p| root.join(p));
+        assert_eq!(boost, Boost::PerPolicy(paths.to_vec()));
+
+        assert_eq!(set(&boost, false).len(), 2);
+        for path in &paths {
+            assert_eq!(fs::read_to_string(path).unwrap(), "0");
+        }
+        assert_eq!(boost.get(), Some(false));
+    }
+
+    #[test]
+    fn missing() {
+        let root = tree("missing", &[("cpufreq/policy0/scaling_governor", "schedutil\n")]);
+        assert_eq!(Boost::detect_in(&root), None);
+        assert_eq!(Boost::detect_in(&root.join("absent")), None);
+    }
+}
@@ -2,28 +2,304 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{fs, path::Path};
-use system76_power_zbus::ChargeProfile;
+use crate::{
+    config::{self, ConfigError},
+    state::State,
+    util::{WriteError, Written},
+};
+use inotify::{Inotify, WatchMask};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use system76_power_zbus::{BatteryThresholds, ChargeProfile, ChargeThresholdsStatus};
 
-const START_THRESHOLD: &str = "/sys/class/power_supply/BAT0/charge_control_start_threshold";
-const END_THRESHOLD: &str = "/sys/class/power_supply/BAT0/charge_control_end_threshold";
-const UNSUPPORTED_ERROR: &str = "Not running System76 firmware with charge threshold support";
-const OUT_OF_RANGE_ERROR: &str = "Charge threshold out of range: should be 0-100";
-const ORDER_ERROR: &str = "Charge end threshold must be strictly greater than start";
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+const START_THRESHOLD: &str = "charge_control_start_threshold";
+const END_THRESHOLD: &str = "charge_control_end_threshold";
+const CHARGE_PROFILES_CONFIG: &str = "charge-profiles.toml";
+const CHARGE_BEHAVIOUR: &str = "charge_behaviour";
+
+/// The files holding the thresholds of a battery, relative to its power supply directory,
+/// which are named differently across kernels and vendors.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ThresholdFiles {
+    /// Name of the variant, reported for debugging.
+    pub variant: &'static str,
+    pub start:   &'static str,
+    pub end:     &'static str,
+}
+
+/// The variants of the threshold files, probed in order for the first whose end threshold
+/// exists.
+pub const THRESHOLD_FILES: &[ThresholdFiles] = &[
+    // The kernel ABI, of System76, ThinkPad, ASUS, LG and Huawei laptops.
+    ThresholdFiles { variant: "charge_control", start: START_THRESHOLD, end: END_THRESHOLD },
+    // Its former names, which older kernels and out-of-tree drivers expose alone.
+    ThresholdFiles {
+        variant: "charge_start_stop",
+        start:   "charge_start_threshold",
+        end:     "charge_stop_threshold",
+    },
+    // Vendor drivers exposing them on the ACPI device of the battery.
+    ThresholdFiles {
+        variant: "device",
+        start:   "device/charge_control_start_threshold",
+        end:     "device/charge_control_end_threshold",
+    },
+];
+
+// The variant each battery was found to use, keyed by its power supply directory.
+static RESOLVED: Lazy<Mutex<BTreeMap<PathBuf, &'static ThresholdFiles>>> =
+    Lazy::new(Mutex::default);
+
+/// Steps some firmware only accepts thresholds in, rounding others to them, from the smallest.
+const ROUNDING_STEPS: &[u8] = &[5, 10];
+
+// The step the firmware of each battery was found to round thresholds to, keyed by its power
+// supply directory.
+static STEPS: Lazy<Mutex<BTreeMap<PathBuf, u8>>> = Lazy::new(Mutex::default);
+
+/// Charge behaviour discharging the battery while on AC, which calibrations rely on.
+pub const FORCE_DISCHARGE: &str = "force-discharge";
+/// Charge behaviour charging the battery up to its thresholds.
+pub const AUTO_BEHAVIOUR: &str = "auto";
+
+/// Reported in place of a profile ID for thresholds matching no profile.
+pub const CUSTOM_PROFILE: &str = "custom";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChargeThresholdError {
+    #[error("Not running System76 firmware with charge threshold support")]
+    Unsupported,
+    #[error("Charge threshold out of range: should be 0-100")]
+    OutOfRange,
+    #[error("Charge end threshold must be strictly greater than start")]
+    Order,
+    #[error("No battery named {}", _0)]
+    UnknownBattery(String),
+    #[error("The battery rejected {} {}, holding {}: {}", threshold, value, current, source)]
+    Rejected { threshold: &'static str, value: String, current: String, source: io::Error },
+    #[error(
+        "The battery holds {}-{} instead of {}-{}, which it does not accept",
+        held.0,
+        held.1,
+        requested.0,
+        requested.1
+    )]
+    NotHeld { requested: (u8, u8), held: (u8, u8) },
+    #[error(
+        "The battery holds an end threshold of {} instead of {}, which it does not accept",
+        held,
+        requested
+    )]
+    EndNotHeld { requested: u8, held: u8 },
+    #[error("failed to access charge thresholds: {}", _0)]
+    Io(#[from] io::Error),
+    #[error("invalid charge threshold: {}", _0)]
+    Parse(#[from] ParseIntError),
+}
 
 fn is_supported() -> bool {
-    // For now, only support thresholds on System76 hardware
+    // System76 hardware
     Path::new("/sys/bus/acpi/devices/17761776:00").is_dir() ||
-    // and Huawei
-    Path::new("/sys/devices/platform/huawei-wmi/charge_control_thresholds").exists()
+    // Huawei
+    Path::new("/sys/devices/platform/huawei-wmi/charge_control_thresholds").exists() ||
+    // and the batteries of other vendors with threshold files of a known variant
+    !batteries().is_empty()
+}
+
+/// The threshold files of a battery, or `None` if it has none of a known variant. The variant
+/// found is kept for the battery, which is probed again only if none was.
+pub(crate) fn threshold_files(battery: &Path) -> Option<&'static ThresholdFiles> {
+    let mut resolved = RESOLVED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(&files) = resolved.get(battery) {
+        return Some(files);
+    }
+
+    let files = THRESHOLD_FILES.iter().find(|files| battery.join(files.end).exists())?;
+    log::debug!("{} has charge thresholds of the {} variant", battery.display(), files.variant);
+    resolved.insert(battery.to_owned(), files);
+    Some(files)
+}
+
+/// The threshold files of a battery, by its power supply name, such as `BAT0`.
+#[must_use]
+pub fn battery_threshold_files(battery: &str) -> Option<&'static ThresholdFiles> {
+    threshold_files(&Path::new(POWER_SUPPLY_DIR).join(battery))
+}
+
+/// Batteries with an end threshold, which may lack a start threshold, sorted by supply name so
+/// that `BAT0` comes first.
+fn batteries() -> Vec<PathBuf> {
+    let Ok(supplies) = fs::read_dir(POWER_SUPPLY_DIR) else { return Vec::new() };
+
+    let mut batteries = supplies
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            fs::read_to_string(path.join("type")).map_or(false, |kind| kind.trim() == "Battery")
+                && threshold_files(path).is_some()
+        })
+        .collect::<Vec<_>>();
+
+    batteries.sort();
+    batteries
+}
+
+/// Names of the system batteries, such as `BAT0`, whether or not they have charge thresholds,
+/// sorted.
+#[must_use]
+pub fn battery_names() -> Vec<String> {
+    let Ok(supplies) = fs::read_dir(POWER_SUPPLY_DIR) else { return Vec::new() };
+
+    let mut names = supplies
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("BAT"))
+        .collect::<Vec<_>>();
+
+    names.sort();
+    names
+}
+
+/// The first of `profiles` matching the thresholds. Without a start threshold, the end
+/// threshold alone is matched.
+#[must_use]
+pub fn matching_profile(
+    profiles: &[ChargeProfile],
+    start: Option<u8>,
+    end: u8,
+) -> Option<&ChargeProfile> {
+    profiles
+        .iter()
+        .find(|profile| start.map_or(true, |start| profile.start == start) && profile.end == end)
+}
+
+/// Whether the battery has a start threshold, which some firmware lacks while having an end
+/// threshold.
+fn start_supported(battery: &Path) -> bool {
+    threshold_files(battery).map_or(false, |files| battery.join(files.start).exists())
+}
+
+/// Thresholds of a battery, with a start of 0 if it has no start threshold.
+fn read_thresholds(battery: &Path) -> Result<(u8, u8), ChargeThresholdError> {
+    let Some(files) = threshold_files(battery) else {
+        return Err(ChargeThresholdError::Unsupported);
+    };
+
+    let read = |file: &str| -> Result<u8, ChargeThresholdError> {
+        Ok(fs::read_to_string(battery.join(file))?.trim().parse::<u8>()?)
+    };
+
+    let end = read(files.end)?;
+    let start = if start_supported(battery) { read(files.start)? } else { 0 };
+    Ok((start, end))
+}
+
+/// Thresholds of a battery, or `None` if it has no end threshold.
+fn battery_thresholds(battery: &Path) -> Result<Option<BatteryThresholds>, ChargeThresholdError> {
+    if threshold_files(battery).is_none() {
+        return Ok(None);
+    }
+
+    let (start, end) = read_thresholds(battery)?;
+    Ok(Some(BatteryThresholds { start, end, start_supported: start_supported(battery) }))
+}
+
+/// Whether a battery holds the thresholds, or their end if it has no start threshold.
+#[must_use]
+pub fn holds(battery: &BatteryThresholds, (start, end): (u8, u8)) -> bool {
+    battery.end == end && (!battery.start_supported || battery.start == start)
+}
+
+/// `charge-profiles.toml`, defining profiles in addition to the built-in ones.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CustomProfiles {
+    profile: Vec<CustomProfile>,
+    /// Steps the firmware accepts thresholds in, keyed by model, as in
+    /// `/sys/class/dmi/id/product_version`, in place of those found by writing them.
+    steps:   BTreeMap<String, u8>,
 }
 
-fn supports_thresholds() -> bool {
-    Path::new(START_THRESHOLD).exists() && Path::new(END_THRESHOLD).exists()
+/// A `[[profile]]` of `charge-profiles.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CustomProfile {
+    name:        String,
+    #[serde(default)]
+    description: String,
+    start:       u8,
+    end:         u8,
 }
 
+/// The built-in profiles, followed by those of `/etc/system76-power/charge-profiles.toml`,
+/// or only the built-in ones if it is invalid.
 #[must_use]
 pub fn get_charge_profiles() -> Vec<ChargeProfile> {
+    load_charge_profiles().unwrap_or_else(|why| {
+        log::warn!("using the built-in charge profiles: {}", why);
+        builtin_charge_profiles()
+    })
+}
+
+/// The built-in profiles, followed by those of `/etc/system76-power/charge-profiles.toml`.
+pub fn load_charge_profiles() -> Result<Vec<ChargeProfile>, ConfigError> {
+    let custom: CustomProfiles = config::load(CHARGE_PROFILES_CONFIG)?;
+    custom_charge_profiles(custom)
+}
+
+/// Appends the custom profiles to the built-in ones, rejecting invalid ranges and names which
+/// are already taken, and steps which do not divide 100.
+fn custom_charge_profiles(custom: CustomProfiles) -> Result<Vec<ChargeProfile>, ConfigError> {
+    let mut profiles = builtin_charge_profiles();
+    let mut names = profiles.iter().map(|profile| profile.id.clone()).collect::<BTreeSet<_>>();
+
+    for (model, &step) in &custom.steps {
+        if !divides_100(step) {
+            return Err(ConfigError::Invalid {
+                path: config::path(CHARGE_PROFILES_CONFIG),
+                key:  format!("steps '{}'", model),
+                why:  "must divide 100, such as 5 or 10".into(),
+            });
+        }
+    }
+
+    for profile in custom.profile {
+        let invalid = |why: String| ConfigError::Invalid {
+            path: config::path(CHARGE_PROFILES_CONFIG),
+            key: format!("profile '{}'", profile.name),
+            why,
+        };
+
+        if profile.name.is_empty() {
+            return Err(invalid("name must not be empty".into()));
+        } else if profile.name == CUSTOM_PROFILE {
+            return Err(invalid("this name stands for thresholds matching no profile".into()));
+        } else if !names.insert(profile.name.clone()) {
+            return Err(invalid("a profile of this name is already defined".into()));
+        }
+
+        check_range((profile.start, profile.end)).map_err(|why| invalid(why.to_string()))?;
+        profiles.push(ChargeProfile {
+            title:       profile.name.clone(),
+            id:          profile.name,
+            description: profile.description,
+            start:       profile.start,
+            end:         profile.end,
+        });
+    }
+
+    Ok(profiles)
+}
+
+pub(crate) fn builtin_charge_profiles() -> Vec<ChargeProfile> {
     vec![
         ChargeProfile {
             id:          "full_charge".to_string(),
@@ -39,9 +315,9 @@ pub fn get_charge_profiles() -> Vec<ChargeProfile> {
             title:       "Balanced".to_string(),
             description: "Use this threshold when you unplug frequently but don't need the full \
                           battery capacity. Charging stops when the battery reaches 90% capacity \
-                          and resumes when the battery falls below 85%."
+                          and resumes when the battery falls below 80%."
                 .to_string(),
-            start:       86,
+            start:       80,
             end:         90,
         },
         ChargeProfile {
@@ -57,35 +333,818 @@ pub fn get_charge_profiles() -> Vec<ChargeProfile> {
     ]
 }
 
-pub(crate) fn get_charge_thresholds() -> anyhow::Result<(u8, u8)> {
-    if !is_supported() || !supports_thresholds() {
-        return Err(anyhow::anyhow!(UNSUPPORTED_ERROR));
+/// Thresholds of the first battery, with a start of 0 if it has no start threshold.
+pub(crate) fn get_charge_thresholds() -> Result<(u8, u8), ChargeThresholdError> {
+    let batteries = batteries();
+    match batteries.first() {
+        Some(battery) if is_supported() => read_thresholds(battery),
+        _ => Err(ChargeThresholdError::Unsupported),
     }
+}
 
-    let start_str = fs::read_to_string(START_THRESHOLD)?;
-    let end_str = fs::read_to_string(END_THRESHOLD)?;
+/// Thresholds of every battery, keyed by power supply name, with a start of 0 for those
+/// without a start threshold.
+pub(crate) fn get_battery_charge_thresholds(
+) -> Result<BTreeMap<String, (u8, u8)>, ChargeThresholdError> {
+    if !is_supported() {
+        return Err(ChargeThresholdError::Unsupported);
+    }
 
-    let start = start_str.trim().parse::<u8>()?;
-    let end = end_str.trim().parse::<u8>()?;
+    let mut thresholds = BTreeMap::new();
+    for battery in batteries() {
+        let name = battery.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        thresholds.insert(name, read_thresholds(&battery)?);
+    }
 
-    Ok((start, end))
+    Ok(thresholds)
+}
+
+/// Thresholds of every battery, whether the platform supports them, and the profile of
+/// `profiles` they match.
+///
+/// Unsupported platforms are reported in the status rather than as an error.
+pub(crate) fn get_charge_thresholds_status(
+    profiles: &[ChargeProfile],
+) -> Result<ChargeThresholdsStatus, ChargeThresholdError> {
+    let mut status = ChargeThresholdsStatus { min: 0, max: 100, ..Default::default() };
+    if !is_supported() {
+        return Ok(status);
+    }
+
+    for battery in batteries() {
+        let name = battery.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        if let Some(thresholds) = battery_thresholds(&battery)? {
+            status.batteries.insert(name, thresholds);
+        }
+    }
+
+    status.supported = !status.batteries.is_empty();
+    status.start_supported =
+        status.supported && status.batteries.values().all(|battery| battery.start_supported);
+
+    if let Some(first) = status.batteries.values().next() {
+        let start = first.start_supported.then_some(first.start);
+        status.profile = matching_profile(profiles, start, first.end)
+            .map_or_else(String::new, |profile| profile.id.clone());
+    }
+
+    Ok(status)
+}
+
+/// Errors of the batteries which failed, keyed by power supply name.
+type Failures = BTreeMap<String, ChargeThresholdError>;
+
+/// The values written to the batteries, in order, the thresholds each battery set holds, and
+/// the batteries which failed, with their error, while the others were set.
+#[derive(Debug, Default)]
+pub struct BatteryWrites {
+    pub written: Vec<Written>,
+    /// Thresholds of each battery set, keyed by power supply name, as rounded to the steps its
+    /// firmware accepts, with a start of 0 for those without a start threshold.
+    pub applied: BTreeMap<String, (u8, u8)>,
+    pub failed:  Failures,
+}
+
+impl BatteryWrites {
+    /// The errors of the batteries which failed, keyed by power supply name.
+    #[must_use]
+    pub fn failures(&self) -> BTreeMap<String, String> {
+        self.failed.iter().map(|(battery, why)| (battery.clone(), why.to_string())).collect()
+    }
+}
+
+/// Sets the thresholds of `battery`, or of every battery if `None`, returning the values
+/// written. Batteries lacking thresholds are reported as failed, unless none could be set.
+pub(crate) fn set_charge_thresholds(
+    thresholds: (u8, u8),
+    battery: Option<&str>,
+) -> Result<BatteryWrites, ChargeThresholdError> {
+    write_batteries(&selected(thresholds, battery)?)
+}
+
+/// Sets the thresholds saved for each battery, by itself or along with every battery.
+pub(crate) fn restore_charge_thresholds(
+    state: &State,
+) -> Result<BatteryWrites, ChargeThresholdError> {
+    let thresholds = battery_names()
+        .into_iter()
+        .filter_map(|battery| Some((battery.clone(), state.charge_thresholds_of(&battery)?)))
+        .collect();
+
+    write_batteries(&thresholds)
+}
+
+/// The values setting the thresholds of `battery`, or of every battery if `None`, writes, in
+/// order, without writing them.
+pub(crate) fn plan_charge_thresholds(
+    thresholds: (u8, u8),
+    battery: Option<&str>,
+) -> Result<BatteryWrites, ChargeThresholdError> {
+    let (plans, failed) = plan_batteries(&selected(thresholds, battery)?)?;
+    let applied = plans.iter().map(|(name, plan)| (name.clone(), plan.thresholds)).collect();
+    let written = plans.into_values().flat_map(|plan| plan.writes).collect();
+    Ok(BatteryWrites { written, applied, failed })
+}
+
+/// The thresholds to set on a battery, by its power supply name, rounded to the steps its
+/// firmware accepts.
+#[must_use]
+pub(crate) fn rounded_thresholds(battery: &str, thresholds: (u8, u8)) -> (u8, u8) {
+    round_thresholds(thresholds, step(&Path::new(POWER_SUPPLY_DIR).join(battery)))
+}
+
+/// Rounds the thresholds to the nearest multiples of `step`, keeping the start below the end.
+fn round_thresholds((start, end): (u8, u8), step: u8) -> (u8, u8) {
+    if step <= 1 {
+        return (start, end);
+    }
+
+    let round = |value: u8| (value.min(100) + step / 2) / step * step;
+    let end = round(end).max(step);
+    (round(start).min(end - step), end)
+}
+
+fn divides_100(step: u8) -> bool { step > 0 && 100 % step == 0 }
+
+/// The step the thresholds of a battery are rounded to: that of the model in
+/// `charge-profiles.toml`, or the one its firmware was found to round them to, or 1.
+fn step(battery: &Path) -> u8 {
+    let model_step = || {
+        let custom: CustomProfiles = config::load(CHARGE_PROFILES_CONFIG).ok()?;
+        let model = fs::read_to_string("/sys/class/dmi/id/product_version").ok()?;
+        custom.steps.get(model.trim()).copied().filter(|&step| divides_100(step))
+    };
+
+    model_step().or_else(|| found_step(battery)).unwrap_or(1)
+}
+
+/// The step the firmware of a battery was found to round its thresholds to, if any.
+fn found_step(battery: &Path) -> Option<u8> {
+    let steps = STEPS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    steps.get(battery).copied()
+}
+
+/// The step of the firmware holding other values than those written, given as pairs of the
+/// value written and the value held: the smallest of the known steps of which each value held
+/// is a multiple, less than a step away from the value written. `None` if none is.
+fn inferred_step(values: &[(u8, u8)]) -> Option<u8> {
+    ROUNDING_STEPS.iter().copied().find(|&step| {
+        values.iter().all(|&(written, held)| held % step == 0 && written.abs_diff(held) < step)
+    })
+}
+
+/// The thresholds to set on `battery`, or on every battery if `None`, keyed by power supply
+/// name.
+fn selected(
+    thresholds: (u8, u8),
+    battery: Option<&str>,
+) -> Result<BTreeMap<String, (u8, u8)>, ChargeThresholdError> {
+    let names = battery_names();
+    match battery {
+        Some(battery) if !names.iter().any(|name| name == battery) => {
+            Err(ChargeThresholdError::UnknownBattery(battery.to_owned()))
+        }
+        Some(battery) => Ok(BTreeMap::from([(battery.to_owned(), thresholds)])),
+        None => Ok(names.into_iter().map(|name| (name, thresholds)).collect()),
+    }
+}
+
+/// The plan of each battery, and the batteries which fail, such as those lacking thresholds.
+/// Fails if every battery does.
+fn plan_batteries(
+    thresholds: &BTreeMap<String, (u8, u8)>,
+) -> Result<(BTreeMap<String, BatteryPlan>, Failures), ChargeThresholdError> {
+    if !is_supported() || thresholds.is_empty() {
+        return Err(ChargeThresholdError::Unsupported);
+    }
+
+    let mut plans = BTreeMap::new();
+    let mut failed = BTreeMap::new();
+    for (name, &thresholds) in thresholds {
+        let battery = Path::new(POWER_SUPPLY_DIR).join(name);
+        match plan_battery(&battery, thresholds, step(&battery)) {
+            Ok(plan) => {
+                plans.insert(name.clone(), plan);
+            }
+            Err(why) => {
+                failed.insert(name.clone(), why);
+            }
+        }
+    }
+
+    if plans.is_empty() {
+        return Err(failed.into_values().next().unwrap_or(ChargeThresholdError::Unsupported));
+    }
+
+    Ok((plans, failed))
+}
+
+/// The values setting the thresholds of a battery writes, in order, and the thresholds they
+/// set, rounded to the steps its firmware accepts.
+#[derive(Debug)]
+struct BatteryPlan {
+    writes:     Vec<Written>,
+    /// With a start of 0 if the battery has no start threshold.
+    thresholds: (u8, u8),
 }
 
-pub(crate) fn set_charge_thresholds((start, end): (u8, u8)) -> anyhow::Result<()> {
-    if !is_supported() || !supports_thresholds() {
-        return Err(anyhow::anyhow!(UNSUPPORTED_ERROR));
-    } else if start > 100 || end > 100 {
-        return Err(anyhow::anyhow!(OUT_OF_RANGE_ERROR));
+/// The plan setting the thresholds of a battery, rounded to `step`. Without a start threshold,
+/// only the end is checked and written.
+fn plan_battery(
+    battery: &Path,
+    thresholds: (u8, u8),
+    step: u8,
+) -> Result<BatteryPlan, ChargeThresholdError> {
+    let Some(files) = threshold_files(battery) else {
+        return Err(ChargeThresholdError::Unsupported);
+    };
+
+    if !start_supported(battery) {
+        if thresholds.1 > 100 {
+            return Err(ChargeThresholdError::OutOfRange);
+        }
+
+        log::info!("{} has no start threshold, setting its end threshold only", battery.display());
+        let (_, end) = round_thresholds((0, thresholds.1), step);
+        let path = battery.join(files.end).to_string_lossy().into_owned();
+        return Ok(BatteryPlan {
+            writes:     vec![Written::new(files.end, path, end)],
+            thresholds: (0, end),
+        });
+    }
+
+    check_range(thresholds)?;
+    let thresholds = round_thresholds(thresholds, step);
+    let order = WriteOrder::new(read_thresholds(battery).ok(), thresholds.1);
+    Ok(BatteryPlan { writes: order.writes(battery, files, thresholds), thresholds })
+}
+
+/// Writes the thresholds of each battery, going on with the others when one fails.
+fn write_batteries(
+    thresholds: &BTreeMap<String, (u8, u8)>,
+) -> Result<BatteryWrites, ChargeThresholdError> {
+    let (plans, mut failed) = plan_batteries(thresholds)?;
+
+    let mut written = Vec::new();
+    let mut applied = BTreeMap::new();
+    for (battery, plan) in plans {
+        match write_battery(&plan.writes, plan.thresholds) {
+            Ok((writes, held)) => {
+                written.extend(writes);
+                applied.insert(battery, held);
+            }
+            Err(why) => {
+                failed.insert(battery, why);
+            }
+        }
+    }
+
+    if written.is_empty() {
+        return Err(failed.into_values().next().unwrap_or(ChargeThresholdError::Unsupported));
+    }
+
+    Ok(BatteryWrites { written, applied, failed })
+}
+
+/// The order of the writes setting the thresholds of a battery, so that the start stays below
+/// the end in between, as the firmware may reject the value otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WriteOrder {
+    /// Raising the end, or keeping it.
+    EndFirst,
+    /// Lowering the end.
+    StartFirst,
+    /// Raising the end to 100 first, as the current thresholds are unknown.
+    EndToFull,
+}
+
+impl WriteOrder {
+    fn new(current: Option<(u8, u8)>, end: u8) -> Self {
+        match current {
+            Some((_, current_end)) if end >= current_end => Self::EndFirst,
+            Some(_) => Self::StartFirst,
+            None => Self::EndToFull,
+        }
+    }
+
+    fn writes(
+        self,
+        battery: &Path,
+        files: &ThresholdFiles,
+        (start, end): (u8, u8),
+    ) -> Vec<Written> {
+        let path = |file: &str| battery.join(file).to_string_lossy().into_owned();
+        let start = Written::new(files.start, path(files.start), start);
+        let end = Written::new(files.end, path(files.end), end);
+        match self {
+            Self::EndFirst => vec![end, start],
+            Self::StartFirst => vec![start, end],
+            Self::EndToFull => vec![Written::new(files.end, path(files.end), 100), start, end],
+        }
+    }
+}
+
+/// Writes the thresholds of a battery, returning the values written and the thresholds held.
+/// If the firmware rejects a value, they are written again in the opposite order before
+/// failing with the value rejected. The thresholds are then read back, as some firmware adjusts
+/// values it does not accept.
+fn write_battery(
+    writes: &[Written],
+    thresholds: (u8, u8),
+) -> Result<(Vec<Written>, (u8, u8)), ChargeThresholdError> {
+    let write = |writes: &[Written]| writes.iter().try_for_each(Written::write);
+
+    let written = match write(writes) {
+        Ok(()) => writes.to_vec(),
+        Err(why) if rejected(&why.source) && writes.len() > 1 => {
+            let mut retry = writes[writes.len() - 2..].to_vec();
+            retry.reverse();
+            log::debug!("retrying the charge thresholds in the opposite order: {}", why);
+            write(&retry).map_err(rejection)?;
+            retry
+        }
+        Err(why) => return Err(rejection(why)),
+    };
+
+    let Some(battery) = writes.first().and_then(battery_of) else {
+        return Ok((written, thresholds));
+    };
+
+    Ok((written, held_thresholds(battery, thresholds)?))
+}
+
+/// The thresholds a battery holds once `thresholds` are written, only checking the end if it
+/// has no start threshold. Values which the firmware rounded to one of the known steps are
+/// accepted, keeping the step for the battery, while it fails with other values.
+fn held_thresholds(battery: &Path, thresholds: (u8, u8)) -> Result<(u8, u8), ChargeThresholdError> {
+    let Ok(held) = read_thresholds(battery) else { return Ok(thresholds) };
+
+    let has_start = start_supported(battery);
+    let values = if has_start {
+        vec![(thresholds.0, held.0), (thresholds.1, held.1)]
+    } else {
+        vec![(thresholds.1, held.1)]
+    };
+
+    if values.iter().all(|(written, held)| written == held) {
+        return Ok(held);
+    }
+
+    if let Some(step) = inferred_step(&values) {
+        log::info!(
+            "{} rounds charge thresholds to steps of {}, holding {:?} instead of {:?}",
+            battery.display(),
+            step,
+            held,
+            thresholds
+        );
+        let mut steps = STEPS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        steps.insert(battery.to_owned(), step);
+        return Ok(held);
+    }
+
+    if has_start {
+        Err(ChargeThresholdError::NotHeld { requested: thresholds, held })
+    } else {
+        Err(ChargeThresholdError::EndNotHeld { requested: thresholds.1, held: held.1 })
+    }
+}
+
+/// Whether the firmware rejected the value written, rather than the file being inaccessible.
+fn rejected(why: &io::Error) -> bool {
+    matches!(why.raw_os_error(), Some(libc::EINVAL | libc::EIO))
+}
+
+/// The power supply directory of the battery a threshold is written to, which may be above
+/// the directory of the file.
+fn battery_of(write: &Written) -> Option<&Path> {
+    write.path.strip_suffix(write.name)?.strip_suffix('/').map(Path::new)
+}
+
+fn rejection(why: WriteError) -> ChargeThresholdError {
+    if !rejected(&why.source) {
+        return why.source.into();
+    }
+
+    let start = THRESHOLD_FILES.iter().any(|files| files.start == why.written.name);
+    let threshold = if start { "start threshold" } else { "end threshold" };
+    ChargeThresholdError::Rejected {
+        threshold,
+        current: why.written.current().unwrap_or_else(|| "an unknown value".into()),
+        value: why.written.value,
+        source: why.source,
+    }
+}
+
+fn check_range((start, end): (u8, u8)) -> Result<(), ChargeThresholdError> {
+    if start > 100 || end > 100 {
+        Err(ChargeThresholdError::OutOfRange)
     } else if end <= start {
-        return Err(anyhow::anyhow!(ORDER_ERROR));
+        Err(ChargeThresholdError::Order)
+    } else {
+        Ok(())
     }
+}
+
+/// Watches the threshold files of every battery for writes, including those made outside of
+/// the daemon.
+pub(crate) fn watch_charge_thresholds() -> io::Result<Inotify> {
+    let inotify = Inotify::init()?;
+    let mut watches = inotify.watches();
+
+    for battery in batteries() {
+        let Some(files) = threshold_files(&battery) else { continue };
+        for file in [files.start, files.end] {
+            let path = battery.join(file);
+            if path.exists() {
+                watches.add(path, WatchMask::MODIFY)?;
+            }
+        }
+    }
+
+    Ok(inotify)
+}
 
-    // Without this, setting start threshold may fail if the previous end
-    // threshold is higher.
-    fs::write(END_THRESHOLD, "100")?;
+/// The charge behaviour selected for a battery, and those it accepts, from its
+/// `charge_behaviour`, which lists them with the selected one in brackets.
+pub(crate) fn charge_behaviour(battery: &str) -> io::Result<(String, Vec<String>)> {
+    let contents =
+        fs::read_to_string(Path::new(POWER_SUPPLY_DIR).join(battery).join(CHARGE_BEHAVIOUR))?;
+    Ok(parse_charge_behaviour(&contents))
+}
+
+fn parse_charge_behaviour(contents: &str) -> (String, Vec<String>) {
+    let mut selected = String::new();
+    let behaviours = contents
+        .split_whitespace()
+        .map(|behaviour| match behaviour.strip_prefix('[').and_then(|b| b.strip_suffix(']')) {
+            Some(behaviour) => {
+                selected = behaviour.to_owned();
+                selected.clone()
+            }
+            None => behaviour.to_owned(),
+        })
+        .collect();
 
-    fs::write(START_THRESHOLD, format!("{}", start))?;
-    fs::write(END_THRESHOLD, format!("{}", end))?;
+    (selected, behaviours)
+}
 
-    Ok(())
+pub(crate) fn set_charge_behaviour(battery: &str, behaviour: &str) -> io::Result<()> {
+    fs::write(Path::new(POWER_SUPPLY_DIR).join(battery).join(CHARGE_BEHAVIOUR), behaviour)
+}
+
+/// The first battery which can be force-discharged, to calibrate it.
+#[must_use]
+pub(crate) fn calibration_battery() -> Option<String> {
+    battery_names().into_iter().find(|battery| {
+        charge_behaviour(battery).map_or(false, |(_, behaviours)| {
+            behaviours.iter().any(|behaviour| behaviour == FORCE_DISCHARGE)
+        })
+    })
+}
+
+/// The thresholds of a battery, or `None` if the platform or the battery has none.
+#[must_use]
+pub(crate) fn calibration_thresholds(battery: &str) -> Option<(u8, u8)> {
+    if !is_supported() {
+        return None;
+    }
+
+    let thresholds = battery_thresholds(&Path::new(POWER_SUPPLY_DIR).join(battery)).ok()??;
+    Some((thresholds.start, thresholds.end))
+}
+
+/// The charge of a battery, in percent of its capacity, and whether it reports being full.
+pub(crate) fn battery_charge(battery: &str) -> io::Result<(u8, bool)> {
+    let path = Path::new(POWER_SUPPLY_DIR).join(battery);
+    let capacity = fs::read_to_string(path.join("capacity"))?
+        .trim()
+        .parse::<u8>()
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+    let full =
+        fs::read_to_string(path.join("status")).map_or(false, |status| status.trim() == "Full");
+
+    Ok((capacity.min(100), full))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn parse(toml: &str) -> Result<Vec<ChargeProfile>, String> {
+        let custom = toml::from_str(toml).map_err(|why: toml::de::Error| why.to_string())?;
+        custom_charge_profiles(custom).map_err(|why| why.to_string())
+    }
+
+    #[test]
+    fn custom_profiles() {
+        let ids = |profiles: Vec<ChargeProfile>| {
+            profiles.into_iter().map(|profile| profile.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(parse("").unwrap()), ids(builtin_charge_profiles()));
+
+        let profiles = parse(
+            "[[profile]]\nname = \"desk\"\ndescription = \"Plugged in all day\"\nstart = 55\nend \
+             = 60\n",
+        )
+        .unwrap();
+        let desk = profiles.last().unwrap();
+        assert_eq!(profiles.len(), builtin_charge_profiles().len() + 1);
+        assert_eq!((desk.id.as_str(), desk.start, desk.end), ("desk", 55, 60));
+        assert!(parse("[steps]\n\"ThinkPad X1 Carbon Gen 9\" = 5\n").is_ok());
+    }
+
+    #[test]
+    fn invalid_custom_profiles() {
+        assert_eq!(
+            parse("[[profile]]\nname = \"desk\"\nstart = 60\nend = 55\n").unwrap_err(),
+            "invalid profile 'desk' in /etc/system76-power/charge-profiles.toml: Charge end \
+             threshold must be strictly greater than start"
+        );
+        assert_eq!(
+            parse("[[profile]]\nname = \"desk\"\nstart = 55\nend = 101\n").unwrap_err(),
+            "invalid profile 'desk' in /etc/system76-power/charge-profiles.toml: Charge threshold \
+             out of range: should be 0-100"
+        );
+        assert_eq!(
+            parse("[[profile]]\nname = \"balanced\"\nstart = 55\nend = 60\n").unwrap_err(),
+            "invalid profile 'balanced' in /etc/system76-power/charge-profiles.toml: a profile of \
+             this name is already defined"
+        );
+        assert_eq!(
+            parse("[[profile]]\nname = \"custom\"\nstart = 55\nend = 60\n").unwrap_err(),
+            "invalid profile 'custom' in /etc/system76-power/charge-profiles.toml: this name \
+             stands for thresholds matching no profile"
+        );
+        assert_eq!(
+            parse("[steps]\noryp6 = 3\n").unwrap_err(),
+            "invalid steps 'oryp6' in /etc/system76-power/charge-profiles.toml: must divide 100, \
+             such as 5 or 10"
+        );
+
+        let why = parse("[[profile]]\nname = \"desk\"\nstart = 55\nend = 300\n").unwrap_err();
+        assert!(why.contains("line 4"), "{}", why);
+    }
+
+    #[test]
+    fn write_order() {
+        assert_eq!(WriteOrder::new(Some((40, 80)), 90), WriteOrder::EndFirst);
+        assert_eq!(WriteOrder::new(Some((40, 80)), 80), WriteOrder::EndFirst);
+        assert_eq!(WriteOrder::new(Some((40, 80)), 60), WriteOrder::StartFirst);
+        assert_eq!(WriteOrder::new(None, 60), WriteOrder::EndToFull);
+
+        let root = TempDir::new("thresholds");
+        let battery = root.join("BAT0");
+        fs::create_dir_all(&battery).unwrap();
+        fs::write(battery.join(START_THRESHOLD), "40\n").unwrap();
+        fs::write(battery.join(END_THRESHOLD), "80\n").unwrap();
+
+        let files = threshold_files(&battery).unwrap();
+        let writes = WriteOrder::StartFirst.writes(&battery, files, (50, 60));
+        let names = writes.iter().map(|write| write.name).collect::<Vec<_>>();
+        assert_eq!(names, [START_THRESHOLD, END_THRESHOLD]);
+        assert_eq!(write_battery(&writes, (50, 60)).unwrap(), (writes.clone(), (50, 60)));
+        assert_eq!(read_thresholds(&battery).unwrap(), (50, 60));
+
+        let writes = WriteOrder::EndToFull.writes(&battery, files, (86, 90));
+        assert_eq!(
+            writes.iter().map(|write| write.value.as_str()).collect::<Vec<_>>(),
+            ["100", "86", "90"]
+        );
+
+        // Firmware adjusting the values it does not accept.
+        let why = write_battery(&writes[..1], (86, 90)).unwrap_err();
+        assert_eq!(
+            why.to_string(),
+            "The battery holds 50-100 instead of 86-90, which it does not accept"
+        );
+
+        let why = WriteError {
+            written: Written::new(START_THRESHOLD, "/nonexistent", 96),
+            source:  io::Error::from_raw_os_error(libc::EINVAL),
+        };
+        assert_eq!(
+            rejection(why).to_string(),
+            "The battery rejected start threshold 96, holding an unknown value: Invalid argument \
+             (os error 22)"
+        );
+    }
+
+    #[test]
+    fn start_optional() {
+        let root = TempDir::new("batteries");
+        let fixture = |name: &str, files: &[(&str, &str)]| {
+            let battery = root.join(name);
+            fs::create_dir_all(&battery).unwrap();
+            for (file, value) in files {
+                fs::write(battery.join(file), value).unwrap();
+            }
+            battery
+        };
+
+        let both = fixture("BAT0", &[(START_THRESHOLD, "40\n"), (END_THRESHOLD, "80\n")]);
+        let end_only = fixture("BAT1", &[(END_THRESHOLD, "80\n")]);
+        let neither = fixture("BAT2", &[]);
+
+        let thresholds = |battery: &Path| battery_thresholds(battery).unwrap();
+        assert_eq!(
+            thresholds(&both),
+            Some(BatteryThresholds {
+                start:           40,
+                end:             80,
+                start_supported: true,
+            })
+        );
+        assert_eq!(
+            thresholds(&end_only),
+            Some(BatteryThresholds {
+                start:           0,
+                end:             80,
+                start_supported: false,
+            })
+        );
+        assert_eq!(thresholds(&neither), None);
+        assert!(holds(&thresholds(&end_only).unwrap(), (86, 80)));
+        assert!(!holds(&thresholds(&both).unwrap(), (86, 80)));
+
+        // The full charge profile, of which only the end applies without a start threshold.
+        let names = |writes: &[Written]| writes.iter().map(|write| write.name).collect::<Vec<_>>();
+        let writes = plan_battery(&both, (90, 100), 1).unwrap().writes;
+        assert_eq!(names(&writes), [END_THRESHOLD, START_THRESHOLD]);
+        assert_eq!(write_battery(&writes, (90, 100)).unwrap().0.len(), 2);
+        assert_eq!(read_thresholds(&both).unwrap(), (90, 100));
+
+        let plan = plan_battery(&end_only, (90, 100), 1).unwrap();
+        assert_eq!(plan.thresholds, (0, 100));
+        let writes = plan.writes;
+        assert_eq!(names(&writes), [END_THRESHOLD]);
+        assert_eq!(write_battery(&writes, (90, 100)).unwrap(), (writes.clone(), (0, 100)));
+        assert_eq!(read_thresholds(&end_only).unwrap(), (0, 100));
+        assert!(!end_only.join(START_THRESHOLD).exists());
+
+        // Only the thresholds the battery has are validated.
+        assert!(plan_battery(&end_only, (95, 60), 1).is_ok());
+        assert!(matches!(plan_battery(&both, (95, 60), 1), Err(ChargeThresholdError::Order)));
+        assert!(matches!(
+            plan_battery(&end_only, (0, 101), 1),
+            Err(ChargeThresholdError::OutOfRange)
+        ));
+        assert!(matches!(
+            plan_battery(&neither, (90, 100), 1),
+            Err(ChargeThresholdError::Unsupported)
+        ));
+        assert!(read_thresholds(&neither).is_err());
+
+        // Firmware adjusting an end it does not accept.
+        let why = write_battery(&writes, (90, 60)).unwrap_err();
+        assert_eq!(
+            why.to_string(),
+            "The battery holds an end threshold of 100 instead of 60, which it does not accept"
+        );
+    }
+
+    #[test]
+    fn variants() {
+        let root = TempDir::new("variants");
+        let fixture = |name: &str, files: &ThresholdFiles, end_only: bool| {
+            let battery = root.join(name);
+            fs::create_dir_all(battery.join("device")).unwrap();
+            if !end_only {
+                fs::write(battery.join(files.start), "40\n").unwrap();
+            }
+            fs::write(battery.join(files.end), "80\n").unwrap();
+            battery
+        };
+
+        for files in THRESHOLD_FILES {
+            let battery = fixture(files.variant, files, false);
+            assert_eq!(threshold_files(&battery), Some(files));
+            assert_eq!(read_thresholds(&battery).unwrap(), (40, 80));
+
+            let writes = plan_battery(&battery, (50, 60), 1).unwrap().writes;
+            let paths = writes.iter().map(|write| write.path.as_str()).collect::<Vec<_>>();
+            assert_eq!(
+                paths,
+                [battery.join(files.start), battery.join(files.end)]
+                    .iter()
+                    .map(|path| path.to_str().unwrap())
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(write_battery(&writes, (50, 60)).unwrap().0.len(), 2);
+            assert_eq!(read_thresholds(&battery).unwrap(), (50, 60));
+
+            let end_only = fixture(&format!("{}-end", files.variant), files, true);
+            assert_eq!(
+                battery_thresholds(&end_only).unwrap(),
+                Some(BatteryThresholds {
+                    start:           0,
+                    end:             80,
+                    start_supported: false,
+                })
+            );
+        }
+
+        // The kernel ABI is preferred over the former names, and the variant found is kept.
+        let both = fixture("both", &THRESHOLD_FILES[1], false);
+        fs::write(both.join(END_THRESHOLD), "90\n").unwrap();
+        assert_eq!(threshold_files(&both), Some(&THRESHOLD_FILES[0]));
+        fs::remove_file(both.join(END_THRESHOLD)).unwrap();
+        assert_eq!(threshold_files(&both), Some(&THRESHOLD_FILES[0]));
+
+        assert_eq!(threshold_files(&root.join("missing")), None);
+
+        let why = WriteError {
+            written: Written::new(THRESHOLD_FILES[1].start, "/nonexistent", 96),
+            source:  io::Error::from_raw_os_error(libc::EIO),
+        };
+        assert!(rejection(why).to_string().starts_with("The battery rejected start threshold"));
+    }
+
+    #[test]
+    fn rounding() {
+        assert_eq!(round_thresholds((83, 87), 1), (83, 87));
+        assert_eq!(round_thresholds((83, 92), 5), (85, 90));
+        assert_eq!(round_thresholds((83, 87), 5), (80, 85));
+        assert_eq!(round_thresholds((83, 87), 10), (80, 90));
+        assert_eq!(round_thresholds((96, 100), 5), (95, 100));
+        assert_eq!(round_thresholds((1, 2), 10), (0, 10));
+
+        assert_eq!(inferred_step(&[(83, 85)]), Some(5));
+        assert_eq!(inferred_step(&[(85, 80)]), Some(10));
+        assert_eq!(inferred_step(&[(96, 95), (100, 100)]), Some(5));
+        assert_eq!(inferred_step(&[(96, 95), (99, 99)]), None);
+        assert_eq!(inferred_step(&[(86, 50)]), None);
+
+        // The built-in profiles are safe from rounding.
+        for profile in builtin_charge_profiles() {
+            for &step in ROUNDING_STEPS {
+                let thresholds = (profile.start, profile.end);
+                assert_eq!(round_thresholds(thresholds, step), thresholds, "{}", profile.id);
+            }
+        }
+
+        // Firmware clamping the values written to steps of 5.
+        let root = TempDir::new("rounding");
+        let battery = root.join("BAT0");
+        fs::create_dir_all(&battery).unwrap();
+        fs::write(battery.join(START_THRESHOLD), "40\n").unwrap();
+        fs::write(battery.join(END_THRESHOLD), "80\n").unwrap();
+
+        assert_eq!(found_step(&battery), None);
+        let plan = plan_battery(&battery, (83, 92), 1).unwrap();
+        assert_eq!(plan.thresholds, (83, 92));
+        plan.writes.iter().try_for_each(Written::write).unwrap();
+        fs::write(battery.join(START_THRESHOLD), "85\n").unwrap();
+        fs::write(battery.join(END_THRESHOLD), "90\n").unwrap();
+        assert_eq!(held_thresholds(&battery, (83, 92)).unwrap(), (85, 90));
+
+        // The step is kept for the battery, so that requests are rounded before being written.
+        assert_eq!(found_step(&battery), Some(5));
+        let plan = plan_battery(&battery, (72, 88), 5).unwrap();
+        assert_eq!(plan.thresholds, (70, 90));
+        let values = plan.writes.iter().map(|write| write.value.as_str()).collect::<Vec<_>>();
+        assert_eq!(values, ["90", "70"]);
+
+        // Values which are not rounded ones still fail.
+        fs::write(battery.join(START_THRESHOLD), "50\n").unwrap();
+        fs::write(battery.join(END_THRESHOLD), "100\n").unwrap();
+        assert!(matches!(
+            held_thresholds(&battery, (70, 90)),
+            Err(ChargeThresholdError::NotHeld { held: (50, 100), .. })
+        ));
+    }
+
+    #[test]
+    fn charge_behaviours() {
+        let (selected, behaviours) =
+            parse_charge_behaviour("[auto] inhibit-charge force-discharge\n");
+        assert_eq!(selected, "auto");
+        assert_eq!(behaviours, ["auto", "inhibit-charge", "force-discharge"]);
+
+        let (selected, behaviours) = parse_charge_behaviour("auto [force-discharge]");
+        assert_eq!(selected, FORCE_DISCHARGE);
+        assert_eq!(behaviours.len(), 2);
+        assert_eq!(parse_charge_behaviour(""), (String::new(), Vec::new()));
+    }
+
+    #[test]
+    fn unknown_battery() {
+        for battery in ["BAT9", "../BAT0", ""] {
+            assert!(matches!(
+                selected((40, 80), Some(battery)),
+                Err(ChargeThresholdError::UnknownBattery(name)) if name == battery
+            ));
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let profiles = builtin_charge_profiles();
+        let id = |start, end| matching_profile(&profiles, start, end).map(|p| p.id.as_str());
+        assert_eq!(id(Some(50), 60), Some("max_lifespan"));
+        assert_eq!(id(Some(40), 60), None);
+        assert_eq!(id(None, 90), Some("balanced"));
+        assert_eq!(id(None, 80), None);
+    }
 }
@@ -2,17 +2,397 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::args::{Args, GraphicsArgs};
+use crate::{
+    args::{
+        Args, ChargeThresholdsArgs, Command, Format, GraphicsArgs, PowerProfile, ProfileArgs,
+        ProfileName,
+    },
+    boost::Boost,
+    charge_thresholds::{
+        battery_names, battery_threshold_files, get_charge_profiles, get_charge_thresholds_status,
+        matching_profile, plan_charge_thresholds, ThresholdFiles, CUSTOM_PROFILE,
+    },
+    cpufreq,
+    daemon::{
+        direct::{self, DirectError},
+        plan_profile,
+    },
+    graphics::Graphics,
+    info::Report,
+    pcie_aspm, plain, power_draw, power_supply, tunables, usb_autosuspend,
+    util::Written,
+    wifi,
+};
 use anyhow::Context;
+use futures_lite::StreamExt;
 use intel_pstate::PState;
-use std::io;
-use sysfs_class::{Backlight, Brightness, Leds, SysClass};
-use system76_power_zbus::PowerDaemonProxy;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, IsTerminal, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use sysfs_class::{Backlight, Brightness, Leds, ScsiHost, SysClass};
+use system76_power_zbus::{
+    client::Client, BatteryCapStatus, BatteryStatus, BatteryThresholds, CalibrationStatus,
+    ChargeProfile, ChargeThresholdsStatus, CpuFrequencyStatus, GraphicsCapabilities,
+    GraphicsDeviceInfo, GraphicsMode, GraphicsPower, GraphicsPowerStatus, GraphicsStatus,
+    JobStatus, PlannedAction, Profile, ProfileFailure, ProfileInfo, ProfileParameter,
+    ProfileStatus, ProfileTunable, SwitchableStatus,
+};
 
-async fn profile(client: &mut PowerDaemonProxy<'_>) -> io::Result<()> {
-    let profile = client.get_profile().await.ok();
-    let profile = profile.as_ref().map_or("?", |s| s.as_str());
-    println!("Power Profile: {}", profile);
+// Output of the queries with `--json`, for those not returned as is by the daemon. The fields
+// are documented in the README, and their names are kept across versions, as are the keys of
+// `--format plain`, which are derived from them.
+
+/// `graphics --short`
+#[derive(Serialize)]
+struct ModeOutput {
+    mode: String,
+}
+
+/// `graphics default`
+#[derive(Serialize)]
+struct RecommendationOutput {
+    mode:       String,
+    reason:     String,
+    /// The mode configured for the next boot, `custom`, or empty.
+    configured: String,
+}
+
+/// `graphics power auto|on|off`
+#[derive(Serialize)]
+struct PowerOutput {
+    /// `on` or `off`.
+    power: &'static str,
+}
+
+/// `graphics power off --force`
+#[derive(Serialize)]
+struct ForcedPowerOutput {
+    power:  &'static str,
+    /// Actions taken only because the power off was forced.
+    forced: Vec<String>,
+}
+
+/// `--dry-run` of `profile`, `graphics` mode switches and `charge-thresholds`
+#[derive(Serialize)]
+struct PlanOutput {
+    /// Files that would be written or removed, and commands that would be run, in order.
+    actions: Vec<PlannedAction>,
+}
+
+/// `graphics power --dry-run`
+#[derive(Serialize)]
+struct PowerPlanOutput {
+    power:   &'static str,
+    actions: Vec<String>,
+}
+
+/// `graphics hotplug-check`
+#[derive(Serialize)]
+struct HotplugOutput {
+    external_displays_require_dgpu: bool,
+}
+
+/// `graphics list`
+#[derive(Serialize)]
+struct DevicesOutput {
+    devices: Vec<GraphicsDeviceInfo>,
+}
+
+/// `graphics watch`, one per line.
+#[derive(Serialize)]
+struct ModeChangeOutput<'a> {
+    old:             &'a str,
+    new:             &'a str,
+    reboot_required: bool,
+}
+
+/// `profile --watch`, one per line.
+#[derive(Serialize)]
+struct ProfileSwitchOutput<'a> {
+    old:       &'a str,
+    new:       &'a str,
+    initiator: &'a str,
+}
+
+/// `charge-thresholds --watch`, one per line.
+#[derive(Serialize)]
+struct ChargeThresholdsSwitchOutput<'a> {
+    /// Thresholds of each battery, keyed by power supply name.
+    batteries: BTreeMap<String, ThresholdsOutput>,
+    initiator: &'a str,
+}
+
+/// Thresholds of a battery in `charge-thresholds --watch`, with a start of 0 if it has no start
+/// threshold, or those requested in `charge-thresholds`.
+#[derive(Serialize)]
+struct ThresholdsOutput {
+    start: u8,
+    end:   u8,
+}
+
+/// `charge-thresholds`
+#[derive(Serialize)]
+struct ChargeThresholdsOutput {
+    /// ID of the profile matching the thresholds, `custom` if none does, or empty if
+    /// unsupported.
+    profile:         String,
+    /// Thresholds of the first battery.
+    start:           u8,
+    end:             u8,
+    supported:       bool,
+    start_supported: bool,
+    min:             u8,
+    max:             u8,
+    /// Thresholds of each battery, keyed by power supply name.
+    batteries:       BTreeMap<String, BatteryThresholdsOutput>,
+    /// Errors of the batteries whose thresholds failed to be set, keyed by power supply name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    failures:        BTreeMap<String, String>,
+    /// Thresholds just set, as requested, which the firmware of the batteries may have rounded
+    /// to those they hold.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requested:       Option<ThresholdsOutput>,
+}
+
+/// Thresholds of a battery in `charge-thresholds`.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct BatteryThresholdsOutput {
+    /// Whether the battery has an end threshold.
+    supported:       bool,
+    start_supported: bool,
+    /// Thresholds, or null if the battery lacks them.
+    start:           Option<u8>,
+    end:             Option<u8>,
+    /// ID of the profile matching the thresholds, `custom` if none does, or null if
+    /// unsupported.
+    profile:         Option<String>,
+    /// Variant of the files holding the thresholds, such as `charge_control`, or null if
+    /// unsupported.
+    variant:         Option<String>,
+}
+
+impl BatteryThresholdsOutput {
+    fn new(
+        thresholds: Option<&BatteryThresholds>,
+        files: Option<&ThresholdFiles>,
+        profiles: &[ChargeProfile],
+    ) -> Self {
+        let Some(thresholds) = thresholds else {
+            return Self {
+                supported:       false,
+                start_supported: false,
+                start:           None,
+                end:             None,
+                profile:         None,
+                variant:         None,
+            };
+        };
+
+        let start = thresholds.start_supported.then_some(thresholds.start);
+        let profile = matching_profile(profiles, start, thresholds.end)
+            .map_or(CUSTOM_PROFILE, |profile| profile.id.as_str());
+        Self {
+            supported: true,
+            start_supported: thresholds.start_supported,
+            start,
+            end: Some(thresholds.end),
+            profile: Some(profile.to_owned()),
+            variant: files.map(|files| files.variant.to_owned()),
+        }
+    }
+}
+
+/// `profile`
+#[derive(Serialize, Default)]
+struct ProfileOutput {
+    #[serde(flatten)]
+    status:                ProfileStatus,
+    /// Profile restored when the daemon starts, or empty if unknown.
+    persisted:             String,
+    /// Parameters the profile failed to set, or empty if the daemon predates them.
+    failures:              Vec<ProfileFailure>,
+    /// Scaling of the CPUs, read by the client if the daemon predates it.
+    cpu:                   CpuFrequencyStatus,
+    /// Estimates of the power drawn, in watts, with `--verbose`, leaving out those which
+    /// cannot be made.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    power_estimate:        BTreeMap<String, f64>,
+    /// Cap of the CPU frequency on battery, left out if the daemon predates it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    battery_frequency_cap: Option<BatteryCapStatus>,
+}
+
+/// `profile --list`
+#[derive(Serialize)]
+struct ProfilesOutput {
+    profiles: Vec<ProfileInfo>,
+    /// Parameters which `profiles.toml` may override, or empty if the daemon predates them.
+    tunables: Vec<ProfileTunable>,
+}
+
+/// `charge-thresholds --list-profiles`
+#[derive(Serialize)]
+struct ChargeProfilesOutput {
+    profiles: Vec<ChargeProfileOutput>,
+}
+
+/// A charge profile in `charge-thresholds --list-profiles`.
+#[derive(Serialize)]
+struct ChargeProfileOutput {
+    #[serde(flatten)]
+    profile: ChargeProfile,
+    /// Whether the profile matches the thresholds of the first battery.
+    current: bool,
+}
+
+impl ChargeProfilesOutput {
+    /// The profiles, marking the one of `current`, the ID of the profile the thresholds match.
+    fn new(profiles: Vec<ChargeProfile>, current: &str) -> Self {
+        let profiles = profiles
+            .into_iter()
+            .map(|profile| ChargeProfileOutput { current: profile.id == current, profile })
+            .collect();
+        Self { profiles }
+    }
+}
+
+/// `battery`
+#[derive(Serialize)]
+struct BatteriesOutput<'a> {
+    batteries: &'a [BatteryStatus],
+}
+
+/// A failure, printed to stderr.
+#[derive(Serialize)]
+struct ErrorOutput<'a> {
+    /// Name of the DBus error, or empty for failures of the client.
+    error:     &'a str,
+    message:   &'a str,
+    /// Exit code of the client.
+    code:      i32,
+    /// Symbolic name of the exit code, such as `daemon-unreachable`.
+    code_name: &'static str,
+}
+
+// Set once by `client`, as the output is printed from everywhere `--json` is handled.
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Prints the output of a query as JSON, or as `KEY=VALUE` lines with `--format plain`.
+fn print_json(value: &impl Serialize) -> anyhow::Result<()> {
+    if PLAIN.load(Ordering::Relaxed) {
+        println!("{}", plain::to_string(value)?);
+    } else {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    }
+    Ok(())
+}
+
+/// Prints an event of a stream on a single line, as JSON or as `KEY=VALUE` pairs.
+fn print_event(value: &impl Serialize) -> anyhow::Result<()> {
+    if PLAIN.load(Ordering::Relaxed) {
+        println!("{}", plain::to_line(value)?);
+    } else {
+        println!("{}", serde_json::to_string(value)?);
+    }
+    Ok(())
+}
+
+/// Where the client prints, besides the results of queries and JSON, which always go to
+/// stdout: the outcome of a change to stdout, and progress and other information to stderr.
+/// `--quiet` silences both, leaving errors, which are printed by `print_error`.
+struct Output<O = io::Stdout, E = io::Stderr> {
+    out:     O,
+    err:     E,
+    quiet:   bool,
+    /// Whether queries print details, such as each function of the discrete GPU.
+    verbose: bool,
+}
+
+impl Output {
+    fn new(quiet: bool, verbose: bool) -> Self {
+        Self { out: io::stdout(), err: io::stderr(), quiet, verbose }
+    }
+}
+
+impl<O: Write, E: Write> Output<O, E> {
+    /// Prints the outcome of a change, such as the final status of a graphics switch.
+    fn outcome(&mut self, line: impl fmt::Display) {
+        if !self.quiet {
+            let _res = writeln!(self.out, "{}", line);
+        }
+    }
+
+    /// Prints progress or other information, such as the steps of a graphics switch.
+    fn info(&mut self, line: impl fmt::Display) {
+        if !self.quiet {
+            let _res = writeln!(self.err, "{}", line);
+        }
+    }
+}
+
+/// Prints an error to stderr in the format of the output, returning the exit code.
+pub fn print_error(why: &anyhow::Error, format: Format, quiet: bool) -> i32 {
+    write_error(&mut io::stderr(), why, format, quiet)
+}
+
+/// Writes an error as JSON or `ERROR=...` lines if requested, or else as text, on a single
+/// line if quiet, returning the exit code.
+fn write_error(err: &mut impl Write, why: &anyhow::Error, format: Format, quiet: bool) -> i32 {
+    let (name, code) = why
+        .downcast_ref::<ClientError>()
+        .map_or(("", ExitCode::Failure), |why| (why.name.as_str(), why.code));
+
+    let message = format!("{:#}", why);
+    let output = ErrorOutput {
+        error:     name,
+        message:   message.trim(),
+        code:      code.into(),
+        code_name: code.name(),
+    };
+
+    let _res = if format == Format::Json {
+        writeln!(err, "{}", serde_json::to_string(&output).unwrap_or_default())
+    } else if format == Format::Plain {
+        writeln!(err, "{}", plain::to_string(&output).unwrap_or_default())
+    } else if quiet {
+        let message = format!("{:#}", why);
+        let lines = message.lines().map(str::trim).filter(|line| !line.is_empty());
+        writeln!(err, "{}", lines.collect::<Vec<_>>().join(" "))
+    } else {
+        writeln!(err, "{:?}", why)
+    };
+
+    code.into()
+}
+
+/// Prints the profile, if known, with the profile restored on startup if it differs, the cap
+/// of the CPU frequency on battery if enabled, and the state of the hardware it changes. The
+/// parameters the hardware lacks are listed with their paths only if `verbose`, with the policy of
+/// each SATA host and the power save of each Wi-Fi interface, while those it failed to set are
+/// flagged with their error.
+fn profile(
+    status: Option<ProfileStatus>,
+    persisted: Option<String>,
+    failures: &[ProfileFailure],
+    cpu: &CpuFrequencyStatus,
+    power: &BTreeMap<String, f64>,
+    cap: Option<&BatteryCapStatus>,
+    verbose: bool,
+) -> io::Result<()> {
+    match status {
+        Some(ref status) => {
+            println!("Power Profile: {}", status.profile);
+            if let Some(persisted) = persisted.filter(|p| *p != status.profile) {
+                println!("Persisted Profile: {}", persisted);
+            }
+        }
+        None => println!("Power Profile: ?"),
+    }
 
     if let Ok(values) = PState::new().and_then(|pstate| pstate.values()) {
         println!(
@@ -21,6 +401,43 @@ async fn profile(client: &mut PowerDaemonProxy<'_>) -> io::Result<()> {
             values.max_perf_pct,
             if values.no_turbo { "No Turbo" } else { "Turbo" }
         );
+    } else if let Some(enabled) = Boost::detect().and_then(|boost| boost.get()) {
+        println!("CPU Boost: {}", if enabled { "on" } else { "off" });
+    }
+
+    if let Some(cap) = cap.filter(|cap| cap.enabled) {
+        let state = if cap.active { "active" } else { "inactive" };
+        println!("Battery frequency cap: {}% ({})", cap.percent, state);
+    }
+
+    if let Some(policy) = pcie_aspm::current() {
+        println!("PCIe ASPM policy: {}", policy);
+    }
+
+    // The scaling of the CPUs, the policy of each SATA host, including those which rejected
+    // that of the profile, and the power save of each Wi-Fi interface.
+    if verbose {
+        cpu_frequency(cpu);
+
+        if let Some(watts) = power.get(power_draw::PACKAGE) {
+            println!("CPU package power: {:.1} W (estimate)", watts);
+        }
+        if let Some(watts) = power.get(power_draw::BATTERY) {
+            println!("Battery discharge: {:.1} W (estimate)", watts);
+        }
+
+        for host in ScsiHost::iter().filter_map(Result::ok) {
+            let path = host.path().join("link_power_management_policy");
+            if let Ok(policy) = fs::read_to_string(path) {
+                println!("SATA link policy {}: {}", host.id(), policy.trim());
+            }
+        }
+
+        for interface in wifi::interfaces() {
+            if let Some(enabled) = wifi::power_save(&interface) {
+                println!("Wi-Fi power save {}: {}", interface, if enabled { "on" } else { "off" });
+            }
+        }
     }
 
     for backlight in Backlight::iter() {
@@ -47,129 +464,2476 @@ async fn profile(client: &mut PowerDaemonProxy<'_>) -> io::Result<()> {
         );
     }
 
+    if let Some(status) = status {
+        // USB devices are counted, and only listed if `verbose`, as there are many.
+        let (usb, mut parameters): (Vec<_>, Vec<_>) = status
+            .parameters
+            .into_iter()
+            .partition(|parameter| parameter.name == usb_autosuspend::NAME);
+        if !usb.is_empty() {
+            let set = usb.iter().filter(|parameter| !parameter.intended.is_empty()).count();
+            println!("USB autosuspend: {} devices set, {} skipped", set, usb.len() - set);
+        }
+        if verbose {
+            parameters.extend(usb);
+        }
+
+        // Parameters the hardware lacks have no value to set.
+        let (set, not_applicable): (Vec<_>, Vec<_>) =
+            parameters.into_iter().partition(|parameter| !parameter.intended.is_empty());
+
+        if verbose {
+            list_parameters(&[set, not_applicable].concat(), failures);
+        } else {
+            list_parameters(&set, failures);
+            if !not_applicable.is_empty() {
+                let names = not_applicable.iter().map(|parameter| parameter.name.as_str());
+                println!("Not applicable: {}", names.collect::<Vec<_>>().join(", "));
+            }
+        }
+    }
+
     Ok(())
 }
 
-#[tokio::main(flavor = "current_thread")]
-pub async fn client(args: &Args) -> anyhow::Result<()> {
-    let connection =
-        zbus::Connection::system().await.context("failed to create zbus system connection")?;
+/// Estimates of the power drawn, with `verbose`, as the daemon makes them, reading the energy
+/// counters reserved to root, or as the client does if the daemon predates them.
+async fn estimate_power(client: &Client<'_>, verbose: bool) -> BTreeMap<String, f64> {
+    if !verbose {
+        return BTreeMap::new();
+    }
 
-    let mut client = PowerDaemonProxy::new(&connection)
-        .await
-        .context("failed to connect to system76-power daemon")?;
+    match client.power_draw().await {
+        Ok(watts) => watts,
+        Err(_) => local_power_estimate(),
+    }
+}
 
-    match args {
-        Args::Profile { profile: name } => match name.as_deref() {
-            Some("balanced") => client.balanced().await.map_err(zbus_error),
-            Some("battery") => {
-                if client.get_desktop().await.map_err(zbus_error)? {
-                    return Err(anyhow::anyhow!(
-                        r#"
-Battery power profile is not supported on desktop computers.
-"#,
-                    ));
-                }
-                client.battery().await.map_err(zbus_error)
-            }
-            Some("performance") => client.performance().await.map_err(zbus_error),
-            _ => profile(&mut client).await.context("failed to get power profile"),
-        },
-        Args::Graphics { cmd } => {
-            if !client.get_switchable().await? {
-                return Err(anyhow::anyhow!(
-                    r#"
-Graphics switching is not supported on this device, because
-this device is either a desktop or doesn't have both an iGPU and dGPU.
-"#,
-                ));
-            }
+fn local_power_estimate() -> BTreeMap<String, f64> {
+    power_draw::estimate().into_iter().map(|(key, watts)| (key.to_owned(), watts)).collect()
+}
 
-            match cmd.as_ref() {
-                Some(GraphicsArgs::Compute) => {
-                    client.set_graphics("compute").await.map_err(zbus_error)
-                }
-                Some(GraphicsArgs::Hybrid) => {
-                    client.set_graphics("hybrid").await.map_err(zbus_error)
-                }
-                Some(GraphicsArgs::Integrated) => {
-                    client.set_graphics("integrated").await.map_err(zbus_error)
-                }
-                Some(GraphicsArgs::Nvidia) => {
-                    client.set_graphics("nvidia").await.map_err(zbus_error)
-                }
-                Some(GraphicsArgs::Switchable) => client
-                    .get_switchable()
-                    .await
-                    .map_err(zbus_error)
-                    .map(|b| println!("{}", if b { "switchable" } else { "not switchable" })),
-                Some(GraphicsArgs::Power { state }) => match state.as_deref() {
-                    Some("auto") => client.auto_graphics_power().await.map_err(zbus_error),
-                    Some("off") => client.set_graphics_power(false).await.map_err(zbus_error),
-                    Some("on") => client.set_graphics_power(true).await.map_err(zbus_error),
-                    _ => {
-                        if client.get_graphics_power().await.map_err(zbus_error)? {
-                            println!("on (discrete)");
-                        } else {
-                            println!("off (discrete)");
-                        }
-                        Ok(())
+/// Prints the scaling driver, governors, boost and frequencies of the CPUs, which are unknown
+/// without cpufreq.
+fn cpu_frequency(cpu: &CpuFrequencyStatus) {
+    if cpu.scaling_driver.is_empty() {
+        println!("CPU scaling: unknown, cpufreq is not available");
+        return;
+    }
+
+    match cpu.driver_mode.as_str() {
+        "" => println!("CPU scaling driver: {}", cpu.scaling_driver),
+        mode => println!("CPU scaling driver: {} ({})", cpu.scaling_driver, mode),
+    }
+
+    if !cpu.governor.is_empty() {
+        println!("CPU governor: {}", cpu.governor);
+    }
+    for policy in &cpu.policy_governors {
+        println!("CPU governor {}: {}", policy.policy, policy.governor);
+    }
+
+    if !cpu.energy_performance_preference.is_empty() {
+        println!("CPU energy performance preference: {}", cpu.energy_performance_preference);
+    }
+    if !cpu.boost.is_empty() {
+        println!("CPU boost: {}", cpu.boost);
+    }
+
+    let mhz = |khz: u32| if khz == 0 { "?".to_owned() } else { format!("{} MHz", khz / 1000) };
+    println!(
+        "CPU frequency: {} ({} - {})",
+        mhz(cpu.current_khz),
+        mhz(cpu.min_khz),
+        mhz(cpu.max_khz)
+    );
+}
+
+/// Prints the parameters set by the profile, flagging those which failed, those changed since,
+/// and those the hardware lacks.
+fn list_parameters(parameters: &[ProfileParameter], failures: &[ProfileFailure]) {
+    if parameters.is_empty() {
+        return;
+    }
+
+    let current = |parameter: &ProfileParameter| match parameter.current.as_str() {
+        _ if parameter.intended.is_empty() => "not applicable".to_owned(),
+        "" => "unknown".to_owned(),
+        current => current.to_owned(),
+    };
+    let width = |column: &dyn Fn(&ProfileParameter) -> usize, title: &str| {
+        parameters.iter().map(column).chain([title.len()]).max().unwrap_or_default()
+    };
+
+    let name = width(&|parameter| parameter.name.len(), "Parameter");
+    let intended = width(&|parameter| parameter.intended.len(), "Set");
+    let current_width = width(&|parameter| current(parameter).len(), "Current");
+
+    let flag = |parameter: &ProfileParameter| {
+        let failure = failures.iter().find(|failure| failure.path == parameter.path);
+        match failure {
+            Some(failure) => format!(" (failed: {})", failure.message),
+            None if !parameter.matches => " (changed)".to_owned(),
+            None => String::new(),
+        }
+    };
+
+    println!("{:name$}  {:intended$}  {:current_width$}  Path", "Parameter", "Set", "Current");
+    for parameter in parameters {
+        println!(
+            "{:name$}  {:intended$}  {:current_width$}  {}{}",
+            parameter.name,
+            parameter.intended,
+            current(parameter),
+            parameter.path,
+            flag(parameter)
+        );
+    }
+}
+
+/// Warns of the parameters a profile failed to set, although it set most others, failing with
+/// `strict`.
+fn profile_failures(
+    output: &mut Output,
+    failures: &[ProfileFailure],
+    strict: bool,
+) -> anyhow::Result<()> {
+    for failure in failures {
+        match failure.path.as_str() {
+            "" => output.info(format_args!("warning: {}: {}", failure.name, failure.message)),
+            path => output.info(format_args!(
+                "warning: failed to write {} to {}: {}",
+                failure.value, path, failure.message
+            )),
+        }
+    }
+
+    if strict && !failures.is_empty() {
+        let message = format!("{} parameters of the profile failed to apply", failures.len());
+        return Err(ClientError::failure(ExitCode::PartiallyApplied, message));
+    }
+
+    Ok(())
+}
+
+/// Switches the graphics mode, printing the progress of the job to stderr until it finishes,
+/// and its final status, or only its final status as JSON.
+///
+/// Without `wait`, returns once the job has started. Ctrl-C or the `timeout` stop following the
+/// job, which continues in the daemon.
+///
+/// With `no_initramfs`, the initramfs is not rebuilt, which is warned about once the switch
+/// succeeded, or started.
+#[allow(clippy::too_many_arguments)]
+async fn set_graphics(
+    client: &Client<'_>,
+    output: &mut Output,
+    mode: GraphicsMode,
+    force: bool,
+    no_initramfs: bool,
+    json: bool,
+    wait: bool,
+    timeout: Option<Duration>,
+) -> anyhow::Result<()> {
+    let path = if no_initramfs {
+        client.start_graphics_switch_without_initramfs(mode, force).await
+    } else {
+        client.start_graphics_switch(mode, force).await
+    };
+    let path = path.map_err(zbus_error)?;
+    let warn = || {
+        if no_initramfs {
+            warn_initramfs_skipped(mode);
+        }
+    };
+
+    if !wait {
+        warn();
+        if json {
+            return print_json(&client.job(&path).await.map_err(zbus_error)?);
+        }
+
+        output.outcome(format_args!("Switching to {} as job {}", mode, path.as_str()));
+        return Ok(());
+    }
+
+    let follow = client.follow_job(&path, |step, percent| {
+        if !json {
+            output.info(format_args!("{:>3}% {}", percent, step));
+        }
+    });
+
+    let status = tokio::select! {
+        status = follow => Some(status.map_err(zbus_error)?),
+        _ = tokio::signal::ctrl_c() => None,
+        () = tokio::time::sleep(timeout.unwrap_or(Duration::MAX)), if timeout.is_some() => {
+            return Err(anyhow::anyhow!(
+                "timed out waiting for job {}, which continues in the background",
+                path.as_str()
+            ));
+        }
+    };
+
+    let Some(status) = status else {
+        output.info(format_args!(
+            "Stopped waiting; job {} continues in the background",
+            path.as_str()
+        ));
+        return Ok(());
+    };
+
+    if !status.success {
+        return Err(dbus_error(&status.error, &status.message));
+    }
+
+    warn();
+    if json {
+        return print_json(&status);
+    }
+
+    output.outcome(&status.message);
+    Ok(())
+}
+
+fn warn_initramfs_skipped(mode: GraphicsMode) {
+    log::warn!(
+        "the initramfs was not regenerated; rebuild it with `dracut --force` before rebooting, or \
+         the {} mode may not start",
+        mode
+    );
+}
+
+fn graphics_status(status: &GraphicsStatus, json: bool) -> anyhow::Result<()> {
+    if json {
+        return print_json(status);
+    }
+
+    println!("Mode: {}", status.mode);
+    println!(
+        "Configured: {}",
+        if status.configured.is_empty() { "none" } else { &status.configured }
+    );
+    println!("Discrete GPU: {}", status.power);
+    println!("Reboot required: {}", if status.reboot_required { "yes" } else { "no" });
+    Ok(())
+}
+
+fn graphics_mode(mode: GraphicsMode, json: bool) -> anyhow::Result<()> {
+    if json {
+        return print_json(&ModeOutput { mode: mode.to_string() });
+    }
+
+    println!("{}", mode);
+    Ok(())
+}
+
+/// Powers off the discrete GPU even though it may be in use, and prints what was forced.
+async fn force_graphics_power_off(
+    client: &Client<'_>,
+    output: &mut Output,
+    cmd: Option<&GraphicsArgs>,
+    json: bool,
+) -> anyhow::Result<()> {
+    if !matches!(cmd, Some(GraphicsArgs::Power { state: Some(state) }) if state == "off") {
+        let message = "--force only applies to `graphics power off`";
+        return Err(ClientError::failure(ExitCode::Usage, message));
+    }
+
+    let forced = client.force_graphics_power_off().await.map_err(zbus_error)?;
+    if json {
+        return print_json(&ForcedPowerOutput { power: "off", forced });
+    }
+
+    print_forced(output, &forced);
+    Ok(())
+}
+
+fn print_forced(output: &mut Output<impl Write, impl Write>, forced: &[String]) {
+    if forced.is_empty() {
+        output.outcome("off (discrete), nothing needed forcing");
+    } else {
+        output.outcome("off (discrete), forced:");
+        for action in forced {
+            output.outcome(format_args!("  - {}", action));
+        }
+    }
+}
+
+/// A change which may be planned with `--dry-run`, or made without the daemon with `--direct`.
+enum Change<'a> {
+    /// The profile, and `--temporary`.
+    Profile(&'a ProfileName, bool),
+    /// The mode, `--force` and `--no-initramfs`.
+    Graphics(GraphicsMode, bool, bool),
+    GraphicsPower(GraphicsPower, bool),
+    /// The thresholds, and `--battery`.
+    ChargeThresholds((u8, u8), Option<&'a str>),
+    /// The charge profile, and `--battery`.
+    ChargeProfile(&'a str, Option<&'a str>),
+}
+
+impl<'a> Change<'a> {
+    /// The change requested by the arguments, if it is one of the above.
+    fn new(args: &'a Command) -> anyhow::Result<Option<Self>> {
+        let request = match args {
+            Command::Profile { profile: Some(profile), cmd: None, temporary, .. } => {
+                Some(Self::Profile(profile, *temporary))
+            }
+            Command::Graphics { cmd: Some(cmd), force, .. } => match cmd {
+                GraphicsArgs::Power { state: Some(state) } => {
+                    if *force && state != "off" {
+                        let message = "--force only applies to `graphics power off`";
+                        return Err(ClientError::failure(ExitCode::Usage, message));
                     }
-                },
-                None => {
-                    println!("{}", client.get_graphics().await.map_err(zbus_error)?);
-                    Ok(())
+                    Some(Self::GraphicsPower(graphics_power_state(state), *force))
                 }
+                cmd => GraphicsMode::try_from(cmd)
+                    .ok()
+                    .map(|mode| Self::Graphics(mode, *force, cmd.no_initramfs())),
+            },
+            Command::ChargeThresholds { start: Some(start), end: Some(end), battery, .. } => {
+                Some(Self::ChargeThresholds((*start, *end), battery.as_deref()))
+            }
+            Command::ChargeThresholds { profile: Some(name), battery, .. } => {
+                Some(Self::ChargeProfile(name, battery.as_deref()))
             }
+            _ => None,
+        };
+
+        Ok(request)
+    }
+
+    /// The change to plan with `--dry-run`.
+    fn planned(args: &'a Command) -> anyhow::Result<Self> {
+        Self::new(args)?.ok_or_else(|| {
+            ClientError::failure(
+                ExitCode::Usage,
+                "--dry-run only applies to setting a profile, a graphics mode, the graphics power \
+                 or charge thresholds",
+            )
+        })
+    }
+}
+
+/// Prints the changes a command would make, as planned by the daemon, without making them.
+async fn dry_run(client: &Client<'_>, args: &Command) -> anyhow::Result<()> {
+    let actions = match Change::planned(args)? {
+        Change::Profile(ProfileName::Builtin(profile), _) => {
+            client.plan_profile((*profile).into()).await
         }
-        Args::ChargeThresholds { profile, list_profiles, thresholds } => {
-            if client.get_desktop().await.map_err(zbus_error)? {
-                return Err(anyhow::anyhow!(
-                    r#"
-Charge thresholds are not supported on desktop computers.
-"#,
-                ));
+        Change::Profile(ProfileName::Custom(name), _) => client.plan_profile_by_name(name).await,
+        Change::Graphics(mode, force, false) => client.plan_graphics_switch(mode, force).await,
+        Change::Graphics(mode, force, true) => {
+            client.plan_graphics_switch_without_initramfs(mode, force).await
+        }
+        Change::GraphicsPower(power, force) => {
+            let (on, actions) =
+                client.plan_graphics_power(power, force).await.map_err(zbus_error)?;
+            return print_power_plan(on, actions, args.json());
+        }
+        Change::ChargeThresholds(thresholds, battery) => {
+            plan_charge_thresholds_of(client, thresholds, battery).await
+        }
+        Change::ChargeProfile(name, battery) => {
+            let profiles = client.charge_profiles().await.map_err(zbus_error)?;
+            let thresholds = charge_profile_thresholds(&profiles, name)?;
+            plan_charge_thresholds_of(client, thresholds, battery).await
+        }
+    };
+
+    print_plan(actions.map_err(zbus_error)?, args.json())
+}
+
+/// The values setting the thresholds of `battery`, or of every battery, would write, as planned
+/// by daemons predating `--battery` for every battery.
+async fn plan_charge_thresholds_of(
+    client: &Client<'_>,
+    thresholds: (u8, u8),
+    battery: Option<&str>,
+) -> zbus::Result<Vec<PlannedAction>> {
+    match battery {
+        Some(_) => client.plan_battery_charge_thresholds(battery, thresholds).await,
+        None => client.plan_charge_thresholds(thresholds).await,
+    }
+}
+
+/// Prints the changes a command would make, as planned from sysfs, without making them.
+fn local_dry_run(args: &Command) -> anyhow::Result<()> {
+    let graphics = || Graphics::without_rescan().context("failed to read the PCI bus");
+    let written = |written: Vec<Written>| written.iter().map(Written::planned).collect();
+
+    let actions = match Change::planned(args)? {
+        // Backlights are only dimmed once the daemon set a profile since it started.
+        Change::Profile(profile, _) => {
+            if let Err(why) = tunables::load() {
+                log::warn!("planning the built-in profile: {}", why);
             }
+            let name = profile.daemon_name();
+            let Some(profile) = tunables::find(&name) else {
+                let message = format!("No such profile '{}'", name);
+                return Err(ClientError::failure(ExitCode::Usage, message));
+            };
+            plan_profile(&profile, false)
+        }
+        Change::Graphics(mode, force, no_initramfs) => {
+            graphics()?.plan_vendor(mode, force, no_initramfs)?
+        }
+        Change::GraphicsPower(power, force) => {
+            let power = match power {
+                GraphicsPower::On => Some(true),
+                GraphicsPower::Off => Some(false),
+                GraphicsPower::Auto => None,
+            };
 
-            let profiles = client.get_charge_profiles().await.map_err(zbus_error)?;
+            let (on, actions) = graphics()?.plan_power(power, force)?;
+            let actions = actions.iter().map(ToString::to_string).collect();
+            return print_power_plan(on, actions, args.json());
+        }
+        Change::ChargeThresholds(thresholds, battery) => {
+            written(plan_charge_thresholds(thresholds, battery)?.written)
+        }
+        Change::ChargeProfile(name, battery) => {
+            let thresholds = charge_profile_thresholds(&get_charge_profiles(), name)?;
+            written(plan_charge_thresholds(thresholds, battery)?.written)
+        }
+    };
 
-            if !thresholds.is_empty() {
-                let start = thresholds[0];
-                let end = thresholds[1];
-                client.set_charge_thresholds(&(start, end)).await.map_err(zbus_error)?;
-            } else if let Some(name) = profile {
-                if let Some(profile) = profiles.iter().find(|p| &p.id == name) {
-                    client
-                        .set_charge_thresholds(&(profile.start, profile.end))
-                        .await
-                        .map_err(zbus_error)?;
-                } else {
-                    return Err(anyhow::anyhow!("No such profile '{}'", name));
-                }
-            } else if *list_profiles {
-                for profile in &profiles {
-                    println!("{}", profile.id);
-                    println!("  Title: {}", profile.title);
-                    println!("  Description: {}", profile.description);
-                    println!("  Start: {}", profile.start);
-                    println!("  End: {}", profile.end);
+    print_plan(actions, args.json())
+}
+
+/// Makes a change without the daemon, printing what its reply would print.
+fn direct_change(change: Change, args: &Command, output: &mut Output) -> anyhow::Result<()> {
+    let json = args.json();
+
+    let (thresholds, battery) = match change {
+        Change::Profile(profile, temporary) => {
+            let failures =
+                direct::set_profile(&profile.daemon_name(), temporary).map_err(direct_error)?;
+            let strict = matches!(args, Command::Profile { strict: true, .. });
+            return profile_failures(output, &failures, strict);
+        }
+        Change::Graphics(mode, force, no_initramfs) => {
+            let mut last = (String::new(), 0);
+            let message = direct::set_graphics(mode, force, no_initramfs, |step, percent| {
+                if !json {
+                    output.info(format_args!("{:>3}% {}", percent, step));
                 }
-                return Ok(());
+                last = (step.to_owned(), percent);
+            })
+            .map_err(direct_error)?;
+
+            if no_initramfs {
+                warn_initramfs_skipped(mode);
             }
 
-            let (start, end) = client.get_charge_thresholds().await.map_err(zbus_error)?;
-            if let Some(profile) = profiles.iter().find(|p| p.start == start && p.end == end) {
-                println!("Profile: {} ({})", profile.title, profile.id);
-            } else {
-                println!("Profile: Custom");
+            if json {
+                let (step, percent) = last;
+                return print_json(&JobStatus {
+                    kind: "graphics".into(),
+                    step,
+                    percent,
+                    finished: true,
+                    success: true,
+                    message,
+                    error: String::new(),
+                });
             }
-            println!("Start: {}", start);
-            println!("End: {}", end);
 
-            Ok(())
+            output.outcome(message);
+            return Ok(());
+        }
+        Change::GraphicsPower(power, force) => {
+            let (on, forced) =
+                direct::set_graphics_power(power.into(), force).map_err(direct_error)?;
+            let power = if on { "on" } else { "off" };
+            match (force, json) {
+                (true, true) => print_json(&ForcedPowerOutput { power, forced })?,
+                (true, false) => print_forced(output, &forced),
+                (false, true) => print_json(&PowerOutput { power })?,
+                (false, false) => output.outcome(format_args!("{} (discrete)", power)),
+            }
+            return Ok(());
         }
-        Args::Daemon { .. } => unreachable!(),
+        Change::ChargeThresholds(thresholds, battery) => (thresholds, battery),
+        Change::ChargeProfile(name, battery) => {
+            (charge_profile_thresholds(&get_charge_profiles(), name)?, battery)
+        }
+    };
+
+    let writes = direct::set_charge_thresholds(thresholds, battery).map_err(direct_error)?;
+    let failures = writes.failures();
+    battery_failures(output, &failures);
+    let profiles = get_charge_profiles();
+    let status = get_charge_thresholds_status(&profiles)?;
+    skipped_starts(output, &status, battery);
+    applied_thresholds(output, &status, thresholds, &writes.applied);
+    if output.quiet && !json {
+        return Ok(());
+    }
+
+    charge_thresholds(status, &profiles, failures, Some(thresholds), json, output.verbose)
+}
+
+/// Notes the batteries set among `battery`, or every battery, which have no start threshold,
+/// so that only their end threshold was set.
+fn skipped_starts(output: &mut Output, status: &ChargeThresholdsStatus, battery: Option<&str>) {
+    for (name, thresholds) in &status.batteries {
+        if !thresholds.start_supported && battery.map_or(true, |battery| battery == name) {
+            output.info(format_args!(
+                "note: {} has no start threshold, only its end threshold was set",
+                name
+            ));
+        }
+    }
+}
+
+/// Sets the thresholds of `battery`, or of every battery if `None`, through the daemon,
+/// returning the errors of the batteries which failed, keyed by name. Daemons predating
+/// `--battery` report no failures.
+async fn set_battery_thresholds(
+    client: &Client<'_>,
+    battery: Option<&str>,
+    thresholds: (u8, u8),
+) -> anyhow::Result<BTreeMap<String, String>> {
+    match client.set_battery_charge_thresholds(battery, thresholds).await {
+        Err(ref why) if battery.is_none() && unknown_method(why) => {
+            client.set_charge_thresholds(thresholds).await.map_err(zbus_error)?;
+            Ok(BTreeMap::new())
+        }
+        result => result.map_err(zbus_error),
     }
 }
 
-fn zbus_error(why: zbus::Error) -> anyhow::Error { anyhow::anyhow!("{}", why) }
+/// Whether the daemon predates the method called.
+fn unknown_method(why: &zbus::Error) -> bool {
+    matches!(why, zbus::Error::MethodError(name, ..) if name.as_str().ends_with(".UnknownMethod"))
+}
+
+/// Notes the thresholds which the firmware of each battery set rounded, such as `requested 83,
+/// applied 85`, leaving out the start of those without a start threshold.
+fn applied_thresholds(
+    output: &mut Output,
+    status: &ChargeThresholdsStatus,
+    (start, end): (u8, u8),
+    applied: &BTreeMap<String, (u8, u8)>,
+) {
+    for (battery, &(applied_start, applied_end)) in applied {
+        let start_supported =
+            status.batteries.get(battery).map_or(false, |thresholds| thresholds.start_supported);
+        let thresholds =
+            [("start", start, applied_start, start_supported), ("end", end, applied_end, true)];
+        for (threshold, requested, value, supported) in thresholds {
+            if supported && requested != value {
+                output.info(format_args!(
+                    "note: {} rounded its {} threshold: requested {}, applied {}",
+                    battery, threshold, requested, value
+                ));
+            }
+        }
+    }
+}
+
+/// Warns of the batteries whose thresholds failed to be set, while the others were.
+fn battery_failures(output: &mut Output, failures: &BTreeMap<String, String>) {
+    for (battery, why) in failures {
+        output.info(format_args!("warning: failed to set the thresholds of {}: {}", battery, why));
+    }
+}
+
+/// The thresholds of the charge profile named `name`.
+fn charge_profile_thresholds(profiles: &[ChargeProfile], name: &str) -> anyhow::Result<(u8, u8)> {
+    profiles
+        .iter()
+        .find(|profile| profile.id == name)
+        .map(|profile| (profile.start, profile.end))
+        .ok_or_else(|| ClientError::failure(ExitCode::Usage, format!("No such profile '{}'", name)))
+}
+
+fn print_plan(actions: Vec<PlannedAction>, json: bool) -> anyhow::Result<()> {
+    if json {
+        return print_json(&PlanOutput { actions });
+    }
+
+    if actions.is_empty() {
+        println!("nothing would change");
+    }
+
+    for action in &actions {
+        println!("{}", action);
+    }
+    Ok(())
+}
+
+fn print_power_plan(on: bool, actions: Vec<String>, json: bool) -> anyhow::Result<()> {
+    let power = if on { "on" } else { "off" };
+    if json {
+        return print_json(&PowerPlanOutput { power, actions });
+    }
+
+    println!("{} (discrete), planned:", power);
+    for action in &actions {
+        println!("  - {}", action);
+    }
+    Ok(())
+}
+
+/// Parses a graphics power state of `on`, `off` or `auto`, as accepted by the arguments.
+fn graphics_power_state(state: &str) -> GraphicsPower {
+    match state {
+        "on" => GraphicsPower::On,
+        "off" => GraphicsPower::Off,
+        _ => GraphicsPower::Auto,
+    }
+}
+
+/// Prints whether the discrete GPU is active, suspended, off or needs a rescan, and with
+/// `verbose`, the driver and runtime PM status of each of its functions.
+fn graphics_power(status: &GraphicsPowerStatus, json: bool, verbose: bool) -> anyhow::Result<()> {
+    if json {
+        return print_json(status);
+    }
+
+    println!("{} (discrete)", status.power);
+    if status.power_cap_watts > 0 {
+        println!("power cap: {} W", status.power_cap_watts);
+    }
+    if verbose {
+        let unset = |value: &str| if value.is_empty() { "none" } else { value }.to_owned();
+        for function in &status.functions {
+            println!(
+                "  {}: driver {}, {}",
+                function.bus_id,
+                unset(&function.driver),
+                unset(&function.runtime_status)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Prints whether graphics can be switched, why not, and the GPUs found, failing if they
+/// cannot be switched.
+fn switchable(status: &SwitchableStatus, json: bool) -> anyhow::Result<()> {
+    if json {
+        print_json(status)?;
+    } else {
+        if status.switchable {
+            println!("switchable");
+        } else {
+            println!("not switchable ({})", status.reason);
+        }
+
+        for device in &status.devices {
+            let vendor = match device.vendor_id {
+                0x10DE => "NVIDIA".to_owned(),
+                0x1002 => "AMD".to_owned(),
+                0x8086 => "Intel".to_owned(),
+                id => format!("{:04x}", id),
+            };
+            let driver = if device.driver.is_empty() { "none" } else { &device.driver };
+            println!("  {}: {} {}, driver {}", device.bus_id, vendor, device.kind, driver);
+        }
+    }
+
+    if status.switchable {
+        Ok(())
+    } else {
+        Err(not_switchable())
+    }
+}
+
+fn graphics_capabilities(capabilities: &GraphicsCapabilities, json: bool) -> anyhow::Result<()> {
+    if json {
+        return print_json(capabilities);
+    }
+
+    let unknown = |value: &str| if value.is_empty() { "unknown" } else { value }.to_owned();
+    println!("Switchable: {}", if capabilities.switchable { "yes" } else { "no" });
+    println!("Desktop: {}", if capabilities.desktop { "yes" } else { "no" });
+    println!("Kernel driver version: {}", unknown(&capabilities.kernel_driver_version));
+    println!("Userspace driver version: {}", unknown(&capabilities.userspace_driver_version));
+    println!("GSP firmware: {}", unknown(&capabilities.gsp_firmware));
+    println!("NVIDIA kernel modules:");
+    for (kernel, module) in &capabilities.kernel_modules {
+        if module.module_present {
+            println!(
+                "  {}: {} ({})",
+                kernel,
+                unknown(&module.module_version),
+                unknown(&module.source)
+            );
+        } else {
+            println!("  {}: missing", kernel);
+        }
+    }
+    Ok(())
+}
+
+fn list_graphics(devices: &[GraphicsDeviceInfo]) {
+    let unknown = |value: &str| if value.is_empty() { "unknown" } else { value }.to_owned();
+
+    for device in devices {
+        println!(
+            "{} {} ({:04x}:{:04x})",
+            device.bus_id,
+            if device.name.is_empty() { "Unknown GPU" } else { &device.name },
+            device.vendor_id,
+            device.device_id
+        );
+        println!("  Kind: {}", device.kind);
+        println!(
+            "  Subsystem: {:04x}:{:04x}",
+            device.subsystem_vendor_id, device.subsystem_device_id
+        );
+        println!("  Driver: {}", if device.driver.is_empty() { "none" } else { &device.driver });
+        println!("  Power state: {}", unknown(&device.power_state));
+    }
+}
+
+/// Prints the attributes of each battery, those which cannot be read as unknown.
+fn batteries(batteries: &[BatteryStatus], json: bool) -> anyhow::Result<()> {
+    if json {
+        return print_json(&BatteriesOutput { batteries });
+    }
+
+    if batteries.is_empty() {
+        println!("No battery");
+    }
+
+    let known = |value: i32, unit: &str| {
+        if value < 0 {
+            "unknown".to_owned()
+        } else {
+            format!("{}{}", value, unit)
+        }
+    };
+    let from_milli = |value: i32| f64::from(value) / 1000.0;
+
+    for (index, battery) in batteries.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        let model = [battery.manufacturer.as_str(), battery.model.as_str()]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+        if model.is_empty() {
+            println!("{}: unknown model", battery.name);
+        } else {
+            println!("{}: {}", battery.name, model.join(" "));
+        }
+
+        println!("Status: {}", if battery.status.is_empty() { "unknown" } else { &battery.status });
+        println!("Charge: {}", known(battery.capacity, "%"));
+        if battery.energy_full_mwh < 0 || battery.energy_full_design_mwh < 0 {
+            println!("Health: {}", known(battery.health, "%"));
+        } else {
+            println!(
+                "Health: {} ({:.1} Wh of {:.1} Wh when new)",
+                known(battery.health, "%"),
+                from_milli(battery.energy_full_mwh),
+                from_milli(battery.energy_full_design_mwh)
+            );
+        }
+        println!("Cycles: {}", known(battery.cycle_count, ""));
+        if battery.power_mw < 0 {
+            println!("Rate: unknown");
+        } else {
+            println!("Rate: {:.1} W", from_milli(battery.power_mw));
+        }
+        match (battery.start_threshold, battery.end_threshold) {
+            (_, end) if end < 0 => println!("Thresholds: not supported by hardware"),
+            (start, end) if start < 0 => println!("Thresholds: end {}%", end),
+            (start, end) => println!("Thresholds: {}-{}%", start, end),
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the thresholds of each battery, and the profile they match, with the files holding
+/// them if `verbose`. Batteries without thresholds are listed as unsupported. `failures` are
+/// those of the thresholds just set, which are warned about already, keyed by battery, and
+/// `requested` the thresholds just set.
+fn charge_thresholds(
+    status: ChargeThresholdsStatus,
+    profiles: &[ChargeProfile],
+    failures: BTreeMap<String, String>,
+    requested: Option<(u8, u8)>,
+    json: bool,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let (start, end) =
+        status.batteries.values().next().map_or((0, 0), |first| (first.start, first.end));
+
+    let mut batteries = status
+        .batteries
+        .iter()
+        .map(|(name, thresholds)| {
+            let files = battery_threshold_files(name);
+            (name.clone(), BatteryThresholdsOutput::new(Some(thresholds), files, profiles))
+        })
+        .collect::<BTreeMap<_, _>>();
+    for name in battery_names() {
+        batteries.entry(name).or_insert_with(|| BatteryThresholdsOutput::new(None, None, profiles));
+    }
+
+    if json {
+        let profile = if status.supported && status.profile.is_empty() {
+            CUSTOM_PROFILE.to_owned()
+        } else {
+            status.profile
+        };
+
+        return print_json(&ChargeThresholdsOutput {
+            profile,
+            start,
+            end,
+            supported: status.supported,
+            start_supported: status.start_supported,
+            min: status.min,
+            max: status.max,
+            batteries,
+            failures,
+            requested: requested.map(|(start, end)| ThresholdsOutput { start, end }),
+        });
+    }
+
+    if !status.supported {
+        println!("Charge thresholds are not supported on this machine");
+        for name in batteries.keys() {
+            println!("{}: unsupported", name);
+        }
+        return Ok(());
+    }
+
+    match profiles.iter().find(|profile| profile.id == status.profile) {
+        Some(profile) => println!("Profile: {} ({})", profile.title, profile.id),
+        None => println!("Profile: Custom"),
+    }
+    match status.batteries.values().next() {
+        Some(first) if !first.start_supported => println!("Start: not supported by hardware"),
+        _ => println!("Start: {}", start),
+    }
+    println!("End: {}", end);
+    println!(
+        "Supported range: {} - {}{}",
+        status.min,
+        status.max,
+        if status.start_supported { "" } else { " (end threshold only)" }
+    );
+
+    if batteries.len() > 1 {
+        for (battery, thresholds) in &batteries {
+            let profile = thresholds.profile.as_deref().unwrap_or_default();
+            match (thresholds.start, thresholds.end) {
+                (Some(start), Some(end)) => {
+                    println!("{}: {} - {} ({})", battery, start, end, profile);
+                }
+                (None, Some(end)) => {
+                    println!(
+                        "{}: end {}, start not supported by hardware ({})",
+                        battery, end, profile
+                    );
+                }
+                _ => println!("{}: unsupported", battery),
+            }
+        }
+    }
+
+    if verbose {
+        for battery in status.batteries.keys() {
+            if let Some(files) = battery_threshold_files(battery) {
+                println!(
+                    "{} threshold files: {}, {} ({})",
+                    battery, files.start, files.end, files.variant
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn auto_profile_status(client: &Client<'_>, json: bool) -> anyhow::Result<()> {
+    let status = client.auto_profile().await.map_err(zbus_error)?;
+    if json {
+        return print_json(&status);
+    }
+
+    println!("Automatic profile switching: {}", if status.enabled { "on" } else { "off" });
+    println!("On AC: {}", status.ac);
+    println!("On battery: {}", status.battery);
+    println!(
+        "Manual changes: {}",
+        if status.pin_manual { "kept until the next transition" } else { "overridden" }
+    );
+
+    if status.last_trigger.is_empty() {
+        println!("Last trigger: none");
+    } else {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs());
+        println!(
+            "Last trigger: {} ({} profile, {}s ago)",
+            status.last_trigger,
+            status.last_trigger_profile,
+            now.saturating_sub(status.last_trigger_time)
+        );
+    }
+
+    Ok(())
+}
+
+async fn profile_schedule(client: &Client<'_>, json: bool) -> anyhow::Result<()> {
+    let status = client.profile_schedule().await.map_err(zbus_error)?;
+    if json {
+        return print_json(&status);
+    }
+
+    if status.ranges.is_empty() {
+        println!("No schedule, set ranges in the [schedule] section of daemon.toml");
+        return Ok(());
+    }
+
+    for range in &status.ranges {
+        let days = if range.days.is_empty() { "every day".into() } else { range.days.join(",") };
+        println!("{}-{} {}: {}", range.from, range.to, days, range.profile);
+    }
+
+    println!("Current: {}", if status.current.is_empty() { "none" } else { &status.current });
+    if status.next_profile.is_empty() {
+        println!("Next transition: none");
+    } else {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs());
+        let minutes = status.next_time.saturating_sub(now).div_ceil(60);
+        println!(
+            "Next transition: {} profile in {}h {:02}m",
+            status.next_profile,
+            minutes / 60,
+            minutes % 60
+        );
+    }
+
+    Ok(())
+}
+
+async fn watch_profile(client: &Client<'_>, json: bool) -> anyhow::Result<()> {
+    let mut switches = client.receive_profile_switches().await.map_err(zbus_error)?;
+
+    while let Some(switch) = switches.next().await {
+        let args = switch.args().map_err(zbus_error)?;
+        if json {
+            let output = ProfileSwitchOutput {
+                old:       args.old,
+                new:       args.new,
+                initiator: args.initiator,
+            };
+            print_event(&output)?;
+            continue;
+        }
+
+        println!(
+            "{} -> {} (by {})",
+            if args.old.is_empty() { "none" } else { args.old },
+            args.new,
+            if args.initiator.is_empty() { "unknown" } else { args.initiator }
+        );
+    }
+
+    Ok(())
+}
+
+async fn watch_charge_thresholds(client: &Client<'_>, json: bool) -> anyhow::Result<()> {
+    let mut switches = client.receive_charge_threshold_switches().await.map_err(zbus_error)?;
+
+    while let Some(switch) = switches.next().await {
+        let args = switch.args().map_err(zbus_error)?;
+        if json {
+            let batteries = args
+                .thresholds
+                .iter()
+                .map(|(battery, &(start, end))| (battery.clone(), ThresholdsOutput { start, end }))
+                .collect();
+            print_event(&ChargeThresholdsSwitchOutput { batteries, initiator: args.initiator })?;
+            continue;
+        }
+
+        let thresholds = args
+            .thresholds
+            .iter()
+            .map(|(battery, (start, end))| format!("{}: {}-{}", battery, start, end))
+            .collect::<Vec<_>>();
+        println!(
+            "{} (by {})",
+            if thresholds.is_empty() { "none".to_owned() } else { thresholds.join(", ") },
+            if args.initiator.is_empty() { "unknown" } else { args.initiator }
+        );
+    }
+
+    Ok(())
+}
+
+/// Starts or cancels a calibration, or follows the one running.
+async fn calibrate(
+    client: &Client<'_>,
+    output: &mut Output,
+    cancel: bool,
+    watch: bool,
+    json: bool,
+) -> anyhow::Result<()> {
+    if watch {
+        return watch_calibration(client, json).await;
+    }
+
+    let status = if cancel {
+        client.cancel_calibration().await.map_err(zbus_error)?;
+        client.calibration().await.map_err(zbus_error)?
+    } else {
+        let status = client.start_calibration().await.map_err(zbus_error)?;
+        output.info("Follow it with `system76-power charge-thresholds calibrate --watch`");
+        status
+    };
+
+    if json {
+        return print_json(&status);
+    }
+
+    println!("{}", describe_calibration(&status));
+    Ok(())
+}
+
+/// Prints the progress of the calibration running until it ends.
+async fn watch_calibration(client: &Client<'_>, json: bool) -> anyhow::Result<()> {
+    let mut changes = client.receive_calibration_changes().await;
+    let mut status = client.calibration().await.map_err(zbus_error)?;
+    let mut last = None;
+
+    loop {
+        if last.as_ref() != Some(&status) {
+            if json {
+                print_event(&status)?;
+            } else {
+                println!("{}", describe_calibration(&status));
+            }
+        }
+
+        if !matches!(status.phase.as_str(), "discharging" | "charging") {
+            return Ok(());
+        }
+
+        let Some(change) = changes.next().await else { return Ok(()) };
+        let next = CalibrationStatus::from(change.get().await.map_err(zbus_error)?);
+        last = Some(std::mem::replace(&mut status, next));
+    }
+}
+
+fn describe_calibration(status: &CalibrationStatus) -> String {
+    let battery = &status.battery;
+    match status.phase.as_str() {
+        "idle" => "No battery was calibrated".to_owned(),
+        "discharging" => format!(
+            "{}: discharging, at {}%, down to {}%",
+            battery, status.capacity, status.low_percent
+        ),
+        "charging" => format!("{}: charging full, at {}%", battery, status.capacity),
+        "finished" => format!("{}: calibrated", battery),
+        phase if status.message.is_empty() => format!("{}: {}", battery, phase),
+        phase => format!("{}: {} ({})", battery, phase, status.message),
+    }
+}
+
+async fn watch_graphics(client: &Client<'_>, json: bool) -> anyhow::Result<()> {
+    let mut changes = client.receive_mode_changes().await.map_err(zbus_error)?;
+
+    while let Some(change) = changes.next().await {
+        let args = change.args().map_err(zbus_error)?;
+        if json {
+            let output = ModeChangeOutput {
+                old:             args.old,
+                new:             args.new,
+                reboot_required: args.reboot_required,
+            };
+            print_event(&output)?;
+            continue;
+        }
+
+        println!(
+            "{} -> {}{}",
+            if args.old.is_empty() { "unknown" } else { args.old },
+            args.new,
+            if args.reboot_required { " (reboot required)" } else { "" }
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the version of the daemon and the revision of its interface, if it is reachable,
+/// warning if its major version differs from that of the client.
+#[tokio::main(flavor = "current_thread")]
+pub async fn daemon_version() {
+    let Ok(client) = Client::system().await else { return };
+
+    match client.version().await {
+        Ok((version, api_version)) => {
+            println!("Daemon: {} (interface revision {})", version, api_version);
+
+            let major = |version: &str| version.split('.').next().unwrap_or_default().to_owned();
+            if major(&version) != major(env!("CARGO_PKG_VERSION")) {
+                eprintln!(
+                    "warning: the daemon is version {}, which may not be compatible with this \
+                     client, restart it after upgrading both",
+                    version
+                );
+            }
+        }
+        Err(zbus::Error::MethodError(ref name, ..))
+            if name.as_str().ends_with(".UnknownMethod") =>
+        {
+            println!("Daemon: older than interface revision 1");
+        }
+        // The daemon is not running.
+        Err(_) => (),
+    }
+}
+
+/// Runs the command of the client. With `--quiet`, only results and errors are printed.
+#[tokio::main(flavor = "current_thread")]
+pub async fn client(args: &Args) -> anyhow::Result<()> {
+    PLAIN.store(args.format() == Format::Plain, Ordering::Relaxed);
+    let timeout = args.timeout.map_or_else(|| default_timeout(&args.command), Duration::from_secs);
+    // Following a graphics switch is only bounded by an explicit --timeout.
+    let wait_timeout = args.timeout.map(Duration::from_secs);
+    let (args, quiet, verbose, direct) = (&args.command, args.quiet, args.verbose, args.direct);
+
+    // The report is printed even if the daemon is not running, which it then tells.
+    if let Command::Info { json } = args {
+        let report = Report::gather(connect(timeout).await.ok().as_ref()).await;
+        if *json {
+            return print_json(&report);
+        }
+
+        report.print();
+        return Ok(());
+    }
+
+    let result = match connect(timeout).await {
+        Ok(client) => run(&client, args, wait_timeout, &mut Output::new(quiet, verbose)).await,
+        Err(why) => Err(why),
+    };
+
+    // Root may read everything a query needs from sysfs, but other users may not, and should
+    // start the daemon instead. Changes are only made without the daemon with --direct, and
+    // never while it owns its name, such as when it did not reply in time.
+    match result {
+        Err(why) if daemon_unreachable(&why) && answers_locally(args) && is_root() => {
+            log::debug!("answering from sysfs, as the daemon is unreachable: {}", why);
+            local(args, verbose)
+        }
+        Err(why) if daemon_unreachable(&why) => {
+            let Some(change) = Change::new(args)? else { return Err(why) };
+            if !direct {
+                return Err(if is_root() { suggest_direct(why) } else { why });
+            }
+            if !is_root() {
+                let message = "--direct must be run as root";
+                return Err(ClientError::failure(ExitCode::PermissionDenied, message));
+            }
+            if direct::daemon_running().await {
+                return Err(why);
+            }
+
+            log::warn!("the daemon is not running, making the change directly, bypassing it");
+            direct_change(change, args, &mut Output::new(quiet, verbose))
+        }
+        result => result,
+    }
+}
+
+async fn connect(timeout: Duration) -> anyhow::Result<Client<'static>> {
+    let connection = zbus::Connection::system().await.map_err(|why| not_running(&why))?;
+    Ok(Client::new(&connection).await.map_err(zbus_error)?.with_timeout(timeout))
+}
+
+/// How long to wait for each reply of the daemon without `--timeout`: longer for changes,
+/// which may wait for the user to authorize them, than for queries. Switches of the graphics
+/// mode only wait for their job to start.
+fn default_timeout(args: &Command) -> Duration {
+    let changes = match args {
+        _ if args.dry_run() => false,
+        Command::Profile { profile: Some(_), .. } => true,
+        Command::Profile { cmd: Some(ProfileArgs::Auto { state: Some(state) }), .. } => {
+            state != "status"
+        }
+        Command::Graphics { cmd, .. } => match cmd {
+            None
+            | Some(
+                GraphicsArgs::Capabilities
+                | GraphicsArgs::HotplugCheck
+                | GraphicsArgs::List
+                | GraphicsArgs::Switchable
+                | GraphicsArgs::Watch
+                | GraphicsArgs::Power { state: None },
+            ) => false,
+            Some(GraphicsArgs::Default { apply }) => *apply,
+            Some(_) => true,
+        },
+        Command::ChargeThresholds { profile, start, cmd, .. } => {
+            let calibration =
+                matches!(cmd, Some(ChargeThresholdsArgs::Calibrate { watch: false, .. }));
+            profile.is_some() || start.is_some() || calibration
+        }
+        Command::Profile { .. }
+        | Command::Daemon { .. }
+        | Command::Apply
+        | Command::Battery { .. }
+        | Command::Info { .. }
+        | Command::Completions { .. }
+        | Command::Mangen { .. } => false,
+    };
+
+    Duration::from_secs(if changes { 120 } else { 30 })
+}
+
+fn is_root() -> bool { unsafe { libc::geteuid() == 0 } }
+
+/// Whether a query, or a dry run, is answered without the daemon when it cannot be reached.
+fn answers_locally(args: &Command) -> bool {
+    if args.dry_run() {
+        return true;
+    }
+
+    match args {
+        Command::Profile { profile, cmd, watch, list, .. } => {
+            profile.is_none() && cmd.is_none() && !watch && !list
+        }
+        Command::Graphics { cmd, .. } => matches!(
+            cmd,
+            None | Some(
+                GraphicsArgs::Capabilities
+                    | GraphicsArgs::Switchable
+                    | GraphicsArgs::Power { state: None }
+            )
+        ),
+        Command::ChargeThresholds { profile, list_profiles, start, watch, cmd, .. } => {
+            profile.is_none() && !list_profiles && start.is_none() && !watch && cmd.is_none()
+        }
+        Command::Battery { .. } => true,
+        Command::Daemon { .. }
+        | Command::Apply
+        | Command::Info { .. }
+        | Command::Completions { .. }
+        | Command::Mangen { .. } => false,
+    }
+}
+
+/// Answers a query from sysfs, without rescanning the PCI bus.
+fn local(args: &Command, verbose: bool) -> anyhow::Result<()> {
+    if args.dry_run() {
+        return local_dry_run(args);
+    }
+
+    match args {
+        Command::Profile { json: true, .. } => print_json(&ProfileOutput {
+            cpu: cpufreq::status(),
+            power_estimate: if verbose { local_power_estimate() } else { BTreeMap::new() },
+            ..ProfileOutput::default()
+        }),
+        Command::Profile { .. } => {
+            let power = if verbose { local_power_estimate() } else { BTreeMap::new() };
+            profile(None, None, &[], &cpufreq::status(), &power, None, verbose)
+                .context("failed to get power profile")
+        }
+        Command::Graphics { cmd, json, short, .. } => {
+            let graphics = Graphics::without_rescan().context("failed to read the PCI bus")?;
+
+            match cmd {
+                Some(GraphicsArgs::Capabilities) => {
+                    graphics_capabilities(&graphics.capabilities(), *json)
+                }
+                Some(GraphicsArgs::Switchable) => switchable(&graphics.switchable_status()?, *json),
+                _ if !graphics.can_switch() => Err(not_switchable()),
+                Some(GraphicsArgs::Power { .. }) => {
+                    graphics_power(&graphics.power_status()?, *json, verbose)
+                }
+                None if *short => graphics_mode(graphics.get_vendor()?, *json),
+                _ => graphics_status(&graphics.status()?, *json),
+            }
+        }
+        Command::ChargeThresholds { json, .. } => {
+            let profiles = get_charge_profiles();
+            let status = get_charge_thresholds_status(&profiles)?;
+            charge_thresholds(status, &profiles, BTreeMap::new(), None, *json, verbose)
+        }
+        Command::Battery { json } => batteries(&power_supply::batteries(), *json),
+        Command::Daemon { .. }
+        | Command::Apply
+        | Command::Info { .. }
+        | Command::Completions { .. }
+        | Command::Mangen { .. } => unreachable!(),
+    }
+}
+
+async fn run(
+    client: &Client<'_>,
+    args: &Command,
+    wait_timeout: Option<Duration>,
+    output: &mut Output,
+) -> anyhow::Result<()> {
+    if args.dry_run() {
+        return dry_run(client, args).await;
+    }
+
+    match args {
+        Command::Profile { watch: true, json, .. } => watch_profile(client, *json).await,
+        Command::Profile { list: true, json, .. } => {
+            let profiles = client.profiles().await.map_err(zbus_error)?;
+            let tunables = client.profile_tunables().await.unwrap_or_default();
+            if *json {
+                return print_json(&ProfilesOutput { profiles, tunables });
+            }
+
+            let unset = |value: &str| if value.is_empty() { "not set" } else { value }.to_owned();
+            for profile in &profiles {
+                match profile.name.parse::<Profile>() {
+                    Ok(known) => println!("{}", PowerProfile::from(known)),
+                    Err(()) => println!("{}", profile.name),
+                }
+
+                let tunable = |key: &str| {
+                    tunables
+                        .iter()
+                        .find(|tunable| tunable.profile == profile.name && tunable.key == key)
+                };
+
+                // Values set in `profiles.toml` are told from those of the built-in profile.
+                let show = |label: &str, key: &str, value: &str| {
+                    let overridden = tunable(key).map_or(false, |tunable| tunable.overridden);
+                    let source = if overridden { " (profiles.toml)" } else { "" };
+                    println!("  {}: {}{}", label, unset(value), source);
+                };
+
+                println!("  Description: {}", profile.description);
+                show("Governor", "governor", &profile.governor);
+                show("EPP", "energy_performance_preference", &profile.epp);
+                show("Turbo", "turbo", &profile.turbo);
+                if let Some(percent) = tunable("max_frequency_percent") {
+                    show("Max frequency percent", &percent.key, &percent.value);
+                }
+                show("Platform profile", "platform_profile", &profile.platform_profile);
+                if let Some(policy) = tunable("pcie_aspm_policy") {
+                    show("PCIe ASPM policy", &policy.key, &policy.value);
+                }
+                if let Some(policy) = tunable("sata_link_policy") {
+                    show("SATA link policy", &policy.key, &policy.value);
+                }
+                if let Some(brightness) = tunable("brightness") {
+                    show("Screen brightness", &brightness.key, &brightness.value);
+                }
+                let usb = [
+                    ("USB autosuspend", "usb_autosuspend"),
+                    ("USB autosuspend denied to", "usb_autosuspend_deny_ids"),
+                    ("USB autosuspend denied to classes", "usb_autosuspend_deny_classes"),
+                ];
+                for (label, key) in usb {
+                    if let Some(tunable) = tunable(key) {
+                        show(label, key, &tunable.value);
+                    }
+                }
+
+                // Parameters the profile leaves alone get their value from before the profiles
+                // changed them.
+                let restorable = [
+                    ("Dirty writeback centisecs", "dirty_writeback_centisecs"),
+                    ("Dirty expire centisecs", "dirty_expire_centisecs"),
+                    ("Laptop mode", "laptop_mode"),
+                    ("NMI watchdog", "nmi_watchdog"),
+                    ("Wi-Fi power save", "wifi_powersave"),
+                    ("dGPU power cap", "dgpu_power_cap_watts"),
+                ];
+                for (label, key) in restorable {
+                    if let Some(tunable) = tunable(key) {
+                        let value =
+                            if tunable.value.is_empty() { "restored" } else { &tunable.value };
+                        show(label, key, value);
+                    }
+                }
+            }
+            Ok(())
+        }
+        Command::Profile { cmd: Some(ProfileArgs::Schedule), json, .. } => {
+            profile_schedule(client, *json).await
+        }
+        Command::Profile { cmd: Some(ProfileArgs::Auto { state }), json, .. } => {
+            match state.as_deref() {
+                Some("on") => client.set_auto_profile(true).await.map_err(zbus_error),
+                Some("off") => client.set_auto_profile(false).await.map_err(zbus_error),
+                _ => auto_profile_status(client, *json).await,
+            }
+        }
+        Command::Profile { profile: Some(profile), temporary, strict, .. } => {
+            let battery = ProfileName::Builtin(PowerProfile::Battery);
+            if *profile == battery && client.desktop().await.map_err(zbus_error)? {
+                return Err(ClientError::failure(
+                    ExitCode::Unsupported,
+                    "Battery power profile is not supported on desktop computers.",
+                ));
+            }
+            let result = match profile {
+                ProfileName::Builtin(profile) if *temporary => {
+                    client.set_temporary_profile((*profile).into()).await
+                }
+                ProfileName::Builtin(profile) => client.set_profile((*profile).into()).await,
+                ProfileName::Custom(name) => client.set_profile_by_name(name, *temporary).await,
+            };
+            result.map_err(zbus_error)?;
+
+            // Daemons predating the failures report none.
+            let failures = client.profile_failures().await.unwrap_or_default();
+            profile_failures(output, &failures, *strict)
+        }
+        Command::Profile { json: true, .. } => {
+            let status = client.profile_status().await.map_err(zbus_error)?;
+            let persisted = client.persisted_profile_name().await.unwrap_or_default();
+            let failures = client.profile_failures().await.unwrap_or_default();
+            let cpu = client.cpu_frequency().await.unwrap_or_else(|_| cpufreq::status());
+            let power_estimate = estimate_power(client, output.verbose).await;
+            let battery_frequency_cap = client.battery_frequency_cap().await.ok();
+            print_json(&ProfileOutput {
+                status,
+                persisted,
+                failures,
+                cpu,
+                power_estimate,
+                battery_frequency_cap,
+            })
+        }
+        Command::Profile { .. } => {
+            let status = client.profile_status().await.ok();
+            let persisted = client.persisted_profile_name().await.ok();
+            let failures = client.profile_failures().await.unwrap_or_default();
+            let cpu = client.cpu_frequency().await.unwrap_or_else(|_| cpufreq::status());
+            let power = estimate_power(client, output.verbose).await;
+            let cap = client.battery_frequency_cap().await.ok();
+            profile(status, persisted, &failures, &cpu, &power, cap.as_ref(), output.verbose)
+                .context("failed to get power profile")
+        }
+        Command::Graphics { cmd: Some(GraphicsArgs::Capabilities), json, .. } => {
+            graphics_capabilities(&client.graphics_capabilities().await.map_err(zbus_error)?, *json)
+        }
+        Command::Graphics { cmd: Some(GraphicsArgs::Switchable), json, .. } => {
+            switchable(&client.switchable_status().await.map_err(zbus_error)?, *json)
+        }
+        Command::Graphics { cmd: Some(GraphicsArgs::List), json, .. } => {
+            let devices = client.graphics_devices().await.map_err(zbus_error)?;
+            if *json {
+                return print_json(&DevicesOutput { devices });
+            }
+
+            list_graphics(&devices);
+            Ok(())
+        }
+        Command::Graphics { cmd, force, wait, no_wait, json, short, .. } => {
+            if !client.switchable().await.map_err(zbus_error)? {
+                return Err(not_switchable());
+            }
+
+            let wait = *wait || (!*no_wait && io::stdout().is_terminal());
+
+            if let Some(cmd) = cmd {
+                if let Ok(mode) = GraphicsMode::try_from(cmd) {
+                    let no_initramfs = cmd.no_initramfs();
+                    return set_graphics(
+                        client,
+                        output,
+                        mode,
+                        *force,
+                        no_initramfs,
+                        *json,
+                        wait,
+                        wait_timeout,
+                    )
+                    .await;
+                }
+            }
+
+            match cmd.as_ref() {
+                Some(
+                    GraphicsArgs::Capabilities
+                    | GraphicsArgs::List
+                    | GraphicsArgs::Switchable
+                    | GraphicsArgs::Compute { .. }
+                    | GraphicsArgs::Hybrid { .. }
+                    | GraphicsArgs::Integrated { .. }
+                    | GraphicsArgs::Nvidia { .. },
+                ) => unreachable!(),
+                Some(GraphicsArgs::Default { apply }) => {
+                    if *apply && *json {
+                        let message =
+                            "--json and --format do not apply to `graphics default --apply`";
+                        return Err(ClientError::failure(ExitCode::Usage, message));
+                    }
+
+                    let (mode, reason) =
+                        client.graphics_recommendation().await.map_err(zbus_error)?;
+                    let configured = client.configured_graphics().await.map_err(zbus_error)?;
+                    if *json {
+                        return print_json(&RecommendationOutput {
+                            mode: mode.to_string(),
+                            reason,
+                            configured,
+                        });
+                    }
+
+                    let recommended = format!("Recommended: {} ({})", mode, reason);
+                    let configured_line = format!(
+                        "Configured: {}",
+                        if configured.is_empty() { "none" } else { &configured }
+                    );
+
+                    // The comparison is the result of the query, but only informs the switch.
+                    if !*apply {
+                        println!("{}\n{}", recommended, configured_line);
+                        return Ok(());
+                    }
+
+                    output.info(recommended);
+                    output.info(configured_line);
+                    if configured == mode.to_string() {
+                        return Ok(());
+                    }
+
+                    set_graphics(client, output, mode, *force, false, false, wait, wait_timeout)
+                        .await
+                }
+                Some(GraphicsArgs::SetOption { option }) => {
+                    let (key, value) = option.split_once('=').ok_or_else(|| {
+                        ClientError::failure(
+                            ExitCode::Usage,
+                            format!("expected KEY=VALUE, got '{}'", option),
+                        )
+                    })?;
+                    client.set_graphics_option(key, value).await.map_err(zbus_error)
+                }
+                Some(GraphicsArgs::HotplugCheck) => {
+                    let required =
+                        client.external_displays_require_dgpu().await.map_err(zbus_error)?;
+                    if *json {
+                        return print_json(&HotplugOutput {
+                            external_displays_require_dgpu: required,
+                        });
+                    }
+
+                    println!(
+                        "external displays {} the discrete GPU",
+                        if required { "require" } else { "do not require" }
+                    );
+                    Ok(())
+                }
+                Some(GraphicsArgs::Watch) => watch_graphics(client, *json).await,
+                Some(GraphicsArgs::Power { .. }) if *force => {
+                    force_graphics_power_off(client, output, cmd.as_ref(), *json).await
+                }
+                Some(GraphicsArgs::Power { state }) => match state.as_deref() {
+                    Some(state) => {
+                        let power = graphics_power_state(state);
+                        let on = client.set_graphics_power(power).await.map_err(zbus_error)?;
+                        let power = if on { "on" } else { "off" };
+                        if *json {
+                            print_json(&PowerOutput { power })?;
+                        } else if state == "auto" {
+                            output.outcome(format_args!("{} (discrete)", power));
+                        }
+                        Ok(())
+                    }
+                    None => {
+                        let status = client.graphics_power_status().await.map_err(zbus_error)?;
+                        graphics_power(&status, *json, output.verbose)
+                    }
+                },
+                None if *short => {
+                    graphics_mode(client.graphics().await.map_err(zbus_error)?, *json)
+                }
+                None => {
+                    graphics_status(&client.graphics_status().await.map_err(zbus_error)?, *json)
+                }
+            }
+        }
+        Command::ChargeThresholds {
+            profile,
+            list_profiles,
+            start,
+            end,
+            battery,
+            watch,
+            cmd,
+            json,
+            ..
+        } => {
+            if client.desktop().await.map_err(zbus_error)? {
+                return Err(ClientError::failure(
+                    ExitCode::Unsupported,
+                    "Charge thresholds are not supported on desktop computers.",
+                ));
+            }
+
+            if let Some(ChargeThresholdsArgs::Calibrate { cancel, watch }) = cmd {
+                return calibrate(client, output, *cancel, *watch, *json).await;
+            }
+
+            if *watch {
+                return watch_charge_thresholds(client, *json).await;
+            }
+
+            let profiles = client.charge_profiles().await.map_err(zbus_error)?;
+
+            let set = if let (Some(start), Some(end)) = (start, end) {
+                Some((*start, *end))
+            } else if let Some(name) = profile {
+                Some(charge_profile_thresholds(&profiles, name)?)
+            } else {
+                None
+            };
+
+            if let Some(thresholds) = set {
+                // Daemons predating rounding report no thresholds applied.
+                let battery = battery.as_deref();
+                let (applied, failures) =
+                    match client.apply_battery_charge_thresholds(battery, thresholds).await {
+                        Err(ref why) if unknown_method(why) => (
+                            BTreeMap::new(),
+                            set_battery_thresholds(client, battery, thresholds).await?,
+                        ),
+                        result => result.map_err(zbus_error)?,
+                    };
+                battery_failures(output, &failures);
+
+                // The status printed is the outcome of the change, which --quiet silences.
+                let status = client.charge_thresholds_status().await.map_err(zbus_error)?;
+                skipped_starts(output, &status, battery);
+                applied_thresholds(output, &status, thresholds, &applied);
+                if output.quiet && !*json {
+                    return Ok(());
+                }
+                return charge_thresholds(
+                    status,
+                    &profiles,
+                    failures,
+                    Some(thresholds),
+                    *json,
+                    output.verbose,
+                );
+            } else if *list_profiles {
+                // The profile matching the thresholds, as set by the same profiles.
+                let current = client.charge_thresholds_status().await.unwrap_or_default().profile;
+                let listing = ChargeProfilesOutput::new(profiles, &current);
+                if *json {
+                    return print_json(&listing);
+                }
+
+                for ChargeProfileOutput { profile, current } in &listing.profiles {
+                    println!("{}{}", profile.id, if *current { " (current)" } else { "" });
+                    println!("  Title: {}", profile.title);
+                    if !profile.description.is_empty() {
+                        println!("  Description: {}", profile.description);
+                    }
+                    println!("  Start: {}", profile.start);
+                    println!("  End: {}", profile.end);
+                }
+                return Ok(());
+            }
+
+            let status = client.charge_thresholds_status().await.map_err(zbus_error)?;
+            charge_thresholds(status, &profiles, BTreeMap::new(), None, *json, output.verbose)
+        }
+        Command::Battery { json } => {
+            batteries(&client.batteries().await.map_err(zbus_error)?, *json)
+        }
+        Command::Daemon { .. }
+        | Command::Apply
+        | Command::Info { .. }
+        | Command::Completions { .. }
+        | Command::Mangen { .. } => unreachable!(),
+    }
+}
+
+/// Exit codes of the client, as documented in the README.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Any other failure.
+    Failure = 1,
+    /// Invalid arguments, also used by clap.
+    Usage = 2,
+    /// The hardware does not support the request.
+    Unsupported = 3,
+    DaemonUnreachable = 4,
+    PermissionDenied = 5,
+    /// The daemon failed to carry out the request, or only partly.
+    OperationFailed = 6,
+    /// A profile was set with `--strict`, but some of its parameters failed.
+    PartiallyApplied = 7,
+}
+
+impl ExitCode {
+    /// Name of the code in JSON errors.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            ExitCode::Failure => "failure",
+            ExitCode::Usage => "usage",
+            ExitCode::Unsupported => "unsupported",
+            ExitCode::DaemonUnreachable => "daemon-unreachable",
+            ExitCode::PermissionDenied => "permission-denied",
+            ExitCode::OperationFailed => "operation-failed",
+            ExitCode::PartiallyApplied => "partially-applied",
+        }
+    }
+}
+
+impl From<ExitCode> for i32 {
+    fn from(code: ExitCode) -> i32 { code as i32 }
+}
+
+/// An error reported with its own exit code.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct ClientError {
+    /// Name of the DBus error, or empty for failures of the client.
+    pub name:    String,
+    pub message: String,
+    pub code:    ExitCode,
+}
+
+impl ClientError {
+    /// A failure of the client itself, rather than one replied by the daemon.
+    pub fn failure(code: ExitCode, message: impl Into<String>) -> anyhow::Error {
+        ClientError { name: String::new(), message: message.into(), code }.into()
+    }
+}
+
+const DAEMON_NOT_RUNNING: &str = "the system76-power daemon is not running; start it, and at \
+                                  every boot, with `systemctl enable --now \
+                                  com.system76.PowerDaemon.service`";
+
+/// The daemon could not be reached, as opposed to replying with an error.
+const NOT_SWITCHABLE: &str = "Graphics switching is not supported on this device, because this \
+                              device is either a desktop or doesn't have both an iGPU and dGPU.";
+
+fn not_running(why: &zbus::Error) -> anyhow::Error {
+    ClientError::failure(ExitCode::DaemonUnreachable, format!("{} ({})", DAEMON_NOT_RUNNING, why))
+}
+
+fn daemon_unreachable(why: &anyhow::Error) -> bool {
+    why.downcast_ref::<ClientError>().map_or(false, |why| why.code == ExitCode::DaemonUnreachable)
+}
+
+fn not_switchable() -> anyhow::Error { ClientError::failure(ExitCode::Unsupported, NOT_SWITCHABLE) }
+
+/// Tells root, who may make the change without the daemon, how to.
+fn suggest_direct(why: anyhow::Error) -> anyhow::Error {
+    let message = format!("{:#}\nAs root, --direct makes the change without the daemon", why);
+    match why.downcast::<ClientError>() {
+        Ok(why) => ClientError { message, ..why }.into(),
+        Err(_) => ClientError::failure(ExitCode::DaemonUnreachable, message),
+    }
+}
+
+/// Explains the error of a change made with `--direct`, as if the daemon replied with it.
+fn direct_error(why: DirectError) -> anyhow::Error { dbus_error(&why.name, &why.message) }
+
+fn zbus_error(why: zbus::Error) -> anyhow::Error {
+    match why {
+        zbus::Error::InputOutput(ref why) if why.kind() == io::ErrorKind::TimedOut => {
+            ClientError::failure(ExitCode::DaemonUnreachable, why.to_string())
+        }
+        zbus::Error::MethodError(ref name, ref message, _) => {
+            dbus_error(name.as_str(), message.as_deref().unwrap_or_default())
+        }
+        why => anyhow::anyhow!("{}", why),
+    }
+}
+
+/// Explains the errors named by the daemon, as listed in the README.
+fn dbus_error(name: &str, message: &str) -> anyhow::Error {
+    // Raised when the bus fails to start the daemon on demand.
+    if name.starts_with("org.freedesktop.DBus.Error.Spawn.") {
+        let message = format!("{} ({})", DAEMON_NOT_RUNNING, message);
+        let code = ExitCode::DaemonUnreachable;
+        return ClientError { name: name.to_owned(), message, code }.into();
+    }
+
+    let (message, code) = match name.rsplit_once('.').map_or(name, |(_, name)| name) {
+        "AccessDenied" => (
+            format!(
+                "{}: authorization is required, run from a desktop session with a polkit agent or \
+                 as root",
+                if message.is_empty() { "access denied" } else { message }
+            ),
+            ExitCode::PermissionDenied,
+        ),
+        "ServiceUnknown" | "NameHasNoOwner" => {
+            (DAEMON_NOT_RUNNING.to_owned(), ExitCode::DaemonUnreachable)
+        }
+        "NotSwitchable" => (NOT_SWITCHABLE.to_owned(), ExitCode::Unsupported),
+        "DriverMismatch" => (
+            format!("{}; reboot after updating the NVIDIA driver, then try again", message),
+            ExitCode::Unsupported,
+        ),
+        "Unsupported" => (message.to_owned(), ExitCode::Unsupported),
+        "InvalidMode" | "InvalidOption" | "InvalidThresholds" | "InvalidArgs" => {
+            (message.to_owned(), ExitCode::Usage)
+        }
+        "DeviceInUse" => (
+            format!("{}\nClose the applications using the GPU, or use --force", message),
+            ExitCode::OperationFailed,
+        ),
+        "InitramfsFailed" => (
+            format!(
+                "{}\nThe new graphics mode may not apply until the initramfs is rebuilt",
+                message
+            ),
+            ExitCode::OperationFailed,
+        ),
+        "Busy" | "TryAgainLater" => {
+            (format!("{}; try again once it finishes", message), ExitCode::OperationFailed)
+        }
+        "RateLimited" => (format!("{}; try again later", message), ExitCode::OperationFailed),
+        "ProfileFailed" => (message.to_owned(), ExitCode::OperationFailed),
+        _ if message.is_empty() => (name.to_owned(), ExitCode::Failure),
+        _ => (message.to_owned(), ExitCode::Failure),
+    };
+
+    ClientError { name: name.to_owned(), message, code }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::charge_thresholds::{builtin_charge_profiles, THRESHOLD_FILES};
+    use clap::Parser;
+    use std::fs;
+    use system76_power_zbus::{
+        AutoProfileStatus, GraphicsFunction, NvidiaKernelModule, PolicyGovernor, ScheduleStatus,
+        ScheduledRange,
+    };
+
+    /// Compares the JSON of a command with its snapshot in `src/snapshots`, which are rewritten
+    /// with `make snapshots`. A changed snapshot is a change of the documented output.
+    fn snapshot(command: &str, value: &impl Serialize) {
+        let path = format!("{}/src/snapshots/{}.json", env!("CARGO_MANIFEST_DIR"), command);
+        let json = serde_json::to_string_pretty(value).unwrap() + "\n";
+
+        if std::env::var_os("S76_POWER_UPDATE_SNAPSHOTS").is_some() {
+            fs::write(&path, &json).unwrap();
+            return;
+        }
+
+        let shipped = fs::read_to_string(&path).unwrap_or_default();
+        assert!(shipped == json, "{} is out of date, run `make snapshots`:\n{}", path, json);
+    }
+
+    #[test]
+    fn profile_json() {
+        snapshot(
+            "profile",
+            &ProfileOutput {
+                status:                ProfileStatus {
+                    profile:    "Balanced".into(),
+                    parameters: vec![
+                        ProfileParameter {
+                            name:     "scaling_governor".into(),
+                            path:     "/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"
+                                .into(),
+                            intended: "powersave".into(),
+                            current:  "performance".into(),
+                            matches:  false,
+                        },
+                        ProfileParameter {
+                            name:     "intel_pstate".into(),
+                            path:     "/sys/devices/system/cpu/intel_pstate".into(),
+                            intended: String::new(),
+                            current:  String::new(),
+                            matches:  true,
+                        },
+                    ],
+                },
+                persisted:             "Battery".into(),
+                failures:              vec![ProfileFailure {
+                    name:    "energy_performance_preference".into(),
+                    path:    "/sys/devices/system/cpu/cpufreq/policy0/\
+                              energy_performance_preference"
+                        .into(),
+                    value:   "power".into(),
+                    errno:   16,
+                    message: "Device or resource busy (os error 16)".into(),
+                }],
+                cpu:                   CpuFrequencyStatus {
+                    scaling_driver:                "intel_pstate".into(),
+                    driver_mode:                   "active".into(),
+                    governor:                      String::new(),
+                    policy_governors:              vec![
+                        PolicyGovernor { policy: "policy0".into(), governor: "powersave".into() },
+                        PolicyGovernor {
+                            policy:   "policy1".into(),
+                            governor: "performance".into(),
+                        },
+                    ],
+                    energy_performance_preference: "balance_performance".into(),
+                    boost:                         "on".into(),
+                    min_khz:                       400_000,
+                    max_khz:                       4_700_000,
+                    current_khz:                   1_200_000,
+                },
+                power_estimate:        BTreeMap::from([
+                    ("battery_watts".to_owned(), 11.5),
+                    ("package_watts".to_owned(), 6.25),
+                ]),
+                battery_frequency_cap: Some(BatteryCapStatus {
+                    enabled: true,
+                    percent: 60,
+                    active:  true,
+                }),
+            },
+        );
+
+        snapshot(
+            "profile-auto",
+            &AutoProfileStatus {
+                enabled:              true,
+                ac:                   "performance".into(),
+                battery:              "battery".into(),
+                pin_manual:           true,
+                last_trigger:         "battery".into(),
+                last_trigger_profile: "battery".into(),
+                last_trigger_time:    1_700_000_000,
+            },
+        );
+
+        snapshot(
+            "battery",
+            &BatteriesOutput {
+                batteries: &[
+                    BatteryStatus {
+                        name:                   "BAT0".into(),
+                        manufacturer:           "SMP".into(),
+                        model:                  "5B10W13975".into(),
+                        status:                 "Discharging".into(),
+                        capacity:               78,
+                        cycle_count:            123,
+                        energy_full_mwh:        50_200,
+                        energy_full_design_mwh: 55_000,
+                        health:                 91,
+                        power_mw:               7300,
+                        start_threshold:        40,
+                        end_threshold:          80,
+                    },
+                    BatteryStatus {
+                        name:                   "ups".into(),
+                        manufacturer:           String::new(),
+                        model:                  String::new(),
+                        status:                 String::new(),
+                        capacity:               -1,
+                        cycle_count:            -1,
+                        energy_full_mwh:        -1,
+                        energy_full_design_mwh: -1,
+                        health:                 -1,
+                        power_mw:               -1,
+                        start_threshold:        -1,
+                        end_threshold:          -1,
+                    },
+                ],
+            },
+        );
+
+        snapshot(
+            "charge-thresholds-calibrate",
+            &CalibrationStatus {
+                phase:       "discharging".into(),
+                battery:     "BAT0".into(),
+                capacity:    42,
+                low_percent: 5,
+                message:     String::new(),
+            },
+        );
+
+        snapshot(
+            "profile-schedule",
+            &ScheduleStatus {
+                ranges:       vec![
+                    ScheduledRange {
+                        from:    "09:00".into(),
+                        to:      "18:00".into(),
+                        days:    vec!["mon".into(), "tue".into(), "wed".into()],
+                        profile: "performance".into(),
+                    },
+                    ScheduledRange {
+                        from:    "22:00".into(),
+                        to:      "07:00".into(),
+                        days:    Vec::new(),
+                        profile: "battery".into(),
+                    },
+                ],
+                current:      "Performance".into(),
+                next_profile: "Battery".into(),
+                next_time:    1_700_000_000,
+            },
+        );
+
+        snapshot(
+            "profile-list",
+            &ProfilesOutput {
+                profiles: vec![ProfileInfo {
+                    name:             "Balanced".into(),
+                    description:      "Balances performance and power use, the default".into(),
+                    governor:         "powersave".into(),
+                    epp:              "balance_performance".into(),
+                    turbo:            String::new(),
+                    platform_profile: "balanced".into(),
+                }],
+                tunables: vec![ProfileTunable {
+                    profile:    "Balanced".into(),
+                    key:        "energy_performance_preference".into(),
+                    value:      "balance_performance".into(),
+                    overridden: true,
+                }],
+            },
+        );
+
+        snapshot(
+            "charge-thresholds-watch",
+            &ChargeThresholdsSwitchOutput {
+                batteries: [
+                    ("BAT0".to_owned(), ThresholdsOutput { start: 50, end: 60 }),
+                    ("BAT1".to_owned(), ThresholdsOutput { start: 0, end: 80 }),
+                ]
+                .into(),
+                initiator: "external",
+            },
+        );
+
+        snapshot(
+            "profile-watch",
+            &ProfileSwitchOutput {
+                old:       "Balanced",
+                new:       "Battery",
+                initiator: ":1.42",
+            },
+        );
+    }
+
+    #[test]
+    fn graphics_json() {
+        snapshot(
+            "graphics",
+            &GraphicsStatus {
+                mode:            "integrated".into(),
+                configured:      "hybrid".into(),
+                power:           "off".into(),
+                reboot_required: true,
+            },
+        );
+        snapshot("graphics-short", &ModeOutput { mode: GraphicsMode::Hybrid.to_string() });
+        snapshot(
+            "graphics-default",
+            &RecommendationOutput {
+                mode:       GraphicsMode::Hybrid.to_string(),
+                reason:     "runtimepm-supported".into(),
+                configured: GraphicsMode::Integrated.to_string(),
+            },
+        );
+        snapshot(
+            "graphics-power",
+            &GraphicsPowerStatus {
+                power:           "suspended".into(),
+                functions:       vec![
+                    GraphicsFunction {
+                        bus_id:         "0000:01:00.0".into(),
+                        driver:         "nvidia".into(),
+                        runtime_status: "suspended".into(),
+                    },
+                    GraphicsFunction {
+                        bus_id:         "0000:01:00.1".into(),
+                        driver:         "snd_hda_intel".into(),
+                        runtime_status: "suspended".into(),
+                    },
+                ],
+                power_cap_watts: 0,
+            },
+        );
+        snapshot("graphics-power-set", &PowerOutput { power: "off" });
+        snapshot(
+            "graphics-power-force",
+            &ForcedPowerOutput {
+                power:  "off",
+                forced: vec!["stopped nvidia-persistenced.service".into()],
+            },
+        );
+        snapshot(
+            "graphics-power-plan",
+            &PowerPlanOutput {
+                power:   "off",
+                actions: vec![
+                    "run systemctl stop nvidia-persistenced.service".into(),
+                    "write /sys/bus/pci/devices/0000:01:00.0/remove = 1".into(),
+                ],
+            },
+        );
+        snapshot(
+            "graphics-switchable",
+            &SwitchableStatus {
+                switchable: false,
+                reason:     "no-discrete-gpu".into(),
+                devices:    vec![GraphicsDeviceInfo {
+                    vendor_id:           0x8086,
+                    device_id:           0xa7a0,
+                    subsystem_vendor_id: 0x1558,
+                    subsystem_device_id: 0xa650,
+                    bus_id:              "0000:00:02.0".into(),
+                    kind:                "integrated".into(),
+                    driver:              "i915".into(),
+                    power_state:         "D0".into(),
+                    name:                "Raptor Lake-P [Iris Xe Graphics]".into(),
+                }],
+            },
+        );
+        snapshot(
+            "graphics-hotplug-check",
+            &HotplugOutput { external_displays_require_dgpu: false },
+        );
+
+        snapshot(
+            "graphics-capabilities",
+            &GraphicsCapabilities {
+                switchable:               true,
+                desktop:                  false,
+                kernel_driver_version:    "550.78".into(),
+                userspace_driver_version: "550.78".into(),
+                gsp_firmware:             "default".into(),
+                kernel_modules:           [(
+                    "6.8.9-300.fc40.x86_64".to_owned(),
+                    NvidiaKernelModule {
+                        module_present: true,
+                        module_version: "550.78".into(),
+                        source:         "akmod".into(),
+                    },
+                )]
+                .into(),
+            },
+        );
+
+        snapshot(
+            "graphics-list",
+            &DevicesOutput {
+                devices: vec![GraphicsDeviceInfo {
+                    vendor_id:           0x10de,
+                    device_id:           0x28a0,
+                    subsystem_vendor_id: 0x1558,
+                    subsystem_device_id: 0xa650,
+                    bus_id:              "0000:01:00.0".into(),
+                    kind:                "discrete".into(),
+                    driver:              "nvidia".into(),
+                    power_state:         "D3cold".into(),
+                    name:                "GeForce RTX 4060 Max-Q / Mobile".into(),
+                }],
+            },
+        );
+
+        snapshot(
+            "graphics-switch",
+            &JobStatus {
+                kind:     "graphics".into(),
+                step:     "rebuilding the initramfs".into(),
+                percent:  100,
+                finished: true,
+                success:  true,
+                message:  "switched to hybrid, reboot required".into(),
+                error:    String::new(),
+            },
+        );
+
+        snapshot(
+            "graphics-watch",
+            &ModeChangeOutput {
+                old:             "integrated",
+                new:             "hybrid",
+                reboot_required: true,
+            },
+        );
+    }
+
+    #[test]
+    fn charge_thresholds_json() {
+        snapshot(
+            "charge-thresholds",
+            &ChargeThresholdsOutput {
+                profile:         "max_lifespan".into(),
+                start:           50,
+                end:             60,
+                supported:       true,
+                start_supported: false,
+                min:             0,
+                max:             100,
+                batteries:       [
+                    (
+                        "BAT0".to_owned(),
+                        BatteryThresholdsOutput {
+                            supported:       true,
+                            start_supported: true,
+                            start:           Some(50),
+                            end:             Some(60),
+                            profile:         Some("max_lifespan".into()),
+                            variant:         Some("charge_control".into()),
+                        },
+                    ),
+                    (
+                        "BAT1".to_owned(),
+                        BatteryThresholdsOutput {
+                            supported:       true,
+                            start_supported: false,
+                            start:           None,
+                            end:             Some(80),
+                            profile:         Some("custom".into()),
+                            variant:         Some("charge_start_stop".into()),
+                        },
+                    ),
+                    (
+                        "BAT2".to_owned(),
+                        BatteryThresholdsOutput {
+                            supported:       false,
+                            start_supported: false,
+                            start:           None,
+                            end:             None,
+                            profile:         None,
+                            variant:         None,
+                        },
+                    ),
+                ]
+                .into(),
+                failures:        [(
+                    "BAT2".to_owned(),
+                    "Not running System76 firmware with charge threshold support".to_owned(),
+                )]
+                .into(),
+                requested:       Some(ThresholdsOutput { start: 48, end: 60 }),
+            },
+        );
+
+        let profiles = [ChargeProfile {
+            id:          "balanced".into(),
+            title:       "Balanced".into(),
+            description: String::new(),
+            start:       86,
+            end:         90,
+        }];
+        let end_only =
+            BatteryThresholds { start: 0, end: 90, start_supported: false };
+        let battery =
+            BatteryThresholdsOutput::new(Some(&end_only), Some(&THRESHOLD_FILES[1]), &profiles);
+        assert_eq!((battery.start, battery.end), (None, Some(90)));
+        assert_eq!(battery.variant.as_deref(), Some("charge_start_stop"));
+        assert_eq!(battery.profile.as_deref(), Some("balanced"));
+        let both =
+            BatteryThresholds { start: 40, end: 90, start_supported: true };
+        let battery = BatteryThresholdsOutput::new(Some(&both), None, &profiles);
+        assert_eq!(battery.profile.as_deref(), Some("custom"));
+        assert!(!BatteryThresholdsOutput::new(None, None, &profiles).supported);
+
+        snapshot(
+            "charge-thresholds-list-profiles",
+            &ChargeProfilesOutput::new(
+                vec![ChargeProfile {
+                    id:          "max_lifespan".into(),
+                    title:       "Maximum Lifespan".into(),
+                    description: "Use this if you primarily use your computer plugged in.".into(),
+                    start:       50,
+                    end:         60,
+                }],
+                "max_lifespan",
+            ),
+        );
+
+        let listing = ChargeProfilesOutput::new(builtin_charge_profiles(), CUSTOM_PROFILE);
+        assert!(listing.profiles.iter().all(|profile| !profile.current));
+    }
+
+    fn code(why: &anyhow::Error) -> ExitCode { why.downcast_ref::<ClientError>().unwrap().code }
+
+    #[test]
+    fn exit_codes() {
+        let absent = zbus::Error::Address("unix:path=/nonexistent".into());
+        assert_eq!(code(&not_running(&absent)), ExitCode::DaemonUnreachable);
+        let unknown = dbus_error("org.freedesktop.DBus.Error.ServiceUnknown", "");
+        assert_eq!(code(&unknown), ExitCode::DaemonUnreachable);
+        let suggested = suggest_direct(unknown);
+        assert_eq!(code(&suggested), ExitCode::DaemonUnreachable);
+        assert!(suggested.to_string().contains("systemctl enable --now"));
+        assert!(suggested.to_string().ends_with("--direct makes the change without the daemon"));
+
+        assert_eq!(code(&not_switchable()), ExitCode::Unsupported);
+        let why = dbus_error("com.system76.PowerDaemon.Error.NotSwitchable", "no dGPU");
+        assert_eq!(code(&why), ExitCode::Unsupported);
+
+        let why = dbus_error("org.freedesktop.DBus.Error.AccessDenied", "");
+        assert_eq!(code(&why), ExitCode::PermissionDenied);
+        let why = dbus_error("com.system76.PowerDaemon.Error.DeviceInUse", "in use by Xorg");
+        assert_eq!(code(&why), ExitCode::OperationFailed);
+
+        let why = Args::try_parse_from(["system76-power", "graphics", "bogus"]).err().unwrap();
+        assert_eq!(why.exit_code(), i32::from(ExitCode::Usage));
+        let why = dbus_error("com.system76.PowerDaemon.Error.InvalidMode", "bogus");
+        assert_eq!(code(&why), ExitCode::Usage);
+    }
+
+    #[test]
+    fn output_streams() {
+        let output = |quiet| {
+            let mut output = Output { out: Vec::new(), err: Vec::new(), quiet, verbose: false };
+            output.info(" 50% Writing /etc/prime-discrete");
+            print_forced(&mut output, &["stopped nvidia-persistenced.service".into()]);
+            (String::from_utf8(output.out).unwrap(), String::from_utf8(output.err).unwrap())
+        };
+
+        let (out, err) = output(false);
+        assert_eq!(out, "off (discrete), forced:\n  - stopped nvidia-persistenced.service\n");
+        assert_eq!(err, " 50% Writing /etc/prime-discrete\n");
+        assert_eq!(output(true), (String::new(), String::new()));
+
+        let why =
+            ClientError::failure(ExitCode::OperationFailed, "initramfs failed:\n\ndracut: error\n");
+        let mut err = Vec::new();
+        let code = write_error(&mut err, &why, Format::Human, true);
+        assert_eq!(code, i32::from(ExitCode::OperationFailed));
+        assert_eq!(String::from_utf8(err).unwrap(), "initramfs failed: dracut: error\n");
+
+        let mut err = Vec::new();
+        write_error(&mut err, &why, Format::Plain, false);
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "CODE=6\nCODE_NAME=operation-failed\nERROR=''\nMESSAGE='initramfs failed:\n\ndracut: \
+             error'\n"
+        );
+    }
+
+    #[test]
+    fn dry_run_requests() {
+        let request = |args: &[&str]| {
+            let args =
+                Args::try_parse_from([&["system76-power"][..], args].concat()).unwrap().command;
+            assert!(args.dry_run());
+            Change::planned(&args).map(|request| match request {
+                Change::Profile(profile, _) => profile.daemon_name(),
+                Change::Graphics(mode, force, no_initramfs) => {
+                    format!("{} {} {}", mode, force, no_initramfs)
+                }
+                Change::GraphicsPower(power, force) => format!("{:?} {}", power, force),
+                Change::ChargeThresholds((start, end), _) => format!("{} {}", start, end),
+                Change::ChargeProfile(name, _) => name.to_owned(),
+            })
+        };
+
+        assert_eq!(request(&["profile", "performance", "--dry-run"]).unwrap(), "Performance");
+        assert_eq!(request(&["profile", "presentation", "--dry-run"]).unwrap(), "presentation");
+        assert_eq!(request(&["graphics", "--dry-run", "hybrid"]).unwrap(), "hybrid false false");
+        let args = ["graphics", "integrated", "--no-initramfs", "--dry-run"];
+        assert_eq!(request(&args).unwrap(), "integrated false true");
+        assert_eq!(
+            request(&["graphics", "power", "off", "--force", "--dry-run"]).unwrap(),
+            "Off true"
+        );
+        assert_eq!(request(&["charge-thresholds", "40", "80", "--dry-run"]).unwrap(), "40 80");
+        let profile = request(&["charge-thresholds", "--profile", "balanced", "--dry-run"]);
+        assert_eq!(profile.unwrap(), "balanced");
+
+        for args in [
+            &["profile", "--dry-run"][..],
+            &["profile", "auto", "on", "--dry-run"],
+            &["graphics", "--dry-run"],
+            &["graphics", "power", "on", "--force", "--dry-run"],
+            &["charge-thresholds", "--dry-run"],
+        ] {
+            let why = request(args).err().unwrap();
+            assert_eq!(code(&why), ExitCode::Usage, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn plan_json() {
+        snapshot(
+            "plan",
+            &PlanOutput {
+                actions: vec![
+                    PlannedAction {
+                        kind:    "write".into(),
+                        target:  "/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor".into(),
+                        value:   "performance".into(),
+                        current: "powersave".into(),
+                    },
+                    PlannedAction {
+                        kind:    "run".into(),
+                        target:  "systemctl".into(),
+                        value:   "stop thermald.service".into(),
+                        current: String::new(),
+                    },
+                ],
+            },
+        );
+    }
+
+    #[test]
+    fn error_json() {
+        let why = dbus_error("com.system76.PowerDaemon.Error.Busy", "a graphics switch is running");
+        let why = why.downcast_ref::<ClientError>().unwrap();
+        snapshot(
+            "error",
+            &ErrorOutput {
+                error:     &why.name,
+                message:   &why.message,
+                code:      why.code.into(),
+                code_name: why.code.name(),
+            },
+        );
+    }
+
+    /// A daemon which never replies, as when it is wedged.
+    struct Stalled;
+
+    #[zbus::dbus_interface(name = "com.system76.PowerDaemon")]
+    impl Stalled {
+        async fn get_profile(&self) -> String { futures_lite::future::pending().await }
+    }
+
+    #[tokio::test]
+    async fn stalled_daemon() {
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        let guid = zbus::Guid::generate();
+
+        let (server, client) = futures_lite::future::zip(
+            zbus::ConnectionBuilder::unix_stream(server)
+                .server(&guid)
+                .p2p()
+                .serve_at(crate::DBUS_PATH, Stalled)
+                .unwrap()
+                .build(),
+            zbus::ConnectionBuilder::unix_stream(client).p2p().build(),
+        )
+        .await;
+        let (_server, client) = (server.unwrap(), client.unwrap());
+
+        let client = Client::new(&client).await.unwrap().with_timeout(Duration::from_millis(100));
+        let why = zbus_error(client.profile().await.unwrap_err());
+        assert_eq!(code(&why), ExitCode::DaemonUnreachable);
+        assert_eq!(why.to_string(), "the daemon did not respond within 100ms");
+
+        let timeout = |args: &[&str]| {
+            default_timeout(
+                &Args::try_parse_from([&["system76-power"][..], args].concat()).unwrap().command,
+            )
+        };
+        assert_eq!(timeout(&["profile"]), Duration::from_secs(30));
+        assert_eq!(timeout(&["graphics", "power"]), Duration::from_secs(30));
+        assert_eq!(timeout(&["profile", "battery"]), Duration::from_secs(120));
+        assert_eq!(timeout(&["graphics", "hybrid"]), Duration::from_secs(120));
+        assert_eq!(timeout(&["graphics", "hybrid", "--dry-run"]), Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Persistent daemon settings, stored as TOML files in `/etc/system76-power`.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub const CONFIG_DIR: &str = "/etc/system76-power";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("invalid {} in {}: {}", key, path.display(), why)]
+    Invalid { path: PathBuf, key: String, why: String },
+    #[error("failed to parse {}: {}", path.display(), why)]
+    Parse { path: PathBuf, why: toml::de::Error },
+    #[error("failed to read {}: {}", path.display(), why)]
+    Read { path: PathBuf, why: io::Error },
+    #[error("failed to serialize config: {}", _0)]
+    Serialize(toml::ser::Error),
+    #[error("failed to write {}: {}", path.display(), why)]
+    Write { path: PathBuf, why: io::Error },
+}
+
+/// Path of a config file in the config directory.
+#[must_use]
+pub fn path(name: &str) -> PathBuf { Path::new(CONFIG_DIR).join(name) }
+
+/// Loads a config file, falling back to the defaults if it does not exist.
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> Result<T, ConfigError> {
+    let path = path(name);
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(why) => return Err(ConfigError::Read { path, why }),
+    };
+
+    toml::from_str(&contents).map_err(|why| ConfigError::Parse { path, why })
+}
+
+pub fn save<T: Serialize>(name: &str, config: &T) -> Result<(), ConfigError> {
+    let path = path(name);
+    let contents = toml::to_string(config).map_err(ConfigError::Serialize)?;
+
+    fs::create_dir_all(CONFIG_DIR)
+        .and_then(|()| fs::write(&path, contents))
+        .map_err(|why| ConfigError::Write { path, why })
+}
@@ -0,0 +1,173 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Other power managers, which also set some of the parameters of the profiles, so that each
+//! undoes the changes of the other. They are detected by their active systemd service.
+
+use std::{collections::BTreeSet, process};
+
+use system76_power_zbus::PowerConflict;
+
+/// Service of power-profiles-daemon, which serves the bus names the daemon also serves.
+pub const POWER_PROFILES_DAEMON: &str = "power-profiles-daemon.service";
+
+/// A power manager, with the parameters of the profiles it also sets, named as in the profile
+/// status. Parameters of hybrid CPUs are matched without their `pcore.` or `ecore.` prefix.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PowerManager {
+    pub service:    &'static str,
+    pub reason:     &'static str,
+    pub parameters: &'static [&'static str],
+}
+
+/// The power managers known to conflict with the profiles.
+pub const POWER_MANAGERS: &[PowerManager] = &[
+    PowerManager {
+        service:    "tlp.service",
+        reason:     "also sets power parameters on AC/battery transitions",
+        parameters: &[
+            "scaling_governor",
+            "energy_performance_preference",
+            "scaling_min_freq",
+            "scaling_max_freq",
+            "boost",
+            "no_turbo",
+            "hwp_dynamic_boost",
+            "min_perf_pct",
+            "max_perf_pct",
+            "platform_profile",
+            "dirty_writeback_centisecs",
+            "dirty_expire_centisecs",
+            "laptop_mode",
+            "nmi_watchdog",
+            "link_power_management_policy",
+            "pcie_aspm_policy",
+            "control",
+            "usb_autosuspend",
+            "wifi_powersave",
+            "power_dpm_state",
+            "power_method",
+        ],
+    },
+    PowerManager {
+        service:    POWER_PROFILES_DAEMON,
+        reason:     "also serves the power profiles DBus interface",
+        parameters: &[
+            "platform_profile",
+            "energy_performance_preference",
+            "scaling_governor",
+            "boost",
+            "no_turbo",
+        ],
+    },
+    PowerManager {
+        service:    "tuned.service",
+        reason:     "also sets CPU and device power parameters",
+        parameters: &[
+            "scaling_governor",
+            "energy_performance_preference",
+            "scaling_min_freq",
+            "scaling_max_freq",
+            "boost",
+            "no_turbo",
+            "min_perf_pct",
+            "max_perf_pct",
+            "platform_profile",
+            "dirty_writeback_centisecs",
+            "dirty_expire_centisecs",
+            "nmi_watchdog",
+            "link_power_management_policy",
+            "pcie_aspm_policy",
+            "control",
+            "usb_autosuspend",
+            "wifi_powersave",
+        ],
+    },
+    PowerManager {
+        service:    "auto-cpufreq.service",
+        reason:     "also sets the CPU governor and turbo",
+        parameters: &[
+            "scaling_governor",
+            "energy_performance_preference",
+            "scaling_min_freq",
+            "scaling_max_freq",
+            "boost",
+            "no_turbo",
+            "platform_profile",
+        ],
+    },
+    PowerManager {
+        service:    "thermald.service",
+        reason:     "also limits the power and the performance of the CPU",
+        parameters: &["model", "max_perf_pct"],
+    },
+];
+
+impl PowerManager {
+    /// Whether the power manager also sets the parameter.
+    #[must_use]
+    pub fn overlaps(&self, parameter: &str) -> bool {
+        let parameter = parameter.rsplit('.').next().unwrap_or(parameter);
+        self.parameters.contains(&parameter)
+    }
+
+    #[must_use]
+    pub fn status(&self) -> PowerConflict {
+        PowerConflict {
+            service:    self.service.to_owned(),
+            reason:     self.reason.to_owned(),
+            parameters: self.parameters.iter().map(|&name| name.to_owned()).collect(),
+        }
+    }
+}
+
+/// The power managers whose service is active. power-profiles-daemon is left out while the
+/// daemon owns the power profiles bus names, which it then cannot serve.
+#[must_use]
+pub fn detect(owns_power_profiles: bool) -> Vec<&'static PowerManager> {
+    POWER_MANAGERS
+        .iter()
+        .filter(|manager| !(owns_power_profiles && manager.service == POWER_PROFILES_DAEMON))
+        .filter(|manager| active(manager.service))
+        .collect()
+}
+
+/// The parameters the power managers also set.
+#[must_use]
+pub fn parameters(managers: &[&PowerManager]) -> BTreeSet<&'static str> {
+    managers.iter().flat_map(|manager| manager.parameters.iter().copied()).collect()
+}
+
+/// Whether a systemd service is active.
+#[must_use]
+pub fn active(service: &str) -> bool {
+    process::Command::new("systemctl")
+        .args(["is-active", "--quiet", service])
+        .status()
+        .map_or(false, |status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlaps() {
+        let thermald = &POWER_MANAGERS[4];
+        assert!(thermald.overlaps("max_perf_pct"));
+        assert!(!thermald.overlaps("scaling_governor"));
+
+        let auto_cpufreq = &POWER_MANAGERS[3];
+        assert!(auto_cpufreq.overlaps("pcore.scaling_governor"));
+        assert!(!auto_cpufreq.overlaps("usb_autosuspend"));
+
+        let both = parameters(&[thermald, auto_cpufreq]);
+        assert!(both.contains("model") && both.contains("no_turbo"));
+        assert!(!both.contains("laptop_mode"));
+
+        let status = thermald.status();
+        assert_eq!(status.service, "thermald.service");
+        assert_eq!(status.parameters, ["model", "max_perf_pct"]);
+    }
+}
@@ -1,74 +1,346 @@
 // Copyright 2022 System76 <info@system76.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::{util::write_value, Profile};
+use crate::{
+    boost::Boost,
+    tunables::{CoreTunables, ProfileTunables},
+    util::{write_value, Written},
+    Profile,
+};
 use concat_in_place::strcat;
 use std::{
+    collections::BTreeMap,
     fmt::Write,
     fs::{self, File},
     io::Read,
+    path::Path,
 };
+use system76_power_zbus::{CpuFrequencyStatus, PolicyGovernor};
 
-pub fn set(profile: Profile, max_percent: u8) {
-    let mut core = Cpu::new(0);
+const CPU_PATH: &str = "/sys/devices/system/cpu";
+const CPUFREQ_PATH: &str = "/sys/devices/system/cpu/cpufreq";
+const DEVICES_PATH: &str = "/sys/devices";
+const AMD_PSTATE_STATUS_PATH: &str = "/sys/devices/system/cpu/amd_pstate/status";
 
-    let min_freq = core.frequency_minimum();
-    let max_freq = core.frequency_maximum();
+/// The scaling driver of the CPUs, with the mode of amd-pstate, which decide the parameters the
+/// profiles set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Driver {
+    /// Such as `amd-pstate-epp`, `amd-pstate`, `intel_pstate` or `acpi-cpufreq`.
+    pub name: String,
+    /// `active`, `guided` or `passive` with amd-pstate, or empty with other drivers.
+    pub mode: String,
+}
 
-    if let Some(driver) = core.scaling_driver() {
-        let is_amd_pstate = driver.starts_with("amd-pstate");
+impl Driver {
+    /// The driver of the first policy, which is that of every CPU.
+    #[must_use]
+    pub fn detect() -> Option<Self> {
+        let name = read(&[CPUFREQ_PATH, "/policy0/scaling_driver"].concat())?;
+        let mode = if name.starts_with("amd-pstate") {
+            read(AMD_PSTATE_STATUS_PATH).unwrap_or_default()
+        } else {
+            String::new()
+        };
 
-        // The profile for the `energy_performance_preference`.
-        let mut epp = None;
+        Some(Self { name, mode })
+    }
 
-        // Decide the scaling governor to use with this profile.
-        let governor = match profile {
-            // Prefer battery life over efficiency
-            Profile::Battery => match driver {
-                "amd-pstate" | "intel_pstate" => "powersave",
-                "amd-pstate-epp" => {
-                    epp = Some("balance_power");
-                    "powersave"
-                }
-                _ => "conservative",
-            },
-            // The most energy-efficient profile
-            Profile::Balanced => match driver {
-                "amd-pstate" => "ondemand",
-                "amd-pstate-epp" => {
-                    epp = Some("balance_performance");
-                    "powersave"
-                }
-                "intel_pstate" => "powersave",
-                _ => "schedutil",
-            },
-            // Maximum performance
-            Profile::Performance => {
-                epp = (driver == "amd-pstate-epp").then_some("performance");
-                "performance"
+    /// The files telling the driver, and the mode of amd-pstate, as they were read, to tell
+    /// which parameters the profile set.
+    #[must_use]
+    pub fn read_from(&self) -> Vec<Written> {
+        let mut read = vec![Written::new(
+            "scaling_driver",
+            [CPUFREQ_PATH, "/policy0/scaling_driver"].concat(),
+            &self.name,
+        )];
+        if !self.mode.is_empty() {
+            read.push(Written::new("amd_pstate", AMD_PSTATE_STATUS_PATH, &self.mode));
+        }
+        read
+    }
+
+    /// Whether the frequency limits of the profile are written. amd-pstate picks the frequency
+    /// itself in active and passive mode, where they are only written if set in
+    /// `profiles.toml`, while its guided mode picks it within them.
+    fn limits_frequency(&self, tunables: &ProfileTunables) -> bool {
+        !self.name.starts_with("amd-pstate")
+            || self.mode == "guided"
+            || tunables.max_frequency_percent.is_some()
+    }
+}
+
+/// The type of the cores of a policy on hybrid CPUs, such as Alder Lake and Raptor Lake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoreType {
+    Performance,
+    Efficient,
+}
+
+impl CoreType {
+    /// The overrides of `profiles.toml` for cores of this type.
+    fn tunables(self, tunables: &ProfileTunables) -> &CoreTunables {
+        match self {
+            Self::Performance => &tunables.pcore,
+            Self::Efficient => &tunables.ecore,
+        }
+    }
+
+    /// Name of a parameter of a policy with cores of this type, as the profile status lists
+    /// it, such as `pcore.scaling_governor`.
+    fn parameter(self, name: &'static str) -> &'static str {
+        match (self, name) {
+            (Self::Performance, "scaling_governor") => "pcore.scaling_governor",
+            (Self::Performance, "scaling_min_freq") => "pcore.scaling_min_freq",
+            (Self::Performance, "scaling_max_freq") => "pcore.scaling_max_freq",
+            (Self::Performance, "energy_performance_preference") => {
+                "pcore.energy_performance_preference"
             }
-        };
+            (Self::Efficient, "scaling_governor") => "ecore.scaling_governor",
+            (Self::Efficient, "scaling_min_freq") => "ecore.scaling_min_freq",
+            (Self::Efficient, "scaling_max_freq") => "ecore.scaling_max_freq",
+            (Self::Efficient, "energy_performance_preference") => {
+                "ecore.energy_performance_preference"
+            }
+            _ => name,
+        }
+    }
+}
 
-        if let Some((cpus, (min, max))) = num_cpus().zip(min_freq.zip(max_freq)) {
-            let max = max * max_percent.min(100) as usize / 100;
-            eprintln!("setting {} with max {}", governor, max);
+/// The type of the cores of each policy, by name, on hybrid CPUs, as the kernel lists them
+/// with its `cpu_core` and `cpu_atom` PMUs. Other CPUs have none.
+#[must_use]
+pub fn core_types() -> BTreeMap<String, CoreType> {
+    core_types_in(Path::new(DEVICES_PATH), Path::new(CPUFREQ_PATH))
+}
 
-            for cpu in 0..=cpus {
-                core.load(cpu);
+fn core_types_in(devices: &Path, cpufreq: &Path) -> BTreeMap<String, CoreType> {
+    let cpus = |pmu: &str| {
+        let list = fs::read_to_string(devices.join(pmu).join("cpus")).unwrap_or_default();
+        parse_cpu_list(&list)
+    };
+    let (pcores, ecores) = (cpus("cpu_core"), cpus("cpu_atom"));
+    if pcores.is_empty() || ecores.is_empty() {
+        return BTreeMap::new();
+    }
 
-                if !is_amd_pstate {
-                    core.set_frequency_minimum(min);
-                    core.set_frequency_maximum(max);
-                }
+    // The CPUs of a policy share their type.
+    policies_in(cpufreq)
+        .into_iter()
+        .filter_map(|policy| {
+            let related = fs::read_to_string(cpufreq.join(&policy).join("related_cpus")).ok()?;
+            let cpu = related.split_whitespace().next()?.parse::<usize>().ok()?;
+            let core = if pcores.contains(&cpu) {
+                CoreType::Performance
+            } else if ecores.contains(&cpu) {
+                CoreType::Efficient
+            } else {
+                return None;
+            };
+            Some((policy, core))
+        })
+        .collect()
+}
+
+/// The CPUs of a list such as `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let range = |range: &str| -> Option<std::ops::RangeInclusive<usize>> {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        Some(first.parse().ok()?..=last.parse().ok()?)
+    };
+    list.trim().split(',').filter_map(range).flatten().collect()
+}
 
-                core.set_governor(governor);
+/// The governor, the frequency limits and the energy performance preference of a profile, as
+/// written to each policy. The governor and preference of `tunables` replace those of the
+/// profile, and on hybrid CPUs, those of the `pcore` and `ecore` tables replace them in turn
+/// for the policies of their type of cores, which are named after it.
+#[must_use]
+pub fn plan(profile: Profile, max_percent: u8, tunables: &ProfileTunables) -> Vec<Written> {
+    let mut written = Vec::new();
+    let Some(driver) = Driver::detect() else { return written };
+    let unified = overridden(governor(profile, &driver.name), tunables);
+    let core_types = core_types();
 
-                if let Some(preference) = epp {
-                    core.set_epp(preference);
-                }
+    for name in policies() {
+        let mut policy = Cpu::policy(&name);
+        let core = core_types.get(&name).copied();
+        let (governor, epp, max_percent) = core_values(core, unified, max_percent, tunables);
+        let start = written.len();
+
+        let limits_frequency = driver.limits_frequency(tunables)
+            || core.map_or(false, |core| core.tunables(tunables).max_frequency_percent.is_some());
+        if limits_frequency {
+            let limits = policy.frequency_minimum().zip(policy.frequency_maximum());
+            if let Some((min, max)) = limits {
+                let max = max * max_percent.min(100) as usize / 100;
+                written.push(policy.planned("scaling_min_freq", min));
+                written.push(policy.planned("scaling_max_freq", max));
+            }
+        }
+
+        written.push(policy.planned("scaling_governor", governor));
+
+        // amd-pstate takes a preference in active mode, as intel_pstate may in profiles.toml.
+        if let Some(preference) = epp {
+            written.push(policy.planned("energy_performance_preference", preference));
+        }
+
+        if let Some(core) = core {
+            for value in &mut written[start..] {
+                value.name = core.parameter(value.name);
             }
         }
     }
+
+    written
+}
+
+/// The governor, preference and maximum frequency of a policy, in percent, as the `pcore` or
+/// `ecore` table of `tunables` overrides those of the profile for its type of cores.
+fn core_values<'a>(
+    core: Option<CoreType>,
+    (governor, epp): (&'a str, Option<&'a str>),
+    max_percent: u8,
+    tunables: &'a ProfileTunables,
+) -> (&'a str, Option<&'a str>, u8) {
+    let Some(core) = core else { return (governor, epp, max_percent) };
+    let core = core.tunables(tunables);
+    (
+        core.governor.as_deref().unwrap_or(governor),
+        core.energy_performance_preference.as_deref().or(epp),
+        core.max_frequency_percent.unwrap_or(max_percent),
+    )
+}
+
+/// The governor and `energy_performance_preference` which `set` applies with the scaling
+/// driver of this machine, without applying them.
+#[must_use]
+pub fn planned(profile: Profile, tunables: &ProfileTunables) -> Option<(String, Option<String>)> {
+    Driver::detect().map(|driver| {
+        let (governor, epp) = overridden(governor(profile, &driver.name), tunables);
+        (governor.to_owned(), epp.map(str::to_owned))
+    })
+}
+
+/// The scaling driver, governors, boost and frequencies of the CPUs, as read from cpufreq
+/// without writing anything. Values are empty without cpufreq, such as on virtual machines.
+#[must_use]
+pub fn status() -> CpuFrequencyStatus { status_in(Path::new(CPU_PATH)) }
+
+/// The status of a tree such as `/sys/devices/system/cpu`.
+fn status_in(root: &Path) -> CpuFrequencyStatus {
+    let read = |path: &Path| fs::read_to_string(path).ok().map(|value| value.trim().to_owned());
+    let cpufreq = root.join("cpufreq");
+    let policy0 = |file: &str| read(&cpufreq.join("policy0").join(file)).unwrap_or_default();
+    let khz = |file: &str| policy0(file).parse::<u32>().unwrap_or(0);
+
+    let scaling_driver = policy0("scaling_driver");
+    let driver_mode = match scaling_driver.as_str() {
+        "intel_pstate" | "intel_cpufreq" => read(&root.join("intel_pstate/status")),
+        driver if driver.starts_with("amd-pstate") => read(&root.join("amd_pstate/status")),
+        _ => None,
+    };
+
+    // A single governor is reported if every policy has the same.
+    let mut policy_governors = policies_in(&cpufreq)
+        .into_iter()
+        .filter_map(|policy| {
+            let governor = read(&cpufreq.join(&policy).join("scaling_governor"))?;
+            Some(PolicyGovernor { policy, governor })
+        })
+        .collect::<Vec<_>>();
+    let uniform = policy_governors.windows(2).all(|pair| pair[0].governor == pair[1].governor);
+    let governor = match policy_governors.first() {
+        Some(first) if uniform => first.governor.clone(),
+        _ => String::new(),
+    };
+    if uniform {
+        policy_governors.clear();
+    }
+
+    let boost = Boost::detect_in(root).and_then(|boost| boost.get());
+
+    CpuFrequencyStatus {
+        scaling_driver,
+        driver_mode: driver_mode.unwrap_or_default(),
+        governor,
+        policy_governors,
+        energy_performance_preference: policy0("energy_performance_preference"),
+        boost: boost.map_or("", |enabled| if enabled { "on" } else { "off" }).to_owned(),
+        min_khz: khz("scaling_min_freq"),
+        max_khz: khz("scaling_max_freq"),
+        current_khz: khz("scaling_cur_freq"),
+    }
+}
+
+/// The cpufreq policies, such as `policy0`, each applying to one or more CPUs, in order.
+fn policies() -> Vec<String> { policies_in(Path::new(CPUFREQ_PATH)) }
+
+fn policies_in(cpufreq: &Path) -> Vec<String> {
+    let mut policies = fs::read_dir(cpufreq)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|name| Some((name.strip_prefix("policy")?.parse::<usize>().ok()?, name)))
+        .collect::<Vec<_>>();
+
+    policies.sort_unstable();
+    policies.into_iter().map(|(_, name)| name).collect()
+}
+
+/// The trimmed contents of a file, if it can be read.
+fn read(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|contents| contents.trim().to_owned())
+}
+
+/// The governor and preference of a profile, as overridden by `tunables`.
+fn overridden<'a>(
+    (governor, epp): (&'a str, Option<&'a str>),
+    tunables: &'a ProfileTunables,
+) -> (&'a str, Option<&'a str>) {
+    (
+        tunables.governor.as_deref().unwrap_or(governor),
+        tunables.energy_performance_preference.as_deref().or(epp),
+    )
+}
+
+/// Decides the scaling governor, and the `energy_performance_preference` if any, to use with a
+/// profile and a scaling driver.
+fn governor(profile: Profile, driver: &str) -> (&'static str, Option<&'static str>) {
+    // The profile for the `energy_performance_preference`.
+    let mut epp = None;
+
+    let governor = match profile {
+        // Prefer battery life over efficiency, or quiet fans, with a conservative preference
+        Profile::Battery | Profile::Quiet => match driver {
+            "amd-pstate" | "intel_pstate" => "powersave",
+            "amd-pstate-epp" => {
+                epp = Some("balance_power");
+                "powersave"
+            }
+            _ => "conservative",
+        },
+        // The most energy-efficient profile
+        Profile::Balanced => match driver {
+            "amd-pstate" => "ondemand",
+            "amd-pstate-epp" => {
+                epp = Some("balance_performance");
+                "powersave"
+            }
+            "intel_pstate" => "powersave",
+            _ => "schedutil",
+        },
+        // Maximum performance
+        Profile::Performance => {
+            epp = (driver == "amd-pstate-epp").then_some("performance");
+            "performance"
+        }
+    };
+
+    (governor, epp)
 }
 
 pub struct Cpu {
@@ -89,6 +361,13 @@ impl Cpu {
         Self { path_len: path.len(), path, read_buffer: Vec::with_capacity(16) }
     }
 
+    /// The files of a cpufreq policy, such as `policy0`, which apply to one or more CPUs.
+    #[must_use]
+    pub fn policy(name: &str) -> Self {
+        let path = [CPUFREQ_PATH, "/", name, "/"].concat();
+        Self { path_len: path.len(), path, read_buffer: Vec::with_capacity(16) }
+    }
+
     pub fn load(&mut self, core: usize) {
         self.path.clear();
         cpu_path(&mut self.path, core);
@@ -108,23 +387,32 @@ impl Cpu {
     #[must_use]
     pub fn scaling_driver(&mut self) -> Option<&str> { self.get_value("scaling_driver") }
 
-    pub fn set_epp(&mut self, preference: &str) {
-        self.set_value("energy_performance_preference", preference);
+    pub fn set_epp(&mut self, preference: &str) -> Written {
+        self.set_value("energy_performance_preference", preference)
     }
 
-    pub fn set_frequency_maximum(&mut self, frequency: usize) {
-        self.set_value("scaling_max_freq", frequency);
+    pub fn set_frequency_maximum(&mut self, frequency: usize) -> Written {
+        self.set_value("scaling_max_freq", frequency)
     }
 
-    pub fn set_frequency_minimum(&mut self, frequency: usize) {
-        self.set_value("scaling_min_freq", frequency);
+    pub fn set_frequency_minimum(&mut self, frequency: usize) -> Written {
+        self.set_value("scaling_min_freq", frequency)
     }
 
-    pub fn set_governor(&mut self, governor: &str) { self.set_value("scaling_governor", governor); }
+    pub fn set_governor(&mut self, governor: &str) -> Written {
+        self.set_value("scaling_governor", governor)
+    }
+
+    fn set_value<V: std::fmt::Display>(&mut self, file: &'static str, value: V) -> Written {
+        let written = self.planned(file, value);
+        write_value(&written.path, &written.value);
+        written
+    }
 
-    fn set_value<V: std::fmt::Display>(&mut self, file: &str, value: V) {
+    /// The value of a file of this CPU, as it would be written.
+    fn planned<V: std::fmt::Display>(&mut self, file: &'static str, value: V) -> Written {
         self.path.truncate(self.path_len);
-        write_value(strcat!(&mut self.path, file), value);
+        Written::new(file, strcat!(&mut self.path, file).as_str(), value)
     }
 
     fn get_value(&mut self, file: &str) -> Option<&str> {
@@ -150,3 +438,159 @@ pub fn num_cpus() -> Option<usize> {
 fn cpu_path(buffer: &mut String, core: usize) {
     let _ = write!(buffer, "/sys/devices/system/cpu/cpu{}/cpufreq/", core);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn governors() {
+        assert_eq!(governor(Profile::Battery, "intel_pstate"), ("powersave", None));
+        assert_eq!(governor(Profile::Balanced, "acpi-cpufreq"), ("schedutil", None));
+        assert_eq!(
+            governor(Profile::Quiet, "amd-pstate-epp"),
+            ("powersave", Some("balance_power"))
+        );
+        assert_eq!(
+            governor(Profile::Balanced, "amd-pstate-epp"),
+            ("powersave", Some("balance_performance"))
+        );
+        assert_eq!(governor(Profile::Performance, "amd-pstate"), ("performance", None));
+    }
+
+    #[test]
+    fn frequency_limits() {
+        let driver = |name: &str, mode: &str| Driver { name: name.into(), mode: mode.into() };
+        let builtin = ProfileTunables::default();
+        let limited = ProfileTunables { max_frequency_percent: Some(80), ..builtin.clone() };
+
+        assert!(driver("intel_pstate", "").limits_frequency(&builtin));
+        assert!(driver("acpi-cpufreq", "").limits_frequency(&builtin));
+        assert!(driver("amd-pstate", "guided").limits_frequency(&builtin));
+        assert!(!driver("amd-pstate", "passive").limits_frequency(&builtin));
+        assert!(!driver("amd-pstate-epp", "active").limits_frequency(&builtin));
+        assert!(driver("amd-pstate-epp", "active").limits_frequency(&limited));
+    }
+
+    #[test]
+    fn frequency_status() {
+        let root = TempDir::new("cpufreq");
+        let write = |path: &str, value: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        // Without cpufreq, such as on virtual machines, nothing is known.
+        assert_eq!(status_in(&root), CpuFrequencyStatus::default());
+
+        write("intel_pstate/status", "passive\n");
+        write("intel_pstate/no_turbo", "1\n");
+        for policy in ["policy0", "policy1", "policy10"] {
+            write(&format!("cpufreq/{}/scaling_driver", policy), "intel_cpufreq\n");
+            write(&format!("cpufreq/{}/scaling_governor", policy), "schedutil\n");
+        }
+        write("cpufreq/policy0/scaling_min_freq", "400000\n");
+        write("cpufreq/policy0/scaling_max_freq", "4700000\n");
+        write("cpufreq/policy0/scaling_cur_freq", "1200000\n");
+
+        let status = status_in(&root);
+        assert_eq!(
+            (status.scaling_driver.as_str(), status.driver_mode.as_str()),
+            ("intel_cpufreq", "passive")
+        );
+        assert_eq!((status.governor.as_str(), status.boost.as_str()), ("schedutil", "off"));
+        assert!(status.policy_governors.is_empty());
+        assert_eq!(
+            (status.min_khz, status.max_khz, status.current_khz),
+            (400_000, 4_700_000, 1_200_000)
+        );
+        assert_eq!(status.energy_performance_preference, "");
+
+        // Governors are listed by policy once they differ.
+        write("cpufreq/policy10/scaling_governor", "performance\n");
+        let status = status_in(&root);
+        assert_eq!(status.governor, "");
+        let governors: Vec<_> = status
+            .policy_governors
+            .iter()
+            .map(|policy| (policy.policy.as_str(), policy.governor.as_str()))
+            .collect();
+        assert_eq!(
+            governors,
+            [("policy0", "schedutil"), ("policy1", "schedutil"), ("policy10", "performance")]
+        );
+    }
+
+    #[test]
+    fn hybrid() {
+        let root = TempDir::new("hybrid");
+        let write = |path: &str, value: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        // Without the PMUs of both types of cores, CPUs are not hybrid.
+        write("cpu/system/cpu/cpufreq/policy0/related_cpus", "0\n");
+        let cpufreq = root.join("cpu/system/cpu/cpufreq");
+        assert!(core_types_in(&root, &cpufreq).is_empty());
+
+        // Two performance cores with two threads each, then four efficient cores.
+        write("cpu_core/cpus", "0-3\n");
+        write("cpu_atom/cpus", "4-7\n");
+        for (policy, cpus) in
+            [("policy0", "0 1"), ("policy2", "2 3"), ("policy4", "4"), ("policy7", "7")]
+        {
+            write(&format!("cpu/system/cpu/cpufreq/{}/related_cpus", policy), cpus);
+        }
+
+        let types = core_types_in(&root, &cpufreq);
+        let types: Vec<_> = types.iter().map(|(policy, core)| (policy.as_str(), *core)).collect();
+        assert_eq!(
+            types,
+            [
+                ("policy0", CoreType::Performance),
+                ("policy2", CoreType::Performance),
+                ("policy4", CoreType::Efficient),
+                ("policy7", CoreType::Efficient),
+            ]
+        );
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), [0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(CoreType::Efficient.parameter("scaling_max_freq"), "ecore.scaling_max_freq");
+
+        // The tables of each type of cores override the values of the profile.
+        let tunables = ProfileTunables {
+            pcore: CoreTunables {
+                energy_performance_preference: Some("power".into()),
+                max_frequency_percent: Some(60),
+                ..CoreTunables::default()
+            },
+            ..ProfileTunables::default()
+        };
+        let unified = governor(Profile::Battery, "intel_pstate");
+        let values = |core| core_values(core, unified, 50, &tunables);
+        assert_eq!(values(Some(CoreType::Performance)), ("powersave", Some("power"), 60));
+        assert_eq!(values(Some(CoreType::Efficient)), ("powersave", None, 50));
+        assert_eq!(values(None), ("powersave", None, 50));
+    }
+
+    #[test]
+    fn overridden_governors() {
+        let tunables = ProfileTunables {
+            energy_performance_preference: Some("power".into()),
+            ..ProfileTunables::default()
+        };
+        assert_eq!(
+            overridden(governor(Profile::Balanced, "amd-pstate-epp"), &tunables),
+            ("powersave", Some("power"))
+        );
+
+        let tunables = ProfileTunables { governor: Some("ondemand".into()), ..tunables };
+        assert_eq!(
+            overridden(governor(Profile::Battery, "acpi-cpufreq"), &tunables),
+            ("ondemand", Some("power"))
+        );
+    }
+}
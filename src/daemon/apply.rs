@@ -0,0 +1,127 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! `apply` restores the settings the daemon would restore when it starts, and exits, for
+//! systems which do not run the daemon: the graphics power, the profile and the charge
+//! thresholds. Every step is attempted, even after another failed.
+
+use std::sync::atomic::Ordering;
+
+use crate::{
+    charge_thresholds::{battery_names, restore_charge_thresholds},
+    graphics::Graphics,
+    power_supply,
+    state::State,
+    tunables, DBUS_NAME,
+};
+
+use super::{
+    apply_graphics_power, auto_profile::startup_profile, direct::daemon_running, profiles,
+    profiles::Applied, settings::DaemonConfig, PCI_RUNTIME_PM,
+};
+
+/// Applies the saved settings, failing if any of them could not be applied, or if the daemon
+/// is running, as it applies them itself.
+#[tokio::main(flavor = "current_thread")]
+pub async fn apply() -> anyhow::Result<()> {
+    if daemon_running().await {
+        return Err(anyhow::anyhow!(
+            "{} is running, which applies the settings itself; stop it first",
+            DBUS_NAME
+        ));
+    }
+
+    let pci_runtime_pm = std::env::var("S76_POWER_PCI_RUNTIME_PM").ok().map_or(false, |v| v == "1");
+    PCI_RUNTIME_PM.store(pci_runtime_pm, Ordering::SeqCst);
+
+    if let Err(why) = tunables::load() {
+        log::warn!("using the built-in profile tunables: {}", why);
+    }
+    let config = DaemonConfig::load().unwrap_or_else(|why| {
+        log::warn!("using default daemon config: {}", why);
+        DaemonConfig::default()
+    });
+
+    let state = State::load();
+
+    let mut steps = 0;
+    let mut failed = 0;
+    let mut step = |name: &str, result: Result<String, String>| {
+        steps += 1;
+        match result {
+            Ok(done) => log::info!("{}: {}", name, done),
+            Err(why) => {
+                failed += 1;
+                log::error!("{}: {}", name, why);
+            }
+        }
+    };
+
+    let power = match state.graphics_power {
+        Some(ref power) => Some(power.as_str()),
+        None if config.startup.auto_power => Some("auto"),
+        None => None,
+    };
+
+    if let Some(power) = power {
+        let result = Graphics::new()
+            .map_err(|why| format!("failed to read the PCI bus: {}", why))
+            .and_then(|graphics| {
+                apply_graphics_power(&graphics, power).map_err(|why| why.to_string())
+            })
+            .map(|on| format!("applied {}, the discrete GPU is {}", power, on_off(on)));
+        step("Graphics power", result);
+    }
+
+    let on_ac = power_supply::on_ac();
+    let (name, _) = startup_profile(&config.auto_profile, on_ac, state.profile.as_deref());
+    let name = name.as_str();
+    if let Some(profile) = tunables::find(name) {
+        let mut applied = Applied::default();
+        profiles::set(&profile, &mut applied, false);
+
+        // Failures are logged as warnings if most parameters were set.
+        let result = applied
+            .outcome(name)
+            .map(|failures| match failures.len() {
+                0 => format!("applied {}", name),
+                failed => format!("applied {}, but {} parameters failed", name, failed),
+            })
+            .map_err(|why| why.to_string());
+        step("Profile", result);
+    }
+
+    if state.has_charge_thresholds() {
+        let result = restore_charge_thresholds(&state)
+            .map(|writes| {
+                let applied = battery_names()
+                    .into_iter()
+                    .filter(|battery| !writes.failed.contains_key(battery))
+                    .filter_map(|battery| {
+                        let (start, end) = state.charge_thresholds_of(&battery)?;
+                        Some(format!("{} to {} on {}", start, end, battery))
+                    });
+                let failed = writes
+                    .failed
+                    .iter()
+                    .map(|(battery, why)| format!("failed on {}: {}", battery, why));
+                format!("applied {}", applied.chain(failed).collect::<Vec<_>>().join(", "))
+            })
+            .map_err(|why| why.to_string());
+        step("Charge thresholds", result);
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(anyhow::anyhow!("{} of {} settings failed to apply", failed, steps)),
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Changes requested by clients are limited per client, so that a misbehaving applet cannot
+//! flap the profile many times a second, and logged with the credentials of the client and
+//! the outcome. The latest are kept for `GetRecentActions`.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use system76_power_zbus::RecentAction;
+
+use super::{error::PowerError, sender, System76Power};
+
+/// Number of actions kept for `GetRecentActions`.
+const RECENT_ACTIONS: usize = 100;
+
+/// The `[rate_limit]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled:    bool,
+    /// Changes a client may request at once, after being quiet.
+    pub burst:      u32,
+    /// Changes a client may request per minute in the long run.
+    pub per_minute: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self { Self { enabled: true, burst: 20, per_minute: 60 } }
+}
+
+/// A token bucket, refilled at `per_minute` up to `burst` tokens.
+struct Bucket {
+    tokens:  f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, config: &RateLimitConfig, now: Instant) -> f64 {
+        let rate = f64::from(config.per_minute) / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(f64::from(config.burst));
+        self.updated = now;
+        self.tokens
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Audit {
+    buckets: HashMap<String, Bucket>,
+    recent:  VecDeque<RecentAction>,
+}
+
+impl Audit {
+    /// Takes a token from the bucket of `sender`, returning `false` if none remains.
+    pub fn admit(&mut self, config: &RateLimitConfig, sender: &str, now: Instant) -> bool {
+        if !config.enabled {
+            return true;
+        }
+
+        // Full buckets are forgotten, so that clients which left the bus are not remembered.
+        let burst = f64::from(config.burst);
+        self.buckets.retain(|_, bucket| bucket.refill(config, now) < burst);
+
+        let bucket = self
+            .buckets
+            .entry(sender.to_owned())
+            .or_insert(Bucket { tokens: burst, updated: now });
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    pub fn record(&mut self, action: RecentAction) {
+        if self.recent.len() == RECENT_ACTIONS {
+            self.recent.pop_front();
+        }
+
+        self.recent.push_back(action);
+    }
+
+    /// The recorded actions, oldest first.
+    pub fn recent(&self) -> Vec<RecentAction> { self.recent.iter().cloned().collect() }
+}
+
+/// The process ID and user ID of a client, or 0 and `u32::MAX` if they cannot be resolved.
+async fn credentials(connection: &zbus::Connection, sender: &str) -> (u32, u32) {
+    let unknown = (0, u32::MAX);
+    if connection.unique_name().is_none() {
+        return unknown;
+    }
+
+    let result = async {
+        let dbus = zbus::fdo::DBusProxy::new(connection).await?;
+        let name = zbus::names::BusName::try_from(sender)?;
+        Ok::<_, zbus::Error>(dbus.get_connection_credentials(name).await?)
+    };
+
+    match result.await {
+        Ok(credentials) => (
+            credentials.process_id().unwrap_or(unknown.0),
+            credentials.unix_user_id().unwrap_or(unknown.1),
+        ),
+        Err(why) => {
+            log::debug!("Failed to resolve the credentials of {}: {}", sender, why);
+            unknown
+        }
+    }
+}
+
+impl System76Power {
+    /// Performs a change requested by a client, unless the client exceeded its rate, and logs
+    /// and records it with its outcome. `request` describes the arguments of `method`.
+    pub(super) async fn audited<T>(
+        &self,
+        connection: &zbus::Connection,
+        header: &zbus::MessageHeader<'_>,
+        method: &'static str,
+        request: String,
+        action: impl Future<Output = Result<T, PowerError>>,
+    ) -> Result<T, PowerError> {
+        let sender = sender(header);
+        let (pid, uid) = credentials(connection, &sender).await;
+
+        let admitted = {
+            let mut this = self.0.lock().await;
+            let this = &mut *this;
+            this.audit.admit(&this.config.rate_limit, &sender, Instant::now())
+        };
+
+        let result = if admitted {
+            action.await
+        } else {
+            let config = &self.0.lock().await.config.rate_limit;
+            Err(PowerError::RateLimited(format!(
+                "{} requested more than {} changes per minute",
+                sender, config.per_minute
+            )))
+        };
+
+        let outcome = match &result {
+            Ok(_) => "ok".to_owned(),
+            Err(why) => format!("{}: {}", why.error_name(), why.message()),
+        };
+
+        log::info!(
+            method = method, sender = sender.as_str(), pid = pid, uid = uid,
+            request = request.as_str(), outcome = outcome.as_str();
+            "{}({}) from {} (pid {}, uid {}): {}", method, request, sender, pid, uid, outcome
+        );
+
+        self.0.lock().await.audit.record(RecentAction {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            sender,
+            pid,
+            uid,
+            method: method.to_owned(),
+            request,
+            outcome,
+        });
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn token_buckets() {
+        let config = RateLimitConfig { enabled: true, burst: 3, per_minute: 60 };
+        let mut audit = Audit::default();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(audit.admit(&config, ":1.1", start));
+        }
+        assert!(!audit.admit(&config, ":1.1", start));
+        assert!(audit.admit(&config, ":1.2", start), "buckets are per client");
+
+        // One token is refilled per second.
+        assert!(audit.admit(&config, ":1.1", start + Duration::from_secs(1)));
+        assert!(!audit.admit(&config, ":1.1", start + Duration::from_secs(1)));
+
+        // Refilled buckets are forgotten.
+        assert!(audit.admit(&config, ":1.1", start + Duration::from_secs(60)));
+        assert_eq!(audit.buckets.len(), 1);
+
+        let disabled = RateLimitConfig { enabled: false, ..config };
+        assert!((0..10).all(|_| audit.admit(&disabled, ":1.1", start)));
+    }
+
+    #[test]
+    fn recent_actions() {
+        let mut audit = Audit::default();
+        for pid in 0..RECENT_ACTIONS as u32 + 5 {
+            audit.record(RecentAction { pid, ..RecentAction::default() });
+        }
+
+        let recent = audit.recent();
+        assert_eq!(recent.len(), RECENT_ACTIONS);
+        assert_eq!(recent[0].pid, 5);
+        assert_eq!(recent[RECENT_ACTIONS - 1].pid, RECENT_ACTIONS as u32 + 4);
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Switching the profile when the system is plugged in or unplugged.
+
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use system76_power_zbus::AutoProfileStatus;
+
+use crate::tunables;
+
+/// The `[auto_profile]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoProfileConfig {
+    pub enabled:    bool,
+    /// Profile applied when the AC adapter is plugged in, built in or custom.
+    pub ac:         String,
+    /// Profile applied when running on battery.
+    pub battery:    String,
+    /// Keep a profile set by a client until the next transition. Otherwise, the mapped profile
+    /// is re-applied whenever a power supply reports a change, such as its charge level.
+    pub pin_manual: bool,
+}
+
+impl Default for AutoProfileConfig {
+    fn default() -> Self {
+        Self {
+            enabled:    false,
+            ac:         "performance".into(),
+            battery:    "battery".into(),
+            pin_manual: true,
+        }
+    }
+}
+
+impl AutoProfileConfig {
+    /// Name of the profile mapped to a power source, as known to the daemon.
+    pub fn profile(&self, on_ac: bool) -> Result<String, String> {
+        profile_name(if on_ac { &self.ac } else { &self.battery })
+    }
+}
+
+/// Name of a profile of the config, such as `battery` or a custom profile of `profiles.toml`,
+/// as known to the daemon.
+pub(super) fn profile_name(name: &str) -> Result<String, String> {
+    match name {
+        "battery" => Ok("Battery".into()),
+        "quiet" => Ok("Quiet".into()),
+        "balanced" => Ok("Balanced".into()),
+        "performance" => Ok("Performance".into()),
+        _ if tunables::find(name).map_or(false, |profile| profile.is_custom()) => Ok(name.into()),
+        _ => Err(format!(
+            "unknown profile '{}', expected battery, quiet, balanced, performance or a custom \
+             profile of profiles.toml",
+            name
+        )),
+    }
+}
+
+/// Why a profile is applied when the daemon starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Startup {
+    /// Mapped to the power source, which is `on_ac`.
+    Mapped(bool),
+    /// Saved by a previous run.
+    Persisted,
+    Default,
+}
+
+/// The profile applied when the daemon starts: the one mapped to the power source if switching
+/// is enabled, else the last one saved if it is still defined, or `Balanced`.
+pub(super) fn startup_profile(
+    config: &AutoProfileConfig,
+    on_ac: Option<bool>,
+    persisted: Option<&str>,
+) -> (String, Startup) {
+    if let Some(on_ac) = on_ac.filter(|_| config.enabled) {
+        match config.profile(on_ac) {
+            Ok(profile) => return (profile, Startup::Mapped(on_ac)),
+            Err(why) => log::warn!("Not starting with the profile of the power source: {}", why),
+        }
+    }
+
+    match persisted.filter(|name| tunables::find(name).is_some()) {
+        Some(name) => (name.to_owned(), Startup::Persisted),
+        None => ("Balanced".to_owned(), Startup::Default),
+    }
+}
+
+/// The power source the last profile was applied for.
+pub(super) struct Trigger {
+    pub on_ac:   bool,
+    pub profile: String,
+    pub time:    SystemTime,
+}
+
+#[derive(Default)]
+pub(super) struct AutoProfile {
+    /// Power source at the last check, or `None` if the system has no AC adapter.
+    pub on_ac:        Option<bool>,
+    pub last_trigger: Option<Trigger>,
+}
+
+impl AutoProfile {
+    pub fn status(&self, config: &AutoProfileConfig) -> AutoProfileStatus {
+        let mut status = AutoProfileStatus {
+            enabled: config.enabled,
+            ac: config.ac.clone(),
+            battery: config.battery.clone(),
+            pin_manual: config.pin_manual,
+            ..AutoProfileStatus::default()
+        };
+
+        if let Some(ref trigger) = self.last_trigger {
+            status.last_trigger = if trigger.on_ac { "ac" } else { "battery" }.into();
+            status.last_trigger_profile = trigger.profile.clone();
+            status.last_trigger_time = trigger
+                .time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs());
+        }
+
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config() {
+        let config: AutoProfileConfig =
+            toml::from_str("enabled = true\nac = \"balanced\"").unwrap();
+        assert!(config.enabled && config.pin_manual);
+        assert_eq!(config.profile(true), Ok("Balanced".into()));
+        assert_eq!(config.profile(false), Ok("Battery".into()));
+
+        let config = AutoProfileConfig { battery: "turbo".into(), ..config };
+        assert!(config.profile(false).is_err());
+    }
+
+    /// Restarts with and without switching enabled, a saved profile and an AC adapter.
+    #[test]
+    fn startup() {
+        let disabled = AutoProfileConfig::default();
+        let enabled = AutoProfileConfig { enabled: true, ..AutoProfileConfig::default() };
+        let saved = Some("Performance");
+
+        // Without switching, the saved profile wins over the default.
+        let persisted = ("Performance".into(), Startup::Persisted);
+        assert_eq!(startup_profile(&disabled, Some(false), saved), persisted);
+        assert_eq!(startup_profile(&disabled, None, saved), persisted);
+        let default = ("Balanced".into(), Startup::Default);
+        assert_eq!(startup_profile(&disabled, Some(true), None), default);
+        assert_eq!(startup_profile(&disabled, Some(true), Some("removed")), default);
+
+        // With switching, the mapped profile wins over the saved one.
+        let mapped = ("Battery".into(), Startup::Mapped(false));
+        assert_eq!(startup_profile(&enabled, Some(false), saved), mapped);
+        assert_eq!(startup_profile(&enabled, Some(false), None), mapped);
+        let mapped = ("Performance".into(), Startup::Mapped(true));
+        assert_eq!(startup_profile(&enabled, Some(true), Some("Battery")), mapped);
+
+        // Unless the system has no AC adapter, or the mapping is invalid.
+        assert_eq!(startup_profile(&enabled, None, saved), persisted);
+        assert_eq!(startup_profile(&enabled, None, None), default);
+        let invalid = AutoProfileConfig { battery: "turbo".into(), ..enabled };
+        assert_eq!(startup_profile(&invalid, Some(false), saved), persisted);
+        assert_eq!(startup_profile(&invalid, Some(false), None), default);
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Capping the maximum frequency of the CPUs while running on battery, whatever the profile.
+//! The profile is set again with the cap on AC/battery transitions, keeping its own limits
+//! where they are lower, and without it once disabled, or when the daemon stops.
+
+use serde::{Deserialize, Serialize};
+use system76_power_zbus::BatteryCapStatus;
+
+/// The `[battery_frequency_cap]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct BatteryCapConfig {
+    pub enabled: bool,
+    /// Maximum frequency on battery, in percent of the maximum of each CPU.
+    pub percent: u8,
+}
+
+impl Default for BatteryCapConfig {
+    fn default() -> Self { Self { enabled: false, percent: 60 } }
+}
+
+impl BatteryCapConfig {
+    pub fn validate(&self) -> Result<(), (&'static str, String)> {
+        if !(1..=100).contains(&self.percent) {
+            return Err(("battery_frequency_cap.percent", "must be from 1 to 100".into()));
+        }
+
+        Ok(())
+    }
+
+    /// The cap applying on the power source, in percent, if any. The power source is taken
+    /// to be AC if it is unknown.
+    #[must_use]
+    pub fn cap(&self, on_ac: Option<bool>) -> Option<u8> {
+        (self.enabled && on_ac == Some(false)).then_some(self.percent)
+    }
+
+    /// The settings, and whether the profile was set with the cap.
+    #[must_use]
+    pub fn status(&self, applied: Option<u8>) -> BatteryCapStatus {
+        BatteryCapStatus {
+            enabled: self.enabled,
+            percent: self.percent,
+            active:  applied.is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap() {
+        let config = BatteryCapConfig { enabled: true, percent: 60 };
+        assert_eq!(config.cap(Some(false)), Some(60));
+        assert_eq!(config.cap(Some(true)), None);
+        assert_eq!(config.cap(None), None);
+        assert_eq!(BatteryCapConfig::default().cap(Some(false)), None);
+
+        assert!(config.validate().is_ok());
+        let why = BatteryCapConfig { enabled: true, percent: 0 }.validate().unwrap_err();
+        assert_eq!(why.0, "battery_frequency_cap.percent");
+
+        let status = config.status(Some(60));
+        assert!(status.enabled && status.active);
+        assert_eq!(status.percent, 60);
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Leaving the brightness of the screens to the desktops which manage it themselves, as told
+//! by the sessions logind reports.
+
+use serde::{Deserialize, Serialize};
+
+use super::sleep::LoginManagerProxy;
+
+/// The `[brightness]` section of `daemon.toml`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct BrightnessConfig {
+    /// Desktops of active sessions which keep the brightness, as logind names them, such as
+    /// `GNOME` or `KDE`.
+    pub managed_by: Vec<String>,
+}
+
+#[zbus::dbus_proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait LoginSession {
+    #[dbus_proxy(property)]
+    fn active(&self) -> zbus::Result<bool>;
+
+    /// The desktop of the session, from `XDG_SESSION_DESKTOP`.
+    #[dbus_proxy(property)]
+    fn desktop(&self) -> zbus::Result<String>;
+}
+
+/// The desktop of an active session which manages the brightness, if any.
+pub(super) async fn managing_desktop(
+    connection: &zbus::Connection,
+    config: &BrightnessConfig,
+) -> Option<String> {
+    if config.managed_by.is_empty() {
+        return None;
+    }
+
+    let manager = match LoginManagerProxy::new(connection).await {
+        Ok(manager) => manager,
+        Err(why) => {
+            log::warn!("Not checking the desktops managing brightness: {}", why);
+            return None;
+        }
+    };
+
+    for (.., path) in manager.list_sessions().await.ok()? {
+        let Ok(builder) = LoginSessionProxy::builder(connection).path(path.into_inner()) else {
+            continue;
+        };
+        let Ok(session) = builder.build().await else { continue };
+
+        if !session.active().await.unwrap_or(false) {
+            continue;
+        }
+
+        let desktop = session.desktop().await.unwrap_or_default();
+        if config.managed_by.iter().any(|managed| managed.eq_ignore_ascii_case(&desktop)) {
+            return Some(desktop);
+        }
+    }
+
+    None
+}
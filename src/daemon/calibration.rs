@@ -0,0 +1,363 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Calibrating a battery, so that the charge it reports matches its capacity again: its charge
+//! thresholds are lifted and it is force-discharged down to a low point, then charged full.
+//! Its thresholds and charge behaviour are saved to the state before they are changed, and
+//! restored once the calibration ends or is cancelled, or on startup if the daemon stopped
+//! meanwhile.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use system76_power_zbus::CalibrationStatus;
+
+use crate::{
+    charge_thresholds::{
+        battery_charge, calibration_battery, calibration_thresholds, charge_behaviour,
+        set_charge_behaviour, set_charge_thresholds, ChargeThresholdError, AUTO_BEHAVIOUR,
+        FORCE_DISCHARGE,
+    },
+    state::{self, State, STATE_PATH},
+};
+
+use super::{error::PowerError, PowerDaemon, System76Power, INITIATOR_SYSTEM};
+
+// How often the charge of the battery is read while it is calibrated.
+const POLL: Duration = Duration::from_secs(30);
+
+/// The `[calibration]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct CalibrationConfig {
+    /// Charge down to which the battery is discharged before it is charged full, in percent.
+    pub low_percent: u8,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self { Self { low_percent: 5 } }
+}
+
+impl CalibrationConfig {
+    pub fn validate(&self) -> Result<(), (&'static str, String)> {
+        if !(1..=50).contains(&self.low_percent) {
+            return Err(("calibration.low_percent", "must be from 1 to 50".into()));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Phase {
+    #[default]
+    Idle,
+    Discharging,
+    Charging,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+impl Phase {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Discharging => "discharging",
+            Self::Charging => "charging",
+            Self::Finished => "finished",
+            Self::Cancelled => "cancelled",
+            Self::Failed => "failed",
+        }
+    }
+
+    fn running(self) -> bool { matches!(self, Self::Discharging | Self::Charging) }
+
+    /// The phase following this one at a charge of `capacity`, in percent: charging once the
+    /// charge falls to `low_percent`, and finished once the battery is full.
+    fn next(self, capacity: u8, full: bool, low_percent: u8) -> Self {
+        match self {
+            Self::Discharging if capacity <= low_percent => Self::Charging,
+            Self::Charging if full || capacity >= 100 => Self::Finished,
+            phase => phase,
+        }
+    }
+}
+
+/// The calibration running, or the last one.
+#[derive(Default)]
+pub(super) struct Calibration {
+    phase:    Phase,
+    battery:  String,
+    /// Charge of the battery when last read, in percent.
+    capacity: u8,
+    message:  String,
+    /// Counts the calibrations started, so that the watch of a cancelled one stops.
+    run:      u64,
+}
+
+impl Calibration {
+    /// Whether a battery is being calibrated, so that its thresholds are left alone.
+    pub fn running(&self) -> bool { self.phase.running() }
+
+    /// The battery being calibrated, or calibrated last.
+    pub fn battery(&self) -> &str { &self.battery }
+
+    pub fn status(&self, config: &CalibrationConfig) -> CalibrationStatus {
+        CalibrationStatus {
+            phase:       self.phase.as_str().to_owned(),
+            battery:     self.battery.clone(),
+            capacity:    self.capacity,
+            low_percent: config.low_percent,
+            message:     self.message.clone(),
+        }
+    }
+}
+
+/// Lifts the thresholds of the battery, if it has any, and force-discharges it.
+fn lift(saved: &state::Calibration) -> Result<(), ChargeThresholdError> {
+    if saved.thresholds.is_some() {
+        set_charge_thresholds((0, 100), Some(&saved.battery))?;
+    }
+
+    set_charge_behaviour(&saved.battery, FORCE_DISCHARGE)?;
+    Ok(())
+}
+
+/// Restores the charge behaviour and the thresholds a battery had before its calibration.
+fn restore(saved: &state::Calibration) -> Result<(), ChargeThresholdError> {
+    let behaviour = if saved.behaviour.is_empty() { AUTO_BEHAVIOUR } else { &saved.behaviour };
+    set_charge_behaviour(&saved.battery, behaviour)?;
+
+    if let Some(thresholds) = saved.thresholds {
+        set_charge_thresholds(thresholds, Some(&saved.battery))?;
+    }
+
+    Ok(())
+}
+
+impl PowerDaemon {
+    /// Ends the calibration with `phase`, restoring its battery.
+    fn finish_calibration(&mut self, phase: Phase, message: String) {
+        self.calibration.phase = phase;
+        self.calibration.message = message;
+
+        let Some(saved) = self.state.calibration.clone() else { return };
+        log::info!("Restoring the charge thresholds and behaviour of {}", saved.battery);
+        if let Err(why) = restore(&saved) {
+            log::warn!("Failed to restore {} after its calibration: {}", saved.battery, why);
+        }
+
+        self.remember(|state| state.calibration = None);
+    }
+
+    /// Restores the battery of a calibration which the daemon stopped during, whether or not
+    /// the other settings of the previous run are restored.
+    pub(super) fn recover_calibration(&mut self) {
+        let mut state = State::load();
+        let Some(saved) = state.calibration.take() else { return };
+
+        log::warn!(
+            "Restoring the charge thresholds and behaviour of {} after an interrupted calibration",
+            saved.battery
+        );
+        if let Err(why) = restore(&saved) {
+            log::warn!("Failed to restore {}: {}", saved.battery, why);
+        }
+
+        self.state.calibration = None;
+        if let Err(why) = state.save() {
+            log::warn!("Failed to save daemon state to {}: {}", STATE_PATH, why);
+        }
+    }
+
+    /// Cancels the calibration running as the daemon stops, restoring its battery.
+    pub(super) fn stop_calibration(&mut self) {
+        if self.calibration.running() {
+            log::info!("Cancelling the calibration of {}", self.calibration.battery);
+            self.finish_calibration(Phase::Cancelled, "The daemon stopped".into());
+        }
+    }
+}
+
+impl System76Power {
+    /// Starts calibrating the first battery which can be force-discharged, unless one is
+    /// being calibrated.
+    pub(super) async fn begin_calibration(
+        &self,
+        context: &zbus::SignalContext<'_>,
+    ) -> Result<CalibrationStatus, PowerError> {
+        let Some(battery) = calibration_battery() else {
+            return Err(PowerError::Unsupported(
+                "No battery can be force-discharged, which calibrating it requires".into(),
+            ));
+        };
+
+        let started = {
+            let mut this = self.0.lock().await;
+            if this.calibration.running() {
+                let why = format!("{} is already being calibrated", this.calibration.battery);
+                return Err(PowerError::Busy(why));
+            }
+
+            let (behaviour, _) = charge_behaviour(&battery).map_err(|why| {
+                PowerError::Failed(format!("Failed to read the charge behaviour: {}", why))
+            })?;
+            let saved = state::Calibration {
+                battery: battery.clone(),
+                thresholds: calibration_thresholds(&battery),
+                behaviour,
+            };
+
+            // Saved before the battery is changed, so that it is restored on startup if the
+            // daemon stops meanwhile.
+            this.remember(|state| state.calibration = Some(saved.clone()));
+            this.calibration = Calibration {
+                phase:    Phase::Discharging,
+                battery:  battery.clone(),
+                capacity: battery_charge(&battery).map_or(0, |(capacity, _)| capacity),
+                message:  String::new(),
+                run:      this.calibration.run + 1,
+            };
+
+            let low_percent = this.config.calibration.low_percent;
+            match lift(&saved) {
+                Ok(()) => {
+                    log::info!("Calibrating {}, discharging it down to {}%", battery, low_percent);
+                    Ok((this.calibration.status(&this.config.calibration), this.calibration.run))
+                }
+                Err(why) => {
+                    this.finish_calibration(Phase::Failed, why.to_string());
+                    Err(why)
+                }
+            }
+        };
+
+        self.calibration_updated(context, true).await;
+        let (status, run) = started?;
+        tokio::spawn(watch(self.clone(), context.to_owned(), run));
+        Ok(status)
+    }
+
+    /// Cancels the calibration running, restoring its battery.
+    pub(super) async fn cancel_running_calibration(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        initiator: &str,
+    ) -> Result<(), PowerError> {
+        {
+            let mut this = self.0.lock().await;
+            if !this.calibration.running() {
+                return Err(PowerError::Failed("No calibration is running".into()));
+            }
+
+            log::info!("Cancelling the calibration of {}", this.calibration.battery);
+            this.finish_calibration(Phase::Cancelled, format!("Cancelled by {}", initiator));
+        }
+
+        self.calibration_updated(context, true).await;
+        Ok(())
+    }
+
+    /// Reads the charge of the battery calibrated, charging it once it falls to the low point,
+    /// and ending the calibration once it is full. Returns `false` once the calibration `run`
+    /// ended.
+    async fn step_calibration(&self, context: &zbus::SignalContext<'_>, run: u64) -> bool {
+        let (changed, running) = {
+            let mut this = self.0.lock().await;
+            let this = &mut *this;
+            if this.calibration.run != run || !this.calibration.running() {
+                return false;
+            }
+
+            let before = this.calibration.status(&this.config.calibration);
+            let battery = this.calibration.battery.clone();
+            match battery_charge(&battery) {
+                Ok((capacity, full)) => {
+                    this.calibration.capacity = capacity;
+                    let phase = this.calibration.phase;
+                    match phase.next(capacity, full, this.config.calibration.low_percent) {
+                        Phase::Charging if phase == Phase::Discharging => {
+                            log::info!("{} fell to {}%, charging it full", battery, capacity);
+                            match set_charge_behaviour(&battery, AUTO_BEHAVIOUR) {
+                                Ok(()) => this.calibration.phase = Phase::Charging,
+                                Err(why) => {
+                                    let why = format!("Failed to charge {}: {}", battery, why);
+                                    this.finish_calibration(Phase::Failed, why);
+                                }
+                            }
+                        }
+                        Phase::Finished => {
+                            log::info!("{} is full, its calibration finished", battery);
+                            this.finish_calibration(Phase::Finished, String::new());
+                        }
+                        _ => (),
+                    }
+                }
+                Err(why) => {
+                    let why = format!("Failed to read the charge of {}: {}", battery, why);
+                    this.finish_calibration(Phase::Failed, why);
+                }
+            }
+
+            let running = this.calibration.running();
+            (this.calibration.status(&this.config.calibration) != before, running)
+        };
+
+        if changed {
+            self.calibration_updated(context, !running).await;
+        }
+        running
+    }
+
+    /// Announces the progress of the calibration, and the thresholds once they were changed.
+    async fn calibration_updated(&self, context: &zbus::SignalContext<'_>, thresholds: bool) {
+        let _res = self.calibration_changed(context).await;
+        if thresholds {
+            self.refresh_charge_thresholds(context, INITIATOR_SYSTEM).await;
+        }
+    }
+}
+
+/// Follows the calibration `run` until it ends.
+async fn watch(daemon: System76Power, context: zbus::SignalContext<'static>, run: u64) {
+    loop {
+        tokio::time::sleep(POLL).await;
+        if !daemon.step_calibration(&context, run).await {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases() {
+        // Discharging goes on down to the low point, then the battery charges until full.
+        assert_eq!(Phase::Discharging.next(6, false, 5), Phase::Discharging);
+        assert_eq!(Phase::Discharging.next(5, false, 5), Phase::Charging);
+        assert_eq!(Phase::Charging.next(99, false, 5), Phase::Charging);
+        assert_eq!(Phase::Charging.next(97, true, 5), Phase::Finished);
+        assert_eq!(Phase::Charging.next(100, false, 5), Phase::Finished);
+
+        // Ended calibrations stay ended.
+        assert_eq!(Phase::Cancelled.next(0, false, 5), Phase::Cancelled);
+        assert!(Phase::Charging.running() && !Phase::Finished.running());
+
+        let calibration = Calibration { phase: Phase::Charging, ..Calibration::default() };
+        let status = calibration.status(&CalibrationConfig::default());
+        assert_eq!((status.phase.as_str(), status.low_percent), ("charging", 5));
+    }
+
+    #[test]
+    fn config() {
+        let config: CalibrationConfig = toml::from_str("low_percent = 10").unwrap();
+        assert!(config.validate().is_ok());
+        let config = CalibrationConfig { low_percent: 0 };
+        assert_eq!(config.validate().unwrap_err().0, "calibration.low_percent");
+        let config = CalibrationConfig { low_percent: 51 };
+        assert_eq!(config.validate().unwrap_err().0, "calibration.low_percent");
+    }
+}
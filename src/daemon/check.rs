@@ -0,0 +1,284 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! `daemon --check` verifies what the daemon relies on, without starting it, and prints a line
+//! per check. Checks which need root are skipped, and said so, when run as another user.
+
+use std::{fmt, fs, os::unix::ffi::OsStrExt, path::Path};
+
+use crate::{
+    charge_thresholds::{get_charge_profiles, get_charge_thresholds_status},
+    conflicts,
+    graphics::{Graphics, UPDATE_DRACUT_CMD},
+    nvidia::{self, DriverVersions},
+    state::STATE_PATH,
+    util::find_command,
+    DBUS_NAME,
+};
+
+use super::bus;
+
+// Virtual machines may not provide DMI data, which only selects quirks, so it is not required.
+const SYSFS_PATHS: [(&str, &str, bool); 3] = [
+    ("/sys/class/dmi/id", "DMI", false),
+    ("/sys/bus/pci/devices", "PCI bus", true),
+    ("/sys/class/power_supply", "Power supplies", true),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+    /// Not checked, as it requires root.
+    Skip,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Outcome::Pass => "pass",
+            Outcome::Warn => "warn",
+            Outcome::Fail => "FAIL",
+            Outcome::Skip => "skip",
+        })
+    }
+}
+
+struct Check {
+    name:    &'static str,
+    outcome: Outcome,
+    detail:  String,
+}
+
+impl Check {
+    fn new(name: &'static str, outcome: Outcome, detail: impl Into<String>) -> Self {
+        Self { name, outcome, detail: detail.into() }
+    }
+}
+
+/// Runs every check, failing if any of them failed.
+#[tokio::main(flavor = "current_thread")]
+pub async fn check() -> anyhow::Result<()> {
+    let root = unsafe { libc::geteuid() } == 0;
+
+    let mut checks = bus_checks(root).await;
+    checks.extend(
+        SYSFS_PATHS.iter().map(|&(path, name, required)| sysfs_check(path, name, required)),
+    );
+    checks.push(state_check(root));
+    checks.push(initramfs_check());
+    checks.push(charge_thresholds_check());
+    checks.extend(power_manager_checks());
+    checks.extend(graphics_checks());
+
+    for check in &checks {
+        println!("{:4} {}: {}", check.outcome, check.name, check.detail);
+    }
+
+    if !root && checks.iter().any(|check| check.outcome == Outcome::Skip) {
+        println!("Run as root to perform the skipped checks");
+    }
+
+    match checks.iter().filter(|check| check.outcome == Outcome::Fail).count() {
+        0 => Ok(()),
+        failed => Err(anyhow::anyhow!("{} of {} checks failed", failed, checks.len())),
+    }
+}
+
+/// Whether the system bus is reachable, and the name of the daemon can be owned.
+async fn bus_checks(root: bool) -> Vec<Check> {
+    let connection = match bus() {
+        Ok(builder) => builder.build().await,
+        Err(why) => Err(why),
+    };
+
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(why) => {
+            return vec![
+                Check::new("System bus", Outcome::Fail, format!("unreachable: {}", why)),
+                Check::new("Bus name", Outcome::Skip, "the system bus is unreachable"),
+            ];
+        }
+    };
+
+    let owned = match zbus::fdo::DBusProxy::new(&connection).await {
+        Ok(proxy) => match DBUS_NAME.try_into() {
+            Ok(name) => proxy.name_has_owner(name).await.map_err(zbus::Error::from),
+            Err(why) => Err(zbus::Error::from(why)),
+        },
+        Err(why) => Err(why),
+    };
+
+    let name = match owned {
+        Ok(true) => Check::new(
+            "Bus name",
+            Outcome::Warn,
+            format!("{} is owned, the daemon may already be running", DBUS_NAME),
+        ),
+        Ok(false) if !root => Check::new(
+            "Bus name",
+            Outcome::Skip,
+            format!("{} is free, but owning it requires root", DBUS_NAME),
+        ),
+        // The name is released as soon as the connection is dropped.
+        Ok(false) => match bus().and_then(|builder| builder.name(DBUS_NAME)) {
+            Ok(builder) => match builder.build().await {
+                Ok(_) => {
+                    Check::new("Bus name", Outcome::Pass, format!("{} can be owned", DBUS_NAME))
+                }
+                Err(why) => Check::new(
+                    "Bus name",
+                    Outcome::Fail,
+                    format!("{} cannot be owned: {}", DBUS_NAME, why),
+                ),
+            },
+            Err(why) => Check::new("Bus name", Outcome::Fail, why.to_string()),
+        },
+        Err(why) => Check::new("Bus name", Outcome::Fail, format!("failed to query: {}", why)),
+    };
+
+    vec![Check::new("System bus", Outcome::Pass, "reachable"), name]
+}
+
+fn sysfs_check(path: &'static str, name: &'static str, required: bool) -> Check {
+    match fs::read_dir(path) {
+        Ok(_) => Check::new(name, Outcome::Pass, path),
+        Err(why) => Check::new(
+            name,
+            if required { Outcome::Fail } else { Outcome::Warn },
+            format!("{}: {}", path, why),
+        ),
+    }
+}
+
+/// Whether the state of the previous run can be saved.
+fn state_check(root: bool) -> Check {
+    let dir = Path::new(STATE_PATH).parent().unwrap_or(Path::new("/"));
+    if !root {
+        return Check::new("State directory", Outcome::Skip, "writing it requires root");
+    }
+
+    // The directory is created on the first save.
+    let existing = dir.ancestors().find(|dir| dir.exists()).unwrap_or(Path::new("/"));
+    let path = std::ffi::CString::new(existing.as_os_str().as_bytes()).unwrap_or_default();
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+        Check::new("State directory", Outcome::Pass, dir.display().to_string())
+    } else {
+        Check::new(
+            "State directory",
+            Outcome::Warn,
+            format!("{} is not writable, settings will not persist", existing.display()),
+        )
+    }
+}
+
+fn initramfs_check() -> Check {
+    match find_command(UPDATE_DRACUT_CMD) {
+        Some(path) => Check::new("Initramfs tool", Outcome::Pass, path.display().to_string()),
+        None => Check::new(
+            "Initramfs tool",
+            Outcome::Warn,
+            format!(
+                "{} not found, graphics switches cannot rebuild the initramfs",
+                UPDATE_DRACUT_CMD
+            ),
+        ),
+    }
+}
+
+fn charge_thresholds_check() -> Check {
+    let status = match get_charge_thresholds_status(&get_charge_profiles()) {
+        Ok(status) => status,
+        Err(why) => return Check::new("Charge thresholds", Outcome::Fail, why.to_string()),
+    };
+
+    if !status.supported {
+        return Check::new("Charge thresholds", Outcome::Warn, "not supported on this machine");
+    }
+
+    let batteries = status.batteries.keys().cloned().collect::<Vec<_>>().join(", ");
+    if status.start_supported {
+        Check::new("Charge thresholds", Outcome::Pass, batteries)
+    } else {
+        Check::new("Charge thresholds", Outcome::Warn, format!("{}, end threshold only", batteries))
+    }
+}
+
+/// Whether other power managers run, which also set parameters of the profiles.
+fn power_manager_checks() -> Vec<Check> {
+    let managers = conflicts::detect(false);
+    if managers.is_empty() {
+        return vec![Check::new("Power managers", Outcome::Pass, "none conflicting")];
+    }
+
+    managers
+        .into_iter()
+        .map(|manager| {
+            let parameters = manager.parameters.join(", ");
+            let detail = format!("{} {}: {}", manager.service, manager.reason, parameters);
+            Check::new("Power managers", Outcome::Warn, detail)
+        })
+        .collect()
+}
+
+/// Whether the GPUs have drivers, and the parts of the NVIDIA driver agree.
+fn graphics_checks() -> Vec<Check> {
+    let devices = match Graphics::devices() {
+        Ok(devices) => devices,
+        Err(why) => return vec![Check::new("GPUs", Outcome::Fail, why.to_string())],
+    };
+
+    let mut checks = Vec::new();
+    for device in &devices {
+        let name = match device.vendor_id {
+            0x10DE => "NVIDIA GPU",
+            0x1002 => "AMD GPU",
+            0x8086 => "Intel GPU",
+            _ => "GPU",
+        };
+
+        checks.push(if device.driver.is_empty() {
+            // The dGPU has no driver in integrated mode.
+            Check::new(name, Outcome::Warn, format!("{} has no driver bound", device.bus_id))
+        } else {
+            Check::new(name, Outcome::Pass, format!("{} uses {}", device.bus_id, device.driver))
+        });
+    }
+
+    if !devices.iter().any(|device| device.vendor_id == 0x10DE) {
+        return checks;
+    }
+
+    let versions = DriverVersions::probe();
+    if let Some((kernel, userspace)) = versions.mismatch() {
+        checks.push(Check::new(
+            "NVIDIA driver",
+            Outcome::Fail,
+            format!("kernel module {} but userspace {}, reboot after updating", kernel, userspace),
+        ));
+        return checks;
+    }
+
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+    let release = release.trim();
+    let module = nvidia::kernel_modules().remove(release).unwrap_or_default();
+
+    checks.push(match (module.present, versions.userspace) {
+        (true, _) => Check::new(
+            "NVIDIA driver",
+            Outcome::Pass,
+            format!("{} for {}", module.version.as_deref().unwrap_or("unknown version"), release),
+        ),
+        (false, Some(userspace)) => Check::new(
+            "NVIDIA driver",
+            Outcome::Fail,
+            format!("userspace {} is installed, but no kernel module for {}", userspace, release),
+        ),
+        (false, None) => Check::new("NVIDIA driver", Outcome::Warn, "not installed"),
+    });
+
+    checks
+}
@@ -0,0 +1,193 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Changes made by the client itself with `--direct`, as root, when the daemon is not running.
+//! They are made as the daemon makes them, and saved to be restored when it starts, but no
+//! signals are emitted. The scripts of profile changes run before the client exits.
+
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    charge_thresholds::{self, BatteryWrites},
+    config,
+    graphics::{Graphics, GraphicsMode, LastSwitch, GRAPHICS_CONFIG},
+    state::{State, STATE_PATH},
+    tunables, DBUS_NAME,
+};
+
+use system76_power_zbus::ProfileFailure;
+
+use super::{
+    apply_graphics_power, bus, error::PowerError, hooks, parse_profile, profiles,
+    profiles::Applied, settings::DaemonConfig,
+};
+
+/// The error of a change, named as the daemon would name it in its reply.
+#[derive(Debug)]
+pub struct DirectError {
+    pub name:    String,
+    pub message: String,
+}
+
+impl fmt::Display for DirectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(&self.message) }
+}
+
+impl std::error::Error for DirectError {}
+
+impl From<PowerError> for DirectError {
+    fn from(why: PowerError) -> Self { Self { name: why.error_name(), message: why.message() } }
+}
+
+/// Whether the daemon owns its name on the system bus. Without a bus, it cannot be running.
+pub async fn daemon_running() -> bool {
+    let connection = match bus() {
+        Ok(builder) => builder.build().await,
+        Err(why) => Err(why),
+    };
+
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(why) => {
+            log::debug!("not checking for the daemon, as the system bus is unreachable: {}", why);
+            return false;
+        }
+    };
+
+    let owned = match zbus::fdo::DBusProxy::new(&connection).await {
+        Ok(proxy) => match DBUS_NAME.try_into() {
+            Ok(name) => proxy.name_has_owner(name).await.map_err(zbus::Error::from),
+            Err(why) => Err(zbus::Error::from(why)),
+        },
+        Err(why) => Err(why),
+    };
+
+    owned.unwrap_or_else(|why| {
+        log::warn!("failed to check whether {} is running: {}", DBUS_NAME, why);
+        false
+    })
+}
+
+/// Applies a profile, built in or custom, saving it unless `temporary`, and returns the
+/// parameters which failed, if most others were set.
+pub fn set_profile(name: &str, temporary: bool) -> Result<Vec<ProfileFailure>, DirectError> {
+    if let Err(why) = tunables::load() {
+        log::warn!("using the built-in profile tunables: {}", why);
+    }
+
+    let profile = parse_profile(name)?;
+    log::info!(operation = "set_profile", profile = name; "Setting profile {}", name);
+    let mut applied = Applied::default();
+    profiles::set(&profile, &mut applied, false);
+
+    let failures =
+        applied.outcome(name).map_err(|why| PowerError::ProfileFailed(why.to_string()))?;
+
+    if !temporary {
+        remember(|state| state.profile = Some(name.to_owned()));
+    }
+
+    // The profile set before is unknown without the daemon.
+    let config = DaemonConfig::load().unwrap_or_else(|why| {
+        log::warn!("using default daemon config: {}", why);
+        DaemonConfig::default()
+    });
+    hooks::run_now(&config.hooks, "", name, "direct");
+
+    Ok(failures)
+}
+
+/// Switches the graphics mode, reporting each step with its percentage of completion, and
+/// returns the message the daemon would finish its job with.
+pub fn set_graphics(
+    mode: GraphicsMode,
+    force: bool,
+    skip_initramfs: bool,
+    progress: impl FnMut(&str, u32),
+) -> Result<String, DirectError> {
+    let mut graphics = graphics()?;
+    graphics.strict_driver_check = DaemonConfig::load()
+        .map(|config| config.graphics.strict_driver_check)
+        .unwrap_or_else(|why| {
+            log::warn!("using default daemon config: {}", why);
+            false
+        });
+    match config::load(GRAPHICS_CONFIG) {
+        Ok(config) => graphics.config = config,
+        Err(why) => log::warn!("using default graphics config: {}", why),
+    }
+
+    let result = graphics.set_vendor(mode, force, skip_initramfs, progress);
+    let rebuild = match &result {
+        Ok(rebuild) => rebuild.clone(),
+        Err(why) => why.initramfs_rebuild().cloned(),
+    };
+
+    let name = <&'static str>::from(mode);
+    let result = result
+        .map(|_| match graphics.get_vendor() {
+            Ok(active) if active == mode => format!("switched to {}", name),
+            _ => format!("switched to {}, reboot required", name),
+        })
+        .map_err(PowerError::from);
+
+    let record = LastSwitch {
+        mode:      name.to_owned(),
+        time:      SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+        success:   result.is_ok(),
+        message:   result.as_ref().map_or_else(PowerError::message, Clone::clone),
+        initramfs: rebuild,
+    };
+
+    if let Err(why) = record.save() {
+        log::warn!("failed to record the graphics switch: {}", why);
+    }
+
+    result.map_err(DirectError::from)
+}
+
+/// Sets the power of the discrete GPU to `on`, `off` or `auto`, saving it, and returns
+/// whether it is on, and the actions taken only because of `force`.
+pub fn set_graphics_power(state: &str, force: bool) -> Result<(bool, Vec<String>), DirectError> {
+    let graphics = graphics()?;
+    let (power, forced) = if force {
+        (false, graphics.power_off(true).map_err(PowerError::from)?)
+    } else {
+        (apply_graphics_power(&graphics, state)?, Vec::new())
+    };
+
+    remember(|saved| saved.graphics_power = Some(state.to_owned()));
+    Ok((power, forced))
+}
+
+/// Sets the charge thresholds of `battery`, or of every battery if `None`, saving them.
+/// Returns the thresholds applied to each battery set, and the batteries which failed while
+/// the others were set.
+pub fn set_charge_thresholds(
+    thresholds: (u8, u8),
+    battery: Option<&str>,
+) -> Result<BatteryWrites, DirectError> {
+    let writes =
+        charge_thresholds::set_charge_thresholds(thresholds, battery).map_err(PowerError::from)?;
+    remember(|state| state.set_charge_thresholds(battery, thresholds));
+    Ok(writes)
+}
+
+fn graphics() -> Result<Graphics, DirectError> {
+    Graphics::new()
+        .map_err(|why| PowerError::Failed(format!("failed to rescan the PCI bus: {}", why)).into())
+}
+
+/// Saves a change to the state the daemon restores when it starts.
+fn remember(change: impl FnOnce(&mut State)) {
+    let mut state = State::load();
+    change(&mut state);
+
+    if let Err(why) = state.save() {
+        log::warn!("Failed to save daemon state to {}: {}", STATE_PATH, why);
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Errors returned to clients, named so that they can be told apart without parsing the
+//! message. The mapping is documented in the README.
+
+use crate::{charge_thresholds::ChargeThresholdError, graphics::GraphicsDeviceError};
+
+#[derive(Debug, zbus::DBusError)]
+#[dbus_error(prefix = "com.system76.PowerDaemon.Error")]
+pub(super) enum PowerError {
+    #[dbus_error(zbus_error)]
+    ZBus(zbus::Error),
+    /// The device lacks either an integrated or a discrete GPU.
+    NotSwitchable(String),
+    /// Processes or drivers are using the discrete GPU.
+    DeviceInUse(String),
+    /// The initramfs could not be rebuilt. The message ends with the output of the tool.
+    InitramfsFailed(String),
+    /// The graphics mode is not one of `integrated`, `hybrid`, `nvidia` or `compute`.
+    InvalidMode(String),
+    /// The graphics option or its value is unknown.
+    InvalidOption(String),
+    /// The NVIDIA kernel module and userspace driver versions differ.
+    DriverMismatch(String),
+    /// The hardware, firmware or driver lacks the feature.
+    Unsupported(String),
+    /// The charge thresholds are out of range, the end is not above the start, or they were
+    /// requested for a battery which does not exist.
+    InvalidThresholds(String),
+    /// Parts of the profile could not be applied.
+    ProfileFailed(String),
+    /// An operation of the same kind is already running.
+    Busy(String),
+    /// A package manager is installing or updating packages.
+    TryAgainLater(String),
+    /// The client requested more changes than its rate limit allows.
+    RateLimited(String),
+    /// Any other failure.
+    Failed(String),
+}
+
+impl PowerError {
+    /// The name the error is replied with, which for errors of zbus is their own.
+    pub(super) fn error_name(&self) -> String {
+        match self {
+            Self::ZBus(zbus::Error::FDO(why)) => zbus::DBusError::name(why.as_ref()).to_string(),
+            why => zbus::DBusError::name(why).to_string(),
+        }
+    }
+
+    /// The message of the error, without its name.
+    pub(super) fn message(&self) -> String {
+        match self {
+            Self::ZBus(zbus::Error::FDO(why)) => {
+                zbus::DBusError::description(why.as_ref()).unwrap_or_default().to_owned()
+            }
+            Self::ZBus(why) => why.to_string(),
+            why => zbus::DBusError::description(why).unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+impl From<zbus::fdo::Error> for PowerError {
+    fn from(why: zbus::fdo::Error) -> Self { Self::ZBus(why.into()) }
+}
+
+impl From<GraphicsDeviceError> for PowerError {
+    fn from(why: GraphicsDeviceError) -> Self {
+        let message = why.to_string();
+        match why {
+            GraphicsDeviceError::NotSwitchable(_) => Self::NotSwitchable(message),
+            GraphicsDeviceError::DeviceInUse { .. }
+            | GraphicsDeviceError::ComputeInUse(_)
+            | GraphicsDeviceError::ModesetEnabled => Self::DeviceInUse(message),
+            GraphicsDeviceError::UpdateDracut(rebuild) if !rebuild.stderr_tail.is_empty() => {
+                Self::InitramfsFailed(format!("{}:\n{}", message, rebuild.stderr_tail))
+            }
+            GraphicsDeviceError::UpdateDracut(_) => Self::InitramfsFailed(message),
+            GraphicsDeviceError::InvalidOptionValue { .. }
+            | GraphicsDeviceError::UnknownOption(_) => Self::InvalidOption(message),
+            GraphicsDeviceError::DriverVersionMismatch { .. } => Self::DriverMismatch(message),
+            GraphicsDeviceError::GspUnsupported(_) => Self::Unsupported(message),
+            _ => Self::Failed(message),
+        }
+    }
+}
+
+impl From<ChargeThresholdError> for PowerError {
+    fn from(why: ChargeThresholdError) -> Self {
+        let message = why.to_string();
+        match why {
+            ChargeThresholdError::Unsupported => Self::Unsupported(message),
+            ChargeThresholdError::UnknownBattery(_) => Self::InvalidThresholds(message),
+            ChargeThresholdError::OutOfRange | ChargeThresholdError::Order => {
+                Self::InvalidThresholds(message)
+            }
+            _ => Self::Failed(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::NotSwitchableReason;
+
+    fn name(why: impl Into<PowerError>) -> String { why.into().error_name() }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            name(GraphicsDeviceError::NotSwitchable(NotSwitchableReason::Desktop)),
+            "com.system76.PowerDaemon.Error.NotSwitchable"
+        );
+        assert_eq!(
+            name(GraphicsDeviceError::ComputeInUse("blender".into())),
+            "com.system76.PowerDaemon.Error.DeviceInUse"
+        );
+        assert_eq!(
+            name(ChargeThresholdError::Order),
+            "com.system76.PowerDaemon.Error.InvalidThresholds"
+        );
+        assert_eq!(
+            name(ChargeThresholdError::Unsupported),
+            "com.system76.PowerDaemon.Error.Unsupported"
+        );
+        assert_eq!(
+            name(GraphicsDeviceError::Rescan(std::io::ErrorKind::Other.into())),
+            "com.system76.PowerDaemon.Error.Failed"
+        );
+        assert_eq!(
+            name(zbus::fdo::Error::AccessDenied("denied".into())),
+            "org.freedesktop.DBus.Error.AccessDenied"
+        );
+    }
+
+    #[test]
+    fn initramfs_output() {
+        let rebuild = crate::graphics::InitramfsRebuild {
+            tool: "dracut".into(),
+            stderr_tail: "dracut: cannot install nvidia".into(),
+            ..Default::default()
+        };
+
+        let why = PowerError::from(GraphicsDeviceError::UpdateDracut(rebuild));
+        assert_eq!(why.error_name(), "com.system76.PowerDaemon.Error.InitramfsFailed");
+        assert_eq!(
+            why.message(),
+            "dracut failed to rebuild the initramfs:\ndracut: cannot install nvidia"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Profiles held by applications, such as games requesting the performance profile while
+//! they run. The profile selected before the first hold is restored after the last one is
+//! released, whether explicitly or by the application leaving the bus.
+
+use futures_lite::StreamExt;
+use system76_power_zbus::ProfileHold;
+
+use super::{System76Power, INITIATOR_SYSTEM};
+
+/// Holds of profiles, and the profile to restore once they are released.
+#[derive(Default)]
+pub(super) struct Holds {
+    next_cookie: u32,
+    holds:       Vec<ProfileHold>,
+    selected:    Option<String>,
+}
+
+impl Holds {
+    /// Adds a hold of `profile`, requested while `current` is applied, returning its cookie.
+    pub fn add(
+        &mut self,
+        current: &str,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+        sender: &str,
+    ) -> u32 {
+        if self.holds.is_empty() {
+            self.selected = Some(current.to_owned());
+        }
+
+        let cookie = self.next_cookie;
+        self.next_cookie = self.next_cookie.wrapping_add(1);
+
+        self.holds.push(ProfileHold {
+            cookie,
+            profile: profile.to_owned(),
+            reason: reason.to_owned(),
+            application_id: application_id.to_owned(),
+            sender: sender.to_owned(),
+        });
+
+        cookie
+    }
+
+    /// Releases the holds matching `release`, returning their cookies.
+    pub fn release(&mut self, release: impl Fn(&ProfileHold) -> bool) -> Vec<u32> {
+        let mut released = Vec::new();
+        self.holds.retain(|hold| {
+            let matches = release(hold);
+            if matches {
+                released.push(hold.cookie);
+            }
+            !matches
+        });
+
+        released
+    }
+
+    /// Releases every hold, as a profile was selected which replaces the one to restore.
+    pub fn clear(&mut self) -> Vec<u32> {
+        self.selected = None;
+        self.release(|_| true)
+    }
+
+    /// Sets the profile to restore once the holds are released, if any are active.
+    ///
+    /// Returns `false` if there are no holds, in which case the profile should be applied.
+    pub fn select(&mut self, profile: &str) -> bool {
+        if self.holds.is_empty() {
+            return false;
+        }
+
+        self.selected = Some(profile.to_owned());
+        true
+    }
+
+    /// The profile to apply: the held profile of highest priority, or the selected profile
+    /// once no holds remain. Battery takes priority over Performance, which takes priority
+    /// over Quiet and Balanced.
+    pub fn effective(&mut self) -> Option<String> {
+        let priority = |profile: &str| match profile {
+            "Battery" => 2,
+            "Performance" => 1,
+            _ => 0,
+        };
+
+        match self.holds.iter().max_by_key(|hold| priority(&hold.profile)) {
+            Some(hold) => Some(hold.profile.clone()),
+            None => self.selected.take(),
+        }
+    }
+
+    pub fn active(&self) -> &[ProfileHold] { &self.holds }
+}
+
+/// Releases the holds of clients as they leave the bus.
+pub(super) async fn release_on_disconnect(
+    daemon: System76Power,
+    connection: zbus::Connection,
+    context: zbus::SignalContext<'static>,
+) {
+    let changes = match zbus::fdo::DBusProxy::new(&connection).await {
+        Ok(proxy) => proxy.receive_name_owner_changed().await,
+        Err(why) => Err(why),
+    };
+
+    let mut changes = match changes {
+        Ok(changes) => changes,
+        Err(why) => {
+            log::warn!("Not releasing profile holds of clients leaving the bus: {}", why);
+            return;
+        }
+    };
+
+    while let Some(change) = changes.next().await {
+        let Ok(args) = change.args() else { continue };
+        if args.new_owner().is_some() || !args.name().starts_with(':') {
+            continue;
+        }
+
+        let name = args.name().as_str();
+        daemon.release(&context, |hold| hold.sender == name, INITIATOR_SYSTEM).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_and_restore() {
+        let mut holds = Holds::default();
+
+        let game = holds.add("Balanced", "Performance", "playing", "game", ":1.1");
+        assert_eq!(holds.effective().as_deref(), Some("Performance"));
+
+        let saver = holds.add("Performance", "Battery", "low battery", "applet", ":1.2");
+        assert_ne!(game, saver);
+        assert_eq!(holds.effective().as_deref(), Some("Battery"));
+
+        // Leaving the bus releases the holds of the client.
+        assert_eq!(holds.release(|hold| hold.sender == ":1.2"), vec![saver]);
+        assert_eq!(holds.effective().as_deref(), Some("Performance"));
+
+        // The profile selected before the first hold is restored.
+        assert_eq!(holds.release(|hold| hold.cookie == game), vec![game]);
+        assert_eq!(holds.effective().as_deref(), Some("Balanced"));
+        assert_eq!(holds.effective(), None);
+        assert!(holds.active().is_empty());
+    }
+
+    #[test]
+    fn selection_while_held() {
+        let mut holds = Holds::default();
+        assert!(!holds.select("Battery"));
+
+        let cookie = holds.add("Balanced", "Performance", "", "", ":1.1");
+        assert!(holds.select("Battery"));
+        assert_eq!(holds.effective().as_deref(), Some("Performance"));
+
+        holds.release(|hold| hold.cookie == cookie);
+        assert_eq!(holds.effective().as_deref(), Some("Battery"));
+
+        holds.add("Balanced", "Performance", "", "", ":1.1");
+        assert_eq!(holds.clear().len(), 1);
+        assert_eq!(holds.effective(), None);
+    }
+}
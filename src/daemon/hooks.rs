@@ -0,0 +1,277 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Scripts run after each change of the profile, from `/etc/system76-power/profile.d`. They
+//! run in the background, one at a time in the order of their file names, and changes are
+//! handled in the order they were made. Their output goes to the log, and their failures are
+//! logged without failing the change. As they run as root, scripts which root does not own,
+//! or which their group or others may write, are skipped.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+const HOOKS_DIR: &str = "/etc/system76-power/profile.d";
+
+/// The `[hooks]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct HooksConfig {
+    pub enabled:      bool,
+    /// How long each script may run before it is killed.
+    pub timeout_secs: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self { Self { enabled: true, timeout_secs: 30 } }
+}
+
+/// A change of the profile, passed to the scripts.
+#[derive(Clone, Debug)]
+struct Change {
+    old:       String,
+    new:       String,
+    initiator: String,
+    timeout:   Duration,
+}
+
+/// How a script ended.
+#[derive(Debug)]
+enum Outcome {
+    Exited(ExitStatus),
+    TimedOut,
+    Failed(io::Error),
+}
+
+// Changes waiting for their scripts, which a single task runs in order.
+static QUEUE: Lazy<UnboundedSender<Change>> = Lazy::new(|| {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(run_queue(receiver));
+    sender
+});
+
+impl Change {
+    /// The change, unless the scripts are disabled.
+    fn new(config: &HooksConfig, old: &str, new: &str, initiator: &str) -> Option<Self> {
+        config.enabled.then(|| Self {
+            old:       old.to_owned(),
+            new:       new.to_owned(),
+            initiator: initiator.to_owned(),
+            timeout:   Duration::from_secs(config.timeout_secs),
+        })
+    }
+}
+
+/// Queues the scripts for a change of the profile, unless they are disabled.
+pub(super) fn profile_changed(config: &HooksConfig, old: &str, new: &str, initiator: &str) {
+    let Some(change) = Change::new(config, old, new, initiator) else { return };
+
+    if QUEUE.send(change).is_err() {
+        log::warn!("Failed to queue the scripts of {}", HOOKS_DIR);
+    }
+}
+
+/// Runs the scripts for a change of the profile made without the daemon, unless they are
+/// disabled, and waits for them, as the process exits once the change is made.
+pub(super) fn run_now(config: &HooksConfig, old: &str, new: &str, initiator: &str) {
+    let Some(change) = Change::new(config, old, new, initiator) else { return };
+
+    // On a thread of its own, as the caller may already be running on a runtime.
+    let finished = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        runtime.block_on(run_scripts(&change));
+        Ok::<_, io::Error>(())
+    })
+    .join();
+
+    match finished {
+        Ok(Ok(())) => (),
+        Ok(Err(why)) => log::warn!("Failed to run the scripts of {}: {}", HOOKS_DIR, why),
+        Err(_) => log::warn!("Failed to run the scripts of {}", HOOKS_DIR),
+    }
+}
+
+async fn run_queue(mut receiver: UnboundedReceiver<Change>) {
+    while let Some(change) = receiver.recv().await {
+        run_scripts(&change).await;
+    }
+}
+
+async fn run_scripts(change: &Change) {
+    for script in scripts(Path::new(HOOKS_DIR), 0) {
+        let name = script.display();
+        match run(&script, change).await {
+            Outcome::Exited(status) if status.success() => {
+                log::debug!("{} finished", name);
+            }
+            Outcome::Exited(status) => log::warn!("{} failed: {}", name, status),
+            Outcome::TimedOut => {
+                log::warn!("{} killed after {} s", name, change.timeout.as_secs());
+            }
+            Outcome::Failed(why) => log::warn!("failed to run {}: {}", name, why),
+        }
+    }
+}
+
+/// The executable files of the directory, ordered by name. Hidden files and backups, ending
+/// with `~`, are skipped, as are files which `owner` does not own, or which the group or
+/// others may write.
+fn scripts(dir: &Path, owner: u32) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut scripts: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && !name.ends_with('~')
+        })
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let Ok(meta) = fs::metadata(path) else { return false };
+            if !meta.is_file() || meta.permissions().mode() & 0o111 == 0 {
+                log::debug!("skipping {}, which is not an executable file", path.display());
+                return false;
+            }
+
+            let trusted = meta.uid() == owner && meta.mode() & 0o022 == 0;
+            if !trusted {
+                log::warn!(
+                    "skipping {}, which is not owned by uid {}, or is writable by others",
+                    path.display(),
+                    owner
+                );
+            }
+            trusted
+        })
+        .collect();
+
+    scripts.sort();
+    scripts
+}
+
+/// Runs a script with the old and new profiles as arguments, and in the environment, logging
+/// each line of its output.
+async fn run(script: &Path, change: &Change) -> Outcome {
+    let mut command = Command::new(script);
+    command
+        .args([&change.old, &change.new])
+        .env("SYSTEM76_POWER_OLD_PROFILE", &change.old)
+        .env("SYSTEM76_POWER_PROFILE", &change.new)
+        .env("SYSTEM76_POWER_INITIATOR", &change.initiator)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(why) => return Outcome::Failed(why),
+    };
+
+    let name = script
+        .file_name()
+        .map_or_else(|| script.display().to_string(), |name| name.to_string_lossy().into_owned());
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let finished = tokio::time::timeout(change.timeout, async {
+        tokio::join!(log_lines(&name, stdout, false), log_lines(&name, stderr, true));
+        child.wait().await
+    })
+    .await;
+
+    match finished {
+        Ok(Ok(status)) => Outcome::Exited(status),
+        Ok(Err(why)) => Outcome::Failed(why),
+        Err(_) => {
+            let _res = child.kill().await;
+            Outcome::TimedOut
+        }
+    }
+}
+
+async fn log_lines(name: &str, output: Option<impl AsyncRead + Unpin>, stderr: bool) {
+    let Some(output) = output else {
+        return;
+    };
+
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if stderr {
+            log::warn!("{}: {}", name, line);
+        } else {
+            log::info!("{}: {}", name, line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[tokio::test]
+    async fn hooks() {
+        let root = TempDir::new("hooks");
+        let args = root.join("args");
+        let write = |name: &str, script: &str, mode: u32| {
+            let path = root.join(name);
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            path
+        };
+
+        let record = format!(
+            "#!/bin/sh\necho \"$1 $2 $SYSTEM76_POWER_OLD_PROFILE $SYSTEM76_POWER_PROFILE \
+             $SYSTEM76_POWER_INITIATOR\" > {}\n",
+            args.display()
+        );
+        let second = write("20-record", &record, 0o755);
+        let first = write("10-fail", "#!/bin/sh\necho failing >&2\nexit 3\n", 0o755);
+        let slow = write("30-slow", "#!/bin/sh\nsleep 5\n", 0o755);
+        write("40-disabled", "#!/bin/sh\n", 0o644);
+        write(".hidden", "#!/bin/sh\n", 0o755);
+        write("50-backup~", "#!/bin/sh\n", 0o755);
+        write("70-group-writable", "#!/bin/sh\n", 0o775);
+        write("80-world-writable", "#!/bin/sh\n", 0o757);
+        fs::create_dir(root.join("60-dir")).unwrap();
+
+        let owner = fs::metadata(&root).unwrap().uid();
+        assert_eq!(scripts(&root, owner), [first.clone(), second.clone(), slow.clone()]);
+        assert!(scripts(&root, owner + 1).is_empty());
+        assert!(scripts(&root.join("missing"), owner).is_empty());
+
+        let change = Change {
+            old:       "Balanced".into(),
+            new:       "Battery".into(),
+            initiator: "power-source".into(),
+            timeout:   Duration::from_millis(500),
+        };
+
+        assert!(matches!(run(&second, &change).await, Outcome::Exited(status) if status.success()));
+        assert_eq!(
+            fs::read_to_string(&args).unwrap(),
+            "Balanced Battery Balanced Battery power-source\n"
+        );
+
+        let outcome = run(&first, &change).await;
+        assert!(matches!(outcome, Outcome::Exited(status) if status.code() == Some(3)));
+        assert!(matches!(run(&slow, &change).await, Outcome::TimedOut));
+        assert!(matches!(run(&root.join("missing"), &change).await, Outcome::Failed(_)));
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Switching to a lower profile while every session is idle, as reported by logind, and back
+//! on activity. A profile set by anything else during an idle period cancels the switch until
+//! the next idle period.
+
+use futures_lite::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+use crate::{tunables, Profile};
+
+use super::{auto_profile, sleep::LoginManagerProxy, System76Power};
+
+// Initiator of profile changes made on idle and on activity.
+pub(super) const INITIATOR_IDLE: &str = "idle";
+
+// Longest wait between checks while profiles are held, which prevent the switch.
+const HELD_RECHECK: Duration = Duration::from_secs(60);
+
+// Wakes the watch when the settings change.
+static WAKE: Notify = Notify::const_new();
+
+/// The `[idle]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct IdleConfig {
+    pub enabled:    bool,
+    /// Seconds every session must be idle for before switching.
+    pub after_secs: u64,
+    /// Profile applied while idle: battery, quiet, balanced, performance or a custom profile.
+    pub profile:    String,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self { Self { enabled: false, after_secs: 1800, profile: "balanced".into() } }
+}
+
+impl IdleConfig {
+    /// Name of the profile applied while idle, as known to the daemon.
+    pub fn profile(&self) -> Result<String, String> { auto_profile::profile_name(&self.profile) }
+}
+
+/// Progress of the current idle period.
+#[derive(Default)]
+pub(super) struct Idle {
+    idle:      bool,
+    /// Profile to restore on activity, once the idle profile was applied.
+    restore:   Option<String>,
+    /// Whether a profile was set during this idle period.
+    cancelled: bool,
+}
+
+impl Idle {
+    /// Notes a profile set by anything but the idle switching, which then leaves the profile
+    /// alone until the next idle period.
+    pub fn profile_set(&mut self) {
+        if self.idle {
+            self.cancelled = true;
+            self.restore = None;
+        }
+    }
+}
+
+/// What to do on a change of the idle state.
+enum Switch {
+    Idle(String),
+    Restore(String),
+}
+
+/// Orders profiles by their power use, which is that of their base for custom profiles.
+fn rank(profile: &str) -> u8 {
+    match tunables::find(profile).map(|profile| profile.base) {
+        Some(Profile::Battery) => 0,
+        Some(Profile::Quiet) => 1,
+        Some(Profile::Balanced) => 2,
+        _ => 3,
+    }
+}
+
+impl System76Power {
+    /// Switches profile according to the idle state, returning how long to wait before the
+    /// next check, or `None` to wait for the idle state to change.
+    async fn refresh_idle(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        idle: bool,
+        idle_for: Duration,
+    ) -> Option<Duration> {
+        let switch = {
+            let mut this = self.0.lock().await;
+            let this = &mut *this;
+            let config = &this.config.idle;
+
+            if !(idle && config.enabled) {
+                this.idle.idle = false;
+                this.idle.cancelled = false;
+                Switch::Restore(this.idle.restore.take()?)
+            } else {
+                this.idle.idle = true;
+                if this.idle.cancelled || this.idle.restore.is_some() {
+                    return None;
+                }
+
+                let after = Duration::from_secs(config.after_secs);
+                if idle_for < after {
+                    return Some(after - idle_for);
+                }
+
+                let profile = match config.profile() {
+                    Ok(profile) => profile,
+                    Err(why) => {
+                        log::warn!("Not switching profile while idle: {}", why);
+                        return None;
+                    }
+                };
+
+                if rank(&profile) >= rank(&this.power_profile) {
+                    return None;
+                }
+
+                if !this.holds.active().is_empty() {
+                    return Some(HELD_RECHECK);
+                }
+
+                log::info!("Idle for {}s, switching to {} profile", idle_for.as_secs(), profile);
+                this.idle.restore = Some(this.power_profile.clone());
+                Switch::Idle(profile)
+            }
+        };
+
+        let profile = match switch {
+            Switch::Idle(profile) => profile,
+            Switch::Restore(profile) => {
+                log::info!("No longer idle, restoring {} profile", profile);
+                let mut this = self.0.lock().await;
+                if this.low_battery.defer(&profile) {
+                    return None;
+                }
+
+                if this.holds.select(&profile) {
+                    log::info!("Profiles are held, restoring once they are released");
+                    return None;
+                }
+
+                profile
+            }
+        };
+
+        if let Some(profile) = tunables::find(&profile) {
+            if let Err(why) = self.set_profile(context, &profile, INITIATOR_IDLE).await {
+                log::warn!("Failed to switch profile on idle: {}", why);
+            }
+        }
+
+        None
+    }
+}
+
+/// Wakes the watch, to apply changed settings.
+pub(super) fn settings_changed() { WAKE.notify_one(); }
+
+/// Switches profile as the sessions become idle and active.
+pub(super) async fn watch(
+    daemon: System76Power,
+    connection: zbus::Connection,
+    context: zbus::SignalContext<'static>,
+) {
+    let manager = match LoginManagerProxy::new(&connection).await {
+        Ok(manager) => manager,
+        Err(why) => {
+            log::warn!("Not switching profile while idle, logind is unavailable: {}", why);
+            return;
+        }
+    };
+
+    let mut changes = manager.receive_idle_hint_changed().await;
+
+    loop {
+        let idle = manager.idle_hint().await.unwrap_or(false);
+        let since = manager.idle_since_hint().await.unwrap_or_default();
+        let idle_for = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_micros(since))
+            .unwrap_or_default();
+
+        let wait = daemon.refresh_idle(&context, idle, idle_for).await;
+
+        tokio::select! {
+            _ = changes.next() => (),
+            () = WAKE.notified() => (),
+            () = tokio::time::sleep(wait.unwrap_or(Duration::MAX)), if wait.is_some() => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelled_by_profile_changes() {
+        let mut idle = Idle::default();
+
+        // Profiles set while active are not idle switching's concern.
+        idle.profile_set();
+        assert!(!idle.cancelled);
+
+        idle.idle = true;
+        idle.restore = Some("Performance".into());
+        idle.profile_set();
+        assert!(idle.cancelled);
+        assert_eq!(idle.restore, None);
+    }
+
+    #[test]
+    fn config() {
+        let config: IdleConfig = toml::from_str("enabled = true\nprofile = \"battery\"").unwrap();
+        assert_eq!(config.after_secs, 1800);
+        assert_eq!(config.profile().as_deref(), Ok("Battery"));
+        assert!(rank("Battery") < rank("Quiet") && rank("Quiet") < rank("Balanced"));
+        assert!(rank("Balanced") < rank("Performance"));
+    }
+}
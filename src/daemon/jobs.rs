@@ -0,0 +1,162 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Operations which may outlast a DBus method call are run as jobs, each served at its own
+//! object path, where it announces its progress and its outcome.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+use system76_power_zbus::JobStatus;
+use zvariant::OwnedObjectPath;
+
+use super::{error::PowerError, STATUS_IDLE};
+use crate::{sd_notify, DBUS_PATH};
+
+// Number of finished jobs which remain queryable.
+const FINISHED_JOBS: usize = 16;
+
+static JOBS: Mutex<Jobs> = Mutex::new(Jobs { next_id: 0, jobs: BTreeMap::new() });
+
+struct Jobs {
+    next_id: u32,
+    jobs:    BTreeMap<u32, (OwnedObjectPath, Arc<Mutex<JobStatus>>)>,
+}
+
+/// The DBus object of a job.
+struct JobObject;
+
+#[zbus::dbus_interface(name = "com.system76.PowerDaemon.Job")]
+impl JobObject {
+    /// `percent` is the completion of the job when `step` started.
+    #[dbus_interface(signal)]
+    async fn progress(
+        context: &zbus::SignalContext<'_>,
+        step: &str,
+        percent: u32,
+    ) -> zbus::Result<()>;
+
+    /// `message` describes the outcome, such as the error of a failed job.
+    #[dbus_interface(signal)]
+    async fn finished(
+        context: &zbus::SignalContext<'_>,
+        success: bool,
+        message: &str,
+    ) -> zbus::Result<()>;
+}
+
+pub(super) struct Job {
+    context: zbus::SignalContext<'static>,
+    status:  Arc<Mutex<JobStatus>>,
+}
+
+impl Job {
+    /// Starts a job, unless one of the same kind is running.
+    pub(super) async fn start(
+        connection: &zbus::Connection,
+        kind: &str,
+    ) -> Result<Self, PowerError> {
+        let (id, path, status) = {
+            let mut jobs = JOBS.lock().unwrap_or_else(PoisonError::into_inner);
+
+            let running = jobs.jobs.values().any(|(_, status)| {
+                let status = status.lock().unwrap_or_else(PoisonError::into_inner);
+                status.kind == kind && !status.finished
+            });
+
+            if running {
+                return Err(PowerError::Busy(format!("a {} job is already running", kind)));
+            }
+
+            jobs.next_id += 1;
+            let id = jobs.next_id;
+            let path = OwnedObjectPath::try_from(format!("{}/jobs/{}", DBUS_PATH, id))
+                .map_err(zbus::Error::from)?;
+
+            let status =
+                Arc::new(Mutex::new(JobStatus { kind: kind.into(), ..JobStatus::default() }));
+            jobs.jobs.insert(id, (path.clone(), status.clone()));
+            (id, path, status)
+        };
+
+        log::info!("Starting {} job {}", kind, path.as_str());
+        if let Err(why) = connection.object_server().at(&path, JobObject).await {
+            JOBS.lock().unwrap_or_else(PoisonError::into_inner).jobs.remove(&id);
+            return Err(why.into());
+        }
+
+        let context = zbus::SignalContext::new(connection, path)?;
+
+        Ok(Self { context, status })
+    }
+
+    pub(super) fn path(&self) -> OwnedObjectPath { self.context.path().to_owned().into() }
+
+    pub(super) async fn progress(&self, step: &str, percent: u32) {
+        {
+            let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+            status.step = step.into();
+            status.percent = percent;
+        }
+
+        log::info!("{}: {} ({}%)", self.context.path().as_str(), step, percent);
+        sd_notify::status(step);
+        let _res = JobObject::progress(&self.context, step, percent).await;
+    }
+
+    /// Records the outcome of the job, and forgets the oldest finished jobs.
+    pub(super) async fn finish(self, result: &Result<String, PowerError>) {
+        let (success, message, error) = match result {
+            Ok(message) => (true, message.clone(), String::new()),
+            Err(why) => (false, why.message(), why.error_name()),
+        };
+        let message = message.as_str();
+
+        {
+            let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+            status.finished = true;
+            status.success = success;
+            status.message = message.into();
+            status.error = error;
+            if success {
+                status.percent = 100;
+            }
+        }
+
+        log::info!("{}: finished: {}", self.context.path().as_str(), message);
+        sd_notify::status(STATUS_IDLE);
+        let _res = JobObject::finished(&self.context, success, message).await;
+
+        let expired = {
+            let mut jobs = JOBS.lock().unwrap_or_else(PoisonError::into_inner);
+            let finished = jobs
+                .jobs
+                .iter()
+                .filter(|(_, (_, status))| {
+                    status.lock().unwrap_or_else(PoisonError::into_inner).finished
+                })
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+
+            let excess = finished.len().saturating_sub(FINISHED_JOBS);
+            finished[..excess].iter().filter_map(|id| jobs.jobs.remove(id)).collect::<Vec<_>>()
+        };
+
+        let server = self.context.connection().object_server();
+        for (path, _) in expired {
+            let _res = server.remove::<JobObject, _>(&path).await;
+        }
+    }
+}
+
+/// Status of a running job, or of a recently finished one.
+pub(super) fn status(path: &str) -> Option<JobStatus> {
+    let jobs = JOBS.lock().unwrap_or_else(PoisonError::into_inner);
+
+    jobs.jobs
+        .values()
+        .find(|(job, _)| job.as_str() == path)
+        .map(|(_, status)| status.lock().unwrap_or_else(PoisonError::into_inner).clone())
+}
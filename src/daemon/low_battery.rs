@@ -0,0 +1,263 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Forcing the battery profile while the battery is low, whatever profile was selected or
+//! held, and restoring the previous profile once it recovers or the AC adapter is plugged in.
+//! Profiles selected meanwhile by the other automations, or released holds, are applied then
+//! instead.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use system76_power_zbus::LowBatteryStatus;
+use tokio::sync::Notify;
+
+use crate::{power_supply, tunables};
+
+use super::System76Power;
+
+// Initiator of profile changes made when the battery is low, and once it recovers.
+pub(super) const INITIATOR_LOW_BATTERY: &str = "low-battery";
+
+// Profile applied while the battery is low.
+const LOW_BATTERY_PROFILE: &str = "Battery";
+
+// Longest wait between checks of the charge, as not every battery reports its capacity with
+// uevents.
+const POLL: Duration = Duration::from_secs(60);
+
+// Wakes the watch when a power supply changes, or the settings do.
+static WAKE: Notify = Notify::const_new();
+
+/// The `[low_battery]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct LowBatteryConfig {
+    pub enabled:            bool,
+    /// Charge under which the battery profile is forced while discharging, in percent.
+    pub threshold_percent:  u8,
+    /// Charge above the threshold the battery must reach before the previous profile is
+    /// restored, in percent.
+    pub hysteresis_percent: u8,
+}
+
+impl Default for LowBatteryConfig {
+    fn default() -> Self { Self { enabled: false, threshold_percent: 20, hysteresis_percent: 5 } }
+}
+
+impl LowBatteryConfig {
+    /// Checks the threshold, and that it can be recovered from.
+    pub fn validate(&self) -> Result<(), (&'static str, String)> {
+        if !(1..=99).contains(&self.threshold_percent) {
+            return Err(("low_battery.threshold_percent", "must be from 1 to 99".into()));
+        }
+
+        if u16::from(self.threshold_percent) + u16::from(self.hysteresis_percent) > 100 {
+            let why = "must not take the threshold over 100".into();
+            return Err(("low_battery.hysteresis_percent", why));
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the battery profile is forced, and the profile to restore.
+#[derive(Default)]
+pub(super) struct LowBattery {
+    low:        bool,
+    /// Whether a client set a profile while the battery was low, which is then kept.
+    overridden: bool,
+    restore:    Option<String>,
+}
+
+/// What to do on a change of the charge.
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    Force(String),
+    Restore(Option<String>),
+}
+
+impl LowBattery {
+    /// Whether the battery profile is forced, so that other profiles are not applied.
+    pub fn forced(&self) -> bool { self.low && !self.overridden }
+
+    /// The profile to restore while the battery profile is forced, which holds restore in turn
+    /// once released.
+    pub fn restoring(&self) -> Option<&str> { self.restore.as_deref().filter(|_| self.forced()) }
+
+    /// Notes a profile set by a client, which is kept until the battery recovers.
+    pub fn profile_set(&mut self) {
+        if self.low {
+            self.overridden = true;
+            self.restore = None;
+        }
+    }
+
+    /// Keeps a profile selected by the other automations to restore once the battery
+    /// recovers, returning `false` if it should be applied now.
+    pub fn defer(&mut self, profile: &str) -> bool {
+        if !self.forced() {
+            return false;
+        }
+
+        log::info!("The battery is low, switching to {} profile once it recovers", profile);
+        self.restore = Some(profile.to_owned());
+        true
+    }
+
+    /// Forces the battery profile when the charge falls under the threshold while
+    /// discharging, and restores the previous profile once it rises over the threshold and
+    /// the hysteresis, or the AC adapter is plugged in.
+    fn update(
+        &mut self,
+        config: &LowBatteryConfig,
+        current: &str,
+        charge: Option<(u8, bool)>,
+        on_ac: bool,
+    ) -> Option<Change> {
+        let recovers = u16::from(config.threshold_percent) + u16::from(config.hysteresis_percent);
+        let low = match charge {
+            Some((percent, discharging)) if !self.low => {
+                discharging && !on_ac && percent < config.threshold_percent
+            }
+            Some((percent, _)) => !on_ac && u16::from(percent) < recovers,
+            None => false,
+        };
+
+        // Disabling the switch restores the previous profile.
+        let low = low && config.enabled;
+        if low == self.low {
+            return None;
+        }
+
+        self.low = low;
+        if low {
+            self.overridden = false;
+            self.restore = Some(current.to_owned());
+            Some(Change::Force(LOW_BATTERY_PROFILE.to_owned()))
+        } else {
+            self.overridden = false;
+            Some(Change::Restore(self.restore.take()))
+        }
+    }
+
+    pub fn status(&self, config: &LowBatteryConfig) -> LowBatteryStatus {
+        LowBatteryStatus {
+            enabled:            config.enabled,
+            threshold_percent:  config.threshold_percent,
+            hysteresis_percent: config.hysteresis_percent,
+            active:             self.forced(),
+            restore_profile:    self.restore.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl System76Power {
+    /// Forces the battery profile, or restores the previous profile, as the charge changes.
+    pub(super) async fn refresh_low_battery(&self, context: &zbus::SignalContext<'_>) {
+        let charge = power_supply::battery();
+        let on_ac = power_supply::on_ac().unwrap_or(false);
+
+        let profile = {
+            let mut this = self.0.lock().await;
+            let this = &mut *this;
+            let config = &this.config.low_battery;
+            match this.low_battery.update(config, &this.power_profile, charge, on_ac) {
+                Some(Change::Force(profile)) => {
+                    log::info!(
+                        "The battery is under {}%, switching to {} profile",
+                        config.threshold_percent,
+                        profile
+                    );
+                    profile
+                }
+                Some(Change::Restore(profile)) => {
+                    let Some(profile) = profile else { return };
+                    log::info!("The battery recovered, restoring {} profile", profile);
+                    // Holds taken meanwhile apply first.
+                    this.holds.select(&profile);
+                    this.holds.effective().unwrap_or(profile)
+                }
+                None => return,
+            }
+        };
+
+        if let Some(profile) = tunables::find(&profile) {
+            if let Err(why) = self.set_profile(context, &profile, INITIATOR_LOW_BATTERY).await {
+                log::warn!("Failed to switch profile on low battery: {}", why);
+            }
+        }
+    }
+}
+
+/// Wakes the watch, to check the charge after a power supply or the settings changed.
+pub(super) fn wake() { WAKE.notify_one(); }
+
+/// Forces the battery profile while the battery is low.
+pub(super) async fn watch(daemon: System76Power, context: zbus::SignalContext<'static>) {
+    loop {
+        daemon.refresh_low_battery(&context).await;
+
+        tokio::select! {
+            () = WAKE.notified() => (),
+            () = tokio::time::sleep(POLL) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hysteresis() {
+        let config = LowBatteryConfig { enabled: true, ..LowBatteryConfig::default() };
+        let mut low_battery = LowBattery::default();
+
+        // Charging under the threshold, or discharging above it, leaves the profile alone.
+        assert_eq!(low_battery.update(&config, "Performance", Some((15, false)), true), None);
+        assert_eq!(low_battery.update(&config, "Performance", Some((20, true)), false), None);
+
+        let forced = low_battery.update(&config, "Performance", Some((19, true)), false);
+        assert_eq!(forced, Some(Change::Force("Battery".into())));
+        assert!(low_battery.forced());
+
+        // Other automations are deferred, until the charge rises over the hysteresis.
+        assert!(low_battery.defer("Balanced"));
+        assert_eq!(low_battery.update(&config, "Battery", Some((24, false)), false), None);
+        let restored = low_battery.update(&config, "Battery", Some((25, false)), false);
+        assert_eq!(restored, Some(Change::Restore(Some("Balanced".into()))));
+        assert!(!low_battery.defer("Balanced"));
+
+        // Plugging the AC adapter in restores the profile at once.
+        low_battery.update(&config, "Performance", Some((10, true)), false);
+        let restored = low_battery.update(&config, "Battery", Some((10, false)), true);
+        assert_eq!(restored, Some(Change::Restore(Some("Performance".into()))));
+
+        // A profile set by a client is kept.
+        low_battery.update(&config, "Performance", Some((10, true)), false);
+        low_battery.profile_set();
+        assert!(!low_battery.forced());
+        let restored = low_battery.update(&config, "Performance", Some((10, false)), true);
+        assert_eq!(restored, Some(Change::Restore(None)));
+
+        // Disabling the switch restores the profile, and nothing is forced while disabled.
+        low_battery.update(&config, "Performance", Some((10, true)), false);
+        let config = LowBatteryConfig::default();
+        let restored = low_battery.update(&config, "Battery", Some((10, true)), false);
+        assert_eq!(restored, Some(Change::Restore(Some("Performance".into()))));
+        assert_eq!(low_battery.update(&config, "Performance", Some((10, true)), false), None);
+    }
+
+    #[test]
+    fn config() {
+        let config: LowBatteryConfig = toml::from_str("enabled = true").unwrap();
+        assert_eq!((config.threshold_percent, config.hysteresis_percent), (20, 5));
+        assert!(config.validate().is_ok());
+
+        let config = LowBatteryConfig { threshold_percent: 0, ..config };
+        assert_eq!(config.validate().unwrap_err().0, "low_battery.threshold_percent");
+        let config = LowBatteryConfig { threshold_percent: 98, ..config };
+        assert_eq!(config.validate().unwrap_err().0, "low_battery.hysteresis_percent");
+    }
+}
@@ -4,63 +4,164 @@
 
 use anyhow::Context;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt::Display,
     fs,
+    marker::PhantomData,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
-    signal::unix::{signal, SignalKind},
+    signal::unix::{signal, Signal, SignalKind},
     sync::Mutex,
+    task::JoinHandle,
     time::sleep,
 };
-use zbus::Interface;
+use zbus::{names::InterfaceName, Interface};
+use zbus_polkit::policykit1::{AuthorityProxy, CheckAuthorizationFlags, Subject};
 
 use crate::{
-    charge_thresholds::{get_charge_profiles, get_charge_thresholds, set_charge_thresholds},
-    errors::ProfileError,
+    charge_thresholds::{
+        builtin_charge_profiles, get_battery_charge_thresholds, get_charge_thresholds,
+        get_charge_thresholds_status, holds, load_charge_profiles, plan_charge_thresholds,
+        restore_charge_thresholds, rounded_thresholds, set_charge_thresholds,
+        watch_charge_thresholds, BatteryWrites,
+    },
+    config,
+    conflicts::{self, PowerManager},
+    cpufreq,
     fan::FanDaemon,
-    graphics::{Graphics, GraphicsMode},
+    graphics::{Graphics, GraphicsMode, InitramfsRebuild, LastSwitch, GRAPHICS_CONFIG},
     hid_backlight,
     hotplug::{mux, Detect, HotPlugDetect},
-    kernel_parameters::{KernelParameter, NmiWatchdog},
+    mode_files::ModeFiles,
+    power_draw, power_supply,
     runtime_pm::{runtime_pm_quirks, thunderbolt_hotplug_wakeup},
-    DBUS_NAME, DBUS_PATH,
+    sd_notify,
+    state::{State, STATE_PATH},
+    tunables::{self, ProfileDef},
+    uevent::UeventMonitor,
+    util::Written,
+    Profile, DBUS_NAME, DBUS_PATH,
 };
 
+mod apply;
+mod audit;
+mod auto_profile;
+mod battery_cap;
+mod brightness;
+mod calibration;
+mod check;
+pub mod direct;
+mod error;
+mod holds;
+mod hooks;
+mod idle;
+mod jobs;
+mod low_battery;
+mod operation;
+mod package_transaction;
 mod profiles;
-use self::profiles::{balanced, battery, performance};
+mod schedule;
+mod settings;
+mod sleep;
+pub use self::{apply::apply, check::check, profiles::plan as plan_profile};
+
+use self::{
+    audit::Audit,
+    auto_profile::{AutoProfile, Startup, Trigger},
+    calibration::Calibration,
+    error::PowerError,
+    holds::Holds,
+    idle::{Idle, INITIATOR_IDLE},
+    jobs::Job,
+    low_battery::{LowBattery, LowBatteryConfig},
+    operation::Operation,
+    profiles::{describe, Applied, ProfileFailed},
+    schedule::Schedule,
+    settings::DaemonConfig,
+};
 
-use system76_power_zbus::ChargeProfile;
+use system76_power_zbus::{
+    AutoProfileStatus, BatteryCapStatus, BatteryStatus, CalibrationStatus, ChargeProfile,
+    ChargeThresholdsStatus, CpuFrequencyStatus, GraphicsCapabilities, GraphicsDeviceInfo,
+    GraphicsPowerStatus, GraphicsStatus, JobStatus, LowBatteryStatus, PlannedAction, PowerConflict,
+    ProfileFailure, ProfileHold, ProfileInfo, ProfileStatus, ProfileTunable, RecentAction,
+    ScheduleStatus, SwitchableStatus, FLAG_DRY_RUN, FLAG_FORCE, FLAG_NO_INITRAMFS, FLAG_TEMPORARY,
+};
 
+const GRAPHICS_POLICY: &str = "com.system76.powerdaemon.switch-graphics";
+const PROFILE_POLICY: &str = "com.system76.powerdaemon.set-profile";
 const THRESHOLD_POLICY: &str = "com.system76.powerdaemon.set-charge-thresholds";
+// Initiator of profile changes made by the daemon itself.
+const INITIATOR_SYSTEM: &str = "system";
+// Initiator of profile changes made on AC/battery transitions.
+const INITIATOR_POWER_SOURCE: &str = "power-source";
+// Initiator of charge threshold changes made outside of the daemon, such as by vendor tools.
+const INITIATOR_EXTERNAL: &str = "external";
 const NET_HADESS_POWER_PROFILES_DBUS_NAME: &str = "net.hadess.PowerProfiles";
 const NET_HADESS_POWER_PROFILES_DBUS_PATH: &str = "/net/hadess/PowerProfiles";
 const POWER_PROFILES_DBUS_NAME: &str = "org.freedesktop.UPower.PowerProfiles";
 const POWER_PROFILES_DBUS_PATH: &str = "/org/freedesktop/UPower/PowerProfiles";
 
-static CONTINUE: AtomicBool = AtomicBool::new(true);
+// Status reported to systemd while no operation is running.
+const STATUS_IDLE: &str = "Idle";
 
-async fn signal_handling() {
-    let mut int = signal(SignalKind::interrupt()).unwrap();
-    let mut hup = signal(SignalKind::hangup()).unwrap();
-    let mut term = signal(SignalKind::terminate()).unwrap();
+static CONTINUE: AtomicBool = AtomicBool::new(true);
 
-    let sig = tokio::select! {
-        _ = int.recv() => "SIGINT",
-        _ = hup.recv() => "SIGHUP",
-        _ = term.recv() => "SIGTERM"
+// Longest time to wait for an in-flight operation, such as an initramfs rebuild, on shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(120);
+
+// How often the charge thresholds are read, as firmware changes them without inotify events.
+const CHARGE_THRESHOLDS_POLL: Duration = Duration::from_secs(30);
+
+/// Reloads the daemon on SIGHUP, and stops it on SIGINT or SIGTERM.
+async fn signal_handling(
+    mut int: Signal,
+    mut hup: Signal,
+    mut term: Signal,
+    daemon: System76Power,
+) {
+    let sig = loop {
+        tokio::select! {
+            _ = int.recv() => break "SIGINT",
+            _ = term.recv() => break "SIGTERM",
+            _ = hup.recv() => {
+                log::info!("caught signal: SIGHUP");
+                daemon.reload().await;
+            }
+        }
     };
 
     log::info!("caught signal: {}", sig);
     CONTINUE.store(false, Ordering::SeqCst);
 }
 
+/// Pings the systemd watchdog while the event loop is responsive.
+///
+/// This runs beside the main loop rather than in it, because the main loop may wait for an
+/// operation such as an initramfs rebuild, which can legitimately outlast the watchdog.
+async fn watchdog() {
+    let Some(interval) = sd_notify::watchdog_interval() else { return };
+    log::info!("Pinging the watchdog every {:?}", interval / 2);
+
+    let mut last_ping = Instant::now();
+    sd_notify::notify_or_log("WATCHDOG=1");
+
+    while CONTINUE.load(Ordering::SeqCst) {
+        sleep(Duration::from_millis(1000)).await;
+
+        if last_ping.elapsed() >= interval / 2 {
+            sd_notify::notify_or_log("WATCHDOG=1");
+            last_ping = Instant::now();
+        }
+    }
+}
+
 // Disabled by default because some systems have quirky ACPI tables that fail to resume from
 // suspension.
 static PCI_RUNTIME_PM: AtomicBool = AtomicBool::new(false);
@@ -69,304 +170,2196 @@ static PCI_RUNTIME_PM: AtomicBool = AtomicBool::new(false);
 pub(crate) fn pci_runtime_pm_support() -> bool { PCI_RUNTIME_PM.load(Ordering::SeqCst) }
 
 struct PowerDaemon {
-    initial_set:    bool,
-    graphics:       Graphics,
-    power_profile:  String,
-    profile_errors: Vec<ProfileError>,
-    held_profiles:  Vec<(u32, &'static str, String, String)>,
-    profile_ids:    u32,
-    connections:    Option<(zbus::Connection, zbus::Connection, zbus::Connection)>,
+    initial_set:                    bool,
+    /// Whether the desktop of an active session manages the brightness, which profiles then
+    /// leave alone.
+    brightness_managed:             bool,
+    graphics:                       Graphics,
+    power_profile:                  String,
+    /// Values written by the last profile applied.
+    applied:                        Applied,
+    holds:                          Holds,
+    idle:                           Idle,
+    /// Rate limits and recent changes of clients.
+    audit:                          Audit,
+    /// Files written by the last switch, to tell edits made by other tools.
+    mode_files:                     ModeFiles,
+    connections:                    Option<(zbus::Connection, zbus::Connection, zbus::Connection)>,
+    hot_plug:                       [bool; 4],
+    external_displays_require_dgpu: bool,
+    /// Settings requested by clients, restored on startup.
+    state:                          State,
+    config:                         DaemonConfig,
+    auto_profile:                   AutoProfile,
+    schedule:                       Schedule,
+    low_battery:                    LowBattery,
+    /// Last thresholds announced with `ChargeThresholdsChanged`, keyed by battery.
+    charge_thresholds:              BTreeMap<String, (u8, u8)>,
+    /// The built-in charge profiles, and those of `charge-profiles.toml`.
+    charge_profiles:                Vec<ChargeProfile>,
+    /// Other power managers running, which also set parameters of the profiles.
+    conflicts:                      Vec<&'static PowerManager>,
+    /// Maximum frequency the last profile was capped to on battery, in percent.
+    battery_cap:                    Option<u8>,
+    calibration:                    Calibration,
 }
 
 impl PowerDaemon {
-    fn new() -> anyhow::Result<Self> {
-        let graphics = Graphics::new()?;
+    fn new() -> anyhow::Result<Self> { Ok(Self::with_graphics(Graphics::new()?)) }
 
-        Ok(Self {
+    fn with_graphics(graphics: Graphics) -> Self {
+        Self {
             initial_set: false,
+            brightness_managed: false,
             graphics,
             power_profile: String::new(),
-            profile_errors: Vec::new(),
-            held_profiles: Vec::new(),
-            profile_ids: 0,
+            applied: Applied::default(),
+            holds: Holds::default(),
+            idle: Idle::default(),
+            audit: Audit::default(),
+            mode_files: ModeFiles::default(),
             connections: None,
-        })
+            hot_plug: [false; 4],
+            external_displays_require_dgpu: false,
+            state: State::default(),
+            config: DaemonConfig::default(),
+            auto_profile: AutoProfile::default(),
+            schedule: Schedule::default(),
+            low_battery: LowBattery::default(),
+            charge_thresholds: BTreeMap::new(),
+            charge_profiles: builtin_charge_profiles(),
+            conflicts: Vec::new(),
+            battery_cap: None,
+            calibration: Calibration::default(),
+        }
     }
 
-    async fn apply_profile(
-        &mut self,
-        context: &zbus::SignalContext<'_>,
-        func: fn(&mut Vec<ProfileError>, bool),
-        name: &str,
-    ) -> Result<(), String> {
-        if self.power_profile == name {
-            log::info!("profile was already set");
-            return Ok(());
+    /// Records a change requested by a client, so that it is restored after a restart.
+    fn remember(&mut self, change: impl FnOnce(&mut State)) {
+        change(&mut self.state);
+
+        if let Err(why) = self.state.save() {
+            log::warn!("Failed to save daemon state to {}: {}", STATE_PATH, why);
         }
+    }
 
-        let _res = System76Power::power_profile_switch(context, name).await;
+    /// Re-applies the settings of a previous run, except the graphics power, which is applied
+    /// once the daemon answers queries, and the charge thresholds if disabled.
+    fn restore(&mut self, state: State) {
+        if state.has_charge_thresholds() && self.config.charge_thresholds.reapply {
+            log::info!("Restoring charge thresholds");
+            match restore_charge_thresholds(&state) {
+                Ok(writes) => log_battery_failures(&writes),
+                Err(why) => log::warn!("Failed to restore charge thresholds: {}", why),
+            }
+        }
 
-        func(&mut self.profile_errors, self.initial_set);
+        self.state = state;
+    }
 
-        self.power_profile = name.into();
+    /// The last profile saved, if it is still defined, or `Balanced`.
+    fn persisted_profile(&self) -> &str {
+        let name = self.state.profile.as_deref().filter(|name| tunables::find(name).is_some());
+        name.unwrap_or("Balanced")
+    }
 
-        if self.profile_errors.is_empty() {
-            Ok(())
-        } else {
-            let mut error_message = String::from("Errors found when setting profile:");
-            for error in self.profile_errors.drain(..) {
-                error_message = format!("{}\n    - {}", error_message, error);
+    /// Applies the profile the daemon starts with, before any client can be notified of it:
+    /// the one mapped to the power source if switching is enabled, else the one saved.
+    fn apply_initial_profile(&mut self) {
+        let on_ac = power_supply::on_ac();
+        let (name, startup) = auto_profile::startup_profile(
+            &self.config.auto_profile,
+            on_ac,
+            self.state.profile.as_deref(),
+        );
+
+        // The power source is known, so that it is not taken for a transition later.
+        self.auto_profile.on_ac = on_ac;
+        match startup {
+            Startup::Mapped(on_ac) => {
+                let source = if on_ac { "AC" } else { "battery" };
+                log::info!("Running on {}, setting initial profile {}", source, name);
+                self.auto_profile.last_trigger = Some(Trigger {
+                    on_ac,
+                    profile: name.clone(),
+                    time: std::time::SystemTime::now(),
+                });
             }
+            Startup::Persisted => log::info!("Restoring saved profile {}", name),
+            Startup::Default => log::info!("Setting default profile {}", name),
+        }
 
-            Err(error_message)
+        if let Some(profile) = tunables::find(&name) {
+            if let Err(why) = self.run_profile(&profile) {
+                log::warn!("Error setting initial profile: {}", why);
+            }
         }
-    }
-}
 
-#[derive(Clone)]
-struct System76Power(Arc<Mutex<PowerDaemon>>);
+        self.power_profile = name;
+        self.initial_set = true;
+    }
 
-impl System76Power {
-    pub async fn emit_active_profile_changed(&self) {
-        let (upp_connection, hadess_connection, profile) = {
-            let this = self.0.lock().await;
-            let Some((_, upp, hadess)) = this.connections.clone() else { return };
+    /// Sets the parameters of a profile, recording the values written and the errors met, and
+    /// returns the parameters which failed, unless most failed. On battery, the maximum
+    /// frequency is capped if enabled.
+    fn run_profile(&mut self, profile: &ProfileDef) -> Result<Vec<ProfileFailure>, ProfileFailed> {
+        let cap = self.config.battery_cap.cap(self.auto_profile.on_ac);
+        self.run_profile_with_cap(profile, cap)
+    }
 
-            let profile = system76_profile_to_upp_str(&this.power_profile);
-            (upp, hadess, profile)
+    /// Sets the parameters of a profile, with the maximum frequency capped to `cap` percent,
+    /// if any.
+    fn run_profile_with_cap(
+        &mut self,
+        profile: &ProfileDef,
+        cap: Option<u8>,
+    ) -> Result<Vec<ProfileFailure>, ProfileFailed> {
+        let capped = cap.map(|percent| profiles::capped(profile, percent));
+        self.battery_cap = cap;
+        self.applied.clear();
+        self.applied.yielded = if self.config.conflicts.yield_parameters {
+            conflicts::parameters(&self.conflicts)
+        } else {
+            BTreeSet::new()
         };
+        let set_brightness = self.set_brightness();
+        profiles::set(capped.as_ref().unwrap_or(profile), &mut self.applied, set_brightness);
+        self.applied.outcome(&profile.name)
+    }
 
-        let value = zvariant::Value::Str(zvariant::Str::from(profile));
-        let changed = HashMap::from_iter(std::iter::once(("ActiveProfile", &value)));
-        let invalidated = &[];
-
-        if let Ok(context) = zbus::SignalContext::new(&upp_connection, POWER_PROFILES_DBUS_PATH) {
-            let _res = zbus::fdo::Properties::properties_changed(
-                &context,
-                UPowerPowerProfiles::name(),
-                &changed,
-                invalidated,
-            )
-            .await;
+    /// Detects the other power managers running, warning of those newly detected, and returns
+    /// whether they changed.
+    fn detect_conflicts(&mut self, owns_power_profiles: bool) -> bool {
+        let detected = conflicts::detect(owns_power_profiles);
+        if detected == self.conflicts {
+            return false;
         }
 
-        if let Ok(context) =
-            zbus::SignalContext::new(&hadess_connection, NET_HADESS_POWER_PROFILES_DBUS_PATH)
-        {
-            let _res = zbus::fdo::Properties::properties_changed(
-                &context,
-                NetHadessPowerProfiles::name(),
-                &changed,
-                invalidated,
-            )
-            .await;
+        let outcome = if self.config.conflicts.yield_parameters {
+            "which are left to it"
+        } else {
+            "which it may change back; stop it, or set conflicts.yield_parameters in \
+             /etc/system76-power/daemon.toml to leave them to it"
+        };
+        for &manager in detected.iter().filter(|&&manager| !self.conflicts.contains(&manager)) {
+            log::warn!(
+                "{} is running, and also sets {}, {}",
+                manager.service,
+                manager.parameters.join(", "),
+                outcome
+            );
         }
+
+        self.conflicts = detected;
+        true
     }
-}
 
-#[zbus::dbus_interface(name = "com.system76.PowerDaemon")]
-impl System76Power {
-    async fn battery(
+    /// Whether profiles set the brightness of backlights: not when the daemon starts, nor while
+    /// a desktop manages it.
+    fn set_brightness(&self) -> bool { self.initial_set && !self.brightness_managed }
+
+    async fn apply_profile(
         &mut self,
-        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
-    ) -> zbus::fdo::Result<()> {
-        let result = self
-            .0
-            .lock()
-            .await
-            .apply_profile(&context, battery, "Battery")
-            .await
-            .map_err(zbus_error_from_display);
+        context: &zbus::SignalContext<'_>,
+        profile: &ProfileDef,
+        initiator: &str,
+    ) -> Result<Vec<ProfileFailure>, ProfileFailed> {
+        let name = profile.name.as_str();
+        if initiator != INITIATOR_IDLE {
+            self.idle.profile_set();
+        }
 
-        if result.is_ok() {
-            self.emit_active_profile_changed().await
+        if self.power_profile == name {
+            log::info!("profile was already set");
+            return Ok(Vec::new());
         }
 
-        result
-    }
+        log::info!(operation = "set_profile", profile = name; "Setting profile {}", name);
 
-    async fn balanced(
-        &mut self,
-        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
-    ) -> zbus::fdo::Result<()> {
-        let result = self
-            .0
-            .lock()
-            .await
-            .apply_profile(&context, balanced, "Balanced")
-            .await
-            .map_err(zbus_error_from_display);
+        let _res = System76Power::power_profile_switch(context, name).await;
+
+        let desktop =
+            brightness::managing_desktop(context.connection(), &self.config.brightness).await;
+        if let Some(ref desktop) = desktop {
+            log::info!("Leaving the brightness to {}", desktop);
+        }
+        self.brightness_managed = desktop.is_some();
+
+        let result = self.run_profile(profile);
 
+        let old = std::mem::replace(&mut self.power_profile, name.into());
+        let _res = System76Power::power_profile_switched(context, &old, name, initiator).await;
         if result.is_ok() {
-            self.emit_active_profile_changed().await
+            hooks::profile_changed(&self.config.hooks, &old, name, initiator);
         }
 
         result
     }
+}
 
-    async fn performance(
-        &mut self,
-        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
-    ) -> zbus::fdo::Result<()> {
+#[derive(Clone)]
+struct System76Power(Arc<Mutex<PowerDaemon>>);
+
+impl System76Power {
+    /// Applies a profile, and announces it to the clients of every interface. Returns the
+    /// parameters which failed, if most others were set.
+    async fn set_profile(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        profile: &ProfileDef,
+        initiator: &str,
+    ) -> Result<Vec<ProfileFailure>, PowerError> {
         let result = self
             .0
             .lock()
             .await
-            .apply_profile(&context, performance, "Performance")
+            .apply_profile(context, profile, initiator)
             .await
-            .map_err(zbus_error_from_display);
+            .map_err(|why| PowerError::ProfileFailed(why.to_string()));
 
         if result.is_ok() {
+            let _res = self.power_profile_changed(context).await;
             self.emit_active_profile_changed().await
         }
 
         result
     }
 
-    #[dbus_interface(out_args("profile"))]
-    async fn get_profile(&self) -> zbus::fdo::Result<String> {
-        Ok(self.0.lock().await.power_profile.clone())
-    }
+    /// Applies a profile requested by a client, which is restored after a restart once applied,
+    /// unless it is `temporary`. Holds of profiles are released, as they would otherwise override
+    /// it, and the profile is kept while the battery is low. Returns the parameters which failed,
+    /// if most others were set.
+    async fn request_profile(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        profile: &ProfileDef,
+        initiator: &str,
+        temporary: bool,
+    ) -> Result<Vec<ProfileFailure>, PowerError> {
+        let released = {
+            let mut this = self.0.lock().await;
+            this.low_battery.profile_set();
+            this.holds.clear()
+        };
+        self.announce_released(context, &released).await;
 
-    #[dbus_interface(out_args("required"))]
-    async fn get_external_displays_require_dgpu(&mut self) -> zbus::fdo::Result<bool> {
-        self.0
-            .lock()
-            .await
-            .graphics
-            .get_external_displays_require_dgpu()
-            .map_err(zbus_error_from_display)
+        let result = self.set_profile(context, profile, initiator).await;
+        if result.is_ok() && !temporary {
+            self.0.lock().await.remember(|state| state.profile = Some(profile.name.clone()));
+        }
+        result
     }
 
-    #[dbus_interface(out_args("vendor"))]
-    async fn get_default_graphics(&self) -> zbus::fdo::Result<String> {
-        self.0
-            .lock()
-            .await
-            .graphics
-            .get_default_graphics()
-            .map_err(zbus_error_from_display)
-            .map(|mode| <&'static str>::from(mode).to_owned())
-    }
+    /// Holds a profile until the hold is released, or the client leaves the bus.
+    async fn hold(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+        sender: &str,
+    ) -> u32 {
+        let cookie = {
+            let mut this = self.0.lock().await;
+            let this = &mut *this;
+            let current = this.low_battery.restoring().unwrap_or(&this.power_profile);
+            this.holds.add(current, profile, reason, application_id, sender)
+        };
 
-    #[dbus_interface(out_args("vendor"))]
-    async fn get_graphics(&self) -> zbus::fdo::Result<String> {
-        self.0
-            .lock()
-            .await
-            .graphics
-            .get_vendor()
-            .map_err(zbus_error_from_display)
-            .map(|mode| <&'static str>::from(mode).to_owned())
+        log::info!(
+            operation = "hold_profile", profile = profile;
+            "{} ({}) holds the {} profile: {}", application_id, sender, profile, reason
+        );
+        self.apply_holds(context, sender).await;
+        cookie
     }
 
-    async fn set_graphics(&mut self, vendor: &str) -> zbus::fdo::Result<()> {
-        self.0
-            .lock()
-            .await
-            .graphics
-            .set_vendor(GraphicsMode::from(vendor))
-            .map_err(zbus_error_from_display)
+    /// Releases the holds matching `release`, returning their cookies.
+    async fn release(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        release: impl Fn(&ProfileHold) -> bool,
+        initiator: &str,
+    ) -> Vec<u32> {
+        let released = self.0.lock().await.holds.release(release);
+
+        if !released.is_empty() {
+            log::info!("Released profile holds {:?}", released);
+            self.announce_released(context, &released).await;
+            self.apply_holds(context, initiator).await;
+        }
+
+        released
     }
 
-    #[dbus_interface(out_args("desktop"))]
-    async fn get_desktop(&mut self) -> zbus::fdo::Result<bool> {
-        Ok(self.0.lock().await.graphics.is_desktop())
+    /// Releases a hold of `sender`, which may not release the holds of other clients.
+    async fn release_own(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        cookie: u32,
+        sender: &str,
+    ) -> Result<(), PowerError> {
+        let own = |hold: &ProfileHold| hold.cookie == cookie && hold.sender == sender;
+        if self.release(context, own, sender).await.is_empty() {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "{} has no hold with cookie {}",
+                sender, cookie
+            ))
+            .into());
+        }
+
+        Ok(())
     }
 
-    #[dbus_interface(out_args("switchable"))]
-    async fn get_switchable(&mut self) -> zbus::fdo::Result<bool> {
-        Ok(self.0.lock().await.graphics.can_switch())
+    /// Applies the profile called for by the holds, or the profile to restore after them,
+    /// once the battery is no longer low.
+    async fn apply_holds(&self, context: &zbus::SignalContext<'_>, initiator: &str) {
+        let profile = {
+            let mut this = self.0.lock().await;
+            let Some(profile) = this.holds.effective() else { return };
+            if this.low_battery.defer(&profile) {
+                return;
+            }
+            profile
+        };
+
+        if let Some(profile) = tunables::find(&profile) {
+            if let Err(why) = self.set_profile(context, &profile, initiator).await {
+                log::warn!("Failed to apply held profile: {}", why);
+            }
+        }
     }
 
-    #[dbus_interface(out_args("power"))]
-    async fn get_graphics_power(&mut self) -> zbus::fdo::Result<bool> {
-        self.0.lock().await.graphics.get_power().map_err(zbus_error_from_display)
+    /// Emits `ProfileReleased` on this interface and on `org.freedesktop.UPower.PowerProfiles`.
+    async fn announce_released(&self, context: &zbus::SignalContext<'_>, cookies: &[u32]) {
+        let upp = self.0.lock().await.connections.as_ref().and_then(|(_, upp, _)| {
+            zbus::SignalContext::new(upp, POWER_PROFILES_DBUS_PATH).ok().map(|c| c.into_owned())
+        });
+
+        for &cookie in cookies {
+            let _res = Self::profile_released(context, cookie).await;
+            if let Some(ref upp) = upp {
+                let _res = UPowerPowerProfiles::profile_released(upp, cookie).await;
+            }
+        }
     }
 
-    async fn set_graphics_power(&mut self, power: bool) -> zbus::fdo::Result<()> {
-        self.0.lock().await.graphics.set_power(power).map_err(zbus_error_from_display)
+    /// Announces the charge thresholds if they differ from the last ones announced, as changed
+    /// by `initiator`.
+    async fn refresh_charge_thresholds(&self, context: &zbus::SignalContext<'_>, initiator: &str) {
+        let thresholds = get_battery_charge_thresholds().unwrap_or_default();
+
+        {
+            let mut this = self.0.lock().await;
+            if this.charge_thresholds == thresholds {
+                return;
+            }
+            this.charge_thresholds = thresholds.clone();
+        }
+
+        log::info!("Charge thresholds changed by {}: {:?}", initiator, thresholds);
+        let _res = Self::charge_thresholds_switched(context, &thresholds, initiator).await;
+        let _res = Self::charge_thresholds_changed_signal(context, thresholds).await;
+        let _res = self.charge_thresholds_changed(context).await;
     }
 
-    async fn auto_graphics_power(&mut self) -> zbus::fdo::Result<()> {
-        self.0.lock().await.graphics.auto_power().map_err(zbus_error_from_display)
+    /// Sets the thresholds of `battery`, or of every battery if `None`, as requested by a
+    /// client, which are restored after a restart, and announces them. They are refused while
+    /// a battery is calibrated, as its thresholds are lifted until it ends.
+    async fn request_charge_thresholds(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        thresholds: (u8, u8),
+        battery: Option<&str>,
+        initiator: &str,
+    ) -> Result<BatteryWrites, PowerError> {
+        let writes = {
+            let mut this = self.0.lock().await;
+            if this.calibration.running() {
+                return Err(PowerError::Busy(format!(
+                    "{} is being calibrated, cancel the calibration to set charge thresholds",
+                    this.calibration.battery()
+                )));
+            }
+
+            let writes = set_charge_thresholds(thresholds, battery)?;
+            this.remember(|state| state.set_charge_thresholds(battery, thresholds));
+            writes
+        };
+
+        log_battery_failures(&writes);
+        self.refresh_charge_thresholds(context, initiator).await;
+        Ok(writes)
     }
 
-    #[dbus_interface(out_args("start", "end"))]
-    async fn get_charge_thresholds(&mut self) -> zbus::fdo::Result<(u8, u8)> {
-        get_charge_thresholds().map_err(zbus_error_from_display)
+    /// Starts a job switching the graphics mode, unless another graphics operation is running.
+    async fn spawn_graphics_switch(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        vendor: GraphicsMode,
+        force: bool,
+        skip_initramfs: bool,
+    ) -> Result<(zvariant::OwnedObjectPath, JoinHandle<Result<String, PowerError>>), PowerError>
+    {
+        let name = <&'static str>::from(vendor);
+        let operation = Operation::start(format!("a graphics switch to {}", name))?;
+        let settings = self.0.lock().await.config.graphics.clone();
+        let wait =
+            package_transaction::guard(context.connection(), &settings, "set_vendor").await?;
+        let job = Job::start(context.connection(), "graphics").await?;
+        let path = job.path();
+        operation.describe(format!("a graphics switch to {} (job {})", name, path.as_str()));
+
+        let this = self.clone();
+        let context = context.to_owned();
+        let task = tokio::spawn(async move {
+            let result = if wait {
+                let job = &job;
+                let waiting = move |holder| async move {
+                    job.progress(&format!("waiting for {} to finish", holder), 0).await;
+                };
+                package_transaction::wait(context.connection(), &settings, "set_vendor", waiting)
+                    .await
+            } else {
+                Ok(())
+            };
+
+            let result = match result {
+                Ok(()) => this.switch_graphics(&context, &job, vendor, force, skip_initramfs).await,
+                Err(why) => Err(why),
+            };
+            this.0.lock().await.mode_files = ModeFiles::read();
+            drop(operation);
+            job.finish(&result).await;
+            result
+        });
+
+        Ok((path, task))
     }
 
-    async fn set_charge_thresholds(&mut self, thresholds: (u8, u8)) -> zbus::fdo::Result<()> {
-        let connection = zbus::Connection::system().await?;
-        let polkit = zbus_polkit::policykit1::AuthorityProxy::new(&connection)
+    /// Switches the graphics mode, and announces the change with the `ModeChanged` signal.
+    ///
+    /// The switch runs on a blocking thread, so that the progress of the job can be reported
+    /// while it rebuilds the initramfs. The daemon is not locked meanwhile, so that queries are
+    /// answered.
+    async fn switch_graphics(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        job: &Job,
+        vendor: GraphicsMode,
+        force: bool,
+        skip_initramfs: bool,
+    ) -> Result<String, PowerError> {
+        let (progress, mut updates) = tokio::sync::mpsc::unbounded_channel();
+        let graphics = self.0.lock().await.graphics.clone();
+
+        let switch = tokio::task::spawn_blocking(move || {
+            let old = Graphics::get_configured_vendor().or_else(|| graphics.get_vendor().ok());
+
+            let result = graphics.set_vendor(vendor, force, skip_initramfs, |step, percent| {
+                let _res = progress.send((step.to_owned(), percent));
+            });
+
+            let rebuild = match &result {
+                Ok(rebuild) => rebuild.clone(),
+                Err(why) => why.initramfs_rebuild().cloned(),
+            };
+
+            let result = result
+                .map(|_| graphics.get_vendor().map_or(true, |active| active != vendor))
+                .map_err(PowerError::from);
+
+            (old, result, rebuild)
+        });
+
+        while let Some((step, percent)) = updates.recv().await {
+            job.progress(&step, percent).await;
+        }
+
+        let (old, result, rebuild) = switch
             .await
-            .context("could not connect to polkit authority daemon")
-            .map_err(zbus_error_from_display)?;
+            .map_err(|why| zbus::fdo::Error::Failed(format!("graphics switch failed: {}", why)))?;
 
-        let pid = std::process::id();
+        let new = <&'static str>::from(vendor);
+        if let Some(rebuild) = &rebuild {
+            Self::announce_initramfs_rebuild(context, rebuild).await;
+        }
 
-        let permitted = if pid == 0 {
-            true
-        } else {
-            let subject = zbus_polkit::policykit1::Subject::new_for_owner(pid, None, None)
-                .context("could not create policykit1 subject")
-                .map_err(zbus_error_from_display)?;
-
-            polkit
-                .check_authorization(
-                    &subject,
-                    THRESHOLD_POLICY,
-                    &std::collections::HashMap::new(),
-                    Default::default(),
-                    "",
-                )
-                .await
-                .context("could not check policykit authorization")
-                .map_err(zbus_error_from_display)?
-                .is_authorized
-        };
-
-        if permitted {
-            set_charge_thresholds(thresholds).map_err(zbus_error_from_display)
-        } else {
-            Err(zbus_error_from_display("Operation not permitted by Polkit"))
+        let result = result.map(|reboot_required| {
+            if reboot_required {
+                (format!("switched to {}, reboot required", new), reboot_required)
+            } else {
+                (format!("switched to {}", new), reboot_required)
+            }
+        });
+
+        let record = LastSwitch {
+            mode:      new.to_owned(),
+            time:      SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            success:   result.is_ok(),
+            message:   result
+                .as_ref()
+                .map_or_else(PowerError::message, |(message, _)| message.clone()),
+            initramfs: rebuild,
+        };
+
+        if let Err(why) = record.save() {
+            log::warn!("failed to record the graphics switch: {}", why);
+        }
+
+        let (message, reboot_required) = result?;
+
+        let _res = self.graphics_mode_changed(context).await;
+
+        let old = old.map_or("", <&'static str>::from);
+        log::info!(
+            operation = "set_vendor", mode = new;
+            "Graphics mode changed from {} to {}", old, new
+        );
+
+        if let Err(why) = Self::mode_changed(context, old, new, reboot_required).await {
+            log::warn!("failed to emit ModeChanged signal: {}", why);
         }
+
+        Ok(message)
     }
 
-    #[dbus_interface(out_args("profiles"))]
-    async fn get_charge_profiles(&mut self) -> zbus::fdo::Result<Vec<ChargeProfile>> {
-        Ok(get_charge_profiles())
+    async fn announce_initramfs_rebuild(
+        context: &zbus::SignalContext<'_>,
+        rebuild: &InitramfsRebuild,
+    ) {
+        let result = Self::initramfs_job_completed(
+            context,
+            rebuild.success,
+            &rebuild.tool,
+            rebuild.duration_ms,
+            &rebuild.stderr_tail,
+            rebuild.skipped,
+        )
+        .await;
+
+        if let Err(why) = result {
+            log::warn!("failed to emit InitramfsJobCompleted signal: {}", why);
+        }
     }
 
-    #[dbus_interface(signal)]
-    async fn hot_plug_detect(context: &zbus::SignalContext<'_>, port: u64) -> zbus::Result<()>;
+    /// Sets the autosuspend of the USB devices connected since the profile was set.
+    async fn refresh_usb_devices(&self) {
+        let mut this = self.0.lock().await;
+        if let Some(profile) = tunables::find(&this.power_profile) {
+            profiles::usb_devices_changed(&mut this.applied, &profile);
+        }
+    }
 
-    #[dbus_interface(signal)]
-    async fn power_profile_switch(
-        context: &zbus::SignalContext<'_>,
-        profile: &str,
-    ) -> zbus::Result<()>;
-}
+    /// Sets the power save of the Wi-Fi interfaces added since the profile was set.
+    async fn refresh_wifi_interfaces(&self) {
+        let mut this = self.0.lock().await;
+        if let Some(profile) = tunables::find(&this.power_profile) {
+            profiles::wifi_interfaces_changed(&mut this.applied, &profile);
+        }
+    }
+
+    /// Applies the profile mapped to the power source when it changes, if enabled, and caps
+    /// the frequency on battery, if enabled.
+    async fn refresh_power_source(&self, context: &zbus::SignalContext<'_>, force: bool) {
+        self.switch_for_power_source(context, force).await;
+        self.refresh_battery_cap().await;
+    }
+
+    /// Applies the profile mapped to the power source when it changes, if enabled.
+    ///
+    /// With `force`, or if manual changes are not pinned, the mapped profile is applied even
+    /// if the power source did not change.
+    async fn switch_for_power_source(&self, context: &zbus::SignalContext<'_>, force: bool) {
+        let on_ac = power_supply::on_ac();
+
+        let profile = {
+            let mut this = self.0.lock().await;
+            let transition = this.auto_profile.on_ac != on_ac;
+            this.auto_profile.on_ac = on_ac;
+
+            let config = &this.config.auto_profile;
+            let Some(on_ac) = on_ac else { return };
+            if !config.enabled || !(transition || force || !config.pin_manual) {
+                return;
+            }
+
+            let profile = match config.profile(on_ac) {
+                Ok(profile) => profile,
+                Err(why) => {
+                    log::warn!("Not switching profile automatically: {}", why);
+                    return;
+                }
+            };
+
+            if !transition && this.power_profile == profile {
+                return;
+            }
+
+            this.auto_profile.last_trigger = Some(Trigger {
+                on_ac,
+                profile: profile.clone(),
+                time: std::time::SystemTime::now(),
+            });
+
+            log::info!(
+                "Running on {}, switching to {} profile",
+                if on_ac { "AC" } else { "battery" },
+                profile
+            );
+
+            if this.low_battery.defer(&profile) {
+                return;
+            }
+
+            if this.holds.select(&profile) {
+                log::info!("Profiles are held, switching once they are released");
+                return;
+            }
+
+            profile
+        };
+
+        if let Some(profile) = tunables::find(&profile) {
+            if let Err(why) = self.set_profile(context, &profile, INITIATOR_POWER_SOURCE).await {
+                log::warn!("Failed to switch profile automatically: {}", why);
+            }
+        }
+    }
+
+    /// Sets the current profile again if the cap of the frequency on battery changed, as the
+    /// power source did, unless a profile switched to already applied it.
+    async fn refresh_battery_cap(&self) {
+        let mut this = self.0.lock().await;
+        let cap = this.config.battery_cap.cap(this.auto_profile.on_ac);
+        if cap == this.battery_cap {
+            return;
+        }
+
+        match cap {
+            Some(percent) => {
+                log::info!("Running on battery, capping the CPU frequency to {}%", percent)
+            }
+            None => log::info!("Removing the cap of the CPU frequency"),
+        }
+
+        if let Some(profile) = tunables::find(&this.power_profile) {
+            if let Err(why) = this.run_profile_with_cap(&profile, cap) {
+                log::warn!("Error setting profile {}: {}", profile.name, why);
+            }
+        }
+    }
+
+    /// Accepts or undoes changes of the files of a switch made by other tools, depending on
+    /// the settings. Accepted changes are announced as a change of the configured mode.
+    async fn refresh_mode_files(&self, context: &zbus::SignalContext<'_>) {
+        // Switches write the files themselves, and record them once done.
+        if Operation::running() {
+            return;
+        }
+
+        let files = ModeFiles::read();
+        let (old_files, graphics, restore) = {
+            let mut this = self.0.lock().await;
+            if this.mode_files == files {
+                return;
+            }
+
+            let old_files = std::mem::replace(&mut this.mode_files, files.clone());
+            (old_files, this.graphics.clone(), this.config.graphics.restore_external_edits)
+        };
+
+        let old = graphics.configured_mode(&old_files).to_string();
+        let new = graphics.configured_mode(&files).to_string();
+        log::warn!(
+            operation = "external_edit";
+            "External change of the graphics configuration detected, configured mode {} -> {}: {}",
+            old,
+            new,
+            old_files.diff(&files)
+        );
+
+        if restore {
+            match old_files.restore() {
+                Ok(()) => {
+                    log::info!("Restored the graphics configuration for {}", old);
+                    self.0.lock().await.mode_files = old_files;
+                    return;
+                }
+                Err(why) => log::warn!("Failed to restore the graphics configuration: {}", why),
+            }
+        }
+
+        let _res = self.graphics_mode_changed(context).await;
+        let _res = self.configured_graphics_mode_changed(context).await;
+        if let Err(why) = Self::mode_changed(context, &old, &new, true).await {
+            log::warn!("failed to emit ModeChanged signal: {}", why);
+        }
+    }
+
+    /// Recomputes whether external displays require the dGPU, announcing any change.
+    async fn refresh_external_displays_require_dgpu(&self, context: &zbus::SignalContext<'_>) {
+        {
+            let mut this = self.0.lock().await;
+            let required = this.graphics.get_external_displays_require_dgpu().unwrap_or(false);
+            if this.external_displays_require_dgpu == required {
+                return;
+            }
+            this.external_displays_require_dgpu = required;
+        }
+
+        let _res = self.external_displays_require_dgpu_changed(context).await;
+    }
+
+    /// Switches the graphics mode within the method call.
+    async fn set_graphics_and_wait(
+        &self,
+        context: &zbus::SignalContext<'_>,
+        vendor: GraphicsMode,
+        force: bool,
+    ) -> Result<(), PowerError> {
+        let (_, task) = self.spawn_graphics_switch(context, vendor, force, false).await?;
+
+        match task.await {
+            Ok(result) => result.map(|_| ()),
+            Err(why) => Err(zbus_error_from_display(why).into()),
+        }
+    }
+
+    /// Detects the other power managers running, announcing changes of the `Conflicts`
+    /// property.
+    async fn refresh_conflicts(&self, context: &zbus::SignalContext<'_>) {
+        let owns_power_profiles = self.owns_power_profiles().await;
+        if self.0.lock().await.detect_conflicts(owns_power_profiles) {
+            let _res = self.conflicts_changed(context).await;
+        }
+    }
+
+    /// Whether the daemon owns the bus name of power-profiles-daemon.
+    async fn owns_power_profiles(&self) -> bool {
+        let Some((_, upp, _)) = self.0.lock().await.connections.clone() else { return false };
+        let Ok(name) = zbus::names::BusName::try_from(POWER_PROFILES_DBUS_NAME) else {
+            return false;
+        };
+
+        let owner = match zbus::fdo::DBusProxy::new(&upp).await {
+            Ok(proxy) => proxy.get_name_owner(name).await,
+            Err(why) => Err(why.into()),
+        };
+
+        match (owner, upp.unique_name()) {
+            (Ok(owner), Some(unique)) => owner.as_str() == unique.as_str(),
+            _ => false,
+        }
+    }
+
+    /// Re-reads the config files, detects the other power managers again, and re-applies the
+    /// current profile.
+    async fn reload(&self) {
+        log::info!("Reloading configuration");
+        sd_notify::status("Reloading configuration");
+
+        let owns_power_profiles = self.owns_power_profiles().await;
+        let mut this = self.0.lock().await;
+
+        match config::load(GRAPHICS_CONFIG) {
+            Ok(config) => this.graphics.config = config,
+            Err(why) => log::warn!("keeping previous graphics config: {}", why),
+        }
+
+        // The profiles of the settings may be custom profiles, which must be known first.
+        if let Err(why) = tunables::load() {
+            log::warn!("keeping previous profile tunables: {}", why);
+        }
+
+        match DaemonConfig::load() {
+            Ok(config) => this.config = config,
+            Err(why) => log::warn!("keeping previous daemon config: {}", why),
+        }
+
+        this.graphics.strict_driver_check = this.config.graphics.strict_driver_check;
+
+        match load_charge_profiles() {
+            Ok(profiles) => this.charge_profiles = profiles,
+            Err(why) => log::warn!("keeping previous charge profiles: {}", why),
+        }
+
+        let conflicts_changed = this.detect_conflicts(owns_power_profiles);
+
+        if let Some(profile) = tunables::find(&this.power_profile) {
+            log::info!("Re-applying {} profile", this.power_profile);
+            if let Err(why) = this.run_profile(&profile) {
+                log::warn!("Error re-applying profile: {}", why);
+            }
+        }
+
+        let connection = this.connections.as_ref().map(|(connection, ..)| connection.clone());
+        drop(this);
+
+        if let Some(connection) = connection.filter(|_| conflicts_changed) {
+            if let Ok(context) = zbus::SignalContext::new(&connection, DBUS_PATH) {
+                let _res = self.conflicts_changed(&context).await;
+            }
+        }
+
+        idle::settings_changed();
+        schedule::settings_changed();
+        low_battery::wake();
+        log::info!("Reloaded configuration");
+        sd_notify::status(STATUS_IDLE);
+    }
+
+    /// Re-applies the graphics power, the profile and the charge thresholds, which firmware
+    /// may have reset during suspend.
+    async fn reapply_after_resume(&self, context: &zbus::SignalContext<'_>) {
+        let (graphics, graphics_power) = {
+            let this = self.0.lock().await;
+            (
+                this.graphics.clone(),
+                this.state.graphics_power.clone().unwrap_or_else(|| "auto".into()),
+            )
+        };
+
+        let on_off = |power: bool| if power { "on" } else { "off" };
+        match Operation::start("re-applying graphics power after resume".into()) {
+            Ok(_operation) if graphics.can_switch() => {
+                let before = graphics.get_power().ok();
+                match apply_graphics_power(&graphics, &graphics_power) {
+                    Ok(after) => match before {
+                        Some(before) if before != after => log::warn!(
+                            "Discrete GPU was powered {} after resume, now {} as set to {}",
+                            on_off(before),
+                            on_off(after),
+                            graphics_power
+                        ),
+                        _ => (),
+                    },
+                    Err(why) => log::warn!("Failed to re-apply graphics power: {}", why),
+                }
+            }
+            Ok(_operation) => (),
+            Err(why) => log::info!("Not re-applying graphics power: {}", why),
+        }
+
+        {
+            let mut this = self.0.lock().await;
+            let this = &mut *this;
+            if let Some(profile) = tunables::find(&this.power_profile) {
+                log::info!("Re-applying {} profile after resume", this.power_profile);
+                if let Err(why) = this.run_profile(&profile) {
+                    log::warn!("Error re-applying profile: {}", why);
+                }
+            }
+        }
+
+        let _res = self.graphics_power_changed(context).await;
+        self.reapply_charge_thresholds(context, "after resume").await;
+    }
+
+    /// Re-applies the charge thresholds last requested to the batteries holding others, as
+    /// firmware may reset them, unless disabled. Batteries without a start threshold only
+    /// compare their end. `when` tells the event, such as `after resume`.
+    async fn reapply_charge_thresholds(&self, context: &zbus::SignalContext<'_>, when: &str) {
+        let state = {
+            let this = self.0.lock().await;
+            // The thresholds of a battery being calibrated are lifted until it ends.
+            if !this.config.charge_thresholds.reapply || this.calibration.running() {
+                return;
+            }
+            this.state.clone()
+        };
+
+        let mut reset = false;
+        let batteries = get_charge_thresholds_status(&[]).map(|status| status.batteries);
+        for (battery, current) in batteries.unwrap_or_default() {
+            match state.charge_thresholds_of(&battery) {
+                Some(thresholds) if !holds(&current, rounded_thresholds(&battery, thresholds)) => {
+                    log::warn!(
+                        "Charge thresholds of {} were {:?} {}, re-applying {:?}",
+                        battery,
+                        (current.start, current.end),
+                        when,
+                        thresholds
+                    );
+                    reset = true;
+                }
+                _ => (),
+            }
+        }
+
+        if reset {
+            match restore_charge_thresholds(&state) {
+                Ok(writes) => log_battery_failures(&writes),
+                Err(why) => log::warn!("Failed to re-apply charge thresholds: {}", why),
+            }
+        }
+
+        self.refresh_charge_thresholds(context, INITIATOR_SYSTEM).await;
+    }
+
+    /// Applies the graphics power of the previous run, or the automatic power if enabled at
+    /// startup. This may rescan the PCI bus, so it runs on a blocking thread while queries are
+    /// answered.
+    async fn apply_startup_graphics_power(&self, context: &zbus::SignalContext<'_>) {
+        let (graphics, power) = {
+            let this = self.0.lock().await;
+            let power = match this.state.graphics_power {
+                Some(ref power) => power.clone(),
+                None if this.config.startup.auto_power => "auto".into(),
+                None => return,
+            };
+
+            (this.graphics.clone(), power)
+        };
+
+        let _operation = match Operation::start(format!("applying the graphics power {}", power)) {
+            Ok(operation) => operation,
+            Err(why) => {
+                log::info!("Not applying the graphics power at startup: {}", why);
+                return;
+            }
+        };
+
+        log::info!("Applying graphics power {}", power);
+        let result =
+            tokio::task::spawn_blocking(move || apply_graphics_power(&graphics, &power)).await;
+
+        match result {
+            Ok(Ok(_)) => (),
+            Ok(Err(why)) => log::warn!("Failed to apply the graphics power: {}", why),
+            Err(why) => log::warn!("Failed to apply the graphics power: {}", why),
+        }
+
+        let _res = self.graphics_power_changed(context).await;
+    }
+
+    /// Switches to the mode recommended for the model on the first run, if no mode was ever
+    /// configured. The switch is recorded in the state, so that it is never repeated.
+    async fn apply_default_graphics(&self, context: &zbus::SignalContext<'_>) {
+        let default = {
+            let mut this = self.0.lock().await;
+            if let Some(ref mode) = this.state.default_graphics {
+                log::debug!("Default graphics mode {} was applied on a previous run", mode);
+                return;
+            }
+
+            let files = &this.mode_files;
+            if files.prime_discrete.is_some() || files.modprobe.is_some() {
+                log::debug!("A graphics mode is configured, not applying the default");
+                return;
+            }
+
+            if !this.graphics.can_switch() {
+                return;
+            }
+
+            let default = match this.graphics.get_default_graphics() {
+                Ok(default) => default.mode,
+                Err(why) => {
+                    log::warn!("Failed to determine the default graphics mode: {}", why);
+                    return;
+                }
+            };
+
+            // Recorded before switching, so that a failed or interrupted switch is not retried
+            // on every boot.
+            let mode = <&'static str>::from(default);
+            this.remember(|state| state.default_graphics = Some(mode.to_owned()));
+            default
+        };
+
+        log::warn!(
+            operation = "default_graphics", mode = <&'static str>::from(default);
+            "No graphics mode was ever configured, applying the default of this model, {}. Set \
+             apply_default_graphics = false in /etc/system76-power/daemon.toml to prevent this",
+            default
+        );
+
+        let task = match self.spawn_graphics_switch(context, default, false, false).await {
+            Ok((_, task)) => task,
+            Err(why @ PowerError::TryAgainLater(_)) => {
+                // Retried on the next start, since the package transaction will have finished.
+                log::warn!("Not applying the default graphics mode yet: {}", why);
+                self.0.lock().await.remember(|state| state.default_graphics = None);
+                return;
+            }
+            Err(why) => {
+                log::warn!("Failed to apply the default graphics mode: {}", why);
+                return;
+            }
+        };
+
+        tokio::spawn(async move {
+            match task.await {
+                Ok(Ok(_)) => log::warn!(
+                    operation = "default_graphics", mode = <&'static str>::from(default);
+                    "Applied the default graphics mode, {}, which takes effect after a reboot",
+                    default
+                ),
+                Ok(Err(why)) => log::warn!("Failed to apply the default graphics mode: {}", why),
+                Err(why) => log::warn!("Failed to apply the default graphics mode: {}", why),
+            }
+        });
+    }
+
+    /// Stops serving clients, and waits for an in-flight operation to finish.
+    async fn shutdown(&self) {
+        {
+            let mut this = self.0.lock().await;
+            this.stop_calibration();
+            let profile =
+                tunables::find(&this.power_profile).filter(|_| this.battery_cap.is_some());
+            if let Some(profile) = profile {
+                log::info!("Removing the cap of the CPU frequency");
+                if let Err(why) = this.run_profile_with_cap(&profile, None) {
+                    log::warn!("Error setting profile {}: {}", profile.name, why);
+                }
+            }
+        }
+
+        let connections = self.0.lock().await.connections.clone();
+
+        if let Some((connection, upp, hadess)) = connections {
+            log::info!("Releasing bus names");
+            let _res = connection.release_name(DBUS_NAME).await;
+            let _res = upp.release_name(POWER_PROFILES_DBUS_NAME).await;
+            let _res = hadess.release_name(NET_HADESS_POWER_PROFILES_DBUS_NAME).await;
+        }
+
+        log::info!("Waiting for in-flight operations");
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, Operation::wait()).await.is_err() {
+            log::warn!("Timed out waiting for in-flight operations");
+        }
+    }
+
+    pub async fn emit_active_profile_changed(&self) {
+        let (upp_connection, hadess_connection, profile) = {
+            let this = self.0.lock().await;
+            let Some((_, upp, hadess)) = this.connections.clone() else { return };
+
+            let profile = system76_profile_to_upp_str(&this.power_profile);
+            (upp, hadess, profile)
+        };
+
+        let value = zvariant::Value::Str(zvariant::Str::from(profile));
+        let changed = HashMap::from_iter(std::iter::once(("ActiveProfile", &value)));
+        let invalidated = &[];
+
+        if let Ok(context) = zbus::SignalContext::new(&upp_connection, POWER_PROFILES_DBUS_PATH) {
+            let _res = zbus::fdo::Properties::properties_changed(
+                &context,
+                UPowerPowerProfiles::name(),
+                &changed,
+                invalidated,
+            )
+            .await;
+        }
+
+        if let Ok(context) =
+            zbus::SignalContext::new(&hadess_connection, NET_HADESS_POWER_PROFILES_DBUS_PATH)
+        {
+            let _res = zbus::fdo::Properties::properties_changed(
+                &context,
+                NetHadessPowerProfiles::name(),
+                &changed,
+                invalidated,
+            )
+            .await;
+        }
+    }
+}
+
+#[zbus::dbus_interface(name = "com.system76.PowerDaemon")]
+impl System76Power {
+    async fn battery(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+            let profile = tunables::builtin(Profile::Battery);
+            self.request_profile(&context, &profile, &sender(&header), false).await.map(drop)
+        };
+        self.audited(connection, &header, "Battery", String::new(), action).await
+    }
+
+    async fn balanced(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+            let profile = tunables::builtin(Profile::Balanced);
+            self.request_profile(&context, &profile, &sender(&header), false).await.map(drop)
+        };
+        self.audited(connection, &header, "Balanced", String::new(), action).await
+    }
+
+    async fn performance(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+            let profile = tunables::builtin(Profile::Performance);
+            self.request_profile(&context, &profile, &sender(&header), false).await.map(drop)
+        };
+        self.audited(connection, &header, "Performance", String::new(), action).await
+    }
+
+    /// Sets a profile by name, built in or custom, with flags: 2 only plans the change, without
+    /// authorization, and 4 applies it without saving it, so that the saved profile is restored
+    /// on startup.
+    /// Replies with the values written and the commands run, or those planned.
+    #[dbus_interface(out_args("actions"))]
+    async fn set_profile_with_flags(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        profile: &str,
+        flags: u32,
+    ) -> Result<Vec<PlannedAction>, PowerError> {
+        check_flags(flags, FLAG_DRY_RUN | FLAG_TEMPORARY)?;
+        let parsed = parse_profile(profile)?;
+
+        if flags & FLAG_DRY_RUN != 0 {
+            let set_brightness = self.0.lock().await.set_brightness();
+            return Ok(profiles::plan(&parsed, set_brightness));
+        }
+
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+            let temporary = flags & FLAG_TEMPORARY != 0;
+            self.request_profile(&context, &parsed, &sender(&header), temporary).await?;
+            Ok(self.0.lock().await.applied.written.iter().map(Written::planned).collect())
+        };
+
+        let args = format!("{} flags={}", profile, flags);
+        self.audited(connection, &header, "SetProfileWithFlags", args, action).await
+    }
+
+    #[dbus_interface(out_args("profile"))]
+    async fn get_profile(&self) -> zbus::fdo::Result<String> {
+        Ok(self.0.lock().await.power_profile.clone())
+    }
+
+    /// The profile applied when the daemon starts, which differs from the active one after a
+    /// temporary switch, or while profiles are held.
+    #[dbus_interface(out_args("profile"))]
+    async fn get_persisted_profile(&self) -> zbus::fdo::Result<String> {
+        Ok(self.0.lock().await.persisted_profile().to_owned())
+    }
+
+    /// The profile, with the parameters it set and the values they hold now, to tell those
+    /// which another tool changed since, then those the hardware lacks, with empty values.
+    #[dbus_interface(out_args("status"))]
+    async fn get_profile_status(&self) -> zbus::fdo::Result<ProfileStatus> {
+        let this = self.0.lock().await;
+        Ok(ProfileStatus {
+            profile:    this.power_profile.clone(),
+            parameters: this.applied.parameters(),
+        })
+    }
+
+    /// The parameters which the last profile failed to set, although it set most others, with
+    /// the error of the OS.
+    #[dbus_interface(out_args("failures"))]
+    async fn get_profile_failures(&self) -> zbus::fdo::Result<Vec<ProfileFailure>> {
+        Ok(self.0.lock().await.applied.failures())
+    }
+
+    /// The scaling driver, governors, boost and frequencies of the CPUs, as cpufreq reports
+    /// them, whatever set them. Values are empty without cpufreq, such as on virtual machines.
+    #[dbus_interface(out_args("status"))]
+    async fn get_cpu_frequency(&self) -> zbus::fdo::Result<CpuFrequencyStatus> {
+        Ok(cpufreq::status())
+    }
+
+    /// Estimates of the power drawn, in watts, from two readings half a second apart:
+    /// `package_watts` by the CPU package, from RAPL or amd_energy, and `battery_watts` by the
+    /// batteries while discharging. Estimates which cannot be made are left out.
+    #[dbus_interface(out_args("watts"))]
+    async fn get_power_draw(&self) -> zbus::fdo::Result<BTreeMap<String, f64>> {
+        let first = power_draw::Sample::read();
+        tokio::time::sleep(power_draw::INTERVAL).await;
+        let watts = first.estimate(&power_draw::Sample::read());
+        Ok(watts.into_iter().map(|(key, watts)| (key.to_owned(), watts)).collect())
+    }
+
+    /// The system batteries, with their charge, status, cycle count, capacities, model, charge
+    /// thresholds and rate. Attributes which cannot be read are empty, or -1.
+    #[dbus_interface(out_args("batteries"))]
+    async fn get_batteries(&self) -> zbus::fdo::Result<Vec<BatteryStatus>> {
+        Ok(power_supply::batteries())
+    }
+
+    /// The other power managers running, such as TLP, which also set some of the parameters of
+    /// the profiles, detected when the daemon starts and reloads.
+    #[dbus_interface(out_args("conflicts"))]
+    async fn get_conflicts(&self) -> zbus::fdo::Result<Vec<PowerConflict>> {
+        Ok(self.0.lock().await.conflicts.iter().map(|manager| manager.status()).collect())
+    }
+
+    /// The profiles, built in then custom, with the key parameters they set on this machine.
+    #[dbus_interface(out_args("profiles"))]
+    async fn get_profiles(&self) -> zbus::fdo::Result<Vec<ProfileInfo>> {
+        Ok(tunables::all().iter().map(describe).collect())
+    }
+
+    /// The parameters of the profiles which `profiles.toml` may override, with the values they
+    /// set on this machine, and whether they were overridden.
+    #[dbus_interface(out_args("tunables"))]
+    async fn get_profile_tunables(&self) -> zbus::fdo::Result<Vec<ProfileTunable>> {
+        Ok(tunables::all().iter().flat_map(profiles::tunables).collect())
+    }
+
+    /// Settings and last trigger of the automatic profile switching on AC/battery transitions.
+    #[dbus_interface(out_args("status"))]
+    async fn get_auto_profile(&self) -> zbus::fdo::Result<AutoProfileStatus> {
+        let this = self.0.lock().await;
+        Ok(this.auto_profile.status(&this.config.auto_profile))
+    }
+
+    /// The time ranges of the schedule of profiles, the profile of the current range, and the
+    /// next transition, with its profile and its time in seconds since the Unix epoch.
+    #[dbus_interface(out_args("schedule"))]
+    async fn get_profile_schedule(&self) -> zbus::fdo::Result<ScheduleStatus> {
+        let this = self.0.lock().await;
+        Ok(this.config.schedule.status(this.schedule.next.as_ref()))
+    }
+
+    /// Settings of the switch to the battery profile while the battery is low, whether it is
+    /// forced now, and the profile to restore once the battery recovers.
+    #[dbus_interface(out_args("status"))]
+    async fn get_low_battery(&self) -> zbus::fdo::Result<LowBatteryStatus> {
+        let this = self.0.lock().await;
+        Ok(this.low_battery.status(&this.config.low_battery))
+    }
+
+    /// Settings of the cap of the CPU frequency on battery, and whether the profile was set with
+    /// it.
+    #[dbus_interface(out_args("status"))]
+    async fn get_battery_frequency_cap(&self) -> zbus::fdo::Result<BatteryCapStatus> {
+        let this = self.0.lock().await;
+        Ok(this.config.battery_cap.status(this.battery_cap))
+    }
+
+    /// Holds `Battery`, `Quiet`, `Balanced` or `Performance` until the hold is released with
+    /// the returned cookie, or the client leaves the bus. While profiles are held, `Battery`
+    /// takes priority over `Performance`, which takes priority over `Quiet` and `Balanced`.
+    #[dbus_interface(out_args("cookie"))]
+    async fn hold_profile(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+    ) -> Result<u32, PowerError> {
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+
+            parse_builtin_profile(profile)?;
+            Ok(self.hold(&context, profile, reason, application_id, &sender(&header)).await)
+        };
+
+        let request = format!("{}, {:?}, {:?}", profile, reason, application_id);
+        self.audited(connection, &header, "HoldProfile", request, action).await
+    }
+
+    /// Releases a hold of the client. Holds of other clients cannot be released.
+    async fn release_profile(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        cookie: u32,
+    ) -> Result<(), PowerError> {
+        let sender = sender(&header);
+        let action = self.release_own(&context, cookie, &sender);
+        self.audited(connection, &header, "ReleaseProfile", cookie.to_string(), action).await
+    }
+
+    #[dbus_interface(out_args("holds"))]
+    async fn get_active_holds(&self) -> zbus::fdo::Result<Vec<ProfileHold>> {
+        Ok(self.0.lock().await.holds.active().to_vec())
+    }
+
+    /// The latest changes requested by clients, oldest first, with their outcome.
+    #[dbus_interface(out_args("actions"))]
+    async fn get_recent_actions(&self) -> zbus::fdo::Result<Vec<RecentAction>> {
+        Ok(self.0.lock().await.audit.recent())
+    }
+
+    /// The version of the daemon, and the revision of its interface, which is incremented
+    /// whenever methods, signals or properties are added, so that clients may check for them.
+    ///
+    /// Revisions:
+    /// - 1: the interface with `GetVersion`, including graphics switch jobs, profile holds,
+    ///   `GetConfig`, `GetProfileStatus` and `ConfiguredGraphicsMode`.
+    /// - 2: `SetIdleProfile`.
+    /// - 3: `GetGraphicsRecommendation`.
+    /// - 4: `GetRecentActions`.
+    /// - 5: `GetGraphicsStatus`.
+    /// - 6: `GetProfiles`.
+    /// - 7: `GetChargeThresholdsStatus`.
+    /// - 8: `SetGraphicsPowerStateWithFlags`.
+    /// - 9: `GetSwitchableStatus`.
+    /// - 10: `SetProfileWithFlags`, `StartGraphicsSwitchWithFlags`, `SetChargeThresholdsWithFlags`,
+    ///   and dry runs of `SetGraphicsPowerStateWithFlags`.
+    /// - 11: `GetPersistedProfile`, and temporary profiles with `SetProfileWithFlags`.
+    /// - 12: `GetGraphicsPowerStatus`.
+    /// - 13: skipping the initramfs rebuild with `StartGraphicsSwitchWithFlags`.
+    /// - 14: `GetProfileTunables`.
+    /// - 15: custom profiles of `profiles.toml` with `SetProfileWithFlags`.
+    /// - 16: the parameters the hardware lacks in `GetProfileStatus`.
+    /// - 17: `GetProfileFailures`.
+    /// - 18: `GetProfileSchedule`.
+    /// - 19: `GetLowBattery` and `SetLowBattery`.
+    /// - 20: the `Quiet` profile.
+    /// - 21: `GetCpuFrequency`.
+    /// - 22: `GetPowerDraw`.
+    /// - 23: `GetConflicts` and the `Conflicts` property.
+    /// - 24: `GetBatteryFrequencyCap`.
+    /// - 25: `SetBatteryChargeThresholds`.
+    /// - 26: `ChargeThresholdsSwitched`.
+    /// - 27: `StartCalibration`, `CancelCalibration`, `GetCalibration` and the `Calibration`
+    ///   property.
+    /// - 28: `GetBatteries`.
+    /// - 29: `ApplyBatteryChargeThresholds`.
+    #[dbus_interface(out_args("version", "api_version"))]
+    async fn get_version(&self) -> (String, u32) {
+        (env!("CARGO_PKG_VERSION").to_owned(), system76_power_zbus::API_VERSION)
+    }
+
+    /// The effective settings of `daemon.toml`, including the defaults of unset keys, as TOML.
+    #[dbus_interface(out_args("config"))]
+    async fn get_config(&self) -> Result<String, PowerError> {
+        toml::to_string(&self.0.lock().await.config)
+            .map_err(|why| PowerError::Failed(format!("failed to serialize config: {}", why)))
+    }
+
+    /// Enables or disables the automatic profile switching, and saves the setting.
+    async fn set_auto_profile(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        enabled: bool,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+
+            {
+                let mut this = self.0.lock().await;
+                this.config.auto_profile.enabled = enabled;
+                this.config.save().map_err(zbus_error_from_display)?;
+            }
+
+            let state = if enabled { "enabled" } else { "disabled" };
+            log::info!("Automatic profile switching {}", state);
+            if enabled {
+                self.refresh_power_source(&context, true).await;
+            }
+
+            Ok(())
+        };
+
+        self.audited(connection, &header, "SetAutoProfile", enabled.to_string(), action).await
+    }
+
+    /// Enables or disables switching to the idle profile of `daemon.toml` while every session
+    /// is idle, and saves the setting.
+    async fn set_idle_profile(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        enabled: bool,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+
+            {
+                let mut this = self.0.lock().await;
+                this.config.idle.enabled = enabled;
+                this.config.save().map_err(zbus_error_from_display)?;
+            }
+
+            log::info!("Idle profile switching {}", if enabled { "enabled" } else { "disabled" });
+            idle::settings_changed();
+            Ok(())
+        };
+
+        self.audited(connection, &header, "SetIdleProfile", enabled.to_string(), action).await
+    }
+
+    /// Enables or disables switching to the battery profile while the battery is under
+    /// `threshold_percent`, and saves the settings.
+    async fn set_low_battery(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        enabled: bool,
+        threshold_percent: u8,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+
+            {
+                let mut this = self.0.lock().await;
+                let config = LowBatteryConfig {
+                    enabled,
+                    threshold_percent,
+                    ..this.config.low_battery.clone()
+                };
+                if let Err((key, why)) = config.validate() {
+                    let message = format!("invalid {}: {}", key, why);
+                    return Err(zbus::fdo::Error::InvalidArgs(message).into());
+                }
+
+                this.config.low_battery = config;
+                this.config.save().map_err(zbus_error_from_display)?;
+            }
+
+            let state = if enabled { "enabled" } else { "disabled" };
+            log::info!("Low battery profile switching {} at {}%", state, threshold_percent);
+            low_battery::wake();
+            Ok(())
+        };
+
+        let request = format!("{} {}", enabled, threshold_percent);
+        self.audited(connection, &header, "SetLowBattery", request, action).await
+    }
+
+    #[dbus_interface(out_args("required"))]
+    async fn get_external_displays_require_dgpu(&mut self) -> Result<bool, PowerError> {
+        self.0.lock().await.graphics.get_external_displays_require_dgpu().map_err(PowerError::from)
+    }
+
+    #[dbus_interface(out_args("vendor"))]
+    async fn get_default_graphics(&self) -> Result<String, PowerError> {
+        self.0
+            .lock()
+            .await
+            .graphics
+            .get_default_graphics()
+            .map_err(PowerError::from)
+            .map(|default| <&'static str>::from(default.mode).to_owned())
+    }
+
+    /// The active and configured modes, the power state of the discrete GPU, and whether a
+    /// reboot is required for the configured mode to take effect.
+    #[dbus_interface(out_args("status"))]
+    async fn get_graphics_status(&self) -> Result<GraphicsStatus, PowerError> {
+        self.0.lock().await.graphics.status().map_err(PowerError::from)
+    }
+
+    /// The mode recommended for this model, and a code of how it was derived:
+    /// `vendor-not-system76`, `runtimepm-supported`, `runtimepm-unsupported`,
+    /// `model-blacklisted` or `no-driver`.
+    #[dbus_interface(out_args("vendor", "reason"))]
+    async fn get_graphics_recommendation(&self) -> Result<(String, String), PowerError> {
+        let default = self.0.lock().await.graphics.get_default_graphics()?;
+        Ok((<&'static str>::from(default.mode).to_owned(), default.reason.code().to_owned()))
+    }
+
+    #[dbus_interface(out_args("vendor"))]
+    async fn get_graphics(&self) -> Result<String, PowerError> {
+        self.0
+            .lock()
+            .await
+            .graphics
+            .get_vendor()
+            .map_err(PowerError::from)
+            .map(|mode| <&'static str>::from(mode).to_owned())
+    }
+
+    async fn set_graphics(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        vendor: &str,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+            self.set_graphics_and_wait(&context, graphics_mode(vendor)?, false).await
+        };
+        self.audited(connection, &header, "SetGraphics", vendor.to_owned(), action).await
+    }
+
+    /// Like `SetGraphics`, but switches even if processes are using the dGPU.
+    async fn set_graphics_force(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        vendor: &str,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+            self.set_graphics_and_wait(&context, graphics_mode(vendor)?, true).await
+        };
+        self.audited(connection, &header, "SetGraphicsForce", vendor.to_owned(), action).await
+    }
+
+    /// Starts switching the graphics mode, replying with the path of the job performing it.
+    #[dbus_interface(out_args("job"))]
+    async fn start_graphics_switch(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        vendor: &str,
+        force: bool,
+    ) -> Result<zvariant::OwnedObjectPath, PowerError> {
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+            let (job, _) =
+                self.spawn_graphics_switch(&context, graphics_mode(vendor)?, force, false).await?;
+            Ok(job)
+        };
+
+        let request = format!("{}, {}", vendor, force);
+        self.audited(connection, &header, "StartGraphicsSwitch", request, action).await
+    }
+
+    /// Like StartGraphicsSwitch, with flags: 1 forces the switch, 2 only plans it, without
+    /// authorization, and 8 does not rebuild the initramfs, which must then be rebuilt before
+    /// rebooting. Replies with the path of the job, or `/` for a dry run, and the actions
+    /// planned, which are only listed for a dry run.
+    #[dbus_interface(out_args("job", "actions"))]
+    async fn start_graphics_switch_with_flags(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        vendor: &str,
+        flags: u32,
+    ) -> Result<(zvariant::OwnedObjectPath, Vec<PlannedAction>), PowerError> {
+        check_flags(flags, FLAG_FORCE | FLAG_DRY_RUN | FLAG_NO_INITRAMFS)?;
+        let mode = graphics_mode(vendor)?;
+        let force = flags & FLAG_FORCE != 0;
+        let skip_initramfs = flags & FLAG_NO_INITRAMFS != 0;
+
+        if flags & FLAG_DRY_RUN != 0 {
+            let graphics = self.0.lock().await.graphics.clone();
+            let plan = graphics.plan_vendor(mode, force, skip_initramfs)?;
+            let root = zvariant::ObjectPath::from_static_str_unchecked("/");
+            return Ok((root.into(), plan));
+        }
+
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+            let (job, _) =
+                self.spawn_graphics_switch(&context, mode, force, skip_initramfs).await?;
+            Ok((job, Vec::new()))
+        };
+
+        let request = format!("{} flags={}", vendor, flags);
+        self.audited(connection, &header, "StartGraphicsSwitchWithFlags", request, action).await
+    }
+
+    /// Status of a running job, or of a recently finished one.
+    #[dbus_interface(out_args("status"))]
+    async fn get_job(&self, job: zvariant::ObjectPath<'_>) -> zbus::fdo::Result<JobStatus> {
+        jobs::status(job.as_str())
+            .ok_or_else(|| zbus::fdo::Error::UnknownObject(format!("no such job: {}", job)))
+    }
+
+    async fn set_graphics_option(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        option: &str,
+        value: &str,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+            let _operation =
+                Operation::start(format!("a change of the graphics option {}", option))?;
+            let settings = self.0.lock().await.config.graphics.clone();
+            if package_transaction::guard(connection, &settings, "set_option").await? {
+                let waiting = |_| async {};
+                package_transaction::wait(connection, &settings, "set_option", waiting).await?;
+            }
+
+            let mut graphics = self.0.lock().await.graphics.clone();
+            let result = graphics.set_option(option, value);
+            {
+                let mut this = self.0.lock().await;
+                this.graphics.config = graphics.config;
+                this.mode_files = ModeFiles::read();
+            }
+
+            let rebuild = match &result {
+                Ok(rebuild) => rebuild.as_ref(),
+                Err(why) => why.initramfs_rebuild(),
+            };
+
+            if let Some(rebuild) = rebuild {
+                Self::announce_initramfs_rebuild(&context, rebuild).await;
+            }
+
+            result.map(|_| ()).map_err(PowerError::from)
+        };
+
+        self.audited(
+            connection,
+            &header,
+            "SetGraphicsOption",
+            format!("{}, {}", option, value),
+            action,
+        )
+        .await
+    }
+
+    #[dbus_interface(out_args("capabilities"))]
+    async fn get_graphics_capabilities(&self) -> zbus::fdo::Result<GraphicsCapabilities> {
+        Ok(self.0.lock().await.graphics.capabilities())
+    }
+
+    /// GPUs on the PCI bus, read at the time of the call.
+    #[dbus_interface(out_args("devices"))]
+    async fn get_graphics_devices(&self) -> Result<Vec<GraphicsDeviceInfo>, PowerError> {
+        Graphics::devices().map_err(PowerError::from)
+    }
+
+    #[dbus_interface(out_args("desktop"))]
+    async fn get_desktop(&mut self) -> zbus::fdo::Result<bool> {
+        Ok(self.0.lock().await.graphics.is_desktop())
+    }
+
+    #[dbus_interface(out_args("switchable"))]
+    async fn get_switchable(&mut self) -> zbus::fdo::Result<bool> {
+        Ok(self.0.lock().await.graphics.can_switch())
+    }
+
+    /// Whether graphics can be switched, the reason they cannot: `desktop`, `no-discrete-gpu`
+    /// or `no-integrated-gpu`, and the GPUs on the PCI bus.
+    #[dbus_interface(out_args("status"))]
+    async fn get_switchable_status(&self) -> Result<SwitchableStatus, PowerError> {
+        self.0.lock().await.graphics.switchable_status().map_err(PowerError::from)
+    }
+
+    #[dbus_interface(out_args("power"))]
+    async fn get_graphics_power(&mut self) -> Result<bool, PowerError> {
+        self.0.lock().await.graphics.get_power().map_err(PowerError::from)
+    }
+
+    /// Whether the discrete GPU is `active`, `suspended` by runtime power management, `off`,
+    /// or `rescan-needed` after powering it on failed, with the bus ID, driver and runtime PM
+    /// status of each function present on the bus, and the power cap of the GPU in watts, or 0.
+    #[dbus_interface(out_args("status"))]
+    async fn get_graphics_power_status(&self) -> Result<GraphicsPowerStatus, PowerError> {
+        self.0.lock().await.graphics.power_status().map_err(PowerError::from)
+    }
+
+    /// Deprecated: use SetGraphicsPowerState, which also supports "auto".
+    async fn set_graphics_power(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        power: bool,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+            let state = if power { "on" } else { "off" };
+            let _operation = Operation::start(format!("powering {} the discrete GPU", state))?;
+
+            let graphics = self.0.lock().await.graphics.clone();
+            graphics.set_power(power)?;
+            self.0.lock().await.remember(|saved| saved.graphics_power = Some(state.into()));
+            let _res = self.graphics_power_changed(&context).await;
+            Ok(())
+        };
+
+        self.audited(connection, &header, "SetGraphicsPower", power.to_string(), action).await
+    }
+
+    async fn auto_graphics_power(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+            let _operation = Operation::start("automatic power of the discrete GPU".into())?;
+
+            let graphics = self.0.lock().await.graphics.clone();
+            graphics.auto_power()?;
+            self.0.lock().await.remember(|state| state.graphics_power = Some("auto".into()));
+            let _res = self.graphics_power_changed(&context).await;
+            Ok(())
+        };
+
+        self.audited(connection, &header, "AutoGraphicsPower", String::new(), action).await
+    }
+
+    /// Sets the discrete graphics power state to "on", "off" or "auto", replying with the
+    /// state that was applied.
+    #[dbus_interface(out_args("applied"))]
+    async fn set_graphics_power_state(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        state: &str,
+    ) -> Result<String, PowerError> {
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+
+            let _operation =
+                Operation::start(format!("setting the discrete GPU power to {}", state))?;
+
+            let graphics = self.0.lock().await.graphics.clone();
+            let power = apply_graphics_power(&graphics, state)?;
+            self.0.lock().await.remember(|saved| saved.graphics_power = Some(state.to_owned()));
+
+            let _res = self.graphics_power_changed(&context).await;
+            Ok(String::from(if power { "on" } else { "off" }))
+        };
+
+        self.audited(connection, &header, "SetGraphicsPowerState", state.to_owned(), action).await
+    }
+
+    /// Like SetGraphicsPowerState, with flags: 1 powers "off" the discrete GPU even though it
+    /// may be in use, stopping the services which keep it open, and 2 only plans the change,
+    /// without authorization. Replies with the state that was applied, and the actions taken
+    /// only because of the flags, or the state and every action planned for a dry run.
+    #[dbus_interface(out_args("applied", "forced"))]
+    async fn set_graphics_power_state_with_flags(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        state: &str,
+        flags: u32,
+    ) -> Result<(String, Vec<String>), PowerError> {
+        check_flags(flags, FLAG_FORCE | FLAG_DRY_RUN)?;
+        let force = flags & FLAG_FORCE != 0;
+        if force && state != "off" {
+            return Err(zbus::fdo::Error::InvalidArgs(
+                "only powering off the discrete GPU can be forced".into(),
+            )
+            .into());
+        }
+
+        if flags & FLAG_DRY_RUN != 0 {
+            let graphics = self.0.lock().await.graphics.clone();
+            let (power, plan) = graphics.plan_power(graphics_power(state)?, force)?;
+            let plan = plan.iter().map(ToString::to_string).collect();
+            return Ok((String::from(if power { "on" } else { "off" }), plan));
+        }
+
+        let action = async {
+            check_authorization(connection, &header, GRAPHICS_POLICY).await?;
+
+            let _operation =
+                Operation::start(format!("setting the discrete GPU power to {}", state))?;
+
+            let graphics = self.0.lock().await.graphics.clone();
+            let (power, forced) = if force {
+                (false, graphics.power_off(true)?)
+            } else {
+                (apply_graphics_power(&graphics, state)?, Vec::new())
+            };
+            self.0.lock().await.remember(|saved| saved.graphics_power = Some(state.to_owned()));
+
+            let _res = self.graphics_power_changed(&context).await;
+            Ok((String::from(if power { "on" } else { "off" }), forced))
+        };
+
+        let args = format!("{} flags={}", state, flags);
+        self.audited(connection, &header, "SetGraphicsPowerStateWithFlags", args, action).await
+    }
+
+    #[dbus_interface(out_args("start", "end"))]
+    async fn get_charge_thresholds(&mut self) -> Result<(u8, u8), PowerError> {
+        get_charge_thresholds().map_err(PowerError::from)
+    }
+
+    async fn set_charge_thresholds(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        thresholds: (u8, u8),
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, THRESHOLD_POLICY).await?;
+
+            self.request_charge_thresholds(&context, thresholds, None, &sender(&header)).await?;
+            Ok(())
+        };
+
+        self.audited(
+            connection,
+            &header,
+            "SetChargeThresholds",
+            format!("{}, {}", thresholds.0, thresholds.1),
+            action,
+        )
+        .await
+    }
+
+    /// Like SetChargeThresholds, with flags: 2 only plans the change, without authorization.
+    /// Replies with the values written, or those planned.
+    #[dbus_interface(out_args("actions"))]
+    async fn set_charge_thresholds_with_flags(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        thresholds: (u8, u8),
+        flags: u32,
+    ) -> Result<Vec<PlannedAction>, PowerError> {
+        check_flags(flags, FLAG_DRY_RUN)?;
+
+        if flags & FLAG_DRY_RUN != 0 {
+            let plan = plan_charge_thresholds(thresholds, None)?;
+            return Ok(plan.written.iter().map(Written::planned).collect());
+        }
+
+        let action = async {
+            check_authorization(connection, &header, THRESHOLD_POLICY).await?;
+
+            let writes = self
+                .request_charge_thresholds(&context, thresholds, None, &sender(&header))
+                .await?;
+            Ok(writes.written.iter().map(Written::planned).collect())
+        };
+
+        let args = format!("{}, {} flags={}", thresholds.0, thresholds.1, flags);
+        self.audited(connection, &header, "SetChargeThresholdsWithFlags", args, action).await
+    }
+
+    /// Like SetChargeThresholdsWithFlags, for the battery named `battery`, such as `BAT1`, or
+    /// for every battery if empty. Batteries which fail, such as those lacking thresholds, are
+    /// replied with their error while the others are set, and only if one was set.
+    #[dbus_interface(out_args("actions", "failures"))]
+    async fn set_battery_charge_thresholds(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        battery: String,
+        thresholds: (u8, u8),
+        flags: u32,
+    ) -> Result<(Vec<PlannedAction>, BTreeMap<String, String>), PowerError> {
+        check_flags(flags, FLAG_DRY_RUN)?;
+        let battery = Some(battery.as_str()).filter(|battery| !battery.is_empty());
+
+        let reply = |writes: BatteryWrites| {
+            (writes.written.iter().map(Written::planned).collect(), writes.failures())
+        };
+
+        if flags & FLAG_DRY_RUN != 0 {
+            return Ok(reply(plan_charge_thresholds(thresholds, battery)?));
+        }
+
+        let action = async {
+            check_authorization(connection, &header, THRESHOLD_POLICY).await?;
+
+            let writes = self
+                .request_charge_thresholds(&context, thresholds, battery, &sender(&header))
+                .await?;
+            Ok(reply(writes))
+        };
+
+        let args = format!(
+            "{} {}, {} flags={}",
+            battery.unwrap_or("*"),
+            thresholds.0,
+            thresholds.1,
+            flags
+        );
+        self.audited(connection, &header, "SetBatteryChargeThresholds", args, action).await
+    }
+
+    /// Like SetBatteryChargeThresholds, also replying with the thresholds applied to each
+    /// battery set, keyed by name, which are rounded to the steps its firmware accepts, with a
+    /// start of 0 for batteries without a start threshold.
+    #[allow(clippy::type_complexity)]
+    #[dbus_interface(out_args("actions", "applied", "failures"))]
+    async fn apply_battery_charge_thresholds(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+        battery: String,
+        thresholds: (u8, u8),
+        flags: u32,
+    ) -> Result<
+        (Vec<PlannedAction>, BTreeMap<String, (u8, u8)>, BTreeMap<String, String>),
+        PowerError,
+    > {
+        check_flags(flags, FLAG_DRY_RUN)?;
+        let battery = Some(battery.as_str()).filter(|battery| !battery.is_empty());
+
+        let reply = |writes: BatteryWrites| {
+            let failures = writes.failures();
+            (writes.written.iter().map(Written::planned).collect(), writes.applied, failures)
+        };
+
+        if flags & FLAG_DRY_RUN != 0 {
+            return Ok(reply(plan_charge_thresholds(thresholds, battery)?));
+        }
+
+        let action = async {
+            check_authorization(connection, &header, THRESHOLD_POLICY).await?;
+
+            let writes = self
+                .request_charge_thresholds(&context, thresholds, battery, &sender(&header))
+                .await?;
+            Ok(reply(writes))
+        };
+
+        let args = format!(
+            "{} {}, {} flags={}",
+            battery.unwrap_or("*"),
+            thresholds.0,
+            thresholds.1,
+            flags
+        );
+        self.audited(connection, &header, "ApplyBatteryChargeThresholds", args, action).await
+    }
+
+    #[dbus_interface(out_args("thresholds"))]
+    async fn get_battery_charge_thresholds(
+        &mut self,
+    ) -> Result<BTreeMap<String, (u8, u8)>, PowerError> {
+        get_battery_charge_thresholds().map_err(PowerError::from)
+    }
+
+    /// The thresholds of each battery, whether the platform supports charge thresholds and
+    /// start thresholds, the range they accept, and the charge profile they match.
+    #[dbus_interface(out_args("status"))]
+    async fn get_charge_thresholds_status(&self) -> Result<ChargeThresholdsStatus, PowerError> {
+        get_charge_thresholds_status(&self.0.lock().await.charge_profiles).map_err(PowerError::from)
+    }
+
+    /// The built-in charge profiles, followed by those of `charge-profiles.toml`, with the
+    /// thresholds they set. `GetChargeThresholdsStatus` tells the one the thresholds match.
+    #[dbus_interface(out_args("profiles"))]
+    async fn get_charge_profiles(&mut self) -> zbus::fdo::Result<Vec<ChargeProfile>> {
+        Ok(self.0.lock().await.charge_profiles.clone())
+    }
+
+    /// Calibrates the first battery which can be force-discharged: its thresholds are lifted
+    /// and it is discharged down to `low_percent` of `[calibration]`, then charged full, before
+    /// its thresholds and charge behaviour are restored. Replies with its progress.
+    #[dbus_interface(out_args("status"))]
+    async fn start_calibration(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+    ) -> Result<CalibrationStatus, PowerError> {
+        let action = async {
+            check_authorization(connection, &header, THRESHOLD_POLICY).await?;
+            self.begin_calibration(&context).await
+        };
+
+        self.audited(connection, &header, "StartCalibration", String::new(), action).await
+    }
+
+    /// Cancels the calibration running, restoring the thresholds and charge behaviour of its
+    /// battery.
+    async fn cancel_calibration(
+        &mut self,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        #[zbus(signal_context)] context: zbus::SignalContext<'_>,
+    ) -> Result<(), PowerError> {
+        let action = async {
+            check_authorization(connection, &header, THRESHOLD_POLICY).await?;
+            self.cancel_running_calibration(&context, &sender(&header)).await
+        };
+
+        self.audited(connection, &header, "CancelCalibration", String::new(), action).await
+    }
+
+    /// The progress of the calibration running, or of the last one: its phase, battery,
+    /// charge, low point, and why it failed or was cancelled.
+    #[dbus_interface(out_args("status"))]
+    async fn get_calibration(&self) -> zbus::fdo::Result<CalibrationStatus> {
+        let this = self.0.lock().await;
+        Ok(this.calibration.status(&this.config.calibration))
+    }
+
+    /// The mode configured for the next boot, `custom` if its files were edited to match no
+    /// mode, or empty if no mode was configured yet.
+    #[dbus_interface(property)]
+    async fn configured_graphics_mode(&self) -> String {
+        let this = self.0.lock().await;
+        this.graphics.configured_mode(&ModeFiles::read()).to_string()
+    }
+
+    #[dbus_interface(property)]
+    async fn graphics_mode(&self) -> zbus::fdo::Result<String> {
+        self.0
+            .lock()
+            .await
+            .graphics
+            .get_vendor()
+            .map_err(zbus_error_from_display)
+            .map(|mode| <&'static str>::from(mode).to_owned())
+    }
+
+    #[dbus_interface(property)]
+    async fn graphics_power(&self) -> zbus::fdo::Result<bool> {
+        self.0.lock().await.graphics.get_power().map_err(zbus_error_from_display)
+    }
+
+    #[dbus_interface(property)]
+    async fn power_profile(&self) -> String { self.0.lock().await.power_profile.clone() }
+
+    #[dbus_interface(property)]
+    async fn switchable(&self) -> bool { self.0.lock().await.graphics.can_switch() }
+
+    #[dbus_interface(property)]
+    async fn charge_thresholds(&self) -> zbus::fdo::Result<(u8, u8)> {
+        get_charge_thresholds().map_err(zbus_error_from_display)
+    }
+
+    /// The other power managers running, as `GetConflicts` returns them: their service, why
+    /// they conflict, and the parameters of the profiles they also set.
+    #[dbus_interface(property)]
+    async fn conflicts(&self) -> Vec<(String, String, Vec<String>)> {
+        let this = self.0.lock().await;
+        let status = this.conflicts.iter().map(|manager| manager.status());
+        status.map(|conflict| (conflict.service, conflict.reason, conflict.parameters)).collect()
+    }
+
+    /// The progress of the calibration, as `GetCalibration` returns it.
+    #[dbus_interface(property)]
+    async fn calibration(&self) -> (String, String, u8, u8, String) {
+        let this = self.0.lock().await;
+        let status = this.calibration.status(&this.config.calibration);
+        (status.phase, status.battery, status.capacity, status.low_percent, status.message)
+    }
+
+    #[dbus_interface(property, name = "ExternalDisplaysRequireDgpu")]
+    async fn external_displays_require_dgpu(&self) -> bool {
+        self.0.lock().await.external_displays_require_dgpu
+    }
+
+    /// Whether a display is connected to each hotplug-detected port.
+    #[dbus_interface(property, name = "HotPlugDetect")]
+    async fn hot_plug_state(&self) -> Vec<bool> { self.0.lock().await.hot_plug.to_vec() }
+
+    #[dbus_interface(signal, name = "ChargeThresholdsChanged")]
+    async fn charge_thresholds_changed_signal(
+        context: &zbus::SignalContext<'_>,
+        thresholds: BTreeMap<String, (u8, u8)>,
+    ) -> zbus::Result<()>;
+
+    /// Like `ChargeThresholdsChanged`, with the initiator of the change: the bus name of the
+    /// client which set the thresholds, `system` for those the daemon re-applied, or `external`
+    /// for those changed outside of the daemon, such as by writing to sysfs.
+    #[dbus_interface(signal)]
+    async fn charge_thresholds_switched(
+        context: &zbus::SignalContext<'_>,
+        thresholds: &BTreeMap<String, (u8, u8)>,
+        initiator: &str,
+    ) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn hot_plug_detect(context: &zbus::SignalContext<'_>, port: u64) -> zbus::Result<()>;
+
+    /// Sent when the initramfs was rebuilt, or its rebuild was skipped as it was up to date.
+    #[dbus_interface(signal)]
+    async fn initramfs_job_completed(
+        context: &zbus::SignalContext<'_>,
+        success: bool,
+        tool: &str,
+        duration_ms: u64,
+        stderr_tail: &str,
+        skipped: bool,
+    ) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn mode_changed(
+        context: &zbus::SignalContext<'_>,
+        old: &str,
+        new: &str,
+        reboot_required: bool,
+    ) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn power_profile_switch(
+        context: &zbus::SignalContext<'_>,
+        profile: &str,
+    ) -> zbus::Result<()>;
+
+    /// `initiator` is the bus name of the client which set the profile, `system` for changes
+    /// made by the daemon itself, or empty if the client is unknown.
+    #[dbus_interface(signal)]
+    async fn power_profile_switched(
+        context: &zbus::SignalContext<'_>,
+        old: &str,
+        new: &str,
+        initiator: &str,
+    ) -> zbus::Result<()>;
+
+    /// A hold was released, by its client, by the client leaving the bus, or by a profile
+    /// being set.
+    #[dbus_interface(signal)]
+    async fn profile_released(context: &zbus::SignalContext<'_>, cookie: u32) -> zbus::Result<()>;
+}
+
+#[derive(Clone)]
+struct UPowerPowerProfiles(Arc<Mutex<PowerDaemon>>);
+
+impl UPowerPowerProfiles {
+    /// The daemon, with a context for the signals of its interface, once it is on the bus.
+    async fn system76(&self) -> zbus::fdo::Result<(System76Power, zbus::SignalContext<'static>)> {
+        let this = self.0.lock().await;
+        let Some((ref connection, ..)) = this.connections else {
+            return Err(zbus::fdo::Error::Failed("the daemon is starting".into()));
+        };
 
-struct UPowerPowerProfiles(Arc<Mutex<PowerDaemon>>);
+        let context = zbus::SignalContext::new(connection, DBUS_PATH)?.into_owned();
+        Ok((System76Power(self.0.clone()), context))
+    }
 
-impl UPowerPowerProfiles {
-    pub async fn apply_held_profile(&mut self) {
-        let mut set_profile = "balanced";
-
-        for (_, profile, ..) in &self.0.lock().await.held_profiles {
-            match *profile {
-                "power-saver" => {
-                    set_profile = "power-saver";
-                    break;
-                }
-                "performance" => set_profile = "performance",
-                _ => (),
-            }
-        }
+    /// Sets `ActiveProfile` as `SetProfileWithFlags` sets the profile, with the same
+    /// authorization.
+    async fn set_active_profile_from(
+        &self,
+        header: &zbus::MessageHeader<'_>,
+        profile: &str,
+    ) -> zbus::fdo::Result<()> {
+        let Some(profile) = upp_str_to_system76_profile(profile).and_then(tunables::find) else {
+            return Err(zbus::fdo::Error::InvalidArgs(String::from("unknown power profile")));
+        };
+
+        let (daemon, context) = self.system76().await?;
+        let connection = context.connection();
+        let action = async {
+            check_authorization(connection, header, PROFILE_POLICY).await?;
+            daemon.request_profile(&context, &profile, &sender(header), false).await.map(drop)
+        };
 
-        self.set_active_profile(set_profile).await;
+        let request = profile.name.clone();
+        daemon
+            .audited(connection, header, "UPower.ActiveProfile", request, action)
+            .await
+            .map_err(fdo_error)
     }
 }
 
@@ -375,47 +2368,43 @@ impl UPowerPowerProfiles {
     #[dbus_interface(out_args("cookie"))]
     async fn hold_profile(
         &mut self,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
         profile: &str,
         reason: &str,
         application_id: &str,
     ) -> zbus::fdo::Result<u32> {
-        let mut this = self.0.lock().await;
-        let id = this.profile_ids;
-
-        let profile_static = match profile {
-            "power-saver" => "power-saver",
-            "balanced" => "balanced",
-            "performance" => "performance",
-            _ => return Err(zbus::fdo::Error::Failed(String::from("unknown power profile"))),
+        let Some(profile) = upp_str_to_system76_profile(profile) else {
+            return Err(zbus::fdo::Error::Failed(String::from("unknown power profile")));
         };
 
-        this.profile_ids += 1;
-        this.held_profiles.push((id, profile_static, reason.into(), application_id.into()));
-        drop(this);
-
-        self.apply_held_profile().await;
+        let (daemon, context) = self.system76().await?;
+        let connection = context.connection();
+        let action = async {
+            check_authorization(connection, &header, PROFILE_POLICY).await?;
+            Ok(daemon.hold(&context, profile, reason, application_id, &sender(&header)).await)
+        };
 
-        Ok(id)
+        let request = format!("{}, {:?}, {:?}", profile, reason, application_id);
+        daemon
+            .audited(connection, &header, "UPower.HoldProfile", request, action)
+            .await
+            .map_err(fdo_error)
     }
 
-    async fn release_profile(&mut self, cookie: u32) {
-        let mut this = self.0.lock().await;
-
-        if let Some(pos) = this.held_profiles.iter().position(|(id, ..)| *id == cookie) {
-            this.held_profiles.swap_remove(pos);
-            drop(this);
-
-            self.apply_held_profile().await;
-
-            let this = self.0.lock().await;
-            let Some((_, ref connection, _)) = this.connections else {
-                return;
-            };
-
-            if let Ok(context) = zbus::SignalContext::new(connection, POWER_PROFILES_DBUS_PATH) {
-                let _res = Self::profile_released(&context, cookie);
-            }
-        }
+    async fn release_profile(
+        &mut self,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+        cookie: u32,
+    ) -> zbus::fdo::Result<()> {
+        let (daemon, context) = self.system76().await?;
+        let connection = context.connection();
+        let sender = sender(&header);
+        let action = daemon.release_own(&context, cookie, &sender);
+        let request = cookie.to_string();
+        daemon
+            .audited(connection, &header, "UPower.ReleaseProfile", request, action)
+            .await
+            .map_err(fdo_error)
     }
 
     #[dbus_interface(signal)]
@@ -426,24 +2415,10 @@ impl UPowerPowerProfiles {
         system76_profile_to_upp_str(self.0.lock().await.power_profile.as_str())
     }
 
+    /// Writable, though it is set by [`ProfilesProperties`], which knows the caller to authorize.
     #[dbus_interface(property)]
-    async fn set_active_profile(&mut self, profile: &str) {
-        let (func, profile): (fn(&mut Vec<ProfileError>, bool), &'static str) = match profile {
-            "power-saver" => (battery, "Battery"),
-            "balanced" => (balanced, "Balanced"),
-            "performance" => (performance, "Performance"),
-            _ => return,
-        };
-
-        let mut this = self.0.lock().await;
-        let Some((ref connection, ..)) = this.connections else {
-            return;
-        };
-
-        if let Ok(context) = zbus::SignalContext::new(connection, DBUS_PATH) {
-            let _res =
-                this.apply_profile(&context, func, profile).await.map_err(zbus_error_from_display);
-        }
+    async fn set_active_profile(&mut self, _profile: &str) -> zbus::fdo::Result<()> {
+        Err(unauthorized_property())
     }
 
     #[dbus_interface(property)]
@@ -474,7 +2449,21 @@ impl UPowerPowerProfiles {
     async fn performance_inhibited(&self) -> &str { "" }
 
     #[dbus_interface(property)]
-    async fn active_profile_holds(&self) -> Vec<HashMap<String, zvariant::Value>> { Vec::new() }
+    async fn active_profile_holds(&self) -> Vec<HashMap<String, zvariant::Value>> {
+        let this = self.0.lock().await;
+        this.holds
+            .active()
+            .iter()
+            .map(|hold| {
+                let mut map = HashMap::new();
+                let profile = system76_profile_to_upp_str(&hold.profile);
+                map.insert("ApplicationId".into(), hold.application_id.clone().into());
+                map.insert("Profile".into(), profile.into());
+                map.insert("Reason".into(), hold.reason.clone().into());
+                map
+            })
+            .collect()
+    }
 
     #[dbus_interface(property)]
     async fn actions(&self) -> Vec<String> { vec![] }
@@ -490,9 +2479,10 @@ impl NetHadessPowerProfiles {
     #[dbus_interface(property)]
     async fn active_profile(&self) -> &str { self.0.active_profile().await }
 
+    /// Writable, though it is set by [`ProfilesProperties`], which knows the caller to authorize.
     #[dbus_interface(property)]
-    async fn set_active_profile(&mut self, profile: &str) {
-        self.0.set_active_profile(profile).await
+    async fn set_active_profile(&mut self, _profile: &str) -> zbus::fdo::Result<()> {
+        Err(unauthorized_property())
     }
 
     #[dbus_interface(property)]
@@ -507,20 +2497,158 @@ impl NetHadessPowerProfiles {
     async fn actions(&self) -> Vec<String> { self.0.actions().await }
 }
 
+/// The interfaces of power-profiles-daemon, which are served on their own connections.
+trait PowerProfiles: Interface {
+    fn upower(&self) -> &UPowerPowerProfiles;
+}
+
+impl PowerProfiles for UPowerPowerProfiles {
+    fn upower(&self) -> &UPowerPowerProfiles { self }
+}
+
+impl PowerProfiles for NetHadessPowerProfiles {
+    fn upower(&self) -> &UPowerPowerProfiles { &self.0 }
+}
+
+/// Replaces `org.freedesktop.DBus.Properties` on the object of a power-profiles-daemon
+/// interface: zbus does not give property setters the message header, and the caller setting
+/// `ActiveProfile` is authorized from it.
+struct ProfilesProperties<I>(PhantomData<fn() -> I>);
+
+impl<I: PowerProfiles> ProfilesProperties<I> {
+    /// Serves the properties of the object at `path` of the connection in place of those of zbus.
+    async fn serve(connection: &zbus::Connection, path: &str) -> zbus::Result<()> {
+        let server = connection.object_server();
+        server.remove::<zbus::fdo::Properties, _>(path).await?;
+        server.at(path, Self(PhantomData)).await.map(drop)
+    }
+
+    /// The interface whose properties are requested.
+    async fn interface(
+        server: &zbus::ObjectServer,
+        header: &zbus::MessageHeader<'_>,
+        interface_name: &InterfaceName<'_>,
+    ) -> zbus::fdo::Result<zbus::InterfaceRef<I>> {
+        if interface_name.as_str() != I::name().as_str() {
+            let message = format!("Unknown interface '{}'", interface_name);
+            return Err(zbus::fdo::Error::UnknownInterface(message));
+        }
+
+        let path = header.path()?.ok_or(zbus::Error::MissingField)?;
+        Ok(server.interface::<_, I>(path.as_str()).await?)
+    }
+}
+
+#[zbus::dbus_interface(name = "org.freedesktop.DBus.Properties")]
+impl<I: PowerProfiles> ProfilesProperties<I> {
+    async fn get(
+        &self,
+        interface_name: InterfaceName<'_>,
+        property_name: &str,
+        #[zbus(object_server)] server: &zbus::ObjectServer,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+    ) -> zbus::fdo::Result<zvariant::OwnedValue> {
+        let interface = Self::interface(server, &header, &interface_name).await?;
+        let value = interface.get().await.get(property_name).await;
+        value.unwrap_or_else(|| Err(unknown_property(property_name)))
+    }
+
+    async fn get_all(
+        &self,
+        interface_name: InterfaceName<'_>,
+        #[zbus(object_server)] server: &zbus::ObjectServer,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+    ) -> zbus::fdo::Result<HashMap<String, zvariant::OwnedValue>> {
+        let interface = Self::interface(server, &header, &interface_name).await?;
+        let properties = interface.get().await.get_all().await;
+        Ok(properties)
+    }
+
+    async fn set(
+        &self,
+        interface_name: InterfaceName<'_>,
+        property_name: &str,
+        value: zvariant::Value<'_>,
+        #[zbus(object_server)] server: &zbus::ObjectServer,
+        #[zbus(header)] header: zbus::MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let interface = Self::interface(server, &header, &interface_name).await?;
+        if property_name == "ActiveProfile" {
+            let profile = <&str>::try_from(&value).map_err(zbus::Error::Variant)?;
+            let upower = interface.get().await.upower().clone();
+            return upower.set_active_profile_from(&header, profile).await;
+        }
+
+        let value = interface.get().await.get(property_name).await;
+        match value {
+            Some(_) => Err(zbus::fdo::Error::PropertyReadOnly(format!(
+                "Property '{}' is read-only",
+                property_name
+            ))),
+            None => Err(unknown_property(property_name)),
+        }
+    }
+
+    #[dbus_interface(signal)]
+    async fn properties_changed(
+        context: &zbus::SignalContext<'_>,
+        interface_name: InterfaceName<'_>,
+        changed_properties: &HashMap<&str, &zvariant::Value<'_>>,
+        invalidated_properties: &[&str],
+    ) -> zbus::Result<()>;
+}
+
+fn unknown_property(name: &str) -> zbus::fdo::Error {
+    zbus::fdo::Error::UnknownProperty(format!("Unknown property '{}'", name))
+}
+
+/// The error of setting a property through the setter of zbus, which does not know the caller.
+fn unauthorized_property() -> zbus::fdo::Error {
+    zbus::fdo::Error::AccessDenied(String::from("the caller of the property setter is unknown"))
+}
+
 #[tokio::main(flavor = "current_thread")]
 #[allow(clippy::too_many_lines)]
-pub async fn daemon() -> anyhow::Result<()> {
-    let signal_handling_fut = signal_handling();
+pub async fn daemon(restore: bool) -> anyhow::Result<()> {
+    log::info!("Starting daemon");
+    sd_notify::status("Starting");
+
+    // Registered before anything else, so that early signals are not lost.
+    let int = signal(SignalKind::interrupt()).context("failed to handle SIGINT")?;
+    let hup = signal(SignalKind::hangup()).context("failed to handle SIGHUP")?;
+    let term = signal(SignalKind::terminate()).context("failed to handle SIGTERM")?;
 
     let pci_runtime_pm = std::env::var("S76_POWER_PCI_RUNTIME_PM").ok().map_or(false, |v| v == "1");
 
     PCI_RUNTIME_PM.store(pci_runtime_pm, Ordering::SeqCst);
 
-    let daemon = PowerDaemon::new()?;
+    let mut daemon = PowerDaemon::new()?;
 
-    let nvidia_exists = !daemon.graphics.nvidia.is_empty();
+    match config::load(GRAPHICS_CONFIG) {
+        Ok(config) => daemon.graphics.config = config,
+        Err(why) => log::warn!("using default graphics config: {}", why),
+    }
+
+    // The profiles of the settings may be custom profiles, which must be known first.
+    if let Err(why) = tunables::load() {
+        log::warn!("using the built-in profile tunables: {}", why);
+    }
+
+    match DaemonConfig::load() {
+        Ok(config) => daemon.config = config,
+        Err(why) => log::warn!("using default daemon config: {}", why),
+    }
+
+    daemon.graphics.strict_driver_check = daemon.config.graphics.strict_driver_check;
 
-    NmiWatchdog.set(b"0");
+    match load_charge_profiles() {
+        Ok(profiles) => daemon.charge_profiles = profiles,
+        Err(why) => log::warn!("using the built-in charge profiles: {}", why),
+    }
+
+    daemon.mode_files = ModeFiles::read();
+
+    let nvidia_exists = !daemon.graphics.nvidia.is_empty();
 
     // Get the NVIDIA device ID before potentially removing it.
     let nvidia_device_id = if nvidia_exists {
@@ -530,17 +2658,49 @@ pub async fn daemon() -> anyhow::Result<()> {
     };
 
     let daemon = Arc::new(Mutex::new(daemon));
-    let mut system76_daemon = System76Power(daemon.clone());
+    let system76_daemon = System76Power(daemon.clone());
+
+    // The name is acquired first, so that a second instance exits before touching the
+    // hardware, and so that clients starting the daemon through the bus are answered soon.
+    // Queries wait for the initial profile meanwhile.
+    let connection = {
+        let mut this = daemon.lock().await;
+        let Some(connection) = serve(system76_daemon.clone())
+            .await
+            .context("unable to create system service for com.system76.PowerDaemon")?
+        else {
+            log::info!("{} is already running", DBUS_NAME);
+            return Ok(());
+        };
 
-    match system76_daemon.auto_graphics_power().await {
-        Ok(()) => (),
-        Err(err) => {
-            log::warn!("Failed to set automatic graphics power: {}", err);
+        // A calibration interrupted by a crash is undone whether or not the state is restored.
+        this.recover_calibration();
+        if restore {
+            this.restore(State::load());
+        } else {
+            log::info!("Not restoring the state of a previous run");
+            // Whether the default graphics mode was applied is not a setting, and is kept.
+            this.state.default_graphics = State::load().default_graphics;
         }
+        // Other power managers are detected first, so that their parameters can be left to them.
+        this.detect_conflicts(false);
+        this.apply_initial_profile();
+        connection
+    };
+
+    if system76_daemon.0.lock().await.config.startup.verify_initramfs {
+        verify_initramfs();
     }
 
-    let vendor = fs::read_to_string("/sys/class/dmi/id/sys_vendor")?;
-    let model = fs::read_to_string("/sys/class/dmi/id/product_version")?;
+    // Virtual machines may not provide DMI data.
+    let dmi = |name: &str| {
+        fs::read_to_string(format!("/sys/class/dmi/id/{}", name)).unwrap_or_else(|why| {
+            log::warn!("Failed to read {} from DMI: {}", name, why);
+            String::new()
+        })
+    };
+    let vendor = dmi("sys_vendor");
+    let model = dmi("product_version");
     match runtime_pm_quirks(&model, &vendor) {
         Ok(()) => (),
         Err(err) => {
@@ -550,7 +2710,7 @@ pub async fn daemon() -> anyhow::Result<()> {
 
     // Register DBus interface for org.freedesktop.UPower.PowerProfiles.
     // This is used by powerprofilesctl
-    let upp_connection = zbus::ConnectionBuilder::system()
+    let upp_connection = bus()
         .context("failed to create zbus connection builder")?
         .name(POWER_PROFILES_DBUS_NAME)
         .context("unable to register name")?
@@ -559,32 +2719,30 @@ pub async fn daemon() -> anyhow::Result<()> {
         .build()
         .await
         .context("unable to create system service for org.freedesktop.UPower.PowerProfiles")?;
+    ProfilesProperties::<UPowerPowerProfiles>::serve(&upp_connection, POWER_PROFILES_DBUS_PATH)
+        .await
+        .context("unable to serve the properties of org.freedesktop.UPower.PowerProfiles")?;
 
     // Register DBus interface for net.hadess.PowerProfiles.
     // This is used by gnome-shell
-    let hadess_connection = zbus::ConnectionBuilder::system()
+    let hadess_connection = bus()
         .context("failed to create zbus connection builder")?
         .name(NET_HADESS_POWER_PROFILES_DBUS_NAME)
         .context("unable to register name")?
         .serve_at(
             NET_HADESS_POWER_PROFILES_DBUS_PATH,
-            NetHadessPowerProfiles(UPowerPowerProfiles(daemon)),
+            NetHadessPowerProfiles(UPowerPowerProfiles(daemon.clone())),
         )
         .context("unable to serve")?
         .build()
         .await
         .context("unable to create system service for net.hadess.PowerProfiles")?;
-
-    // Register DBus interface for com.system76.PowerDaemon.
-    let connection = zbus::ConnectionBuilder::system()
-        .context("failed to create zbus connection builder")?
-        .name(DBUS_NAME)
-        .context("unable to register name")?
-        .serve_at(DBUS_PATH, system76_daemon.clone())
-        .context("unable to serve")?
-        .build()
-        .await
-        .context("unable to create system service for com.system76.PowerDaemon")?;
+    ProfilesProperties::<NetHadessPowerProfiles>::serve(
+        &hadess_connection,
+        NET_HADESS_POWER_PROFILES_DBUS_PATH,
+    )
+    .await
+    .context("unable to serve the properties of net.hadess.PowerProfiles")?;
 
     system76_daemon.0.lock().await.connections =
         Some((connection.clone(), upp_connection, hadess_connection));
@@ -592,11 +2750,29 @@ pub async fn daemon() -> anyhow::Result<()> {
     let context = zbus::SignalContext::new(&connection, DBUS_PATH)
         .context("unable to create signal context")?;
 
-    if let Err(why) = system76_daemon.balanced(context.clone()).await {
-        log::warn!("Failed to set initial profile: {}", why);
+    // Announce the profile which was applied before the daemon was on the bus.
+    let profile = system76_daemon.0.lock().await.power_profile.clone();
+    let _res =
+        System76Power::power_profile_switched(&context, "", &profile, INITIATOR_SYSTEM).await;
+
+    // Once its bus names are served, power-profiles-daemon can no longer serve them.
+    system76_daemon.refresh_conflicts(&context).await;
+
+    system76_daemon.apply_startup_graphics_power(&context).await;
+
+    if system76_daemon.0.lock().await.config.startup.apply_default_graphics {
+        system76_daemon.apply_default_graphics(&context).await;
     }
 
-    system76_daemon.0.lock().await.initial_set = true;
+    tokio::spawn(sleep::watch(system76_daemon.clone(), connection.clone(), context.to_owned()));
+    tokio::spawn(idle::watch(system76_daemon.clone(), connection.clone(), context.to_owned()));
+    tokio::spawn(schedule::watch(system76_daemon.clone(), context.to_owned()));
+    tokio::spawn(low_battery::watch(system76_daemon.clone(), context.to_owned()));
+    tokio::spawn(holds::release_on_disconnect(
+        system76_daemon.clone(),
+        connection.clone(),
+        context.to_owned(),
+    ));
 
     // Spawn hid backlight daemon
     let _hid_backlight = thread::spawn(hid_backlight::daemon);
@@ -611,14 +2787,117 @@ pub async fn daemon() -> anyhow::Result<()> {
         }
     };
 
+    let mut charge_thresholds_watch = match watch_charge_thresholds() {
+        Ok(inotify) => Some(inotify),
+        Err(why) => {
+            log::warn!("Failed to watch charge thresholds: {}", why);
+            None
+        }
+    };
+
+    system76_daemon.refresh_charge_thresholds(&context, INITIATOR_SYSTEM).await;
+
+    let mut mode_files_watch = match ModeFiles::watch() {
+        Ok(inotify) => Some(inotify),
+        Err(why) => {
+            log::warn!("Failed to watch the graphics configuration for external edits: {}", why);
+            None
+        }
+    };
+
+    let mut uevents = match UeventMonitor::new() {
+        Ok(monitor) => Some(monitor),
+        Err(why) => {
+            log::warn!(
+                "Failed to monitor displays, power supplies and hotplugged devices: {}",
+                why
+            );
+            None
+        }
+    };
+
+    {
+        let mut this = system76_daemon.0.lock().await;
+        this.external_displays_require_dgpu =
+            this.graphics.get_external_displays_require_dgpu().unwrap_or(false);
+    }
+
+    system76_daemon.refresh_power_source(&context, false).await;
+
+    sd_notify::notify_or_log(&["READY=1\nSTATUS=", STATUS_IDLE].concat());
+
+    let signal_handling_fut = signal_handling(int, hup, term, system76_daemon.clone());
+    let shutdown_daemon = system76_daemon.clone();
+
     let main_loop = async move {
         let mut last = hpd();
+        let mut inotify_buffer = [0; 1024];
+        let mut thresholds_read = Instant::now();
+        system76_daemon.0.lock().await.hot_plug = last;
 
         while CONTINUE.load(Ordering::SeqCst) {
             sleep(Duration::from_millis(1000)).await;
 
             fan_daemon.step();
 
+            if let Some(ref mut inotify) = charge_thresholds_watch {
+                match inotify.read_events(&mut inotify_buffer) {
+                    Ok(mut events) => {
+                        if events.next().is_some() {
+                            system76_daemon
+                                .refresh_charge_thresholds(&context, INITIATOR_EXTERNAL)
+                                .await;
+                            thresholds_read = Instant::now();
+                        }
+                    }
+                    Err(why) if why.kind() == std::io::ErrorKind::WouldBlock => (),
+                    Err(why) => log::warn!("Failed to read charge threshold events: {}", why),
+                }
+            }
+
+            if thresholds_read.elapsed() >= CHARGE_THRESHOLDS_POLL {
+                system76_daemon.refresh_charge_thresholds(&context, INITIATOR_EXTERNAL).await;
+                thresholds_read = Instant::now();
+            }
+
+            if let Some(ref mut inotify) = mode_files_watch {
+                match inotify.read_events(&mut inotify_buffer) {
+                    Ok(mut events) => {
+                        if events.any(|event| event.name.map_or(false, ModeFiles::is_watched)) {
+                            system76_daemon.refresh_mode_files(&context).await;
+                        }
+                    }
+                    Err(why) if why.kind() == std::io::ErrorKind::WouldBlock => (),
+                    Err(why) => log::warn!("Failed to read graphics configuration events: {}", why),
+                }
+            }
+
+            let changes = uevents.as_mut().map(UeventMonitor::changes).unwrap_or_default();
+            let subsystems = &changes.subsystems;
+
+            // The threshold files of a battery added again are new, and may hold the defaults.
+            if changes.added("power_supply").any(|name| name.starts_with("BAT")) {
+                charge_thresholds_watch = watch_charge_thresholds().ok();
+                system76_daemon.reapply_charge_thresholds(&context, "once added").await;
+            }
+
+            if subsystems.contains("drm") {
+                system76_daemon.refresh_external_displays_require_dgpu(&context).await;
+            }
+
+            if subsystems.contains("power_supply") {
+                system76_daemon.refresh_power_source(&context, false).await;
+                low_battery::wake();
+            }
+
+            if subsystems.contains("usb") {
+                system76_daemon.refresh_usb_devices().await;
+            }
+
+            if subsystems.contains("net") {
+                system76_daemon.refresh_wifi_interfaces().await;
+            }
+
             // HACK: As of Linux 6.9.3, TBT5 controller must be active for HPD
             // to work on USB-C ports.
             match thunderbolt_hotplug_wakeup(&vendor, &model) {
@@ -636,6 +2915,11 @@ pub async fn daemon() -> anyhow::Result<()> {
                 }
             }
 
+            if hpd != last {
+                system76_daemon.0.lock().await.hot_plug = hpd;
+                let _res = system76_daemon.hot_plug_detect_changed(&context).await;
+            }
+
             last = hpd;
 
             if let Ok(ref mux) = mux_res {
@@ -647,21 +2931,590 @@ pub async fn daemon() -> anyhow::Result<()> {
     };
 
     log::info!("Handling dbus requests");
-    futures_lite::future::zip(signal_handling_fut, main_loop).await;
+    futures_lite::future::zip(
+        signal_handling_fut,
+        futures_lite::future::zip(main_loop, watchdog()),
+    )
+    .await;
+
+    log::info!("Shutting down");
+    sd_notify::notify_or_log("STOPPING=1\nSTATUS=Shutting down");
+    shutdown_daemon.shutdown().await;
 
     log::info!("daemon exited from loop");
     Ok(())
 }
 
+/// Connects to the bus which started the daemon, if it was started by DBus activation, or to
+/// the system bus.
+fn bus() -> zbus::Result<zbus::ConnectionBuilder<'static>> {
+    match std::env::var("DBUS_STARTER_ADDRESS") {
+        Ok(address) => zbus::ConnectionBuilder::address(address.as_str()),
+        Err(_) => zbus::ConnectionBuilder::system(),
+    }
+}
+
+/// Serves the daemon as `com.system76.PowerDaemon`, or returns `None` if another instance
+/// owns the name.
+async fn serve(daemon: System76Power) -> zbus::Result<Option<zbus::Connection>> {
+    let connection = bus()?.name(DBUS_NAME)?.serve_at(DBUS_PATH, daemon)?.build().await;
+
+    match connection {
+        Ok(connection) => Ok(Some(connection)),
+        Err(zbus::Error::NameTaken) => Ok(None),
+        Err(why) => Err(why),
+    }
+}
+
+/// Warns if the initramfs may not include the modprobe config of the configured mode.
+fn verify_initramfs() {
+    if let Some(mode) = Graphics::get_configured_vendor() {
+        if !Graphics::initramfs_is_current() {
+            log::warn!(
+                "The initramfs predates the modprobe config for {} graphics, which may not apply \
+                 until the initramfs is rebuilt with `dracut --force`",
+                mode
+            );
+        }
+    }
+}
+
+/// Applies a graphics power state of `on`, `off` or `auto`, returning whether the dGPU is
+/// powered on.
+fn apply_graphics_power(graphics: &Graphics, state: &str) -> Result<bool, PowerError> {
+    match graphics_power(state)? {
+        None => graphics.auto_power(),
+        Some(power) => graphics.set_power(power).map(|()| power),
+    }
+    .map_err(PowerError::from)
+}
+
+/// Parses a graphics power state of `on`, `off` or `auto`, which is `None`.
+fn graphics_power(state: &str) -> Result<Option<bool>, PowerError> {
+    match state {
+        "auto" => Ok(None),
+        "off" => Ok(Some(false)),
+        "on" => Ok(Some(true)),
+        _ => Err(zbus::fdo::Error::InvalidArgs(format!(
+            "invalid graphics power state '{}', expected auto, off or on",
+            state
+        ))
+        .into()),
+    }
+}
+
+/// Logs the batteries whose thresholds failed to be set, while the others were.
+fn log_battery_failures(writes: &BatteryWrites) {
+    for (battery, why) in &writes.failed {
+        log::warn!("Failed to set the charge thresholds of {}: {}", battery, why);
+    }
+}
+
+/// Rejects the flags of a `...WithFlags` method other than those it knows.
+fn check_flags(flags: u32, known: u32) -> Result<(), PowerError> {
+    if flags & !known != 0 {
+        return Err(
+            zbus::fdo::Error::InvalidArgs(format!("unknown flags {:#x}", flags & !known)).into()
+        );
+    }
+
+    Ok(())
+}
+
+/// Parses a graphics mode requested by a client, such as `hybrid`.
+fn graphics_mode(vendor: &str) -> Result<GraphicsMode, PowerError> {
+    vendor.parse().map_err(|()| {
+        let names = GraphicsMode::ALL.map(<&str>::from).join(", ");
+        PowerError::InvalidMode(format!("invalid graphics mode '{}', expected {}", vendor, names))
+    })
+}
+
+/// Parses a profile requested by a client, such as `Battery`, or a custom profile.
+fn parse_profile(name: &str) -> Result<ProfileDef, PowerError> {
+    tunables::find(name).ok_or_else(|| {
+        let names = tunables::all().into_iter().map(|profile| profile.name).collect::<Vec<_>>();
+        let message = format!("invalid profile '{}', expected {}", name, names.join(", "));
+        zbus::fdo::Error::InvalidArgs(message).into()
+    })
+}
+
+/// Parses a built-in profile, such as `Battery`, which are the only profiles which may be held.
+fn parse_builtin_profile(name: &str) -> Result<Profile, PowerError> {
+    name.parse().map_err(|()| {
+        let names = Profile::ALL.map(<&str>::from).join(", ");
+        zbus::fdo::Error::InvalidArgs(format!("invalid profile '{}', expected {}", name, names))
+            .into()
+    })
+}
+
+/// Asks polkit whether the sender of a message may perform an action.
+async fn check_authorization(
+    connection: &zbus::Connection,
+    header: &zbus::MessageHeader<'_>,
+    action: &str,
+) -> zbus::fdo::Result<()> {
+    let polkit = AuthorityProxy::new(connection)
+        .await
+        .context("could not connect to polkit authority daemon")
+        .map_err(zbus_error_from_display)?;
+
+    let subject = Subject::new_for_message_header(header)
+        .context("could not create policykit1 subject")
+        .map_err(zbus_error_from_display)?;
+
+    // Only prompt for a password if the client is able to wait for it.
+    let flags = if header.primary().flags().contains(zbus::MessageFlags::AllowInteractiveAuth) {
+        CheckAuthorizationFlags::AllowUserInteraction.into()
+    } else {
+        Default::default()
+    };
+
+    let permitted = polkit
+        .check_authorization(&subject, action, &HashMap::new(), flags, "")
+        .await
+        .context("could not check policykit authorization")
+        .map_err(zbus_error_from_display)?
+        .is_authorized;
+
+    if permitted {
+        Ok(())
+    } else {
+        Err(zbus::fdo::Error::AccessDenied(String::from("Operation not permitted by Polkit")))
+    }
+}
+
+/// Unique bus name of the client which sent a message.
+fn sender(header: &zbus::MessageHeader<'_>) -> String {
+    header.sender().ok().flatten().map_or_else(String::new, ToString::to_string)
+}
+
+fn upp_str_to_system76_profile(upp_profile: &str) -> Option<&'static str> {
+    match upp_profile {
+        "power-saver" => Some("Battery"),
+        "balanced" => Some("Balanced"),
+        "performance" => Some("Performance"),
+        _ => None,
+    }
+}
+
+/// The profile of `org.freedesktop.UPower.PowerProfiles` of a profile, which is that of its
+/// base for custom profiles.
 fn system76_profile_to_upp_str(system76_profile: &str) -> &'static str {
-    match system76_profile {
-        "Battery" => "power-saver",
-        "Balanced" => "balanced",
-        "Performance" => "performance",
-        _ => "unknown",
+    match tunables::find(system76_profile).map(|profile| profile.base) {
+        Some(Profile::Battery | Profile::Quiet) => "power-saver",
+        Some(Profile::Balanced) => "balanced",
+        Some(Profile::Performance) => "performance",
+        None => "unknown",
+    }
+}
+
+/// An error of the daemon as replied by the compatibility interfaces, which only reply with
+/// the errors of `zbus`.
+fn fdo_error(why: PowerError) -> zbus::fdo::Error {
+    match why {
+        PowerError::ZBus(zbus::Error::FDO(why)) => *why,
+        why => zbus::fdo::Error::Failed(why.message()),
     }
 }
 
 fn zbus_error_from_display<E: Display>(why: E) -> zbus::fdo::Error {
     zbus::fdo::Error::Failed(format!("{}", why))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    // Regenerate with `make introspection` after changing the interface.
+    const INTROSPECTION_PATH: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/data/com.system76.PowerDaemon.xml");
+
+    /// Introspection data of the interface, generated from the same definitions the daemon
+    /// serves.
+    fn introspection_xml() -> String {
+        let daemon =
+            System76Power(Arc::new(Mutex::new(PowerDaemon::with_graphics(Graphics::stub()))));
+
+        let mut xml = format!(
+            "<!DOCTYPE node PUBLIC \"-//freedesktop//DTD D-BUS Object Introspection 1.0//EN\"\n \
+             \"http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd\">\n<node name=\"{}\">\n",
+            DBUS_PATH
+        );
+        daemon.introspect_to_writer(&mut xml, 2);
+        xml.push_str("</node>\n");
+        name_out_args(&xml)
+    }
+
+    /// Names the out args of methods replying with a single value, which zbus leaves unnamed
+    /// although their `out_args` name them, reading the names from the attributes in this file.
+    fn name_out_args(xml: &str) -> String {
+        let mut names = HashMap::new();
+        let mut out_arg = None;
+        for line in include_str!("mod.rs").lines().map(str::trim) {
+            if let Some(args) = line.strip_prefix("#[dbus_interface(out_args(\"") {
+                // Several names are already applied to the fields of the reply.
+                out_arg = args.strip_suffix("\"))]").filter(|name| !name.contains('"'));
+            } else if let Some(method) = line.strip_prefix("async fn ") {
+                let Some(name) = out_arg.take() else { continue };
+                let method = method.split('(').next().unwrap_or_default();
+                let method: String = method
+                    .split('_')
+                    .map(|word| {
+                        let mut chars = word.chars();
+                        chars.next().map_or_else(String::new, |first| {
+                            first.to_ascii_uppercase().to_string() + chars.as_str()
+                        })
+                    })
+                    .collect();
+                names.insert(method, name);
+            }
+        }
+
+        let mut method = None;
+        let mut named = String::with_capacity(xml.len());
+        for line in xml.lines() {
+            if let Some(name) = line.trim().strip_prefix("<method name=\"") {
+                method = name.strip_suffix("\">").and_then(|name| names.get(name));
+            }
+
+            match method {
+                Some(name) if line.contains("<arg type=") && line.contains("direction=\"out\"") => {
+                    named.push_str(&line.replacen("<arg ", &format!("<arg name=\"{}\" ", name), 1));
+                }
+                _ => named.push_str(line),
+            }
+            named.push('\n');
+        }
+
+        named
+    }
+
+    #[test]
+    fn introspection_members() {
+        let xml = introspection_xml();
+
+        for member in [
+            r#"<method name="SetGraphics">"#,
+            r#"<method name="GetGraphicsCapabilities">"#,
+            r#"<method name="GetGraphicsDevices">"#,
+            r#"<method name="StartGraphicsSwitch">"#,
+            r#"<method name="GetJob">"#,
+            r#"<method name="GetConfig">"#,
+            r#"<method name="HoldProfile">"#,
+            r#"<method name="ReleaseProfile">"#,
+            r#"<method name="GetActiveHolds">"#,
+            r#"<signal name="ProfileReleased">"#,
+            r#"<method name="SetGraphicsPowerState">"#,
+            r#"<method name="SetGraphicsPowerStateWithFlags">"#,
+            r#"<method name="SetChargeThresholds">"#,
+            r#"<arg name="thresholds" type="(yy)" direction="in"/>"#,
+            r#"<signal name="ModeChanged">"#,
+            r#"<signal name="InitramfsJobCompleted">"#,
+            r#"<signal name="PowerProfileSwitched">"#,
+            r#"<signal name="ChargeThresholdsChanged">"#,
+            r#"<signal name="ChargeThresholdsSwitched">"#,
+            r#"<property name="GraphicsMode" type="s" access="read"/>"#,
+            r#"<method name="GetProfileStatus">"#,
+            r#"<method name="GetVersion">"#,
+            r#"<method name="SetIdleProfile">"#,
+            r#"<method name="GetGraphicsRecommendation">"#,
+            r#"<method name="GetRecentActions">"#,
+            r#"<method name="GetGraphicsStatus">"#,
+            r#"<method name="GetProfiles">"#,
+            r#"<method name="GetChargeThresholdsStatus">"#,
+            r#"<method name="GetSwitchableStatus">"#,
+            r#"<method name="SetProfileWithFlags">"#,
+            r#"<method name="GetPersistedProfile">"#,
+            r#"<method name="GetGraphicsPowerStatus">"#,
+            r#"<method name="GetProfileSchedule">"#,
+            r#"<method name="GetLowBattery">"#,
+            r#"<method name="GetCpuFrequency">"#,
+            r#"<method name="GetPowerDraw">"#,
+            r#"<method name="GetConflicts">"#,
+            r#"<method name="GetBatteryFrequencyCap">"#,
+            r#"<method name="SetBatteryChargeThresholds">"#,
+            r#"<method name="SetLowBattery">"#,
+            r#"<method name="StartGraphicsSwitchWithFlags">"#,
+            r#"<method name="SetChargeThresholdsWithFlags">"#,
+            r#"<property name="ConfiguredGraphicsMode" type="s" access="read"/>"#,
+            r#"<property name="ExternalDisplaysRequireDgpu" type="b" access="read"/>"#,
+            r#"<property name="HotPlugDetect" type="ab" access="read"/>"#,
+            r#"<property name="Conflicts" type="a(ssas)" access="read"/>"#,
+            r#"<method name="StartCalibration">"#,
+            r#"<method name="CancelCalibration">"#,
+            r#"<method name="GetCalibration">"#,
+            r#"<method name="GetBatteries">"#,
+            r#"<method name="ApplyBatteryChargeThresholds">"#,
+            r#"<property name="Calibration" type="(ssyys)" access="read"/>"#,
+        ] {
+            assert!(xml.contains(member), "{} missing from introspection data", member);
+        }
+
+        for line in xml.lines().filter(|line| line.contains("direction=\"out\"")) {
+            assert!(line.contains("<arg name="), "unnamed out arg in introspection data: {}", line);
+        }
+        assert!(xml.contains(r#"<arg name="profile" type="s" direction="out"/>"#));
+        assert!(xml.contains(r#"<arg name="thresholds" type="a{s(yy)}" direction="out"/>"#));
+    }
+
+    #[test]
+    fn introspection_data_is_current() {
+        let xml = introspection_xml();
+
+        if std::env::var_os("S76_POWER_UPDATE_INTROSPECTION").is_some() {
+            fs::write(INTROSPECTION_PATH, &xml).unwrap();
+            return;
+        }
+
+        let shipped = fs::read_to_string(INTROSPECTION_PATH).unwrap();
+        assert!(shipped == xml, "{} is out of date, run `make introspection`", INTROSPECTION_PATH);
+    }
+
+    /// Whether the daemon replied, even if only with an error from the hardware.
+    fn replied<T>(result: zbus::Result<T>) -> bool {
+        matches!(result, Ok(_) | Err(zbus::Error::MethodError(..) | zbus::Error::FDO(_)))
+    }
+
+    /// Serves the daemon on a private connection, and exercises every call of the client.
+    #[tokio::test]
+    async fn client() {
+        use system76_power_zbus::{client::Client, GraphicsMode, GraphicsPower, Profile};
+
+        let mut daemon = PowerDaemon::with_graphics(Graphics::stub());
+        daemon.power_profile = "Balanced".into();
+        let daemon = System76Power(Arc::new(Mutex::new(daemon)));
+
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        let guid = zbus::Guid::generate();
+
+        let (server, client) = futures_lite::future::zip(
+            zbus::ConnectionBuilder::unix_stream(server)
+                .server(&guid)
+                .p2p()
+                .serve_at(DBUS_PATH, daemon)
+                .unwrap()
+                .build(),
+            zbus::ConnectionBuilder::unix_stream(client).p2p().build(),
+        )
+        .await;
+        let (_server, client) = (server.unwrap(), client.unwrap());
+
+        let client = Client::new(&client).await.unwrap();
+
+        assert_eq!(client.profile().await.unwrap(), Profile::Balanced);
+        assert_eq!(client.profile_status().await.unwrap().profile, "Balanced");
+        assert_eq!(client.persisted_profile().await.unwrap(), Profile::Balanced);
+        assert!(!client.auto_profile().await.unwrap().enabled);
+        assert!(client.config().await.unwrap().contains("[startup]"));
+        assert_eq!(
+            client.version().await.unwrap(),
+            (env!("CARGO_PKG_VERSION").to_owned(), system76_power_zbus::API_VERSION)
+        );
+        assert!(client.active_holds().await.unwrap().is_empty());
+        assert!(!client.switchable().await.unwrap());
+        assert!(!client.external_displays_require_dgpu().await.unwrap());
+        assert!(!client.charge_profiles().await.unwrap().is_empty());
+        client.graphics_capabilities().await.unwrap();
+        client.cpu_frequency().await.unwrap();
+        client.power_draw().await.unwrap();
+        client.conflicts().await.unwrap();
+        client.battery_frequency_cap().await.unwrap();
+
+        assert!(replied(client.desktop().await));
+        assert!(replied(client.graphics().await));
+        assert!(replied(client.graphics_devices().await));
+        assert!(replied(client.default_graphics().await));
+        assert!(replied(client.graphics_recommendation().await));
+        assert!(replied(client.graphics_status().await));
+        assert!(replied(client.switchable_status().await));
+        assert!(replied(client.graphics_power_status().await));
+        assert!(client.recent_actions().await.unwrap().is_empty());
+        let profiles = client.profiles().await.unwrap();
+        let names: Vec<_> = profiles.iter().map(|profile| profile.name.as_str()).collect();
+        assert_eq!(names, ["Battery", "Quiet", "Balanced", "Performance"]);
+        assert!(replied(client.configured_graphics().await));
+        assert!(replied(client.graphics_power().await));
+        assert!(replied(client.charge_thresholds().await));
+        assert!(replied(client.battery_charge_thresholds().await));
+        assert!(replied(client.charge_thresholds_status().await));
+
+        // Dry runs need no authorization, and are not recorded.
+        assert!(replied(client.plan_profile(Profile::Battery).await));
+        assert!(replied(client.plan_graphics_switch(GraphicsMode::Hybrid, false).await));
+        assert!(replied(client.plan_graphics_power(GraphicsPower::Off, true).await));
+        assert!(replied(client.plan_charge_thresholds((40, 80)).await));
+        assert!(replied(client.plan_battery_charge_thresholds(Some("BAT1"), (40, 80)).await));
+
+        let _switches = client.receive_profile_switches().await.unwrap();
+        let _releases = client.receive_profile_releases().await.unwrap();
+        let _changes = client.receive_mode_changes().await.unwrap();
+        let _thresholds = client.receive_charge_thresholds_changes().await.unwrap();
+        let _hot_plug = client.receive_hot_plug_detects().await.unwrap();
+
+        // Changes are refused, as the peer cannot be authorized without a bus.
+        assert!(client.set_profile(Profile::Battery).await.is_err());
+        assert!(client.hold_profile(Profile::Performance, "test", "test").await.is_err());
+        assert!(client.release_profile(0).await.is_err());
+        assert!(client.set_auto_profile(true).await.is_err());
+        assert!(client.set_idle_profile(true).await.is_err());
+        assert!(client.set_low_battery(true, 20).await.is_err());
+        assert!(client.set_graphics(GraphicsMode::Hybrid, false).await.is_err());
+        assert!(client.switch_graphics(GraphicsMode::Hybrid, false, |_, _| ()).await.is_err());
+        assert!(client.set_graphics_option("gsp", "off").await.is_err());
+        assert!(client.set_graphics_power(GraphicsPower::Auto).await.is_err());
+        assert!(client.force_graphics_power_off().await.is_err());
+        assert!(client.set_charge_thresholds((40, 80)).await.is_err());
+        assert!(client.apply_battery_charge_thresholds(Some("BAT1"), (83, 92)).await.is_err());
+        assert_eq!(client.profile().await.unwrap(), Profile::Balanced);
+
+        // Each refusal is recorded, with the error replied.
+        let actions = client.recent_actions().await.unwrap();
+        assert_eq!(actions.len(), 13);
+        assert_eq!(actions[0].method, "Battery");
+        assert_eq!(actions[5].method, "SetLowBattery");
+        assert_eq!(actions[10].method, "SetGraphicsPowerStateWithFlags");
+        assert_eq!(actions[10].request, "off flags=1");
+        assert_eq!(actions[11].method, "SetChargeThresholds");
+        assert_eq!(actions[11].request, "40, 80");
+        assert_eq!(actions[12].method, "ApplyBatteryChargeThresholds");
+        assert_eq!(actions[12].request, "BAT1 83, 92 flags=0");
+        assert!(actions.iter().all(|action| action.outcome != "ok"));
+    }
+
+    /// Of two switches started together, one runs and the other is refused as busy.
+    #[tokio::test]
+    async fn concurrent_switches() {
+        let daemon =
+            System76Power(Arc::new(Mutex::new(PowerDaemon::with_graphics(Graphics::stub()))));
+
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        let guid = zbus::Guid::generate();
+
+        let (server, _client) = futures_lite::future::zip(
+            zbus::ConnectionBuilder::unix_stream(server).server(&guid).p2p().build(),
+            zbus::ConnectionBuilder::unix_stream(client).p2p().build(),
+        )
+        .await;
+        let server = server.unwrap();
+        let context = zbus::SignalContext::new(&server, DBUS_PATH).unwrap();
+
+        let (first, second) = tokio::join!(
+            daemon.spawn_graphics_switch(&context, GraphicsMode::Hybrid, false, false),
+            daemon.spawn_graphics_switch(&context, GraphicsMode::Integrated, false, false),
+        );
+
+        let (path, task) = first.unwrap();
+        match second {
+            Err(PowerError::Busy(message)) => assert_eq!(
+                message,
+                format!("a graphics switch to hybrid (job {}) is in progress", path.as_str())
+            ),
+            other => panic!("expected the second switch to be busy, got {:?}", other.err()),
+        }
+
+        // Cancelled before it runs, releasing the operation.
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+        drop(Operation::start("a test".into()).unwrap());
+    }
+
+    fn stub_daemon() -> System76Power {
+        let mut daemon = PowerDaemon::with_graphics(Graphics::stub());
+        daemon.power_profile = "Balanced".into();
+        System76Power(Arc::new(Mutex::new(daemon)))
+    }
+
+    /// Serves the daemon when started by the bus of the `activation` test, after checking that
+    /// a second instance leaves the name alone. Otherwise, does nothing.
+    #[tokio::test]
+    async fn activated_service() {
+        if std::env::var_os("DBUS_STARTER_ADDRESS").is_none() {
+            return;
+        }
+
+        let connection = serve(stub_daemon()).await.unwrap().unwrap();
+        assert!(serve(stub_daemon()).await.unwrap().is_none());
+
+        let bus = zbus::fdo::DBusProxy::new(&connection).await.unwrap();
+        while bus.get_id().await.is_ok() {
+            sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    /// Starts the daemon through DBus activation on a private bus, as a client calling it
+    /// before it runs does.
+    #[tokio::test]
+    async fn activation() {
+        use std::{
+            io::{BufRead, BufReader},
+            process::{Command, Stdio},
+        };
+        use system76_power_zbus::{client::Client, Profile};
+
+        let dir = TempDir::new("activation");
+        let services = dir.join("services");
+        fs::create_dir_all(&services).unwrap();
+
+        // This binary is the activated daemon, running only the `activated_service` test.
+        fs::write(
+            services.join(format!("{}.service", DBUS_NAME)),
+            format!(
+                "[D-BUS Service]\nName={}\nExec={} --exact daemon::tests::activated_service\n",
+                DBUS_NAME,
+                std::env::current_exe().unwrap().display()
+            ),
+        )
+        .unwrap();
+
+        let config = dir.join("bus.conf");
+        fs::write(
+            &config,
+            format!(
+                concat!(
+                    "<busconfig>\n",
+                    "  <type>session</type>\n",
+                    "  <listen>unix:path={}</listen>\n",
+                    "  <servicedir>{}</servicedir>\n",
+                    "  <auth>EXTERNAL</auth>\n",
+                    "  <policy context=\"default\">\n",
+                    "    <allow send_destination=\"*\" eavesdrop=\"true\"/>\n",
+                    "    <allow eavesdrop=\"true\"/>\n",
+                    "    <allow own=\"*\"/>\n",
+                    "  </policy>\n",
+                    "</busconfig>\n",
+                ),
+                dir.join("bus").display(),
+                services.display()
+            ),
+        )
+        .unwrap();
+
+        let bus = Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(["--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn();
+
+        let Ok(mut bus) = bus else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+
+        // Kept open until the end, as the activated daemon inherits the output of the bus.
+        let mut output = BufReader::new(bus.stdout.take().unwrap());
+        let mut address = String::new();
+        output.read_line(&mut address).unwrap();
+
+        let profile = tokio::time::timeout(Duration::from_secs(30), async {
+            let connection = zbus::ConnectionBuilder::address(address.trim())?.build().await?;
+            Client::new(&connection).await?.profile().await
+        })
+        .await;
+
+        bus.kill().unwrap();
+        bus.wait().unwrap();
+
+        assert_eq!(profile.expect("timed out").unwrap(), Profile::Balanced);
+    }
+}
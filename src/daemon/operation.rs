@@ -0,0 +1,49 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Operations changing the graphics configuration run one at a time. Rather than waiting
+//! behind the running one, a second operation is refused as busy.
+
+use std::sync::{Mutex as StdMutex, PoisonError};
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::error::PowerError;
+
+static LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Description of the running operation, such as `graphics switch (job 3)`.
+static RUNNING: StdMutex<String> = StdMutex::new(String::new());
+
+/// A running operation, which ends when this is dropped.
+pub(super) struct Operation {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Operation {
+    /// Starts an operation, unless another is running.
+    pub(super) fn start(description: String) -> Result<Self, PowerError> {
+        let guard = LOCK.try_lock().map_err(|_| {
+            let running = RUNNING.lock().unwrap_or_else(PoisonError::into_inner);
+            PowerError::Busy(format!("{} is in progress", running))
+        })?;
+
+        *RUNNING.lock().unwrap_or_else(PoisonError::into_inner) = description;
+        Ok(Self { _guard: guard })
+    }
+
+    /// Replaces the description of the operation, once it is known in full.
+    pub(super) fn describe(&self, description: String) {
+        *RUNNING.lock().unwrap_or_else(PoisonError::into_inner) = description;
+    }
+
+    /// Whether an operation is running.
+    pub(super) fn running() -> bool { LOCK.try_lock().is_err() }
+
+    /// Waits for the running operation to end.
+    pub(super) async fn wait() { drop(LOCK.lock().await) }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) { RUNNING.lock().unwrap_or_else(PoisonError::into_inner).clear() }
+}
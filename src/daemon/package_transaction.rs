@@ -0,0 +1,280 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Package managers rebuild the initramfs and may install modprobe configs of their own, so
+//! graphics operations changing either are refused, or wait, while a package transaction runs.
+//!
+//! A transaction is detected from the pid file of dnf, the lock of the rpm database, and the
+//! transactions of PackageKit which change packages. What cannot be checked is logged and
+//! ignored.
+
+use std::{
+    fs::{self, File},
+    future::Future,
+    io,
+    os::unix::io::AsRawFd,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use super::{error::PowerError, settings::GraphicsSettings};
+
+const DNF_PID: &str = "/var/run/dnf.pid";
+const RPM_LOCK: &str = "/var/lib/rpm/.rpm.lock";
+const PACKAGEKIT: &str = "org.freedesktop.PackageKit";
+
+/// How often a running transaction is checked again while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long PackageKit may take to list its transactions.
+const PACKAGEKIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Roles of PackageKit transactions which install, remove or update packages: install files,
+/// install packages, remove packages, update packages, upgrade system and repair system.
+const PACKAGEKIT_CHANGING_ROLES: [u32; 6] = [10, 11, 14, 22, 29, 30];
+
+#[zbus::dbus_proxy(
+    interface = "org.freedesktop.PackageKit",
+    default_service = "org.freedesktop.PackageKit",
+    default_path = "/org/freedesktop/PackageKit"
+)]
+trait PackageKit {
+    fn get_transaction_list(&self) -> zbus::Result<Vec<zvariant::OwnedObjectPath>>;
+}
+
+#[zbus::dbus_proxy(
+    interface = "org.freedesktop.PackageKit.Transaction",
+    default_service = "org.freedesktop.PackageKit"
+)]
+trait PackageKitTransaction {
+    #[dbus_proxy(property)]
+    fn role(&self) -> zbus::Result<u32>;
+}
+
+/// Whether a package transaction is running.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Check {
+    Clear,
+    /// A transaction is running, held by the named package manager.
+    Active(String),
+    /// No transaction was found, but a source could not be checked.
+    Unknown(String),
+}
+
+impl Check {
+    /// The result as logged.
+    fn result(&self) -> &'static str {
+        match self {
+            Self::Clear => "clear",
+            Self::Active(_) => "active",
+            Self::Unknown(_) => "unknown",
+        }
+    }
+}
+
+/// Checks every source, a running transaction taking precedence over a failed check.
+pub(super) async fn check(connection: &zbus::Connection) -> Check {
+    let checks = [dnf(Path::new(DNF_PID)), rpm(Path::new(RPM_LOCK)), packagekit(connection).await];
+
+    let mut result = Check::Clear;
+    for check in checks {
+        match check {
+            Check::Active(_) => return check,
+            Check::Unknown(_) if result == Check::Clear => result = check,
+            _ => (),
+        }
+    }
+
+    result
+}
+
+/// Checks for a running transaction before `operation`, refusing it if one runs and waiting
+/// is not configured. Returns whether the caller must `wait` for the transaction.
+pub(super) async fn guard(
+    connection: &zbus::Connection,
+    settings: &GraphicsSettings,
+    operation: &'static str,
+) -> Result<bool, PowerError> {
+    let check = check(connection).await;
+    log_check(operation, &check);
+
+    match check {
+        Check::Active(holder) if !settings.wait_for_package_manager => {
+            Err(PowerError::TryAgainLater(format!("{} is installing or updating packages", holder)))
+        }
+        Check::Active(_) => Ok(true),
+        Check::Clear | Check::Unknown(_) => Ok(false),
+    }
+}
+
+/// Waits for the running transaction to end, up to the configured timeout. `waiting` is
+/// called with the holder of the transaction on every check.
+pub(super) async fn wait<F: Future<Output = ()>>(
+    connection: &zbus::Connection,
+    settings: &GraphicsSettings,
+    operation: &'static str,
+    waiting: impl Fn(String) -> F,
+) -> Result<(), PowerError> {
+    let timeout = Duration::from_secs(settings.package_manager_timeout_secs);
+    let start = Instant::now();
+
+    loop {
+        let holder = match check(connection).await {
+            Check::Active(holder) => holder,
+            _ => {
+                log::info!(
+                    operation = operation, package_transaction = "waited",
+                    waited_secs = start.elapsed().as_secs();
+                    "The package transaction finished after {}s, proceeding",
+                    start.elapsed().as_secs()
+                );
+                return Ok(());
+            }
+        };
+
+        if start.elapsed() >= timeout {
+            log::warn!(
+                operation = operation, package_transaction = "timed_out";
+                "Gave up waiting {}s for {} to finish", timeout.as_secs(), holder
+            );
+            return Err(PowerError::TryAgainLater(format!(
+                "{} was still installing or updating packages after {}s",
+                holder,
+                timeout.as_secs()
+            )));
+        }
+
+        waiting(holder).await;
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn log_check(operation: &'static str, check: &Check) {
+    let result = check.result();
+    match check {
+        Check::Clear => log::debug!(
+            operation = operation, package_transaction = result;
+            "No package transaction is running"
+        ),
+        Check::Active(holder) => log::warn!(
+            operation = operation, package_transaction = result;
+            "{} is running a package transaction", holder
+        ),
+        Check::Unknown(why) => log::warn!(
+            operation = operation, package_transaction = result;
+            "Proceeding without knowing whether a package transaction runs: {}", why
+        ),
+    }
+}
+
+/// dnf writes its pid while running, which is stale if the process is gone.
+fn dnf(path: &Path) -> Check {
+    let pid = match fs::read_to_string(path) {
+        Ok(pid) => pid,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Check::Clear,
+        Err(why) => return Check::Unknown(format!("failed to read {}: {}", path.display(), why)),
+    };
+
+    match pid.trim().parse::<u32>() {
+        Ok(pid) if pid > 0 && Path::new(&format!("/proc/{}", pid)).exists() => {
+            Check::Active(format!("dnf (pid {})", pid))
+        }
+        Ok(_) => Check::Clear,
+        Err(_) => Check::Unknown(format!("{} holds no pid", path.display())),
+    }
+}
+
+/// rpm holds a write lock of its lock file while it changes the database.
+fn rpm(path: &Path) -> Check {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Check::Clear,
+        Err(why) => return Check::Unknown(format!("failed to open {}: {}", path.display(), why)),
+    };
+
+    // SAFETY: `flock` is plain data, which is valid zeroed.
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+
+    // SAFETY: the descriptor is open for the duration of the call, and `lock` is valid.
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) } == -1 {
+        let why = io::Error::last_os_error();
+        return Check::Unknown(format!("failed to test the lock of {}: {}", path.display(), why));
+    }
+
+    if lock.l_type == libc::F_UNLCK as libc::c_short {
+        Check::Clear
+    } else {
+        Check::Active(format!("rpm (pid {})", lock.l_pid))
+    }
+}
+
+/// PackageKit is only asked if it is running, so that the check does not activate it.
+async fn packagekit(connection: &zbus::Connection) -> Check {
+    if connection.unique_name().is_none() {
+        return Check::Unknown("not connected to a message bus".into());
+    }
+
+    let result = async {
+        let dbus = zbus::fdo::DBusProxy::new(connection).await?;
+        let name = zbus::names::BusName::try_from(PACKAGEKIT)?;
+        if !dbus.name_has_owner(name).await? {
+            return Ok(Check::Clear);
+        }
+
+        let packagekit = PackageKitProxy::new(connection).await?;
+        for path in packagekit.get_transaction_list().await? {
+            let transaction =
+                PackageKitTransactionProxy::builder(connection).path(path)?.build().await?;
+            if PACKAGEKIT_CHANGING_ROLES.contains(&transaction.role().await?) {
+                return Ok(Check::Active("PackageKit".into()));
+            }
+        }
+
+        Ok::<_, zbus::Error>(Check::Clear)
+    };
+
+    match tokio::time::timeout(PACKAGEKIT_TIMEOUT, result).await {
+        Ok(Ok(check)) => check,
+        Ok(Err(why)) => Check::Unknown(format!("failed to ask PackageKit: {}", why)),
+        Err(_) => Check::Unknown("PackageKit did not list its transactions in time".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn dnf_pid() {
+        let dir = TempDir::new("package-transaction");
+        let path = dir.join("dnf.pid");
+
+        assert_eq!(dnf(&path), Check::Clear);
+
+        fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+        assert_eq!(dnf(&path), Check::Active(format!("dnf (pid {})", std::process::id())));
+
+        fs::write(&path, format!("{}\n", u32::MAX)).unwrap();
+        assert_eq!(dnf(&path), Check::Clear);
+
+        fs::write(&path, "").unwrap();
+        assert!(matches!(dnf(&path), Check::Unknown(_)));
+    }
+
+    #[test]
+    fn rpm_lock() {
+        let dir = TempDir::new("rpm-lock");
+        let path = dir.join(".rpm.lock");
+
+        assert_eq!(rpm(&path), Check::Clear);
+
+        // Locks of this process never conflict with its own test, so only the unlocked file
+        // can be checked here.
+        fs::write(&path, "").unwrap();
+        assert_eq!(rpm(&path), Check::Clear);
+    }
+}
@@ -4,63 +4,662 @@
 
 use super::pci_runtime_pm_support;
 use crate::{
-    errors::{BacklightError, ModelError, PciDeviceError, ProfileError, ScsiHostError},
-    kernel_parameters::{DeviceList, Dirty, KernelParameter, LaptopMode},
+    boost::{self, Boost},
+    dgpu_power_cap::{self, Gpu},
+    errors::{BacklightError, DgpuPowerCapError, ModelError, ProfileError},
+    kernel_parameters::{DeviceList, KernelParameter},
+    pcie_aspm,
     radeon::RadeonDevice,
-    Profile,
+    sysctl::{self, Sysctl},
+    tunables::{ProfileDef, ProfileTunables, KEYS},
+    usb_autosuspend::{self, UsbDevice},
+    util::{planned_command, Written},
+    wifi, Profile,
 };
 use intel_pstate::{PState, PStateError, PStateValues};
 use std::{
-    fs,
+    collections::BTreeSet,
+    fmt, fs,
     io::{self, Read, Seek, SeekFrom, Write},
-    path::Path,
+    path::{Path, PathBuf},
     process::Command,
 };
 use sysfs_class::{
-    Backlight, Brightness, Leds, PciDevice, RuntimePM, RuntimePowerManagement, ScsiHost, SysClass,
+    Backlight, Brightness, Leds, PciDevice, RuntimePowerManagement, ScsiHost, SysClass,
 };
+use system76_power_zbus::{
+    PlannedAction, ProfileFailure, ProfileInfo, ProfileParameter, ProfileTunable,
+};
+
+const BOOST_PATH: &str = "/sys/devices/system/cpu/cpufreq/boost";
+const CPUFREQ_PATH: &str = "/sys/devices/system/cpu/cpufreq/policy0";
+const INTEL_PSTATE_PATH: &str = "/sys/devices/system/cpu/intel_pstate";
+const SCSI_HOST_PATH: &str = "/sys/class/scsi_host";
+const RAPL_PATH: &str = "/sys/class/powercap/intel-rapl:0";
+
+/// Lowest brightness the profiles dim the screens to, in percent, unless overridden.
+const BRIGHTNESS_FLOOR: u8 = 5;
+
+/// The file of a SCSI host setting the power management of its SATA links.
+const LINK_POLICY: &str = "link_power_management_policy";
+
+/// Sets the parameters of a built-in profile, optionally including the brightness of
+/// backlights, as overridden.
+pub type ProfileFn = fn(&mut Applied, bool, &ProfileTunables);
+
+/// Outcome of setting a profile: the values written to sysfs, the parameters the hardware
+/// lacks, and the errors met, which stop no other parameter from being set.
+#[derive(Default)]
+pub struct Applied {
+    pub errors:         Vec<ProfileError>,
+    /// Values read to choose those written, such as the scaling driver.
+    pub read:           Vec<Written>,
+    pub written:        Vec<Written>,
+    /// Parameters which were not set, by name, with the file which does not exist, or which
+    /// the kernel refused.
+    pub not_applicable: Vec<(&'static str, String)>,
+    /// Parameters left to other power managers, which are not set, and kept across profiles.
+    pub yielded:        BTreeSet<&'static str>,
+}
+
+impl Applied {
+    /// Forgets the values written by the previous profile.
+    pub fn clear(&mut self) {
+        self.errors.clear();
+        self.read.clear();
+        self.written.clear();
+        self.not_applicable.clear();
+    }
+
+    /// The values read to choose those written, and the values written, with the values their
+    /// files hold now, then the parameters the hardware lacks, with empty values.
+    #[must_use]
+    pub fn parameters(&self) -> Vec<ProfileParameter> {
+        let not_applicable = self.not_applicable.iter().map(|(name, path)| ProfileParameter {
+            name: (*name).to_owned(),
+            path: path.clone(),
+            matches: true,
+            ..ProfileParameter::default()
+        });
+
+        self.read.iter().chain(&self.written).map(parameter).chain(not_applicable).collect()
+    }
+
+    /// The parameters which could not be set, with the errors of the OS.
+    #[must_use]
+    pub fn failures(&self) -> Vec<ProfileFailure> { self.errors.iter().map(failure).collect() }
+
+    /// Whether most parameters were set, counting each error other than a failed write as a
+    /// parameter, as the values of devices which fail are not recorded.
+    #[must_use]
+    pub fn mostly_applied(&self) -> bool {
+        let others = self.errors.iter().filter(|why| !matches!(why, ProfileError::Write(_)));
+        self.errors.len() * 2 < self.written.len() + others.count()
+    }
+
+    /// The outcome of setting the profile `name`: the parameters which failed, which are logged
+    /// as warnings, if most parameters were set, or else all of them as the error.
+    pub fn outcome(&self, name: &str) -> Result<Vec<ProfileFailure>, ProfileFailed> {
+        let failures = self.failures();
+        if !failures.is_empty() && !self.mostly_applied() {
+            return Err(ProfileFailed(failures));
+        }
+
+        for failure in &failures {
+            log::warn!("{}: {}", name, Described(failure));
+        }
+        Ok(failures)
+    }
+
+    /// Whether a parameter is left to another power manager. Parameters of hybrid CPUs are
+    /// matched without their `pcore.` or `ecore.` prefix.
+    fn is_yielded(&self, name: &str) -> bool {
+        self.yielded.contains(name.rsplit('.').next().unwrap_or(name))
+    }
+
+    /// Makes a parameter left to another power manager not applicable, returning whether it is.
+    fn yields(&mut self, name: &'static str, path: &str) -> bool {
+        if !self.is_yielded(name) {
+            return false;
+        }
+
+        log::info!("{} is not applicable: it is left to another power manager", name);
+        self.skip(name, path);
+        true
+    }
+
+    fn record(&mut self, written: impl IntoIterator<Item = Written>) {
+        self.written.extend(written);
+    }
+
+    /// Writes values, recording them, and the errors of those which could not be written
+    /// without stopping at them.
+    fn write(&mut self, written: impl IntoIterator<Item = Written>) {
+        for written in written {
+            if self.yields(written.name, &written.path) {
+                continue;
+            }
+
+            if let Err(why) = written.write() {
+                self.errors.push(why.into());
+            }
+            self.written.push(written);
+        }
+    }
+
+    /// Writes a value, making its parameter not applicable rather than failed if the kernel
+    /// `refused` the write, as it does for parameters which cannot be changed on this system.
+    fn write_unless_refused(&mut self, written: Written, refused: fn(&io::Error) -> bool) {
+        if self.yields(written.name, &written.path) {
+            return;
+        }
+
+        match written.write() {
+            Ok(()) => self.written.push(written),
+            Err(why) if refused(&why.source) => {
+                log::info!("{} is not applicable: {}", written.name, why);
+                self.skip(written.name, written.path);
+            }
+            Err(why) => {
+                self.errors.push(why.into());
+                self.written.push(written);
+            }
+        }
+    }
+
+    fn skip(&mut self, name: &'static str, path: impl Into<String>) {
+        self.not_applicable.push((name, path.into()));
+    }
+}
+
+fn parameter(written: &Written) -> ProfileParameter {
+    let current = written.current();
+    ProfileParameter {
+        name:     written.name.to_owned(),
+        path:     written.path.clone(),
+        intended: written.value.clone(),
+        matches:  current.as_deref() == Some(written.value.as_str()),
+        current:  current.unwrap_or_default(),
+    }
+}
+
+/// Most parameters of a profile could not be set: those which failed, with their errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileFailed(pub Vec<ProfileFailure>);
+
+impl fmt::Display for ProfileFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Errors found when setting profile:")?;
+        for failure in &self.0 {
+            write!(f, "\n    - {}", Described(failure))?;
+        }
+        Ok(())
+    }
+}
+
+/// A parameter which failed, described as its error was.
+struct Described<'a>(&'a ProfileFailure);
+
+impl fmt::Display for Described<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let failure = self.0;
+        match failure.path.as_str() {
+            "" => f.write_str(&failure.message),
+            path => write!(f, "failed to write {} to {}: {}", failure.value, path, failure.message),
+        }
+    }
+}
+
+fn failure(error: &ProfileError) -> ProfileFailure {
+    match error {
+        ProfileError::Write(why) => ProfileFailure {
+            name:    why.written.name.to_owned(),
+            path:    why.written.path.clone(),
+            value:   why.written.value.clone(),
+            errno:   why.errno().unwrap_or_default(),
+            message: why.source.to_string(),
+        },
+        why => ProfileFailure {
+            name: why.parameter().to_owned(),
+            message: why.to_string(),
+            ..ProfileFailure::default()
+        },
+    }
+}
+
+/// Sets the parameters of a profile, optionally including the brightness of backlights.
+pub fn set(profile: &ProfileDef, applied: &mut Applied, set_brightness: bool) {
+    let func: ProfileFn = match profile.base {
+        Profile::Battery => battery,
+        Profile::Quiet => quiet,
+        Profile::Balanced => balanced,
+        Profile::Performance => performance,
+    };
+
+    func(applied, set_brightness, &profile.tunables);
+}
+
+/// Describes a profile, with the key parameters it sets on this machine, without setting them.
+#[must_use]
+pub fn describe(def: &ProfileDef) -> ProfileInfo {
+    let (profile, tunables) = (def.base, &def.tunables);
+    let description = match profile {
+        Profile::Battery => {
+            "Saves power: limits the CPU to half its frequency, disables turbo and dims backlights"
+        }
+        Profile::Quiet => {
+            "Keeps the fans quiet: disables turbo, without the power saving of the battery profile \
+             which adds latency"
+        }
+        Profile::Balanced => "Balances performance and power use, the default",
+        Profile::Performance => "Favors performance over power use, keeping devices powered",
+    };
+    let description = match def.description.as_str() {
+        _ if !def.is_custom() => description.to_owned(),
+        "" => format!("Custom profile based on {}", profile),
+        custom => custom.to_owned(),
+    };
+
+    let (governor, epp) = crate::cpufreq::planned(profile, tunables).unwrap_or_default();
+
+    // Turbo is controlled through the Intel PState values, if they exist, and otherwise
+    // through the boost of cpufreq, which profiles may leave alone.
+    let turbo = match (PState::new(), cpufreq_boost(profile, tunables)) {
+        (Ok(_), _) if turbo(profile, tunables) => "on",
+        (Ok(_), _) => "off",
+        (Err(_), Some(enabled)) if Boost::detect().is_some() => on_off(enabled),
+        (Err(_), _) => "",
+    };
+
+    ProfileInfo {
+        name: def.name.clone(),
+        description,
+        governor,
+        epp: epp.unwrap_or_default(),
+        turbo: turbo.to_owned(),
+        platform_profile: platform_profile(profile, tunables).unwrap_or_default(),
+    }
+}
+
+/// The parameters of a profile which `profiles.toml` may override, with the values the
+/// profile sets on this machine.
+#[must_use]
+pub fn tunables(def: &ProfileDef) -> Vec<ProfileTunable> {
+    let (profile, tunables) = (def.base, &def.tunables);
+    let info = describe(def);
+
+    // The frequency is limited through the scaling driver or the Intel PState values.
+    let max_percent = if info.governor.is_empty() && info.turbo.is_empty() {
+        String::new()
+    } else {
+        max_percent(profile, tunables).to_string()
+    };
+
+    KEYS.into_iter()
+        .map(|key| {
+            let value = match key {
+                "governor" => info.governor.clone(),
+                "energy_performance_preference" => info.epp.clone(),
+                "turbo" => info.turbo.clone(),
+                "max_frequency_percent" => max_percent.clone(),
+                "platform_profile" => info.platform_profile.clone(),
+                "pcie_aspm_policy" => pcie_aspm(profile, tunables).unwrap_or_default(),
+                "wifi_powersave" => wifi_powersave_enabled(profile, tunables)
+                    .map(on_off)
+                    .unwrap_or_default()
+                    .to_owned(),
+                "brightness" => match screen_brightness(profile, tunables) {
+                    Some((percent, true)) => format!("at most {}%", percent),
+                    Some((percent, false)) => format!("{}%", percent),
+                    None => "unchanged".to_owned(),
+                },
+                "brightness_floor" => {
+                    format!("{}%", tunables.brightness_floor.unwrap_or(BRIGHTNESS_FLOOR))
+                }
+                "dgpu_power_cap_watts" => {
+                    tunables.dgpu_power_cap_watts.map(|w| format!("{} W", w)).unwrap_or_default()
+                }
+                "usb_autosuspend" => on_off(usb_autosuspend_enabled(profile, tunables)).to_owned(),
+                "usb_autosuspend_deny_ids" => {
+                    let ids = tunables.usb_autosuspend_deny_ids.as_deref().unwrap_or_default();
+                    let ids = ids.iter().map(|&id| usb_autosuspend::format_id(id));
+                    ids.collect::<Vec<_>>().join(", ")
+                }
+                "usb_autosuspend_deny_classes" => tunables
+                    .usb_autosuspend_deny_classes
+                    .as_deref()
+                    .unwrap_or(&usb_autosuspend::DENIED_CLASSES)
+                    .iter()
+                    .map(|class| format!("{:#04x}", class))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                "sata_link_policy" => sata_link_policies(profile, tunables).remove(0),
+                _ => sysctl_values(profile, tunables)
+                    .into_iter()
+                    .find(|(sysctl, _)| sysctl.name() == key)
+                    .and_then(|(sysctl, value)| match sysctl {
+                        Sysctl::NmiWatchdog => value.map(|v| on_off(v == "1").to_owned()),
+                        _ => value,
+                    })
+                    .unwrap_or_default(),
+            };
+
+            ProfileTunable {
+                profile: def.name.clone(),
+                key: key.to_owned(),
+                value,
+                overridden: tunables.is_set(key),
+            }
+        })
+        .collect()
+}
+
+/// Whether a profile lets the Intel PState driver boost the frequency.
+fn turbo(profile: Profile, tunables: &ProfileTunables) -> bool {
+    tunables.turbo.unwrap_or(!matches!(profile, Profile::Battery | Profile::Quiet))
+}
+
+/// Whether a profile enables the boost of cpufreq, if it sets it. Only the battery and quiet
+/// profiles disable it, unless overridden; the others restore the boost the profiles found.
+fn cpufreq_boost(profile: Profile, tunables: &ProfileTunables) -> Option<bool> {
+    tunables.turbo.or(matches!(profile, Profile::Battery | Profile::Quiet).then_some(false))
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// The maximum frequency of the CPUs with a profile, in percent of their maximum.
+fn max_percent(profile: Profile, tunables: &ProfileTunables) -> u8 {
+    let builtin = if profile == Profile::Battery { 50 } else { 100 };
+    tunables.max_frequency_percent.unwrap_or(builtin)
+}
+
+/// The profile with the maximum frequency of the CPUs capped to `percent`, as are the limits of
+/// the `pcore` and `ecore` tables, keeping the limits of the profile which are lower.
+#[must_use]
+pub fn capped(def: &ProfileDef, percent: u8) -> ProfileDef {
+    let mut def = def.clone();
+    let tunables = &mut def.tunables;
+    tunables.max_frequency_percent = Some(max_percent(def.base, tunables).min(percent));
+    for core in [&mut tunables.pcore, &mut tunables.ecore] {
+        core.max_frequency_percent = core.max_frequency_percent.map(|limit| limit.min(percent));
+    }
+    def
+}
+
+/// The Intel [`PState`] values of a profile.
+fn pstate(profile: Profile, tunables: &ProfileTunables) -> PStateValues {
+    let values = PStateValues::default()
+        .min_perf_pct(0)
+        .max_perf_pct(max_percent(profile, tunables))
+        .no_turbo(!turbo(profile, tunables));
+
+    if matches!(profile, Profile::Battery | Profile::Quiet) {
+        values
+    } else {
+        values.hwp_dynamic_boost(true)
+    }
+}
+
+/// The ACPI platform profile of a profile, if the hardware is supported by the kernel.
+fn platform_profile(profile: Profile, tunables: &ProfileTunables) -> Option<String> {
+    crate::acpi_platform::planned(profile, tunables.platform_profile.as_deref())
+}
+
+/// The values setting a profile writes on this machine, and the commands it runs, without
+/// setting it. Backlights are dimmed only with `set_brightness`, as when setting the profile.
+#[must_use]
+pub fn plan(def: &ProfileDef, set_brightness: bool) -> Vec<PlannedAction> {
+    let (profile, tunables) = (def.base, &def.tunables);
+
+    // Radeon power profile, DPM state and DPM performance level.
+    let radeon = match profile {
+        Profile::Battery => ("low", "battery", "low"),
+        Profile::Quiet => ("low", "balanced", "auto"),
+        Profile::Balanced => ("auto", "performance", "auto"),
+        Profile::Performance => ("high", "performance", "auto"),
+    };
+
+    let mut written = Vec::new();
+    written.extend(
+        platform_profile(profile, tunables).map(|choice| crate::acpi_platform::plan(&choice)),
+    );
+
+    // Parameters the profile leaves alone restore the value a previous profile changed, if any.
+    for (sysctl, value) in sysctl_values(profile, tunables) {
+        if let Some(value) = value.or_else(|| sysctl::original(sysctl.path())) {
+            written.push(Written::new(sysctl.name(), sysctl.path(), value));
+        }
+    }
+
+    for dev in RadeonDevice::get_devices() {
+        written.extend(radeon_written(&dev, radeon));
+    }
+
+    // The first policy the host accepts is set, which is usually the first.
+    let link_policy = &sata_link_policies(profile, tunables)[0];
+    for path in sata_link_policy_paths().filter_map(Result::ok) {
+        written.push(Written::new(LINK_POLICY, path.to_string_lossy(), link_policy));
+    }
+
+    if set_brightness {
+        let keyboard = match profile {
+            Profile::Battery => Some(0),
+            Profile::Balanced => Some(50),
+            Profile::Quiet | Profile::Performance => None,
+        };
+
+        if let Some((percent, only_lower)) = screen_brightness(profile, tunables) {
+            written.extend(
+                Backlight::iter()
+                    .filter_map(Result::ok)
+                    .filter_map(|backlight| planned_brightness(&backlight, percent, only_lower)),
+            );
+        }
+
+        // Keyboards are only dimmed, except on battery where they are turned off.
+        if let Some(percent) = keyboard {
+            let only_lower = profile != Profile::Battery;
+            written.extend(
+                Leds::iter_keyboards()
+                    .filter_map(Result::ok)
+                    .filter_map(|keyboard| planned_brightness(&keyboard, percent, only_lower)),
+            );
+        }
+    }
+
+    written.extend(crate::cpufreq::plan(profile, max_percent(profile, tunables), tunables));
+
+    if PState::new().is_ok() {
+        written.extend(pstate_written(&pstate(profile, tunables)));
+    }
+
+    // Profiles leaving boost alone restore the value a previous profile changed, if any.
+    if let Some(interface) = Boost::detect().filter(|i| !matches!(i, Boost::IntelPstate(_))) {
+        let enabled = cpufreq_boost(profile, tunables).or_else(boost::original);
+        written.extend(enabled.map(|enabled| interface.plan(enabled)).unwrap_or_default());
+    }
+
+    if pci_runtime_pm_support() {
+        let pm = if profile == Profile::Performance {
+            RuntimePowerManagement::Off
+        } else {
+            RuntimePowerManagement::On
+        };
+        for device in PciDevice::iter().filter_map(Result::ok) {
+            written.push(runtime_pm_written(&device, pm));
+        }
+    }
+
+    written.extend(pcie_aspm(profile, tunables).map(|policy| pcie_aspm::plan(&policy)));
+
+    for device in UsbDevice::all() {
+        if let Some(control) = usb_control(&device, profile, tunables) {
+            let path = device.control_path();
+            written.push(Written::new(usb_autosuspend::NAME, path.to_string_lossy(), control));
+        }
+    }
+
+    let mut plan = written.iter().map(Written::planned).collect::<Vec<_>>();
+
+    // Profiles leaving the power save of Wi-Fi alone restore the value a previous profile
+    // changed, if any.
+    for interface in wifi::interfaces() {
+        let enabled =
+            wifi_powersave_enabled(profile, tunables).or_else(|| wifi::original(&interface));
+        if let Some(enabled) = enabled.filter(|&e| wifi::power_save(&interface) != Some(e)) {
+            plan.push(planned_command(
+                "iw",
+                &["dev", &interface, "set", "power_save", on_off(enabled)],
+            ));
+        }
+    }
+
+    // Likewise for the power cap of the discrete GPUs, except those suspended, which reading
+    // their limits would wake.
+    for gpu in Gpu::all().into_iter().filter(|gpu| !gpu.suspended()) {
+        let watts = tunables.dgpu_power_cap_watts.or_else(|| dgpu_power_cap::original(&gpu.bus_id));
+        let Some(watts) = watts else { continue };
+        let Some(limits) = gpu.limits() else { continue };
+        let watts = limits.clamp(watts);
+        match gpu.cap_path() {
+            Some(path) => {
+                let microwatts = Gpu::cap_microwatts(watts);
+                plan.push(
+                    Written::new(dgpu_power_cap::NAME, path.to_string_lossy(), microwatts)
+                        .planned(),
+                );
+            }
+            None if limits.current != watts => plan.push(planned_command(
+                "nvidia-smi",
+                &["-i", &gpu.bus_id, "-pl", &watts.to_string()],
+            )),
+            None => (),
+        }
+    }
+
+    if let Some(model_profiles) = ModelProfiles::new() {
+        plan.extend(
+            match profile {
+                Profile::Battery | Profile::Quiet => model_profiles.battery,
+                Profile::Balanced => model_profiles.balanced,
+                Profile::Performance => model_profiles.performance,
+            }
+            .plan(),
+        );
+    }
+
+    plan
+}
+
+/// The brightness a backlight is set to, at `percent` of its maximum, unless `only_lower` and
+/// it is already as dim.
+fn planned_brightness<B: Brightness>(
+    backlight: &B,
+    percent: u8,
+    only_lower: bool,
+) -> Option<Written> {
+    let max = backlight.max_brightness().ok()?;
+    // A percent above zero never turns the backlight off.
+    let value = (max * u64::from(percent) / 100).max(u64::from(percent > 0));
+    if only_lower && backlight.brightness().ok()? <= value {
+        return None;
+    }
+
+    Some(Written::new("brightness", backlight.path().join("brightness").to_string_lossy(), value))
+}
+
+/// The brightness a profile sets the screens to, in percent, and whether it only dims them, or
+/// `None` if it leaves them unchanged. The battery and balanced profiles dim the screens to 10%
+/// and 40%, never below the floor of the profile.
+fn screen_brightness(profile: Profile, tunables: &ProfileTunables) -> Option<(u8, bool)> {
+    let (percent, only_lower) = match tunables.brightness {
+        Some(percent) => (percent?, false),
+        None => match profile {
+            Profile::Battery => (10, true),
+            Profile::Balanced => (40, true),
+            Profile::Quiet | Profile::Performance => return None,
+        },
+    };
+
+    Some((percent.max(tunables.brightness_floor.unwrap_or(BRIGHTNESS_FLOOR)), only_lower))
+}
+
+/// Sets the brightness of the screens, as [`screen_brightness`] tells, recording the values
+/// written, and logging those they replace.
+fn screens(applied: &mut Applied, profile: Profile, tunables: &ProfileTunables) {
+    let Some((percent, only_lower)) = screen_brightness(profile, tunables) else { return };
+    for backlight in Backlight::iter() {
+        match backlight {
+            Ok(backlight) => {
+                let Some(written) = planned_brightness(&backlight, percent, only_lower) else {
+                    continue;
+                };
+
+                if let Ok(previous) = backlight.brightness() {
+                    log::info!(
+                        "Setting brightness of {} from {} to {}",
+                        backlight.id(),
+                        previous,
+                        written.value
+                    );
+                }
+                applied.write(Some(written));
+            }
+            Err(why) => log::warn!("failed to iterate backlight: {}", why),
+        }
+    }
+}
+
+/// The power limits of the model, unless they are left to another power manager.
+fn model_profiles(applied: &mut Applied) -> Option<ModelProfiles> {
+    ModelProfiles::new().filter(|_| !applied.yields("model", RAPL_PATH))
+}
 
 /// Instead of returning on the first error, we want to collect all errors that occur while
 /// setting a profile. Even if one parameter fails to set, we'll still be able to set other
 /// parameters successfully.
 macro_rules! catch {
-    ($errors:ident, $result:expr) => {
+    ($applied:ident, $result:expr) => {
         match $result {
             Ok(_) => (),
-            Err(why) => $errors.push(why.into()),
+            Err(why) => $applied.errors.push(why.into()),
         }
     };
 }
 
 /// Sets parameters for the balanced profile.
-pub fn balanced(errors: &mut Vec<ProfileError>, set_brightness: bool) {
+pub fn balanced(applied: &mut Applied, set_brightness: bool, tunables: &ProfileTunables) {
     // Use the ACPI Platform Profile if the hardware is supported by the kernel.
-    if crate::acpi_platform::supported() {
-        crate::acpi_platform::balanced();
-    }
+    apply_platform_profile(applied, Profile::Balanced, tunables);
 
-    // The dirty kernel parameter controls how often the OS will sync data to disks. The less
+    // The dirty kernel parameters control how often the OS will sync data to disks. The less
     // frequently this occurs, the more power can be saved, yet the higher the risk of sudden
-    // power loss causing loss of data. 15s is a reasonable number.
-    Dirty::default().set_max_lost_work(15);
-
-    // Enables the laptop mode feature in the kernel, which allows mechanical drives to spin down
-    // when inactive.
-    LaptopMode.set(b"2");
+    // power loss causing loss of data. 15s is a reasonable number. The laptop mode of the
+    // kernel allows mechanical drives to spin down when inactive.
+    sysctls(applied, Profile::Balanced, tunables);
 
     // Sets radeon power profiles for AMD graphics.
-    RadeonDevice::get_devices().for_each(|dev| dev.set_profiles("auto", "performance", "auto"));
+    radeon(applied, ("auto", "performance", "auto"));
+
+    // Caps the power of the discrete GPUs, if the profile sets a cap, or restores it.
+    dgpu_power_cap(applied, tunables);
 
     // Enables SCSI / SATA link time power management.
-    catch!(errors, scsi_host_link_time_pm_policy(&["med_power_with_dipm", "medium_power"]));
+    sata_link_policy(applied, Profile::Balanced, tunables);
 
     if set_brightness {
         // Manage screen backlights.
-        catch!(errors, iterate_backlights(Backlight::iter(), &Brightness::set_if_lower_than, 40));
+        screens(applied, Profile::Balanced, tunables);
 
         // Manage keyboard backlights.
         catch!(
-            errors,
+            applied,
             iterate_backlights(Leds::iter_keyboards(), &Brightness::set_if_lower_than, 50)
         );
     }
@@ -68,102 +667,490 @@ pub fn balanced(errors: &mut Vec<ProfileError>, set_brightness: bool) {
     // Parameters which may cause on certain systems.
     if pci_runtime_pm_support() {
         // Enables PCI device runtime power management.
-        catch!(errors, pci_device_runtime_pm(RuntimePowerManagement::On));
+        pci_device_runtime_pm(applied, RuntimePowerManagement::On);
     }
 
+    // Sets the PCIe ASPM policy, unless the firmware keeps control of it.
+    pcie_aspm_policy(applied, Profile::Balanced, tunables);
+
+    // Lets USB devices suspend when idle, except those which misbehave when suspended.
+    usb_autosuspend(applied, Profile::Balanced, tunables, |_| true);
+
+    // Leaves the power save of Wi-Fi to the previous value, as it adds latency to calls.
+    wifi_powersave(applied, Profile::Balanced, tunables);
+
     // Set to balanced profile.
-    crate::cpufreq::set(Profile::Balanced, 100);
+    cpufreq(applied, Profile::Balanced, tunables);
+    apply_boost(applied, Profile::Balanced, tunables);
 
     // Control Intel PState values, if they exist.
-    catch!(
-        errors,
-        pstate_values(
-            PStateValues::default()
-                .hwp_dynamic_boost(true)
-                .min_perf_pct(0)
-                .max_perf_pct(100)
-                .no_turbo(false)
-        )
-    );
+    catch!(applied, pstate_values(applied, pstate(Profile::Balanced, tunables)));
 
-    if let Some(model_profiles) = ModelProfiles::new() {
-        catch!(errors, model_profiles.balanced.set());
+    if let Some(model_profiles) = model_profiles(applied) {
+        catch!(applied, model_profiles.balanced.set());
+    }
+}
+
+/// Sets parameters for the quiet profile, which keeps the fans quiet without the power saving
+/// of the battery profile which adds latency: the sync of data to disk, the SATA links, the USB
+/// devices and the Wi-Fi are left to perform, and the screens are left alone.
+pub fn quiet(applied: &mut Applied, _set_brightness: bool, tunables: &ProfileTunables) {
+    apply_platform_profile(applied, Profile::Quiet, tunables);
+
+    sysctls(applied, Profile::Quiet, tunables);
+    radeon(applied, ("low", "balanced", "auto"));
+    dgpu_power_cap(applied, tunables);
+    sata_link_policy(applied, Profile::Quiet, tunables);
+    cpufreq(applied, Profile::Quiet, tunables);
+    apply_boost(applied, Profile::Quiet, tunables);
+    catch!(applied, pstate_values(applied, pstate(Profile::Quiet, tunables)));
+
+    if pci_runtime_pm_support() {
+        pci_device_runtime_pm(applied, RuntimePowerManagement::On);
+    }
+
+    pcie_aspm_policy(applied, Profile::Quiet, tunables);
+    usb_autosuspend(applied, Profile::Quiet, tunables, |_| true);
+    wifi_powersave(applied, Profile::Quiet, tunables);
+
+    // The lowest power limits of the model keep the fans quiet.
+    if let Some(model_profiles) = model_profiles(applied) {
+        catch!(applied, model_profiles.battery.set());
     }
 }
 
 /// Sets parameters for the performance profile
-pub fn performance(errors: &mut Vec<ProfileError>, _set_brightness: bool) {
+pub fn performance(applied: &mut Applied, _set_brightness: bool, tunables: &ProfileTunables) {
     // Use the ACPI Platform Profile if the hardware is supported by the kernel.
-    if crate::acpi_platform::supported() {
-        crate::acpi_platform::performance();
-    }
-
-    Dirty::default().set_max_lost_work(15);
-    LaptopMode.set(b"0");
-    RadeonDevice::get_devices().for_each(|dev| dev.set_profiles("high", "performance", "auto"));
-    catch!(errors, scsi_host_link_time_pm_policy(&["med_power_with_dipm", "max_performance"]));
-    crate::cpufreq::set(Profile::Performance, 100);
-    catch!(
-        errors,
-        pstate_values(
-            PStateValues::default()
-                .hwp_dynamic_boost(true)
-                .min_perf_pct(0)
-                .max_perf_pct(100)
-                .no_turbo(false)
-        )
-    );
+    apply_platform_profile(applied, Profile::Performance, tunables);
+
+    sysctls(applied, Profile::Performance, tunables);
+    radeon(applied, ("high", "performance", "auto"));
+    dgpu_power_cap(applied, tunables);
+    sata_link_policy(applied, Profile::Performance, tunables);
+    cpufreq(applied, Profile::Performance, tunables);
+    apply_boost(applied, Profile::Performance, tunables);
+    catch!(applied, pstate_values(applied, pstate(Profile::Performance, tunables)));
 
     if pci_runtime_pm_support() {
-        catch!(errors, pci_device_runtime_pm(RuntimePowerManagement::Off));
+        pci_device_runtime_pm(applied, RuntimePowerManagement::Off);
     }
 
-    if let Some(model_profiles) = ModelProfiles::new() {
-        catch!(errors, model_profiles.performance.set());
+    pcie_aspm_policy(applied, Profile::Performance, tunables);
+    usb_autosuspend(applied, Profile::Performance, tunables, |_| true);
+    wifi_powersave(applied, Profile::Performance, tunables);
+
+    if let Some(model_profiles) = model_profiles(applied) {
+        catch!(applied, model_profiles.performance.set());
     }
 }
 
 /// Sets parameters for the battery profile
-pub fn battery(errors: &mut Vec<ProfileError>, set_brightness: bool) {
+pub fn battery(applied: &mut Applied, set_brightness: bool, tunables: &ProfileTunables) {
     // Use the ACPI Platform Profile if the hardware is supported by the kernel.
-    if crate::acpi_platform::supported() {
-        crate::acpi_platform::battery();
-    }
-
-    Dirty::default().set_max_lost_work(15);
-    LaptopMode.set(b"2");
-    RadeonDevice::get_devices().for_each(|dev| dev.set_profiles("low", "battery", "low"));
-    catch!(errors, scsi_host_link_time_pm_policy(&["min_power", "min_power"]));
-    crate::cpufreq::set(Profile::Battery, 50);
+    apply_platform_profile(applied, Profile::Battery, tunables);
 
-    catch!(
-        errors,
-        pstate_values(PStateValues::default().min_perf_pct(0).max_perf_pct(50).no_turbo(true))
-    );
+    sysctls(applied, Profile::Battery, tunables);
+    radeon(applied, ("low", "battery", "low"));
+    dgpu_power_cap(applied, tunables);
+    sata_link_policy(applied, Profile::Battery, tunables);
+    cpufreq(applied, Profile::Battery, tunables);
+    apply_boost(applied, Profile::Battery, tunables);
+    catch!(applied, pstate_values(applied, pstate(Profile::Battery, tunables)));
 
     if set_brightness {
-        catch!(errors, iterate_backlights(Backlight::iter(), &Brightness::set_if_lower_than, 10));
-        catch!(errors, iterate_backlights(Leds::iter_keyboards(), &Brightness::set_brightness, 0));
+        screens(applied, Profile::Battery, tunables);
+        catch!(applied, iterate_backlights(Leds::iter_keyboards(), &Brightness::set_brightness, 0));
     }
 
     if pci_runtime_pm_support() {
-        catch!(errors, pci_device_runtime_pm(RuntimePowerManagement::On));
+        pci_device_runtime_pm(applied, RuntimePowerManagement::On);
     }
 
-    if let Some(model_profiles) = ModelProfiles::new() {
-        catch!(errors, model_profiles.battery.set());
+    pcie_aspm_policy(applied, Profile::Battery, tunables);
+    usb_autosuspend(applied, Profile::Battery, tunables, |_| true);
+    wifi_powersave(applied, Profile::Battery, tunables);
+
+    if let Some(model_profiles) = model_profiles(applied) {
+        catch!(applied, model_profiles.battery.set());
+    }
+}
+
+/// Applies the ACPI platform profile of a profile, if the hardware is supported by the kernel.
+fn apply_platform_profile(applied: &mut Applied, profile: Profile, tunables: &ProfileTunables) {
+    match platform_profile(profile, tunables) {
+        Some(choice) => {
+            let wanted = tunables.platform_profile.as_ref().filter(|wanted| **wanted != choice);
+            if let Some(wanted) = wanted {
+                log::warn!("The firmware lacks the {} platform profile, using {}", wanted, choice);
+            }
+            applied.write(Some(crate::acpi_platform::plan(&choice)));
+        }
+        None => applied.skip("platform_profile", crate::acpi_platform::SYSFS_PATH),
+    }
+}
+
+/// Sets the governor, the frequency limits and the energy performance preference of the CPUs,
+/// as their scaling driver takes them, recording which driver it was.
+fn cpufreq(applied: &mut Applied, profile: Profile, tunables: &ProfileTunables) {
+    match crate::cpufreq::Driver::detect() {
+        Some(driver) => applied.read.extend(driver.read_from()),
+        None => applied.skip("cpufreq", CPUFREQ_PATH),
+    }
+
+    applied.write(crate::cpufreq::plan(profile, max_percent(profile, tunables), tunables));
+}
+
+/// Enables or disables the boost of cpufreq as the profile sets it, remembering the previous
+/// value, or restores that value if the profile leaves boost alone. The boost of intel_pstate
+/// is set with its PState values.
+fn apply_boost(applied: &mut Applied, profile: Profile, tunables: &ProfileTunables) {
+    let interface = match Boost::detect() {
+        Some(Boost::IntelPstate(_)) => return,
+        Some(interface) => interface,
+        None => return applied.skip("boost", BOOST_PATH),
+    };
+
+    match cpufreq_boost(profile, tunables) {
+        Some(enabled) => {
+            interface.remember();
+            applied.write(interface.plan(enabled));
+        }
+        None => match boost::restore() {
+            Some(enabled) => applied.write(interface.plan(enabled)),
+            None => applied.read.extend(interface.read_from()),
+        },
+    }
+}
+
+/// The values a profile writes to the parameters of `/proc/sys`, or `None` for those it leaves
+/// alone. The battery and balanced profiles limit the work lost on a sudden power loss to 15
+/// seconds, and enable the laptop mode, while only the battery profile disables the NMI
+/// watchdog.
+fn sysctl_values(profile: Profile, tunables: &ProfileTunables) -> [(Sysctl, Option<String>); 4] {
+    let saves = matches!(profile, Profile::Battery | Profile::Balanced);
+    let nmi_watchdog = tunables.nmi_watchdog.or((profile == Profile::Battery).then_some(false));
+
+    [
+        (
+            Sysctl::DirtyWritebackCentisecs,
+            tunables.dirty_writeback_centisecs.or(saves.then_some(1500)).map(|c| c.to_string()),
+        ),
+        (
+            Sysctl::DirtyExpireCentisecs,
+            tunables.dirty_expire_centisecs.or(saves.then_some(1500)).map(|c| c.to_string()),
+        ),
+        (Sysctl::LaptopMode, tunables.laptop_mode.or(saves.then_some(2)).map(|s| s.to_string())),
+        (Sysctl::NmiWatchdog, nmi_watchdog.map(|enabled| u8::from(enabled).to_string())),
+    ]
+}
+
+/// Sets the parameters of `/proc/sys` of a profile, remembering their previous values, or
+/// restores those values for the parameters it leaves alone. Where `/proc/sys` is read-only, as
+/// in containers, they are not applicable.
+fn sysctls(applied: &mut Applied, profile: Profile, tunables: &ProfileTunables) {
+    for (sysctl, value) in sysctl_values(profile, tunables) {
+        let (name, path) = (sysctl.name(), sysctl.path());
+        if !Path::new(path).exists() {
+            applied.skip(name, path);
+            continue;
+        }
+
+        let value = match value {
+            Some(value) => {
+                sysctl::remember(path);
+                value
+            }
+            None => match sysctl::restore(path) {
+                Some(value) => value,
+                None => {
+                    applied.read.extend(sysctl::read(path).map(|v| Written::new(name, path, v)));
+                    continue;
+                }
+            },
+        };
+
+        applied.write_unless_refused(Written::new(name, path, value), sysctl::read_only);
+    }
+}
+
+/// Sets the power profile, DPM state and DPM performance level of Radeon graphics.
+fn radeon(applied: &mut Applied, profiles: (&str, &str, &str)) {
+    for dev in RadeonDevice::get_devices() {
+        applied.write(radeon_written(&dev, profiles));
+    }
+}
+
+/// The Radeon profiles of a device, in the order `RadeonDevice::set_profiles` would write
+/// them.
+fn radeon_written(
+    dev: &RadeonDevice,
+    (power_profile, dpm_state, dpm_perf): (&str, &str, &str),
+) -> [Written; 4] {
+    [
+        Written::new("power_dpm_state", dev.dpm_state.get_path().to_string_lossy(), dpm_state),
+        Written::new(
+            "power_dpm_force_performance_level",
+            dev.dpm_force_performance.get_path().to_string_lossy(),
+            dpm_perf,
+        ),
+        Written::new("power_method", dev.power_method.get_path().to_string_lossy(), "profile"),
+        Written::new(
+            "power_profile",
+            dev.power_profile.get_path().to_string_lossy(),
+            power_profile,
+        ),
+    ]
+}
+
+/// Sets the PCIe ASPM policy of a profile. Kernels without it, or refusing it, as when the
+/// firmware keeps control of ASPM, make it not applicable rather than failed.
+fn pcie_aspm_policy(applied: &mut Applied, profile: Profile, tunables: &ProfileTunables) {
+    let Some(policy) = pcie_aspm(profile, tunables) else {
+        return applied.skip("pcie_aspm_policy", pcie_aspm::SYSFS_PATH);
+    };
+
+    applied.write_unless_refused(pcie_aspm::plan(&policy), pcie_aspm::locked);
+}
+
+/// The PCIe ASPM policy of a profile, if the kernel accepts it.
+fn pcie_aspm(profile: Profile, tunables: &ProfileTunables) -> Option<String> {
+    let policy = tunables.pcie_aspm_policy.as_deref().unwrap_or(pcie_aspm::builtin(profile));
+    pcie_aspm::supported(policy).then(|| policy.to_owned())
+}
+
+/// Whether a profile lets USB devices suspend when idle: not with the performance and quiet
+/// profiles, unless overridden.
+fn usb_autosuspend_enabled(profile: Profile, tunables: &ProfileTunables) -> bool {
+    let builtin = matches!(profile, Profile::Battery | Profile::Balanced);
+    tunables.usb_autosuspend.unwrap_or(builtin)
+}
+
+/// The `power/control` of a USB device with a profile: `auto` to let it suspend when idle, or
+/// `on` with the performance and quiet profiles, or `None` if the device is denied autosuspend.
+fn usb_control(
+    device: &UsbDevice,
+    profile: Profile,
+    tunables: &ProfileTunables,
+) -> Option<&'static str> {
+    if !usb_autosuspend_enabled(profile, tunables) {
+        return Some("on");
+    }
+
+    let ids = tunables.usb_autosuspend_deny_ids.as_deref().unwrap_or_default();
+    let classes = tunables
+        .usb_autosuspend_deny_classes
+        .as_deref()
+        .unwrap_or(&usb_autosuspend::DENIED_CLASSES);
+    (!device.denied(ids, classes)).then_some("auto")
+}
+
+/// Sets the autosuspend of the USB devices which are `selected`, skipping those denied it.
+fn usb_autosuspend(
+    applied: &mut Applied,
+    profile: Profile,
+    tunables: &ProfileTunables,
+    selected: impl Fn(&str) -> bool,
+) {
+    for device in UsbDevice::all() {
+        let path = device.control_path();
+        let path = path.to_string_lossy();
+        if !Path::new(&*path).exists() || !selected(&path) {
+            continue;
+        }
+
+        match usb_control(&device, profile, tunables) {
+            Some(control) => {
+                applied.write(Some(Written::new(usb_autosuspend::NAME, path, control)))
+            }
+            None => applied.skip(usb_autosuspend::NAME, path),
+        }
+    }
+}
+
+/// Sets the autosuspend of the USB devices connected since the profile was set, and forgets
+/// those disconnected since, as USB devices come and go.
+pub fn usb_devices_changed(applied: &mut Applied, def: &ProfileDef) {
+    let kept = |name: &str, path: &str| name != usb_autosuspend::NAME || Path::new(path).exists();
+    applied.written.retain(|written| kept(written.name, &written.path));
+    applied.not_applicable.retain(|(name, path)| kept(name, path));
+
+    let known: BTreeSet<String> = applied
+        .written
+        .iter()
+        .map(|written| (written.name, &written.path))
+        .chain(applied.not_applicable.iter().map(|(name, path)| (*name, path)))
+        .filter(|&(name, _)| name == usb_autosuspend::NAME)
+        .map(|(_, path)| path.clone())
+        .collect();
+
+    usb_autosuspend(applied, def.base, &def.tunables, |path| !known.contains(path));
+}
+
+/// Whether a profile enables the power save of Wi-Fi, or `None` if it leaves it alone: enabled
+/// on battery, and disabled with the performance profile.
+fn wifi_powersave_enabled(profile: Profile, tunables: &ProfileTunables) -> Option<bool> {
+    match tunables.wifi_powersave.as_deref() {
+        Some("on") => Some(true),
+        Some("off") => Some(false),
+        Some(_) => None,
+        None => match profile {
+            Profile::Battery => Some(true),
+            Profile::Quiet | Profile::Balanced => None,
+            Profile::Performance => Some(false),
+        },
     }
 }
 
-/// Controls the Intel [`PState`] values.
-fn pstate_values(values: PStateValues) -> Result<(), PStateError> {
-    if let Ok(pstate) = PState::new() {
-        pstate.set_values(values)?;
+/// Sets the power save of the Wi-Fi interfaces through `iw`, remembering their previous
+/// values, or restores those values if the profile leaves it alone. Interfaces already set are
+/// left untouched.
+fn wifi_powersave(applied: &mut Applied, profile: Profile, tunables: &ProfileTunables) {
+    if applied.yields("wifi_powersave", wifi::NET_PATH) {
+        return;
+    }
+
+    let interfaces = wifi::interfaces();
+    if interfaces.is_empty() {
+        return applied.skip("wifi_powersave", wifi::NET_PATH);
+    } else if wifi::iw().is_none() {
+        return applied.skip("wifi_powersave", "/usr/sbin/iw");
+    }
+
+    for interface in interfaces {
+        let enabled = match wifi_powersave_enabled(profile, tunables) {
+            Some(enabled) => {
+                wifi::remember(&interface);
+                enabled
+            }
+            None => match wifi::restore(&interface) {
+                Some(enabled) => enabled,
+                None => continue,
+            },
+        };
+
+        if wifi::power_save(&interface) != Some(enabled) {
+            catch!(applied, wifi::set_power_save(&interface, enabled));
+        }
+    }
+}
+
+/// Sets the power save of the Wi-Fi interfaces added since the profile was set, such as those
+/// of USB adapters.
+pub fn wifi_interfaces_changed(applied: &mut Applied, def: &ProfileDef) {
+    if wifi_powersave_enabled(def.base, &def.tunables).is_some() {
+        applied.not_applicable.retain(|&(name, _)| name != "wifi_powersave");
+        wifi_powersave(applied, def.base, &def.tunables);
+    }
+}
+
+/// Caps the power of the discrete GPUs, clamped to the caps they accept, remembering their
+/// previous caps, or restores those caps if the profile leaves it alone. GPUs suspended by
+/// runtime power management are skipped rather than woken.
+fn dgpu_power_cap(applied: &mut Applied, tunables: &ProfileTunables) {
+    let gpus = Gpu::all();
+    if gpus.is_empty() {
+        if tunables.dgpu_power_cap_watts.is_some() {
+            log::info!("{} is not applicable: no GPU exposes a power cap", dgpu_power_cap::NAME);
+            applied.skip(dgpu_power_cap::NAME, dgpu_power_cap::PCI_PATH);
+        }
+        return;
+    }
+
+    for gpu in gpus {
+        let setting = tunables.dgpu_power_cap_watts.is_some();
+        if !setting && dgpu_power_cap::original(&gpu.bus_id).is_none() {
+            continue;
+        } else if gpu.suspended() {
+            log::info!("{} is not applicable: {} is suspended", dgpu_power_cap::NAME, gpu.bus_id);
+            let status = gpu.path.join("power/runtime_status");
+            applied.skip(dgpu_power_cap::NAME, status.to_string_lossy());
+            continue;
+        }
+
+        let Some(limits) = gpu.limits() else {
+            applied.errors.push(DgpuPowerCapError::Limits(gpu.bus_id).into());
+            continue;
+        };
+
+        let watts = match tunables.dgpu_power_cap_watts {
+            Some(watts) => {
+                dgpu_power_cap::remember(&gpu, limits);
+                watts
+            }
+            None => match dgpu_power_cap::restore(&gpu.bus_id) {
+                Some(watts) => watts,
+                None => continue,
+            },
+        };
+
+        let clamped = limits.clamp(watts);
+        if clamped != watts {
+            log::info!(
+                "capping {} to {} W rather than {} W, as it accepts {} to {} W",
+                gpu.bus_id,
+                clamped,
+                watts,
+                limits.min,
+                limits.max
+            );
+        }
+
+        match gpu.cap_path() {
+            Some(path) => {
+                let microwatts = Gpu::cap_microwatts(clamped);
+                applied.write(Some(Written::new(
+                    dgpu_power_cap::NAME,
+                    path.to_string_lossy(),
+                    microwatts,
+                )));
+            }
+            None if limits.current != clamped => {
+                catch!(applied, gpu.set_with_nvidia_smi(clamped));
+            }
+            None => (),
+        }
+    }
+}
+
+/// Controls the Intel [`PState`] values, if they exist.
+fn pstate_values(applied: &mut Applied, values: PStateValues) -> Result<(), PStateError> {
+    match PState::new() {
+        Ok(pstate) => {
+            let written = pstate_written(&values);
+            if written.iter().any(|written| applied.is_yielded(written.name)) {
+                log::info!("intel_pstate is not applicable: it is left to another power manager");
+                applied.skip("intel_pstate", INTEL_PSTATE_PATH);
+                return Ok(());
+            }
+
+            applied.record(written);
+            pstate.set_values(values)?;
+        }
+        Err(_) => applied.skip("intel_pstate", INTEL_PSTATE_PATH),
     }
 
     Ok(())
 }
 
+/// The Intel [`PState`] values, as they are written.
+fn pstate_written(values: &PStateValues) -> Vec<Written> {
+    let flag = |set: bool| if set { "1" } else { "0" };
+    let path = |name: &str| [INTEL_PSTATE_PATH, name].join("/");
+
+    let mut written = Vec::new();
+    if let Some(boost) = values.hwp_dynamic_boost {
+        written.push(Written::new("hwp_dynamic_boost", path("hwp_dynamic_boost"), flag(boost)));
+    }
+
+    written.extend([
+        Written::new("min_perf_pct", path("min_perf_pct"), values.min_perf_pct),
+        Written::new("max_perf_pct", path("max_perf_pct"), values.max_perf_pct),
+        Written::new("no_turbo", path("no_turbo"), flag(values.no_turbo)),
+    ]);
+    written
+}
+
 /// Iterates across all backlights in the supplied iterator, executing the given strategy function
 /// on each discovered backlight source.
 fn iterate_backlights<B: Brightness>(
@@ -184,38 +1171,92 @@ fn iterate_backlights<B: Brightness>(
 }
 
 /// Iterates on all available PCI devices, disabling or enabling runtime power mangement.
-fn pci_device_runtime_pm(pm: RuntimePowerManagement) -> Result<(), PciDeviceError> {
+fn pci_device_runtime_pm(applied: &mut Applied, pm: RuntimePowerManagement) {
     for device in PciDevice::iter() {
         match device {
-            Ok(device) => device
-                .set_runtime_pm(pm)
-                .map_err(|why| PciDeviceError::SetRuntimePm(device.id().to_owned(), why))?,
+            Ok(device) => applied.write(Some(runtime_pm_written(&device, pm))),
             Err(why) => {
                 log::warn!("failed to iterate PCI device: {}", why);
             }
         }
     }
+}
 
-    Ok(())
+/// The runtime power management of a PCI device, as `set_runtime_pm` would write it.
+fn runtime_pm_written(device: &PciDevice, pm: RuntimePowerManagement) -> Written {
+    let control = match pm {
+        RuntimePowerManagement::On => "auto",
+        RuntimePowerManagement::Off => "on",
+    };
+    Written::new("control", device.path().join("power/control").to_string_lossy(), control)
 }
 
-/// Iterates on all available SCSI/SATA hosts, setting the first link time power mangement policy
-/// that succeeeds.
-fn scsi_host_link_time_pm_policy(policies: &'static [&'static str]) -> Result<(), ScsiHostError> {
-    for device in ScsiHost::iter() {
-        match device {
-            Ok(device) => {
-                device.set_link_power_management_policy(policies).map_err(|why| {
-                    ScsiHostError::LinkTimePolicy(policies[0], device.id().to_owned(), why)
-                })?;
-            }
+/// The link power management policies of a profile, in the order they are tried, as kernels
+/// before 4.15 lack `med_power_with_dipm`. The performance and quiet profiles keep the links
+/// awake.
+fn sata_link_policies(profile: Profile, tunables: &ProfileTunables) -> Vec<String> {
+    if let Some(policy) = &tunables.sata_link_policy {
+        return vec![policy.clone()];
+    }
+
+    let policies: &[&str] = match profile {
+        Profile::Battery | Profile::Balanced => &["med_power_with_dipm", "medium_power"],
+        Profile::Quiet | Profile::Performance => &["max_performance"],
+    };
+
+    policies.iter().map(|&policy| policy.to_owned()).collect()
+}
+
+/// The policy files of the SCSI hosts which have one, as SATA hosts do.
+fn sata_link_policy_paths() -> impl Iterator<Item = io::Result<PathBuf>> {
+    ScsiHost::iter().filter_map(|host| match host {
+        Ok(host) => Some(host.path().join(LINK_POLICY)).filter(|path| path.exists()).map(Ok),
+        Err(why) => Some(Err(why)),
+    })
+}
+
+/// Sets the link power management policy of every SATA host, to the first policy it accepts.
+/// Hosts which reject them all, as some eSATA controllers do, are not applicable, and NVMe-only
+/// systems have no host to set.
+fn sata_link_policy(applied: &mut Applied, profile: Profile, tunables: &ProfileTunables) {
+    let policies = sata_link_policies(profile, tunables);
+    let mut hosts = 0;
+    for path in sata_link_policy_paths() {
+        let path = match path {
+            Ok(path) => path.to_string_lossy().into_owned(),
             Err(why) => {
                 log::warn!("failed to iterate SCSI Host device: {}", why);
+                continue;
+            }
+        };
+
+        hosts += 1;
+        let mut rejected = None;
+        let accepted = policies.iter().find_map(|policy| {
+            let written = Written::new(LINK_POLICY, path.as_str(), policy);
+            match written.write() {
+                Ok(()) => Some(written),
+                Err(why) => {
+                    rejected = Some(why);
+                    None
+                }
+            }
+        });
+
+        match accepted {
+            Some(written) => applied.written.push(written),
+            None => {
+                if let Some(why) = rejected {
+                    log::warn!("SATA host rejected the link power management policy: {}", why);
+                }
+                applied.skip(LINK_POLICY, path);
             }
         }
     }
 
-    Ok(())
+    if hosts == 0 {
+        applied.skip(LINK_POLICY, SCSI_HOST_PATH);
+    }
 }
 
 /// Generically sets a backlight value to the backlight, using the provided strategy function.
@@ -238,6 +1279,34 @@ pub struct ModelProfile {
 impl ModelProfile {
     // TODO pub fn get() -> Result<Self, ModelError> {}
 
+    /// The commands `set` runs and the values it writes, without running or writing them.
+    #[must_use]
+    pub fn plan(&self) -> Vec<PlannedAction> {
+        let mut plan = vec![planned_command("systemctl", &["stop", "thermald.service"])];
+
+        for (limit, constraint) in [(self.pl1, 0), (self.pl2, 1)] {
+            if let Some(limit) = limit {
+                let path = format!(
+                    "/sys/class/powercap/intel-rapl:0/constraint_{}_power_limit_uw",
+                    constraint
+                );
+                let name = "power_limit_uw";
+                plan.push(Written::new(name, path, u64::from(limit) * 1_000_000).planned());
+            }
+        }
+
+        if let Some(tcc_offset) = self.tcc_offset {
+            plan.push(PlannedAction {
+                kind: "write".into(),
+                target: "/dev/cpu/0/msr".into(),
+                value: format!("TCC offset {} at 0x1A2", tcc_offset),
+                ..PlannedAction::default()
+            });
+        }
+
+        plan
+    }
+
     pub fn set(&self) -> Result<(), ModelError> {
         // Thermald sets pl1 and pl2 on its own, conflicting with system76-power
         let _status = Command::new("systemctl")
@@ -341,3 +1410,147 @@ impl ModelProfiles {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn parameters_read_back() {
+        let dir = TempDir::new("profile");
+        let governor = dir.join("scaling_governor");
+        let epp = dir.join("energy_performance_preference");
+
+        let mut applied = Applied::default();
+        for (name, path, value) in [
+            ("scaling_governor", &governor, "powersave"),
+            ("energy_performance_preference", &epp, "power"),
+        ] {
+            fs::write(path, value).unwrap();
+            applied.record(Some(Written::new(name, path.to_string_lossy(), value)));
+        }
+
+        // Another tool changed the governor, and the preference can no longer be read.
+        fs::write(&governor, "performance\n").unwrap();
+        fs::remove_file(&epp).unwrap();
+
+        let parameters = applied.parameters();
+        assert_eq!(parameters[0].intended, "powersave");
+        assert_eq!(parameters[0].current, "performance");
+        assert!(!parameters[0].matches);
+        assert_eq!(parameters[1].current, "");
+        assert!(!parameters[1].matches);
+
+        fs::write(&governor, "powersave\n").unwrap();
+        assert!(applied.parameters()[0].matches);
+
+        // Parameters the hardware lacks follow, without values.
+        applied.skip("intel_pstate", INTEL_PSTATE_PATH);
+        let parameters = applied.parameters();
+        assert_eq!(parameters.len(), 3);
+        assert_eq!(
+            (parameters[2].name.as_str(), parameters[2].path.as_str()),
+            ("intel_pstate", INTEL_PSTATE_PATH)
+        );
+        assert!(parameters[2].intended.is_empty() && parameters[2].matches);
+
+        applied.clear();
+        assert!(applied.parameters().is_empty());
+
+        // Files listing the choices hold the selected one in brackets.
+        let policy = dir.join("policy");
+        fs::write(&policy, "default performance [powersave] powersupersave\n").unwrap();
+        let written = Written::new("pcie_aspm_policy", policy.to_string_lossy(), "powersave");
+        assert_eq!(parameter(&written).current, "powersave");
+        assert!(parameter(&written).matches);
+    }
+
+    #[test]
+    fn partial_failures() {
+        let dir = TempDir::new("failures");
+
+        // A directory cannot be written even by root, unlike a file without write permission.
+        let read_only = dir.join("energy_performance_preference");
+        fs::create_dir_all(&read_only).unwrap();
+
+        let written = |name, file: &Path, value| Written::new(name, file.to_string_lossy(), value);
+        let mut applied = Applied::default();
+        applied.write([
+            written("scaling_governor", &dir.join("scaling_governor"), "powersave"),
+            written("energy_performance_preference", &read_only, "power"),
+            written("scaling_max_freq", &dir.join("scaling_max_freq"), "2400000"),
+        ]);
+
+        // Every parameter was attempted, and the one after the failure was still written.
+        assert_eq!(applied.written.len(), 3);
+        assert_eq!(fs::read_to_string(dir.join("scaling_max_freq")).unwrap(), "2400000");
+
+        let failures = applied.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].name, "energy_performance_preference");
+        assert_eq!(failures[0].path, read_only.to_string_lossy());
+        assert_eq!(failures[0].value, "power");
+        assert_eq!(failures[0].errno, 21); // EISDIR
+        assert!(applied.mostly_applied());
+        assert_eq!(applied.outcome("Battery").unwrap(), failures);
+        assert!(!applied.parameters()[1].matches);
+
+        // Once most parameters failed, the profile fails, naming each file.
+        applied.write([written("scaling_min_freq", &read_only, "400000")]);
+        applied.write([written("no_turbo", &dir.join("missing/no_turbo"), "1")]);
+        assert!(!applied.mostly_applied());
+        let why = applied.outcome("Battery").unwrap_err();
+        assert_eq!(why.0.len(), 3);
+        let why = why.to_string();
+        assert!(why.contains(&read_only.to_string_lossy().into_owned()), "{}", why);
+        assert!(why.contains("missing/no_turbo"), "{}", why);
+    }
+
+    #[test]
+    fn capped_frequency() {
+        let def = |base, max_frequency_percent| ProfileDef {
+            name: String::new(),
+            description: String::new(),
+            base,
+            tunables: ProfileTunables { max_frequency_percent, ..ProfileTunables::default() },
+        };
+
+        // The stricter limit wins, whether the cap or that of the profile.
+        let capped = |def: ProfileDef| super::capped(&def, 60).tunables.max_frequency_percent;
+        assert_eq!(capped(def(Profile::Performance, None)), Some(60));
+        assert_eq!(capped(def(Profile::Battery, None)), Some(50));
+        assert_eq!(capped(def(Profile::Balanced, Some(40))), Some(40));
+        assert_eq!(capped(def(Profile::Balanced, Some(80))), Some(60));
+
+        let mut hybrid = def(Profile::Balanced, None);
+        hybrid.tunables.pcore.max_frequency_percent = Some(90);
+        hybrid.tunables.ecore.max_frequency_percent = Some(30);
+        let capped = super::capped(&hybrid, 60).tunables;
+        assert_eq!(capped.pcore.max_frequency_percent, Some(60));
+        assert_eq!(capped.ecore.max_frequency_percent, Some(30));
+    }
+
+    #[test]
+    fn yielded() {
+        let dir = TempDir::new("yielded");
+
+        let written =
+            |name, file: &str| Written::new(name, dir.join(file).to_string_lossy(), "powersave");
+        let mut applied = Applied { yielded: ["scaling_governor"].into(), ..Applied::default() };
+        applied.write([
+            written("pcore.scaling_governor", "policy0"),
+            written("pcie_aspm_policy", "policy"),
+        ]);
+
+        // The parameter left to another power manager is neither written nor failed.
+        assert!(!dir.join("policy0").exists());
+        assert_eq!(applied.not_applicable[0].0, "pcore.scaling_governor");
+        assert_eq!(applied.written.len(), 1);
+        assert!(applied.errors.is_empty());
+
+        applied.clear();
+        assert!(applied.yields("scaling_governor", "policy0"));
+        assert!(!applied.yields("pcie_aspm_policy", "policy"));
+    }
+}
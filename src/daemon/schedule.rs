@@ -0,0 +1,341 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Switching the profile by time of day, as mapped in the `[schedule]` section of
+//! `daemon.toml`. The mapped profile is applied at each boundary of a time range, so that a
+//! profile set by anything else is kept until the next boundary.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use system76_power_zbus::{ScheduleStatus, ScheduledRange};
+use tokio::sync::Notify;
+
+use crate::tunables;
+
+use super::{auto_profile, System76Power};
+
+// Initiator of profile changes made by the schedule.
+pub(super) const INITIATOR_SCHEDULE: &str = "schedule";
+
+// Longest wait between checks of the time, as timers stop during suspend, and the local time
+// shifts with daylight saving.
+const RECHECK: Duration = Duration::from_secs(60);
+
+const DAY: u32 = 24 * 60;
+const WEEK: u32 = 7 * DAY;
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+// Wakes the watch when the settings change.
+static WAKE: Notify = Notify::const_new();
+
+/// The `[schedule]` section of `daemon.toml`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct ScheduleConfig {
+    /// Time ranges mapped to profiles, of which the last one listed applies where they
+    /// overlap.
+    pub ranges: Vec<RangeConfig>,
+}
+
+/// A time range of the schedule, in local time.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct RangeConfig {
+    /// Start of the range, as `HH:MM`.
+    pub from:    String,
+    /// End of the range, as `HH:MM`, which is on the next day if it is not after the start.
+    pub to:      String,
+    /// Days the range starts on, such as `mon`, or every day if empty.
+    #[serde(default)]
+    pub days:    Vec<String>,
+    /// Profile applied during the range: battery, quiet, balanced, performance or a custom
+    /// profile.
+    pub profile: String,
+}
+
+/// A range of the schedule, in minutes of the day.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Range {
+    start:   u32,
+    end:     u32,
+    /// Days the range starts on, from Monday in the lowest bit.
+    days:    u8,
+    /// Name of the profile, as known to the daemon.
+    profile: String,
+}
+
+impl RangeConfig {
+    fn parse(&self) -> Result<Range, String> {
+        let mut days = 0;
+        for day in &self.days {
+            let index =
+                DAYS.iter().position(|name| day.eq_ignore_ascii_case(name)).ok_or_else(|| {
+                    format!("unknown day '{}', expected one of {}", day, DAYS.join(", "))
+                })?;
+            days |= 1 << index;
+        }
+
+        Ok(Range {
+            start:   parse_time(&self.from)?,
+            end:     parse_time(&self.to)?,
+            days:    if days == 0 { 0x7f } else { days },
+            profile: auto_profile::profile_name(&self.profile)?,
+        })
+    }
+}
+
+impl ScheduleConfig {
+    /// Checks every range, returning the key of the first invalid one with the error.
+    pub fn validate(&self) -> Result<(), (String, String)> {
+        for (index, range) in self.ranges.iter().enumerate() {
+            range.parse().map_err(|why| (format!("schedule.ranges[{}]", index), why))?;
+        }
+
+        Ok(())
+    }
+
+    fn ranges(&self) -> Vec<Range> {
+        self.ranges.iter().filter_map(|range| range.parse().ok()).collect()
+    }
+
+    /// The ranges, as returned by `GetProfileSchedule`, with the profile of the current range
+    /// and the next transition.
+    pub fn status(&self, next: Option<&Transition>) -> ScheduleStatus {
+        let ranges = self.ranges.iter().map(|range| ScheduledRange {
+            from:    range.from.clone(),
+            to:      range.to.clone(),
+            days:    range.days.clone(),
+            profile: range.profile.clone(),
+        });
+
+        let current = local_now().and_then(|(minute, _)| {
+            let ranges = self.ranges();
+            profile_at(&ranges, minute).map(|range| range.profile.clone())
+        });
+
+        ScheduleStatus {
+            ranges:       ranges.collect(),
+            current:      current.unwrap_or_default(),
+            next_profile: next.map(|next| next.profile.clone()).unwrap_or_default(),
+            next_time:    next.map_or(0, |next| {
+                next.at.duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+            }),
+        }
+    }
+}
+
+/// The next boundary of a range, and the profile applied then.
+pub(super) struct Transition {
+    pub at:      SystemTime,
+    pub profile: String,
+}
+
+/// Progress of the schedule.
+#[derive(Default)]
+pub(super) struct Schedule {
+    /// Whether the schedule has ranges, and the profile of the current one was applied.
+    armed:    bool,
+    pub next: Option<Transition>,
+}
+
+/// Parses a time of day, such as `09:30`, into minutes of the day.
+fn parse_time(time: &str) -> Result<u32, String> {
+    let parsed = time.split_once(':').and_then(|(hours, minutes)| {
+        let hours = hours.parse::<u32>().ok().filter(|hours| *hours < 24)?;
+        let minutes = minutes.parse::<u32>().ok().filter(|_| minutes.len() == 2)?;
+        (minutes < 60).then_some(hours * 60 + minutes)
+    });
+    parsed.ok_or_else(|| format!("invalid time '{}', expected HH:MM", time))
+}
+
+/// The start of a range on each day it starts on, in minutes of the week, with its length.
+fn occurrences(range: &Range) -> impl Iterator<Item = (u32, u32)> + '_ {
+    let length = match (range.end + DAY - range.start) % DAY {
+        0 => DAY,
+        length => length,
+    };
+    (0..7)
+        .filter(move |day| range.days & (1 << day) != 0)
+        .map(move |day| (day * DAY + range.start, length))
+}
+
+/// The range applying at a minute of the week, the last listed of those overlapping.
+fn profile_at(ranges: &[Range], minute: u32) -> Option<&Range> {
+    ranges.iter().rev().find(|range| {
+        occurrences(range).any(|(start, length)| (minute + WEEK - start) % WEEK < length)
+    })
+}
+
+/// The minutes until the next boundary of a range after a minute of the week, with the range
+/// applying from then. Boundaries leaving no range applying are not transitions.
+fn next_transition(ranges: &[Range], minute: u32) -> Option<(u32, &Range)> {
+    let mut boundaries: Vec<u32> = ranges
+        .iter()
+        .flat_map(occurrences)
+        .flat_map(|(start, length)| [start, (start + length) % WEEK])
+        .map(|boundary| (boundary + WEEK - minute - 1) % WEEK + 1)
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    boundaries
+        .into_iter()
+        .find_map(|after| profile_at(ranges, (minute + after) % WEEK).map(|range| (after, range)))
+}
+
+/// The current minute of the week in local time, from Monday, with the seconds elapsed in it.
+fn local_now() -> Option<(u32, u32)> {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        return None;
+    }
+
+    let day = u32::try_from((tm.tm_wday + 6) % 7).ok()?;
+    let minute = u32::try_from(tm.tm_hour * 60 + tm.tm_min).ok()?;
+    Some((day * DAY + minute, u32::try_from(tm.tm_sec).ok()?.min(59)))
+}
+
+impl System76Power {
+    /// Applies the profile of the schedule at a boundary, or when the schedule is first
+    /// armed, and plans the next transition, returning how long to wait before the next check,
+    /// or `None` without ranges.
+    async fn refresh_schedule(&self, context: &zbus::SignalContext<'_>) -> Option<Duration> {
+        let (profile, wait) = {
+            let mut this = self.0.lock().await;
+            let this = &mut *this;
+
+            let ranges = this.config.schedule.ranges();
+            let Some((minute, seconds)) = local_now().filter(|_| !ranges.is_empty()) else {
+                this.schedule = Schedule::default();
+                return None;
+            };
+
+            let now = SystemTime::now();
+            let due = this.schedule.next.as_ref().map_or(false, |next| next.at <= now);
+            let apply = due || !this.schedule.armed;
+            this.schedule.armed = true;
+
+            this.schedule.next = next_transition(&ranges, minute).map(|(after, range)| {
+                let at = now + Duration::from_secs(u64::from(after * 60 - seconds));
+                Transition { at, profile: range.profile.clone() }
+            });
+
+            let wait = this.schedule.next.as_ref().map_or(RECHECK, |next| {
+                next.at.duration_since(now).unwrap_or_default().min(RECHECK)
+            });
+
+            let profile = profile_at(&ranges, minute)
+                .map(|range| range.profile.clone())
+                .filter(|profile| apply && *profile != this.power_profile);
+            let Some(profile) = profile else { return Some(wait) };
+
+            log::info!("Scheduled to switch to {} profile", profile);
+            if this.low_battery.defer(&profile) {
+                return Some(wait);
+            }
+
+            if this.holds.select(&profile) {
+                log::info!("Profiles are held, switching once they are released");
+                return Some(wait);
+            }
+
+            (profile, wait)
+        };
+
+        if let Some(profile) = tunables::find(&profile) {
+            if let Err(why) = self.set_profile(context, &profile, INITIATOR_SCHEDULE).await {
+                log::warn!("Failed to switch profile on schedule: {}", why);
+            }
+        }
+
+        Some(wait)
+    }
+}
+
+/// Wakes the watch, to apply changed settings.
+pub(super) fn settings_changed() { WAKE.notify_one(); }
+
+/// Switches profile at the boundaries of the ranges of the schedule.
+pub(super) async fn watch(daemon: System76Power, context: zbus::SignalContext<'static>) {
+    loop {
+        let wait = daemon.refresh_schedule(&context).await;
+
+        tokio::select! {
+            () = WAKE.notified() => (),
+            () = tokio::time::sleep(wait.unwrap_or(Duration::MAX)), if wait.is_some() => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_range(from: &str, to: &str, days: &[&str], profile: &str) -> Range {
+        let config = RangeConfig {
+            from:    from.into(),
+            to:      to.into(),
+            days:    days.iter().map(|&day| day.to_owned()).collect(),
+            profile: profile.into(),
+        };
+        config.parse().unwrap()
+    }
+
+    fn at(day: u32, time: &str) -> u32 { day * DAY + parse_time(time).unwrap() }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("09:30"), Ok(570));
+        assert_eq!(parse_time("0:00"), Ok(0));
+        assert!(parse_time("24:00").is_err());
+        assert!(parse_time("9:5").is_err());
+        assert!(parse_time("nine").is_err());
+
+        let config = RangeConfig {
+            from:    "09:00".into(),
+            to:      "18:00".into(),
+            days:    vec!["Sat".into(), "weekday".into()],
+            profile: "performance".into(),
+        };
+        let why = config.parse().unwrap_err();
+        assert!(why.starts_with("unknown day 'weekday'"), "{}", why);
+    }
+
+    #[test]
+    fn transitions() {
+        let ranges = [
+            parse_range("09:00", "18:00", &["mon", "tue", "wed", "thu", "fri"], "performance"),
+            parse_range("22:00", "07:00", &[], "battery"),
+        ];
+
+        // Work hours, and nights crossing midnight.
+        assert_eq!(profile_at(&ranges, at(0, "10:00")).unwrap().profile, "Performance");
+        assert_eq!(profile_at(&ranges, at(0, "23:00")).unwrap().profile, "Battery");
+        assert_eq!(profile_at(&ranges, at(1, "06:59")).unwrap().profile, "Battery");
+        assert_eq!(profile_at(&ranges, at(5, "10:00")), None);
+        assert_eq!(profile_at(&ranges, at(0, "20:00")), None);
+
+        // The end of work hours leaves no range applying, so the night is next.
+        let (after, range) = next_transition(&ranges, at(0, "10:00")).unwrap();
+        assert_eq!((after, range.profile.as_str()), (12 * 60, "Battery"));
+
+        // From Friday night, the weekend has no work hours.
+        let (after, range) = next_transition(&ranges, at(4, "22:00")).unwrap();
+        assert_eq!((after, range.profile.as_str()), (24 * 60, "Battery"));
+        let (after, range) = next_transition(&ranges, at(6, "22:00")).unwrap();
+        assert_eq!((after, range.profile.as_str()), (11 * 60, "Performance"));
+
+        // A later range overrides an earlier one, which applies again once it ends.
+        let ranges = [
+            parse_range("09:00", "18:00", &[], "performance"),
+            parse_range("12:00", "13:00", &[], "balanced"),
+        ];
+        assert_eq!(profile_at(&ranges, at(2, "12:30")).unwrap().profile, "Balanced");
+        let (after, range) = next_transition(&ranges, at(2, "12:30")).unwrap();
+        assert_eq!((after, range.profile.as_str()), (30, "Performance"));
+
+        assert_eq!(next_transition(&[], at(0, "00:00")), None);
+    }
+}
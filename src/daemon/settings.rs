@@ -0,0 +1,272 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Settings of the daemon, read from `/etc/system76-power/daemon.toml` at startup and on
+//! SIGHUP. `data/daemon.toml` documents every key.
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    audit::RateLimitConfig, auto_profile::AutoProfileConfig, battery_cap::BatteryCapConfig,
+    brightness::BrightnessConfig, calibration::CalibrationConfig, hooks::HooksConfig,
+    idle::IdleConfig, low_battery::LowBatteryConfig, schedule::ScheduleConfig,
+};
+use crate::config::{self, ConfigError};
+
+const DAEMON_CONFIG: &str = "daemon.toml";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct DaemonConfig {
+    pub startup:           StartupConfig,
+    pub sleep:             SleepConfig,
+    pub graphics:          GraphicsSettings,
+    pub auto_profile:      AutoProfileConfig,
+    pub idle:              IdleConfig,
+    pub rate_limit:        RateLimitConfig,
+    pub brightness:        BrightnessConfig,
+    pub schedule:          ScheduleConfig,
+    pub low_battery:       LowBatteryConfig,
+    pub hooks:             HooksConfig,
+    pub conflicts:         ConflictsConfig,
+    #[serde(rename = "battery_frequency_cap")]
+    pub battery_cap:       BatteryCapConfig,
+    pub charge_thresholds: ChargeThresholdsConfig,
+    pub calibration:       CalibrationConfig,
+}
+
+/// The `[startup]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct StartupConfig {
+    /// Switch to the mode recommended for the model on the first run, if no mode was ever
+    /// configured.
+    pub apply_default_graphics: bool,
+    /// Let the discrete GPU power off when unused.
+    pub auto_power:             bool,
+    /// Warn if the initramfs predates the modprobe config of the configured mode.
+    pub verify_initramfs:       bool,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            apply_default_graphics: true,
+            auto_power:             true,
+            verify_initramfs:       true,
+        }
+    }
+}
+
+/// The `[sleep]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct SleepConfig {
+    /// Re-apply the graphics power, the profile and the charge thresholds on resume.
+    pub reapply_on_resume: bool,
+}
+
+impl Default for SleepConfig {
+    fn default() -> Self { Self { reapply_on_resume: true } }
+}
+
+/// The `[conflicts]` section of `daemon.toml`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct ConflictsConfig {
+    /// Leave the parameters other power managers running also set to them, instead of setting
+    /// them too.
+    pub yield_parameters: bool,
+}
+
+/// The `[charge_thresholds]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct ChargeThresholdsConfig {
+    /// Re-apply the thresholds last requested when the daemon starts, after resume, and when
+    /// a battery is added, if the battery holds others.
+    pub reapply: bool,
+}
+
+impl Default for ChargeThresholdsConfig {
+    fn default() -> Self { Self { reapply: true } }
+}
+
+/// The `[graphics]` section of `daemon.toml`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct GraphicsSettings {
+    /// Undo edits of `/etc/prime-discrete` and the modprobe config made by other tools,
+    /// instead of accepting them.
+    pub restore_external_edits:       bool,
+    /// Wait for a running package transaction to finish before changing the graphics
+    /// configuration, instead of refusing the change.
+    pub wait_for_package_manager:     bool,
+    /// How long to wait for a package transaction to finish.
+    pub package_manager_timeout_secs: u64,
+    /// Refuse to switch to a mode using the NVIDIA driver when its versions mismatch.
+    pub strict_driver_check:          bool,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            restore_external_edits:       false,
+            wait_for_package_manager:     false,
+            package_manager_timeout_secs: 600,
+            strict_driver_check:          false,
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Loads the config, falling back to the defaults if it does not exist.
+    pub fn load() -> Result<Self, ConfigError> {
+        let config: Self = config::load(DAEMON_CONFIG)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> { config::save(DAEMON_CONFIG, self) }
+
+    /// Checks the values which are not constrained by their type.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let auto_profile = &self.auto_profile;
+        for (key, on_ac) in [("auto_profile.ac", true), ("auto_profile.battery", false)] {
+            auto_profile.profile(on_ac).map_err(|why| ConfigError::Invalid {
+                path: config::path(DAEMON_CONFIG),
+                key: key.to_owned(),
+                why,
+            })?;
+        }
+
+        let invalid = |key: &str, why: String| ConfigError::Invalid {
+            path: config::path(DAEMON_CONFIG),
+            key: key.to_owned(),
+            why,
+        };
+
+        self.idle.profile().map_err(|why| invalid("idle.profile", why))?;
+        self.schedule.validate().map_err(|(key, why)| invalid(&key, why))?;
+        self.low_battery.validate().map_err(|(key, why)| invalid(key, why))?;
+        self.battery_cap.validate().map_err(|(key, why)| invalid(key, why))?;
+        self.calibration.validate().map_err(|(key, why)| invalid(key, why))?;
+        if self.idle.after_secs == 0 {
+            return Err(invalid("idle.after_secs", "must be at least 1".into()));
+        }
+
+        if self.rate_limit.burst == 0 {
+            return Err(invalid("rate_limit.burst", "must be at least 1".into()));
+        }
+
+        if self.rate_limit.per_minute == 0 {
+            return Err(invalid("rate_limit.per_minute", "must be at least 1".into()));
+        }
+
+        if self.hooks.timeout_secs == 0 {
+            return Err(invalid("hooks.timeout_secs", "must be at least 1".into()));
+        }
+
+        if self.graphics.package_manager_timeout_secs == 0 {
+            return Err(invalid(
+                "graphics.package_manager_timeout_secs",
+                "must be at least 1".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/data/daemon.toml"));
+
+    fn parse(toml: &str) -> Result<DaemonConfig, String> {
+        let config: DaemonConfig = toml::from_str(toml).map_err(|why| why.to_string())?;
+        config.validate().map_err(|why| why.to_string())?;
+        Ok(config)
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(parse("").unwrap(), DaemonConfig::default());
+        assert_eq!(parse(EXAMPLE).unwrap(), DaemonConfig::default());
+    }
+
+    #[test]
+    fn partial() {
+        let config = parse("[startup]\nauto_power = false\n").unwrap();
+        assert!(!config.startup.auto_power);
+        assert!(config.startup.verify_initramfs);
+        assert!(config.sleep.reapply_on_resume);
+        assert_eq!(config.graphics, GraphicsSettings::default());
+        assert_eq!(config.auto_profile, AutoProfileConfig::default());
+        assert_eq!(config.idle, IdleConfig::default());
+        assert_eq!(config.rate_limit, RateLimitConfig::default());
+        assert_eq!(config.brightness, BrightnessConfig::default());
+        assert_eq!(config.schedule, ScheduleConfig::default());
+        assert_eq!(config.low_battery, LowBatteryConfig::default());
+        assert_eq!(config.hooks, HooksConfig::default());
+        assert_eq!(config.conflicts, ConflictsConfig::default());
+        assert_eq!(config.battery_cap, BatteryCapConfig::default());
+        assert!(config.charge_thresholds.reapply);
+        assert_eq!(config.calibration, CalibrationConfig::default());
+
+        let config = parse("[graphics]\nstrict_driver_check = true\n").unwrap();
+        assert!(config.graphics.strict_driver_check);
+        assert!(!config.graphics.restore_external_edits);
+
+        let config = parse("[charge_thresholds]\nreapply = false\n").unwrap();
+        assert!(!config.charge_thresholds.reapply);
+
+        let config = parse("[calibration]\nlow_percent = 10\n").unwrap();
+        assert_eq!(config.calibration.low_percent, 10);
+        assert!(parse("[calibration]\nlow_percent = 0\n").is_err());
+
+        let config = parse("[battery_frequency_cap]\nenabled = true\n").unwrap();
+        assert!(config.battery_cap.enabled);
+        assert_eq!(config.battery_cap.percent, 60);
+
+        let config = parse("[hooks]\nenabled = false\n").unwrap();
+        assert!(!config.hooks.enabled);
+        assert_eq!(config.hooks.timeout_secs, 30);
+
+        let config = parse("[brightness]\nmanaged_by = [\"GNOME\"]\n").unwrap();
+        assert_eq!(config.brightness.managed_by, ["GNOME"]);
+    }
+
+    #[test]
+    fn invalid() {
+        let why = parse("[startup]\nauto_powr = false\n").unwrap_err();
+        assert!(why.contains("unknown field `auto_powr`"), "{}", why);
+        assert!(why.contains("line 2"), "{}", why);
+
+        let why = parse("[startup]\nauto_power = \"yes\"\n").unwrap_err();
+        assert!(why.contains("expected a boolean"), "{}", why);
+
+        assert_eq!(
+            parse("[auto_profile]\nbattery = \"turbo\"\n").unwrap_err(),
+            "invalid auto_profile.battery in /etc/system76-power/daemon.toml: unknown profile \
+             'turbo', expected battery, quiet, balanced, performance or a custom profile of \
+             profiles.toml"
+        );
+
+        assert_eq!(
+            parse("[idle]\nafter_secs = 0\n").unwrap_err(),
+            "invalid idle.after_secs in /etc/system76-power/daemon.toml: must be at least 1"
+        );
+
+        let schedule = "[schedule]\nranges = [\n  { from = \"22:00\", to = \"7:00\", profile = \
+                        \"battery\" },\n  { from = \"9:00\", to = \"18\", profile = \
+                        \"performance\" },\n]\n";
+        assert_eq!(
+            parse(schedule).unwrap_err(),
+            "invalid schedule.ranges[1] in /etc/system76-power/daemon.toml: invalid time '18', \
+             expected HH:MM"
+        );
+    }
+}
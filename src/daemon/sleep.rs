@@ -0,0 +1,95 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Re-applying settings after suspend, which firmware may reset. A delay inhibitor is held
+//! while awake, so that logind announces sleep before it happens, and released as soon as it
+//! is announced.
+
+use futures_lite::StreamExt;
+
+use super::System76Power;
+
+/// A session's id, user id, user name, seat and object path.
+pub(super) type Session = (String, u32, String, String, zvariant::OwnedObjectPath);
+
+#[zbus::dbus_proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+pub(super) trait LoginManager {
+    fn inhibit(
+        &self,
+        what: &str,
+        who: &str,
+        why: &str,
+        mode: &str,
+    ) -> zbus::Result<zvariant::OwnedFd>;
+
+    fn list_sessions(&self) -> zbus::Result<Vec<Session>>;
+
+    #[dbus_proxy(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+
+    /// Whether every session is idle.
+    #[dbus_proxy(property)]
+    fn idle_hint(&self) -> zbus::Result<bool>;
+
+    /// Time the idle hint last changed, in microseconds since the Unix epoch.
+    #[dbus_proxy(property)]
+    fn idle_since_hint(&self) -> zbus::Result<u64>;
+}
+
+async fn inhibit(manager: &LoginManagerProxy<'_>) -> Option<zvariant::OwnedFd> {
+    let reason = "Re-applies graphics power, the power profile and charge thresholds on resume";
+    match manager.inhibit("sleep", "system76-power", reason, "delay").await {
+        Ok(fd) => Some(fd),
+        Err(why) => {
+            log::warn!("Failed to take a sleep inhibitor: {}", why);
+            None
+        }
+    }
+}
+
+/// Re-applies the settings of the daemon whenever the system resumes.
+pub(super) async fn watch(
+    daemon: System76Power,
+    connection: zbus::Connection,
+    context: zbus::SignalContext<'static>,
+) {
+    let manager = match LoginManagerProxy::new(&connection).await {
+        Ok(manager) => manager,
+        Err(why) => {
+            log::warn!("Not re-applying settings on resume, logind is unavailable: {}", why);
+            return;
+        }
+    };
+
+    let mut signals = match manager.receive_prepare_for_sleep().await {
+        Ok(signals) => signals,
+        Err(why) => {
+            log::warn!("Not re-applying settings on resume: {}", why);
+            return;
+        }
+    };
+
+    let mut inhibitor = inhibit(&manager).await;
+
+    while let Some(signal) = signals.next().await {
+        let Ok(args) = signal.args() else { continue };
+
+        if args.start {
+            log::info!("Preparing for sleep");
+            drop(inhibitor.take());
+            continue;
+        }
+
+        log::info!("Resumed from sleep");
+        inhibitor = inhibit(&manager).await;
+
+        if daemon.0.lock().await.config.sleep.reapply_on_resume {
+            daemon.reapply_after_resume(&context).await;
+        }
+    }
+}
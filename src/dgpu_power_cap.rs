@@ -0,0 +1,265 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The power cap of discrete GPUs, which profiles may raise or lower: through the `power1_cap`
+//! file of their hwmon, which amdgpu exposes on discrete GPUs only, or `nvidia-smi` for the
+//! NVIDIA GPUs lacking it. The caps they had before a profile first changed them are kept,
+//! for the profiles which leave them alone to restore.
+
+use crate::{errors::DgpuPowerCapError, util::find_command};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+pub const PCI_PATH: &str = "/sys/bus/pci/devices";
+
+/// Name of the parameter, which is also its key in `profiles.toml`.
+pub const NAME: &str = "dgpu_power_cap_watts";
+
+const MICROWATTS: u64 = 1_000_000;
+
+/// Caps of the GPUs before a profile changed them, in watts, by bus ID.
+static ORIGINALS: Mutex<BTreeMap<String, u32>> = Mutex::new(BTreeMap::new());
+
+/// How the power cap of a GPU is set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Interface {
+    /// The directory of the hwmon exposing `power1_cap`, in microwatts.
+    Hwmon(PathBuf),
+    /// `nvidia-smi -pl`, in watts, with the proprietary driver.
+    NvidiaSmi,
+}
+
+/// The cap of a GPU, with the lowest and highest it accepts, in watts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PowerLimits {
+    pub current: u32,
+    pub min:     u32,
+    pub max:     u32,
+}
+
+impl PowerLimits {
+    /// The cap nearest to `watts` which the GPU accepts.
+    #[must_use]
+    pub fn clamp(self, watts: u32) -> u32 { watts.clamp(self.min, self.max.max(self.min)) }
+}
+
+/// A GPU whose power cap can be set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gpu {
+    /// PCI address, such as `0000:01:00.0`.
+    pub bus_id:    String,
+    pub path:      PathBuf,
+    pub interface: Interface,
+}
+
+impl Gpu {
+    /// The AMD and NVIDIA GPUs exposing a power cap, by bus ID.
+    #[must_use]
+    pub fn all() -> Vec<Self> { Self::all_in(Path::new(PCI_PATH), nvidia_smi().is_some()) }
+
+    fn all_in(root: &Path, nvidia_smi: bool) -> Vec<Self> {
+        let Ok(entries) = fs::read_dir(root) else { return Vec::new() };
+        let mut gpus: Vec<Self> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| Self::read(&entry.path(), nvidia_smi))
+            .collect();
+        gpus.sort_by(|a, b| a.bus_id.cmp(&b.bus_id));
+        gpus
+    }
+
+    /// Reads a PCI device, or returns `None` for those which are not a GPU with a power cap.
+    fn read(path: &Path, nvidia_smi: bool) -> Option<Self> {
+        let read = |name: &str| fs::read_to_string(path.join(name)).ok();
+        if !read("class")?.starts_with("0x03") {
+            return None;
+        }
+
+        let hwmons = fs::read_dir(path.join("hwmon")).into_iter().flatten().filter_map(Result::ok);
+        let hwmon =
+            hwmons.map(|entry| entry.path()).find(|hwmon| hwmon.join("power1_cap").exists());
+        let interface = match (read("vendor")?.trim(), hwmon) {
+            ("0x1002" | "0x10de", Some(hwmon)) => Interface::Hwmon(hwmon),
+            ("0x10de", None) if nvidia_smi && driver(path).as_deref() == Some("nvidia") => {
+                Interface::NvidiaSmi
+            }
+            _ => return None,
+        };
+
+        let bus_id = path.file_name()?.to_string_lossy().into_owned();
+        Some(Self { bus_id, path: path.to_owned(), interface })
+    }
+
+    /// Whether runtime power management has suspended the GPU, which reading its cap would
+    /// wake.
+    #[must_use]
+    pub fn suspended(&self) -> bool {
+        fs::read_to_string(self.path.join("power/runtime_status"))
+            .map_or(false, |status| status.trim() == "suspended")
+    }
+
+    /// The cap of the GPU, with its bounds, or `None` if they cannot be read.
+    #[must_use]
+    pub fn limits(&self) -> Option<PowerLimits> {
+        match &self.interface {
+            Interface::Hwmon(hwmon) => {
+                let watts = |name: &str| {
+                    let microwatts = fs::read_to_string(hwmon.join(name)).ok()?;
+                    let watts = microwatts.trim().parse::<u64>().ok()? / MICROWATTS;
+                    u32::try_from(watts).ok()
+                };
+                Some(PowerLimits {
+                    current: watts("power1_cap")?,
+                    min:     watts("power1_cap_min").unwrap_or(0),
+                    max:     watts("power1_cap_max").unwrap_or(u32::MAX),
+                })
+            }
+            Interface::NvidiaSmi => {
+                let output = Command::new(nvidia_smi()?)
+                    .args(["-i", &self.bus_id])
+                    .args(["--query-gpu=power.limit,power.min_limit,power.max_limit"])
+                    .args(["--format=csv,noheader,nounits"])
+                    .output()
+                    .ok()?;
+                parse_limits(&String::from_utf8_lossy(&output.stdout))
+            }
+        }
+    }
+
+    /// The file of the hwmon setting the cap, if the GPU has one.
+    #[must_use]
+    pub fn cap_path(&self) -> Option<PathBuf> {
+        match &self.interface {
+            Interface::Hwmon(hwmon) => Some(hwmon.join("power1_cap")),
+            Interface::NvidiaSmi => None,
+        }
+    }
+
+    /// The value written to `power1_cap` to cap the GPU to `watts`.
+    #[must_use]
+    pub fn cap_microwatts(watts: u32) -> u64 { u64::from(watts) * MICROWATTS }
+
+    /// Sets the cap of a GPU without a hwmon exposing it through `nvidia-smi`.
+    pub fn set_with_nvidia_smi(&self, watts: u32) -> Result<(), DgpuPowerCapError> {
+        let nvidia_smi = nvidia_smi().ok_or(DgpuPowerCapError::NoNvidiaSmi)?;
+        let output = Command::new(nvidia_smi)
+            .args(["-i", &self.bus_id, "-pl", &watts.to_string()])
+            .output()
+            .map_err(|why| DgpuPowerCapError::Spawn(self.bus_id.clone(), why))?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            let why = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+            Err(DgpuPowerCapError::PowerLimit(self.bus_id.clone(), watts, why))
+        }
+    }
+}
+
+/// The cap of the first discrete GPU which is awake, in watts.
+#[must_use]
+pub fn active_cap() -> Option<u32> {
+    let gpus = Gpu::all().into_iter().filter(|gpu| !gpu.suspended());
+    gpus.filter_map(|gpu| gpu.limits()).map(|limits| limits.current).next()
+}
+
+/// The `nvidia-smi` command, installed with the proprietary driver.
+#[must_use]
+pub fn nvidia_smi() -> Option<PathBuf> { find_command("nvidia-smi") }
+
+/// Parses the output of `nvidia-smi --query-gpu=power.limit,power.min_limit,power.max_limit`
+/// without units, such as `80.00, 5.00, 115.00`.
+fn parse_limits(output: &str) -> Option<PowerLimits> {
+    let mut watts = output.lines().next()?.split(',').map(|value| {
+        let watts = value.trim().parse::<f64>().ok().filter(|watts| *watts >= 0.0)?;
+        Some(watts.round() as u32)
+    });
+    Some(PowerLimits { current: watts.next()??, min: watts.next()??, max: watts.next()?? })
+}
+
+fn driver(path: &Path) -> Option<String> {
+    let driver = fs::read_link(path.join("driver")).ok()?;
+    Some(driver.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Keeps the cap of a GPU about to be changed, unless a profile already changed it since it
+/// was last restored.
+pub fn remember(gpu: &Gpu, limits: PowerLimits) {
+    let mut originals = ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    originals.entry(gpu.bus_id.clone()).or_insert(limits.current);
+}
+
+/// The cap a GPU had before a profile changed it, if one did.
+#[must_use]
+pub fn original(bus_id: &str) -> Option<u32> {
+    ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(bus_id).copied()
+}
+
+/// Takes the cap to restore, so that changes made by others afterwards are kept.
+pub fn restore(bus_id: &str) -> Option<u32> {
+    ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(bus_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn limits() {
+        let limits = parse_limits("80.00, 5.00, 115.00\n").unwrap();
+        assert_eq!(limits, PowerLimits { current: 80, min: 5, max: 115 });
+        assert_eq!(limits.clamp(150), 115);
+        assert_eq!(limits.clamp(1), 5);
+        assert_eq!(limits.clamp(60), 60);
+
+        assert_eq!(parse_limits("[N/A], [N/A], [N/A]\n"), None);
+        assert_eq!(parse_limits("80.00, 5.00\n"), None);
+        assert_eq!(parse_limits(""), None);
+    }
+
+    #[test]
+    fn gpus() {
+        let root = TempDir::new("dgpu");
+        let write = |path: &str, value: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        // A discrete AMD GPU, an Intel GPU, and an NVIDIA GPU without a hwmon or a driver.
+        write("0000:03:00.0/class", "0x030000\n");
+        write("0000:03:00.0/vendor", "0x1002\n");
+        write("0000:03:00.0/hwmon/hwmon4/name", "amdgpu\n");
+        write("0000:03:00.0/hwmon/hwmon4/power1_cap", "150000000\n");
+        write("0000:03:00.0/hwmon/hwmon4/power1_cap_min", "0\n");
+        write("0000:03:00.0/hwmon/hwmon4/power1_cap_max", "186000000\n");
+        write("0000:03:00.0/power/runtime_status", "active\n");
+        write("0000:00:02.0/class", "0x030000\n");
+        write("0000:00:02.0/vendor", "0x8086\n");
+        write("0000:01:00.0/class", "0x030000\n");
+        write("0000:01:00.0/vendor", "0x10de\n");
+        write("0000:01:00.0/power/runtime_status", "suspended\n");
+
+        let gpus = Gpu::all_in(&root, true);
+        let [amd] = &gpus[..] else { panic!("{:?}", gpus) };
+        assert_eq!(amd.bus_id, "0000:03:00.0");
+        assert!(!amd.suspended());
+        assert_eq!(amd.limits(), Some(PowerLimits { current: 150, min: 0, max: 186 }));
+        assert_eq!(amd.cap_path(), Some(root.join("0000:03:00.0/hwmon/hwmon4/power1_cap")));
+        assert_eq!(Gpu::cap_microwatts(120), 120_000_000);
+
+        // The cap found first is kept across changes, until it is restored.
+        remember(amd, PowerLimits { current: 150, min: 0, max: 186 });
+        remember(amd, PowerLimits { current: 120, min: 0, max: 186 });
+        assert_eq!(original(&amd.bus_id), Some(150));
+        assert_eq!(restore(&amd.bus_id), Some(150));
+        assert_eq!(restore(&amd.bus_id), None);
+
+        assert!(Gpu::all_in(&root.join("missing"), true).is_empty());
+    }
+}
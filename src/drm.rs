@@ -0,0 +1,82 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Display connectors of the DRM devices.
+
+use std::{collections::BTreeMap, fs, io};
+
+const DRM_PATH: &str = "/sys/class/drm";
+
+// Connector types of built-in panels. Writeback connectors are not physical.
+const INTERNAL_CONNECTORS: &[&str] = &["DSI", "eDP", "LVDS", "Writeback"];
+
+pub const NVIDIA_VENDOR: u16 = 0x10de;
+
+/// A DRM device, such as `card1`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Card {
+    /// PCI vendor ID of the GPU, if it is a PCI device.
+    pub vendor:              Option<u16>,
+    /// Number of connectors for external displays.
+    pub external_connectors: usize,
+}
+
+/// DRM devices, keyed by name.
+pub fn cards() -> io::Result<BTreeMap<String, Card>> {
+    let mut cards = BTreeMap::new();
+
+    for entry in fs::read_dir(DRM_PATH)? {
+        let name = entry?.file_name();
+        let Some(name) = name.to_str() else { continue };
+
+        let (card, kind) = match connector(name) {
+            Some((card, kind)) => (card, Some(kind)),
+            None if is_card(name) => (name, None),
+            None => continue,
+        };
+
+        let card = cards.entry(card.to_owned()).or_insert_with(|| Card {
+            vendor:              card_vendor(card),
+            external_connectors: 0,
+        });
+
+        if kind.map_or(false, |kind| !INTERNAL_CONNECTORS.contains(&kind)) {
+            card.external_connectors += 1;
+        }
+    }
+
+    Ok(cards)
+}
+
+fn card_vendor(card: &str) -> Option<u16> {
+    let vendor = fs::read_to_string(format!("{}/{}/device/vendor", DRM_PATH, card)).ok()?;
+    u16::from_str_radix(vendor.trim().trim_start_matches("0x"), 16).ok()
+}
+
+fn is_card(name: &str) -> bool {
+    name.strip_prefix("card")
+        .map_or(false, |id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Splits a connector such as `card1-HDMI-A-1` into its card and connector type.
+fn connector(name: &str) -> Option<(&str, &str)> {
+    let (card, connector) = name.split_once('-')?;
+    if !is_card(card) {
+        return None;
+    }
+
+    Some((card, connector.split('-').next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connectors() {
+        assert_eq!(connector("card1-HDMI-A-1"), Some(("card1", "HDMI")));
+        assert_eq!(connector("card0-eDP-1"), Some(("card0", "eDP")));
+        assert_eq!(connector("card0"), None);
+        assert_eq!(connector("renderD128"), None);
+    }
+}
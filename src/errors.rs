@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+use crate::util::WriteError;
 use intel_pstate::PStateError;
 use std::{io, path::PathBuf, process};
 
@@ -9,6 +10,8 @@ use std::{io, path::PathBuf, process};
 pub enum ProfileError {
     #[error("failed to set backlight profiles: {0}")]
     Backlight(#[from] BacklightError),
+    #[error("failed to set the discrete GPU power cap: {0}")]
+    DgpuPowerCap(#[from] DgpuPowerCapError),
     #[error("failed to set disk power profiles: {0}")]
     DiskPower(#[from] DiskPowerError),
     #[error("failed to set model profiles: {0}")]
@@ -17,8 +20,27 @@ pub enum ProfileError {
     PciDevice(#[from] PciDeviceError),
     #[error("failed to set pstate profiles: {0}")]
     PState(#[from] PStateError),
-    #[error("failed to set scsi host profiles: {0}")]
-    ScsiHost(#[from] ScsiHostError),
+    #[error("failed to set Wi-Fi power save: {0}")]
+    Wifi(#[from] WifiError),
+    #[error("{0}")]
+    Write(#[from] WriteError),
+}
+
+impl ProfileError {
+    /// Name of the parameter which failed, or of the devices, such as `backlight`.
+    #[must_use]
+    pub fn parameter(&self) -> &'static str {
+        match self {
+            Self::Backlight(_) => "backlight",
+            Self::DgpuPowerCap(_) => crate::dgpu_power_cap::NAME,
+            Self::DiskPower(_) => "disk_power",
+            Self::Model(_) => "model",
+            Self::PciDevice(_) => "pci_device",
+            Self::PState(_) => "intel_pstate",
+            Self::Wifi(_) => "wifi_powersave",
+            Self::Write(why) => why.written.name,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -27,6 +49,18 @@ pub enum BacklightError {
     Set(String, io::Error),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum DgpuPowerCapError {
+    #[error("nvidia-smi is not installed")]
+    NoNvidiaSmi,
+    #[error("failed to read the power limits of {}", _0)]
+    Limits(String),
+    #[error("failed to run nvidia-smi on {}: {}", _0, _1)]
+    Spawn(String, io::Error),
+    #[error("nvidia-smi failed to set the power limit of {} to {} W: {}", _0, _1, _2)]
+    PowerLimit(String, u32, String),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum DiskPowerError {
     #[error("failed to set disk APM level on {:?} to {}: {}", _0, _1, _2)]
@@ -66,7 +100,11 @@ pub enum PciDeviceError {
 }
 
 #[derive(Debug, thiserror::Error)]
-pub enum ScsiHostError {
-    #[error("failed to set link time power management policy {} on {}: {}", _0, _1, _2)]
-    LinkTimePolicy(&'static str, String, io::Error),
+pub enum WifiError {
+    #[error("iw is not installed")]
+    NoIw,
+    #[error("failed to run iw on {}: {}", _0, _1)]
+    Spawn(String, io::Error),
+    #[error("iw failed to set power save {} on {}: {}", _1, _0, _2)]
+    PowerSave(String, &'static str, String),
 }
@@ -2,81 +2,44 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::{module::Module, pci::PciBus};
+use crate::{
+    config::{self, ConfigError},
+    drm,
+    mode_files::ModeFiles,
+    modprobe::ModprobeConfig,
+    module::Module,
+    nvidia::{self, DriverVersions},
+    pci::{self, PciBus},
+    util::{planned_command, planned_removal, Written},
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs,
+    env, fmt, fs,
     io::{self, Write},
-    path,
-    process::{self, ExitStatus},
+    path, process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use sysfs_class::{PciDevice, SysClass};
+use system76_power_zbus::{
+    GraphicsCapabilities, GraphicsDeviceInfo, GraphicsFunction, GraphicsPowerStatus,
+    GraphicsStatus, NvidiaKernelModule, PlannedAction, SwitchableStatus,
+};
+
+pub use system76_power_zbus::GraphicsMode;
 
-const MODPROBE_PATH: &str = "/etc/modprobe.d/system76-power.conf";
-
-static MODPROBE_NVIDIA: &[u8] = br"# Automatically generated by system76-power
-options nvidia-drm modeset=1
-";
-
-static MODPROBE_HYBRID: &[u8] = br"# Automatically generated by system76-power
-blacklist i2c_nvidia_gpu
-alias i2c_nvidia_gpu off
-options nvidia NVreg_DynamicPowerManagement=0x02
-options nvidia-drm modeset=1
-";
-
-static MODPROBE_HYBRID_NO_GC6: &[u8] = br"# Automatically generated by system76-power
-blacklist i2c_nvidia_gpu
-alias i2c_nvidia_gpu off
-options nvidia NVreg_DynamicPowerManagement=0x01
-options nvidia-drm modeset=1
-";
-
-static MODPROBE_COMPUTE: &[u8] = br"# Automatically generated by system76-power
-blacklist i2c_nvidia_gpu
-blacklist nvidia-drm
-blacklist nvidia-modeset
-alias i2c_nvidia_gpu off
-alias nvidia-drm off
-alias nvidia-modeset off
-options nvidia NVreg_DynamicPowerManagement=0x02
-";
-
-static MODPROBE_COMPUTE_NO_GC6: &[u8] = br"# Automatically generated by system76-power
-blacklist i2c_nvidia_gpu
-blacklist nvidia-drm
-blacklist nvidia-modeset
-alias i2c_nvidia_gpu off
-alias nvidia-drm off
-alias nvidia-modeset off
-options nvidia NVreg_DynamicPowerManagement=0x01
-";
-
-static MODPROBE_INTEGRATED: &[u8] = br"# Automatically generated by system76-power
-blacklist i2c_nvidia_gpu
-blacklist nouveau
-blacklist nvidia
-blacklist nvidia-drm
-blacklist nvidia-modeset
-alias i2c_nvidia_gpu off
-alias nouveau off
-alias nvidia off
-alias nvidia-drm off
-alias nvidia-modeset off
-";
-
-// Systems that cannot use other sleep options
-static SYSTEM_SLEEP_EMPTY: &[u8] = b"";
-
-// Systems using S0ix must enable S0ix-based power management.
-static SYSTEM_SLEEP_S0IX: &[u8] = br"# Preserve video memory through suspend
-options nvidia NVreg_EnableS0ixPowerManagement=1
-";
-
-// Systems using S3 had suspend issues with WebRender.
-static SYSTEM_SLEEP_S3: &[u8] = br"# Preserve video memory through suspend
-options nvidia NVreg_PreserveVideoMemoryAllocations=1
-";
+pub(crate) const MODPROBE_PATH: &str = "/etc/modprobe.d/system76-power.conf";
+
+pub(crate) const MODPROBE_HEADER: &str = "Automatically generated by system76-power";
+
+// Module parameter that disables GSP firmware, available since driver 510.
+const NVIDIA_GSP_FIRMWARE_PARAM: &str = "NVreg_EnableGpuFirmware";
+const NVIDIA_GSP_FIRMWARE_MIN_VERSION: u32 = 510;
 
 const XORG_CONF_PATH: &str = "/usr/share/X11/xorg.conf.d/11-nvidia-discrete.conf";
 
@@ -91,7 +54,7 @@ Section "OutputClass"
 EndSection
 "#;
 
-const PRIME_DISCRETE_PATH: &str = "/etc/prime-discrete";
+pub(crate) const PRIME_DISCRETE_PATH: &str = "/etc/prime-discrete";
 
 const EXTERNAL_DISPLAY_REQUIRES_NVIDIA: &[&str] = &[
     "addw1",
@@ -122,15 +85,53 @@ const EXTERNAL_DISPLAY_REQUIRES_NVIDIA: &[&str] = &[
 ];
 
 const SYSTEMCTL_CMD: &str = "systemctl";
-const UPDATE_DRACUT_CMD: &str = "dracut";
+pub(crate) const UPDATE_DRACUT_CMD: &str = "dracut";
+
+// Services which keep the NVIDIA driver open, stopped to power off the dGPU with `force`.
+const BLOCKING_SERVICES: &[&str] = &["nvidia-persistenced.service"];
+
+const NVIDIA_DRM_MODESET: &str = "/sys/module/nvidia_drm/parameters/modeset";
+
+// Attempts to unbind the dGPU with `force`, which may fail while its clients close it.
+const UNBIND_ATTEMPTS: u32 = 3;
+const UNBIND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// Bytes of the error output of an initramfs rebuild kept for clients.
+const STDERR_TAIL_LEN: usize = 4096;
+
+pub(crate) const LAST_SWITCH_PATH: &str = "/var/lib/system76-power/last-switch.json";
+
+// Overrides every other location of supported-gpus.json when set.
+const SUPPORTED_GPUS_ENV: &str = "SUPPORTED_GPUS_PATH";
 
+// Locations used by NVIDIA's .run installer, searched after distribution packages.
+const SUPPORTED_GPUS_RUN_INSTALLER: &[&str] = &[
+    "/usr/share/doc/NVIDIA_GLX-1.0/supported-gpus.json",
+    "/usr/share/doc/NVIDIA_GLX-1.0/supported-gpus/supported-gpus.json",
+];
 
 #[derive(Debug, thiserror::Error)]
 pub enum GraphicsDeviceError {
     #[error("failed to execute {} command: {}", cmd, why)]
     Command { cmd: &'static str, why: io::Error },
+    #[error("failed to save graphics config: {}", _0)]
+    Config(ConfigError),
     #[error("{} in use by {}", func, driver)]
     DeviceInUse { func: String, driver: String },
+    #[error("NVIDIA GPU in use by {}; stop these processes or force the switch", _0)]
+    ComputeInUse(String),
+    #[error("nvidia-drm modeset is enabled, so a display server may be using the NVIDIA GPU")]
+    ModesetEnabled,
+    #[error(
+        "NVIDIA kernel module version {} does not match userspace driver version {}",
+        kernel,
+        userspace
+    )]
+    DriverVersionMismatch { kernel: String, userspace: String },
+    #[error("NVIDIA driver {} does not support toggling GSP firmware", _0)]
+    GspUnsupported(String),
+    #[error("invalid value '{}' for graphics option {}", value, option)]
+    InvalidOptionValue { option: String, value: String },
     #[error("failed to probe driver features: {}", _0)]
     Json(io::Error),
     #[error("failed to open system76-power modprobe file: {}", _0)]
@@ -139,8 +140,12 @@ pub enum GraphicsDeviceError {
     ModprobeFileWrite(io::Error),
     #[error("failed to fetch list of active kernel modules: {}", _0)]
     ModulesFetch(io::Error),
-    #[error("does not have switchable graphics")]
-    NotSwitchable,
+    #[error("failed to parse {}: {}", path.display(), why)]
+    SupportedGpusParse { path: path::PathBuf, why: serde_json::Error },
+    #[error("does not have switchable graphics: {}", _0)]
+    NotSwitchable(NotSwitchableReason),
+    #[error("unknown graphics option {}", _0)]
+    UnknownOption(String),
     #[error("PCI driver error on {}: {}", device, why)]
     PciDriver { device: String, why: io::Error },
     #[error("failed to get PRIME value: {}", _0)]
@@ -155,12 +160,13 @@ pub enum GraphicsDeviceError {
     SysFs(io::Error),
     #[error("failed to unbind {} on PCI driver {}: {}", func, driver, why)]
     Unbind { func: String, driver: String, why: io::Error },
-    #[error("update-dracut failed with {} status", _0)]
-    UpdateDracut(ExitStatus),
+    #[error("{} failed to rebuild the initramfs", _0.tool)]
+    UpdateDracut(InitramfsRebuild),
     #[error("failed to access Xserver config: {}", _0)]
     XserverConf(io::Error),
 }
 
+#[derive(Clone)]
 pub struct GraphicsDevice {
     id:        String,
     devid:     u16,
@@ -179,12 +185,41 @@ impl GraphicsDevice {
     #[must_use]
     pub const fn device(&self) -> u16 { self.devid }
 
+    /// Whether runtime power management has suspended the device.
+    #[must_use]
+    pub fn runtime_suspended(&self) -> bool {
+        fs::read_to_string(format!("/sys/bus/pci/devices/{}/power/runtime_status", self.id))
+            .map_or(false, |status| status.trim() == "suspended")
+    }
+
+    /// The functions of the device present on the bus, with their driver and runtime PM
+    /// status.
+    #[must_use]
+    pub fn functions(&self) -> Vec<GraphicsFunction> {
+        let functions = self.functions.iter().filter(|func| func.path().exists());
+        functions
+            .map(|func| GraphicsFunction {
+                bus_id:         func.id().to_owned(),
+                driver:         func
+                    .driver()
+                    .map(|driver| driver.id().to_owned())
+                    .unwrap_or_default(),
+                runtime_status: fs::read_to_string(func.path().join("power/runtime_status"))
+                    .map(|status| status.trim().to_owned())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+
     pub unsafe fn unbind(&self) -> Result<(), GraphicsDeviceError> {
         for func in &self.functions {
             if func.path().exists() {
                 match func.driver() {
                     Ok(driver) => {
-                        log::info!("{}: Unbinding {}", driver.id(), func.id());
+                        log::info!(
+                            operation = "unbind", device = func.id(), driver = driver.id();
+                            "{}: Unbinding {}", driver.id(), func.id()
+                        );
                         driver.unbind(func).map_err(|why| GraphicsDeviceError::Unbind {
                             driver: driver.id().to_owned(),
                             func: func.id().to_owned(),
@@ -207,12 +242,50 @@ impl GraphicsDevice {
         Ok(())
     }
 
-    pub unsafe fn remove(&self) -> Result<(), GraphicsDeviceError> {
+    /// The writes unbinding and removing the device would perform, without performing them.
+    #[must_use]
+    pub fn plan_removal(&self) -> Vec<PlannedAction> {
+        let mut plan = Vec::new();
+
+        for func in self.functions.iter().filter(|func| func.path().exists()) {
+            if let Ok(driver) = func.driver() {
+                let unbind = driver.path().join("unbind");
+                plan.push(Written::new("unbind", unbind.to_string_lossy(), func.id()).planned());
+            }
+
+            let remove = func.path().join("remove");
+            plan.push(Written::new("remove", remove.to_string_lossy(), 1).planned());
+        }
+
+        plan
+    }
+
+    /// Whether a driver is bound to any function of the device.
+    #[must_use]
+    pub fn bound(&self) -> bool {
+        self.functions.iter().any(|func| func.path().exists() && func.driver().is_ok())
+    }
+
+    /// Removes the device from the bus, refusing while a driver is bound to it unless `force`.
+    pub unsafe fn remove(&self, force: bool) -> Result<(), GraphicsDeviceError> {
         for func in &self.functions {
             if func.path().exists() {
                 match func.driver() {
+                    Ok(driver) if force => {
+                        log::warn!(
+                            operation = "remove", device = func.id(), driver = driver.id();
+                            "{}: Removing while in use by {}", func.id(), driver.id()
+                        );
+                        func.remove().map_err(|why| GraphicsDeviceError::Remove {
+                            device: self.id.clone(),
+                            why,
+                        })?;
+                    }
                     Ok(driver) => {
-                        log::error!("{}: in use by {}", func.id(), driver.id());
+                        log::error!(
+                            operation = "remove", device = func.id(), driver = driver.id();
+                            "{}: in use by {}", func.id(), driver.id()
+                        );
                         return Err(GraphicsDeviceError::DeviceInUse {
                             func:   func.id().to_owned(),
                             driver: driver.id().to_owned(),
@@ -220,7 +293,10 @@ impl GraphicsDevice {
                     }
                     Err(why) => match why.kind() {
                         io::ErrorKind::NotFound => {
-                            log::info!("{}: Removing", func.id());
+                            log::info!(
+                                operation = "remove", device = func.id();
+                                "{}: Removing", func.id()
+                            );
                             func.remove().map_err(|why| GraphicsDeviceError::Remove {
                                 device: self.id.clone(),
                                 why,
@@ -254,47 +330,226 @@ struct NvidiaDevice {
     features:     Vec<String>,
 }
 
+impl GraphicsDeviceError {
+    /// The failed initramfs rebuild, if this is the error of one.
+    #[must_use]
+    pub fn initramfs_rebuild(&self) -> Option<&InitramfsRebuild> {
+        match self {
+            Self::UpdateDracut(rebuild) => Some(rebuild),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of an initramfs rebuild.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct InitramfsRebuild {
+    /// Command which rebuilt the initramfs, such as `dracut`.
+    pub tool:        String,
+    /// Whether the rebuild was skipped, as the initramfs was already up to date.
+    pub skipped:     bool,
+    pub success:     bool,
+    pub duration_ms: u64,
+    /// End of the error output of the command.
+    pub stderr_tail: String,
+}
+
+impl InitramfsRebuild {
+    fn skipped() -> Self {
+        Self { tool: UPDATE_DRACUT_CMD.into(), skipped: true, success: true, ..Self::default() }
+    }
+}
+
+/// The last graphics switch, kept in the state directory for diagnosis.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LastSwitch {
+    pub mode:      String,
+    /// Time of the switch, in seconds since the Unix epoch.
+    pub time:      u64,
+    pub success:   bool,
+    /// Outcome reported to the client, such as the error of a failed switch.
+    pub message:   String,
+    pub initramfs: Option<InitramfsRebuild>,
+}
+
+impl LastSwitch {
+    #[must_use]
+    pub fn load() -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(LAST_SWITCH_PATH).ok()?).ok()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = path::Path::new(LAST_SWITCH_PATH).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(LAST_SWITCH_PATH, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// The last `len` bytes of `text`, starting at a line if possible.
+fn tail(text: &str, len: usize) -> &str {
+    if text.len() <= len {
+        return text;
+    }
+
+    let mut start = text.len() - len;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+
+    let tail = &text[start..];
+    tail.split_once('\n').map_or(tail, |(_, lines)| lines)
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct SupportedGpus {
     chips: Vec<NvidiaDevice>,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum GraphicsMode {
-    Integrated,
-    Compute,
-    Hybrid,
-    Discrete,
+/// Whether the NVIDIA driver is told to use its GSP firmware.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GspFirmware {
+    /// Leave the choice to the driver.
+    #[default]
+    Default,
+    On,
+    Off,
+}
+
+impl fmt::Display for GspFirmware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            GspFirmware::Default => "default",
+            GspFirmware::On => "on",
+            GspFirmware::Off => "off",
+        })
+    }
+}
+
+impl FromStr for GspFirmware {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "default" => Ok(GspFirmware::Default),
+            "on" => Ok(GspFirmware::On),
+            "off" => Ok(GspFirmware::Off),
+            _ => Err(()),
+        }
+    }
+}
+
+pub const GRAPHICS_CONFIG: &str = "graphics.toml";
+
+/// The graphics mode configured for the next boot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfiguredMode {
+    /// No mode was configured yet.
+    Unset,
+    Mode(GraphicsMode),
+    /// The configuration matches no mode, such as after being edited.
+    Custom,
+}
+
+impl fmt::Display for ConfiguredMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unset => Ok(()),
+            Self::Mode(mode) => mode.fmt(f),
+            Self::Custom => f.write_str("custom"),
+        }
+    }
+}
+
+/// How the mode recommended for a model was derived.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefaultReason {
+    /// Models of other vendors default to the discrete GPU.
+    VendorNotSystem76,
+    /// The discrete GPU can power off while unused, so hybrid costs little battery.
+    RuntimePmSupported,
+    /// The discrete GPU cannot power off while unused.
+    RuntimePmUnsupported,
+    /// The model supports runtime power management, but should not use hybrid graphics.
+    ModelBlacklisted,
+    /// The driver's list of supported GPUs is missing, so runtime power management is unknown.
+    NoDriver,
 }
 
-impl From<GraphicsMode> for &'static str {
-    fn from(mode: GraphicsMode) -> &'static str {
-        match mode {
-            GraphicsMode::Integrated => "integrated",
-            GraphicsMode::Compute => "compute",
-            GraphicsMode::Hybrid => "hybrid",
-            GraphicsMode::Discrete => "nvidia",
+impl DefaultReason {
+    /// Machine-readable code of the reason, such as `runtimepm-supported`.
+    #[must_use]
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::VendorNotSystem76 => "vendor-not-system76",
+            Self::RuntimePmSupported => "runtimepm-supported",
+            Self::RuntimePmUnsupported => "runtimepm-unsupported",
+            Self::ModelBlacklisted => "model-blacklisted",
+            Self::NoDriver => "no-driver",
         }
     }
 }
 
-impl From<&str> for GraphicsMode {
-    fn from(vendor: &str) -> Self {
-        match vendor {
-            "nvidia" => GraphicsMode::Discrete,
-            "hybrid" => GraphicsMode::Hybrid,
-            "compute" => GraphicsMode::Compute,
-            _ => GraphicsMode::Integrated,
+/// Why graphics cannot be switched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotSwitchableReason {
+    /// Desktops drive their displays from the GPU they are plugged into.
+    Desktop,
+    NoDiscreteGpu,
+    NoIntegratedGpu,
+}
+
+impl NotSwitchableReason {
+    /// Machine-readable code of the reason, such as `no-discrete-gpu`.
+    #[must_use]
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Desktop => "desktop",
+            Self::NoDiscreteGpu => "no-discrete-gpu",
+            Self::NoIntegratedGpu => "no-integrated-gpu",
         }
     }
 }
 
+impl fmt::Display for NotSwitchableReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Desktop => "this is a desktop",
+            Self::NoDiscreteGpu => "no NVIDIA GPU was found",
+            Self::NoIntegratedGpu => "no Intel or AMD GPU was found",
+        })
+    }
+}
+
+/// The mode recommended for a model, and why.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DefaultGraphics {
+    pub mode:   GraphicsMode,
+    pub reason: DefaultReason,
+}
+
+/// Options applied to the generated modprobe config, stored in `graphics.toml`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GraphicsConfig {
+    pub gsp: GspFirmware,
+}
+
+#[derive(Clone)]
 pub struct Graphics {
-    pub bus:    PciBus,
-    pub amd:    Vec<GraphicsDevice>,
-    pub intel:  Vec<GraphicsDevice>,
-    pub nvidia: Vec<GraphicsDevice>,
-    pub other:  Vec<GraphicsDevice>,
+    pub bus:                 PciBus,
+    pub amd:                 Vec<GraphicsDevice>,
+    pub intel:               Vec<GraphicsDevice>,
+    pub nvidia:              Vec<GraphicsDevice>,
+    pub other:               Vec<GraphicsDevice>,
+    /// Refuse to switch to a mode using the NVIDIA driver when its versions mismatch.
+    pub strict_driver_check: bool,
+    pub config:              GraphicsConfig,
+    /// Whether the last attempt to power on the discrete GPU left it off the bus, shared by
+    /// the clones of the daemon.
+    power_on_failed:         Arc<AtomicBool>,
 }
 
 impl Graphics {
@@ -304,6 +559,13 @@ impl Graphics {
         log::info!("Rescanning PCI bus");
         bus.rescan()?;
 
+        Self::scan(bus)
+    }
+
+    /// The GPUs on the PCI bus, without rescanning it first, which requires root.
+    pub fn without_rescan() -> io::Result<Self> { Self::scan(PciBus::new()?) }
+
+    fn scan(bus: PciBus) -> io::Result<Self> {
         let devs = PciDevice::all()?;
 
         let functions = |parent: &PciDevice| -> Vec<PciDevice> {
@@ -365,7 +627,31 @@ impl Graphics {
             }
         }
 
-        Ok(Self { bus, amd, intel, nvidia, other })
+        Ok(Self {
+            bus,
+            amd,
+            intel,
+            nvidia,
+            other,
+            strict_driver_check: false,
+            config: GraphicsConfig::default(),
+            power_on_failed: Arc::default(),
+        })
+    }
+
+    /// Graphics without any devices, for tests.
+    #[cfg(test)]
+    pub(crate) fn stub() -> Self {
+        Self {
+            bus:                 PciBus::stub(),
+            amd:                 Vec::new(),
+            intel:               Vec::new(),
+            nvidia:              Vec::new(),
+            other:               Vec::new(),
+            strict_driver_check: false,
+            config:              GraphicsConfig::default(),
+            power_on_failed:     Arc::default(),
+        }
     }
 
     pub fn is_desktop(&self) -> bool {
@@ -377,44 +663,211 @@ impl Graphics {
     }
 
     #[must_use]
-    pub fn can_switch(&self) -> bool {
-        !self.is_desktop()
-            && (!self.nvidia.is_empty() && (!self.intel.is_empty() || !self.amd.is_empty()))
+    pub fn can_switch(&self) -> bool { self.not_switchable_reason().is_none() }
+
+    /// Why graphics cannot be switched, if they cannot.
+    #[must_use]
+    pub fn not_switchable_reason(&self) -> Option<NotSwitchableReason> {
+        not_switchable_reason(
+            self.is_desktop(),
+            !self.nvidia.is_empty(),
+            !self.intel.is_empty() || !self.amd.is_empty(),
+        )
+    }
+
+    /// Whether graphics can be switched, why not, and the GPUs on the PCI bus.
+    pub fn switchable_status(&self) -> Result<SwitchableStatus, GraphicsDeviceError> {
+        let reason = self.not_switchable_reason();
+
+        Ok(SwitchableStatus {
+            switchable: reason.is_none(),
+            reason:     reason.map(|reason| reason.code().to_owned()).unwrap_or_default(),
+            devices:    Self::devices()?,
+        })
+    }
+
+    #[must_use]
+    pub fn capabilities(&self) -> GraphicsCapabilities {
+        let versions = DriverVersions::probe();
+
+        GraphicsCapabilities {
+            switchable:               self.can_switch(),
+            desktop:                  self.is_desktop(),
+            kernel_driver_version:    versions.kernel.unwrap_or_default(),
+            userspace_driver_version: versions.userspace.unwrap_or_default(),
+            gsp_firmware:             self.config.gsp.to_string(),
+            kernel_modules:           nvidia::kernel_modules()
+                .into_iter()
+                .map(|(kernel, module)| {
+                    let module = NvidiaKernelModule {
+                        module_present: module.present,
+                        module_version: module.version.unwrap_or_default(),
+                        source:         module.source.map(|s| s.to_string()).unwrap_or_default(),
+                    };
+                    (kernel, module)
+                })
+                .collect(),
+        }
+    }
+
+    /// GPUs currently on the PCI bus, read from sysfs rather than the devices found at
+    /// startup.
+    pub fn devices() -> Result<Vec<GraphicsDeviceInfo>, GraphicsDeviceError> {
+        let mut devices = Vec::new();
+
+        for dev in PciDevice::all().map_err(GraphicsDeviceError::SysFs)? {
+            let class = dev.class().map_err(GraphicsDeviceError::SysFs)?;
+            if (class >> 16) & 0xFF != 0x03 {
+                continue;
+            }
+
+            let vendor_id = dev.vendor().map_err(GraphicsDeviceError::SysFs)?;
+            let device_id = dev.device().map_err(GraphicsDeviceError::SysFs)?;
+
+            let name = pci::device_name(vendor_id, device_id).or_else(|| {
+                (vendor_id == 0x10DE)
+                    .then(|| Self::get_nvidia_device(device_id).ok())
+                    .flatten()
+                    .map(|dev| dev.name)
+            });
+
+            let power_state = fs::read_to_string(dev.path().join("power_state"))
+                .map(|state| state.trim().to_owned())
+                .unwrap_or_default();
+
+            devices.push(GraphicsDeviceInfo {
+                vendor_id,
+                device_id,
+                subsystem_vendor_id: dev.subsystem_vendor().unwrap_or_default(),
+                subsystem_device_id: dev.subsystem_device().unwrap_or_default(),
+                bus_id: dev.id().to_owned(),
+                // As when switching, AMD and Intel GPUs are taken to be the integrated ones.
+                kind: match vendor_id {
+                    0x1002 | 0x8086 => "integrated",
+                    0x10DE => "discrete",
+                    _ => "other",
+                }
+                .to_owned(),
+                driver: dev.driver().map(|driver| driver.id().to_owned()).unwrap_or_default(),
+                power_state,
+                name: name.unwrap_or_default(),
+            });
+        }
+
+        Ok(devices)
     }
 
+    /// Whether external display ports are wired to the dGPU.
+    ///
+    /// This is read from the connectors of the NVIDIA DRM device while it is present, and
+    /// from the list of known models otherwise.
     pub fn get_external_displays_require_dgpu(&self) -> Result<bool, GraphicsDeviceError> {
         self.switchable_or_fail()?;
 
-        let model = fs::read_to_string("/sys/class/dmi/id/product_version")
-            .map_err(GraphicsDeviceError::SysFs)?;
+        let cards = drm::cards().unwrap_or_default();
+        let nvidia = cards
+            .values()
+            .filter(|card| card.vendor == Some(drm::NVIDIA_VENDOR))
+            .collect::<Vec<_>>();
+
+        if !nvidia.is_empty() {
+            return Ok(nvidia.iter().any(|card| card.external_connectors > 0));
+        }
+
+        let model = match fs::read_to_string("/sys/class/dmi/id/product_version") {
+            Ok(model) => model,
+            Err(why) => {
+                log::debug!("unable to read the model: {}", why);
+                return Ok(false);
+            }
+        };
 
         Ok(EXTERNAL_DISPLAY_REQUIRES_NVIDIA.contains(&model.trim()))
     }
 
-    fn get_nvidia_device(id: u16) -> Result<NvidiaDevice, GraphicsDeviceError> {
-        let supported_gpus: Vec<path::PathBuf> = fs::read_dir("/usr/share/doc")
-            .map_err(|e| {
-                GraphicsDeviceError::Json(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
-            })?
-            .filter_map(Result::ok)
-            .map(|f| f.path())
-            .filter(|f| f.to_str().unwrap_or_default().contains("nvidia-driver-"))
-            .map(|f| f.join("supported-gpus.json"))
-            .filter(|f| f.exists())
-            .collect();
+    fn supported_gpus_path() -> Result<path::PathBuf, GraphicsDeviceError> {
+        #[derive(Clone, Copy)]
+        enum Source {
+            Override,
+            Package,
+            Installer,
+        }
+
+        let packaged: Vec<path::PathBuf> = match fs::read_dir("/usr/share/doc") {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .map(|f| f.path())
+                .filter(|f| f.to_str().unwrap_or_default().contains("nvidia-driver-"))
+                .map(|f| f.join("supported-gpus.json"))
+                .collect(),
+            Err(why) => {
+                log::warn!("failed to read /usr/share/doc: {}", why);
+                Vec::new()
+            }
+        };
+
+        let candidates = env::var_os(SUPPORTED_GPUS_ENV)
+            .map(|path| (Source::Override, path::PathBuf::from(path)))
+            .into_iter()
+            .chain(packaged.into_iter().map(|path| (Source::Package, path)))
+            .chain(
+                SUPPORTED_GPUS_RUN_INSTALLER
+                    .iter()
+                    .map(|path| (Source::Installer, path::PathBuf::from(path))),
+            );
+
+        let mut found = Vec::new();
+        let mut installer = None;
+        for (source, candidate) in candidates {
+            let exists = candidate.exists();
+            let from = match source {
+                Source::Override => SUPPORTED_GPUS_ENV,
+                Source::Package => "a distribution package",
+                Source::Installer => "the .run installer",
+            };
+            log::debug!(
+                "supported-gpus.json candidate {} (from {}): exists = {}",
+                candidate.display(),
+                from,
+                exists
+            );
+
+            match source {
+                Source::Override if exists => return Ok(candidate),
+                Source::Override => {
+                    return Err(GraphicsDeviceError::Json(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} does not exist", candidate.display()),
+                    )))
+                }
+                Source::Package if exists => found.push(candidate),
+                Source::Installer if exists && installer.is_none() => installer = Some(candidate),
+                Source::Package | Source::Installer => (),
+            }
+        }
 
         // There should be only 1 driver version installed.
-        if supported_gpus.len() != 1 {
-            return Err(GraphicsDeviceError::Json(io::Error::new(
+        match (found.len(), installer) {
+            (1, _) => Ok(found.remove(0)),
+            (0, Some(path)) => Ok(path),
+            (0, None) => Err(GraphicsDeviceError::Json(io::Error::new(
+                io::ErrorKind::NotFound,
+                "supported-gpus.json not found",
+            ))),
+            _ => Err(GraphicsDeviceError::Json(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "NVIDIA drivers misconfigured",
-            )));
+            ))),
         }
+    }
+
+    fn get_nvidia_device(id: u16) -> Result<NvidiaDevice, GraphicsDeviceError> {
+        let path = Self::supported_gpus_path()?;
+        log::info!("Using {}", path.display());
 
-        let raw = fs::read_to_string(&supported_gpus[0]).map_err(GraphicsDeviceError::Json)?;
-        let gpus: SupportedGpus = serde_json::from_str(&raw).map_err(|e| {
-            GraphicsDeviceError::Json(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
-        })?;
+        let raw = fs::read_to_string(&path).map_err(GraphicsDeviceError::Json)?;
+        let gpus: SupportedGpus = serde_json::from_str(&raw)
+            .map_err(|why| GraphicsDeviceError::SupportedGpusParse { path, why })?;
 
         // There may be multiple entries that share the same device ID.
         for dev in gpus.chips {
@@ -442,7 +895,7 @@ impl Graphics {
         }
     }
 
-    pub fn get_default_graphics(&self) -> Result<GraphicsMode, GraphicsDeviceError> {
+    pub fn get_default_graphics(&self) -> Result<DefaultGraphics, GraphicsDeviceError> {
         // Models that support runtimepm, but should not use hybrid graphics
         const DEFAULT_INTEGRATED: &[&str] = &[];
 
@@ -454,10 +907,10 @@ impl Graphics {
         let blacklisted = DEFAULT_INTEGRATED.contains(&product.as_str());
 
         let runtimepm = match self.gpu_supports_runtimepm() {
-            Ok(ok) => ok,
+            Ok(ok) => Some(ok),
             Err(err) => {
                 log::warn!("could not determine GPU runtimepm support: {}", err);
-                false
+                None
             }
         };
 
@@ -466,13 +919,7 @@ impl Graphics {
             .map_err(GraphicsDeviceError::SysFs)
             .map(|s| s.trim().to_string())?;
 
-        if vendor != "System76" {
-            Ok(GraphicsMode::Discrete)
-        } else if runtimepm && !blacklisted {
-            Ok(GraphicsMode::Hybrid)
-        } else {
-            Ok(GraphicsMode::Integrated)
-        }
+        Ok(default_graphics(&vendor, runtimepm, blacklisted))
     }
 
     fn get_prime_discrete() -> Result<String, GraphicsDeviceError> {
@@ -508,110 +955,130 @@ impl Graphics {
         Ok(vendor)
     }
 
-    pub fn set_vendor(&self, vendor: GraphicsMode) -> Result<(), GraphicsDeviceError> {
+    /// A kernel module and userspace libraries from different driver releases boot to a black
+    /// screen, which is common after a partial update.
+    fn check_driver_versions(&self) -> Result<(), GraphicsDeviceError> {
+        let versions = DriverVersions::probe();
+        log::debug!(
+            "NVIDIA driver versions: kernel {:?}, userspace {:?}",
+            versions.kernel,
+            versions.userspace
+        );
+
+        if let Some((kernel, userspace)) = versions.mismatch() {
+            if self.strict_driver_check {
+                return Err(GraphicsDeviceError::DriverVersionMismatch {
+                    kernel:    kernel.to_owned(),
+                    userspace: userspace.to_owned(),
+                });
+            }
+
+            log::warn!(
+                "NVIDIA kernel module version {} does not match userspace driver version {}",
+                kernel,
+                userspace
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Compute jobs keep the driver loaded until they exit, and are gone after the reboot into
+    /// integrated mode, so their owners should know before switching.
+    fn check_compute_users() -> Result<(), GraphicsDeviceError> {
+        let users = nvidia::compute_users();
+        if users.is_empty() {
+            return Ok(());
+        }
+
+        for user in &users {
+            log::error!("NVIDIA GPU: in use by {}", user);
+        }
+
+        let users = users.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        Err(GraphicsDeviceError::ComputeInUse(users))
+    }
+
+    /// Switches the graphics mode, reporting each step with its percentage of completion.
+    ///
+    /// The initramfs is only rebuilt if the modprobe configuration changed since it was last
+    /// built, and never with `skip_initramfs`, which returns `None`. The next switch then
+    /// rebuilds it, as it is older than the modprobe configuration.
+    pub fn set_vendor(
+        &self,
+        vendor: GraphicsMode,
+        force: bool,
+        skip_initramfs: bool,
+        mut progress: impl FnMut(&str, u32),
+    ) -> Result<Option<InitramfsRebuild>, GraphicsDeviceError> {
         self.switchable_or_fail()?;
 
+        progress("Checking drivers", 0);
+
+        if matches!(vendor, GraphicsMode::Discrete | GraphicsMode::Hybrid) {
+            self.check_driver_versions()?;
+        }
+
+        if vendor == GraphicsMode::Integrated {
+            if force {
+                log::info!("Skipping check for NVIDIA compute users");
+            } else {
+                Self::check_compute_users()?;
+            }
+        }
+
         let mode = match vendor {
             GraphicsMode::Hybrid => "on-demand\n",
             GraphicsMode::Discrete => "on\n",
             _ => "off\n",
         };
 
-        log::info!("Setting {} to {}", PRIME_DISCRETE_PATH, mode);
+        progress("Configuring PRIME", 10);
+        log::info!(
+            operation = "set_vendor", mode = <&'static str>::from(vendor);
+            "Setting {} to {}", PRIME_DISCRETE_PATH, mode.trim_end()
+        );
         Self::set_prime_discrete(mode)?;
 
-        let bonw15_hack = {
-            let dmi_vendor = fs::read_to_string("/sys/class/dmi/id/sys_vendor").unwrap_or_default();
-            let dmi_model =
-                fs::read_to_string("/sys/class/dmi/id/product_version").unwrap_or_default();
-            match (dmi_vendor.trim(), dmi_model.trim()) {
-                ("System76", "bonw15") => true,
-                ("System76", "bonw15-b") => true,
-                _ => false,
-            }
-        };
-
-        {
-            log::info!("Creating {}", MODPROBE_PATH);
+        let bonw15_hack = Self::bonw15_hack();
 
-            let mut file = fs::OpenOptions::new()
-                .create(true)
-                .truncate(true)
-                .write(true)
-                .open(MODPROBE_PATH)
-                .map_err(GraphicsDeviceError::ModprobeFileOpen)?;
-
-            let text = match vendor {
-                GraphicsMode::Integrated => MODPROBE_INTEGRATED,
-                GraphicsMode::Compute => {
-                    if bonw15_hack {
-                        MODPROBE_COMPUTE_NO_GC6
-                    } else {
-                        MODPROBE_COMPUTE
-                    }
-                }
-                GraphicsMode::Hybrid => {
-                    if bonw15_hack {
-                        MODPROBE_HYBRID_NO_GC6
-                    } else {
-                        MODPROBE_HYBRID
-                    }
-                }
-                GraphicsMode::Discrete => MODPROBE_NVIDIA,
-            };
-
-            file.write_all(text)
-                .and_then(|()| file.sync_all())
-                .map_err(GraphicsDeviceError::ModprobeFileWrite)?;
-
-            // Power management must be configured depending on if the system
-            // uses S0ix or S3 for suspend.
-            if vendor != GraphicsMode::Integrated {
-                // XXX: Better way to check?
-                let s0ix = fs::read_to_string("/sys/power/mem_sleep")
-                    .unwrap_or_default()
-                    .contains("[s2idle]");
-
-                let (sleep, action) = if bonw15_hack {
-                    (SYSTEM_SLEEP_EMPTY, "disable")
-                } else if s0ix {
-                    (SYSTEM_SLEEP_S0IX, "enable")
-                } else {
-                    (SYSTEM_SLEEP_S3, "enable")
-                };
+        progress("Writing modprobe configuration", 20);
+        log::info!("Creating {}", MODPROBE_PATH);
+        let text = self.modprobe_config(vendor, bonw15_hack).render();
+        let unchanged = fs::read_to_string(MODPROBE_PATH).map_or(false, |current| current == text)
+            && Self::initramfs_is_current();
+        if !unchanged {
+            Self::write_modprobe(&text)?;
+        }
 
-                // We should also check if the GPU supports Video Memory Self
-                // Refresh, but that requires already being in hybrid or nvidia
-                // graphics mode. In compute mode, it just reports '?'.
-
-                file.write_all(sleep)
-                    .and_then(|()| file.sync_all())
-                    .map_err(GraphicsDeviceError::ModprobeFileWrite)?;
-
-                for service in
-                    &["nvidia-hibernate.service", "nvidia-resume.service", "nvidia-suspend.service"]
-                {
-                    let status = process::Command::new(SYSTEMCTL_CMD)
-                        .arg(action)
-                        .arg(service)
-                        .status()
-                        .map_err(|why| GraphicsDeviceError::Command { cmd: SYSTEMCTL_CMD, why })?;
-
-                    if !status.success() {
-                        // Error is ignored in case this service is removed
-                        log::warn!(
-                            "systemctl {} {}: failed with {} (not an error if service does not \
-                             exist!)",
-                            action,
-                            service,
-                            status
-                        );
-                    }
+        progress("Configuring services", 30);
+
+        if vendor != GraphicsMode::Integrated {
+            let action = if bonw15_hack { "disable" } else { "enable" };
+
+            for service in
+                &["nvidia-hibernate.service", "nvidia-resume.service", "nvidia-suspend.service"]
+            {
+                let status = process::Command::new(SYSTEMCTL_CMD)
+                    .arg(action)
+                    .arg(service)
+                    .status()
+                    .map_err(|why| GraphicsDeviceError::Command { cmd: SYSTEMCTL_CMD, why })?;
+
+                if !status.success() {
+                    // Error is ignored in case this service is removed
+                    log::warn!(
+                        "systemctl {} {}: failed with {} (not an error if service does not exist!)",
+                        action,
+                        service,
+                        status
+                    );
                 }
             }
         }
 
         // Configure X server
+        progress("Configuring X server", 40);
         if vendor == GraphicsMode::Discrete {
             let mut file = fs::OpenOptions::new()
                 .create(true)
@@ -649,18 +1116,346 @@ impl Graphics {
             );
         }
 
-        log::info!("Updating dracut");
-        
-        let status = process::Command::new(UPDATE_DRACUT_CMD)
+        if unchanged {
+            log::info!("{} is unchanged, not updating the initramfs", MODPROBE_PATH);
+            return Ok(Some(InitramfsRebuild::skipped()));
+        }
+
+        if skip_initramfs {
+            log::warn!(
+                operation = "set_vendor";
+                "Not updating the initramfs as requested; it must be rebuilt before rebooting"
+            );
+            return Ok(None);
+        }
+
+        progress("Updating initramfs", 50);
+        Self::update_initramfs().map(Some)
+    }
+
+    /// The files switching to `vendor` would write and remove, and the commands it would run,
+    /// without switching. The same checks as [`Self::set_vendor`] are made first.
+    pub fn plan_vendor(
+        &self,
+        vendor: GraphicsMode,
+        force: bool,
+        skip_initramfs: bool,
+    ) -> Result<Vec<PlannedAction>, GraphicsDeviceError> {
+        self.switchable_or_fail()?;
+
+        if matches!(vendor, GraphicsMode::Discrete | GraphicsMode::Hybrid) {
+            self.check_driver_versions()?;
+        }
+
+        if vendor == GraphicsMode::Integrated && !force {
+            Self::check_compute_users()?;
+        }
+
+        let mode = match vendor {
+            GraphicsMode::Hybrid => "on-demand",
+            GraphicsMode::Discrete => "on",
+            _ => "off",
+        };
+
+        let mut plan = vec![Written::new("prime-discrete", PRIME_DISCRETE_PATH, mode).planned()];
+
+        let bonw15_hack = Self::bonw15_hack();
+        let text = self.modprobe_config(vendor, bonw15_hack).render();
+        let unchanged = fs::read_to_string(MODPROBE_PATH).map_or(false, |current| current == text)
+            && Self::initramfs_is_current();
+        if !unchanged {
+            plan.push(Written::new("modprobe", MODPROBE_PATH, text).planned());
+        }
+
+        if vendor != GraphicsMode::Integrated {
+            let action = if bonw15_hack { "disable" } else { "enable" };
+            for service in
+                &["nvidia-hibernate.service", "nvidia-resume.service", "nvidia-suspend.service"]
+            {
+                plan.push(planned_command(SYSTEMCTL_CMD, &[action, service]));
+            }
+        }
+
+        if vendor == GraphicsMode::Discrete {
+            let conf = String::from_utf8_lossy(XORG_CONF_DISCRETE);
+            plan.push(Written::new("xorg.conf", XORG_CONF_PATH, conf).planned());
+        } else if path::Path::new(XORG_CONF_PATH).exists() {
+            plan.push(planned_removal(XORG_CONF_PATH));
+        }
+
+        let action = if vendor == GraphicsMode::Discrete { "enable" } else { "disable" };
+        plan.push(planned_command(SYSTEMCTL_CMD, &[action, "nvidia-fallback.service"]));
+
+        if !unchanged && !skip_initramfs {
+            plan.push(planned_command(UPDATE_DRACUT_CMD, &["--force"]));
+        }
+
+        Ok(plan)
+    }
+
+    /// Sets an option of the generated modprobe config and saves it to the graphics config.
+    ///
+    /// If a mode using the NVIDIA driver is configured, its modprobe config is regenerated, and
+    /// the initramfs is refreshed when that changed its content. The outcome of the refresh is
+    /// returned, if one was due.
+    pub fn set_option(
+        &mut self,
+        option: &str,
+        value: &str,
+    ) -> Result<Option<InitramfsRebuild>, GraphicsDeviceError> {
+        let invalid = || GraphicsDeviceError::InvalidOptionValue {
+            option: option.to_owned(),
+            value:  value.to_owned(),
+        };
+
+        match option {
+            "gsp" => {
+                let gsp = value.parse::<GspFirmware>().map_err(|()| invalid())?;
+                if gsp != GspFirmware::Default {
+                    Self::check_gsp_support()?;
+                }
+                self.config.gsp = gsp;
+            }
+            _ => return Err(GraphicsDeviceError::UnknownOption(option.to_owned())),
+        }
+
+        config::save(GRAPHICS_CONFIG, &self.config).map_err(GraphicsDeviceError::Config)?;
+        log::info!("Set graphics option {} to {}", option, value);
+
+        let vendor = match Self::get_configured_vendor() {
+            Some(vendor) if vendor != GraphicsMode::Integrated => vendor,
+            _ => return Ok(None),
+        };
+
+        let text = self.modprobe_config(vendor, Self::bonw15_hack()).render();
+        if fs::read_to_string(MODPROBE_PATH).map_or(false, |current| current == text) {
+            log::info!("{} is unchanged", MODPROBE_PATH);
+            return Ok(Some(InitramfsRebuild::skipped()));
+        }
+
+        log::info!("Updating {}", MODPROBE_PATH);
+        Self::write_modprobe(&text)?;
+        Self::update_initramfs().map(Some)
+    }
+
+    /// The mode the files of a switch configure, or `Custom` if they differ from those of every
+    /// mode, such as after being edited.
+    #[must_use]
+    pub fn configured_mode(&self, files: &ModeFiles) -> ConfiguredMode {
+        if *files == ModeFiles::default() {
+            return ConfiguredMode::Unset;
+        }
+
+        let bonw15_hack = Self::bonw15_hack();
+        let modes = [
+            GraphicsMode::Integrated,
+            GraphicsMode::Compute,
+            GraphicsMode::Hybrid,
+            GraphicsMode::Discrete,
+        ];
+
+        modes
+            .into_iter()
+            .find(|&mode| {
+                let prime_discrete = match mode {
+                    GraphicsMode::Hybrid => "on-demand",
+                    GraphicsMode::Discrete => "on",
+                    _ => "off",
+                };
+
+                files.prime_discrete.as_deref().map(str::trim) == Some(prime_discrete)
+                    && files.modprobe.as_deref()
+                        == Some(self.modprobe_config(mode, bonw15_hack).render().as_str())
+            })
+            .map_or(ConfiguredMode::Custom, ConfiguredMode::Mode)
+    }
+
+    /// The active and configured modes, the power state of the discrete GPU, and whether a
+    /// reboot is required for the configured mode to take effect.
+    pub fn status(&self) -> Result<GraphicsStatus, GraphicsDeviceError> {
+        let mode = self.get_vendor()?;
+        let configured = self.configured_mode(&ModeFiles::read());
+
+        Ok(GraphicsStatus {
+            mode:            <&'static str>::from(mode).to_owned(),
+            configured:      configured.to_string(),
+            power:           self.power_state().to_owned(),
+            reboot_required: matches!(configured, ConfiguredMode::Mode(next) if next != mode),
+        })
+    }
+
+    /// The mode written by the last switch, which may not be active until a reboot.
+    #[must_use]
+    pub fn get_configured_vendor() -> Option<GraphicsMode> {
+        let modprobe = fs::read_to_string(MODPROBE_PATH).ok()?;
+        if modprobe.lines().any(|line| line == "blacklist nvidia") {
+            return Some(GraphicsMode::Integrated);
+        }
+
+        match Self::get_prime_discrete().ok()?.as_str() {
+            "on-demand" => Some(GraphicsMode::Hybrid),
+            "off" => Some(GraphicsMode::Compute),
+            "on" => Some(GraphicsMode::Discrete),
+            _ => None,
+        }
+    }
+
+    fn check_gsp_support() -> Result<(), GraphicsDeviceError> {
+        let Some(version) = nvidia::kernel_module_version() else {
+            log::warn!("NVIDIA driver version is unknown, assuming GSP firmware can be toggled");
+            return Ok(());
+        };
+
+        let major = version.split('.').next().and_then(|major| major.parse::<u32>().ok());
+        match major {
+            Some(major) if major >= NVIDIA_GSP_FIRMWARE_MIN_VERSION => Ok(()),
+            _ => Err(GraphicsDeviceError::GspUnsupported(version)),
+        }
+    }
+
+    fn bonw15_hack() -> bool {
+        let dmi_vendor = fs::read_to_string("/sys/class/dmi/id/sys_vendor").unwrap_or_default();
+        let dmi_model = fs::read_to_string("/sys/class/dmi/id/product_version").unwrap_or_default();
+        matches!((dmi_vendor.trim(), dmi_model.trim()), ("System76", "bonw15" | "bonw15-b"))
+    }
+
+    fn modprobe_config(&self, vendor: GraphicsMode, bonw15_hack: bool) -> ModprobeConfig {
+        // XXX: Better way to check?
+        let s0ix = !bonw15_hack
+            && fs::read_to_string("/sys/power/mem_sleep").unwrap_or_default().contains("[s2idle]");
+
+        self.modprobe_config_for_sleep(vendor, bonw15_hack, s0ix)
+    }
+
+    /// The modprobe config of `vendor`, given whether the system suspends with S0ix rather
+    /// than S3.
+    pub(crate) fn modprobe_config_for_sleep(
+        &self,
+        vendor: GraphicsMode,
+        bonw15_hack: bool,
+        s0ix: bool,
+    ) -> ModprobeConfig {
+        let dynamic_power_management = if bonw15_hack {
+            "NVreg_DynamicPowerManagement=0x01"
+        } else {
+            "NVreg_DynamicPowerManagement=0x02"
+        };
+
+        let config = ModprobeConfig::new().comment(MODPROBE_HEADER);
+        let config = match vendor {
+            GraphicsMode::Integrated => {
+                return config
+                    .blacklist("i2c_nvidia_gpu")
+                    .blacklist("nouveau")
+                    .blacklist("nvidia")
+                    .blacklist("nvidia-drm")
+                    .blacklist("nvidia-modeset")
+                    .alias_off("i2c_nvidia_gpu")
+                    .alias_off("nouveau")
+                    .alias_off("nvidia")
+                    .alias_off("nvidia-drm")
+                    .alias_off("nvidia-modeset");
+            }
+            GraphicsMode::Compute => config
+                .blacklist("i2c_nvidia_gpu")
+                .blacklist("nvidia-drm")
+                .blacklist("nvidia-modeset")
+                .alias_off("i2c_nvidia_gpu")
+                .alias_off("nvidia-drm")
+                .alias_off("nvidia-modeset")
+                .option("nvidia", dynamic_power_management),
+            GraphicsMode::Hybrid => config
+                .blacklist("i2c_nvidia_gpu")
+                .alias_off("i2c_nvidia_gpu")
+                .option("nvidia", dynamic_power_management)
+                .option("nvidia-drm", "modeset=1"),
+            GraphicsMode::Discrete => config.option("nvidia-drm", "modeset=1"),
+        };
+
+        // Power management must be configured depending on if the system
+        // uses S0ix or S3 for suspend. Systems with the bonw15 hack cannot
+        // use either.
+        //
+        // We should also check if the GPU supports Video Memory Self
+        // Refresh, but that requires already being in hybrid or nvidia
+        // graphics mode. In compute mode, it just reports '?'.
+        let config = if bonw15_hack {
+            config
+        } else {
+            let sleep = if s0ix {
+                // Systems using S0ix must enable S0ix-based power management.
+                "NVreg_EnableS0ixPowerManagement=1"
+            } else {
+                // Systems using S3 had suspend issues with WebRender.
+                "NVreg_PreserveVideoMemoryAllocations=1"
+            };
+
+            config.comment("Preserve video memory through suspend").option("nvidia", sleep)
+        };
+
+        match self.config.gsp {
+            GspFirmware::Default => config,
+            GspFirmware::On => config.option("nvidia", &[NVIDIA_GSP_FIRMWARE_PARAM, "=1"].concat()),
+            GspFirmware::Off => {
+                config.option("nvidia", &[NVIDIA_GSP_FIRMWARE_PARAM, "=0"].concat())
+            }
+        }
+    }
+
+    fn write_modprobe(text: &str) -> Result<(), GraphicsDeviceError> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(MODPROBE_PATH)
+            .map_err(GraphicsDeviceError::ModprobeFileOpen)?;
+
+        file.write_all(text.as_bytes())
+            .and_then(|()| file.sync_all())
+            .map_err(GraphicsDeviceError::ModprobeFileWrite)
+    }
+
+    /// Whether the initramfs of the running kernel was built after the modprobe config was
+    /// last written.
+    pub fn initramfs_is_current() -> bool {
+        let Ok(release) = fs::read_to_string("/proc/sys/kernel/osrelease") else { return false };
+        let initramfs = format!("/boot/initramfs-{}.img", release.trim());
+
+        let modified = |path: &str| fs::metadata(path).and_then(|meta| meta.modified());
+        match (modified(&initramfs), modified(MODPROBE_PATH)) {
+            (Ok(initramfs), Ok(modprobe)) => initramfs > modprobe,
+            _ => false,
+        }
+    }
+
+    fn update_initramfs() -> Result<InitramfsRebuild, GraphicsDeviceError> {
+        log::info!(operation = "update_initramfs"; "Updating dracut");
+
+        let start = Instant::now();
+        let output = process::Command::new(UPDATE_DRACUT_CMD)
             .arg("--force")
-            .status()
+            .stdin(process::Stdio::null())
+            .output()
             .map_err(|why| GraphicsDeviceError::Command { cmd: UPDATE_DRACUT_CMD, why })?;
 
-        if !status.success() {
-            return Err(GraphicsDeviceError::UpdateDracut(status));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let rebuild = InitramfsRebuild {
+            tool:        UPDATE_DRACUT_CMD.into(),
+            skipped:     false,
+            success:     output.status.success(),
+            duration_ms: u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX),
+            stderr_tail: tail(stderr.trim_end(), STDERR_TAIL_LEN).to_owned(),
+        };
+
+        if !rebuild.success {
+            log::error!(
+                operation = "update_initramfs";
+                "{} failed with {}: {}", UPDATE_DRACUT_CMD, output.status, stderr
+            );
+            return Err(GraphicsDeviceError::UpdateDracut(rebuild));
         }
 
-        Ok(())
+        Ok(rebuild)
     }
 
     pub fn get_power(&self) -> Result<bool, GraphicsDeviceError> {
@@ -668,52 +1463,245 @@ impl Graphics {
         Ok(self.nvidia.iter().any(GraphicsDevice::exists))
     }
 
+    /// Power state of the discrete GPU: `active`, `suspended` by runtime power management,
+    /// `off` once removed from the bus, `rescan-needed` if powering it on failed to bring it
+    /// back, or empty if graphics cannot be switched.
+    #[must_use]
+    pub fn power_state(&self) -> &'static str {
+        if !self.can_switch() {
+            return "";
+        }
+
+        match self.nvidia.iter().find(|device| device.exists()) {
+            Some(device) if device.runtime_suspended() => "suspended",
+            Some(_) => "active",
+            None if self.power_on_failed.load(Ordering::SeqCst) => "rescan-needed",
+            None => "off",
+        }
+    }
+
+    /// The power state of the discrete GPU, with the functions present on the bus and its
+    /// power cap.
+    pub fn power_status(&self) -> Result<GraphicsPowerStatus, GraphicsDeviceError> {
+        self.switchable_or_fail()?;
+        Ok(GraphicsPowerStatus {
+            power:           self.power_state().to_owned(),
+            functions:       self.nvidia.iter().flat_map(GraphicsDevice::functions).collect(),
+            power_cap_watts: crate::dgpu_power_cap::active_cap().unwrap_or_default(),
+        })
+    }
+
     pub fn set_power(&self, power: bool) -> Result<(), GraphicsDeviceError> {
         self.switchable_or_fail()?;
 
         if power {
-            log::info!("Enabling graphics power");
-            self.bus.rescan().map_err(GraphicsDeviceError::Rescan)?;
+            log::info!(operation = "set_power", power = "on"; "Enabling graphics power");
+            let rescan = self.bus.rescan();
+            let present = rescan.is_ok() && self.nvidia.iter().any(GraphicsDevice::exists);
+            self.power_on_failed.store(!present, Ordering::SeqCst);
+            rescan.map_err(GraphicsDeviceError::Rescan)?;
 
             sysfs_power_control(self.nvidia[0].id.clone(), self.get_vendor()?);
+            Ok(())
         } else {
-            log::info!("Disabling graphics power");
+            self.power_off(false).map(|_| ())
+        }
+    }
 
-            // TODO: Don't allow turning off power if nvidia_drm modeset is enabled
+    /// Unbinds and removes the discrete GPU, refusing while nvidia-drm modeset is enabled or
+    /// processes use the GPU.
+    ///
+    /// With `force`, these checks are skipped, services keeping the driver open are stopped,
+    /// unbinding is retried, and the GPU is removed even if it remains bound. The actions
+    /// taken only because of `force` are returned, for the user to know what happened.
+    pub fn power_off(&self, force: bool) -> Result<Vec<String>, GraphicsDeviceError> {
+        self.switchable_or_fail()?;
 
-            unsafe {
-                // Unbind NVIDIA graphics devices and their functions
-                let unbinds = self.nvidia.iter().map(|dev| dev.unbind());
+        log::info!(operation = "set_power", power = "off"; "Disabling graphics power");
+        let mut forced = Vec::new();
 
-                // Remove NVIDIA graphics devices and their functions
-                let removes = self.nvidia.iter().map(|dev| dev.remove());
+        let modeset = fs::read_to_string(NVIDIA_DRM_MODESET)
+            .map_or(false, |modeset| matches!(modeset.trim(), "Y" | "1"));
+        if modeset {
+            if !force {
+                return Err(GraphicsDeviceError::ModesetEnabled);
+            }
+            forced.push("ignored that nvidia-drm modeset is enabled".to_owned());
+        }
 
-                unbinds.chain(removes).collect::<Result<_, _>>()?;
+        if force {
+            let users = nvidia::compute_users();
+            if !users.is_empty() {
+                let users = users.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+                forced.push(format!("powered off while in use by {}", users));
             }
+
+            forced.extend(Self::stop_blocking_services());
+        } else {
+            Self::check_compute_users()?;
         }
 
-        Ok(())
+        unsafe {
+            // Unbind NVIDIA graphics devices and their functions
+            for dev in &self.nvidia {
+                let mut attempt = 1;
+                while let Err(why) = dev.unbind() {
+                    if !force {
+                        return Err(why);
+                    } else if attempt == UNBIND_ATTEMPTS {
+                        log::warn!("{}: failed to unbind, removing anyway: {}", dev.id, why);
+                        break;
+                    }
+
+                    log::warn!("{}: retrying to unbind: {}", dev.id, why);
+                    forced.push(format!("retried unbinding {}", dev.id));
+                    thread::sleep(UNBIND_RETRY_DELAY);
+                    attempt += 1;
+                }
+            }
+
+            // Remove NVIDIA graphics devices and their functions
+            for dev in &self.nvidia {
+                if force && dev.bound() {
+                    forced.push(format!("removed {} while its driver was bound", dev.id));
+                }
+                dev.remove(force)?;
+            }
+        }
+
+        self.power_on_failed.store(false, Ordering::SeqCst);
+        Ok(forced)
     }
 
-    pub fn auto_power(&self) -> Result<(), GraphicsDeviceError> {
+    /// The writes and commands setting the power of the discrete GPU would perform, forced if
+    /// `force`, without performing them. `None` plans the power state of `auto_power`, which
+    /// is returned with the plan.
+    pub fn plan_power(
+        &self,
+        power: Option<bool>,
+        force: bool,
+    ) -> Result<(bool, Vec<PlannedAction>), GraphicsDeviceError> {
+        self.switchable_or_fail()?;
+
+        let power = match power {
+            Some(power) => power,
+            None => {
+                self.get_vendor()? != GraphicsMode::Integrated || self.gpu_supports_runtimepm()?
+            }
+        };
+
+        if power {
+            let value = if self.get_vendor()? == GraphicsMode::Discrete { "on" } else { "auto" };
+            let control = format!("/sys/bus/pci/devices/{}/power/control", self.nvidia[0].id);
+            return Ok((
+                true,
+                vec![
+                    Written::new("rescan", "/sys/bus/pci/rescan", 1).planned(),
+                    Written::new("control", control, value).planned(),
+                ],
+            ));
+        }
+
+        let modeset = fs::read_to_string(NVIDIA_DRM_MODESET)
+            .map_or(false, |modeset| matches!(modeset.trim(), "Y" | "1"));
+        if modeset && !force {
+            return Err(GraphicsDeviceError::ModesetEnabled);
+        }
+
+        let mut plan = Vec::new();
+        if force {
+            for service in Self::active_blocking_services() {
+                plan.push(planned_command(SYSTEMCTL_CMD, &["stop", service]));
+            }
+        } else {
+            Self::check_compute_users()?;
+        }
+
+        plan.extend(self.nvidia.iter().flat_map(GraphicsDevice::plan_removal));
+        Ok((false, plan))
+    }
+
+    /// Stops the services keeping the NVIDIA driver open, returning the actions taken.
+    fn stop_blocking_services() -> Vec<String> {
+        let mut stopped = Vec::new();
+
+        for service in Self::active_blocking_services() {
+            log::info!("Stopping {} to power off the NVIDIA GPU", service);
+            match process::Command::new(SYSTEMCTL_CMD).args(["stop", service]).status() {
+                Ok(status) if status.success() => stopped.push(format!("stopped {}", service)),
+                Ok(status) => log::warn!("systemctl stop {}: failed with {}", service, status),
+                Err(why) => log::warn!("systemctl stop {}: {}", service, why),
+            }
+        }
+
+        stopped
+    }
+
+    /// The services keeping the NVIDIA driver open which are running.
+    fn active_blocking_services() -> impl Iterator<Item = &'static str> {
+        BLOCKING_SERVICES.iter().copied().filter(|service| {
+            process::Command::new(SYSTEMCTL_CMD)
+                .args(["is-active", "--quiet", service])
+                .status()
+                .map_or(false, |status| status.success())
+        })
+    }
+
+    /// Powers the discrete GPU on or off depending on the graphics mode, returning the
+    /// power state that was applied.
+    pub fn auto_power(&self) -> Result<bool, GraphicsDeviceError> {
         // Only disable power if in integrated mode and the device does not
         // support runtime power management.
         let vendor = self.get_vendor()?;
         let power = vendor != GraphicsMode::Integrated || self.gpu_supports_runtimepm()?;
 
-        self.set_power(power)
+        self.set_power(power).map(|()| power)
     }
 
     fn switchable_or_fail(&self) -> Result<(), GraphicsDeviceError> {
-        if self.can_switch() {
-            Ok(())
-        } else {
-            Err(GraphicsDeviceError::NotSwitchable)
+        match self.not_switchable_reason() {
+            Some(reason) => Err(GraphicsDeviceError::NotSwitchable(reason)),
+            None => Ok(()),
         }
     }
 }
 
 // HACK
+/// Derives the mode recommended for a model from its vendor, whether its discrete GPU supports
+/// runtime power management, if known, and whether the model should not use hybrid graphics.
+fn default_graphics(vendor: &str, runtimepm: Option<bool>, blacklisted: bool) -> DefaultGraphics {
+    let (mode, reason) = if vendor != "System76" {
+        (GraphicsMode::Discrete, DefaultReason::VendorNotSystem76)
+    } else if blacklisted {
+        (GraphicsMode::Integrated, DefaultReason::ModelBlacklisted)
+    } else {
+        match runtimepm {
+            Some(true) => (GraphicsMode::Hybrid, DefaultReason::RuntimePmSupported),
+            Some(false) => (GraphicsMode::Integrated, DefaultReason::RuntimePmUnsupported),
+            None => (GraphicsMode::Integrated, DefaultReason::NoDriver),
+        }
+    };
+
+    DefaultGraphics { mode, reason }
+}
+
+/// Derives why graphics cannot be switched from the chassis and the GPUs found, if they cannot.
+fn not_switchable_reason(
+    desktop: bool,
+    discrete: bool,
+    integrated: bool,
+) -> Option<NotSwitchableReason> {
+    if desktop {
+        Some(NotSwitchableReason::Desktop)
+    } else if !discrete {
+        Some(NotSwitchableReason::NoDiscreteGpu)
+    } else if !integrated {
+        Some(NotSwitchableReason::NoIntegratedGpu)
+    } else {
+        None
+    }
+}
+
 // Normally, power/control would be set to "auto" by a udev rule in nvidia-drivers, but because
 // of a bug we cannot enable automatic power management too early after turning on the GPU.
 // Otherwise it will turn off before the NVIDIA driver finishes initializing, leaving the
@@ -738,3 +1726,53 @@ fn sysfs_power_control(pciid: String, mode: GraphicsMode) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stderr_tail() {
+        assert_eq!(tail("dracut: failed", 64), "dracut: failed");
+        assert_eq!(tail("first line\nsecond line\nthird", 16), "third");
+        assert_eq!(tail("ééé\nok", 5), "ok");
+    }
+
+    #[test]
+    fn default_reasons() {
+        let default = |vendor, runtimepm, blacklisted| {
+            let default = default_graphics(vendor, runtimepm, blacklisted);
+            (default.mode, default.reason.code())
+        };
+
+        assert_eq!(
+            default("Dell", Some(true), false),
+            (GraphicsMode::Discrete, "vendor-not-system76")
+        );
+        assert_eq!(
+            default("System76", Some(true), false),
+            (GraphicsMode::Hybrid, "runtimepm-supported")
+        );
+        assert_eq!(
+            default("System76", Some(true), true),
+            (GraphicsMode::Integrated, "model-blacklisted")
+        );
+        assert_eq!(
+            default("System76", Some(false), false),
+            (GraphicsMode::Integrated, "runtimepm-unsupported")
+        );
+        assert_eq!(default("System76", None, false), (GraphicsMode::Integrated, "no-driver"));
+    }
+
+    #[test]
+    fn not_switchable_reasons() {
+        let reason = |desktop, discrete, integrated| {
+            not_switchable_reason(desktop, discrete, integrated).map(NotSwitchableReason::code)
+        };
+
+        assert_eq!(reason(false, true, true), None);
+        assert_eq!(reason(true, true, true), Some("desktop"));
+        assert_eq!(reason(false, false, true), Some("no-discrete-gpu"));
+        assert_eq!(reason(false, true, false), Some("no-integrated-gpu"));
+    }
+}
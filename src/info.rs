@@ -0,0 +1,419 @@
+// Copyright 2024 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! `system76-power info` gathers what triaging an issue requires into one report. Each section
+//! is gathered on its own, so that a failing probe is reported in its section instead of
+//! failing the report.
+
+use std::{fmt::Display, fs, path::Path};
+
+use serde::Serialize;
+use system76_power_zbus::{
+    client::Client, ChargeThresholdsStatus, GraphicsCapabilities, GraphicsDeviceInfo,
+    GraphicsStatus, ProfileStatus,
+};
+
+use crate::{
+    charge_thresholds::{get_charge_profiles, get_charge_thresholds_status},
+    conflicts,
+    graphics::{
+        Graphics, LastSwitch, LAST_SWITCH_PATH, MODPROBE_HEADER, MODPROBE_PATH,
+        PRIME_DISCRETE_PATH, UPDATE_DRACUT_CMD,
+    },
+    util::find_command,
+};
+
+const DMI_PATH: &str = "/sys/class/dmi/id";
+const DMI_FILES: [&str; 6] =
+    ["sys_vendor", "product_name", "product_version", "board_vendor", "board_name", "bios_version"];
+
+const MODPROBE_DIR: &str = "/etc/modprobe.d";
+
+/// Services which also serve the power profiles or switch graphics, besides the power managers
+/// which also set parameters of the profiles.
+const CONFLICTING_SERVICES: &[(&str, &str)] = &[
+    ("tuned-ppd.service", "also serves the power profiles DBus interface"),
+    ("supergfxd.service", "also switches graphics"),
+    ("optimus-manager.service", "also switches graphics"),
+];
+
+/// A section of the report, or why it could not be gathered.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Section<T> {
+    Found(T),
+    Failed { error: String },
+}
+
+impl<T, E: Display> From<Result<T, E>> for Section<T> {
+    fn from(result: Result<T, E>) -> Self {
+        match result {
+            Ok(value) => Section::Found(value),
+            Err(why) => Section::Failed { error: why.to_string() },
+        }
+    }
+}
+
+/// The DMI identification of the machine. Empty strings are unreadable values.
+#[derive(Serialize)]
+pub struct Machine {
+    pub sys_vendor:      String,
+    pub product_name:    String,
+    pub product_version: String,
+    pub board_vendor:    String,
+    pub board_name:      String,
+    pub bios_version:    String,
+}
+
+/// The version of the running daemon, and the revision of its interface.
+#[derive(Serialize)]
+pub struct DaemonInfo {
+    pub version:     String,
+    pub api_version: u32,
+}
+
+/// A file a graphics switch writes, or `None` for the contents of a missing file.
+#[derive(Serialize)]
+pub struct ModeFile {
+    pub path:      &'static str,
+    pub contents:  Option<String>,
+    /// Whether the file carries the marker of files generated by system76-power.
+    pub generated: bool,
+}
+
+#[derive(Serialize)]
+pub struct Initramfs {
+    /// Command rebuilding the initramfs, such as `dracut`.
+    pub tool:    &'static str,
+    /// Path of the command, or `None` if it was not found.
+    pub path:    Option<String>,
+    /// Whether the initramfs of the running kernel was built after the modprobe configuration
+    /// was last written.
+    pub current: bool,
+}
+
+/// A service running, or a file present, which may conflict with the daemon.
+#[derive(Serialize)]
+pub struct Conflict {
+    /// Name of the service, or path of the file.
+    pub name:       String,
+    pub reason:     String,
+    /// Parameters of the profiles the service also sets.
+    pub parameters: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub client_version:    &'static str,
+    pub kernel:            Section<String>,
+    pub machine:           Section<Machine>,
+    pub daemon:            Section<DaemonInfo>,
+    pub gpus:              Section<Vec<GraphicsDeviceInfo>>,
+    pub graphics:          Section<GraphicsStatus>,
+    pub mode_files:        Vec<ModeFile>,
+    pub initramfs:         Initramfs,
+    pub drivers:           Section<GraphicsCapabilities>,
+    pub profile:           Section<ProfileStatus>,
+    pub charge_thresholds: Section<ChargeThresholdsStatus>,
+    pub conflicts:         Vec<Conflict>,
+    pub last_switch:       Section<Option<LastSwitch>>,
+}
+
+impl Report {
+    /// Gathers the report, asking the daemon where it is the source of the answer, or where
+    /// reading sysfs requires root. Without a daemon, those sections are read from sysfs.
+    pub async fn gather(client: Option<&Client<'_>>) -> Self {
+        fn unreachable<T>() -> Result<T, &'static str> { Err("the daemon is not running") }
+
+        let graphics = Graphics::without_rescan();
+
+        let (daemon, graphics_status, drivers, profile) = match client {
+            Some(client) => (
+                Section::from(
+                    client
+                        .version()
+                        .await
+                        .map(|(version, api_version)| DaemonInfo { version, api_version }),
+                ),
+                Section::from(client.graphics_status().await),
+                Section::from(client.graphics_capabilities().await),
+                Section::from(client.profile_status().await),
+            ),
+            None => (
+                Section::from(unreachable()),
+                Section::from(
+                    graphics
+                        .as_ref()
+                        .map_err(ToString::to_string)
+                        .and_then(|graphics| graphics.status().map_err(|why| why.to_string())),
+                ),
+                Section::from(graphics.as_ref().map(Graphics::capabilities)),
+                Section::from(unreachable()),
+            ),
+        };
+
+        Self {
+            client_version: env!("CARGO_PKG_VERSION"),
+            kernel: Section::from(
+                fs::read_to_string("/proc/sys/kernel/osrelease")
+                    .map(|release| release.trim().to_owned()),
+            ),
+            machine: Section::from(machine()),
+            daemon,
+            gpus: Section::from(Graphics::devices()),
+            graphics: graphics_status,
+            mode_files: mode_files(),
+            initramfs: Initramfs {
+                tool:    UPDATE_DRACUT_CMD,
+                path:    find_command(UPDATE_DRACUT_CMD).map(|path| path.display().to_string()),
+                current: Graphics::initramfs_is_current(),
+            },
+            drivers,
+            profile,
+            charge_thresholds: Section::from(get_charge_thresholds_status(&get_charge_profiles())),
+            conflicts: conflicts(client.is_some()),
+            last_switch: Section::from(last_switch()),
+        }
+    }
+
+    /// Prints the report as text, a heading per section.
+    pub fn print(&self) {
+        println!("system76-power {}", self.client_version);
+        print_section("Kernel", &self.kernel, |release| println!("  {}", release));
+
+        print_section("Machine", &self.machine, |machine| {
+            println!("  Vendor: {}", machine.sys_vendor);
+            println!("  Product: {} ({})", machine.product_name, machine.product_version);
+            println!("  Board: {} {}", machine.board_vendor, machine.board_name);
+            println!("  BIOS: {}", machine.bios_version);
+        });
+
+        print_section("Daemon", &self.daemon, |daemon| {
+            println!("  {} (interface revision {})", daemon.version, daemon.api_version);
+        });
+
+        print_section("GPUs", &self.gpus, |devices| {
+            for device in devices {
+                let driver = if device.driver.is_empty() { "none" } else { &device.driver };
+                println!(
+                    "  {}: {:04x}:{:04x} {} {}, driver {}, power {}",
+                    device.bus_id,
+                    device.vendor_id,
+                    device.device_id,
+                    device.kind,
+                    device.name,
+                    driver,
+                    device.power_state
+                );
+            }
+        });
+
+        print_section("Graphics", &self.graphics, |status| {
+            println!("  Mode: {}", status.mode);
+            println!("  Configured: {}", status.configured);
+            println!("  Power: {}", status.power);
+            println!("  Reboot required: {}", status.reboot_required);
+        });
+
+        println!("Mode files");
+        for file in &self.mode_files {
+            match &file.contents {
+                Some(contents) => {
+                    let marker = if file.generated { "generated" } else { "not generated" };
+                    println!("  {} ({}):", file.path, marker);
+                    for line in contents.lines() {
+                        println!("    {}", line);
+                    }
+                }
+                None => println!("  {}: missing", file.path),
+            }
+        }
+
+        println!("Initramfs");
+        match &self.initramfs.path {
+            Some(path) => println!("  Tool: {}", path),
+            None => println!("  Tool: {} not found", self.initramfs.tool),
+        }
+        println!("  Current: {}", self.initramfs.current);
+
+        print_section("Drivers", &self.drivers, |capabilities| {
+            let unknown = |value: &str| if value.is_empty() { "unknown" } else { value }.to_owned();
+            println!("  Kernel module: {}", unknown(&capabilities.kernel_driver_version));
+            println!("  Userspace: {}", unknown(&capabilities.userspace_driver_version));
+            println!("  GSP firmware: {}", capabilities.gsp_firmware);
+            for (kernel, module) in &capabilities.kernel_modules {
+                if module.module_present {
+                    println!(
+                        "  {}: {} ({})",
+                        kernel,
+                        unknown(&module.module_version),
+                        unknown(&module.source)
+                    );
+                } else {
+                    println!("  {}: no module", kernel);
+                }
+            }
+        });
+
+        print_section("Profile", &self.profile, |status| {
+            println!("  {}", status.profile);
+            for parameter in &status.parameters {
+                if parameter.intended.is_empty() {
+                    println!("  {}: not applicable", parameter.path);
+                    continue;
+                }
+
+                let differs = if parameter.matches { "" } else { ", differs" };
+                println!(
+                    "  {}: {} (now {}{})",
+                    parameter.path, parameter.intended, parameter.current, differs
+                );
+            }
+        });
+
+        print_section("Charge thresholds", &self.charge_thresholds, |status| {
+            if !status.supported {
+                println!("  not supported");
+            }
+            for (battery, thresholds) in &status.batteries {
+                if thresholds.start_supported {
+                    println!("  {}: {}-{}", battery, thresholds.start, thresholds.end);
+                } else {
+                    println!(
+                        "  {}: end {}, start not supported by hardware",
+                        battery, thresholds.end
+                    );
+                }
+            }
+        });
+
+        println!("Conflicts");
+        if self.conflicts.is_empty() {
+            println!("  none found");
+        }
+        for conflict in &self.conflicts {
+            println!("  {}: {}", conflict.name, conflict.reason);
+            if !conflict.parameters.is_empty() {
+                println!("    also sets {}", conflict.parameters.join(", "));
+            }
+        }
+
+        print_section("Last switch", &self.last_switch, |last| match last {
+            Some(last) => {
+                let outcome = if last.success { "succeeded" } else { "failed" };
+                println!("  {} {} at {}: {}", last.mode, outcome, last.time, last.message);
+            }
+            None => println!("  none recorded"),
+        });
+    }
+}
+
+fn print_section<T>(title: &str, section: &Section<T>, print: impl FnOnce(&T)) {
+    println!("{}", title);
+    match section {
+        Section::Found(value) => print(value),
+        Section::Failed { error } => println!("  unavailable: {}", error),
+    }
+}
+
+fn machine() -> Result<Machine, String> {
+    if !Path::new(DMI_PATH).exists() {
+        return Err(format!("{} does not exist", DMI_PATH));
+    }
+
+    let [sys_vendor, product_name, product_version, board_vendor, board_name, bios_version] =
+        DMI_FILES.map(|file| {
+            fs::read_to_string(Path::new(DMI_PATH).join(file))
+                .map(|value| value.trim().to_owned())
+                .unwrap_or_default()
+        });
+
+    Ok(Machine {
+        sys_vendor,
+        product_name,
+        product_version,
+        board_vendor,
+        board_name,
+        bios_version,
+    })
+}
+
+fn mode_files() -> Vec<ModeFile> {
+    [PRIME_DISCRETE_PATH, MODPROBE_PATH]
+        .into_iter()
+        .map(|path| {
+            let contents = fs::read_to_string(path).ok();
+            let generated =
+                contents.as_deref().map_or(false, |text| text.contains(MODPROBE_HEADER));
+            ModeFile { path, contents, generated }
+        })
+        .collect()
+}
+
+/// The conflicting services which are running, and the NVIDIA modprobe configuration written
+/// by other tools, such as a blacklist of the driver. power-profiles-daemon is left out while
+/// the daemon runs, as it then serves the bus names of power-profiles-daemon.
+fn conflicts(daemon_running: bool) -> Vec<Conflict> {
+    let mut conflicts = conflicts::detect(daemon_running)
+        .into_iter()
+        .map(|manager| Conflict {
+            name:       manager.service.to_owned(),
+            reason:     manager.reason.to_owned(),
+            parameters: manager.parameters.to_vec(),
+        })
+        .collect::<Vec<_>>();
+
+    conflicts.extend(
+        CONFLICTING_SERVICES.iter().filter(|(service, _)| conflicts::active(service)).map(
+            |(service, reason)| Conflict {
+                name:       (*service).to_owned(),
+                reason:     (*reason).to_owned(),
+                parameters: Vec::new(),
+            },
+        ),
+    );
+
+    let Ok(entries) = fs::read_dir(MODPROBE_DIR) else { return conflicts };
+    let mut files = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.to_str() != Some(MODPROBE_PATH))
+        .filter(|path| path.extension().map_or(false, |extension| extension == "conf"))
+        .filter(|path| fs::read_to_string(path).map_or(false, |text| text.contains("nvidia")))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    conflicts.extend(files.into_iter().map(|path| Conflict {
+        name:       path.display().to_string(),
+        reason:     "also configures the NVIDIA driver modules".to_owned(),
+        parameters: Vec::new(),
+    }));
+
+    conflicts
+}
+
+/// The last graphics switch, or `None` if none was recorded.
+fn last_switch() -> Result<Option<LastSwitch>, String> {
+    match LastSwitch::load() {
+        Some(last) => Ok(Some(last)),
+        None if Path::new(LAST_SWITCH_PATH).exists() => {
+            Err(format!("{} cannot be read", LAST_SWITCH_PATH))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_sections_carry_their_error() {
+        let found = Section::from(Ok::<_, String>(42));
+        assert_eq!(serde_json::to_string(&found).unwrap(), "42");
+
+        let failed = Section::<u32>::from(Err("permission denied"));
+        assert_eq!(serde_json::to_string(&failed).unwrap(), r#"{"error":"permission denied"}"#);
+    }
+}
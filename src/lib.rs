@@ -14,24 +14,44 @@
 
 pub mod acpi_platform;
 pub mod args;
+pub mod boost;
 pub mod charge_thresholds;
 pub mod client;
+pub mod config;
+pub mod conflicts;
 pub mod cpufreq;
 pub mod daemon;
+pub mod dgpu_power_cap;
+pub mod drm;
 pub mod errors;
 pub mod fan;
 pub mod graphics;
 pub mod hid_backlight;
 pub mod hotplug;
+pub mod info;
 pub mod kernel_parameters;
 pub mod logging;
+pub mod mode_files;
 pub mod modprobe;
 pub mod module;
+pub mod nvidia;
 pub mod pci;
+pub mod pcie_aspm;
+pub mod plain;
+pub mod power_draw;
+pub mod power_supply;
 pub mod radeon;
 pub mod runtime_pm;
+pub mod sd_notify;
 pub mod snd;
+pub mod state;
 pub mod sys_devices;
+pub mod sysctl;
+#[cfg(test)]
+mod testing;
+pub mod tunables;
+pub mod uevent;
+pub mod usb_autosuspend;
 pub mod util;
 pub mod wifi;
 
@@ -39,9 +59,4 @@ pub static DBUS_NAME: &str = "com.system76.PowerDaemon";
 pub static DBUS_PATH: &str = "/com/system76/PowerDaemon";
 pub static DBUS_IFACE: &str = "com.system76.PowerDaemon";
 
-#[derive(Copy, Clone, Debug)]
-pub enum Profile {
-    Battery,
-    Balanced,
-    Performance,
-}
+pub use system76_power_zbus::Profile;
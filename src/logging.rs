@@ -2,18 +2,285 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+//! Logs go to the journal when the daemon is started by systemd, with the level as the
+//! priority and the key-values of a record, such as `operation = "set_vendor"`, as fields.
+//! Otherwise, they are printed to stderr.
+
 use fern::{Dispatch, InitError};
-use log::LevelFilter;
-use std::io;
+use log::{kv, Level, LevelFilter, Log, Metadata, Record};
+use std::{env, io, os::unix::net::UnixDatagram, str::FromStr};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+const SYSLOG_IDENTIFIER: &str = "system76-power";
+
+/// Targets of the logs of this crate, and of the client calls of `system76_power_zbus`.
+const OWN_TARGETS: [&str; 2] = [env!("CARGO_CRATE_NAME"), "system76_power_zbus"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+    #[error("invalid log level '{}' in '{}'", level, directive)]
+    Level { directive: String, level: String },
+    #[error("empty target in '{}'", _0)]
+    Target(String),
+}
+
+/// Levels of the logs to keep, per target, as in `info,zbus=debug`. A level without a
+/// target applies to the logs of this crate and of the client calls of `system76_power_zbus`,
+/// and the logs of other crates are dropped unless their target is given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    #[must_use]
+    pub fn new(level: LevelFilter) -> Self {
+        Self { targets: OWN_TARGETS.iter().map(|target| ((*target).to_owned(), level)).collect() }
+    }
+
+    /// The level of the most specific target matching `target`.
+    #[must_use]
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(name, _)| {
+                target
+                    .strip_prefix(name.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map_or(LevelFilter::Off, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, level)| *level).max().unwrap_or(LevelFilter::Off)
+    }
+}
+
+impl From<LevelFilter> for Filter {
+    fn from(level: LevelFilter) -> Self { Self::new(level) }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
 
-pub fn setup(filter: LevelFilter) -> Result<(), InitError> {
-    Dispatch::new()
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let mut parsed = Self::new(LevelFilter::Info);
+
+        for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (targets, level) = match directive.split_once('=') {
+                Some((target, level)) => (vec![target.trim()], level.trim()),
+                None => (OWN_TARGETS.to_vec(), directive),
+            };
+
+            if targets.iter().any(|target| target.is_empty()) {
+                return Err(FilterError::Target(directive.to_owned()));
+            }
+
+            let level = level.parse::<LevelFilter>().map_err(|_| FilterError::Level {
+                directive: directive.to_owned(),
+                level:     level.to_owned(),
+            })?;
+
+            for target in targets {
+                parsed.targets.retain(|(name, _)| name != target);
+                parsed.targets.push((target.to_owned(), level));
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+pub fn setup(filter: impl Into<Filter>) -> Result<(), InitError> {
+    let filter = filter.into();
+    if journal_stream() {
+        if let Ok(journal) = Journal::connect(filter.clone()) {
+            log::set_max_level(filter.max_level());
+            log::set_boxed_logger(Box::new(journal))?;
+            return Ok(());
+        }
+    }
+
+    filter
+        .targets
+        .iter()
         // Exclude logs for crates that we use
-        .level(LevelFilter::Off)
-        // Include only the logs for this binary
-        .level_for("system76_power", filter)
+        .fold(Dispatch::new().level(LevelFilter::Off), |dispatch, (target, level)| {
+            dispatch.level_for(target.clone(), *level)
+        })
         .format(|out, message, record| out.finish(format_args!("[{}] {}", record.level(), message)))
         .chain(io::stderr())
         .apply()?;
     Ok(())
 }
+
+/// Whether stderr is connected to the journal, as systemd announces in `$JOURNAL_STREAM`.
+fn journal_stream() -> bool {
+    let Ok(stream) = env::var("JOURNAL_STREAM") else { return false };
+    let Some((device, inode)) = stream.split_once(':') else { return false };
+
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(libc::STDERR_FILENO, stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    let stat = unsafe { stat.assume_init() };
+
+    device.parse() == Ok(stat.st_dev) && inode.parse() == Ok(stat.st_ino)
+}
+
+/// Writes records to the journal with its native protocol, described in systemd-journald(8).
+struct Journal {
+    socket: UnixDatagram,
+    filter: Filter,
+}
+
+impl Journal {
+    fn connect(filter: Filter) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET)?;
+        Ok(Self { socket, filter })
+    }
+}
+
+impl Log for Journal {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Records too large for a datagram are printed instead.
+        if self.socket.send(&journal_entry(record)).is_err() {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Priority of a level, as in syslog(3).
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+fn journal_entry(record: &Record) -> Vec<u8> {
+    struct Fields<'a>(&'a mut Vec<u8>);
+
+    impl<'kvs> kv::VisitSource<'kvs> for Fields<'_> {
+        fn visit_pair(
+            &mut self,
+            key: kv::Key<'kvs>,
+            value: kv::Value<'kvs>,
+        ) -> Result<(), kv::Error> {
+            if let Some(name) = field_name(key.as_str()) {
+                add_field(self.0, &name, &value.to_string());
+            }
+            Ok(())
+        }
+    }
+
+    let mut entry = Vec::new();
+    add_field(&mut entry, "MESSAGE", &record.args().to_string());
+    add_field(&mut entry, "PRIORITY", &priority(record.level()).to_string());
+    add_field(&mut entry, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+    add_field(&mut entry, "TARGET", record.target());
+
+    if let Some(file) = record.file() {
+        add_field(&mut entry, "CODE_FILE", file);
+    }
+
+    if let Some(line) = record.line() {
+        add_field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+
+    let _res = record.key_values().visit(&mut Fields(&mut entry));
+    entry
+}
+
+/// The name of the field of a key, such as `OPERATION` for `operation`.
+///
+/// Names consist of uppercase letters, digits and underscores, and may not start with an
+/// underscore, which is reserved for fields set by the journal.
+fn field_name(key: &str) -> Option<String> {
+    let name = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect::<String>();
+
+    let name = name.trim_start_matches(|c: char| c == '_' || c.is_ascii_digit());
+    (!name.is_empty()).then(|| name.to_owned())
+}
+
+fn add_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+
+    // Values spanning lines are preceded by their length instead of `=`.
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        let filter = "debug,zbus=warn".parse::<Filter>().unwrap();
+        assert_eq!(filter.level("system76_power"), LevelFilter::Debug);
+        assert_eq!(filter.level("system76_power::graphics"), LevelFilter::Debug);
+        assert_eq!(filter.level("system76_power_zbus::client"), LevelFilter::Debug);
+        assert_eq!(filter.level("zbus::connection"), LevelFilter::Warn);
+        assert_eq!(filter.level("zbusx"), LevelFilter::Off);
+        assert_eq!(filter.level("tokio"), LevelFilter::Off);
+
+        let filter = "system76_power::graphics=trace".parse::<Filter>().unwrap();
+        assert_eq!(filter.level("system76_power::graphics"), LevelFilter::Trace);
+        assert_eq!(filter.level("system76_power::daemon"), LevelFilter::Info);
+
+        assert_eq!("".parse::<Filter>().unwrap(), Filter::new(LevelFilter::Info));
+        assert!("loud".parse::<Filter>().is_err());
+        assert!("=debug".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn journal_fields() {
+        let kvs = [("operation", "set_vendor"), ("mode", "hybrid")];
+        let entry = journal_entry(
+            &Record::builder()
+                .args(format_args!("Switching\ngraphics"))
+                .level(Level::Warn)
+                .target("system76_power::graphics")
+                .key_values(&kvs)
+                .build(),
+        );
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&18u64.to_le_bytes());
+        expected.extend_from_slice(
+            b"Switching\ngraphics\nPRIORITY=4\nSYSLOG_IDENTIFIER=system76-power\n\
+              TARGET=system76_power::graphics\nOPERATION=set_vendor\nMODE=hybrid\n",
+        );
+        assert_eq!(entry, expected);
+
+        assert_eq!(field_name("device-id").as_deref(), Some("DEVICE_ID"));
+        assert_eq!(field_name("_pid").as_deref(), Some("PID"));
+        assert_eq!(field_name("__"), None);
+    }
+}
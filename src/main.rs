@@ -5,40 +5,58 @@
 #![deny(clippy::all)]
 
 use clap::Parser;
-use log::LevelFilter;
-use std::process;
-use system76_power::{args::Args, client, daemon, logging};
+use std::{io, process};
+use system76_power::{
+    args::{self, Args, Command},
+    client::{self, ClientError, ExitCode},
+    daemon, logging,
+};
 
 fn main() {
-    let args = Args::parse();
+    let args = match Args::try_parse().and_then(Args::validate) {
+        Ok(args) => args,
+        Err(why) if why.kind() == clap::error::ErrorKind::DisplayVersion => {
+            print!("{}", why);
+            client::daemon_version();
+            return;
+        }
+        Err(why) => why.exit(),
+    };
 
-    let res = match args {
-        Args::Daemon { quiet, verbose } => {
-            if let Err(why) = logging::setup(if verbose {
-                LevelFilter::Debug
-            } else if quiet {
-                LevelFilter::Off
+    if let Err(why) = logging::setup(args.filter()) {
+        eprintln!("failed to set up logging: {}", why);
+        process::exit(1);
+    }
+
+    let format = args.format();
+    let quiet = args.quiet;
+    let res = match args.command {
+        Command::Daemon { check: true, .. } => daemon::check(),
+        Command::Daemon { no_restore, .. } => {
+            if unsafe { libc::geteuid() } == 0 {
+                daemon::daemon(!no_restore)
             } else {
-                LevelFilter::Info
-            }) {
-                eprintln!("failed to set up logging: {}", why);
-                process::exit(1);
+                Err(ClientError::failure(ExitCode::PermissionDenied, "must be run as root"))
             }
-
+        }
+        Command::Apply => {
             if unsafe { libc::geteuid() } == 0 {
-                daemon::daemon()
+                daemon::apply()
             } else {
-                Err(anyhow::anyhow!("must be run as root"))
+                Err(ClientError::failure(ExitCode::PermissionDenied, "must be run as root"))
             }
         }
+        Command::Completions { shell } => {
+            args::completions(shell, &mut io::stdout());
+            Ok(())
+        }
+        Command::Mangen { ref dir } => args::man_pages(dir).map_err(|why| {
+            anyhow::anyhow!("failed to write man pages to {}: {}", dir.display(), why)
+        }),
         _ => client::client(&args),
     };
 
-    match res {
-        Ok(()) => (),
-        Err(err) => {
-            eprintln!("{:?}", err);
-            process::exit(1);
-        }
+    if let Err(why) = res {
+        process::exit(client::print_error(&why, format, quiet));
     }
 }
@@ -0,0 +1,152 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The files a graphics switch writes, which other tools or users may also edit.
+
+use inotify::{Inotify, WatchMask};
+use std::{fs, io, path::Path};
+
+use crate::graphics::{MODPROBE_PATH, PRIME_DISCRETE_PATH};
+
+// Lines of each side shown by a summary of differences.
+const SUMMARY_LINES: usize = 3;
+
+/// Contents of the files, or `None` for those which do not exist.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModeFiles {
+    pub prime_discrete: Option<String>,
+    pub modprobe:       Option<String>,
+}
+
+impl ModeFiles {
+    #[must_use]
+    pub fn read() -> Self {
+        Self {
+            prime_discrete: fs::read_to_string(PRIME_DISCRETE_PATH).ok(),
+            modprobe:       fs::read_to_string(MODPROBE_PATH).ok(),
+        }
+    }
+
+    /// Writes the files back, removing those which did not exist.
+    pub fn restore(&self) -> io::Result<()> {
+        for (path, contents) in self.files() {
+            match contents {
+                Some(contents) => fs::write(path, contents)?,
+                None => match fs::remove_file(path) {
+                    Err(why) if why.kind() != io::ErrorKind::NotFound => return Err(why),
+                    _ => (),
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Summary of the changes made in `newer`, such as
+    /// `/etc/prime-discrete: -"on-demand" +"off"`.
+    #[must_use]
+    pub fn diff(&self, newer: &Self) -> String {
+        self.files()
+            .into_iter()
+            .zip(newer.files())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((path, old), (_, new))| match (old, new) {
+                (None, _) => format!("{}: created", path),
+                (_, None) => format!("{}: removed", path),
+                (Some(old), Some(new)) => format!("{}: {}", path, diff_lines(old, new)),
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// Watches the directories of the files, as editors may replace them rather than write
+    /// to them.
+    pub fn watch() -> io::Result<Inotify> {
+        let inotify = Inotify::init()?;
+        let mut watches = inotify.watches();
+
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_TO
+            | WatchMask::MOVED_FROM
+            | WatchMask::CREATE
+            | WatchMask::DELETE;
+
+        for path in [PRIME_DISCRETE_PATH, MODPROBE_PATH] {
+            if let Some(dir) = Path::new(path).parent() {
+                watches.add(dir, mask)?;
+            }
+        }
+
+        Ok(inotify)
+    }
+
+    /// Whether an entry of a watched directory is one of the files.
+    #[must_use]
+    pub fn is_watched(name: &std::ffi::OsStr) -> bool {
+        [PRIME_DISCRETE_PATH, MODPROBE_PATH]
+            .iter()
+            .any(|path| Path::new(path).file_name() == Some(name))
+    }
+
+    fn files(&self) -> [(&'static str, &Option<String>); 2] {
+        [(PRIME_DISCRETE_PATH, &self.prime_discrete), (MODPROBE_PATH, &self.modprobe)]
+    }
+}
+
+/// Lines removed and added, such as `-"blacklist nvidia" +"options nvidia …"`.
+fn diff_lines(old: &str, new: &str) -> String {
+    let removed = old.lines().filter(|line| !new.lines().any(|new| new == *line));
+    let added = new.lines().filter(|line| !old.lines().any(|old| old == *line));
+
+    let mut summary = Vec::new();
+    for (sign, lines) in [('-', removed.collect::<Vec<_>>()), ('+', added.collect::<Vec<_>>())] {
+        summary.extend(lines.iter().take(SUMMARY_LINES).map(|line| format!("{}{:?}", sign, line)));
+        if lines.len() > SUMMARY_LINES {
+            summary.push(format!("{}{} more", sign, lines.len() - SUMMARY_LINES));
+        }
+    }
+
+    if summary.is_empty() {
+        return "whitespace changed".into();
+    }
+
+    summary.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let old = ModeFiles {
+            prime_discrete: Some("on-demand\n".into()),
+            modprobe:       Some("options nvidia a=1\noptions nvidia b=2\n".into()),
+        };
+
+        assert_eq!(old.diff(&old), "");
+
+        let new = ModeFiles { prime_discrete: Some("off\n".into()), ..old.clone() };
+        assert_eq!(old.diff(&new), r#"/etc/prime-discrete: -"on-demand" +"off""#);
+
+        let new = ModeFiles {
+            modprobe: Some("options nvidia a=1\n\nb\nc\nd\ne\n".into()),
+            ..old.clone()
+        };
+        assert_eq!(
+            old.diff(&new),
+            r#"/etc/modprobe.d/system76-power.conf: -"options nvidia b=2" +"" +"b" +"c" +2 more"#
+        );
+
+        let new = ModeFiles { modprobe: None, ..old.clone() };
+        assert_eq!(old.diff(&new), "/etc/modprobe.d/system76-power.conf: removed");
+        assert_eq!(new.diff(&old), "/etc/modprobe.d/system76-power.conf: created");
+    }
+
+    #[test]
+    fn watched_names() {
+        assert!(ModeFiles::is_watched("prime-discrete".as_ref()));
+        assert!(ModeFiles::is_watched("system76-power.conf".as_ref()));
+        assert!(!ModeFiles::is_watched("fstab".as_ref()));
+    }
+}
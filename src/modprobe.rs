@@ -29,3 +29,157 @@ pub fn load(module: &str, options: &[&str]) -> io::Result<()> {
         }
     })
 }
+
+/// Builds the content of a modprobe.d configuration file, line by line.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModprobeConfig {
+    lines: Vec<String>,
+}
+
+impl ModprobeConfig {
+    #[must_use]
+    pub fn new() -> Self { Self::default() }
+
+    #[must_use]
+    pub fn comment(mut self, text: &str) -> Self {
+        self.lines.push(["# ", text].concat());
+        self
+    }
+
+    #[must_use]
+    pub fn blacklist(mut self, module: &str) -> Self {
+        self.lines.push(["blacklist ", module].concat());
+        self
+    }
+
+    /// Prevents the module from being loaded by its alias.
+    #[must_use]
+    pub fn alias_off(mut self, module: &str) -> Self {
+        self.lines.push(["alias ", module, " off"].concat());
+        self
+    }
+
+    #[must_use]
+    pub fn option(mut self, module: &str, option: &str) -> Self {
+        self.lines.push(["options ", module, " ", option].concat());
+        self
+    }
+
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphics::{Graphics, GraphicsMode, GspFirmware};
+
+    const INTEGRATED: &str = "\
+# Automatically generated by system76-power
+blacklist i2c_nvidia_gpu
+blacklist nouveau
+blacklist nvidia
+blacklist nvidia-drm
+blacklist nvidia-modeset
+alias i2c_nvidia_gpu off
+alias nouveau off
+alias nvidia off
+alias nvidia-drm off
+alias nvidia-modeset off
+";
+
+    const COMPUTE: &str = "\
+# Automatically generated by system76-power
+blacklist i2c_nvidia_gpu
+blacklist nvidia-drm
+blacklist nvidia-modeset
+alias i2c_nvidia_gpu off
+alias nvidia-drm off
+alias nvidia-modeset off
+options nvidia NVreg_DynamicPowerManagement=0x02
+# Preserve video memory through suspend
+options nvidia NVreg_EnableS0ixPowerManagement=1
+";
+
+    const HYBRID: &str = "\
+# Automatically generated by system76-power
+blacklist i2c_nvidia_gpu
+alias i2c_nvidia_gpu off
+options nvidia NVreg_DynamicPowerManagement=0x02
+options nvidia-drm modeset=1
+# Preserve video memory through suspend
+options nvidia NVreg_EnableS0ixPowerManagement=1
+";
+
+    const DISCRETE: &str = "\
+# Automatically generated by system76-power
+options nvidia-drm modeset=1
+# Preserve video memory through suspend
+options nvidia NVreg_EnableS0ixPowerManagement=1
+";
+
+    fn render(mode: GraphicsMode, gsp: GspFirmware, bonw15_hack: bool, s0ix: bool) -> String {
+        let mut graphics = Graphics::stub();
+        graphics.config.gsp = gsp;
+        graphics.modprobe_config_for_sleep(mode, bonw15_hack, s0ix).render()
+    }
+
+    #[test]
+    fn graphics_modes() {
+        for (mode, expected) in [
+            (GraphicsMode::Integrated, INTEGRATED),
+            (GraphicsMode::Compute, COMPUTE),
+            (GraphicsMode::Hybrid, HYBRID),
+            (GraphicsMode::Discrete, DISCRETE),
+        ] {
+            assert_eq!(render(mode, GspFirmware::Default, false, true), expected, "{}", mode);
+
+            // The NVIDIA driver is not loaded in integrated mode, so GSP is left alone there.
+            for (gsp, line) in [
+                (GspFirmware::On, "options nvidia NVreg_EnableGpuFirmware=1\n"),
+                (GspFirmware::Off, "options nvidia NVreg_EnableGpuFirmware=0\n"),
+            ] {
+                let expected = match mode {
+                    GraphicsMode::Integrated => expected.to_owned(),
+                    _ => [expected, line].concat(),
+                };
+                assert_eq!(render(mode, gsp, false, true), expected, "{} with GSP {}", mode, gsp);
+            }
+        }
+    }
+
+    #[test]
+    fn sleep() {
+        assert_eq!(
+            render(GraphicsMode::Hybrid, GspFirmware::Off, false, false),
+            "\
+# Automatically generated by system76-power
+blacklist i2c_nvidia_gpu
+alias i2c_nvidia_gpu off
+options nvidia NVreg_DynamicPowerManagement=0x02
+options nvidia-drm modeset=1
+# Preserve video memory through suspend
+options nvidia NVreg_PreserveVideoMemoryAllocations=1
+options nvidia NVreg_EnableGpuFirmware=0
+"
+        );
+
+        // Systems with the bonw15 hack configure neither S0ix nor S3.
+        assert_eq!(
+            render(GraphicsMode::Hybrid, GspFirmware::Default, true, true),
+            "\
+# Automatically generated by system76-power
+blacklist i2c_nvidia_gpu
+alias i2c_nvidia_gpu off
+options nvidia NVreg_DynamicPowerManagement=0x01
+options nvidia-drm modeset=1
+"
+        );
+    }
+}
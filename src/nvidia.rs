@@ -0,0 +1,305 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Probes the versions of the installed NVIDIA driver components.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const LIBNVIDIA_ML: &str = "libnvidia-ml.so.";
+
+const MODULES_DIR: &str = "/lib/modules";
+
+// Directories of a kernel's module tree that out-of-tree modules are installed to.
+const MODULE_SUBDIRS: &[&str] = &["extra", "updates", "weak-updates"];
+
+// Opened by CUDA and OpenCL, but not by display servers or clients merely probing the driver.
+const NVIDIA_UVM_DEVICE: &str = "/dev/nvidia-uvm";
+
+/// Versions of the NVIDIA kernel module and userspace libraries, when installed.
+#[derive(Clone, Debug, Default)]
+pub struct DriverVersions {
+    pub kernel:    Option<String>,
+    pub userspace: Option<String>,
+}
+
+impl DriverVersions {
+    #[must_use]
+    pub fn probe() -> Self {
+        Self { kernel: kernel_module_version(), userspace: userspace_version() }
+    }
+
+    /// Returns the kernel and userspace versions if both are known and differ.
+    ///
+    /// Systems without either half of the stack (compute-only, nouveau) never mismatch.
+    #[must_use]
+    pub fn mismatch(&self) -> Option<(&str, &str)> {
+        match (self.kernel.as_deref(), self.userspace.as_deref()) {
+            (Some(kernel), Some(userspace)) if kernel != userspace => Some((kernel, userspace)),
+            _ => None,
+        }
+    }
+}
+
+/// Version of the NVIDIA kernel module, preferring the loaded module over the one on disk.
+#[must_use]
+pub fn kernel_module_version() -> Option<String> {
+    if let Ok(version) = fs::read_to_string("/sys/module/nvidia/version") {
+        return Some(version.trim().to_owned());
+    }
+
+    command_stdout("modinfo", &["-F", "version", "nvidia"]).and_then(first_line)
+}
+
+/// Version of the NVIDIA userspace libraries.
+///
+/// `nvidia-smi` is asked first. If it cannot run, which is what happens after a partial update,
+/// the version is taken from the file name of the `libnvidia-ml` library known to the linker.
+#[must_use]
+pub fn userspace_version() -> Option<String> {
+    command_stdout("nvidia-smi", &["--query-gpu=driver_version", "--format=csv,noheader"])
+        .and_then(first_line)
+        .or_else(libnvidia_ml_version)
+}
+
+fn libnvidia_ml_version() -> Option<String> {
+    let libraries = command_stdout("ldconfig", &["-p"])?;
+    libraries
+        .lines()
+        .filter_map(|line| line.split(" => ").nth(1))
+        .filter(|path| path.contains(LIBNVIDIA_ML))
+        .filter_map(|path| fs::canonicalize(path.trim()).ok())
+        .find_map(|path| soname_version(&path))
+}
+
+fn soname_version(path: &Path) -> Option<String> {
+    let version = path.file_name()?.to_str()?.strip_prefix(LIBNVIDIA_ML)?;
+
+    // The soname link only carries the ABI number, such as `libnvidia-ml.so.1`.
+    if version.contains('.') {
+        Some(version.to_owned())
+    } else {
+        None
+    }
+}
+
+/// How the NVIDIA kernel module of a kernel was provided.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModuleSource {
+    Akmod,
+    Builtin,
+    Dkms,
+    WeakUpdate,
+}
+
+impl fmt::Display for ModuleSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ModuleSource::Akmod => "akmod",
+            ModuleSource::Builtin => "builtin",
+            ModuleSource::Dkms => "dkms",
+            ModuleSource::WeakUpdate => "weak-update",
+        })
+    }
+}
+
+/// The NVIDIA kernel module built for an installed kernel.
+#[derive(Clone, Debug, Default)]
+pub struct KernelModule {
+    pub present: bool,
+    pub version: Option<String>,
+    pub source:  Option<ModuleSource>,
+}
+
+/// NVIDIA kernel modules of every kernel in `/lib/modules`, keyed by kernel version.
+///
+/// Files which cannot be read leave the version or source of that kernel unknown.
+#[must_use]
+pub fn kernel_modules() -> BTreeMap<String, KernelModule> {
+    let Ok(kernels) = fs::read_dir(MODULES_DIR) else { return BTreeMap::new() };
+    let dkms = dkms_modules();
+
+    kernels
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .map(|kernel| {
+            let module = kernel_module(&kernel, dkms.get(&kernel));
+            (kernel, module)
+        })
+        .collect()
+}
+
+fn kernel_module(kernel: &str, dkms_version: Option<&String>) -> KernelModule {
+    let tree = Path::new(MODULES_DIR).join(kernel);
+
+    if let Some(path) = MODULE_SUBDIRS.iter().find_map(|dir| find_nvidia_ko(&tree.join(dir))) {
+        let relative = path.strip_prefix(&tree).unwrap_or(&path);
+        let source = if relative.starts_with("weak-updates") {
+            ModuleSource::WeakUpdate
+        } else if dkms_version.is_some() || relative.starts_with("updates/dkms") {
+            ModuleSource::Dkms
+        } else {
+            ModuleSource::Akmod
+        };
+
+        let version = command_stdout("modinfo", &["-F", "version", &path.to_string_lossy()])
+            .and_then(first_line)
+            .or_else(|| dkms_version.cloned());
+
+        return KernelModule { present: true, version, source: Some(source) };
+    }
+
+    let builtin = fs::read_to_string(tree.join("modules.builtin")).map_or(false, |builtin| {
+        builtin.lines().any(|line| line.rsplit('/').next() == Some("nvidia.ko"))
+    });
+
+    if builtin {
+        return KernelModule { present: true, version: None, source: Some(ModuleSource::Builtin) };
+    }
+
+    KernelModule::default()
+}
+
+fn find_nvidia_ko(dir: &Path) -> Option<PathBuf> {
+    for entry in fs::read_dir(dir).ok()?.filter_map(Result::ok) {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else { continue };
+
+        if file_type.is_dir() {
+            if let Some(found) = find_nvidia_ko(&path) {
+                return Some(found);
+            }
+        } else if entry.file_name().to_str().map_or(false, is_nvidia_ko) {
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+/// Matches `nvidia.ko` with any compression suffix, but not `nvidia-drm.ko` and friends.
+fn is_nvidia_ko(name: &str) -> bool {
+    name == "nvidia.ko" || name.strip_prefix("nvidia.ko.").is_some()
+}
+
+/// Kernels with an installed NVIDIA module according to `dkms status`, with its version.
+fn dkms_modules() -> BTreeMap<String, String> {
+    let Some(status) = command_stdout("dkms", &["status", "nvidia"]) else {
+        return BTreeMap::new();
+    };
+
+    status.lines().filter_map(parse_dkms_status).collect()
+}
+
+/// Parses lines such as `nvidia/550.78, 6.8.9-300.fc40.x86_64, x86_64: installed`, or
+/// `nvidia, 550.78, 6.8.9-300.fc40.x86_64, x86_64: installed` from older dkms releases.
+fn parse_dkms_status(line: &str) -> Option<(String, String)> {
+    let (fields, state) = line.rsplit_once(": ")?;
+    if !state.trim().starts_with("installed") {
+        return None;
+    }
+
+    let mut fields = fields.split(", ");
+    let version = match fields.next()? {
+        "nvidia" => fields.next()?,
+        module => module.strip_prefix("nvidia/")?,
+    };
+    let kernel = fields.next()?;
+
+    Some((kernel.to_owned(), version.to_owned()))
+}
+
+/// A process with an NVIDIA device node open.
+#[derive(Clone, Debug)]
+pub struct DeviceUser {
+    pub pid:  u32,
+    pub name: String,
+}
+
+impl fmt::Display for DeviceUser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.pid)
+    }
+}
+
+/// Processes running compute workloads on the NVIDIA GPU.
+///
+/// Only the file descriptor links and `comm` of each process are read, neither of which waits on
+/// the process itself, so a hung process cannot stall the scan.
+#[must_use]
+pub fn compute_users() -> Vec<DeviceUser> {
+    let Ok(procs) = fs::read_dir("/proc") else { return Vec::new() };
+
+    procs
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|&pid| holds_device(pid, NVIDIA_UVM_DEVICE))
+        .map(|pid| {
+            let name = fs::read_to_string(format!("/proc/{}/comm", pid))
+                .map(|name| name.trim().to_owned())
+                .unwrap_or_default();
+            DeviceUser { pid, name }
+        })
+        .collect()
+}
+
+fn holds_device(pid: u32, device: &str) -> bool {
+    let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", pid)) else { return false };
+
+    fds.filter_map(Result::ok)
+        .filter_map(|fd| fs::read_link(fd.path()).ok())
+        .any(|target| target == Path::new(device))
+}
+
+fn command_stdout(cmd: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(cmd)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|why| log::debug!("failed to run {}: {}", cmd, why))
+        .ok()?;
+
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        log::debug!("{} failed with {}", cmd, output.status);
+        None
+    }
+}
+
+fn first_line(output: String) -> Option<String> {
+    output.lines().map(str::trim).find(|line| !line.is_empty()).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dkms_status() {
+        assert_eq!(
+            parse_dkms_status("nvidia/550.78, 6.8.9-300.fc40.x86_64, x86_64: installed"),
+            Some(("6.8.9-300.fc40.x86_64".to_owned(), "550.78".to_owned()))
+        );
+        assert_eq!(
+            parse_dkms_status("nvidia, 470.239.06, 5.15.0-105-generic, x86_64: installed"),
+            Some(("5.15.0-105-generic".to_owned(), "470.239.06".to_owned()))
+        );
+        assert_eq!(parse_dkms_status("nvidia/550.78, 6.9.1-100.fc40.x86_64, x86_64: built"), None);
+        assert_eq!(parse_dkms_status("nvidia/550.78: added"), None);
+    }
+
+    #[test]
+    fn nvidia_ko() {
+        assert!(is_nvidia_ko("nvidia.ko"));
+        assert!(is_nvidia_ko("nvidia.ko.xz"));
+        assert!(!is_nvidia_ko("nvidia-drm.ko.xz"));
+        assert!(!is_nvidia_ko("nvidia_uvm.ko"));
+    }
+}
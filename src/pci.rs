@@ -2,8 +2,16 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::{fs::write, io, path::PathBuf};
+use std::{
+    fs::{self, write},
+    io,
+    path::PathBuf,
+};
 
+// Locations of the PCI ID database, from the hwdata and pciutils packages.
+const PCI_IDS_PATHS: &[&str] = &["/usr/share/hwdata/pci.ids", "/usr/share/misc/pci.ids"];
+
+#[derive(Clone)]
 pub struct PciBus {
     path: PathBuf,
 }
@@ -18,5 +26,67 @@ impl PciBus {
         }
     }
 
+    /// A bus which is never touched, for tests.
+    #[cfg(test)]
+    pub(crate) fn stub() -> Self { Self { path: PathBuf::new() } }
+
     pub fn rescan(&self) -> io::Result<()> { write(self.path.join("rescan"), "1") }
 }
+
+/// Marketing name of a device, such as `GeForce RTX 4060 Max-Q / Mobile`, from the PCI ID
+/// database.
+#[must_use]
+pub fn device_name(vendor: u16, device: u16) -> Option<String> {
+    PCI_IDS_PATHS
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .and_then(|ids| find_device_name(&ids, vendor, device))
+}
+
+/// Device lines follow their vendor line, indented by a tab. Names of chips often carry
+/// the product name in brackets, such as `AD107M [GeForce RTX 4060 Max-Q / Mobile]`.
+fn find_device_name(ids: &str, vendor: u16, device: u16) -> Option<String> {
+    let vendor = format!("{:04x}  ", vendor);
+    let device = format!("\t{:04x}  ", device);
+
+    let name = ids
+        .lines()
+        .skip_while(|line| !line.starts_with(&vendor))
+        .skip(1)
+        .take_while(|line| line.starts_with('\t') || line.starts_with('#'))
+        .find_map(|line| line.strip_prefix(&device))?;
+
+    let product = name.rsplit_once('[').and_then(|(_, product)| product.strip_suffix(']'));
+    Some(product.unwrap_or(name).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDS: &str = "\
+# List of PCI ID's
+8086  Intel Corporation
+\t0a7c  Haswell-ULT DRAM Controller
+\t\t17aa 2214  ThinkPad X240
+\ta7a0  Raptor Lake-P [Iris Xe Graphics]
+10de  NVIDIA Corporation
+\t2882  AD107 [GeForce RTX 4060]
+\t28e0  AD107M [GeForce RTX 4060 Max-Q / Mobile]
+\t28e1  AD107M
+10df  Emulex Corporation
+\t28e1  Unrelated
+";
+
+    #[test]
+    fn device_names() {
+        assert_eq!(find_device_name(IDS, 0x8086, 0xa7a0).as_deref(), Some("Iris Xe Graphics"));
+        assert_eq!(
+            find_device_name(IDS, 0x10de, 0x28e0).as_deref(),
+            Some("GeForce RTX 4060 Max-Q / Mobile")
+        );
+        assert_eq!(find_device_name(IDS, 0x10de, 0x28e1).as_deref(), Some("AD107M"));
+        assert_eq!(find_device_name(IDS, 0x10de, 0x2214), None);
+        assert_eq!(find_device_name(IDS, 0x1002, 0x1638), None);
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The PCIe Active State Power Management policy of the kernel, which the firmware may lock, as
+//! when it keeps control of ASPM, or the kernel is booted with `pcie_aspm=off`.
+
+use crate::{util::Written, Profile};
+use std::{fs, io, path::Path};
+
+pub const SYSFS_PATH: &str = "/sys/module/pcie_aspm/parameters/policy";
+
+/// The policies the kernel accepts, such as `powersupersave`, which older kernels lack.
+fn choices_in(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .split_ascii_whitespace()
+        .map(|choice| choice.trim_start_matches('[').trim_end_matches(']'))
+}
+
+/// The policy selected in the contents of the policy file, such as
+/// `default performance [powersave] powersupersave`.
+fn selected_in(contents: &str) -> Option<&str> {
+    let (_, rest) = contents.split_once('[')?;
+    rest.split_once(']').map(|(choice, _)| choice)
+}
+
+/// The policy in use, or `None` if the kernel lacks ASPM.
+#[must_use]
+pub fn current() -> Option<String> {
+    selected_in(&fs::read_to_string(SYSFS_PATH).ok()?).map(str::to_owned)
+}
+
+/// The policy of a profile, unless overridden: the deepest power saving on battery.
+#[must_use]
+pub fn builtin(profile: Profile) -> &'static str {
+    match profile {
+        Profile::Battery => "powersupersave",
+        Profile::Quiet => "default",
+        Profile::Balanced => "default",
+        Profile::Performance => "performance",
+    }
+}
+
+/// Whether the kernel accepts `policy`. The file is read-only without write permission, while
+/// a locked policy is only refused once written, as told by [`locked`].
+#[must_use]
+pub fn supported(policy: &str) -> bool { supported_in(Path::new(SYSFS_PATH), policy) }
+
+fn supported_in(path: &Path, policy: &str) -> bool {
+    let writable = fs::metadata(path).map_or(false, |meta| !meta.permissions().readonly());
+    writable
+        && fs::read_to_string(path)
+            .map_or(false, |contents| choices_in(&contents).any(|c| c == policy))
+}
+
+/// The policy, as written.
+#[must_use]
+pub fn plan(policy: &str) -> Written { Written::new("pcie_aspm_policy", SYSFS_PATH, policy) }
+
+/// Whether a write of the policy was refused because the firmware or the kernel keeps control
+/// of ASPM, rather than failing.
+#[must_use]
+pub fn locked(why: &io::Error) -> bool {
+    matches!(why.raw_os_error(), Some(libc::EPERM | libc::EACCES | libc::EROFS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn policies() {
+        let contents = "default performance [powersave] powersupersave\n";
+        assert_eq!(selected_in(contents), Some("powersave"));
+        assert_eq!(
+            choices_in(contents).collect::<Vec<_>>(),
+            ["default", "performance", "powersave", "powersupersave"]
+        );
+        assert_eq!(selected_in("default performance powersave\n"), None);
+    }
+
+    #[test]
+    fn read_only() {
+        let dir = TempDir::new("aspm");
+        let path = dir.join("policy");
+        fs::write(&path, "[default] performance powersave\n").unwrap();
+
+        assert!(supported_in(&path, "performance"));
+        assert!(!supported_in(&path, "powersupersave"));
+        assert!(!supported_in(&dir.join("missing"), "default"));
+
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+        assert!(!supported_in(&path, "performance"));
+
+        assert!(locked(&io::Error::from_raw_os_error(libc::EPERM)));
+        assert!(!locked(&io::Error::from_raw_os_error(libc::EINVAL)));
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Output of `--format plain`: the JSON output flattened to `KEY=VALUE` pairs, for `eval` in
+//! shell scripts.
+//!
+//! Keys are the JSON field names in upper case, joined with `_` when nested, such as
+//! `BATTERIES_BAT0_START`. Elements of arrays are numbered from 0, after a `_COUNT` key with
+//! their number. Booleans are `1` or `0`, and values are quoted for the shell when needed.
+//! Fields come in alphabetical order, and elements in their own.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// The pairs of a value, one per line.
+pub fn to_string(value: &impl Serialize) -> serde_json::Result<String> {
+    Ok(pairs(value)?.join("\n"))
+}
+
+/// The pairs of a value on a single line, such as an event of a stream.
+pub fn to_line(value: &impl Serialize) -> serde_json::Result<String> { Ok(pairs(value)?.join(" ")) }
+
+fn pairs(value: &impl Serialize) -> serde_json::Result<Vec<String>> {
+    let mut pairs = Vec::new();
+    flatten(&mut pairs, String::new(), &serde_json::to_value(value)?);
+    Ok(pairs)
+}
+
+fn flatten(pairs: &mut Vec<String>, key: String, value: &Value) {
+    let join = |name: &str| {
+        let name: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        if key.is_empty() {
+            name
+        } else {
+            format!("{}_{}", key, name)
+        }
+    };
+
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                flatten(pairs, join(name), value);
+            }
+        }
+        Value::Array(elements) => {
+            pairs.push(format!("{}={}", join("count"), elements.len()));
+            for (index, value) in elements.iter().enumerate() {
+                flatten(pairs, join(&index.to_string()), value);
+            }
+        }
+        _ => {
+            let key = if key.is_empty() { "VALUE".to_owned() } else { key };
+            pairs.push(format!("{}={}", key, scalar(value)));
+        }
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Bool(true) => "1".into(),
+        Value::Bool(false) => "0".into(),
+        Value::Number(number) => number.to_string(),
+        Value::String(string) => quote(string),
+        _ => String::new(),
+    }
+}
+
+/// Quotes a string for the shell, unless it holds only characters which need no quoting.
+fn quote(string: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-.,:/@%+".contains(c);
+    if !string.is_empty() && string.chars().all(plain) {
+        return string.to_owned();
+    }
+
+    format!("'{}'", string.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn flattened() {
+        let value = json!({
+            "mode": "hybrid",
+            "reboot_required": true,
+            "start": 40,
+            "vendor": "",
+            "batteries": {"BAT0": {"start": 40, "end": 80}},
+            "actions": ["stop thermald.service", "it's"],
+            "devices": [{"bus-id": "0000:01:00.0"}],
+        });
+
+        let expected = [
+            "ACTIONS_COUNT=2",
+            "ACTIONS_0='stop thermald.service'",
+            "ACTIONS_1='it'\\''s'",
+            "BATTERIES_BAT0_END=80",
+            "BATTERIES_BAT0_START=40",
+            "DEVICES_COUNT=1",
+            "DEVICES_0_BUS_ID=0000:01:00.0",
+            "MODE=hybrid",
+            "REBOOT_REQUIRED=1",
+            "START=40",
+            "VENDOR=''",
+        ];
+        assert_eq!(to_string(&value).unwrap(), expected.join("\n"));
+        let switch = json!({"old": "hybrid", "new": "integrated"});
+        assert_eq!(to_line(&switch).unwrap(), "NEW=integrated OLD=hybrid");
+        assert_eq!(to_string(&"on").unwrap(), "VALUE=on");
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Estimates of the power drawn by the system: by the CPU package, from the difference of two
+//! readings of its energy counter, through RAPL or the hwmon of amd_energy, and from the
+//! batteries while discharging. Estimates which cannot be made are left out.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::power_supply;
+
+const RAPL_PATH: &str = "/sys/class/powercap/intel-rapl:0";
+const HWMON_PATH: &str = "/sys/class/hwmon";
+
+/// Time between the two readings of an estimate.
+pub const INTERVAL: Duration = Duration::from_millis(500);
+
+/// Key of the power drawn by the CPU package, in watts.
+pub const PACKAGE: &str = "package_watts";
+
+/// Key of the power the batteries discharge at, in watts.
+pub const BATTERY: &str = "battery_watts";
+
+/// The energy counter of the CPU package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Counter {
+    /// File counting the energy, in microjoules.
+    path:     PathBuf,
+    /// Value at which the counter wraps around to 0, if known.
+    range_uj: Option<u64>,
+}
+
+impl Counter {
+    /// The counter of RAPL, which AMD CPUs also provide with recent kernels, or else that of
+    /// the first socket of amd_energy.
+    fn detect() -> Option<Self> {
+        Self::rapl(Path::new(RAPL_PATH)).or_else(|| Self::amd_energy(Path::new(HWMON_PATH)))
+    }
+
+    fn rapl(zone: &Path) -> Option<Self> {
+        let path = zone.join("energy_uj");
+        let range_uj = read(&zone.join("max_energy_range_uj"));
+        path.exists().then_some(Self { path, range_uj })
+    }
+
+    fn amd_energy(hwmons: &Path) -> Option<Self> {
+        let hwmon =
+            fs::read_dir(hwmons).ok()?.filter_map(Result::ok).map(|entry| entry.path()).find(
+                |hwmon| {
+                    fs::read_to_string(hwmon.join("name"))
+                        .map_or(false, |name| name.trim() == "amd_energy")
+                },
+            )?;
+
+        // The sockets follow the cores, labelled `Esocket0` onwards.
+        (1..=1024)
+            .map_while(|index| {
+                let label =
+                    fs::read_to_string(hwmon.join(format!("energy{}_label", index))).ok()?;
+                Some((index, label))
+            })
+            .find(|(_, label)| label.trim() == "Esocket0")
+            .map(|(index, _)| Self {
+                path:     hwmon.join(format!("energy{}_input", index)),
+                range_uj: None,
+            })
+    }
+}
+
+/// A reading of the energy counter of the CPU package, and of the discharge of the batteries.
+#[derive(Clone, Debug)]
+pub struct Sample {
+    counter:       Option<Counter>,
+    energy_uj:     Option<u64>,
+    battery_watts: Option<f64>,
+    time:          Instant,
+}
+
+impl Sample {
+    #[must_use]
+    pub fn read() -> Self {
+        let counter = Counter::detect();
+        let energy_uj = counter.as_ref().and_then(|counter| read(&counter.path));
+        Self {
+            counter,
+            energy_uj,
+            battery_watts: power_supply::discharge_watts(),
+            time: Instant::now(),
+        }
+    }
+
+    /// The estimates from this reading to a later one, by key, in watts. The discharge of the
+    /// batteries is averaged over both readings.
+    #[must_use]
+    pub fn estimate(&self, later: &Self) -> BTreeMap<&'static str, f64> {
+        let mut watts = BTreeMap::new();
+
+        let secs = later.time.duration_since(self.time).as_secs_f64();
+        let range_uj = self.counter.as_ref().and_then(|counter| counter.range_uj);
+        if let Some((first, second)) = self.energy_uj.zip(later.energy_uj) {
+            if let Some(delta) = energy_delta(first, second, range_uj).filter(|_| secs > 0.0) {
+                watts.insert(PACKAGE, delta as f64 / 1e6 / secs);
+            }
+        }
+
+        let battery = match (self.battery_watts, later.battery_watts) {
+            (Some(first), Some(second)) => Some((first + second) / 2.0),
+            (first, second) => first.or(second),
+        };
+        if let Some(battery) = battery {
+            watts.insert(BATTERY, battery);
+        }
+
+        watts
+    }
+}
+
+/// Estimates the power drawn over [`INTERVAL`], blocking meanwhile.
+#[must_use]
+pub fn estimate() -> BTreeMap<&'static str, f64> {
+    let first = Sample::read();
+    std::thread::sleep(INTERVAL);
+    first.estimate(&Sample::read())
+}
+
+/// The energy counted between two readings, in microjoules, across a wraparound of the counter
+/// if its range is known.
+fn energy_delta(first: u64, second: u64, range_uj: Option<u64>) -> Option<u64> {
+    match second.checked_sub(first) {
+        Some(delta) => Some(delta),
+        None => range_uj?.checked_sub(first)?.checked_add(second),
+    }
+}
+
+fn read(path: &Path) -> Option<u64> { fs::read_to_string(path).ok()?.trim().parse().ok() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn wraparound() {
+        assert_eq!(energy_delta(1_000, 3_500, Some(10_000)), Some(2_500));
+        assert_eq!(energy_delta(9_000, 500, Some(10_000)), Some(1_500));
+        assert_eq!(energy_delta(9_000, 500, None), None);
+        assert_eq!(energy_delta(20_000, 500, Some(10_000)), None);
+    }
+
+    #[test]
+    fn estimates() {
+        let root = TempDir::new("draw");
+        let write = |path: &str, value: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        write("intel-rapl:0/energy_uj", "1000\n");
+        write("intel-rapl:0/max_energy_range_uj", "262143328850\n");
+        let rapl = Counter::rapl(&root.join("intel-rapl:0")).unwrap();
+        assert_eq!(rapl.range_uj, Some(262_143_328_850));
+        assert_eq!(Counter::rapl(&root.join("missing")), None);
+
+        write("hwmon/hwmon3/name", "amd_energy\n");
+        write("hwmon/hwmon3/energy1_label", "Ecore000\n");
+        write("hwmon/hwmon3/energy2_label", "Esocket0\n");
+        let amd = Counter::amd_energy(&root.join("hwmon")).unwrap();
+        assert_eq!(amd, Counter { path: root.join("hwmon/hwmon3/energy2_input"), range_uj: None });
+
+        // 5 J over half a second across a wraparound, while discharging at 10 then 12 W.
+        let time = Instant::now();
+        let sample = |energy_uj, battery_watts, time| Sample {
+            counter: Some(Counter { path: PathBuf::new(), range_uj: Some(10_000_000) }),
+            energy_uj: Some(energy_uj),
+            battery_watts: Some(battery_watts),
+            time,
+        };
+        let first = sample(8_000_000, 10.0, time);
+        let second = sample(3_000_000, 12.0, time + INTERVAL);
+        let estimate = first.estimate(&second);
+        assert_eq!(estimate.get(PACKAGE), Some(&10.0));
+        assert_eq!(estimate.get(BATTERY), Some(&11.0));
+
+        // Missing readings leave the estimates out.
+        let missing = Sample { counter: None, energy_uj: None, battery_watts: None, time };
+        assert!(missing.estimate(&Sample { time: time + INTERVAL, ..missing.clone() }).is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The power source of the system, and its batteries.
+
+use crate::charge_thresholds::{threshold_files, ThresholdFiles};
+use std::{fs, path::Path};
+use system76_power_zbus::BatteryStatus;
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Whether the system runs on external power, or `None` if it has no AC adapter.
+#[must_use]
+pub fn on_ac() -> Option<bool> {
+    let supplies = fs::read_dir(POWER_SUPPLY_DIR).ok()?;
+    let mut on_ac = None;
+
+    for supply in supplies.filter_map(Result::ok) {
+        let path = supply.path();
+        if !is_system_adapter(&path) {
+            continue;
+        }
+
+        let online = read(&path, "online").map_or(false, |online| online == "1");
+        on_ac = Some(on_ac.unwrap_or(false) || online);
+    }
+
+    on_ac
+}
+
+/// The charge of the system batteries, in percent of their capacity, and whether any of them
+/// is discharging, or `None` without a battery.
+#[must_use]
+pub fn battery() -> Option<(u8, bool)> { battery_in(Path::new(POWER_SUPPLY_DIR)) }
+
+fn battery_in(root: &Path) -> Option<(u8, bool)> {
+    let (mut total, mut count, mut discharging) = (0u32, 0u32, false);
+    for supply in fs::read_dir(root).ok()?.filter_map(Result::ok) {
+        let path = supply.path();
+        if read(&path, "type").as_deref() != Some("Battery")
+            || read(&path, "scope").as_deref() == Some("Device")
+        {
+            continue;
+        }
+
+        if let Some(capacity) = read(&path, "capacity").and_then(|c| c.parse::<u32>().ok()) {
+            total += capacity.min(100);
+            count += 1;
+            discharging |= read(&path, "status").as_deref() == Some("Discharging");
+        }
+    }
+
+    let percent = u8::try_from(total.checked_div(count)?).ok()?;
+    Some((percent, discharging))
+}
+
+/// The power the system batteries discharge at, in watts, from their `power_now`, or their
+/// `current_now` and `voltage_now`, or `None` if none is discharging or reports it.
+#[must_use]
+pub fn discharge_watts() -> Option<f64> { discharge_watts_in(Path::new(POWER_SUPPLY_DIR)) }
+
+fn discharge_watts_in(root: &Path) -> Option<f64> {
+    let mut watts = None;
+    for supply in fs::read_dir(root).ok()?.filter_map(Result::ok) {
+        let path = supply.path();
+        if read(&path, "type").as_deref() != Some("Battery")
+            || read(&path, "scope").as_deref() == Some("Device")
+            || read(&path, "status").as_deref() != Some("Discharging")
+        {
+            continue;
+        }
+
+        let Some(power) = microwatts(&path) else { continue };
+        *watts.get_or_insert(0.0) += power / 1e6;
+    }
+
+    watts
+}
+
+/// The power a battery charges or discharges at, in microwatts, from its `power_now`, or its
+/// `current_now` and `voltage_now`.
+fn microwatts(battery: &Path) -> Option<f64> {
+    let micro = |attribute: &str| read(battery, attribute)?.parse::<u64>().ok();
+    match micro("power_now") {
+        Some(microwatts) => Some(microwatts as f64),
+        None => {
+            let (current, voltage) = micro("current_now").zip(micro("voltage_now"))?;
+            Some(current as f64 * voltage as f64 / 1e6)
+        }
+    }
+}
+
+/// The system batteries, and UPSes, sorted by name. AC adapters and the batteries of
+/// peripherals are left out.
+#[must_use]
+pub fn batteries() -> Vec<BatteryStatus> { batteries_in(Path::new(POWER_SUPPLY_DIR)) }
+
+fn batteries_in(root: &Path) -> Vec<BatteryStatus> {
+    let Ok(supplies) = fs::read_dir(root) else { return Vec::new() };
+
+    let mut batteries = supplies
+        .filter_map(Result::ok)
+        .map(|supply| supply.path())
+        .filter(|path| {
+            matches!(read(path, "type").as_deref(), Some("Battery" | "UPS"))
+                && read(path, "scope").as_deref() != Some("Device")
+        })
+        .map(|path| battery_status(&path))
+        .collect::<Vec<_>>();
+
+    batteries.sort_by(|a, b| a.name.cmp(&b.name));
+    batteries
+}
+
+/// The attributes of a battery, with -1 for the numbers which cannot be read.
+fn battery_status(battery: &Path) -> BatteryStatus {
+    let text = |attribute: &str| read(battery, attribute).unwrap_or_default();
+    let number = |attribute: &str| read(battery, attribute)?.parse::<i64>().ok();
+    let known = |value: Option<i64>| value.and_then(|value| i32::try_from(value).ok());
+
+    // In mWh, from µWh, or from the charge in µAh at the design voltage.
+    let energy = |kind: &str| match number(&format!("energy_{}", kind)) {
+        Some(microwatt_hours) => Some(microwatt_hours / 1000),
+        None => {
+            let charge = number(&format!("charge_{}", kind))?;
+            Some(charge * number("voltage_min_design")? / 1_000_000_000)
+        }
+    };
+    let (full, design) = (energy("full"), energy("full_design"));
+    let files = threshold_files(battery);
+    let threshold = |file: fn(&ThresholdFiles) -> &'static str| {
+        files.and_then(|files| known(number(file(files)))).unwrap_or(-1)
+    };
+    let health = full
+        .zip(design)
+        .filter(|&(_, design)| design > 0)
+        .map(|(full, design)| (full * 100 + design / 2) / design);
+
+    BatteryStatus {
+        name:                   battery
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        manufacturer:           text("manufacturer"),
+        model:                  text("model_name"),
+        status:                 text("status"),
+        capacity:               known(number("capacity")).unwrap_or(-1),
+        cycle_count:            known(number("cycle_count")).unwrap_or(-1),
+        energy_full_mwh:        known(full).unwrap_or(-1),
+        energy_full_design_mwh: known(design).unwrap_or(-1),
+        health:                 known(health).unwrap_or(-1),
+        power_mw:               microwatts(battery)
+            .map_or(-1, |microwatts| (microwatts / 1000.0).round() as i32),
+        start_threshold:        threshold(|files| files.start),
+        end_threshold:          threshold(|files| files.end),
+    }
+}
+
+/// AC adapters and USB-C chargers, but not the batteries of peripherals.
+fn is_system_adapter(supply: &Path) -> bool {
+    matches!(read(supply, "type").as_deref(), Some("Mains" | "USB"))
+        && read(supply, "scope").as_deref() != Some("Device")
+}
+
+fn read(supply: &Path, attribute: &str) -> Option<String> {
+    fs::read_to_string(supply.join(attribute)).ok().map(|value| value.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn batteries() {
+        let root = TempDir::new("supply");
+        let write = |path: &str, value: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        // Two system batteries, and the battery of a mouse, which is not counted.
+        write("BAT0/type", "Battery\n");
+        write("BAT0/capacity", "30\n");
+        write("BAT0/status", "Discharging\n");
+        write("BAT1/type", "Battery\n");
+        write("BAT1/capacity", "10\n");
+        write("BAT1/status", "Not charging\n");
+        write("hidpp_battery_0/type", "Battery\n");
+        write("hidpp_battery_0/scope", "Device\n");
+        write("hidpp_battery_0/capacity", "90\n");
+        write("AC/type", "Mains\n");
+        assert_eq!(battery_in(&root), Some((20, true)));
+
+        write("BAT0/status", "Charging\n");
+        assert_eq!(battery_in(&root), Some((20, false)));
+
+        assert_eq!(battery_in(&root.join("AC")), None);
+    }
+
+    #[test]
+    fn discharge() {
+        let root = TempDir::new("discharge");
+        let write = |path: &str, value: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        // Batteries report the power, or the current and the voltage.
+        write("BAT0/type", "Battery\n");
+        write("BAT0/status", "Charging\n");
+        write("BAT0/power_now", "20000000\n");
+        write("BAT1/type", "Battery\n");
+        write("BAT1/status", "Discharging\n");
+        write("BAT1/current_now", "500000\n");
+        write("BAT1/voltage_now", "12000000\n");
+        assert_eq!(discharge_watts_in(&root), Some(6.0));
+
+        write("BAT0/status", "Discharging\n");
+        assert_eq!(discharge_watts_in(&root), Some(26.0));
+
+        // Batteries without either are left out.
+        fs::remove_file(root.join("BAT0/power_now")).unwrap();
+        fs::remove_file(root.join("BAT1/voltage_now")).unwrap();
+        assert_eq!(discharge_watts_in(&root), None);
+    }
+
+    #[test]
+    fn statuses() {
+        let root = TempDir::new("status");
+        let write = |path: &str, value: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        // A battery reporting its energy, one reporting its charge, and one lacking both,
+        // besides an AC adapter and the battery of a mouse, which are left out.
+        write("BAT0/type", "Battery\n");
+        write("BAT0/manufacturer", "SMP\n");
+        write("BAT0/model_name", "5B10W13975\n");
+        write("BAT0/status", "Discharging\n");
+        write("BAT0/capacity", "78\n");
+        write("BAT0/cycle_count", "123\n");
+        write("BAT0/energy_full", "50200000\n");
+        write("BAT0/energy_full_design", "55000000\n");
+        write("BAT0/power_now", "7300000\n");
+        write("BAT0/charge_control_start_threshold", "40\n");
+        write("BAT0/charge_control_end_threshold", "80\n");
+        write("BAT1/type", "Battery\n");
+        write("BAT1/charge_full", "4000000\n");
+        write("BAT1/charge_full_design", "5000000\n");
+        write("BAT1/voltage_min_design", "11400000\n");
+        write("BAT1/charge_control_end_threshold", "90\n");
+        write("ups/type", "UPS\n");
+        write("AC/type", "Mains\n");
+        write("hidpp_battery_0/type", "Battery\n");
+        write("hidpp_battery_0/scope", "Device\n");
+
+        let batteries = batteries_in(&root);
+        let names = batteries.iter().map(|battery| battery.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["BAT0", "BAT1", "ups"]);
+
+        assert_eq!(
+            batteries[0],
+            BatteryStatus {
+                name:                   "BAT0".into(),
+                manufacturer:           "SMP".into(),
+                model:                  "5B10W13975".into(),
+                status:                 "Discharging".into(),
+                capacity:               78,
+                cycle_count:            123,
+                energy_full_mwh:        50_200,
+                energy_full_design_mwh: 55_000,
+                health:                 91,
+                power_mw:               7300,
+                start_threshold:        40,
+                end_threshold:          80,
+            }
+        );
+
+        let bat1 = &batteries[1];
+        assert_eq!((bat1.energy_full_mwh, bat1.energy_full_design_mwh), (45_600, 57_000));
+        assert_eq!((bat1.health, bat1.start_threshold, bat1.end_threshold), (80, -1, 90));
+
+        let ups = &batteries[2];
+        assert!(ups.status.is_empty() && ups.model.is_empty());
+        assert_eq!((ups.capacity, ups.cycle_count, ups.health, ups.power_mw), (-1, -1, -1, -1));
+
+        assert!(batteries_in(&root.join("missing")).is_empty());
+    }
+}
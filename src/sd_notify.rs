@@ -0,0 +1,73 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Service status notifications to systemd, as described in sd_notify(3).
+
+use std::{
+    env, io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    time::Duration,
+};
+
+/// Sends a notification such as `READY=1`, if the service manager expects them.
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else { return Ok(()) };
+    let path = path.to_string_lossy();
+
+    // Names starting with `@` are in the abstract namespace.
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address).map(|_| ())
+}
+
+/// Like [`notify`], but only logs failures.
+pub fn notify_or_log(state: &str) {
+    if let Err(why) = notify(state) {
+        log::warn!("failed to notify systemd of {}: {}", state, why);
+    }
+}
+
+pub fn status(status: &str) { notify_or_log(&["STATUS=", status].concat()) }
+
+/// Interval in which the service manager expects `WATCHDOG=1`, if it watches this process.
+#[must_use]
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn notify_socket() {
+        let dir = TempDir::new("notify");
+        let path = dir.join("socket");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        env::set_var("NOTIFY_SOCKET", &path);
+
+        notify("READY=1\nSTATUS=Idle").unwrap();
+
+        let mut buffer = [0; 64];
+        let len = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1\nSTATUS=Idle");
+
+        env::remove_var("NOTIFY_SOCKET");
+    }
+}
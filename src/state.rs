@@ -0,0 +1,160 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Settings requested by clients, which the daemon restores when it starts.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+pub const STATE_PATH: &str = "/var/lib/system76-power/state.json";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct State {
+    /// Last profile requested by a client, such as `Battery`.
+    pub profile:                   Option<String>,
+    /// Thresholds requested for every battery.
+    pub charge_thresholds:         Option<(u8, u8)>,
+    /// Thresholds requested for single batteries after those of every battery, keyed by power
+    /// supply name.
+    pub battery_charge_thresholds: BTreeMap<String, (u8, u8)>,
+    /// One of `on`, `off` or `auto`.
+    pub graphics_power:            Option<String>,
+    /// Mode applied as the default of the model on the first run, which is done only once.
+    pub default_graphics:          Option<String>,
+    /// What a battery calibration restores, saved before it starts so that a daemon which
+    /// stopped meanwhile restores it on startup.
+    pub calibration:               Option<Calibration>,
+}
+
+/// The settings of a battery before its calibration.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Calibration {
+    /// Power supply name, such as `BAT0`.
+    pub battery:    String,
+    /// Thresholds of the battery, or `None` if it has none.
+    pub thresholds: Option<(u8, u8)>,
+    /// Charge behaviour of the battery, such as `auto`.
+    pub behaviour:  String,
+}
+
+impl State {
+    /// Loads the state, falling back to the defaults if it is missing or corrupt.
+    #[must_use]
+    pub fn load() -> Self { Self::load_from(Path::new(STATE_PATH)) }
+
+    pub fn save(&self) -> io::Result<()> { self.save_to(Path::new(STATE_PATH)) }
+
+    /// Records the thresholds requested for `battery`, or for every battery if `None`, which
+    /// replace those requested for single batteries.
+    pub fn set_charge_thresholds(&mut self, battery: Option<&str>, thresholds: (u8, u8)) {
+        match battery {
+            Some(battery) => {
+                self.battery_charge_thresholds.insert(battery.to_owned(), thresholds);
+            }
+            None => {
+                self.charge_thresholds = Some(thresholds);
+                self.battery_charge_thresholds.clear();
+            }
+        }
+    }
+
+    /// Whether thresholds were requested for any battery.
+    #[must_use]
+    pub fn has_charge_thresholds(&self) -> bool {
+        self.charge_thresholds.is_some() || !self.battery_charge_thresholds.is_empty()
+    }
+
+    /// The thresholds requested for a battery, by itself or along with every battery.
+    #[must_use]
+    pub fn charge_thresholds_of(&self, battery: &str) -> Option<(u8, u8)> {
+        self.battery_charge_thresholds.get(battery).copied().or(self.charge_thresholds)
+    }
+
+    fn load_from(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(why) => {
+                log::debug!("no state to restore from {}: {}", path.display(), why);
+                return Self::default();
+            }
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|why| {
+            log::debug!("ignoring corrupt state in {}: {}", path.display(), why);
+            Self::default()
+        })
+    }
+
+    /// Replaces the state file atomically, so that a crash cannot leave it truncated.
+    fn save_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(temporary, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn state() -> State {
+        State {
+            profile:                   Some("Battery".into()),
+            charge_thresholds:         Some((40, 80)),
+            battery_charge_thresholds: BTreeMap::from([("BAT1".into(), (50, 60))]),
+            graphics_power:            Some("auto".into()),
+            default_graphics:          Some("hybrid".into()),
+            calibration:               Some(Calibration {
+                battery:    "BAT0".into(),
+                thresholds: Some((40, 80)),
+                behaviour:  "auto".into(),
+            }),
+        }
+    }
+
+    #[test]
+    fn charge_thresholds() {
+        let mut state = state();
+        assert_eq!(state.charge_thresholds_of("BAT0"), Some((40, 80)));
+        assert_eq!(state.charge_thresholds_of("BAT1"), Some((50, 60)));
+
+        state.set_charge_thresholds(Some("BAT0"), (70, 90));
+        assert_eq!(state.charge_thresholds_of("BAT0"), Some((70, 90)));
+
+        state.set_charge_thresholds(None, (86, 90));
+        assert!(state.battery_charge_thresholds.is_empty());
+        assert_eq!(state.charge_thresholds_of("BAT1"), Some((86, 90)));
+
+        assert!(!State::default().has_charge_thresholds());
+        assert_eq!(State::default().charge_thresholds_of("BAT0"), None);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let json = serde_json::to_string(&state()).unwrap();
+        assert_eq!(serde_json::from_str::<State>(&json).unwrap(), state());
+
+        let partial = serde_json::from_str::<State>(r#"{"profile":"Performance"}"#).unwrap();
+        assert_eq!(partial, State { profile: Some("Performance".into()), ..State::default() });
+    }
+
+    #[test]
+    fn restart() {
+        let dir = TempDir::new("state");
+        let path = dir.join("state.json");
+
+        assert_eq!(State::load_from(&path), State::default());
+
+        state().save_to(&path).unwrap();
+        assert_eq!(State::load_from(&path), state());
+
+        fs::write(&path, "{\"profile\": ").unwrap();
+        assert_eq!(State::load_from(&path), State::default());
+    }
+}
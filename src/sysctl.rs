@@ -0,0 +1,110 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The parameters of `/proc/sys` set by the profiles: the writeback of dirty pages, the laptop
+//! mode and the NMI watchdog. The values they held before a profile first changed them are
+//! kept, for the profiles which leave them alone to restore.
+
+use std::{collections::BTreeMap, fs, io, sync::Mutex};
+
+/// Values of the parameters before a profile changed them, by path.
+static ORIGINALS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// A parameter of `/proc/sys`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sysctl {
+    DirtyWritebackCentisecs,
+    DirtyExpireCentisecs,
+    LaptopMode,
+    NmiWatchdog,
+}
+
+impl Sysctl {
+    /// Name of the parameter, which is also its key in `profiles.toml`.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::DirtyWritebackCentisecs => "dirty_writeback_centisecs",
+            Self::DirtyExpireCentisecs => "dirty_expire_centisecs",
+            Self::LaptopMode => "laptop_mode",
+            Self::NmiWatchdog => "nmi_watchdog",
+        }
+    }
+
+    #[must_use]
+    pub const fn path(self) -> &'static str {
+        match self {
+            Self::DirtyWritebackCentisecs => "/proc/sys/vm/dirty_writeback_centisecs",
+            Self::DirtyExpireCentisecs => "/proc/sys/vm/dirty_expire_centisecs",
+            Self::LaptopMode => "/proc/sys/vm/laptop_mode",
+            Self::NmiWatchdog => "/proc/sys/kernel/nmi_watchdog",
+        }
+    }
+}
+
+/// The value of a parameter, or `None` if it cannot be read.
+#[must_use]
+pub fn read(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|value| value.trim().to_owned())
+}
+
+/// Keeps the value of a parameter about to be changed, unless a profile already changed it
+/// since it was last restored.
+pub fn remember(path: &str) {
+    let mut originals = ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !originals.contains_key(path) {
+        if let Some(value) = read(path) {
+            originals.insert(path.to_owned(), value);
+        }
+    }
+}
+
+/// The value a parameter held before a profile changed it, if one did.
+#[must_use]
+pub fn original(path: &str) -> Option<String> {
+    ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(path).cloned()
+}
+
+/// Takes the value to restore, so that changes made by others afterwards are kept.
+pub fn restore(path: &str) -> Option<String> {
+    ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(path)
+}
+
+/// Whether a write was refused because `/proc/sys` is read-only, as in containers.
+#[must_use]
+pub fn read_only(why: &io::Error) -> bool {
+    matches!(why.raw_os_error(), Some(libc::EROFS | libc::EACCES | libc::EPERM))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn originals() {
+        let dir = TempDir::new("sysctl");
+        let path = dir.join("dirty_writeback_centisecs");
+        let path = path.to_str().unwrap();
+        fs::write(path, "500\n").unwrap();
+
+        // The value found first is kept across changes, until it is restored.
+        remember(path);
+        fs::write(path, "1500").unwrap();
+        remember(path);
+        assert_eq!(original(path).as_deref(), Some("500"));
+        assert_eq!(restore(path).as_deref(), Some("500"));
+        assert_eq!(restore(path), None);
+
+        remember(path);
+        assert_eq!(original(path).as_deref(), Some("1500"));
+
+        // Missing parameters have nothing to restore.
+        let missing = dir.join("laptop_mode");
+        remember(missing.to_str().unwrap());
+        assert_eq!(original(missing.to_str().unwrap()), None);
+
+        assert!(read_only(&io::Error::from_raw_os_error(libc::EROFS)));
+        assert!(!read_only(&io::Error::from_raw_os_error(libc::EINVAL)));
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Fixtures shared by the tests.
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// A directory of the temporary directory, named after the test and the process, which is
+/// removed when dropped, so that failing tests do not leave it behind.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates the directory, emptied of anything a previous run left behind.
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("system76-power-{}-{}", name, std::process::id()));
+        let _res = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path { &self.0 }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) { let _res = fs::remove_dir_all(&self.0); }
+}
@@ -0,0 +1,849 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Overrides of the parameters set by the built-in profiles, and custom profiles based on
+//! them, read from `/etc/system76-power/profiles.toml`. `data/profiles.toml` documents every
+//! key.
+
+use std::sync::{RwLock, RwLockReadGuard};
+use toml::{Table, Value};
+
+use crate::{
+    config::{self, ConfigError},
+    usb_autosuspend, Profile,
+};
+
+const PROFILES_CONFIG: &str = "profiles.toml";
+
+/// Most custom profiles `profiles.toml` may define.
+pub const MAX_CUSTOM_PROFILES: usize = 16;
+
+// Longest name of a custom profile.
+const MAX_NAME_LEN: usize = 32;
+
+// Names which custom profiles may not take, in any case, as they name built-in profiles or
+// the `profile auto` and `profile schedule` subcommands.
+const RESERVED_NAMES: [&str; 6] =
+    ["battery", "quiet", "balanced", "performance", "auto", "schedule"];
+
+/// The keys of a profile section, in the order they are reported.
+pub const KEYS: [&str; 18] = [
+    "governor",
+    "energy_performance_preference",
+    "turbo",
+    "max_frequency_percent",
+    "platform_profile",
+    "pcie_aspm_policy",
+    "sata_link_policy",
+    "dirty_writeback_centisecs",
+    "dirty_expire_centisecs",
+    "laptop_mode",
+    "nmi_watchdog",
+    "wifi_powersave",
+    "usb_autosuspend",
+    "usb_autosuspend_deny_ids",
+    "usb_autosuspend_deny_classes",
+    "brightness",
+    "brightness_floor",
+    "dgpu_power_cap_watts",
+];
+
+/// The keys of the `pcore` and `ecore` tables of a profile section.
+pub const CORE_KEYS: [&str; 3] =
+    ["governor", "energy_performance_preference", "max_frequency_percent"];
+
+const GOVERNORS: &[&str] =
+    &["conservative", "ondemand", "performance", "powersave", "schedutil", "userspace"];
+
+const PREFERENCES: &[&str] =
+    &["default", "performance", "balance_performance", "balance_power", "power"];
+
+const PLATFORM_PROFILES: &[&str] =
+    &["low-power", "cool", "quiet", "balanced", "balanced-performance", "performance"];
+
+const ASPM_POLICIES: &[&str] = &["default", "performance", "powersave", "powersupersave"];
+
+const WIFI_POWERSAVE: &[&str] = &["on", "off", "unchanged"];
+
+const LINK_POLICIES: &[&str] = &[
+    "max_performance",
+    "medium_power",
+    "med_power_with_dipm",
+    "min_power_with_partial",
+    "min_power",
+    "keep_firmware_settings",
+];
+
+static TUNABLES: RwLock<Tunables> = RwLock::new(Tunables::BUILTIN);
+
+/// The parameters of the CPUs overridden for one type of cores of hybrid CPUs, in the `pcore`
+/// or `ecore` table of a profile. Unset ones, and every one on other CPUs, keep the values of
+/// the profile.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoreTunables {
+    pub governor:                      Option<String>,
+    pub energy_performance_preference: Option<String>,
+    pub max_frequency_percent:         Option<u8>,
+}
+
+impl CoreTunables {
+    const BUILTIN: Self = Self {
+        governor:                      None,
+        energy_performance_preference: None,
+        max_frequency_percent:         None,
+    };
+
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match key {
+            "governor" => self.governor = Some(choice(value, GOVERNORS)?),
+            "energy_performance_preference" => {
+                self.energy_performance_preference = Some(choice(value, PREFERENCES)?);
+            }
+            "max_frequency_percent" => self.max_frequency_percent = Some(percent(value)?),
+            _ => return Err(format!("unknown key, expected one of {}", CORE_KEYS.join(", "))),
+        }
+
+        Ok(())
+    }
+
+    /// These overrides, with those of `base` for the keys unset here.
+    fn or(self, base: &Self) -> Self {
+        Self {
+            governor:                      self.governor.or_else(|| base.governor.clone()),
+            energy_performance_preference: self
+                .energy_performance_preference
+                .or_else(|| base.energy_performance_preference.clone()),
+            max_frequency_percent:         self
+                .max_frequency_percent
+                .or(base.max_frequency_percent),
+        }
+    }
+}
+
+/// The parameters of a profile overridden in `profiles.toml`. Unset ones keep the values of
+/// the built-in profile.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProfileTunables {
+    /// The `scaling_governor` of the CPUs.
+    pub governor:                      Option<String>,
+    /// The `energy_performance_preference` of the CPUs.
+    pub energy_performance_preference: Option<String>,
+    /// Whether the Intel PState driver may boost the frequency.
+    pub turbo:                         Option<bool>,
+    /// The maximum frequency of the CPUs, in percent of their maximum.
+    pub max_frequency_percent:         Option<u8>,
+    /// The ACPI `platform_profile`.
+    pub platform_profile:              Option<String>,
+    /// The policy of PCIe Active State Power Management, such as `powersupersave`.
+    pub pcie_aspm_policy:              Option<String>,
+    /// The `link_power_management_policy` of the SATA hosts, such as `med_power_with_dipm`.
+    pub sata_link_policy:              Option<String>,
+    /// How often dirty pages are written back, in hundredths of a second.
+    pub dirty_writeback_centisecs:     Option<u32>,
+    /// How old dirty pages are when they are written back, in hundredths of a second.
+    pub dirty_expire_centisecs:        Option<u32>,
+    /// The seconds the kernel waits to write back after reading from a disk, or 0 to disable
+    /// the laptop mode.
+    pub laptop_mode:                   Option<u8>,
+    /// Whether the NMI watchdog, which wakes the CPUs to detect lockups, is enabled.
+    pub nmi_watchdog:                  Option<bool>,
+    /// The power save of the Wi-Fi interfaces: `on`, `off`, or `unchanged` to leave it alone.
+    pub wifi_powersave:                Option<String>,
+    /// Whether USB devices are suspended when idle.
+    pub usb_autosuspend:               Option<bool>,
+    /// The vendor and product ids of the USB devices never suspended.
+    pub usb_autosuspend_deny_ids:      Option<Vec<(u16, u16)>>,
+    /// The classes of the USB devices never suspended, in place of audio and HID.
+    pub usb_autosuspend_deny_classes:  Option<Vec<u8>>,
+    /// The brightness of the screens, in percent of their maximum, or `Some(None)` to leave
+    /// it unchanged.
+    pub brightness:                    Option<Option<u8>>,
+    /// The lowest brightness the profile dims the screens to, in percent of their maximum.
+    pub brightness_floor:              Option<u8>,
+    /// The power cap of the discrete GPUs, in watts, clamped to those they accept.
+    pub dgpu_power_cap_watts:          Option<u32>,
+    /// The parameters of the performance cores of hybrid CPUs.
+    pub pcore:                         CoreTunables,
+    /// The parameters of the efficient cores of hybrid CPUs.
+    pub ecore:                         CoreTunables,
+}
+
+impl ProfileTunables {
+    const BUILTIN: Self = Self {
+        governor:                      None,
+        energy_performance_preference: None,
+        turbo:                         None,
+        max_frequency_percent:         None,
+        platform_profile:              None,
+        pcie_aspm_policy:              None,
+        sata_link_policy:              None,
+        dirty_writeback_centisecs:     None,
+        dirty_expire_centisecs:        None,
+        laptop_mode:                   None,
+        nmi_watchdog:                  None,
+        wifi_powersave:                None,
+        usb_autosuspend:               None,
+        usb_autosuspend_deny_ids:      None,
+        usb_autosuspend_deny_classes:  None,
+        brightness:                    None,
+        brightness_floor:              None,
+        dgpu_power_cap_watts:          None,
+        pcore:                         CoreTunables::BUILTIN,
+        ecore:                         CoreTunables::BUILTIN,
+    };
+
+    /// Whether a key is overridden.
+    #[must_use]
+    pub fn is_set(&self, key: &str) -> bool {
+        match key {
+            "governor" => self.governor.is_some(),
+            "energy_performance_preference" => self.energy_performance_preference.is_some(),
+            "turbo" => self.turbo.is_some(),
+            "max_frequency_percent" => self.max_frequency_percent.is_some(),
+            "platform_profile" => self.platform_profile.is_some(),
+            "pcie_aspm_policy" => self.pcie_aspm_policy.is_some(),
+            "sata_link_policy" => self.sata_link_policy.is_some(),
+            "dirty_writeback_centisecs" => self.dirty_writeback_centisecs.is_some(),
+            "dirty_expire_centisecs" => self.dirty_expire_centisecs.is_some(),
+            "laptop_mode" => self.laptop_mode.is_some(),
+            "nmi_watchdog" => self.nmi_watchdog.is_some(),
+            "wifi_powersave" => self.wifi_powersave.is_some(),
+            "usb_autosuspend" => self.usb_autosuspend.is_some(),
+            "usb_autosuspend_deny_ids" => self.usb_autosuspend_deny_ids.is_some(),
+            "usb_autosuspend_deny_classes" => self.usb_autosuspend_deny_classes.is_some(),
+            "brightness" => self.brightness.is_some(),
+            "brightness_floor" => self.brightness_floor.is_some(),
+            "dgpu_power_cap_watts" => self.dgpu_power_cap_watts.is_some(),
+            _ => false,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match key {
+            "governor" => self.governor = Some(choice(value, GOVERNORS)?),
+            "energy_performance_preference" => {
+                self.energy_performance_preference = Some(choice(value, PREFERENCES)?);
+            }
+            "turbo" => self.turbo = Some(boolean(value)?),
+            "max_frequency_percent" => self.max_frequency_percent = Some(percent(value)?),
+            "platform_profile" => self.platform_profile = Some(platform_profile(value)?),
+            "pcie_aspm_policy" => self.pcie_aspm_policy = Some(choice(value, ASPM_POLICIES)?),
+            "sata_link_policy" => self.sata_link_policy = Some(choice(value, LINK_POLICIES)?),
+            "dirty_writeback_centisecs" => {
+                self.dirty_writeback_centisecs = Some(centisecs(value)?);
+            }
+            "dirty_expire_centisecs" => self.dirty_expire_centisecs = Some(centisecs(value)?),
+            "laptop_mode" => match value {
+                Value::Integer(secs @ 0..=60) => self.laptop_mode = Some(secs as u8),
+                value => return Err(format!("invalid value {}, expected 0 to 60", value)),
+            },
+            "nmi_watchdog" => self.nmi_watchdog = Some(boolean(value)?),
+            "wifi_powersave" => self.wifi_powersave = Some(choice(value, WIFI_POWERSAVE)?),
+            "usb_autosuspend" => self.usb_autosuspend = Some(boolean(value)?),
+            "usb_autosuspend_deny_ids" => {
+                let ids = array(value, "\"vendor:product\" ids, such as \"1235:8211\"", |id| {
+                    id.as_str().and_then(usb_autosuspend::parse_id)
+                })?;
+                self.usb_autosuspend_deny_ids = Some(ids);
+            }
+            "usb_autosuspend_deny_classes" => {
+                let classes = array(value, "classes, from 0 to 255", |class| {
+                    class.as_integer().and_then(|class| u8::try_from(class).ok())
+                })?;
+                self.usb_autosuspend_deny_classes = Some(classes);
+            }
+            "brightness" => {
+                self.brightness = Some(match value {
+                    Value::String(unchanged) if unchanged == "unchanged" => None,
+                    Value::Integer(percent @ 1..=100) => Some(percent as u8),
+                    value => {
+                        let expected = "expected 1 to 100 or \"unchanged\"";
+                        return Err(format!("invalid value {}, {}", value, expected));
+                    }
+                });
+            }
+            "brightness_floor" => match value {
+                Value::Integer(percent @ 1..=100) => self.brightness_floor = Some(percent as u8),
+                value => return Err(format!("invalid value {}, expected 1 to 100", value)),
+            },
+            "dgpu_power_cap_watts" => match value {
+                Value::Integer(watts @ 1..=1000) => self.dgpu_power_cap_watts = Some(watts as u32),
+                value => return Err(format!("invalid value {}, expected 1 to 1000", value)),
+            },
+            _ => {
+                let keys = KEYS.join(", ");
+                return Err(format!("unknown key, expected one of {}, pcore or ecore", keys));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the `pcore` or `ecore` table of the section of a profile.
+    fn set_cores(&mut self, section: &str, key: &str, value: Value) -> Result<(), ConfigError> {
+        let section = format!("{}.{}", section, key);
+        let Value::Table(keys) = value else {
+            return Err(invalid(section, "expected a table of parameters".into()));
+        };
+
+        let cores = if key == "pcore" { &mut self.pcore } else { &mut self.ecore };
+        for (key, value) in keys {
+            cores.set(&key, value).map_err(|why| invalid(format!("{}.{}", section, key), why))?;
+        }
+
+        Ok(())
+    }
+
+    /// These overrides, with those of `base` for the keys unset here.
+    fn or(self, base: &Self) -> Self {
+        Self {
+            governor:                      self.governor.or_else(|| base.governor.clone()),
+            energy_performance_preference: self
+                .energy_performance_preference
+                .or_else(|| base.energy_performance_preference.clone()),
+            turbo:                         self.turbo.or(base.turbo),
+            max_frequency_percent:         self
+                .max_frequency_percent
+                .or(base.max_frequency_percent),
+            platform_profile:              self
+                .platform_profile
+                .or_else(|| base.platform_profile.clone()),
+            pcie_aspm_policy:              self
+                .pcie_aspm_policy
+                .or_else(|| base.pcie_aspm_policy.clone()),
+            sata_link_policy:              self
+                .sata_link_policy
+                .or_else(|| base.sata_link_policy.clone()),
+            dirty_writeback_centisecs:     self
+                .dirty_writeback_centisecs
+                .or(base.dirty_writeback_centisecs),
+            dirty_expire_centisecs:        self
+                .dirty_expire_centisecs
+                .or(base.dirty_expire_centisecs),
+            laptop_mode:                   self.laptop_mode.or(base.laptop_mode),
+            nmi_watchdog:                  self.nmi_watchdog.or(base.nmi_watchdog),
+            wifi_powersave:                self
+                .wifi_powersave
+                .or_else(|| base.wifi_powersave.clone()),
+            usb_autosuspend:               self.usb_autosuspend.or(base.usb_autosuspend),
+            usb_autosuspend_deny_ids:      self
+                .usb_autosuspend_deny_ids
+                .or_else(|| base.usb_autosuspend_deny_ids.clone()),
+            usb_autosuspend_deny_classes:  self
+                .usb_autosuspend_deny_classes
+                .or_else(|| base.usb_autosuspend_deny_classes.clone()),
+            brightness:                    self.brightness.or(base.brightness),
+            brightness_floor:              self.brightness_floor.or(base.brightness_floor),
+            dgpu_power_cap_watts:          self.dgpu_power_cap_watts.or(base.dgpu_power_cap_watts),
+            pcore:                         self.pcore.or(&base.pcore),
+            ecore:                         self.ecore.or(&base.ecore),
+        }
+    }
+}
+
+/// A profile as it is set: a built-in profile, or a custom profile of `profiles.toml`, with
+/// the parameters overridden there.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileDef {
+    /// `Battery`, `Quiet`, `Balanced`, `Performance`, or the name of a custom profile.
+    pub name:        String,
+    /// Description of a custom profile, or empty for the built-in profiles.
+    pub description: String,
+    /// The built-in profile setting the parameters which are not tunables.
+    pub base:        Profile,
+    pub tunables:    ProfileTunables,
+}
+
+impl ProfileDef {
+    /// Whether the profile is defined in `profiles.toml`, instead of built in.
+    #[must_use]
+    pub fn is_custom(&self) -> bool { self.name != <&str>::from(self.base) }
+}
+
+/// The overrides of every profile, and the custom profiles.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tunables {
+    pub battery:     ProfileTunables,
+    pub quiet:       ProfileTunables,
+    pub balanced:    ProfileTunables,
+    pub performance: ProfileTunables,
+    /// Custom profiles, by name, with the overrides of their base merged.
+    pub custom:      Vec<ProfileDef>,
+}
+
+impl Tunables {
+    const BUILTIN: Self = Self {
+        battery:     ProfileTunables::BUILTIN,
+        quiet:       ProfileTunables::BUILTIN,
+        balanced:    ProfileTunables::BUILTIN,
+        performance: ProfileTunables::BUILTIN,
+        custom:      Vec::new(),
+    };
+
+    #[must_use]
+    pub fn profile(&self, profile: Profile) -> &ProfileTunables {
+        match profile {
+            Profile::Battery => &self.battery,
+            Profile::Quiet => &self.quiet,
+            Profile::Balanced => &self.balanced,
+            Profile::Performance => &self.performance,
+        }
+    }
+
+    /// The built-in profile, with its overrides.
+    fn builtin(&self, profile: Profile) -> ProfileDef {
+        ProfileDef {
+            name:        profile.to_string(),
+            description: String::new(),
+            base:        profile,
+            tunables:    self.profile(profile).clone(),
+        }
+    }
+
+    /// Reads the sections of the profiles, and the custom profiles of the `custom` section,
+    /// rejecting unknown keys, invalid values and invalid names.
+    fn from_table(table: Table) -> Result<Self, ConfigError> {
+        let mut tunables = Self::default();
+        let mut custom = Table::new();
+        for (section, value) in table {
+            let Value::Table(keys) = value else {
+                return Err(invalid(section, "expected a table of parameters".into()));
+            };
+
+            let profile = match section.as_str() {
+                "battery" => &mut tunables.battery,
+                "quiet" => &mut tunables.quiet,
+                "balanced" => &mut tunables.balanced,
+                "performance" => &mut tunables.performance,
+                "custom" => {
+                    custom = keys;
+                    continue;
+                }
+                _ => {
+                    let why =
+                        "unknown profile, expected battery, quiet, balanced, performance or custom";
+                    return Err(invalid(section, why.into()));
+                }
+            };
+
+            for (key, value) in keys {
+                if matches!(key.as_str(), "pcore" | "ecore") {
+                    profile.set_cores(&section, &key, value)?;
+                    continue;
+                }
+
+                profile
+                    .set(&key, value)
+                    .map_err(|why| invalid(format!("{}.{}", section, key), why))?;
+            }
+        }
+
+        if custom.len() > MAX_CUSTOM_PROFILES {
+            let why = format!("at most {} custom profiles may be defined", MAX_CUSTOM_PROFILES);
+            return Err(invalid("custom".into(), why));
+        }
+
+        // Custom profiles inherit the overrides of their base, which are all read by now.
+        for (name, value) in custom {
+            let section = format!("custom.{}", name);
+            check_name(&name).map_err(|why| invalid(section.clone(), why))?;
+            let Value::Table(keys) = value else {
+                return Err(invalid(section, "expected a table of parameters".into()));
+            };
+
+            let mut profile = ProfileDef {
+                name,
+                description: String::new(),
+                base: Profile::Balanced,
+                tunables: ProfileTunables::default(),
+            };
+
+            for (key, value) in keys {
+                let result = match key.as_str() {
+                    "base" => base(value).map(|base| profile.base = base),
+                    "description" => match value {
+                        Value::String(description) => {
+                            profile.description = description;
+                            Ok(())
+                        }
+                        value => Err(format!("invalid value {}, expected a string", value)),
+                    },
+                    "pcore" | "ecore" => {
+                        profile.tunables.set_cores(&section, &key, value)?;
+                        continue;
+                    }
+                    _ if !KEYS.contains(&key.as_str()) => Err(format!(
+                        "unknown key, expected base, description, pcore, ecore or one of {}",
+                        KEYS.join(", ")
+                    )),
+                    _ => profile.tunables.set(&key, value),
+                };
+
+                result.map_err(|why| invalid(format!("{}.{}", section, key), why))?;
+            }
+
+            profile.tunables = profile.tunables.or(tunables.profile(profile.base));
+            tunables.custom.push(profile);
+        }
+
+        Ok(tunables)
+    }
+}
+
+/// Reads `profiles.toml`, which then applies to the profiles set afterwards. The previous
+/// overrides and custom profiles are kept if it is invalid.
+pub fn load() -> Result<(), ConfigError> {
+    let tunables = Tunables::from_table(config::load(PROFILES_CONFIG)?)?;
+    *TUNABLES.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = tunables;
+    Ok(())
+}
+
+/// A built-in profile, with its overrides, as last loaded.
+#[must_use]
+pub fn builtin(profile: Profile) -> ProfileDef { read().builtin(profile) }
+
+/// The profile of a name, as known to the daemon: `Battery`, `Quiet`, `Balanced`,
+/// `Performance`, or a custom profile, as last loaded.
+#[must_use]
+pub fn find(name: &str) -> Option<ProfileDef> {
+    let tunables = read();
+    match name.parse() {
+        Ok(profile) => Some(tunables.builtin(profile)),
+        Err(()) => tunables.custom.iter().find(|profile| profile.name == name).cloned(),
+    }
+}
+
+/// The built-in profiles, then the custom profiles, as last loaded.
+#[must_use]
+pub fn all() -> Vec<ProfileDef> {
+    let tunables = read();
+    let builtin = Profile::ALL.into_iter().map(|profile| tunables.builtin(profile));
+    builtin.chain(tunables.custom.iter().cloned()).collect()
+}
+
+fn read() -> RwLockReadGuard<'static, Tunables> {
+    TUNABLES.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn invalid(key: String, why: String) -> ConfigError {
+    ConfigError::Invalid { path: config::path(PROFILES_CONFIG), key, why }
+}
+
+/// Checks the name of a custom profile, which is given on the command line and over DBus.
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        Err(format!("the name must be 1 to {} characters long", MAX_NAME_LEN))
+    } else if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Err("the name may only contain letters, digits, '-' and '_'".into())
+    } else if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)) {
+        Err("the name is reserved".into())
+    } else {
+        Ok(())
+    }
+}
+
+/// The built-in profile a custom profile is based on, named as in `profiles.toml`.
+fn base(value: Value) -> Result<Profile, String> {
+    Ok(match choice(value, &["battery", "quiet", "balanced", "performance"])?.as_str() {
+        "battery" => Profile::Battery,
+        "quiet" => Profile::Quiet,
+        "balanced" => Profile::Balanced,
+        _ => Profile::Performance,
+    })
+}
+
+fn choice(value: Value, choices: &[&str]) -> Result<String, String> {
+    match value {
+        Value::String(choice) if choices.contains(&choice.as_str()) => Ok(choice),
+        value => Err(format!("invalid value {}, expected one of {}", value, choices.join(", "))),
+    }
+}
+
+/// A standard ACPI platform profile, or a vendor-specific one, named alike.
+fn platform_profile(value: Value) -> Result<String, String> {
+    let named = |choice: &str| {
+        !choice.is_empty() && choice.bytes().all(|b| matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'-'))
+    };
+
+    match value {
+        Value::String(choice) if named(&choice) => Ok(choice),
+        value => Err(format!(
+            "invalid value {}, expected one of {}, or a vendor-specific profile of lowercase \
+             letters, digits and dashes",
+            value,
+            PLATFORM_PROFILES.join(", ")
+        )),
+    }
+}
+
+fn boolean(value: Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(value) => Ok(value),
+        value => Err(format!("invalid value {}, expected true or false", value)),
+    }
+}
+
+/// An array of values, each parsed by `item`, which returns `None` unless it is one of the
+/// `expected` values.
+fn array<T>(
+    value: Value,
+    expected: &str,
+    item: impl Fn(&Value) -> Option<T>,
+) -> Result<Vec<T>, String> {
+    let items = match &value {
+        Value::Array(values) => values.iter().map(item).collect(),
+        _ => None,
+    };
+    items.ok_or_else(|| format!("invalid value {}, expected an array of {}", value, expected))
+}
+
+/// A share of a maximum, such as the frequency of the CPUs.
+fn percent(value: Value) -> Result<u8, String> {
+    match value {
+        Value::Integer(percent @ 1..=100) => Ok(percent as u8),
+        value => Err(format!("invalid value {}, expected 1 to 100", value)),
+    }
+}
+
+/// An interval of the writeback of dirty pages, of up to an hour.
+fn centisecs(value: Value) -> Result<u32, String> {
+    match value {
+        Value::Integer(centisecs @ 0..=360_000) => Ok(centisecs as u32),
+        value => Err(format!("invalid value {}, expected 0 to 360000", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/data/profiles.toml"));
+
+    fn parse(toml: &str) -> Result<Tunables, String> {
+        let table = toml::from_str(toml).map_err(|why: toml::de::Error| why.to_string())?;
+        Tunables::from_table(table).map_err(|why| why.to_string())
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(parse("").unwrap(), Tunables::BUILTIN);
+        assert_eq!(parse(EXAMPLE).unwrap(), Tunables::BUILTIN);
+    }
+
+    #[test]
+    fn partial() {
+        let tunables = parse(
+            "[balanced]\nenergy_performance_preference = \"power\"\nturbo = \
+             false\n\n[performance]\nmax_frequency_percent = 90\n",
+        )
+        .unwrap();
+
+        assert_eq!(tunables.balanced.energy_performance_preference.as_deref(), Some("power"));
+        assert_eq!(tunables.balanced.turbo, Some(false));
+        assert_eq!(tunables.balanced.governor, None);
+        assert!(tunables.balanced.is_set("turbo"));
+        assert!(!tunables.balanced.is_set("governor"));
+        assert_eq!(tunables.performance.max_frequency_percent, Some(90));
+        assert_eq!(tunables.battery, ProfileTunables::default());
+
+        let tunables =
+            parse("[battery]\ndirty_writeback_centisecs = 6000\nnmi_watchdog = true\n").unwrap();
+        assert_eq!(tunables.battery.dirty_writeback_centisecs, Some(6000));
+        assert_eq!(tunables.battery.nmi_watchdog, Some(true));
+        assert!(tunables.battery.is_set("nmi_watchdog") && !tunables.battery.is_set("laptop_mode"));
+
+        let tunables = parse(
+            "[battery]\nusb_autosuspend_deny_ids = [\"1235:8211\", \
+             \"046D:C52B\"]\nusb_autosuspend_deny_classes = [0x01]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            tunables.battery.usb_autosuspend_deny_ids,
+            Some(vec![(0x1235, 0x8211), (0x046d, 0xc52b)])
+        );
+        assert_eq!(tunables.battery.usb_autosuspend_deny_classes, Some(vec![0x01]));
+
+        let tunables = parse(
+            "[battery]\nbrightness = 30\ndgpu_power_cap_watts = 60\n\n[performance]\nbrightness = \
+             \"unchanged\"\n",
+        )
+        .unwrap();
+        assert_eq!(tunables.battery.brightness, Some(Some(30)));
+        assert_eq!(tunables.battery.dgpu_power_cap_watts, Some(60));
+        assert_eq!(tunables.performance.brightness, Some(None));
+        assert_eq!(tunables.balanced.brightness, None);
+    }
+
+    #[test]
+    fn cores() {
+        let tunables = parse(
+            "[battery]\nenergy_performance_preference = \
+             \"balance_power\"\n\n[battery.pcore]\nenergy_performance_preference = \
+             \"power\"\nmax_frequency_percent = 60\n\n[battery.ecore]\ngovernor = \
+             \"powersave\"\n\n[custom.focus]\nbase = \
+             \"battery\"\n\n[custom.focus.ecore]\nmax_frequency_percent = 80\n",
+        )
+        .unwrap();
+
+        let battery = &tunables.battery;
+        assert_eq!(battery.energy_performance_preference.as_deref(), Some("balance_power"));
+        assert_eq!(battery.pcore.energy_performance_preference.as_deref(), Some("power"));
+        assert_eq!(battery.pcore.max_frequency_percent, Some(60));
+        assert_eq!(battery.pcore.governor, None);
+        assert_eq!(battery.ecore.governor.as_deref(), Some("powersave"));
+        assert_eq!(tunables.balanced.pcore, CoreTunables::default());
+
+        // Custom profiles inherit the tables of their base, key by key.
+        let focus = &tunables.custom[0].tunables;
+        assert_eq!(focus.pcore, battery.pcore);
+        assert_eq!(focus.ecore.governor.as_deref(), Some("powersave"));
+        assert_eq!(focus.ecore.max_frequency_percent, Some(80));
+
+        assert_eq!(
+            parse("[battery.pcore]\nturbo = false\n").unwrap_err(),
+            "invalid battery.pcore.turbo in /etc/system76-power/profiles.toml: unknown key, \
+             expected one of governor, energy_performance_preference, max_frequency_percent"
+        );
+        let why = parse("[custom.focus.ecore]\nmax_frequency_percent = 0\n").unwrap_err();
+        assert!(why.starts_with("invalid custom.focus.ecore.max_frequency_percent in"), "{}", why);
+        let why = parse("[performance]\npcore = 1\n").unwrap_err();
+        assert!(why.starts_with("invalid performance.pcore in"), "{}", why);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            parse("[balanced]\ngovernr = \"powersave\"\n").unwrap_err(),
+            "invalid balanced.governr in /etc/system76-power/profiles.toml: unknown key, expected \
+             one of governor, energy_performance_preference, turbo, max_frequency_percent, \
+             platform_profile, pcie_aspm_policy, sata_link_policy, dirty_writeback_centisecs, \
+             dirty_expire_centisecs, laptop_mode, nmi_watchdog, wifi_powersave, usb_autosuspend, \
+             usb_autosuspend_deny_ids, usb_autosuspend_deny_classes, brightness, \
+             brightness_floor, dgpu_power_cap_watts, pcore or ecore"
+        );
+
+        assert_eq!(
+            parse("[battery]\nturbo = \"off\"\n").unwrap_err(),
+            "invalid battery.turbo in /etc/system76-power/profiles.toml: invalid value \"off\", \
+             expected true or false"
+        );
+
+        assert_eq!(
+            parse("[performance]\nmax_frequency_percent = 0\n").unwrap_err(),
+            "invalid performance.max_frequency_percent in /etc/system76-power/profiles.toml: \
+             invalid value 0, expected 1 to 100"
+        );
+
+        let why = parse("[balanced]\npcie_aspm_policy = \"fast\"\n").unwrap_err();
+        assert!(why.contains("balanced.pcie_aspm_policy"), "{}", why);
+
+        let why = parse("[performance]\nsata_link_policy = \"max_power\"\n").unwrap_err();
+        assert!(why.contains("performance.sata_link_policy"), "{}", why);
+
+        // Vendor-specific platform profiles are accepted, if named like those of the kernel.
+        assert!(parse("[performance]\nplatform_profile = \"max-power\"\n").is_ok());
+        let why = parse("[performance]\nplatform_profile = \"Max Power\"\n").unwrap_err();
+        assert!(why.contains("vendor-specific"), "{}", why);
+
+        assert_eq!(
+            parse("[battery]\nlaptop_mode = 90\n").unwrap_err(),
+            "invalid battery.laptop_mode in /etc/system76-power/profiles.toml: invalid value 90, \
+             expected 0 to 60"
+        );
+
+        let why = parse("[battery]\ndirty_writeback_centisecs = -1\n").unwrap_err();
+        assert!(why.ends_with("invalid value -1, expected 0 to 360000"), "{}", why);
+
+        assert_eq!(
+            parse("[battery]\nusb_autosuspend_deny_ids = [\"1235\"]\n").unwrap_err(),
+            "invalid battery.usb_autosuspend_deny_ids in /etc/system76-power/profiles.toml: \
+             invalid value [\"1235\"], expected an array of \"vendor:product\" ids, such as \
+             \"1235:8211\""
+        );
+
+        let why = parse("[performance]\ndgpu_power_cap_watts = 0\n").unwrap_err();
+        assert!(why.ends_with("invalid value 0, expected 1 to 1000"), "{}", why);
+
+        let why = parse("[battery]\nbrightness = \"dim\"\n").unwrap_err();
+        assert!(why.ends_with("expected 1 to 100 or \"unchanged\""), "{}", why);
+
+        let why = parse("[performance]\nwifi_powersave = true\n").unwrap_err();
+        assert!(why.ends_with("expected one of on, off, unchanged"), "{}", why);
+
+        let why = parse("[battery]\nusb_autosuspend_deny_classes = [256]\n").unwrap_err();
+        assert!(why.ends_with("expected an array of classes, from 0 to 255"), "{}", why);
+
+        let why = parse("[turbo]\ngovernor = \"performance\"\n").unwrap_err();
+        assert!(why.starts_with("invalid turbo in"), "{}", why);
+        let expected = "unknown profile, expected battery, quiet, balanced, performance or custom";
+        assert!(why.ends_with(expected), "{}", why);
+
+        let why = parse("battery = 1\n").unwrap_err();
+        assert!(why.ends_with("expected a table of parameters"), "{}", why);
+    }
+
+    #[test]
+    fn custom() {
+        let tunables = parse(
+            "[balanced]\nturbo = false\n\n[custom.presentation]\ndescription = \"Quiet \
+             fans\"\nplatform_profile = \"quiet\"\n\n[custom.render]\nbase = \"performance\"\n",
+        )
+        .unwrap();
+
+        let [presentation, render] = &tunables.custom[..] else { panic!("{:?}", tunables) };
+        assert_eq!(presentation.name, "presentation");
+        assert_eq!(presentation.description, "Quiet fans");
+        assert_eq!(presentation.base, Profile::Balanced);
+        assert!(presentation.is_custom());
+
+        // The overrides of the base are inherited.
+        assert_eq!(presentation.tunables.platform_profile.as_deref(), Some("quiet"));
+        assert_eq!(presentation.tunables.turbo, Some(false));
+        assert_eq!(render.base, Profile::Performance);
+        assert_eq!(render.tunables, ProfileTunables::default());
+        assert!(!tunables.builtin(Profile::Balanced).is_custom());
+
+        // The quiet profile has overrides of its own, and may be a base.
+        let tunables =
+            parse("[quiet]\nturbo = true\n\n[custom.calls]\nbase = \"quiet\"\n").unwrap();
+        assert_eq!(tunables.quiet.turbo, Some(true));
+        assert_eq!(tunables.custom[0].base, Profile::Quiet);
+        assert_eq!(tunables.custom[0].tunables.turbo, Some(true));
+        assert!(!tunables.builtin(Profile::Quiet).is_custom());
+    }
+
+    #[test]
+    fn invalid_custom() {
+        let why = parse("[custom.\"my profile\"]\n").unwrap_err();
+        assert_eq!(
+            why,
+            "invalid custom.my profile in /etc/system76-power/profiles.toml: the name may only \
+             contain letters, digits, '-' and '_'"
+        );
+
+        let why = parse("[custom.Battery]\n").unwrap_err();
+        assert!(
+            why.ends_with(
+                "custom.Battery in /etc/system76-power/profiles.toml: the name is reserved"
+            ),
+            "{}",
+            why
+        );
+        assert!(parse("[custom.auto]\n").unwrap_err().ends_with("the name is reserved"));
+        assert!(parse("[custom.schedule]\n").unwrap_err().ends_with("the name is reserved"));
+        assert!(parse("[custom.Quiet]\n").unwrap_err().ends_with("the name is reserved"));
+
+        let why = parse("[custom.silent]\nbase = \"turbo\"\n").unwrap_err();
+        assert!(why.starts_with("invalid custom.silent.base in"), "{}", why);
+
+        let why = parse("[custom.silent]\nbsae = \"battery\"\n").unwrap_err();
+        let expected = "unknown key, expected base, description, pcore, ecore or one of";
+        assert!(why.contains(expected), "{}", why);
+
+        let mut many = String::new();
+        for n in 0..=MAX_CUSTOM_PROFILES {
+            many += &format!("[custom.p{}]\n", n);
+        }
+        assert!(parse(&many).unwrap_err().ends_with("at most 16 custom profiles may be defined"));
+    }
+}
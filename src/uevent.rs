@@ -0,0 +1,164 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Kernel uevents, such as those sent when a display is connected or the AC adapter is
+//! unplugged.
+
+use std::{
+    collections::BTreeSet,
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+pub struct UeventMonitor {
+    socket: OwnedFd,
+}
+
+/// The devices which reported a change since the last read.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Subsystems of the devices which changed, including those added or removed.
+    pub subsystems: BTreeSet<String>,
+    /// Devices added, by subsystem and name, such as `power_supply` and `BAT1`.
+    pub added:      BTreeSet<(String, String)>,
+}
+
+impl Changes {
+    /// Names of the devices of the subsystem which were added.
+    pub fn added<'a>(&'a self, subsystem: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.added
+            .iter()
+            .filter(move |(added, _)| added == subsystem)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+/// The fields of a uevent which the daemon handles.
+#[derive(Debug, PartialEq, Eq)]
+struct Uevent {
+    action:    String,
+    subsystem: String,
+    /// Last component of the path of the device.
+    name:      String,
+}
+
+impl UeventMonitor {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        // Multicast group of the uevents sent by the kernel.
+        address.nl_groups = 1;
+
+        let res = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                std::ptr::addr_of!(address).cast(),
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { socket })
+    }
+
+    /// The devices which reported a change since the last call.
+    pub fn changes(&mut self) -> Changes {
+        let mut buffer = [0; 4096];
+        let mut changes = Changes::default();
+
+        loop {
+            let len = unsafe {
+                libc::recv(self.socket.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len(), 0)
+            };
+
+            let Ok(len) = usize::try_from(len) else { break };
+            if len == 0 {
+                break;
+            }
+
+            if let Some(event) = parse(&buffer[..len]) {
+                if event.action == "add" {
+                    changes.added.insert((event.subsystem.clone(), event.name));
+                }
+                changes.subsystems.insert(event.subsystem);
+            }
+        }
+
+        changes
+    }
+}
+
+/// A uevent is a header followed by NUL-separated `KEY=value` fields. Events without a
+/// subsystem, such as those of udev, are skipped.
+fn parse(message: &[u8]) -> Option<Uevent> {
+    let field = |key: &[u8]| {
+        message
+            .split(|&byte| byte == 0)
+            .find_map(|field| field.strip_prefix(key))
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    };
+
+    let devpath = field(b"DEVPATH=").unwrap_or_default();
+    Some(Uevent {
+        action:    field(b"ACTION=").unwrap_or_default(),
+        subsystem: field(b"SUBSYSTEM=")?,
+        name:      devpath.rsplit('/').next().unwrap_or_default().to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsystems() {
+        let subsystem = |event: &[u8]| parse(event).map(|event| event.subsystem);
+
+        let event = b"change@/devices/pci0000:00/0000:00:02.0/drm/card0\0ACTION=change\0\
+                      SUBSYSTEM=drm\0HOTPLUG=1\0";
+        assert_eq!(subsystem(event).as_deref(), Some("drm"));
+
+        let event = b"change@/devices/LNXSYSTM:00/ACPI0003:00/power_supply/AC\0ACTION=change\0\
+                      SUBSYSTEM=power_supply\0POWER_SUPPLY_ONLINE=0\0";
+        assert_eq!(subsystem(event).as_deref(), Some("power_supply"));
+
+        assert_eq!(subsystem(b"libudev\0"), None);
+    }
+
+    #[test]
+    fn added() {
+        let event = b"add@/devices/LNXSYSTM:00/PNP0C0A:01/power_supply/BAT1\0ACTION=add\0\
+                      DEVPATH=/devices/LNXSYSTM:00/PNP0C0A:01/power_supply/BAT1\0\
+                      SUBSYSTEM=power_supply\0";
+        assert_eq!(
+            parse(event),
+            Some(Uevent {
+                action:    "add".into(),
+                subsystem: "power_supply".into(),
+                name:      "BAT1".into(),
+            })
+        );
+
+        let mut changes = Changes::default();
+        changes.added.insert(("power_supply".into(), "BAT1".into()));
+        changes.added.insert(("usb".into(), "1-1".into()));
+        assert_eq!(changes.added("power_supply").collect::<Vec<_>>(), ["BAT1"]);
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2024 System76 <info@system76.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The autosuspend of USB devices, which the profiles enable except on the devices of a
+//! deny-list, such as audio interfaces which click when suspended.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const SYSFS_PATH: &str = "/sys/bus/usb/devices";
+
+/// Name of the parameter of each device, which clients count rather than list.
+pub const NAME: &str = "usb_autosuspend";
+
+/// Classes of the devices never suspended, unless `profiles.toml` sets others: audio, and HID,
+/// as keyboards and mice lose the input waking them.
+pub const DENIED_CLASSES: [u8; 2] = [0x01, 0x03];
+
+/// A USB device, with the classes of its interfaces, as most devices give them per interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsbDevice {
+    pub path:    PathBuf,
+    /// The vendor and product ids.
+    pub id:      (u16, u16),
+    pub classes: Vec<u8>,
+}
+
+impl UsbDevice {
+    /// The devices connected, excluding their interfaces, by path.
+    #[must_use]
+    pub fn all() -> Vec<Self> { Self::all_in(Path::new(SYSFS_PATH)) }
+
+    fn all_in(root: &Path) -> Vec<Self> {
+        let Ok(entries) = fs::read_dir(root) else { return Vec::new() };
+        let mut devices: Vec<Self> =
+            entries.filter_map(Result::ok).filter_map(|entry| Self::read(&entry.path())).collect();
+        devices.sort_by(|a, b| a.path.cmp(&b.path));
+        devices
+    }
+
+    /// Reads a device, or returns `None` for interfaces, which have no ids.
+    fn read(path: &Path) -> Option<Self> {
+        let id = (hex(&path.join("idVendor"))?, hex(&path.join("idProduct"))?);
+
+        let interfaces = fs::read_dir(path).into_iter().flatten().filter_map(Result::ok);
+        let mut classes: Vec<u8> = hex(&path.join("bDeviceClass"))
+            .into_iter()
+            .chain(interfaces.filter_map(|entry| hex(&entry.path().join("bInterfaceClass"))))
+            .filter_map(|class| u8::try_from(class).ok())
+            .collect();
+        classes.sort_unstable();
+        classes.dedup();
+
+        Some(Self { path: path.to_owned(), id, classes })
+    }
+
+    /// Whether the device is never suspended, by its ids or the class of any of its interfaces.
+    #[must_use]
+    pub fn denied(&self, ids: &[(u16, u16)], classes: &[u8]) -> bool {
+        ids.contains(&self.id) || self.classes.iter().any(|class| classes.contains(class))
+    }
+
+    /// The file enabling autosuspend with `auto`, or disabling it with `on`.
+    #[must_use]
+    pub fn control_path(&self) -> PathBuf { self.path.join("power/control") }
+}
+
+/// Parses the ids of a device, as `lsusb` shows them, such as `1235:8211`.
+#[must_use]
+pub fn parse_id(id: &str) -> Option<(u16, u16)> {
+    let (vendor, product) = id.split_once(':')?;
+    let parse = |hex: &str| {
+        let digits = hex.len() == 4 && hex.bytes().all(|byte| byte.is_ascii_hexdigit());
+        digits.then(|| u16::from_str_radix(hex, 16).ok()).flatten()
+    };
+    Some((parse(vendor)?, parse(product)?))
+}
+
+#[must_use]
+pub fn format_id((vendor, product): (u16, u16)) -> String {
+    format!("{:04x}:{:04x}", vendor, product)
+}
+
+fn hex(path: &Path) -> Option<u16> {
+    u16::from_str_radix(fs::read_to_string(path).ok()?.trim(), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn ids() {
+        assert_eq!(parse_id("1235:8211"), Some((0x1235, 0x8211)));
+        assert_eq!(parse_id("1235:821"), None);
+        assert_eq!(parse_id("12358211"), None);
+        assert_eq!(parse_id("+123:8211"), None);
+        assert_eq!(format_id((0x046d, 0xc52b)), "046d:c52b");
+    }
+
+    #[test]
+    fn devices() {
+        let root = TempDir::new("usb");
+        let write = |path: &str, value: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, value).unwrap();
+        };
+
+        // An audio interface, giving its classes per interface, and a hub.
+        write("1-2/idVendor", "1235\n");
+        write("1-2/idProduct", "8211\n");
+        write("1-2/bDeviceClass", "00\n");
+        write("1-2/1-2:1.0/bInterfaceClass", "01\n");
+        write("1-2/1-2:1.1/bInterfaceClass", "fe\n");
+        write("1-2:1.0/bInterfaceClass", "01\n");
+        write("usb1/idVendor", "1d6b\n");
+        write("usb1/idProduct", "0002\n");
+        write("usb1/bDeviceClass", "09\n");
+
+        let devices = UsbDevice::all_in(&root);
+        let [audio, hub] = &devices[..] else { panic!("{:?}", devices) };
+        assert_eq!(audio.id, (0x1235, 0x8211));
+        assert_eq!(audio.classes, [0x00, 0x01, 0xfe]);
+        assert_eq!(hub.classes, [0x09]);
+
+        assert!(audio.denied(&[], &DENIED_CLASSES));
+        assert!(!audio.denied(&[], &[]));
+        assert!(hub.denied(&[(0x1d6b, 0x0002)], &[]));
+        assert!(!hub.denied(&[(0x1235, 0x8211)], &DENIED_CLASSES));
+
+        assert!(UsbDevice::all_in(&root.join("missing")).is_empty());
+    }
+}
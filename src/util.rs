@@ -3,11 +3,13 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use std::{
+    env,
     fmt::Display,
-    fs::{DirEntry, File},
+    fs::{self, DirEntry, File},
     io::{self, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
+use system76_power_zbus::PlannedAction;
 
 pub fn entries<T, F: FnMut(DirEntry) -> T>(path: &Path, mut func: F) -> io::Result<Vec<T>> {
     let mut ret = Vec::new();
@@ -18,6 +20,94 @@ pub fn entries<T, F: FnMut(DirEntry) -> T>(path: &Path, mut func: F) -> io::Resu
     Ok(ret)
 }
 
+/// A value written to a file, which may be compared with the current value of the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Written {
+    /// Name of the parameter, such as `scaling_governor`.
+    pub name:  &'static str,
+    pub path:  String,
+    pub value: String,
+}
+
+impl Written {
+    #[must_use]
+    pub fn new(name: &'static str, path: impl Into<String>, value: impl Display) -> Self {
+        Self { name, path: path.into(), value: value.to_string() }
+    }
+
+    /// The current value of the file, or `None` if it cannot be read. Of files listing the
+    /// choices with the selected one in brackets, such as `default [powersave] performance`,
+    /// this is the selected choice.
+    #[must_use]
+    pub fn current(&self) -> Option<String> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+        let value = contents.trim();
+        let selected = value.split_once('[').and_then(|(_, rest)| rest.split_once(']'));
+        Some(selected.map_or(value, |(choice, _)| choice).to_owned())
+    }
+
+    /// Writes the value, failing with the path and the error of the OS.
+    pub fn write(&self) -> Result<(), WriteError> {
+        fs::write(&self.path, &self.value)
+            .map_err(|source| WriteError { written: self.clone(), source })
+    }
+
+    /// The write, as planned by a dry run.
+    #[must_use]
+    pub fn planned(&self) -> PlannedAction {
+        PlannedAction {
+            kind:    "write".into(),
+            target:  self.path.clone(),
+            value:   self.value.clone(),
+            current: self.current().unwrap_or_default(),
+        }
+    }
+}
+
+/// A value which could not be written.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to write {} to {}: {}", written.value, written.path, source)]
+pub struct WriteError {
+    pub written: Written,
+    pub source:  io::Error,
+}
+
+impl WriteError {
+    /// The error number of the OS, such as `EACCES`, if the error came from the OS.
+    #[must_use]
+    pub fn errno(&self) -> Option<i32> { self.source.raw_os_error() }
+}
+
+/// A command, as planned by a dry run.
+#[must_use]
+pub fn planned_command(command: &str, args: &[&str]) -> PlannedAction {
+    PlannedAction {
+        kind: "run".into(),
+        target: command.to_owned(),
+        value: args.join(" "),
+        ..PlannedAction::default()
+    }
+}
+
+/// The removal of a file, as planned by a dry run.
+#[must_use]
+pub fn planned_removal(path: impl Into<String>) -> PlannedAction {
+    PlannedAction { kind: "remove".into(), target: path.into(), ..PlannedAction::default() }
+}
+
+// Searched besides `PATH`, which may lack the system directories for other users than root.
+const SYSTEM_BIN_DIRS: [&str; 2] = ["/usr/sbin", "/sbin"];
+
+/// The path of a command, searched in `PATH` and the system directories.
+#[must_use]
+pub fn find_command(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&path)
+        .chain(SYSTEM_BIN_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
 /// Write a value that implements `Display` to a file
 pub fn write_value<V: Display>(path: &str, value: V) {
     // eprintln!("writing {} to {}", value, path);
@@ -3,10 +3,23 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use crate::{
+    errors::WifiError,
     kernel_parameters::{DeviceList, KernelParameter, PowerLevel, PowerSave},
     modprobe,
+    util::find_command,
 };
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+pub const NET_PATH: &str = "/sys/class/net";
+
+/// Power save of the interfaces before a profile changed it, by interface.
+static ORIGINALS: Mutex<BTreeMap<String, bool>> = Mutex::new(BTreeMap::new());
 
 pub struct WifiDevice {
     device:      &'static str,
@@ -61,3 +74,104 @@ impl DeviceList<Self> for WifiDevice {
         Box::new(Self::SUPPORTED.iter().filter_map(|dev| Self::new(dev)))
     }
 }
+
+/// The wireless network interfaces, such as `wlan0`, including those of USB adapters.
+#[must_use]
+pub fn interfaces() -> Vec<String> { interfaces_in(Path::new(NET_PATH)) }
+
+fn interfaces_in(root: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(root) else { return Vec::new() };
+    let mut interfaces: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let path = entry.path();
+            path.join("wireless").exists() || path.join("phy80211").exists()
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    interfaces.sort();
+    interfaces
+}
+
+/// The `iw` command, which sets the power save through nl80211.
+#[must_use]
+pub fn iw() -> Option<PathBuf> { find_command("iw") }
+
+/// Whether the power save of an interface is enabled, or `None` if `iw` cannot tell.
+#[must_use]
+pub fn power_save(interface: &str) -> Option<bool> {
+    let output = Command::new(iw()?).args(["dev", interface, "get", "power_save"]).output().ok()?;
+    parse_power_save(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses the output of `iw dev <interface> get power_save`, such as `Power save: on`.
+fn parse_power_save(output: &str) -> Option<bool> {
+    match output.trim().strip_prefix("Power save:")?.trim() {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Enables or disables the power save of an interface.
+pub fn set_power_save(interface: &str, enabled: bool) -> Result<(), WifiError> {
+    let iw = iw().ok_or(WifiError::NoIw)?;
+    let value = if enabled { "on" } else { "off" };
+    let output = Command::new(iw)
+        .args(["dev", interface, "set", "power_save", value])
+        .output()
+        .map_err(|why| WifiError::Spawn(interface.to_owned(), why))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let why = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        Err(WifiError::PowerSave(interface.to_owned(), value, why))
+    }
+}
+
+/// Keeps the power save of an interface about to be changed, unless a profile already changed
+/// it since it was last restored.
+pub fn remember(interface: &str) {
+    let mut originals = ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !originals.contains_key(interface) {
+        if let Some(enabled) = power_save(interface) {
+            originals.insert(interface.to_owned(), enabled);
+        }
+    }
+}
+
+/// The power save of an interface before a profile changed it, if one did.
+#[must_use]
+pub fn original(interface: &str) -> Option<bool> {
+    ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(interface).copied()
+}
+
+/// Takes the power save to restore, so that changes made by others afterwards are kept.
+pub fn restore(interface: &str) -> Option<bool> {
+    ORIGINALS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(interface)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn power_save_output() {
+        assert_eq!(parse_power_save("Power save: on\n"), Some(true));
+        assert_eq!(parse_power_save("Power save: off\n"), Some(false));
+        assert_eq!(parse_power_save("command failed: No such device (-19)\n"), None);
+    }
+
+    #[test]
+    fn wireless_interfaces() {
+        let root = TempDir::new("net");
+        for dir in ["wlp0s20f3/wireless", "wlx00c0ca000000/phy80211", "enp3s0/device", "lo"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        assert_eq!(interfaces_in(&root), ["wlp0s20f3", "wlx00c0ca000000"]);
+        assert!(interfaces_in(&root.join("missing")).is_empty());
+    }
+}
@@ -0,0 +1,702 @@
+// SPDX-License-Identifier: MPL-2.0
+
+//! Typed access to the daemon, for applets and other tools.
+//!
+//! ```no_run
+//! use system76_power_zbus::{client::Client, Profile};
+//!
+//! # async fn run() -> zbus::Result<()> {
+//! let client = Client::system().await?;
+//!
+//! if client.profile().await? != Profile::Battery {
+//!     client.set_profile(Profile::Battery).await?;
+//! }
+//!
+//! println!("Graphics mode: {}", client.graphics().await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    AutoProfileStatus, BatteryCapStatus, BatteryStatus, CalibrationStatus, ChargeProfile,
+    ChargeThresholdsChangedStream, ChargeThresholdsStatus, ChargeThresholdsSwitchedStream,
+    CpuFrequencyStatus, GraphicsCapabilities, GraphicsDeviceInfo, GraphicsMode, GraphicsPower,
+    GraphicsPowerStatus, GraphicsStatus, HotPlugDetectStream, InitramfsJobCompletedStream,
+    JobProxy, JobStatus, LowBatteryStatus, ModeChangedStream, PlannedAction, PowerConflict,
+    PowerDaemonProxy, PowerProfileSwitchedStream, Profile, ProfileFailure, ProfileHold,
+    ProfileInfo, ProfileReleasedStream, ProfileStatus, ProfileTunable, RecentAction,
+    ScheduleStatus, SwitchableStatus, FLAG_DRY_RUN, FLAG_FORCE, FLAG_NO_INITRAMFS, FLAG_TEMPORARY,
+};
+use futures_lite::{future, StreamExt};
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use zbus::PropertyStream;
+use zvariant::{ObjectPath, OwnedObjectPath};
+
+/// Calls a method of the proxy, converting each argument with `Into`, and logs the call with
+/// its arguments, and its outcome with its duration, at the debug level. The call fails with
+/// [`timed_out`] if the client has a timeout, and the daemon does not reply within it.
+macro_rules! call {
+    ($client:ident.$method:ident($($arg:expr),* $(,)?)) => {{
+        let args: &[String] = &[$(format!("{:?}", $arg)),*];
+        log::debug!("calling {}({})", stringify!($method), args.join(", "));
+
+        let started = Instant::now();
+        let call = $client.proxy.$method($($arg.into()),*);
+        let reply = match $client.timeout {
+            Some(timeout) => {
+                let expired = async move {
+                    async_io::Timer::after(timeout).await;
+                    Err(timed_out(timeout))
+                };
+                future::or(call, expired).await
+            }
+            None => call.await,
+        };
+        match reply {
+            Ok(_) => log::debug!("{} returned in {:?}", stringify!($method), started.elapsed()),
+            Err(ref why) => {
+                log::debug!("{} failed in {:?}: {}", stringify!($method), started.elapsed(), why)
+            }
+        }
+        reply
+    }};
+}
+
+pub struct Client<'a> {
+    proxy:   PowerDaemonProxy<'a>,
+    timeout: Option<Duration>,
+}
+
+impl Client<'static> {
+    /// Connects to the daemon on the system bus.
+    pub async fn system() -> zbus::Result<Self> {
+        Self::new(&zbus::Connection::system().await?).await
+    }
+
+    pub async fn new(connection: &zbus::Connection) -> zbus::Result<Self> {
+        Ok(Self::from_proxy(PowerDaemonProxy::new(connection).await?))
+    }
+}
+
+impl<'a> Client<'a> {
+    #[must_use]
+    pub fn from_proxy(proxy: PowerDaemonProxy<'a>) -> Self { Self { proxy, timeout: None } }
+
+    /// Fails each call the daemon does not reply to within `timeout`, instead of waiting for
+    /// it indefinitely. Following a job is not bounded, but the calls it makes are.
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self { Self { timeout: Some(timeout), ..self } }
+
+    /// The underlying proxy, for calls without a typed counterpart.
+    #[must_use]
+    pub fn proxy(&self) -> &PowerDaemonProxy<'a> { &self.proxy }
+
+    /// The active profile, which fails if it is a custom profile, unlike
+    /// [`profile_status`](Self::profile_status).
+    pub async fn profile(&self) -> zbus::Result<Profile> { profile(call!(self.get_profile())?) }
+
+    /// The profile saved for the daemon to apply when it starts, which differs from the
+    /// active one after a temporary switch, or while profiles are held. Fails if it is a custom
+    /// profile, unlike [`persisted_profile_name`](Self::persisted_profile_name).
+    ///
+    /// Requires an interface revision of 11.
+    pub async fn persisted_profile(&self) -> zbus::Result<Profile> {
+        profile(call!(self.get_persisted_profile())?)
+    }
+
+    /// The name of the profile saved for the daemon to apply when it starts, which may be a
+    /// custom profile.
+    ///
+    /// Requires an interface revision of 11.
+    pub async fn persisted_profile_name(&self) -> zbus::Result<String> {
+        call!(self.get_persisted_profile())
+    }
+
+    /// The profile, with the parameters it set and whether they still hold their values.
+    pub async fn profile_status(&self) -> zbus::Result<ProfileStatus> {
+        call!(self.get_profile_status())
+    }
+
+    /// Sets a profile, which is saved for the daemon to apply when it starts.
+    ///
+    /// `Quiet` requires an interface revision of 20.
+    pub async fn set_profile(&self, profile: Profile) -> zbus::Result<()> {
+        match profile {
+            Profile::Battery => call!(self.battery()),
+            Profile::Quiet => call!(self.set_profile_with_flags(profile, 0u32)).map(drop),
+            Profile::Balanced => call!(self.balanced()),
+            Profile::Performance => call!(self.performance()),
+        }
+    }
+
+    /// Sets a profile for this boot only, keeping the saved profile to restore when the daemon
+    /// starts. Holds are released, as with [`set_profile`](Self::set_profile).
+    ///
+    /// Requires an interface revision of 11.
+    pub async fn set_temporary_profile(&self, profile: Profile) -> zbus::Result<()> {
+        call!(self.set_profile_with_flags(profile, FLAG_TEMPORARY)).map(drop)
+    }
+
+    /// The values setting a profile would write, and the commands it would run, without
+    /// setting it.
+    ///
+    /// Requires an interface revision of 10.
+    pub async fn plan_profile(&self, profile: Profile) -> zbus::Result<Vec<PlannedAction>> {
+        call!(self.set_profile_with_flags(profile, FLAG_DRY_RUN))
+    }
+
+    /// Sets a profile by name, which may be a custom profile of `profiles.toml`, for this boot
+    /// only if `temporary`. Holds are released, as with [`set_profile`](Self::set_profile).
+    ///
+    /// Requires an interface revision of 15.
+    pub async fn set_profile_by_name(&self, name: &str, temporary: bool) -> zbus::Result<()> {
+        let flags = if temporary { FLAG_TEMPORARY } else { 0 };
+        call!(self.set_profile_with_flags(name, flags)).map(drop)
+    }
+
+    /// The values setting a profile by name, which may be a custom profile, would write, and
+    /// the commands it would run, without setting it.
+    ///
+    /// Requires an interface revision of 15.
+    pub async fn plan_profile_by_name(&self, name: &str) -> zbus::Result<Vec<PlannedAction>> {
+        call!(self.set_profile_with_flags(name, FLAG_DRY_RUN))
+    }
+
+    /// Holds a profile until the returned cookie is released, or the connection is closed.
+    ///
+    /// `Battery` takes priority over `Performance`, which takes priority over `Quiet` and
+    /// `Balanced`, and the profile selected before is restored once no holds remain.
+    pub async fn hold_profile(
+        &self,
+        profile: Profile,
+        reason: &str,
+        application_id: &str,
+    ) -> zbus::Result<u32> {
+        call!(self.hold_profile(profile, reason, application_id))
+    }
+
+    pub async fn release_profile(&self, cookie: u32) -> zbus::Result<()> {
+        call!(self.release_profile(cookie))
+    }
+
+    pub async fn active_holds(&self) -> zbus::Result<Vec<ProfileHold>> {
+        call!(self.get_active_holds())
+    }
+
+    /// The latest changes requested by clients, oldest first.
+    ///
+    /// Requires an interface revision of 4.
+    pub async fn recent_actions(&self) -> zbus::Result<Vec<RecentAction>> {
+        call!(self.get_recent_actions())
+    }
+
+    /// The profiles, with the key parameters they set on this machine. Custom profiles follow
+    /// the built-in profiles since revision 15.
+    ///
+    /// Requires an interface revision of 6.
+    pub async fn profiles(&self) -> zbus::Result<Vec<ProfileInfo>> { call!(self.get_profiles()) }
+
+    /// The parameters of the profiles which `profiles.toml` may override, with the values
+    /// they set on this machine.
+    ///
+    /// Requires an interface revision of 14.
+    pub async fn profile_tunables(&self) -> zbus::Result<Vec<ProfileTunable>> {
+        call!(self.get_profile_tunables())
+    }
+
+    /// The parameters which the last profile failed to set, although it set most others.
+    ///
+    /// Requires an interface revision of 17.
+    pub async fn profile_failures(&self) -> zbus::Result<Vec<ProfileFailure>> {
+        call!(self.get_profile_failures())
+    }
+
+    /// The scaling driver, governors, boost and frequencies of the CPUs, as cpufreq reports
+    /// them.
+    ///
+    /// Requires an interface revision of 21.
+    pub async fn cpu_frequency(&self) -> zbus::Result<CpuFrequencyStatus> {
+        call!(self.get_cpu_frequency())
+    }
+
+    /// Estimates of the power drawn, in watts: `package_watts` by the CPU package, and
+    /// `battery_watts` by the batteries while discharging, if they can be made.
+    ///
+    /// Requires an interface revision of 22.
+    pub async fn power_draw(&self) -> zbus::Result<BTreeMap<String, f64>> {
+        call!(self.get_power_draw())
+    }
+
+    /// The other power managers running, which also set some of the parameters of the
+    /// profiles.
+    ///
+    /// Requires an interface revision of 23.
+    pub async fn conflicts(&self) -> zbus::Result<Vec<PowerConflict>> {
+        call!(self.get_conflicts())
+    }
+
+    /// The system batteries, with their charge, status, health, model, charge thresholds and
+    /// rate.
+    ///
+    /// Requires an interface revision of 28.
+    pub async fn batteries(&self) -> zbus::Result<Vec<BatteryStatus>> {
+        call!(self.get_batteries())
+    }
+
+    pub async fn auto_profile(&self) -> zbus::Result<AutoProfileStatus> {
+        call!(self.get_auto_profile())
+    }
+
+    /// The schedule of profiles by time of day, and its next transition.
+    ///
+    /// Requires an interface revision of 18.
+    pub async fn profile_schedule(&self) -> zbus::Result<ScheduleStatus> {
+        call!(self.get_profile_schedule())
+    }
+
+    /// The settings of the switch to the battery profile while the battery is low, and
+    /// whether it is forced now.
+    ///
+    /// Requires an interface revision of 19.
+    pub async fn low_battery(&self) -> zbus::Result<LowBatteryStatus> {
+        call!(self.get_low_battery())
+    }
+
+    /// The settings of the cap of the CPU frequency on battery, and whether it applies now.
+    ///
+    /// Requires an interface revision of 24.
+    pub async fn battery_frequency_cap(&self) -> zbus::Result<BatteryCapStatus> {
+        call!(self.get_battery_frequency_cap())
+    }
+
+    /// Enables or disables switching the profile on AC/battery transitions.
+    pub async fn set_auto_profile(&self, enabled: bool) -> zbus::Result<()> {
+        call!(self.set_auto_profile(enabled))
+    }
+
+    /// Enables or disables switching to the idle profile while every session is idle.
+    ///
+    /// Requires an interface revision of 2.
+    pub async fn set_idle_profile(&self, enabled: bool) -> zbus::Result<()> {
+        call!(self.set_idle_profile(enabled))
+    }
+
+    /// Enables or disables switching to the battery profile while the battery is under
+    /// `threshold_percent`.
+    ///
+    /// Requires an interface revision of 19.
+    pub async fn set_low_battery(&self, enabled: bool, threshold_percent: u8) -> zbus::Result<()> {
+        call!(self.set_low_battery(enabled, threshold_percent))
+    }
+
+    /// The version of the daemon, and the revision of its interface, to compare with
+    /// [`API_VERSION`](crate::API_VERSION) before using members added since.
+    pub async fn version(&self) -> zbus::Result<(String, u32)> { call!(self.get_version()) }
+
+    /// The effective settings of the daemon, as the TOML of `daemon.toml`.
+    pub async fn config(&self) -> zbus::Result<String> { call!(self.get_config()) }
+
+    pub async fn graphics(&self) -> zbus::Result<GraphicsMode> {
+        graphics_mode(call!(self.get_graphics())?)
+    }
+
+    /// The mode configured for the next boot: a mode, `custom` if its files were edited to
+    /// match no mode, or empty if no mode was configured yet.
+    pub async fn configured_graphics(&self) -> zbus::Result<String> {
+        call!(self.configured_graphics_mode())
+    }
+
+    /// The mode recommended for this model.
+    pub async fn default_graphics(&self) -> zbus::Result<GraphicsMode> {
+        graphics_mode(call!(self.get_default_graphics())?)
+    }
+
+    /// The active and configured modes, the power of the discrete GPU, and whether a reboot is
+    /// pending.
+    ///
+    /// Requires an interface revision of 5.
+    pub async fn graphics_status(&self) -> zbus::Result<GraphicsStatus> {
+        call!(self.get_graphics_status())
+    }
+
+    /// The mode recommended for this model, and a code of how it was derived, such as
+    /// `runtimepm-supported`.
+    ///
+    /// Requires an interface revision of 3.
+    pub async fn graphics_recommendation(&self) -> zbus::Result<(GraphicsMode, String)> {
+        let (mode, reason) = call!(self.get_graphics_recommendation())?;
+        Ok((graphics_mode(mode)?, reason))
+    }
+
+    /// Switches the graphics mode, waiting for the switch to finish.
+    ///
+    /// With `force`, the switch happens even if processes are using the dGPU.
+    pub async fn set_graphics(&self, mode: GraphicsMode, force: bool) -> zbus::Result<()> {
+        if force {
+            call!(self.set_graphics_force(mode))
+        } else {
+            call!(self.set_graphics(mode))
+        }
+    }
+
+    /// Switches the graphics mode as a job, calling `progress` with each step and its
+    /// percentage of completion, and returns the final status of the job.
+    ///
+    /// A failed switch is not an error of the call: its status names the error instead, such
+    /// as `com.system76.PowerDaemon.Error.InitramfsFailed`.
+    pub async fn switch_graphics(
+        &self,
+        mode: GraphicsMode,
+        force: bool,
+        progress: impl FnMut(&str, u32),
+    ) -> zbus::Result<JobStatus> {
+        let path = self.start_graphics_switch(mode, force).await?;
+        self.follow_job(&path, progress).await
+    }
+
+    /// Starts switching the graphics mode, returning the path of the job performing it,
+    /// without waiting for it to finish.
+    pub async fn start_graphics_switch(
+        &self,
+        mode: GraphicsMode,
+        force: bool,
+    ) -> zbus::Result<OwnedObjectPath> {
+        call!(self.start_graphics_switch(mode, force))
+    }
+
+    /// Starts switching the graphics mode like
+    /// [`start_graphics_switch`](Self::start_graphics_switch), without rebuilding the initramfs,
+    /// which must then be rebuilt before rebooting.
+    ///
+    /// Requires an interface revision of 13.
+    pub async fn start_graphics_switch_without_initramfs(
+        &self,
+        mode: GraphicsMode,
+        force: bool,
+    ) -> zbus::Result<OwnedObjectPath> {
+        let flags = if force { FLAG_FORCE | FLAG_NO_INITRAMFS } else { FLAG_NO_INITRAMFS };
+        call!(self.start_graphics_switch_with_flags(mode, flags)).map(|(job, _)| job)
+    }
+
+    /// The files a switch of the graphics mode would write and the commands it would run,
+    /// without switching.
+    ///
+    /// Requires an interface revision of 10.
+    pub async fn plan_graphics_switch(
+        &self,
+        mode: GraphicsMode,
+        force: bool,
+    ) -> zbus::Result<Vec<PlannedAction>> {
+        let flags = if force { FLAG_FORCE | FLAG_DRY_RUN } else { FLAG_DRY_RUN };
+        call!(self.start_graphics_switch_with_flags(mode, flags)).map(|(_, plan)| plan)
+    }
+
+    /// The files a switch of the graphics mode without rebuilding the initramfs would write,
+    /// and the commands it would run, without switching.
+    ///
+    /// Requires an interface revision of 13.
+    pub async fn plan_graphics_switch_without_initramfs(
+        &self,
+        mode: GraphicsMode,
+        force: bool,
+    ) -> zbus::Result<Vec<PlannedAction>> {
+        let flags = FLAG_DRY_RUN | FLAG_NO_INITRAMFS;
+        let flags = if force { flags | FLAG_FORCE } else { flags };
+        call!(self.start_graphics_switch_with_flags(mode, flags)).map(|(_, plan)| plan)
+    }
+
+    /// Waits for a job to finish, calling `progress` with each step and its percentage of
+    /// completion, and returns its final status.
+    ///
+    /// Dropping the future stops following the job, which continues in the daemon.
+    pub async fn follow_job(
+        &self,
+        path: &ObjectPath<'_>,
+        mut progress: impl FnMut(&str, u32),
+    ) -> zbus::Result<JobStatus> {
+        enum Event {
+            Progress(String, u32),
+            Finished,
+        }
+
+        let job = JobProxy::builder(self.proxy.connection()).path(path.to_owned())?.build().await?;
+
+        let steps = job.receive_progress().await?.map(|signal| {
+            signal.args().map(|args| Event::Progress(args.step.to_owned(), args.percent))
+        });
+
+        let finished = job.receive_finished().await?.map(|_| Ok(Event::Finished));
+
+        // The job may have progressed before its signals were subscribed to.
+        let status = call!(self.get_job(path))?;
+        if status.finished {
+            return Ok(status);
+        }
+
+        let mut last_step = status.step;
+        if !last_step.is_empty() {
+            progress(&last_step, status.percent);
+        }
+
+        let mut events = steps.or(finished);
+        loop {
+            match events.next().await {
+                Some(Ok(Event::Progress(step, percent))) => {
+                    if step != last_step {
+                        progress(&step, percent);
+                        last_step = step;
+                    }
+                }
+                // The status also carries the name of the error of a failed job.
+                Some(Ok(Event::Finished)) => return call!(self.get_job(path)),
+                Some(Err(why)) => return Err(why),
+                None => return Err(zbus::Error::Failure("lost connection to the daemon".into())),
+            }
+        }
+    }
+
+    /// Status of a running job, or of a recently finished one.
+    pub async fn job(&self, path: &ObjectPath<'_>) -> zbus::Result<JobStatus> {
+        call!(self.get_job(path))
+    }
+
+    pub async fn graphics_capabilities(&self) -> zbus::Result<GraphicsCapabilities> {
+        call!(self.get_graphics_capabilities())
+    }
+
+    /// GPUs detected on the PCI bus at the time of the call.
+    pub async fn graphics_devices(&self) -> zbus::Result<Vec<GraphicsDeviceInfo>> {
+        call!(self.get_graphics_devices())
+    }
+
+    /// Sets an option of the generated driver configuration, such as `gsp`.
+    pub async fn set_graphics_option(&self, option: &str, value: &str) -> zbus::Result<()> {
+        call!(self.set_graphics_option(option, value))
+    }
+
+    pub async fn switchable(&self) -> zbus::Result<bool> { call!(self.get_switchable()) }
+
+    /// Whether graphics can be switched, why not, and the GPUs found.
+    ///
+    /// Requires an interface revision of 9.
+    pub async fn switchable_status(&self) -> zbus::Result<SwitchableStatus> {
+        call!(self.get_switchable_status())
+    }
+
+    pub async fn desktop(&self) -> zbus::Result<bool> { call!(self.get_desktop()) }
+
+    pub async fn external_displays_require_dgpu(&self) -> zbus::Result<bool> {
+        call!(self.external_displays_require_dgpu())
+    }
+
+    /// Whether the discrete GPU is powered on.
+    pub async fn graphics_power(&self) -> zbus::Result<bool> { call!(self.get_graphics_power()) }
+
+    /// Whether the discrete GPU is active, suspended by runtime power management, off, or
+    /// needs a rescan of the bus, with its functions.
+    ///
+    /// Requires an interface revision of 12.
+    pub async fn graphics_power_status(&self) -> zbus::Result<GraphicsPowerStatus> {
+        call!(self.get_graphics_power_status())
+    }
+
+    /// Sets the power state of the discrete GPU, returning whether it is powered on.
+    pub async fn set_graphics_power(&self, power: GraphicsPower) -> zbus::Result<bool> {
+        call!(self.set_graphics_power_state(power)).map(|applied| applied == "on")
+    }
+
+    /// Powers off the discrete GPU even though it may be in use, returning the actions taken
+    /// only because it was forced.
+    ///
+    /// Requires an interface revision of 8.
+    pub async fn force_graphics_power_off(&self) -> zbus::Result<Vec<String>> {
+        call!(self.set_graphics_power_state_with_flags("off", FLAG_FORCE)).map(|(_, forced)| forced)
+    }
+
+    /// The power state of the discrete GPU which would be applied, and the actions it would
+    /// take, forced if `force`, without applying it.
+    ///
+    /// Requires an interface revision of 10.
+    pub async fn plan_graphics_power(
+        &self,
+        power: GraphicsPower,
+        force: bool,
+    ) -> zbus::Result<(bool, Vec<String>)> {
+        let flags = if force { FLAG_FORCE | FLAG_DRY_RUN } else { FLAG_DRY_RUN };
+        call!(self.set_graphics_power_state_with_flags(power, flags))
+            .map(|(applied, plan)| (applied == "on", plan))
+    }
+
+    /// Start and end thresholds of the first battery.
+    pub async fn charge_thresholds(&self) -> zbus::Result<(u8, u8)> {
+        call!(self.get_charge_thresholds())
+    }
+
+    /// Start and end thresholds of each battery.
+    pub async fn battery_charge_thresholds(&self) -> zbus::Result<BTreeMap<String, (u8, u8)>> {
+        call!(self.get_battery_charge_thresholds())
+    }
+
+    /// The thresholds of each battery, whether they are supported, and the profile they match.
+    ///
+    /// Requires an interface revision of 7.
+    pub async fn charge_thresholds_status(&self) -> zbus::Result<ChargeThresholdsStatus> {
+        call!(self.get_charge_thresholds_status())
+    }
+
+    /// Sets the start and end thresholds of every battery.
+    pub async fn set_charge_thresholds(&self, thresholds: (u8, u8)) -> zbus::Result<()> {
+        call!(self.set_charge_thresholds(&thresholds))
+    }
+
+    /// The values setting charge thresholds would write, without setting them.
+    ///
+    /// Requires an interface revision of 10.
+    pub async fn plan_charge_thresholds(
+        &self,
+        thresholds: (u8, u8),
+    ) -> zbus::Result<Vec<PlannedAction>> {
+        call!(self.set_charge_thresholds_with_flags(&thresholds, FLAG_DRY_RUN))
+    }
+
+    /// Sets the start and end thresholds of `battery`, such as `BAT1`, or of every battery if
+    /// `None`, returning the errors of the batteries which failed, keyed by name, while the
+    /// others were set.
+    ///
+    /// Requires an interface revision of 25.
+    pub async fn set_battery_charge_thresholds(
+        &self,
+        battery: Option<&str>,
+        thresholds: (u8, u8),
+    ) -> zbus::Result<BTreeMap<String, String>> {
+        call!(self.set_battery_charge_thresholds(battery.unwrap_or_default(), &thresholds, 0u32))
+            .map(|(_, failures)| failures)
+    }
+
+    /// Sets the start and end thresholds of `battery`, or of every battery if `None`, returning
+    /// the thresholds applied to each battery set, which its firmware may round, and the errors
+    /// of the batteries which failed, both keyed by name.
+    ///
+    /// Requires an interface revision of 29.
+    #[allow(clippy::type_complexity)]
+    pub async fn apply_battery_charge_thresholds(
+        &self,
+        battery: Option<&str>,
+        thresholds: (u8, u8),
+    ) -> zbus::Result<(BTreeMap<String, (u8, u8)>, BTreeMap<String, String>)> {
+        call!(self.apply_battery_charge_thresholds(battery.unwrap_or_default(), &thresholds, 0u32))
+            .map(|(_, applied, failures)| (applied, failures))
+    }
+
+    /// The values setting the charge thresholds of `battery`, or of every battery if `None`,
+    /// would write, without setting them.
+    ///
+    /// Requires an interface revision of 25.
+    pub async fn plan_battery_charge_thresholds(
+        &self,
+        battery: Option<&str>,
+        thresholds: (u8, u8),
+    ) -> zbus::Result<Vec<PlannedAction>> {
+        let battery = battery.unwrap_or_default();
+        call!(self.set_battery_charge_thresholds(battery, &thresholds, FLAG_DRY_RUN))
+            .map(|(plan, _)| plan)
+    }
+
+    pub async fn charge_profiles(&self) -> zbus::Result<Vec<ChargeProfile>> {
+        call!(self.get_charge_profiles())
+    }
+
+    /// Starts calibrating the first battery which can be force-discharged, returning its
+    /// progress.
+    ///
+    /// Requires an interface revision of 27.
+    pub async fn start_calibration(&self) -> zbus::Result<CalibrationStatus> {
+        call!(self.start_calibration())
+    }
+
+    /// Stops the calibration running, restoring the thresholds and charge behaviour of its
+    /// battery.
+    ///
+    /// Requires an interface revision of 27.
+    pub async fn cancel_calibration(&self) -> zbus::Result<()> { call!(self.cancel_calibration()) }
+
+    /// The progress of the last calibration, or of the one running.
+    ///
+    /// Requires an interface revision of 27.
+    pub async fn calibration(&self) -> zbus::Result<CalibrationStatus> {
+        call!(self.get_calibration())
+    }
+
+    /// Changes of the `Calibration` property, as the calibration goes on.
+    ///
+    /// Requires an interface revision of 27.
+    pub async fn receive_calibration_changes(
+        &self,
+    ) -> PropertyStream<'a, (String, String, u8, u8, String)> {
+        self.proxy.receive_calibration_changed().await
+    }
+
+    /// Profile changes, with the client which requested them.
+    pub async fn receive_profile_switches(&self) -> zbus::Result<PowerProfileSwitchedStream<'a>> {
+        self.proxy.receive_power_profile_switched().await
+    }
+
+    /// Cookies of released profile holds, including those released as their client left.
+    pub async fn receive_profile_releases(&self) -> zbus::Result<ProfileReleasedStream<'a>> {
+        self.proxy.receive_profile_released().await
+    }
+
+    /// Graphics mode changes, and whether they require a reboot.
+    pub async fn receive_mode_changes(&self) -> zbus::Result<ModeChangedStream<'a>> {
+        self.proxy.receive_mode_changed().await
+    }
+
+    /// Initramfs rebuilds, including those skipped as the initramfs was up to date.
+    pub async fn receive_initramfs_rebuilds(
+        &self,
+    ) -> zbus::Result<InitramfsJobCompletedStream<'a>> {
+        self.proxy.receive_initramfs_job_completed().await
+    }
+
+    pub async fn receive_charge_thresholds_changes(
+        &self,
+    ) -> zbus::Result<ChargeThresholdsChangedStream<'a>> {
+        self.proxy.receive_battery_charge_thresholds_changed().await
+    }
+
+    /// Changes of the charge thresholds, with their initiator, including those made outside of
+    /// the daemon. Requires an interface revision of 26.
+    pub async fn receive_charge_threshold_switches(
+        &self,
+    ) -> zbus::Result<ChargeThresholdsSwitchedStream<'a>> {
+        self.proxy.receive_charge_thresholds_switched().await
+    }
+
+    /// Displays plugged into ports wired to the dGPU.
+    pub async fn receive_hot_plug_detects(&self) -> zbus::Result<HotPlugDetectStream<'a>> {
+        self.proxy.receive_hot_plug_detect().await
+    }
+}
+
+/// The error of a call the daemon did not reply to within `timeout`, an I/O error of the kind
+/// [`io::ErrorKind::TimedOut`].
+#[must_use]
+pub fn timed_out(timeout: Duration) -> zbus::Error {
+    let message = format!("the daemon did not respond within {:?}", timeout);
+    zbus::Error::InputOutput(Arc::new(io::Error::new(io::ErrorKind::TimedOut, message)))
+}
+
+/// Parses a graphics mode replied by the daemon.
+fn graphics_mode(mode: String) -> zbus::Result<GraphicsMode> {
+    mode.parse().map_err(|()| zbus::Error::Failure(format!("unknown graphics mode '{}'", mode)))
+}
+
+/// Parses a profile replied by the daemon.
+fn profile(name: String) -> zbus::Result<Profile> {
+    name.parse().map_err(|()| zbus::Error::Failure(format!("unknown profile '{}'", name)))
+}
@@ -1,9 +1,139 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
 use zvariant::Type;
 
-#[derive(Deserialize, Serialize, Type, Debug)]
+pub mod client;
+
+/// Revision of the interface of the daemon, incremented whenever methods, signals or
+/// properties are added. `GetVersion` documents the revisions.
+pub const API_VERSION: u32 = 29;
+
+/// Flag of the graphics methods taking flags to act even though the discrete GPU may be in
+/// use; `SetGraphicsPowerStateWithFlags` also stops the services which keep it open.
+pub const FLAG_FORCE: u32 = 1;
+
+/// Flag of the methods taking flags to reply with the actions planned, without performing
+/// them, which requires no authorization.
+pub const FLAG_DRY_RUN: u32 = 1 << 1;
+
+/// Flag of `SetProfileWithFlags` to apply the profile for this boot only, without saving it,
+/// so that the profile saved before is restored when the daemon starts.
+pub const FLAG_TEMPORARY: u32 = 1 << 2;
+
+/// Flag of `StartGraphicsSwitchWithFlags` to switch without rebuilding the initramfs, which
+/// must then be rebuilt, such as with `dracut --force`, before rebooting.
+pub const FLAG_NO_INITRAMFS: u32 = 1 << 3;
+
+/// A power profile, named `Battery`, `Quiet`, `Balanced` or `Performance` by the daemon.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Profile {
+    Battery,
+    /// Keeps the fans quiet, without the power saving of `Battery` which adds latency.
+    Quiet,
+    Balanced,
+    Performance,
+}
+
+impl Profile {
+    /// Every profile, from the lowest power use to the highest performance.
+    pub const ALL: [Self; 4] =
+        [Profile::Battery, Profile::Quiet, Profile::Balanced, Profile::Performance];
+}
+
+impl From<Profile> for &'static str {
+    fn from(profile: Profile) -> &'static str {
+        match profile {
+            Profile::Battery => "Battery",
+            Profile::Quiet => "Quiet",
+            Profile::Balanced => "Balanced",
+            Profile::Performance => "Performance",
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str((*self).into()) }
+}
+
+impl FromStr for Profile {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "Battery" => Ok(Profile::Battery),
+            "Quiet" => Ok(Profile::Quiet),
+            "Balanced" => Ok(Profile::Balanced),
+            "Performance" => Ok(Profile::Performance),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A graphics mode, named `integrated`, `compute`, `hybrid` or `nvidia` on the bus.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GraphicsMode {
+    Integrated,
+    Compute,
+    Hybrid,
+    Discrete,
+}
+
+impl GraphicsMode {
+    /// Every graphics mode, from the integrated GPU alone to the discrete GPU alone.
+    pub const ALL: [Self; 4] = [
+        GraphicsMode::Integrated,
+        GraphicsMode::Compute,
+        GraphicsMode::Hybrid,
+        GraphicsMode::Discrete,
+    ];
+}
+
+impl From<GraphicsMode> for &'static str {
+    fn from(mode: GraphicsMode) -> &'static str {
+        match mode {
+            GraphicsMode::Integrated => "integrated",
+            GraphicsMode::Compute => "compute",
+            GraphicsMode::Hybrid => "hybrid",
+            GraphicsMode::Discrete => "nvidia",
+        }
+    }
+}
+
+impl FromStr for GraphicsMode {
+    type Err = ();
+
+    fn from_str(vendor: &str) -> Result<Self, Self::Err> {
+        GraphicsMode::ALL.into_iter().find(|mode| <&str>::from(*mode) == vendor).ok_or(())
+    }
+}
+
+impl fmt::Display for GraphicsMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str((*self).into()) }
+}
+
+/// A power state to set the discrete GPU to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GraphicsPower {
+    On,
+    Off,
+    /// On, unless the graphics mode does not use the dGPU and it lacks runtime power
+    /// management.
+    Auto,
+}
+
+impl From<GraphicsPower> for &'static str {
+    fn from(power: GraphicsPower) -> &'static str {
+        match power {
+            GraphicsPower::On => "on",
+            GraphicsPower::Off => "off",
+            GraphicsPower::Auto => "auto",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Type, Debug, Clone)]
 pub struct ChargeProfile {
     pub id:          String,
     pub title:       String,
@@ -12,6 +142,435 @@ pub struct ChargeProfile {
     pub end:         u8,
 }
 
+/// The charge thresholds of a battery.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryThresholds {
+    /// Charge below which charging resumes, or 0 if the battery has no start threshold.
+    pub start:           u8,
+    /// Charge at which charging stops.
+    pub end:             u8,
+    pub start_supported: bool,
+}
+
+/// The charge thresholds of each battery, and the values they accept.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChargeThresholdsStatus {
+    /// Whether the firmware supports charge thresholds.
+    pub supported:       bool,
+    /// Whether every battery has a start threshold.
+    pub start_supported: bool,
+    /// Lowest threshold accepted.
+    pub min:             u8,
+    /// Highest threshold accepted. The end threshold must be above the start threshold.
+    pub max:             u8,
+    /// ID of the charge profile matching the thresholds of the first battery, or empty if
+    /// none does.
+    pub profile:         String,
+    /// Thresholds of each battery, keyed by power supply name, such as `BAT0`.
+    pub batteries:       BTreeMap<String, BatteryThresholds>,
+}
+
+/// The NVIDIA kernel module built for a kernel. Empty strings are unknown values.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone)]
+pub struct NvidiaKernelModule {
+    pub module_present: bool,
+    pub module_version: String,
+    /// One of `akmod`, `dkms`, `weak-update` or `builtin`.
+    pub source:         String,
+}
+
+/// Graphics switching support and driver details. Empty strings are unknown values.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone)]
+pub struct GraphicsCapabilities {
+    pub switchable:               bool,
+    pub desktop:                  bool,
+    pub kernel_driver_version:    String,
+    pub userspace_driver_version: String,
+    /// One of `on`, `off` or `default`.
+    pub gsp_firmware:             String,
+    /// NVIDIA kernel modules, keyed by the version of each installed kernel.
+    pub kernel_modules:           BTreeMap<String, NvidiaKernelModule>,
+}
+
+/// A GPU detected on the PCI bus. Empty strings are unknown values.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct GraphicsDeviceInfo {
+    pub vendor_id:           u16,
+    pub device_id:           u16,
+    pub subsystem_vendor_id: u16,
+    pub subsystem_device_id: u16,
+    /// PCI address, such as `0000:01:00.0`.
+    pub bus_id:              String,
+    /// One of `integrated`, `discrete` or `other`.
+    pub kind:                String,
+    /// Kernel driver bound to the device, such as `nvidia`.
+    pub driver:              String,
+    /// PCI power state, such as `D0` or `D3cold`.
+    pub power_state:         String,
+    /// Marketing name, such as `GeForce RTX 4060 Max-Q / Mobile`.
+    pub name:                String,
+}
+
+/// Automatic profile switching on AC/battery transitions.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone)]
+pub struct AutoProfileStatus {
+    pub enabled:              bool,
+    /// Profile applied on AC, such as `performance`.
+    pub ac:                   String,
+    /// Profile applied on battery, such as `battery`.
+    pub battery:              String,
+    /// Whether a profile set by a client is kept until the next transition.
+    pub pin_manual:           bool,
+    /// `ac` or `battery`, or empty if no profile was applied yet.
+    pub last_trigger:         String,
+    pub last_trigger_profile: String,
+    /// Time of the last trigger, in seconds since the Unix epoch.
+    pub last_trigger_time:    u64,
+}
+
+/// A time range of the schedule of profiles, in local time.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScheduledRange {
+    /// Start of the range, such as `09:00`.
+    pub from:    String,
+    /// End of the range, on the next day if it is not after the start.
+    pub to:      String,
+    /// Days the range starts on, such as `mon`, or empty for every day.
+    pub days:    Vec<String>,
+    /// Profile applied during the range, as written in `daemon.toml`.
+    pub profile: String,
+}
+
+/// Profile switching by time of day.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScheduleStatus {
+    /// The ranges, of which the last one listed applies where they overlap.
+    pub ranges:       Vec<ScheduledRange>,
+    /// Profile of the range the current time falls in, or empty if none.
+    pub current:      String,
+    /// Profile applied at the next transition, or empty if none is planned.
+    pub next_profile: String,
+    /// Time of the next transition, in seconds since the Unix epoch, or 0.
+    pub next_time:    u64,
+}
+
+/// Switching to the battery profile while the battery is low.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct LowBatteryStatus {
+    pub enabled:            bool,
+    /// Charge under which the battery profile is forced while discharging, in percent.
+    pub threshold_percent:  u8,
+    /// Charge above the threshold at which the previous profile is restored, in percent.
+    pub hysteresis_percent: u8,
+    /// Whether the battery profile is forced now.
+    pub active:             bool,
+    /// Profile restored once the battery recovers, or empty if none.
+    pub restore_profile:    String,
+}
+
+/// Capping the maximum frequency of the CPUs while running on battery, whatever the profile.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct BatteryCapStatus {
+    pub enabled: bool,
+    /// Maximum frequency on battery, in percent of the maximum of each CPU.
+    pub percent: u8,
+    /// Whether the profile was set with the cap.
+    pub active:  bool,
+}
+
+/// A system battery, as its power supply reports it. Empty strings and -1 are attributes
+/// which cannot be read, such as on supplies lacking them or with broken ACPI tables.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct BatteryStatus {
+    /// Power supply name, such as `BAT0`.
+    pub name:                   String,
+    pub manufacturer:           String,
+    pub model:                  String,
+    /// `Charging`, `Discharging`, `Not charging` or `Full`.
+    pub status:                 String,
+    /// Charge, in percent of the capacity.
+    pub capacity:               i32,
+    pub cycle_count:            i32,
+    /// Energy held when full, in mWh, from the charge at the design voltage if the battery
+    /// reports its charge instead.
+    pub energy_full_mwh:        i32,
+    /// Energy held when full by the battery when new, in mWh.
+    pub energy_full_design_mwh: i32,
+    /// `energy_full_mwh` in percent of `energy_full_design_mwh`.
+    pub health:                 i32,
+    /// Power the battery charges or discharges at, in mW.
+    pub power_mw:               i32,
+    /// Charge below which charging resumes, in percent.
+    pub start_threshold:        i32,
+    /// Charge at which charging stops, in percent.
+    pub end_threshold:          i32,
+}
+
+/// A calibration of a battery: force-discharging it to a low point, then charging it full.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct CalibrationStatus {
+    /// `idle`, `discharging`, `charging`, `finished`, `cancelled` or `failed`.
+    pub phase:       String,
+    /// Power supply name of the battery calibrated, or empty if none was.
+    pub battery:     String,
+    /// Charge of the battery when last read, in percent.
+    pub capacity:    u8,
+    /// Charge at which the battery is charged again, in percent.
+    pub low_percent: u8,
+    /// Why the calibration failed or was cancelled, or empty.
+    pub message:     String,
+}
+
+impl From<(String, String, u8, u8, String)> for CalibrationStatus {
+    fn from(
+        (phase, battery, capacity, low_percent, message): (String, String, u8, u8, String),
+    ) -> Self {
+        Self { phase, battery, capacity, low_percent, message }
+    }
+}
+
+/// A profile held by an application until it releases it or leaves the bus.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProfileHold {
+    pub cookie:         u32,
+    /// `Battery`, `Quiet`, `Balanced` or `Performance`.
+    pub profile:        String,
+    pub reason:         String,
+    pub application_id: String,
+    /// Unique bus name of the client holding the profile.
+    pub sender:         String,
+}
+
+/// A parameter set by the active profile, or which it could not set as the hardware lacks it,
+/// such as `intel_pstate` on AMD CPUs. Those have empty values, and always match.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProfileParameter {
+    /// Name of the parameter, such as `scaling_governor`.
+    pub name:     String,
+    /// File the value was written to, or which does not exist.
+    pub path:     String,
+    /// Value written by the profile, or empty if the parameter is not applicable.
+    pub intended: String,
+    /// Value of the file now, or empty if it cannot be read.
+    pub current:  String,
+    /// Whether the file still holds the intended value, unlike one changed by another tool.
+    pub matches:  bool,
+}
+
+/// The active profile, and the parameters it set.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProfileStatus {
+    /// `Battery`, `Quiet`, `Balanced` or `Performance`.
+    pub profile:    String,
+    pub parameters: Vec<ProfileParameter>,
+}
+
+/// The governor of a cpufreq policy, such as `policy0`.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct PolicyGovernor {
+    pub policy:   String,
+    pub governor: String,
+}
+
+/// The scaling of the CPUs, as cpufreq reports it. Empty strings and zeros are values which
+/// cannot be read, such as on virtual machines without cpufreq.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct CpuFrequencyStatus {
+    /// Such as `intel_pstate`, `intel_cpufreq`, `amd-pstate-epp` or `acpi-cpufreq`.
+    pub scaling_driver:                String,
+    /// `active`, `passive` or `guided`, with intel_pstate or amd-pstate.
+    pub driver_mode:                   String,
+    /// The `scaling_governor` of every policy, or empty if they differ.
+    pub governor:                      String,
+    /// The `scaling_governor` of each policy, in order, if they differ.
+    pub policy_governors:              Vec<PolicyGovernor>,
+    /// The `energy_performance_preference` of `policy0`.
+    pub energy_performance_preference: String,
+    /// `on` or `off`, or empty if the driver has no boost control.
+    pub boost:                         String,
+    /// Frequency limits of `policy0`, in kHz.
+    pub min_khz:                       u32,
+    pub max_khz:                       u32,
+    /// Frequency of `policy0`, in kHz.
+    pub current_khz:                   u32,
+}
+
+/// Another power manager running, which also sets some of the parameters of the profiles.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct PowerConflict {
+    /// Its systemd service, such as `tlp.service`.
+    pub service:    String,
+    pub reason:     String,
+    /// Parameters of the profiles it also sets, named as in the profile status.
+    pub parameters: Vec<String>,
+}
+
+/// A parameter which the last profile failed to set, while it set the others.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProfileFailure {
+    /// Name of the parameter, such as `energy_performance_preference`, or of the devices which
+    /// failed, such as `backlight`.
+    pub name:    String,
+    /// File which could not be written, or empty if the failure is not of a single file.
+    pub path:    String,
+    /// Value which could not be written, or empty.
+    pub value:   String,
+    /// Error number of the OS, such as 13 for `EACCES`, or 0 if unknown.
+    pub errno:   i32,
+    pub message: String,
+}
+
+/// A profile, and the key parameters it sets on this machine. Empty strings are parameters
+/// the profile does not set here.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProfileInfo {
+    /// `Battery`, `Quiet`, `Balanced` or `Performance`.
+    pub name:             String,
+    pub description:      String,
+    /// The `scaling_governor` of the CPUs.
+    pub governor:         String,
+    /// The `energy_performance_preference` of the CPUs.
+    pub epp:              String,
+    /// `on` or `off`, through intel_pstate or the boost of cpufreq.
+    pub turbo:            String,
+    /// The ACPI `platform_profile`.
+    pub platform_profile: String,
+}
+
+/// A parameter of a profile which `/etc/system76-power/profiles.toml` may override.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProfileTunable {
+    /// `Battery`, `Quiet`, `Balanced` or `Performance`.
+    pub profile:    String,
+    /// Key of the parameter in `profiles.toml`, such as `governor`.
+    pub key:        String,
+    /// Value the profile sets on this machine, or empty if it does not set the parameter here.
+    pub value:      String,
+    /// Whether the value was set in `profiles.toml`, instead of by the built-in profile.
+    pub overridden: bool,
+}
+
+/// The graphics modes, and the power of the discrete GPU.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct GraphicsStatus {
+    /// Mode of the running system, as returned by `GetGraphics`.
+    pub mode:            String,
+    /// Mode configured for the next boot, `custom` if its files match no mode, or empty if
+    /// none was configured.
+    pub configured:      String,
+    /// `active`, `suspended` by runtime power management, `off`, `rescan-needed` if powering
+    /// it on failed, or empty if graphics cannot be switched.
+    pub power:           String,
+    /// Whether a reboot is required for the configured mode to take effect.
+    pub reboot_required: bool,
+}
+
+/// A PCI function of the discrete GPU, such as its audio controller.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct GraphicsFunction {
+    /// PCI address, such as `0000:01:00.1`.
+    pub bus_id:         String,
+    /// Kernel driver bound to the function, or empty if none is.
+    pub driver:         String,
+    /// `active`, `suspended` or another runtime PM status, or empty if unknown.
+    pub runtime_status: String,
+}
+
+/// The power state of the discrete GPU, and the functions present on the bus.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct GraphicsPowerStatus {
+    /// `active`, `suspended` by runtime power management, `off` once removed from the bus, or
+    /// `rescan-needed` if powering it on failed to bring it back.
+    pub power:           String,
+    pub functions:       Vec<GraphicsFunction>,
+    /// The power cap of the discrete GPU, in watts, or 0 if it has none or is not awake.
+    pub power_cap_watts: u32,
+}
+
+/// Whether graphics can be switched, why not, and the GPUs found.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct SwitchableStatus {
+    pub switchable: bool,
+    /// `desktop`, `no-discrete-gpu` or `no-integrated-gpu`, or empty if switchable.
+    pub reason:     String,
+    /// The GPUs on the PCI bus, as returned by `GetGraphicsDevices`.
+    pub devices:    Vec<GraphicsDeviceInfo>,
+}
+
+/// A file written or removed, or a command run, by a change.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct PlannedAction {
+    /// `write` or `remove` a file, or `run` a command.
+    pub kind:    String,
+    /// Path of the file, or the command.
+    pub target:  String,
+    /// Value written, or the arguments of the command.
+    pub value:   String,
+    /// Current value of the file written, or empty if it cannot be read.
+    pub current: String,
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Whole files, such as a modprobe configuration, are summarized by their length.
+        let summary = |value: &str| match value.trim_end().lines().count() {
+            count if count > 1 => format!("<{} lines>", count),
+            _ => value.to_owned(),
+        };
+
+        match self.kind.as_str() {
+            "write" if self.current.is_empty() => {
+                write!(f, "write {} = {}", self.target, summary(&self.value))
+            }
+            "write" => write!(
+                f,
+                "write {} = {} (now {})",
+                self.target,
+                summary(&self.value),
+                summary(&self.current)
+            ),
+            "run" => write!(f, "run {} {}", self.target, self.value),
+            kind => write!(f, "{} {}", kind, self.target),
+        }
+    }
+}
+
+/// A change requested by a client, as recorded by the daemon.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecentAction {
+    /// Time of the request, in seconds since the Unix epoch.
+    pub time:    u64,
+    /// Unique bus name of the client.
+    pub sender:  String,
+    /// Process ID of the client, or 0 if unknown.
+    pub pid:     u32,
+    /// User ID of the client, or `u32::MAX` if unknown.
+    pub uid:     u32,
+    /// Method called, such as `SetGraphics`.
+    pub method:  String,
+    /// Arguments of the call.
+    pub request: String,
+    /// `ok`, or the name and message of the error replied.
+    pub outcome: String,
+}
+
+/// Status of a long-running operation of the daemon.
+#[derive(Deserialize, Serialize, Type, Debug, Default, Clone)]
+pub struct JobStatus {
+    /// Kind of operation, such as `graphics`.
+    pub kind:     String,
+    /// Step being performed, or the last step performed once finished.
+    pub step:     String,
+    pub percent:  u32,
+    pub finished: bool,
+    pub success:  bool,
+    /// Outcome of a finished job, such as the error of a failed one.
+    pub message:  String,
+    /// Name of the DBus error of a failed job, such as
+    /// `com.system76.PowerDaemon.Error.InitramfsFailed`.
+    pub error:    String,
+}
+
 #[zbus::dbus_proxy(
     interface = "com.system76.PowerDaemon",
     default_service = "com.system76.PowerDaemon",
@@ -19,58 +578,337 @@ pub struct ChargeProfile {
 )]
 trait PowerDaemon {
     /// Balanced method
+    #[dbus_proxy(allow_interactive_auth)]
     fn balanced(&self) -> zbus::Result<()>;
 
     /// Battery method
+    #[dbus_proxy(allow_interactive_auth)]
     fn battery(&self) -> zbus::Result<()>;
 
     /// Performance method
+    #[dbus_proxy(allow_interactive_auth)]
     fn performance(&self) -> zbus::Result<()>;
 
+    /// SetProfileWithFlags method, returning the values written, or planned for a dry run
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_profile_with_flags(&self, profile: &str, flags: u32)
+        -> zbus::Result<Vec<PlannedAction>>;
+
     /// GetProfile method
     fn get_profile(&self) -> zbus::Result<String>;
 
+    /// GetProfileStatus method
+    fn get_profile_status(&self) -> zbus::Result<ProfileStatus>;
+
+    /// GetPersistedProfile method, returning the profile applied when the daemon starts
+    fn get_persisted_profile(&self) -> zbus::Result<String>;
+
+    /// GetAutoProfile method
+    fn get_auto_profile(&self) -> zbus::Result<AutoProfileStatus>;
+
+    /// GetProfileSchedule method
+    fn get_profile_schedule(&self) -> zbus::Result<ScheduleStatus>;
+
+    /// GetLowBattery method
+    fn get_low_battery(&self) -> zbus::Result<LowBatteryStatus>;
+
+    /// GetBatteryFrequencyCap method
+    fn get_battery_frequency_cap(&self) -> zbus::Result<BatteryCapStatus>;
+
+    /// GetConfig method
+    fn get_config(&self) -> zbus::Result<String>;
+
+    /// GetVersion method, returning the version of the daemon and the revision of its
+    /// interface
+    fn get_version(&self) -> zbus::Result<(String, u32)>;
+
+    /// HoldProfile method, returning the cookie to release the hold with
+    #[dbus_proxy(allow_interactive_auth)]
+    fn hold_profile(&self, profile: &str, reason: &str, application_id: &str) -> zbus::Result<u32>;
+
+    /// ReleaseProfile method
+    fn release_profile(&self, cookie: u32) -> zbus::Result<()>;
+
+    /// GetActiveHolds method
+    fn get_active_holds(&self) -> zbus::Result<Vec<ProfileHold>>;
+
+    /// GetProfiles method
+    fn get_profiles(&self) -> zbus::Result<Vec<ProfileInfo>>;
+
+    /// GetProfileTunables method
+    fn get_profile_tunables(&self) -> zbus::Result<Vec<ProfileTunable>>;
+
+    /// GetProfileFailures method
+    fn get_profile_failures(&self) -> zbus::Result<Vec<ProfileFailure>>;
+
+    /// GetCpuFrequency method
+    fn get_cpu_frequency(&self) -> zbus::Result<CpuFrequencyStatus>;
+
+    /// GetPowerDraw method, returning estimates in watts by key, such as `package_watts`
+    fn get_power_draw(&self) -> zbus::Result<BTreeMap<String, f64>>;
+
+    /// GetBatteries method
+    fn get_batteries(&self) -> zbus::Result<Vec<BatteryStatus>>;
+
+    /// GetConflicts method
+    fn get_conflicts(&self) -> zbus::Result<Vec<PowerConflict>>;
+
+    /// GetRecentActions method
+    fn get_recent_actions(&self) -> zbus::Result<Vec<RecentAction>>;
+
+    /// SetAutoProfile method
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_auto_profile(&self, enabled: bool) -> zbus::Result<()>;
+
+    /// SetIdleProfile method
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_idle_profile(&self, enabled: bool) -> zbus::Result<()>;
+
+    /// SetLowBattery method
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_low_battery(&self, enabled: bool, threshold_percent: u8) -> zbus::Result<()>;
+
     /// GetExternalDisplaysRequireDGPU method
     fn get_external_displays_require_dgpu(&self) -> zbus::Result<bool>;
 
     /// GetDefaultGraphics method
     fn get_default_graphics(&self) -> zbus::Result<String>;
 
+    /// GetGraphicsStatus method
+    fn get_graphics_status(&self) -> zbus::Result<GraphicsStatus>;
+
+    /// GetGraphicsRecommendation method, returning the default mode and how it was derived
+    fn get_graphics_recommendation(&self) -> zbus::Result<(String, String)>;
+
     /// GetGraphics method
     fn get_graphics(&self) -> zbus::Result<String>;
 
     /// SetGraphics method
+    #[dbus_proxy(allow_interactive_auth)]
     fn set_graphics(&self, vendor: &str) -> zbus::Result<()>;
 
+    /// GetGraphicsCapabilities method
+    fn get_graphics_capabilities(&self) -> zbus::Result<GraphicsCapabilities>;
+
+    /// GetGraphicsDevices method
+    fn get_graphics_devices(&self) -> zbus::Result<Vec<GraphicsDeviceInfo>>;
+
+    /// SetGraphicsForce method
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_graphics_force(&self, vendor: &str) -> zbus::Result<()>;
+
+    /// StartGraphicsSwitch method, returning the path of the job performing the switch
+    #[dbus_proxy(allow_interactive_auth)]
+    fn start_graphics_switch(
+        &self,
+        vendor: &str,
+        force: bool,
+    ) -> zbus::Result<zvariant::OwnedObjectPath>;
+
+    /// StartGraphicsSwitchWithFlags method, returning the path of the job performing the switch,
+    /// or `/` for a dry run, and the actions planned
+    #[dbus_proxy(allow_interactive_auth)]
+    fn start_graphics_switch_with_flags(
+        &self,
+        vendor: &str,
+        flags: u32,
+    ) -> zbus::Result<(zvariant::OwnedObjectPath, Vec<PlannedAction>)>;
+
+    /// GetJob method
+    fn get_job(&self, job: &zvariant::ObjectPath<'_>) -> zbus::Result<JobStatus>;
+
+    /// SetGraphicsOption method
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_graphics_option(&self, option: &str, value: &str) -> zbus::Result<()>;
+
     /// GetSwitchable method
     fn get_switchable(&self) -> zbus::Result<bool>;
 
+    /// GetSwitchableStatus method
+    fn get_switchable_status(&self) -> zbus::Result<SwitchableStatus>;
+
     /// GetDesktop method
     fn get_desktop(&self) -> zbus::Result<bool>;
 
     /// GetGraphicsPower method
     fn get_graphics_power(&self) -> zbus::Result<bool>;
 
+    /// GetGraphicsPowerStatus method
+    fn get_graphics_power_status(&self) -> zbus::Result<GraphicsPowerStatus>;
+
     /// SetGraphicsPower method
+    #[deprecated(note = "use `set_graphics_power_state`, which also supports \"auto\"")]
+    #[dbus_proxy(allow_interactive_auth)]
     fn set_graphics_power(&self, power: bool) -> zbus::Result<()>;
 
+    /// SetGraphicsPowerState method, returning the power state that was applied
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_graphics_power_state(&self, state: &str) -> zbus::Result<String>;
+
+    /// SetGraphicsPowerStateWithFlags method, returning the power state that was applied and
+    /// the actions forced by the flags, or the power state and actions planned for a dry run
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_graphics_power_state_with_flags(
+        &self,
+        state: &str,
+        flags: u32,
+    ) -> zbus::Result<(String, Vec<String>)>;
+
     /// AutoGraphicsPower
+    #[dbus_proxy(allow_interactive_auth)]
     fn auto_graphics_power(&self) -> zbus::Result<()>;
 
     /// GetChargeProfiles method
     fn get_charge_profiles(&self) -> zbus::Result<Vec<ChargeProfile>>;
 
+    /// GetBatteryChargeThresholds method
+    fn get_battery_charge_thresholds(&self) -> zbus::Result<BTreeMap<String, (u8, u8)>>;
+
+    /// GetChargeThresholdsStatus method
+    fn get_charge_thresholds_status(&self) -> zbus::Result<ChargeThresholdsStatus>;
+
     /// GetChargeThresholds method
     fn get_charge_thresholds(&self) -> zbus::Result<(u8, u8)>;
 
     /// SetChargeThresholds method
+    #[dbus_proxy(allow_interactive_auth)]
     fn set_charge_thresholds(&self, thresholds: &(u8, u8)) -> zbus::Result<()>;
 
+    /// SetChargeThresholdsWithFlags method, returning the values written, or planned for a dry
+    /// run
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_charge_thresholds_with_flags(
+        &self,
+        thresholds: &(u8, u8),
+        flags: u32,
+    ) -> zbus::Result<Vec<PlannedAction>>;
+
+    /// SetBatteryChargeThresholds method, returning the values written, or planned for a dry
+    /// run, and the errors of the batteries which failed, keyed by name
+    #[dbus_proxy(allow_interactive_auth)]
+    fn set_battery_charge_thresholds(
+        &self,
+        battery: &str,
+        thresholds: &(u8, u8),
+        flags: u32,
+    ) -> zbus::Result<(Vec<PlannedAction>, BTreeMap<String, String>)>;
+
+    /// ApplyBatteryChargeThresholds method, returning the values written, or planned for a dry
+    /// run, the thresholds applied to each battery set, and the errors of the batteries which
+    /// failed, both keyed by name
+    #[allow(clippy::type_complexity)]
+    #[dbus_proxy(allow_interactive_auth)]
+    fn apply_battery_charge_thresholds(
+        &self,
+        battery: &str,
+        thresholds: &(u8, u8),
+        flags: u32,
+    ) -> zbus::Result<(Vec<PlannedAction>, BTreeMap<String, (u8, u8)>, BTreeMap<String, String>)>;
+
+    /// StartCalibration method
+    #[dbus_proxy(allow_interactive_auth)]
+    fn start_calibration(&self) -> zbus::Result<CalibrationStatus>;
+
+    /// CancelCalibration method
+    #[dbus_proxy(allow_interactive_auth)]
+    fn cancel_calibration(&self) -> zbus::Result<()>;
+
+    /// GetCalibration method
+    fn get_calibration(&self) -> zbus::Result<CalibrationStatus>;
+
+    /// Calibration property, as `GetCalibration` returns it
+    #[dbus_proxy(property)]
+    fn calibration(&self) -> zbus::Result<(String, String, u8, u8, String)>;
+
+    /// ConfiguredGraphicsMode property
+    #[dbus_proxy(property)]
+    fn configured_graphics_mode(&self) -> zbus::Result<String>;
+
+    /// GraphicsMode property
+    #[dbus_proxy(property)]
+    fn graphics_mode(&self) -> zbus::Result<String>;
+
+    /// GraphicsPower property
+    #[dbus_proxy(property)]
+    fn graphics_power(&self) -> zbus::Result<bool>;
+
+    /// PowerProfile property
+    #[dbus_proxy(property)]
+    fn power_profile(&self) -> zbus::Result<String>;
+
+    /// Switchable property
+    #[dbus_proxy(property)]
+    fn switchable(&self) -> zbus::Result<bool>;
+
+    /// ChargeThresholds property
+    #[dbus_proxy(property)]
+    fn charge_thresholds(&self) -> zbus::Result<(u8, u8)>;
+
+    /// ExternalDisplaysRequireDgpu property
+    #[dbus_proxy(property, name = "ExternalDisplaysRequireDgpu")]
+    fn external_displays_require_dgpu(&self) -> zbus::Result<bool>;
+
+    /// HotPlugDetect property
+    #[dbus_proxy(property, name = "HotPlugDetect")]
+    fn hot_plug_state(&self) -> zbus::Result<Vec<bool>>;
+
+    /// ChargeThresholdsChanged signal
+    #[dbus_proxy(signal, name = "ChargeThresholdsChanged")]
+    fn battery_charge_thresholds_changed(
+        &self,
+        thresholds: BTreeMap<String, (u8, u8)>,
+    ) -> zbus::Result<()>;
+
+    /// ChargeThresholdsSwitched signal
+    #[dbus_proxy(signal)]
+    fn charge_thresholds_switched(
+        &self,
+        thresholds: BTreeMap<String, (u8, u8)>,
+        initiator: &str,
+    ) -> zbus::Result<()>;
+
     /// HotPlugDetect signal
     #[dbus_proxy(signal)]
     fn hot_plug_detect(&self, port: u64) -> zbus::Result<()>;
 
+    /// InitramfsJobCompleted signal
+    #[dbus_proxy(signal)]
+    fn initramfs_job_completed(
+        &self,
+        success: bool,
+        tool: &str,
+        duration_ms: u64,
+        stderr_tail: &str,
+        skipped: bool,
+    ) -> zbus::Result<()>;
+
+    /// ModeChanged signal
+    #[dbus_proxy(signal)]
+    fn mode_changed(&self, old: &str, new: &str, reboot_required: bool) -> zbus::Result<()>;
+
     /// PowerProfileSwitch signal
     #[dbus_proxy(signal)]
     fn power_profile_switch(&self, profile: &str) -> zbus::Result<()>;
+
+    /// PowerProfileSwitched signal
+    #[dbus_proxy(signal)]
+    fn power_profile_switched(&self, old: &str, new: &str, initiator: &str) -> zbus::Result<()>;
+
+    /// ProfileReleased signal
+    #[dbus_proxy(signal)]
+    fn profile_released(&self, cookie: u32) -> zbus::Result<()>;
+}
+
+#[zbus::dbus_proxy(
+    interface = "com.system76.PowerDaemon.Job",
+    default_service = "com.system76.PowerDaemon"
+)]
+trait Job {
+    /// Progress signal
+    #[dbus_proxy(signal)]
+    fn progress(&self, step: &str, percent: u32) -> zbus::Result<()>;
+
+    /// Finished signal
+    #[dbus_proxy(signal)]
+    fn finished(&self, success: bool, message: &str) -> zbus::Result<()>;
 }